@@ -0,0 +1,21 @@
+use std::fs;
+use std::path::Path;
+
+// Passes the version of wasmtime the runtime is built against to the crate
+// as WASMTIME_VERSION, for `wasm-container version`. It comes from the lock
+// file, as wasmtime doesn't expose it.
+fn main() {
+    let lock = Path::new(&std::env::var("CARGO_MANIFEST_DIR").unwrap()).join("Cargo.lock");
+    println!("cargo:rerun-if-changed={}", lock.display());
+    
+    let version = fs::read_to_string(&lock)
+        .ok()
+        .and_then(|lock| {
+            let mut lines = lock.lines();
+            lines.find(|line| *line == "name = \"wasmtime\"")?;
+            let version = lines.next()?.strip_prefix("version = \"")?.strip_suffix('"')?;
+            Some(version.to_string())
+        })
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=WASMTIME_VERSION={}", version);
+}
@@ -0,0 +1,367 @@
+use anyhow::{Result, anyhow};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+use crate::compose::interpolate;
+use crate::compose::{Compose, ComposeFile, Definition, ProjectRecord, Service};
+use crate::config::parse_size;
+use crate::container::{Container, Cpus, PortMapping, Resources};
+use crate::image::{ImageConfig, ImageData, ImageManager, RootFs};
+use crate::network::{Network, HOST_NETWORK, NO_NETWORK};
+use crate::network::policy::{EgressDefault, EgressPolicy, HostRule};
+use crate::runtime::WasmRuntime;
+use crate::volume::{VolumeManager, VolumeSource, VolumeSpec};
+
+// The manifest `apply` looks for in the current directory.
+pub const DEFAULT_FILE: &str = "app.toml";
+
+// An app manifest: wasm components, what each may use and how they are
+// wired together.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AppFile {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub components: BTreeMap<String, Component>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Component {
+    // Either an image to pull or a module on disk, relative to the
+    // manifest.
+    pub image: Option<String>,
+    pub module: Option<PathBuf>,
+    // The export to call instead of `_start`; it takes and returns nothing.
+    pub invoke: Option<String>,
+    pub command: Option<Vec<String>>,
+    pub workdir: Option<String>,
+    pub env: BTreeMap<String, String>,
+    pub ports: Vec<String>,
+    // Components this one talks to, as `name[:port]`: they start first and
+    // their address is passed in the environment.
+    pub uses: Vec<String>,
+    // Components that only need to start first.
+    pub after: Vec<String>,
+    pub grants: Grants,
+    pub limits: Limits,
+}
+
+// What a component may reach: by default the app's network and any
+// destination, but no host directories.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Grants {
+    pub network: NetworkGrant,
+    // Destinations outside the app it may connect to, as `host[:port]`;
+    // without the list, any.
+    pub outbound: Option<Vec<String>>,
+    // Host directories and named volumes, as `-v` takes them.
+    pub dirs: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NetworkGrant {
+    None,
+    // The app's own network, shared by its components.
+    #[default]
+    App,
+    Host,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    // A size like `64m`.
+    pub memory: Option<String>,
+    pub cpus: Option<f64>,
+}
+
+// What `configure` applies to a component's container on top of the
+// service it is translated to.
+#[derive(Debug, Clone, Default)]
+struct Settings {
+    module: Option<PathBuf>,
+    invoke: Option<String>,
+    network: NetworkGrant,
+    outbound: Option<Vec<HostRule>>,
+    resources: Resources,
+}
+
+// A component's container, as `status` reports it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ComponentStatus {
+    pub component: String,
+    // Empty for components that haven't started.
+    pub container: String,
+    pub status: String,
+    pub ports: String,
+}
+
+/// An app manifest, run as a compose project named after the app: each
+/// component is a service, reaching the ones it uses by name on the app's
+/// network.
+#[derive(Debug, Clone)]
+pub struct App {
+    compose: Compose,
+    settings: BTreeMap<String, Settings>,
+}
+
+impl App {
+    /// The app is named by `project`, else the manifest's `name`, else the
+    /// directory holding it.
+    pub fn load(path: &Path, project: Option<&str>) -> Result<Self> {
+        let toml = fs::read_to_string(path)
+            .map_err(|e| anyhow!("Failed to read app manifest {:?}: {}", path, e))?;
+        let dir = std::path::absolute(path)?
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        Self::parse(&toml, &dir, project)
+    }
+    
+    /// Variables are substituted as in compose files.
+    pub fn parse(toml: &str, dir: &Path, project: Option<&str>) -> Result<Self> {
+        let toml = interpolate::interpolate(toml, &interpolate::variables(dir)?)?;
+        let file: AppFile = toml::from_str(&toml).map_err(|e| anyhow!("Invalid app manifest: {}", e))?;
+        if file.components.is_empty() {
+            return Err(anyhow!("App manifest defines no components"));
+        }
+        let project = match project.or(file.name.as_deref()) {
+            Some(project) => project.to_string(),
+            None => Compose::dir_project(dir),
+        };
+        
+        let mut compose_file = ComposeFile::default();
+        let mut settings = BTreeMap::new();
+        for (name, component) in &file.components {
+            let (service, component_settings) = Self::translate(component, &file.components, dir)
+                .map_err(|e| anyhow!("Component {}: {}", name, e))?;
+            for spec in &service.volumes {
+                if let VolumeSource::Named(volume) = &spec.source {
+                    compose_file.volumes.insert(volume.clone(), Definition::default());
+                }
+            }
+            compose_file.services.insert(name.clone(), service);
+            settings.insert(name.clone(), component_settings);
+        }
+        
+        Ok(Self {
+            compose: Compose::new(compose_file, dir, &project)?,
+            settings,
+        })
+    }
+    
+    fn translate(component: &Component, components: &BTreeMap<String, Component>, dir: &Path) -> Result<(Service, Settings)> {
+        let (image, module) = match (&component.image, &component.module) {
+            (Some(image), None) => (image.clone(), None),
+            (None, Some(module)) => (module.to_string_lossy().into_owned(), Some(dir.join(module))),
+            _ => return Err(anyhow!("set one of image or module")),
+        };
+        
+        let mut environment: Vec<String> = component.env.iter().map(|(key, value)| format!("{}={}", key, value)).collect();
+        let mut depends_on = Vec::new();
+        for used in &component.uses {
+            let (target, port) = match used.split_once(':') {
+                Some((target, port)) => {
+                    port.parse::<u16>().map_err(|_| anyhow!("invalid port in uses {:?}", used))?;
+                    (target, Some(port))
+                }
+                None => (used.as_str(), None),
+            };
+            let Some(used_component) = components.get(target) else {
+                return Err(anyhow!("uses undefined component {}", target));
+            };
+            if component.grants.network != NetworkGrant::App || used_component.grants.network != NetworkGrant::App {
+                return Err(anyhow!("uses {}, but only components on the app network can reach each other", target));
+            }
+            // `db:5432` gives DB_HOST=db and DB_ADDR=db:5432.
+            let prefix = target.to_ascii_uppercase().replace('-', "_");
+            environment.push(format!("{}_HOST={}", prefix, target));
+            if let Some(port) = port {
+                environment.push(format!("{}_ADDR={}:{}", prefix, target, port));
+            }
+            depends_on.push(target.to_string());
+        }
+        for after in &component.after {
+            if !components.contains_key(after) {
+                return Err(anyhow!("starts after undefined component {}", after));
+            }
+            depends_on.push(after.clone());
+        }
+        
+        let mut ports = Vec::new();
+        for spec in &component.ports {
+            ports.extend(PortMapping::parse(spec)?);
+        }
+        if !ports.is_empty() && component.grants.network == NetworkGrant::None {
+            return Err(anyhow!("publishes ports without network access"));
+        }
+        let volumes = component.grants.dirs.iter()
+            .map(|spec| VolumeSpec::parse(spec))
+            .collect::<Result<Vec<_>, _>>()?;
+        let outbound = component.grants.outbound.as_ref()
+            .map(|rules| rules.iter().map(|rule| rule.parse()).collect::<Result<Vec<HostRule>>>())
+            .transpose()?;
+        let resources = Resources {
+            memory: component.limits.memory.as_deref().map(parse_size).transpose()?,
+            cpus: component.limits.cpus.map(|cpus| cpus.to_string().parse::<Cpus>()).transpose()?,
+        };
+        
+        let service = Service {
+            image,
+            command: component.command.clone(),
+            environment,
+            ports,
+            volumes,
+            depends_on,
+            networks: Vec::new(),
+            working_dir: component.workdir.clone(),
+        };
+        let settings = Settings {
+            module,
+            invoke: component.invoke.clone(),
+            network: component.grants.network,
+            outbound,
+            resources,
+        };
+        Ok((service, settings))
+    }
+    
+    pub fn compose(&self) -> &Compose {
+        &self.compose
+    }
+    
+    pub fn project(&self) -> &str {
+        self.compose.project()
+    }
+    
+    /// Each component's image: pulled, or made from its module.
+    pub async fn images(&self, image_manager: &ImageManager) -> Result<HashMap<String, ImageData>> {
+        let mut images = HashMap::new();
+        for (name, service) in self.compose.services() {
+            let image = match &self.settings[name].module {
+                Some(module) => module_image(name, module)?,
+                None => {
+                    info!("Pulling image {} for component {}", service.image, name);
+                    image_manager.get_or_pull(&service.image).await?
+                }
+            };
+            images.insert(name.clone(), image);
+        }
+        Ok(images)
+    }
+    
+    /// Starts the components, each after the ones it uses, and runs them
+    /// until they exit.
+    pub async fn apply(&self, state_dir: &Path, images: &HashMap<String, ImageData>) -> Result<()> {
+        info!("Applying app {}", self.project());
+        self.compose.up_with(state_dir, images, |name, container, networks| self.configure(name, container, networks)).await
+    }
+    
+    fn configure(&self, name: &str, container: &mut Container, networks: &[Network]) -> Result<()> {
+        let settings = &self.settings[name];
+        if let Some(export) = &settings.invoke {
+            container.set_export(export);
+        }
+        container.set_resources(settings.resources);
+        match settings.network {
+            NetworkGrant::None => container.set_networks(vec![NO_NETWORK.to_string()]),
+            NetworkGrant::Host => container.set_networks(vec![HOST_NETWORK.to_string()]),
+            NetworkGrant::App => {}
+        }
+        if let Some(outbound) = &settings.outbound {
+            // The rest of the app stays reachable.
+            let mut allow = outbound.clone();
+            for network in networks {
+                allow.extend(network.subnets().map(|(subnet, _)| HostRule { host: subnet.to_string(), port: None }));
+            }
+            container.set_egress_policy(EgressPolicy { default: EgressDefault::Deny, allow, deny: Vec::new() });
+        }
+        Ok(())
+    }
+    
+    /// Removes the app's containers and network, and with `volumes` its
+    /// named volumes too.
+    pub async fn destroy(&self, state_dir: &Path, volumes: bool) -> Result<()> {
+        Compose::down(state_dir, self.project()).await?;
+        if volumes {
+            let manager = VolumeManager::with_dir(state_dir.join("volumes"))?;
+            let names: Vec<String> = self.compose.services().values()
+                .flat_map(|service| &service.volumes)
+                .filter_map(|spec| match &spec.source {
+                    VolumeSource::Named(volume) => Some(self.compose.volume_name(volume)),
+                    VolumeSource::HostPath(_) => None,
+                })
+                .collect();
+            for name in names {
+                if manager.inspect(&name).is_ok() {
+                    manager.remove(&name)?;
+                }
+            }
+        }
+        Ok(())
+    }
+    
+    /// Each component's container, in start order, if the app is applied.
+    pub async fn status(&self, state_dir: &Path) -> Result<Vec<ComponentStatus>> {
+        let record = ProjectRecord::load(state_dir, self.project())?
+            .ok_or_else(|| anyhow!("App {} is not applied", self.project()))?;
+        let runtime = WasmRuntime::with_state_dir(state_dir)?;
+        
+        let mut statuses = Vec::new();
+        for name in self.compose.start_order()? {
+            let started = record.containers.iter().find(|container| container.service == name);
+            let status = match started {
+                Some(started) => match runtime.inspect(&started.id).await {
+                    Ok(details) => ComponentStatus {
+                        component: name.to_string(),
+                        container: details.info.field("Names"),
+                        status: details.info.field("Status"),
+                        ports: details.info.field("Ports"),
+                    },
+                    Err(_) => ComponentStatus {
+                        component: name.to_string(),
+                        container: started.id.clone(),
+                        status: "Removed".to_string(),
+                        ports: String::new(),
+                    },
+                },
+                None => ComponentStatus {
+                    component: name.to_string(),
+                    container: String::new(),
+                    status: "Pending".to_string(),
+                    ports: String::new(),
+                },
+            };
+            statuses.push(status);
+        }
+        Ok(statuses)
+    }
+}
+
+// An image holding just a module from disk.
+fn module_image(component: &str, module: &Path) -> Result<ImageData> {
+    let path = fs::canonicalize(module)
+        .map_err(|e| anyhow!("Module {:?} of component {} is not accessible: {}", module, component, e))?;
+    Ok(ImageData {
+        name: path.to_string_lossy().into_owned(),
+        tag: "local".to_string(),
+        layers: Vec::new(),
+        config: ImageConfig {
+            env: Vec::new(),
+            cmd: Vec::new(),
+            entrypoint: Vec::new(),
+            workdir: "/".to_string(),
+            exposed_ports: HashMap::new(),
+            volumes: HashMap::new(),
+            rootfs: RootFs::default(),
+        },
+        wasm_path: Some(path),
+    })
+}
@@ -0,0 +1,134 @@
+use anyhow::{Result, anyhow};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use tracing::warn;
+
+// The file next to a manifest that variables are also read from.
+pub const ENV_FILE: &str = ".env";
+
+// The variables manifests in `dir` are interpolated with: those in its
+// `.env` file, overridden by the environment.
+pub fn variables(dir: &Path) -> Result<HashMap<String, String>> {
+    let path = dir.join(ENV_FILE);
+    let mut variables = if path.is_file() { read_env_file(&path)? } else { HashMap::new() };
+    variables.extend(std::env::vars());
+    Ok(variables)
+}
+
+// A `.env` file: `KEY=VALUE` lines, optionally prefixed with `export` and
+// with the value in quotes. Blank lines and `#` comments are skipped.
+pub fn read_env_file(path: &Path) -> Result<HashMap<String, String>> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| anyhow!("Failed to read env file {:?}: {}", path, e))?;
+    let mut variables = HashMap::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (key, value) = line.split_once('=')
+            .filter(|(key, _)| is_name(key.trim()))
+            .ok_or_else(|| anyhow!("Invalid line {} in env file {:?}: expected KEY=VALUE", number + 1, path))?;
+        variables.insert(key.trim().to_string(), unquote(value.trim()).to_string());
+    }
+    Ok(variables)
+}
+
+// Substitutes `$VAR`, `${VAR}`, `${VAR:-default}` (also when VAR is empty),
+// `${VAR-default}` (only when VAR is unset), `${VAR:?message}` and
+// `${VAR?message}` (failing with the message instead) as compose does.
+// `$$` is a literal `$`. Unset variables without a default are empty.
+pub fn interpolate(text: &str, variables: &HashMap<String, String>) -> Result<String> {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('$') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+        
+        if let Some(after) = rest.strip_prefix('$') {
+            result.push('$');
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix('{') {
+            let end = closing_brace(after)
+                .ok_or_else(|| anyhow!("Unterminated variable: ${{{}", after))?;
+            result.push_str(&substitute(&after[..end], variables)?);
+            rest = &after[end + 1..];
+        } else {
+            let end = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(rest.len());
+            if end == 0 || rest.starts_with(|c: char| c.is_ascii_digit()) {
+                // Not a variable, e.g. a `$` at the end of a word.
+                result.push('$');
+                continue;
+            }
+            result.push_str(&lookup(&rest[..end], variables));
+            rest = &rest[end..];
+        }
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+// The contents of one `${...}`.
+fn substitute(expression: &str, variables: &HashMap<String, String>) -> Result<String> {
+    let end = expression.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(expression.len());
+    let (name, modifier) = expression.split_at(end);
+    if !is_name(name) {
+        return Err(anyhow!("Invalid variable: ${{{}}}", expression));
+    }
+    
+    let value = variables.get(name);
+    let (unset, argument) = match modifier {
+        "" => return Ok(lookup(name, variables)),
+        _ if modifier.starts_with(":-") || modifier.starts_with(":?") => {
+            (value.is_none_or(|value| value.is_empty()), &modifier[2..])
+        }
+        _ if modifier.starts_with('-') || modifier.starts_with('?') => (value.is_none(), &modifier[1..]),
+        _ => return Err(anyhow!("Invalid variable: ${{{}}}", expression)),
+    };
+    if !unset {
+        return Ok(value.cloned().unwrap_or_default());
+    }
+    // Defaults and messages may use variables themselves.
+    let argument = interpolate(argument, variables)?;
+    if modifier.trim_start_matches(':').starts_with('?') {
+        return Err(anyhow!("Required variable {} is not set: {}", name, argument));
+    }
+    Ok(argument)
+}
+
+fn lookup(name: &str, variables: &HashMap<String, String>) -> String {
+    variables.get(name).cloned().unwrap_or_else(|| {
+        warn!("Variable {} is not set, substituting an empty string", name);
+        String::new()
+    })
+}
+
+// Where the `}` closing a `${` is, skipping nested ones in defaults.
+fn closing_brace(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (index, c) in text.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' if depth == 0 => return Some(index),
+            '}' => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+fn is_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn unquote(value: &str) -> &str {
+    for quote in ['"', '\''] {
+        if let Some(inner) = value.strip_prefix(quote).and_then(|value| value.strip_suffix(quote)) {
+            return inner;
+        }
+    }
+    value
+}
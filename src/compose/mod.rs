@@ -0,0 +1,596 @@
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_yaml::Value;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tokio::task::JoinSet;
+use tracing::{debug, info, warn};
+
+use crate::container::{Container, PortMapping, VolumeMount};
+use crate::image::ImageData;
+use crate::network::Network;
+use crate::network::firewall::NetworkPolicy;
+use crate::runtime::WasmRuntime;
+use crate::state;
+use crate::volume::{VolumeManager, VolumeSource, VolumeSpec};
+
+pub mod interpolate;
+
+// Files `compose` looks for in the current directory, in order.
+pub const DEFAULT_FILES: &[&str] = &["compose.yaml", "compose.yml", "docker-compose.yaml", "docker-compose.yml"];
+// Networks services join when they don't list any.
+pub const DEFAULT_NETWORK: &str = "default";
+
+// How often `up` checks on starting services and on `down`.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+// How long `down` waits for the `up` running the project to exit.
+const DOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+// A Compose file. Only the keys below are understood; others, such as
+// `restart` or `healthcheck`, are ignored.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ComposeFile {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub services: BTreeMap<String, Service>,
+    #[serde(default, deserialize_with = "definitions")]
+    pub networks: BTreeMap<String, Definition>,
+    #[serde(default, deserialize_with = "definitions")]
+    pub volumes: BTreeMap<String, Definition>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Service {
+    pub image: String,
+    // A string is split on whitespace, as no shell runs it.
+    #[serde(default, deserialize_with = "command")]
+    pub command: Option<Vec<String>>,
+    // `KEY=VALUE` pairs; keys given without a value take the host's.
+    #[serde(default, deserialize_with = "environment")]
+    pub environment: Vec<String>,
+    #[serde(default, deserialize_with = "ports")]
+    pub ports: Vec<PortMapping>,
+    #[serde(default, deserialize_with = "volumes")]
+    pub volumes: Vec<VolumeSpec>,
+    // Only the order is taken from the long form; its conditions are not.
+    #[serde(default, deserialize_with = "names")]
+    pub depends_on: Vec<String>,
+    #[serde(default, deserialize_with = "names")]
+    pub networks: Vec<String>,
+    #[serde(default)]
+    pub working_dir: Option<String>,
+}
+
+impl Service {
+    // The project networks the service joins, by their name in the file.
+    pub fn networks(&self) -> Vec<String> {
+        if self.networks.is_empty() {
+            vec![DEFAULT_NETWORK.to_string()]
+        } else {
+            self.networks.clone()
+        }
+    }
+}
+
+// A network or volume declared at the top level. External ones already
+// exist and are used under their own name; the rest are created with the
+// project's name in front.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct Definition {
+    #[serde(default)]
+    pub external: bool,
+    #[serde(default)]
+    pub internal: bool,
+}
+
+// What `compose up` started, kept in the state directory so `compose down`
+// can find it from another process.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProjectRecord {
+    pub name: String,
+    // The process running the project's services.
+    pub pid: u32,
+    // Containers in the order their services started.
+    pub containers: Vec<ServiceContainer>,
+    // Networks created for the project and removed with it.
+    pub networks: Vec<String>,
+    // Set by `compose down` while it tears the project down, so `up` exits
+    // and a failed teardown can be retried.
+    #[serde(default)]
+    pub stopping: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServiceContainer {
+    pub service: String,
+    pub id: String,
+}
+
+impl ProjectRecord {
+    fn path(state_dir: &Path, project: &str) -> PathBuf {
+        state_dir.join("compose").join(format!("{}.json", project))
+    }
+    
+    pub fn load(state_dir: &Path, project: &str) -> Result<Option<Self>> {
+        let path = Self::path(state_dir, project);
+        if !path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_str(&fs::read_to_string(path)?)?))
+    }
+    
+    fn save(&self, state_dir: &Path) -> Result<()> {
+        let path = Self::path(state_dir, &self.name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+    
+    fn remove(state_dir: &Path, project: &str) -> Result<()> {
+        let path = Self::path(state_dir, project);
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+// A Compose file loaded for a project, with relative host paths resolved
+// against the file's directory.
+#[derive(Debug, Clone)]
+pub struct Compose {
+    project: String,
+    dir: PathBuf,
+    file: ComposeFile,
+}
+
+impl Compose {
+    // The first of `DEFAULT_FILES` in `dir`.
+    pub fn find(dir: &Path) -> Result<PathBuf> {
+        DEFAULT_FILES.iter()
+            .map(|name| dir.join(name))
+            .find(|path| path.is_file())
+            .ok_or_else(|| anyhow!("No compose file in {:?} (looked for {})", dir, DEFAULT_FILES.join(", ")))
+    }
+    
+    // The project is named by `project`, else the file's `name`, else the
+    // directory holding the file.
+    pub fn load(path: &Path, project: Option<&str>) -> Result<Self> {
+        let yaml = fs::read_to_string(path)
+            .map_err(|e| anyhow!("Failed to read compose file {:?}: {}", path, e))?;
+        let dir = std::path::absolute(path)?
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        Self::parse(&yaml, &dir, project)
+    }
+    
+    // Variables are substituted first, from the environment and a `.env`
+    // file in `dir`.
+    pub fn parse(yaml: &str, dir: &Path, project: Option<&str>) -> Result<Self> {
+        let yaml = interpolate::interpolate(yaml, &interpolate::variables(dir)?)?;
+        let file: ComposeFile = serde_yaml::from_str(&yaml)
+            .map_err(|e| anyhow!("Invalid compose file: {}", e))?;
+        let project = match project.or(file.name.as_deref()) {
+            Some(project) => project.to_string(),
+            None => Self::dir_project(dir),
+        };
+        Self::new(file, dir, &project)
+    }
+    
+    // The project named after `dir`, for files that don't name one.
+    pub fn dir_project(dir: &Path) -> String {
+        let dir_name = dir.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+        dir_name.to_ascii_lowercase()
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-'))
+            .collect()
+    }
+    
+    // A project from a file built in code, such as one translated from
+    // another format.
+    pub fn new(file: ComposeFile, dir: &Path, project: &str) -> Result<Self> {
+        validate_project_name(project)?;
+        
+        let compose = Self {
+            project: project.to_string(),
+            dir: dir.to_path_buf(),
+            file,
+        };
+        compose.validate()?;
+        Ok(compose)
+    }
+    
+    fn validate(&self) -> Result<()> {
+        if self.file.services.is_empty() {
+            return Err(anyhow!("Compose file defines no services"));
+        }
+        for (name, service) in &self.file.services {
+            if service.image.is_empty() {
+                return Err(anyhow!("Service {} has no image", name));
+            }
+            for network in service.networks() {
+                if network != DEFAULT_NETWORK && !self.file.networks.contains_key(&network) {
+                    return Err(anyhow!("Service {} uses undefined network {}", name, network));
+                }
+            }
+            for spec in &service.volumes {
+                if let VolumeSource::Named(volume) = &spec.source {
+                    if !self.file.volumes.contains_key(volume) {
+                        return Err(anyhow!("Service {} uses undefined volume {}", name, volume));
+                    }
+                }
+            }
+        }
+        self.start_order()?;
+        Ok(())
+    }
+    
+    pub fn project(&self) -> &str {
+        &self.project
+    }
+    
+    pub fn services(&self) -> &BTreeMap<String, Service> {
+        &self.file.services
+    }
+    
+    pub fn service(&self, name: &str) -> Result<&Service> {
+        self.file.services.get(name).ok_or_else(|| anyhow!("No such service: {}", name))
+    }
+    
+    // Services in the order they start: each after the ones it depends
+    // on, and otherwise by name.
+    pub fn start_order(&self) -> Result<Vec<&str>> {
+        let mut pending: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+        for (name, service) in &self.file.services {
+            let mut depends_on = BTreeSet::new();
+            for dependency in &service.depends_on {
+                if !self.file.services.contains_key(dependency) {
+                    return Err(anyhow!("Service {} depends on undefined service {}", name, dependency));
+                }
+                depends_on.insert(dependency.as_str());
+            }
+            pending.insert(name, depends_on);
+        }
+        
+        let mut order = Vec::new();
+        while !pending.is_empty() {
+            let ready = pending.iter()
+                .find(|(_, depends_on)| depends_on.is_empty())
+                .map(|(name, _)| *name);
+            let Some(ready) = ready else {
+                let services: Vec<&str> = pending.keys().copied().collect();
+                return Err(anyhow!("Services depend on each other in a cycle: {}", services.join(", ")));
+            };
+            pending.remove(ready);
+            for depends_on in pending.values_mut() {
+                depends_on.remove(ready);
+            }
+            order.push(ready);
+        }
+        Ok(order)
+    }
+    
+    // The name a network of the file is created under.
+    pub fn network_name(&self, network: &str) -> String {
+        self.scoped_name(self.file.networks.get(network), network)
+    }
+    
+    // The name a volume of the file is created under.
+    pub fn volume_name(&self, volume: &str) -> String {
+        self.scoped_name(self.file.volumes.get(volume), volume)
+    }
+    
+    fn scoped_name(&self, definition: Option<&Definition>, name: &str) -> String {
+        match definition {
+            Some(definition) if definition.external => name.to_string(),
+            _ => format!("{}_{}", self.project, name),
+        }
+    }
+    
+    // The networks of the file in use by a service, by their name in it.
+    fn networks(&self) -> BTreeSet<String> {
+        self.file.services.values().flat_map(Service::networks).collect()
+    }
+    
+    // A container for `service`, named `<project>-<service>-1` and going by
+    // the service's name on the project's networks. Named volumes are
+    // created as needed.
+    pub fn container(&self, service: &str, image: ImageData, volumes: &VolumeManager) -> Result<Container> {
+        let name = service;
+        let service = self.service(name)?;
+        let mut container = Container::new(image, service.command.clone(), service.working_dir.clone(), service.environment.clone())?;
+        container.set_name(&format!("{}-{}-1", self.project, name))?;
+        container.set_hostname(name)?;
+        container.set_networks(service.networks().iter().map(|network| self.network_name(network)).collect());
+        for port_map in &service.ports {
+            container.add_port_mapping(port_map.host_port, port_map.container_port, port_map.protocol.clone());
+        }
+        for spec in &service.volumes {
+            let host_path = match &spec.source {
+                VolumeSource::HostPath(path) => {
+                    let path = self.dir.join(path);
+                    fs::canonicalize(&path)
+                        .map_err(|e| anyhow!("Volume source {:?} of service {} is not accessible: {}", path, name, e))?
+                }
+                VolumeSource::Named(volume) => volumes.create(&self.volume_name(volume))?.mountpoint,
+            };
+            container.add_mount(VolumeMount {
+                host_path,
+                container_path: spec.container_path.clone(),
+                read_only: spec.options.read_only,
+                consistency: spec.options.consistency,
+                mode: spec.options.mode,
+            });
+        }
+        Ok(container)
+    }
+    
+    // Creates the project's networks and starts every service in
+    // dependency order, each once the ones it depends on are running, then
+    // waits for them all to exit. `images` holds each service's image. A
+    // service failing to start stops the rest; `compose down` from another
+    // process ends the wait early.
+    pub async fn up(&self, state_dir: &Path, images: &HashMap<String, ImageData>) -> Result<()> {
+        self.up_with(state_dir, images, |_, _, _| Ok(())).await
+    }
+    
+    // `up`, letting `configure` adjust each service's container before it
+    // starts, given the project's networks.
+    pub async fn up_with<F>(&self, state_dir: &Path, images: &HashMap<String, ImageData>, configure: F) -> Result<()>
+    where
+        F: Fn(&str, &mut Container, &[Network]) -> Result<()>,
+    {
+        if let Some(record) = ProjectRecord::load(state_dir, &self.project)? {
+            if record.pid != std::process::id() && state::process_alive(record.pid) {
+                return Err(anyhow!("Project {} is already up; run compose down first", self.project));
+            }
+            info!("Cleaning up after an earlier run of project {}", self.project);
+            Self::down(state_dir, &self.project).await?;
+        }
+        
+        let runtime = WasmRuntime::with_state_dir(state_dir)?;
+        runtime.join_mesh().await?;
+        let network_manager = runtime.network_manager();
+        let mut record = ProjectRecord {
+            name: self.project.clone(),
+            pid: std::process::id(),
+            ..ProjectRecord::default()
+        };
+        let mut networks = Vec::new();
+        for network in self.networks() {
+            let name = self.network_name(&network);
+            let definition = self.file.networks.get(&network).cloned().unwrap_or_default();
+            if definition.external {
+                networks.push(network_manager.inspect_network(&name).await
+                    .map_err(|_| anyhow!("External network {} does not exist", name))?);
+                continue;
+            }
+            if network_manager.inspect_network(&name).await.is_err() {
+                network_manager.create_network(&name, &[], &[], None).await?;
+                if definition.internal {
+                    network_manager.set_network_policy(&name, NetworkPolicy { internal: true, rules: Vec::new() }).await?;
+                }
+            }
+            networks.push(network_manager.inspect_network(&name).await?);
+            record.networks.push(name);
+        }
+        record.save(state_dir)?;
+        
+        let volumes = VolumeManager::with_dir(state_dir.join("volumes"))?;
+        let mut services = JoinSet::new();
+        for name in self.start_order()? {
+            let image = images.get(name)
+                .cloned()
+                .ok_or_else(|| anyhow!("No image given for service {}", name))?;
+            let mut container = self.container(name, image, &volumes)?;
+            configure(name, &mut container, &networks)?;
+            let container_id = container.id().to_string();
+            record.containers.push(ServiceContainer {
+                service: name.to_string(),
+                id: container_id.clone(),
+            });
+            record.save(state_dir)?;
+            
+            info!("Starting service {} as container {}", name, container_id);
+            let mut service_runtime = WasmRuntime::with_state_dir(state_dir)?;
+            service_runtime.set_network_manager(network_manager.clone());
+            let service = name.to_string();
+            services.spawn(async move { (service, service_runtime.run(container).await) });
+            
+            // Dependents start once the service is running, or has already
+            // run to completion.
+            loop {
+                tokio::select! {
+                    Some(joined) = services.join_next() => finished(joined?)?,
+                    _ = tokio::time::sleep(POLL_INTERVAL) => {}
+                }
+                let status = runtime.inspect(&container_id).await.map(|details| details.info.status);
+                if status.is_ok_and(|status| status != "created") {
+                    break;
+                }
+            }
+        }
+        
+        loop {
+            tokio::select! {
+                joined = services.join_next() => match joined {
+                    Some(joined) => finished(joined?)?,
+                    None => break,
+                },
+                _ = tokio::time::sleep(POLL_INTERVAL) => {
+                    if ProjectRecord::load(state_dir, &self.project)?.is_none_or(|record| record.stopping) {
+                        info!("Project {} was taken down", self.project);
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+    
+    // Removes the project's containers and the networks created for it,
+    // after the `up` running it, if any, has exited. Named volumes are
+    // kept. The record goes last, so a failed teardown can be run again.
+    pub async fn down(state_dir: &Path, project: &str) -> Result<()> {
+        let mut record = ProjectRecord::load(state_dir, project)?
+            .ok_or_else(|| anyhow!("Project {} is not up", project))?;
+        record.stopping = true;
+        record.save(state_dir)?;
+        
+        if record.pid != std::process::id() {
+            let started = Instant::now();
+            while state::process_alive(record.pid) {
+                if started.elapsed() > DOWN_TIMEOUT {
+                    warn!("Project {} is still running in process {}; removing it anyway", project, record.pid);
+                    break;
+                }
+                tokio::time::sleep(POLL_INTERVAL).await;
+            }
+        }
+        
+        let mut runtime = WasmRuntime::with_state_dir(state_dir)?;
+        for container in record.containers.iter().rev() {
+            if runtime.inspect(&container.id).await.is_err() {
+                debug!("Container {} of service {} is already gone", container.id, container.service);
+                continue;
+            }
+            runtime.remove(&container.id, true).await?;
+            info!("Removed service {} (container {})", container.service, container.id);
+        }
+        for network in &record.networks {
+            if runtime.inspect_network(network).await.is_ok() {
+                runtime.remove_network(network).await?;
+            }
+        }
+        ProjectRecord::remove(state_dir, project)
+    }
+}
+
+// Reports a service's run having ended, failing `up` if it failed.
+fn finished((service, result): (String, crate::error::Result<()>)) -> Result<()> {
+    match result {
+        Ok(()) => {
+            info!("Service {} exited", service);
+            Ok(())
+        }
+        Err(e) => Err(anyhow!("Service {} failed: {}", service, e)),
+    }
+}
+
+fn validate_project_name(name: &str) -> Result<()> {
+    let mut chars = name.chars();
+    
+    let valid = match chars.next() {
+        Some(first) => (first.is_ascii_lowercase() || first.is_ascii_digit())
+            && chars.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '_' | '-')),
+        None => false,
+    };
+    
+    if !valid {
+        return Err(anyhow!(
+            "Invalid project name: {:?} (must match [a-z0-9][a-z0-9_-]*)",
+            name
+        ));
+    }
+    Ok(())
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StringOrList {
+    String(String),
+    List(Vec<String>),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ListOrMap {
+    List(Vec<String>),
+    Map(BTreeMap<String, Option<Value>>),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Port {
+    Number(u16),
+    String(String),
+}
+
+fn command<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Vec<String>>, D::Error> {
+    Ok(match Option::<StringOrList>::deserialize(deserializer)? {
+        Some(StringOrList::String(command)) => Some(command.split_whitespace().map(str::to_string).collect()),
+        Some(StringOrList::List(command)) => Some(command),
+        None => None,
+    })
+}
+
+fn environment<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    let from_host = |key: &str| std::env::var(key).ok().map(|value| format!("{}={}", key, value));
+    Ok(match ListOrMap::deserialize(deserializer)? {
+        ListOrMap::List(vars) => vars.into_iter()
+            .filter_map(|var| if var.contains('=') { Some(var) } else { from_host(&var) })
+            .collect(),
+        ListOrMap::Map(vars) => vars.into_iter()
+            .filter_map(|(key, value)| match value {
+                Some(value) => Some(format!("{}={}", key, scalar(&value)?)),
+                None => from_host(&key),
+            })
+            .collect(),
+    })
+}
+
+fn scalar(value: &Value) -> Option<String> {
+    match value {
+        Value::String(value) => Some(value.clone()),
+        Value::Number(value) => Some(value.to_string()),
+        Value::Bool(value) => Some(value.to_string()),
+        _ => None,
+    }
+}
+
+// Ports in the short form: `HOST:CONTAINER[/PROTOCOL]`, or just the
+// container port to publish it on a free host port. Host addresses are not
+// supported.
+fn ports<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<PortMapping>, D::Error> {
+    let mut mappings = Vec::new();
+    for port in Vec::<Port>::deserialize(deserializer)? {
+        let spec = match port {
+            Port::Number(port) => format!("0:{}", port),
+            Port::String(spec) => match spec.matches(':').count() {
+                0 => format!("0:{}", spec),
+                1 => spec,
+                _ => return Err(serde::de::Error::custom(format!("Host addresses in ports are not supported: {}", spec))),
+            },
+        };
+        mappings.extend(PortMapping::parse(&spec).map_err(serde::de::Error::custom)?);
+    }
+    Ok(mappings)
+}
+
+fn volumes<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<VolumeSpec>, D::Error> {
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|spec| VolumeSpec::parse(spec).map_err(serde::de::Error::custom))
+        .collect()
+}
+
+fn names<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    Ok(match ListOrMap::deserialize(deserializer)? {
+        ListOrMap::List(names) => names,
+        ListOrMap::Map(names) => names.into_keys().collect(),
+    })
+}
+
+// Top-level networks and volumes may be declared with no settings at all.
+fn definitions<'de, D: Deserializer<'de>>(deserializer: D) -> Result<BTreeMap<String, Definition>, D::Error> {
+    Ok(BTreeMap::<String, Option<Definition>>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(name, definition)| (name, definition.unwrap_or_default()))
+        .collect())
+}
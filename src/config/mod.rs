@@ -0,0 +1,180 @@
+use anyhow::{Result, anyhow};
+use serde::Deserialize;
+use std::fs;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+
+use crate::container::Cpus;
+use crate::error;
+use crate::network::forward::ForwardOptions;
+use crate::state::store::StateBackend;
+
+const CONFIG_ENV: &str = "WASM_CONTAINER_CONFIG";
+
+// Where the runtime keeps its data, how much of it may be used and the DNS
+// defaults for containers. Every field is optional: unset storage values
+// fall back to the platform defaults in `state`, and unset DNS settings to
+// the host's resolv.conf. Values come from `config.json` in the config directory and can
+// be overridden per-field with `WASM_CONTAINER_*` environment variables.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RuntimeConfig {
+    // Containers, volumes and extracted layers.
+    pub state_dir: Option<PathBuf>,
+    // Pulled images.
+    pub cache_dir: Option<PathBuf>,
+    // Where container records are kept: `json` files, `memory` or a
+    // `sqlite` database. Unset, whatever the state directory already uses.
+    pub state_store: Option<StateBackend>,
+    // Staging area for memory-backed rootfs trees, ideally on tmpfs.
+    pub scratch_dir: Option<PathBuf>,
+    // Maximum size in bytes of a single memory-backed rootfs.
+    #[serde(deserialize_with = "deserialize_size")]
+    pub scratch_size: Option<u64>,
+    // Extracted layers beyond this many bytes are evicted, least recently
+    // used first.
+    #[serde(deserialize_with = "deserialize_size")]
+    pub layer_cache_size: Option<u64>,
+    // Nameservers for containers that don't pass `--dns`.
+    pub dns: Vec<IpAddr>,
+    // Search domains for containers that don't pass `--dns-search`.
+    pub dns_search: Vec<String>,
+    // Guest paths hidden from every container, on top of each container's
+    // own `--mask` paths.
+    pub masked_paths: Vec<PathBuf>,
+    // Proxy settings for containers that don't pass their own: given to
+    // the guest as HTTP_PROXY, HTTPS_PROXY and NO_PROXY, and used for the
+    // connections the host opens on its behalf.
+    pub http_proxy: Option<String>,
+    pub https_proxy: Option<String>,
+    pub no_proxy: Option<String>,
+    // Peering with the runtimes on other hosts: the address to take their
+    // connections on, theirs as `host:port`, and the key all of them
+    // share. The key may come from WASM_CONTAINER_MESH_KEY instead.
+    pub mesh_listen: Option<SocketAddr>,
+    pub mesh_peers: Vec<String>,
+    pub mesh_key: Option<String>,
+    // Port options for containers that don't pass `--port-options`, such
+    // as `idle-timeout=5m,max-connections=100`; the two are merged per option.
+    pub port_options: ForwardOptions,
+    // The most memory and CPUs a container may ask for with `--memory` and
+    // `--cpus`, and what those without limits get: 4G and the host's CPU
+    // count unless set.
+    #[serde(deserialize_with = "deserialize_size")]
+    pub max_memory: Option<u64>,
+    #[serde(deserialize_with = "deserialize_cpus")]
+    pub max_cpus: Option<Cpus>,
+}
+
+impl RuntimeConfig {
+    pub fn load() -> error::Result<Self> {
+        let file = match std::env::var_os(CONFIG_ENV) {
+            Some(path) => Some(PathBuf::from(path)),
+            None => dirs::config_dir().map(|dir| dir.join("wasm-container").join("config.json")),
+        };
+        
+        Self::from_sources(file.as_deref(), |key| std::env::var(key).ok())
+    }
+    
+    // A missing config file is not an error, since every field is optional.
+    pub fn from_sources<F>(file: Option<&Path>, env: F) -> error::Result<Self>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let mut config = match file {
+            Some(path) if path.exists() => {
+                let contents = fs::read_to_string(path)?;
+                serde_json::from_str(&contents)
+                    .map_err(|e| anyhow!("Invalid config file {:?}: {}", path, e))?
+            }
+            _ => Self::default(),
+        };
+        
+        let path = |key: &str| env(key).filter(|v| !v.is_empty()).map(PathBuf::from);
+        config.state_dir = path("WASM_CONTAINER_STATE_DIR").or(config.state_dir);
+        config.cache_dir = path("WASM_CONTAINER_CACHE_DIR").or(config.cache_dir);
+        config.scratch_dir = path("WASM_CONTAINER_SCRATCH_DIR").or(config.scratch_dir);
+        
+        if let Some(backend) = env("WASM_CONTAINER_STATE_STORE").filter(|v| !v.is_empty()) {
+            config.state_store = Some(backend.parse()?);
+        }
+        if let Some(size) = env("WASM_CONTAINER_SCRATCH_SIZE") {
+            config.scratch_size = Some(parse_size(&size)?);
+        }
+        if let Some(size) = env("WASM_CONTAINER_LAYER_CACHE_SIZE") {
+            config.layer_cache_size = Some(parse_size(&size)?);
+        }
+        if let Some(size) = env("WASM_CONTAINER_MAX_MEMORY") {
+            config.max_memory = Some(parse_size(&size)?);
+        }
+        if let Some(cpus) = env("WASM_CONTAINER_MAX_CPUS") {
+            config.max_cpus = Some(cpus.parse()?);
+        }
+        if let Some(key) = env("WASM_CONTAINER_MESH_KEY").filter(|key| !key.is_empty()) {
+            config.mesh_key = Some(key);
+        }
+        
+        Ok(config)
+    }
+}
+
+// Accepts plain byte counts or a binary suffix: `512K`, `64M`, `2G`, `1T`.
+pub fn parse_size(size: &str) -> Result<u64> {
+    let size = size.trim();
+    let (digits, multiplier) = match size.char_indices().last() {
+        Some((i, suffix)) if suffix.is_ascii_alphabetic() => {
+            let shift = match suffix.to_ascii_uppercase() {
+                'K' => 10,
+                'M' => 20,
+                'G' => 30,
+                'T' => 40,
+                _ => return Err(anyhow!("Invalid size: {}", size)),
+            };
+            (&size[..i], 1u64 << shift)
+        }
+        _ => (size, 1),
+    };
+    
+    digits.trim()
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(|| anyhow!("Invalid size: {}", size))
+}
+
+fn deserialize_size<'de, D>(deserializer: D) -> std::result::Result<Option<u64>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Size {
+        Bytes(u64),
+        Text(String),
+    }
+    
+    match Option::<Size>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(Size::Bytes(bytes)) => Ok(Some(bytes)),
+        Some(Size::Text(text)) => parse_size(&text).map(Some).map_err(serde::de::Error::custom),
+    }
+}
+
+// Accepts a number, `2`, or a string, `"0.5"`.
+fn deserialize_cpus<'de, D>(deserializer: D) -> std::result::Result<Option<Cpus>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Count {
+        Number(f64),
+        Text(String),
+    }
+    
+    match Option::<Count>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(Count::Number(cpus)) => cpus.to_string().parse().map(Some).map_err(serde::de::Error::custom),
+        Some(Count::Text(text)) => text.parse().map(Some).map_err(serde::de::Error::custom),
+    }
+}
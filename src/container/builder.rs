@@ -0,0 +1,233 @@
+use anyhow::anyhow;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::PathBuf;
+
+use crate::container::{Consistency, Container, MountMode, PortMapping, Resources, VolumeMount};
+use crate::error;
+use crate::image::ImageData;
+use crate::network::{HOST_NETWORK, NO_NETWORK};
+
+/// Configures a container, checking everything at once in
+/// [`build`](Self::build) so a bad setting fails before the container is
+/// created rather than when it starts.
+///
+/// ```
+/// use wasm_container::{Container, ImageData};
+///
+/// # fn example(image: ImageData) -> anyhow::Result<()> {
+/// let container = Container::builder(image)
+///     .name("web")
+///     .command(["serve", "--port", "80"])
+///     .env("MODE", "production")
+///     .port(8080, 80, "tcp")
+///     .volume("./static", "/srv/static", true)
+///     .build()?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct ContainerBuilder {
+    image: ImageData,
+    command: Option<Vec<String>>,
+    workdir: Option<String>,
+    env: Vec<(String, String)>,
+    name: Option<String>,
+    hostname: Option<String>,
+    labels: BTreeMap<String, String>,
+    volumes: Vec<VolumeMount>,
+    ports: Vec<PortMapping>,
+    networks: Vec<String>,
+    tmpfs: Vec<PathBuf>,
+    resources: Resources,
+    read_only: bool,
+    export: Option<String>,
+}
+
+impl ContainerBuilder {
+    pub fn new(image: ImageData) -> Self {
+        Self {
+            image,
+            command: None,
+            workdir: None,
+            env: Vec::new(),
+            name: None,
+            hostname: None,
+            labels: BTreeMap::new(),
+            volumes: Vec::new(),
+            ports: Vec::new(),
+            networks: Vec::new(),
+            tmpfs: Vec::new(),
+            resources: Resources::default(),
+            read_only: false,
+            export: None,
+        }
+    }
+    
+    /// Replaces the image's command.
+    pub fn command<I, S>(mut self, command: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.command = Some(command.into_iter().map(Into::into).collect());
+        self
+    }
+    
+    /// The absolute directory the guest starts in.
+    pub fn workdir(mut self, workdir: impl Into<String>) -> Self {
+        self.workdir = Some(workdir.into());
+        self
+    }
+    
+    pub fn env(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.env.push((key.into(), value.into()));
+        self
+    }
+    
+    /// Also the hostname, unless [`hostname`](Self::hostname) sets another.
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+    
+    pub fn hostname(mut self, hostname: impl Into<String>) -> Self {
+        self.hostname = Some(hostname.into());
+        self
+    }
+    
+    pub fn label(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.labels.insert(key.into(), value.into());
+        self
+    }
+    
+    /// Mounts an existing host directory or file at an absolute path in the
+    /// guest.
+    pub fn volume(self, host_path: impl Into<PathBuf>, container_path: impl Into<PathBuf>, read_only: bool) -> Self {
+        self.mount(VolumeMount {
+            host_path: host_path.into(),
+            container_path: container_path.into(),
+            read_only,
+            consistency: Consistency::default(),
+            mode: MountMode::default(),
+        })
+    }
+    
+    /// Like [`volume`](Self::volume), with the mount's mode and consistency.
+    pub fn mount(mut self, mount: VolumeMount) -> Self {
+        self.volumes.push(mount);
+        self
+    }
+    
+    /// Publishes `container_port` on `host_port`, or on one the OS picks if
+    /// it is 0. `protocol` is `tcp` or `udp`.
+    pub fn port(mut self, host_port: u16, container_port: u16, protocol: &str) -> Self {
+        self.ports.push(PortMapping { host_port, container_port, protocol: protocol.to_ascii_lowercase() });
+        self
+    }
+    
+    /// Joins `network` instead of the default one; the first joined gives
+    /// the container's address.
+    pub fn network(mut self, network: impl Into<String>) -> Self {
+        self.networks.push(network.into());
+        self
+    }
+    
+    pub fn tmpfs(mut self, container_path: impl Into<PathBuf>) -> Self {
+        self.tmpfs.push(container_path.into());
+        self
+    }
+    
+    pub fn resources(mut self, resources: Resources) -> Self {
+        self.resources = resources;
+        self
+    }
+    
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+    
+    /// Calls this export, which takes and returns nothing, instead of
+    /// `_start`.
+    pub fn export(mut self, export: impl Into<String>) -> Self {
+        self.export = Some(export.into());
+        self
+    }
+    
+    /// The container, or the first setting that is invalid.
+    pub fn build(self) -> error::Result<Container> {
+        for (key, _) in &self.env {
+            if key.is_empty() || key.contains('=') {
+                return Err(anyhow!("Invalid environment variable name: {:?}", key).into());
+            }
+        }
+        if let Some(workdir) = self.workdir.as_deref().filter(|workdir| !workdir.starts_with('/')) {
+            return Err(anyhow!("Working directory must be absolute: {}", workdir).into());
+        }
+        if let Some(mode) = self.networks.iter().find(|network| *network == NO_NETWORK || *network == HOST_NETWORK) {
+            if self.networks.len() > 1 {
+                return Err(anyhow!("Network {} can't be combined with other networks", mode).into());
+            }
+        }
+        
+        let mut published = HashSet::new();
+        for port in &self.ports {
+            if port.protocol != "tcp" && port.protocol != "udp" {
+                return Err(anyhow!("Unsupported protocol for port {}: {} (expected tcp or udp)", port.container_port, port.protocol).into());
+            }
+            if port.container_port == 0 {
+                return Err(anyhow!("Invalid container port: 0").into());
+            }
+            if port.host_port != 0 && !published.insert((port.host_port, port.protocol.as_str())) {
+                return Err(anyhow!("Host port {}/{} is published twice", port.host_port, port.protocol).into());
+            }
+        }
+        
+        let mut volumes = Vec::with_capacity(self.volumes.len());
+        for mut mount in self.volumes {
+            if !mount.container_path.is_absolute() {
+                return Err(anyhow!("Volume destination must be absolute: {:?}", mount.container_path).into());
+            }
+            mount.host_path = fs::canonicalize(&mount.host_path)
+                .map_err(|e| anyhow!("Volume source {:?} is not accessible: {}", mount.host_path, e))?;
+            volumes.push(mount);
+        }
+        if let Some(path) = self.tmpfs.iter().find(|path| !path.is_absolute()) {
+            return Err(anyhow!("tmpfs destination must be absolute: {:?}", path).into());
+        }
+        if self.export.as_deref() == Some("") {
+            return Err(anyhow!("Export name can't be empty").into());
+        }
+        
+        let env = self.env.into_iter().map(|(key, value)| format!("{}={}", key, value)).collect();
+        let mut container = Container::new(self.image, self.command, self.workdir, env)?;
+        if let Some(name) = &self.name {
+            container.set_name(name)?;
+        }
+        if let Some(hostname) = &self.hostname {
+            container.set_hostname(hostname)?;
+        }
+        for (key, value) in &self.labels {
+            container.set_label(key, value);
+        }
+        for mount in volumes {
+            container.add_mount(mount);
+        }
+        for port in self.ports {
+            container.add_port_mapping(port.host_port, port.container_port, port.protocol);
+        }
+        if !self.networks.is_empty() {
+            container.set_networks(self.networks);
+        }
+        for path in self.tmpfs {
+            container.add_tmpfs(path);
+        }
+        container.set_resources(self.resources);
+        container.set_read_only(self.read_only);
+        if let Some(export) = &self.export {
+            container.set_export(export);
+        }
+        Ok(container)
+    }
+}
@@ -1,56 +1,488 @@
-use anyhow::Result;
+use anyhow::{Result, anyhow};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::net::IpAddr;
+use std::ops::RangeInclusive;
 use std::path::PathBuf;
+use std::str::FromStr;
 use uuid::Uuid;
 
+use crate::error;
+use crate::format;
 use crate::image::ImageData;
+use crate::network::{DEFAULT_NETWORK, HOST_NETWORK, NO_NETWORK};
+use crate::network::forward::ForwardOptions;
+use crate::network::netem::Netem;
+use crate::network::policy::EgressPolicy;
+use crate::network::proxy::ProxySettings;
+use crate::network::throttle::{NetworkStats, Rate};
+
+pub mod builder;
+
+use builder::ContainerBuilder;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ContainerInfo {
     pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
     pub image: String,
     pub status: String,
+    // Host ports the running container is published on, with the ones the
+    // OS picked filled in.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ports: Vec<PortMapping>,
+    // The running container's addresses, by network.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub addresses: BTreeMap<String, Vec<IpAddr>>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+    // Unix times: when the container was first run, and when it last
+    // started running.
+    #[serde(default)]
+    pub created_at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub started_at: Option<u64>,
+    // The guest's exit code, once it has exited on its own.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+}
+
+impl ContainerInfo {
+    // What `list --format` can show, and the header of each in a table.
+    pub const FIELDS: &[(&str, &str)] = &[
+        ("ID", "CONTAINER ID"),
+        ("Names", "NAMES"),
+        ("Image", "IMAGE"),
+        ("CreatedAt", "CREATED AT"),
+        ("RunningFor", "CREATED"),
+        ("StartedAt", "STARTED AT"),
+        ("Status", "STATUS"),
+        ("State", "STATE"),
+        ("ExitCode", "EXIT CODE"),
+        ("Ports", "PORTS"),
+        ("Networks", "NETWORKS"),
+        ("Labels", "LABELS"),
+    ];
+    
+    // The value of one of FIELDS, as text; unknown fields are empty.
+    pub fn field(&self, field: &str) -> String {
+        let now = format::unix_now();
+        match field {
+            "ID" => self.id.clone(),
+            "Names" => self.name.clone().unwrap_or_default(),
+            "Image" => self.image.clone(),
+            "CreatedAt" => format::format_timestamp(self.created_at),
+            "RunningFor" => format!("{} ago", format::format_age(now.saturating_sub(self.created_at))),
+            "StartedAt" => self.started_at.map(format::format_timestamp).unwrap_or_default(),
+            "Status" => match (self.status.as_str(), self.started_at, self.exit_code) {
+                ("running", Some(started_at), _) => format!("Up {}", format::format_age(now.saturating_sub(started_at))),
+                (status, _, Some(code)) => format!("{} ({})", capitalize(status), code),
+                (status, _, None) => capitalize(status),
+            },
+            "State" => self.status.clone(),
+            "ExitCode" => self.exit_code.map(|code| code.to_string()).unwrap_or_default(),
+            "Ports" => self.ports.iter().map(ToString::to_string).collect::<Vec<_>>().join(", "),
+            "Networks" => self.addresses.keys().cloned().collect::<Vec<_>>().join(","),
+            "Labels" => self.labels.iter().map(|(key, value)| format!("{}={}", key, value)).collect::<Vec<_>>().join(","),
+            _ => String::new(),
+        }
+    }
+}
+
+fn capitalize(text: &str) -> String {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+// Narrows `list` to some containers: `label=KEY` or `label=KEY=VALUE` for
+// ones with that label, or `name=TEXT` for ones whose name contains TEXT.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ContainerFilter {
+    Label(String, Option<String>),
+    Name(String),
+}
+
+impl ContainerFilter {
+    pub fn matches(&self, info: &ContainerInfo) -> bool {
+        match self {
+            Self::Label(key, value) => match (info.labels.get(key), value) {
+                (Some(actual), Some(value)) => actual == value,
+                (found, None) => found.is_some(),
+                (None, Some(_)) => false,
+            },
+            Self::Name(text) => info.name.as_deref().is_some_and(|name| name.contains(text.as_str())),
+        }
+    }
+}
+
+impl FromStr for ContainerFilter {
+    type Err = anyhow::Error;
+    
+    fn from_str(s: &str) -> Result<Self> {
+        match s.split_once('=') {
+            Some(("label", label)) => {
+                let (key, value) = match label.split_once('=') {
+                    Some((key, value)) => (key, Some(value.to_string())),
+                    None => (label, None),
+                };
+                if key.is_empty() {
+                    return Err(anyhow!("Invalid filter: {} (label needs a key)", s));
+                }
+                Ok(Self::Label(key.to_string(), value))
+            }
+            Some(("name", name)) if !name.is_empty() => Ok(Self::Name(name.to_string())),
+            _ => Err(anyhow!("Invalid filter: {} (expected label=KEY[=VALUE] or name=NAME)", s)),
+        }
+    }
+}
+
+// A `KEY=VALUE` label; a bare `KEY` has an empty value.
+pub fn parse_label(s: &str) -> Result<(String, String)> {
+    let (key, value) = s.split_once('=').unwrap_or((s, ""));
+    if key.is_empty() {
+        return Err(anyhow!("Invalid label: {:?} (expected KEY=VALUE)", s));
+    }
+    Ok((key.to_string(), value.to_string()))
+}
+
+// What `inspect` shows of a container: its info and, while it runs, the
+// traffic through its published ports.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContainerDetails {
+    #[serde(flatten)]
+    pub info: ContainerInfo,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub network: Option<NetworkStats>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Container {
     id: String,
+    // Unique among the containers in the state directory, and accepted
+    // wherever the ID is.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
     image: ImageData,
     command: Option<Vec<String>>,
     workdir: Option<String>,
     env_vars: HashMap<String, String>,
     volumes: Vec<VolumeMount>,
     network_config: NetworkConfig,
+    #[serde(default)]
+    rootfs_backend: RootfsBackend,
+    #[serde(default)]
+    read_only: bool,
+    #[serde(default)]
+    tmpfs: Vec<PathBuf>,
+    #[serde(default)]
+    masked_paths: Vec<PathBuf>,
+    #[serde(default, skip_serializing_if = "Resources::is_empty")]
+    resources: Resources,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    labels: BTreeMap<String, String>,
+    // The exported function the run calls instead of `_start`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    export: Option<String>,
+}
+
+// What the guest may use; unset limits are the runtime's host maximums.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Resources {
+    // Bytes of linear memory.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpus: Option<Cpus>,
+}
+
+impl Resources {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+// A number of CPUs, possibly fractional like `0.5`, kept in thousandths.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Cpus(u32);
+
+impl Cpus {
+    pub fn from_millis(millis: u32) -> Self {
+        Self(millis)
+    }
+    
+    // The host's CPU count.
+    pub fn host() -> Self {
+        let count = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+        Self(u32::try_from(count).unwrap_or(u32::MAX / 1000).saturating_mul(1000))
+    }
+    
+    pub fn millis(&self) -> u32 {
+        self.0
+    }
+    
+    pub fn as_f64(&self) -> f64 {
+        f64::from(self.0) / 1000.0
+    }
+    
+    // Whole CPUs a guest sees, rounding fractions up.
+    pub fn count(&self) -> usize {
+        self.0.div_ceil(1000) as usize
+    }
 }
 
-#[derive(Debug)]
+impl FromStr for Cpus {
+    type Err = anyhow::Error;
+    
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || anyhow!("Invalid CPU count: {:?} (expected a number above zero, e.g. 0.5 or 2)", s);
+        let cpus: f64 = s.trim().parse().map_err(|_| invalid())?;
+        let millis = (cpus * 1000.0).round();
+        if !cpus.is_finite() || millis < 1.0 || millis > f64::from(u32::MAX) {
+            return Err(invalid());
+        }
+        Ok(Self(millis as u32))
+    }
+}
+
+impl TryFrom<String> for Cpus {
+    type Error = anyhow::Error;
+    
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<Cpus> for String {
+    fn from(cpus: Cpus) -> Self {
+        cpus.to_string()
+    }
+}
+
+impl fmt::Display for Cpus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 % 1000 {
+            0 => write!(f, "{}", self.0 / 1000),
+            fraction => {
+                let fraction = format!("{:03}", fraction);
+                write!(f, "{}.{}", self.0 / 1000, fraction.trim_end_matches('0'))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RootfsBackend {
+    #[default]
+    Disk,
+    Memory,
+}
+
+impl FromStr for RootfsBackend {
+    type Err = anyhow::Error;
+    
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "disk" => Ok(Self::Disk),
+            "memory" => Ok(Self::Memory),
+            _ => Err(anyhow!("Unknown rootfs backend: {} (expected disk or memory)", s)),
+        }
+    }
+}
+
+impl fmt::Display for RootfsBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Disk => write!(f, "disk"),
+            Self::Memory => write!(f, "memory"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct VolumeMount {
     pub host_path: PathBuf,
     pub container_path: PathBuf,
     pub read_only: bool,
+    #[serde(default)]
+    pub consistency: Consistency,
+    #[serde(default)]
+    pub mode: MountMode,
+}
+
+// How a directory volume is exposed to the guest. Single files are always
+// copied, since WASI can only preopen directories.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MountMode {
+    // Shared directly through a WASI preopen.
+    #[default]
+    Bind,
+    // Copied into the rootfs once when the container starts.
+    Copy,
+    // Copied, then host-side changes are propagated while the guest runs.
+    Sync,
+    // Like `Sync`, with guest changes also propagated back to the host.
+    SyncBack,
+}
+
+impl fmt::Display for MountMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Bind => write!(f, "bind"),
+            Self::Copy => write!(f, "copy"),
+            Self::Sync => write!(f, "sync"),
+            Self::SyncBack => write!(f, "sync-back"),
+        }
+    }
 }
 
-#[derive(Debug)]
+// How eagerly a copied (single-file) mount is synced while the container
+// runs. Directory mounts are preopened and always live.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Consistency {
+    // Changes on either side are propagated as they happen.
+    #[default]
+    Consistent,
+    // Host changes are propagated live; guest writes reach the host on exit.
+    Cached,
+    // Nothing is propagated until the container exits.
+    Delegated,
+}
+
+impl fmt::Display for Consistency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Consistent => write!(f, "consistent"),
+            Self::Cached => write!(f, "cached"),
+            Self::Delegated => write!(f, "delegated"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct NetworkConfig {
     pub hostname: String,
     pub ports: Vec<PortMapping>,
+    #[serde(default)]
+    pub dns: Vec<IpAddr>,
+    #[serde(default)]
+    pub dns_search: Vec<String>,
+    // Networks joined on start, the first giving the container's own
+    // address.
+    #[serde(default = "default_networks")]
+    pub networks: Vec<String>,
+    // A fixed address on the first network instead of the next free one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ip: Option<IpAddr>,
+    #[serde(default)]
+    pub egress: EgressPolicy,
+    // Bandwidth of the published ports in each direction.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate: Option<Rate>,
+    // Impairments applied to the published ports, for testing.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub netem: Option<Netem>,
+    // Idle timeout, keep-alive and connection limit of each published port.
+    #[serde(default, skip_serializing_if = "ForwardOptions::is_empty")]
+    pub port_options: ForwardOptions,
+    // Overrides the runtime's configured proxy settings.
+    #[serde(default, skip_serializing_if = "ProxySettings::is_empty")]
+    pub proxy: ProxySettings,
+    // Whether published ports are advertised over mDNS/DNS-SD.
+    #[serde(default)]
+    pub mdns: bool,
+    // Whether a host port that is in use is swapped for the next free one
+    // instead of failing the start.
+    #[serde(default)]
+    pub publish_retry: bool,
 }
 
-#[derive(Debug, Clone)]
+fn default_networks() -> Vec<String> {
+    vec![DEFAULT_NETWORK.to_string()]
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PortMapping {
     pub host_port: u16,
     pub container_port: u16,
     pub protocol: String,
 }
 
+impl PortMapping {
+    // Expands a `-p` specification, `HOST:CONTAINER[/PROTOCOL]`, into one
+    // mapping per port. Both sides may be ranges such as `8000-8010`, as
+    // long as they are the same length. A host port of 0 publishes each
+    // container port on a free port the OS picks.
+    pub fn parse(spec: &str) -> error::Result<Vec<Self>> {
+        let invalid = || anyhow!("Invalid port specification: {} (expected HOST:CONTAINER[/tcp|udp])", spec);
+        
+        let (ports, protocol) = match spec.split_once('/') {
+            Some((ports, protocol)) => (ports, protocol.to_ascii_lowercase()),
+            None => (spec, "tcp".to_string()),
+        };
+        if protocol != "tcp" && protocol != "udp" {
+            return Err(anyhow!("Unsupported protocol in {}: {} (expected tcp or udp)", spec, protocol).into());
+        }
+        
+        let (host, container) = ports.split_once(':').ok_or_else(invalid)?;
+        let container = parse_port_range(container).ok_or_else(invalid)?;
+        if host == "0" {
+            return Ok(container
+                .map(|container_port| Self {
+                    host_port: 0,
+                    container_port,
+                    protocol: protocol.clone(),
+                })
+                .collect());
+        }
+        let host = parse_port_range(host).ok_or_else(invalid)?;
+        if host.len() != container.len() {
+            return Err(anyhow!(
+                "Port ranges in {} differ in length: {} host ports for {} container ports",
+                spec,
+                host.len(),
+                container.len()
+            ).into());
+        }
+        
+        Ok(host.zip(container)
+            .map(|(host_port, container_port)| Self {
+                host_port,
+                container_port,
+                protocol: protocol.clone(),
+            })
+            .collect())
+    }
+}
+
+impl fmt::Display for PortMapping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}->{}/{}", self.host_port, self.container_port, self.protocol)
+    }
+}
+
+// A port or an inclusive range of them, `START-END`.
+fn parse_port_range(range: &str) -> Option<RangeInclusive<u16>> {
+    let (start, end) = range.split_once('-').unwrap_or((range, range));
+    let (start, end): (u16, u16) = (start.parse().ok()?, end.parse().ok()?);
+    (start != 0 && start <= end).then_some(start..=end)
+}
+
 impl Container {
     pub fn new(
         image: ImageData,
         command: Option<Vec<String>>,
         workdir: Option<String>,
         env: Vec<String>,
-    ) -> Result<Self> {
+    ) -> error::Result<Self> {
         let id = Uuid::new_v4().to_string();
         
         let mut env_vars = HashMap::new();
@@ -65,6 +497,7 @@ impl Container {
         
         Ok(Self {
             id: id.clone(),
+            name: None,
             image,
             command,
             workdir,
@@ -73,14 +506,59 @@ impl Container {
             network_config: NetworkConfig {
                 hostname: id,
                 ports: Vec::new(),
+                dns: Vec::new(),
+                dns_search: Vec::new(),
+                networks: default_networks(),
+                ip: None,
+                egress: EgressPolicy::default(),
+                rate: None,
+                netem: None,
+                port_options: ForwardOptions::default(),
+                proxy: ProxySettings::default(),
+                mdns: false,
+                publish_retry: false,
             },
+            rootfs_backend: RootfsBackend::default(),
+            read_only: false,
+            tmpfs: Vec::new(),
+            masked_paths: Vec::new(),
+            resources: Resources::default(),
+            labels: BTreeMap::new(),
+            export: None,
         })
     }
     
+    /// A builder for containers with more than `new` takes.
+    pub fn builder(image: ImageData) -> ContainerBuilder {
+        ContainerBuilder::new(image)
+    }
+    
     pub fn id(&self) -> &str {
         &self.id
     }
     
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+    
+    // Also makes the name the container's hostname, so peers on its
+    // networks can reach it by name.
+    pub fn set_name(&mut self, name: &str) -> error::Result<()> {
+        let mut chars = name.chars();
+        let valid = match chars.next() {
+            Some(first) => first.is_ascii_alphanumeric()
+                && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-')),
+            None => false,
+        };
+        if !valid {
+            return Err(anyhow!("Invalid container name: {:?} (must match [a-zA-Z0-9][a-zA-Z0-9_.-]*)", name).into());
+        }
+        
+        self.name = Some(name.to_string());
+        self.network_config.hostname = name.to_string();
+        Ok(())
+    }
+    
     pub fn image_name(&self) -> &str {
         &self.image.name
     }
@@ -97,18 +575,24 @@ impl Container {
         &self.env_vars
     }
     
-    pub async fn get_wasm_binary(&self) -> Result<Vec<u8>> {
-        self.image.get_wasm_binary().await
+    pub async fn get_wasm_binary(&self) -> error::Result<Vec<u8>> {
+        Ok(self.image.get_wasm_binary().await?)
     }
     
     pub fn add_volume(&mut self, host_path: PathBuf, container_path: PathBuf, read_only: bool) {
-        self.volumes.push(VolumeMount {
+        self.add_mount(VolumeMount {
             host_path,
             container_path,
             read_only,
+            consistency: Consistency::default(),
+            mode: MountMode::default(),
         });
     }
     
+    pub fn add_mount(&mut self, mount: VolumeMount) {
+        self.volumes.push(mount);
+    }
+    
     pub fn add_port_mapping(&mut self, host_port: u16, container_port: u16, protocol: String) {
         self.network_config.ports.push(PortMapping {
             host_port,
@@ -117,6 +601,120 @@ impl Container {
         });
     }
     
+    // Publishes every port the image exposes on a free host port, skipping
+    // those already published.
+    pub fn publish_exposed_ports(&mut self) -> error::Result<()> {
+        let mut exposed = Vec::new();
+        for port in self.image.config.exposed_ports.keys() {
+            let (number, protocol) = port.split_once('/').unwrap_or((port, "tcp"));
+            let number: u16 = number.parse().ok()
+                .filter(|number| *number != 0)
+                .ok_or_else(|| anyhow!("Invalid exposed port in image config: {}", port))?;
+            exposed.push((number, protocol.to_ascii_lowercase()));
+        }
+        exposed.sort();
+        
+        for (container_port, protocol) in exposed {
+            let published = self.network_config.ports.iter()
+                .any(|p| p.container_port == container_port && p.protocol == protocol);
+            if !published {
+                self.add_port_mapping(0, container_port, protocol);
+            }
+        }
+        
+        Ok(())
+    }
+    
+    pub fn add_dns_server(&mut self, server: IpAddr) {
+        self.network_config.dns.push(server);
+    }
+    
+    pub fn add_dns_search(&mut self, domain: String) {
+        self.network_config.dns_search.push(domain);
+    }
+    
+    pub fn networks(&self) -> &[String] {
+        &self.network_config.networks
+    }
+    
+    pub fn set_networks(&mut self, networks: Vec<String>) {
+        self.network_config.networks = networks;
+    }
+    
+    // The name the container goes by in its peers' DNS, its HOSTNAME,
+    // /etc/hostname and /etc/hosts, instead of its ID.
+    pub fn set_hostname(&mut self, hostname: &str) -> error::Result<()> {
+        let valid = hostname.len() <= 253 && hostname.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_'))
+        });
+        if !valid {
+            return Err(anyhow!("Invalid hostname: {:?}", hostname).into());
+        }
+        
+        self.network_config.hostname = hostname.to_string();
+        Ok(())
+    }
+    
+    pub fn set_ip(&mut self, ip: IpAddr) {
+        self.network_config.ip = Some(ip);
+    }
+    
+    pub fn set_egress_policy(&mut self, egress: EgressPolicy) {
+        self.network_config.egress = egress;
+    }
+    
+    pub fn set_network_rate(&mut self, rate: Rate) {
+        self.network_config.rate = Some(rate);
+    }
+    
+    pub fn set_netem(&mut self, netem: Netem) {
+        self.network_config.netem = Some(netem);
+    }
+    
+    pub fn set_port_options(&mut self, options: ForwardOptions) {
+        self.network_config.port_options = options;
+    }
+    
+    pub fn set_proxy(&mut self, proxy: ProxySettings) {
+        self.network_config.proxy = proxy;
+    }
+    
+    pub fn set_mdns(&mut self, mdns: bool) {
+        self.network_config.mdns = mdns;
+    }
+    
+    pub fn set_publish_retry(&mut self, retry: bool) {
+        self.network_config.publish_retry = retry;
+    }
+    
+    pub fn network_disabled(&self) -> bool {
+        self.network_config.networks.iter().any(|name| name == NO_NETWORK)
+    }
+    
+    pub fn host_network(&self) -> bool {
+        self.network_config.networks.iter().any(|name| name == HOST_NETWORK)
+    }
+    
+    // Returns false if the container was already connected.
+    pub fn connect_network(&mut self, network: &str) -> bool {
+        if self.network_config.networks.iter().any(|name| name == network) {
+            return false;
+        }
+        self.network_config.networks.push(network.to_string());
+        true
+    }
+    
+    // Returns false if the container wasn't connected.
+    pub fn disconnect_network(&mut self, network: &str) -> bool {
+        let before = self.network_config.networks.len();
+        self.network_config.networks.retain(|name| name != network);
+        self.network_config.networks.len() != before
+    }
+    
     pub fn volumes(&self) -> &[VolumeMount] {
         &self.volumes
     }
@@ -125,7 +723,66 @@ impl Container {
         &self.network_config
     }
     
+    pub fn rootfs_backend(&self) -> RootfsBackend {
+        self.rootfs_backend
+    }
+    
+    pub fn set_rootfs_backend(&mut self, backend: RootfsBackend) {
+        self.rootfs_backend = backend;
+    }
+    
+    pub fn read_only(&self) -> bool {
+        self.read_only
+    }
+    
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+    }
+    
+    pub fn tmpfs(&self) -> &[PathBuf] {
+        &self.tmpfs
+    }
+    
+    pub fn add_tmpfs(&mut self, container_path: PathBuf) {
+        self.tmpfs.push(container_path);
+    }
+    
+    pub fn masked_paths(&self) -> &[PathBuf] {
+        &self.masked_paths
+    }
+    
+    // Masked files read as empty and masked directories list as empty,
+    // whichever preopen the guest reaches them through.
+    pub fn add_masked_path(&mut self, container_path: PathBuf) {
+        self.masked_paths.push(container_path);
+    }
+    
+    pub fn resources(&self) -> Resources {
+        self.resources
+    }
+    
+    pub fn set_resources(&mut self, resources: Resources) {
+        self.resources = resources;
+    }
+    
+    pub fn labels(&self) -> &BTreeMap<String, String> {
+        &self.labels
+    }
+    
+    pub fn set_label(&mut self, key: &str, value: &str) {
+        self.labels.insert(key.to_string(), value.to_string());
+    }
+    
     pub fn image_data(&self) -> &ImageData {
         &self.image
     }
+    
+    pub fn export(&self) -> &str {
+        self.export.as_deref().unwrap_or("_start")
+    }
+    
+    // The function must take and return nothing, like `_start`.
+    pub fn set_export(&mut self, export: &str) {
+        self.export = Some(export.to_string());
+    }
 }
\ No newline at end of file
@@ -0,0 +1,299 @@
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+
+use crate::container::{Container, ContainerInfo, Cpus, PortMapping, Resources, VolumeMount};
+use crate::format;
+use crate::volume::{VolumeManager, VolumeSource, VolumeSpec};
+
+use super::Exit;
+
+// How much of a container's ID stands in for the name of an unnamed one.
+const SHORT_ID: usize = 12;
+
+// The body of `POST /containers/create`, with the fields wasm containers
+// can honor. Docker clients send nulls for most fields they don't set.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "PascalCase", default)]
+pub struct CreateRequest {
+    pub image: String,
+    pub cmd: Option<Vec<String>>,
+    pub entrypoint: Option<Vec<String>>,
+    pub env: Option<Vec<String>>,
+    pub working_dir: Option<String>,
+    pub hostname: Option<String>,
+    pub labels: Option<BTreeMap<String, String>>,
+    pub tty: bool,
+    pub open_stdin: bool,
+    pub host_config: Option<HostConfig>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "PascalCase", default)]
+pub struct HostConfig {
+    pub binds: Option<Vec<String>>,
+    pub port_bindings: Option<HashMap<String, Option<Vec<PortBinding>>>>,
+    pub publish_all_ports: bool,
+    pub network_mode: Option<String>,
+    pub auto_remove: bool,
+    pub memory: u64,
+    pub nano_cpus: u64,
+    pub readonly_rootfs: bool,
+    pub tmpfs: Option<HashMap<String, String>>,
+    pub dns: Option<Vec<IpAddr>>,
+    pub dns_search: Option<Vec<String>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "PascalCase", default)]
+pub struct PortBinding {
+    pub host_ip: String,
+    pub host_port: String,
+}
+
+impl CreateRequest {
+    // Applies everything but the image, which the caller pulls, to a new
+    // container. Named volumes are created in `volumes` as needed.
+    pub fn apply(&self, container: &mut Container, volumes: &VolumeManager) -> Result<()> {
+        // Guests get no terminal, and stdin is never attached.
+        if self.tty {
+            return Err(anyhow!("TTYs aren't supported; run without -t"));
+        }
+        if self.open_stdin {
+            return Err(anyhow!("Attaching stdin isn't supported; run without -i"));
+        }
+        
+        if let Some(hostname) = self.hostname.as_deref().filter(|hostname| !hostname.is_empty()) {
+            container.set_hostname(hostname)?;
+        }
+        for (key, value) in self.labels.iter().flatten() {
+            container.set_label(key, value);
+        }
+        
+        let Some(host_config) = &self.host_config else {
+            return Ok(());
+        };
+        container.set_read_only(host_config.readonly_rootfs);
+        container.set_resources(Resources {
+            memory: Some(host_config.memory).filter(|memory| *memory > 0),
+            cpus: Some(host_config.nano_cpus / 1_000_000)
+                .filter(|millis| *millis > 0)
+                .map(|millis| Cpus::from_millis(millis as u32)),
+        });
+        for path in host_config.tmpfs.iter().flat_map(HashMap::keys) {
+            container.add_tmpfs(path.into());
+        }
+        for server in host_config.dns.iter().flatten() {
+            container.add_dns_server(*server);
+        }
+        for domain in host_config.dns_search.iter().flatten() {
+            container.add_dns_search(domain.clone());
+        }
+        
+        // `default` is the bridge, as it is for docker, and `none` and
+        // `host` mean the same here.
+        match host_config.network_mode.as_deref() {
+            None | Some("") | Some("default") => {}
+            Some(network) => container.set_networks(vec![network.to_string()]),
+        }
+        
+        // Bindings are keyed by the container side, e.g. `80/tcp`, and an
+        // empty host port means any free one.
+        for (container_port, bindings) in host_config.port_bindings.iter().flatten() {
+            for binding in bindings.iter().flatten() {
+                let spec = match (binding.host_ip.as_str(), binding.host_port.as_str()) {
+                    ("", "") => container_port.clone(),
+                    ("", host_port) => format!("{}:{}", host_port, container_port),
+                    (host_ip, host_port) => format!("{}:{}:{}", host_ip, host_port, container_port),
+                };
+                for port_map in PortMapping::parse(&spec)? {
+                    container.add_port_mapping(port_map.host_port, port_map.container_port, port_map.protocol);
+                }
+            }
+        }
+        if host_config.publish_all_ports {
+            container.publish_exposed_ports()?;
+        }
+        
+        for spec in host_config.binds.iter().flatten() {
+            add_bind(container, volumes, spec)?;
+        }
+        
+        Ok(())
+    }
+    
+    // The command the guest runs, entrypoint first, if either is set.
+    pub fn command(&self) -> Option<Vec<String>> {
+        match (&self.entrypoint, &self.cmd) {
+            (None, None) => None,
+            (entrypoint, cmd) => Some(entrypoint.iter().chain(cmd).flatten().cloned().collect()),
+        }
+    }
+    
+    pub fn auto_remove(&self) -> bool {
+        self.host_config.as_ref().is_some_and(|host_config| host_config.auto_remove)
+    }
+}
+
+// Mounts a `-v` style spec, creating the named volume it names if needed.
+pub(super) fn add_bind(container: &mut Container, volumes: &VolumeManager, spec: &str) -> Result<()> {
+    let spec = VolumeSpec::parse(spec)?;
+    let host_path = match spec.source {
+        VolumeSource::HostPath(path) => std::fs::canonicalize(&path)
+            .map_err(|e| anyhow!("Volume source {:?} is not accessible: {}", path, e))?,
+        VolumeSource::Named(name) => volumes.create(&name)?.mountpoint,
+    };
+    container.add_mount(VolumeMount {
+        host_path,
+        container_path: spec.container_path,
+        read_only: spec.options.read_only,
+        consistency: spec.options.consistency,
+        mode: spec.options.mode,
+    });
+    Ok(())
+}
+
+// Docker has containers that were stopped or failed as exited.
+pub fn state(info: &ContainerInfo) -> &'static str {
+    match info.status.as_str() {
+        "created" => "created",
+        "running" | "stopping" => "running",
+        _ => "exited",
+    }
+}
+
+// Unnamed containers go by their short ID.
+pub(super) fn name(info: &ContainerInfo) -> String {
+    match &info.name {
+        Some(name) => name.clone(),
+        None => info.id.chars().take(SHORT_ID).collect(),
+    }
+}
+
+// The leading slash is how docker names containers.
+fn names(info: &ContainerInfo) -> String {
+    format!("/{}", name(info))
+}
+
+fn ports(info: &ContainerInfo) -> Vec<Value> {
+    info.ports.iter()
+        .map(|port| json!({
+            "IP": "0.0.0.0",
+            "PrivatePort": port.container_port,
+            "PublicPort": port.host_port,
+            "Type": port.protocol,
+        }))
+        .collect()
+}
+
+fn networks(info: &ContainerInfo, container: &Container) -> Value {
+    let networks: serde_json::Map<String, Value> = container.networks().iter()
+        .map(|network| {
+            let address = info.addresses.get(network)
+                .and_then(|addresses| addresses.iter().find(|address| address.is_ipv4()))
+                .map(ToString::to_string)
+                .unwrap_or_default();
+            (network.clone(), json!({ "IPAddress": address }))
+        })
+        .collect();
+    Value::Object(networks)
+}
+
+// The reply to `POST /containers/{id}/wait`.
+pub(super) fn wait_body(exit: &Exit) -> String {
+    json!({
+        "StatusCode": exit.code,
+        "Error": exit.error.as_ref().map(|message| json!({ "Message": message })),
+    }).to_string()
+}
+
+// An entry of `GET /containers/json`.
+pub fn summary(info: &ContainerInfo, container: &Container) -> Value {
+    json!({
+        "Id": info.id,
+        "Names": [names(info)],
+        "Image": info.image,
+        "ImageID": "",
+        "Command": container.command().map(|command| command.join(" ")).unwrap_or_default(),
+        "Created": info.created_at,
+        "Ports": ports(info),
+        "Labels": info.labels,
+        "State": state(info),
+        "Status": info.field("Status"),
+        "HostConfig": { "NetworkMode": container.networks().first().cloned().unwrap_or_default() },
+        "NetworkSettings": { "Networks": networks(info, container) },
+        "Mounts": [],
+    })
+}
+
+// The reply to `GET /containers/{id}/json`.
+pub fn inspect(info: &ContainerInfo, container: &Container, auto_remove: bool) -> Value {
+    let mut env: Vec<String> = container.env_vars().iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect();
+    env.sort();
+    let mut port_bindings = serde_json::Map::new();
+    for port in &info.ports {
+        port_bindings.insert(
+            format!("{}/{}", port.container_port, port.protocol),
+            json!([{ "HostIp": "0.0.0.0", "HostPort": port.host_port.to_string() }]),
+        );
+    }
+    let command = container.command().cloned().unwrap_or_default();
+    
+    json!({
+        "Id": info.id,
+        "Created": format::format_rfc3339(info.created_at),
+        "Path": command.first().cloned().unwrap_or_default(),
+        "Args": command.iter().skip(1).collect::<Vec<_>>(),
+        "State": {
+            "Status": state(info),
+            "Running": state(info) == "running",
+            "Paused": false,
+            "Restarting": false,
+            "OOMKilled": false,
+            "Dead": false,
+            "Pid": 0,
+            "ExitCode": info.exit_code.unwrap_or(0),
+            "Error": "",
+            "StartedAt": info.started_at.map_or("0001-01-01T00:00:00Z".to_string(), format::format_rfc3339),
+            "FinishedAt": "0001-01-01T00:00:00Z",
+        },
+        "Image": info.image,
+        "Name": names(info),
+        "RestartCount": 0,
+        "Config": {
+            "Hostname": container.network_config().hostname,
+            "Image": info.image,
+            "Env": env,
+            "Cmd": command,
+            "WorkingDir": container.workdir().unwrap_or_default(),
+            "Labels": info.labels,
+            "Tty": false,
+            "OpenStdin": false,
+            "AttachStdin": false,
+            "AttachStdout": true,
+            "AttachStderr": true,
+        },
+        "HostConfig": {
+            "NetworkMode": container.networks().first().cloned().unwrap_or_default(),
+            "AutoRemove": auto_remove,
+            "Memory": container.resources().memory.unwrap_or(0),
+            "NanoCpus": container.resources().cpus.map_or(0, |cpus| u64::from(cpus.millis()) * 1_000_000),
+        },
+        "NetworkSettings": {
+            "Ports": port_bindings,
+            "Networks": networks(info, container),
+        },
+        "Mounts": container.volumes().iter()
+            .map(|volume| json!({
+                "Type": "bind",
+                "Source": volume.host_path,
+                "Destination": volume.container_path,
+                "RW": !volume.read_only,
+            }))
+            .collect::<Vec<_>>(),
+    })
+}
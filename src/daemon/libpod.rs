@@ -0,0 +1,333 @@
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+
+use crate::container::{Container, ContainerInfo, Cpus, PortMapping, Resources};
+use crate::format;
+use crate::network::{Network, HOST_NETWORK, NO_NETWORK};
+use crate::volume::{VolumeInfo, VolumeManager};
+
+use super::docker;
+use super::Exit;
+
+// The libpod API version spoken, which podman clients check on `_ping`.
+pub const LIBPOD_API_VERSION: &str = "4.9.0";
+
+// The body of `POST /libpod/containers/create`, podman's spec generator,
+// with the fields wasm containers can honor.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct SpecGenerator {
+    pub image: String,
+    pub name: Option<String>,
+    pub command: Option<Vec<String>>,
+    pub entrypoint: Option<Vec<String>>,
+    pub env: Option<BTreeMap<String, String>>,
+    pub work_dir: Option<String>,
+    pub hostname: Option<String>,
+    pub labels: Option<BTreeMap<String, String>>,
+    pub terminal: bool,
+    pub stdin: bool,
+    pub remove: bool,
+    pub portmappings: Option<Vec<PortMappingSpec>>,
+    pub publish_image_ports: bool,
+    pub mounts: Option<Vec<Mount>>,
+    pub volumes: Option<Vec<NamedVolume>>,
+    pub read_only_filesystem: bool,
+    pub netns: Option<Namespace>,
+    #[serde(rename = "Networks")]
+    pub networks: Option<BTreeMap<String, Value>>,
+    pub dns_server: Option<Vec<IpAddr>>,
+    pub dns_search: Option<Vec<String>>,
+    pub resource_limits: Option<ResourceLimits>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct PortMappingSpec {
+    pub container_port: u16,
+    pub host_port: u16,
+    pub host_ip: String,
+    pub protocol: String,
+    pub range: u16,
+}
+
+// An OCI mount, as podman sends `--mount`, `--tmpfs` and host path `-v`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Mount {
+    pub destination: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub source: String,
+    pub options: Option<Vec<String>>,
+}
+
+// A named volume `-v`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "PascalCase", default)]
+pub struct NamedVolume {
+    pub name: String,
+    pub dest: String,
+    pub options: Option<Vec<String>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Namespace {
+    pub nsmode: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ResourceLimits {
+    pub memory: Option<MemoryLimits>,
+    pub cpu: Option<CpuLimits>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct MemoryLimits {
+    pub limit: Option<i64>,
+}
+
+// `--cpus` arrives as a CFS quota per period, both in microseconds.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct CpuLimits {
+    pub quota: Option<i64>,
+    pub period: Option<u64>,
+}
+
+// The body of `POST /libpod/networks/create`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct NetworkCreate {
+    pub name: String,
+    pub subnets: Vec<Subnet>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Subnet {
+    pub subnet: String,
+    pub gateway: Option<IpAddr>,
+}
+
+// The body of `POST /libpod/volumes/create`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "PascalCase", default)]
+pub struct VolumeCreate {
+    pub name: String,
+}
+
+impl SpecGenerator {
+    // Applies everything but the image, command, environment and working
+    // directory, which make the container, to it.
+    pub fn apply(&self, container: &mut Container, volumes: &VolumeManager) -> Result<()> {
+        if self.terminal {
+            return Err(anyhow!("TTYs aren't supported; run without -t"));
+        }
+        if self.stdin {
+            return Err(anyhow!("Attaching stdin isn't supported; run without -i"));
+        }
+        
+        if let Some(name) = self.name.as_deref().filter(|name| !name.is_empty()) {
+            container.set_name(name)?;
+        }
+        if let Some(hostname) = self.hostname.as_deref().filter(|hostname| !hostname.is_empty()) {
+            container.set_hostname(hostname)?;
+        }
+        for (key, value) in self.labels.iter().flatten() {
+            container.set_label(key, value);
+        }
+        container.set_read_only(self.read_only_filesystem);
+        
+        let limits = self.resource_limits.as_ref();
+        let memory = limits.and_then(|limits| limits.memory.as_ref()).and_then(|memory| memory.limit);
+        let cpus = limits.and_then(|limits| limits.cpu.as_ref()).and_then(|cpu| match (cpu.quota, cpu.period) {
+            (Some(quota), Some(period)) if quota > 0 && period > 0 => Some(quota as u64 * 1000 / period),
+            _ => None,
+        });
+        container.set_resources(Resources {
+            memory: memory.filter(|memory| *memory > 0).map(|memory| memory as u64),
+            cpus: cpus.filter(|millis| *millis > 0).map(|millis| Cpus::from_millis(millis as u32)),
+        });
+        for server in self.dns_server.iter().flatten() {
+            container.add_dns_server(*server);
+        }
+        for domain in self.dns_search.iter().flatten() {
+            container.add_dns_search(domain.clone());
+        }
+        
+        // The namespace mode picks no network or the host's; otherwise the
+        // networks are named, or it's the default bridge.
+        match self.netns.as_ref().map(|netns| netns.nsmode.as_str()) {
+            Some("none") => container.set_networks(vec![NO_NETWORK.to_string()]),
+            Some("host") => container.set_networks(vec![HOST_NETWORK.to_string()]),
+            _ => {
+                let networks: Vec<String> = self.networks.iter().flat_map(BTreeMap::keys).cloned().collect();
+                if !networks.is_empty() {
+                    container.set_networks(networks);
+                }
+            }
+        }
+        
+        // A zero host port means any free one, and a range maps that many
+        // consecutive ports.
+        for mapping in self.portmappings.iter().flatten() {
+            let last = |port: u16| port.checked_add(mapping.range.max(1) - 1)
+                .ok_or_else(|| anyhow!("Port range {}-{} exceeds 65535", port, u32::from(port) + u32::from(mapping.range) - 1));
+            let container_ports = match mapping.range {
+                0 | 1 => mapping.container_port.to_string(),
+                _ => format!("{}-{}", mapping.container_port, last(mapping.container_port)?),
+            };
+            let host_ports = match (mapping.host_port, mapping.range) {
+                (0, _) => String::new(),
+                (port, 0 | 1) => port.to_string(),
+                (port, _) => format!("{}-{}", port, last(port)?),
+            };
+            let protocols = match mapping.protocol.as_str() {
+                "" => "tcp",
+                protocols => protocols,
+            };
+            for protocol in protocols.split(',') {
+                let spec = match (mapping.host_ip.as_str(), host_ports.as_str()) {
+                    ("", "") => format!("{}/{}", container_ports, protocol),
+                    ("", host_ports) => format!("{}:{}/{}", host_ports, container_ports, protocol),
+                    (host_ip, host_ports) => format!("{}:{}:{}/{}", host_ip, host_ports, container_ports, protocol),
+                };
+                for port_map in PortMapping::parse(&spec)? {
+                    container.add_port_mapping(port_map.host_port, port_map.container_port, port_map.protocol);
+                }
+            }
+        }
+        if self.publish_image_ports {
+            container.publish_exposed_ports()?;
+        }
+        
+        for mount in self.mounts.iter().flatten() {
+            let options = mount.options.as_deref().unwrap_or_default();
+            match mount.kind.as_str() {
+                "tmpfs" => container.add_tmpfs(mount.destination.clone().into()),
+                "bind" => docker::add_bind(container, volumes, &bind_spec(&mount.source, &mount.destination, options))?,
+                kind => return Err(anyhow!("Unsupported mount type: {}", kind)),
+            }
+        }
+        for volume in self.volumes.iter().flatten() {
+            let options = volume.options.as_deref().unwrap_or_default();
+            docker::add_bind(container, volumes, &bind_spec(&volume.name, &volume.dest, options))?;
+        }
+        
+        Ok(())
+    }
+    
+    // The command the guest runs, entrypoint first, if either is set.
+    pub fn command(&self) -> Option<Vec<String>> {
+        match (&self.entrypoint, &self.command) {
+            (None, None) => None,
+            (entrypoint, command) => Some(entrypoint.iter().chain(command).flatten().cloned().collect()),
+        }
+    }
+    
+    pub fn env(&self) -> Vec<String> {
+        self.env.iter().flatten().map(|(key, value)| format!("{}={}", key, value)).collect()
+    }
+}
+
+// A `-v` spec for a mount, keeping the options `-v` understands.
+fn bind_spec(source: &str, destination: &str, options: &[String]) -> String {
+    let options: Vec<&str> = options.iter()
+        .map(String::as_str)
+        .filter(|option| matches!(*option, "ro" | "rw" | "z" | "Z" | "cached" | "delegated" | "consistent"))
+        .collect();
+    match options.as_slice() {
+        [] => format!("{}:{}", source, destination),
+        options => format!("{}:{}:{}", source, destination, options.join(",")),
+    }
+}
+
+// The reply to `POST /libpod/containers/{id}/wait`: just the exit code.
+pub(super) fn wait_body(exit: &Exit) -> String {
+    exit.code.to_string()
+}
+
+// An entry of `GET /libpod/containers/json`.
+pub fn summary(info: &ContainerInfo, container: &Container) -> Value {
+    let state = docker::state(info);
+    json!({
+        "Id": info.id,
+        "Names": [docker::name(info)],
+        "Image": info.image,
+        "ImageID": "",
+        "Command": container.command().cloned().unwrap_or_default(),
+        "Created": format::format_rfc3339(info.created_at),
+        "CreatedAt": format::format_timestamp(info.created_at),
+        "StartedAt": info.started_at.unwrap_or(0),
+        "State": state,
+        "Status": info.field("Status"),
+        "Exited": state == "exited",
+        "ExitCode": info.exit_code.unwrap_or(0),
+        "ExitedAt": 0,
+        "Labels": info.labels,
+        "Ports": info.ports.iter()
+            .map(|port| json!({
+                "host_ip": "",
+                "container_port": port.container_port,
+                "host_port": port.host_port,
+                "range": 1,
+                "protocol": port.protocol,
+            }))
+            .collect::<Vec<_>>(),
+        "Networks": container.networks(),
+        "Mounts": container.volumes().iter().map(|volume| &volume.container_path).collect::<Vec<_>>(),
+        "Pid": 0,
+        "Pod": "",
+        "PodName": "",
+        "IsInfra": false,
+        "AutoRemove": false,
+        "Namespaces": {},
+    })
+}
+
+// The reply to `GET /libpod/containers/{id}/json`, which is docker's with
+// podman's naming.
+pub fn inspect(info: &ContainerInfo, container: &Container, auto_remove: bool) -> Value {
+    let mut inspect = docker::inspect(info, container, auto_remove);
+    inspect["Name"] = json!(docker::name(info));
+    inspect["ImageName"] = json!(info.image);
+    inspect
+}
+
+pub fn network(network: &Network) -> Value {
+    let subnets: Vec<Value> = network.subnets()
+        .map(|(subnet, gateway)| json!({ "subnet": subnet, "gateway": gateway }))
+        .collect();
+    json!({
+        "name": network.name,
+        "id": network.name,
+        "driver": "bridge",
+        "network_interface": "",
+        "subnets": subnets,
+        "ipv6_enabled": network.subnet6.is_some(),
+        "internal": false,
+        "dns_enabled": true,
+        "labels": {},
+        "options": {},
+        "ipam_options": {},
+    })
+}
+
+pub fn volume(volume: &VolumeInfo) -> Value {
+    json!({
+        "Name": volume.name,
+        "Driver": "local",
+        "Mountpoint": volume.mountpoint,
+        "CreatedAt": format::format_rfc3339(volume.created_at),
+        "Labels": {},
+        "Scope": "local",
+        "Options": {},
+    })
+}
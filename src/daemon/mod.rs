@@ -0,0 +1,895 @@
+use anyhow::{anyhow, Result};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, SeekFrom};
+use std::os::unix::fs::PermissionsExt;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::net::{UnixListener, UnixStream};
+use tracing::{debug, info, warn};
+
+use crate::config::RuntimeConfig;
+use crate::container::{Container, ContainerFilter, ContainerInfo};
+use crate::error::Error;
+use crate::image::ImageManager;
+use crate::network::ingress::RequestHead;
+use crate::network::NetworkManager;
+use crate::runtime::{WasmRuntime, EXIT_RUNTIME_ERROR, WASMTIME_VERSION};
+use crate::state::{self, ContainerStore};
+use crate::volume::VolumeManager;
+
+pub mod docker;
+pub mod libpod;
+
+pub const DEFAULT_SOCKET: &str = "/var/run/wasm-container.sock";
+
+// The Docker Engine API version spoken. Clients negotiate down to it, and
+// requests naming any version in their path are served the same.
+pub const API_VERSION: &str = "1.43";
+const MIN_API_VERSION: &str = "1.24";
+
+const MAX_BODY_SIZE: usize = 1024 * 1024;
+
+// How often followed logs, waits and starts check on the container.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// Docker's stream type for stdout in multiplexed output. The log doesn't
+// tell stdout and stderr apart, so everything is sent as stdout.
+const STDOUT_STREAM: u8 = 1;
+
+// How a run the daemon started ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Exit {
+    code: i64,
+    error: Option<String>,
+    // Whether the guest got going, as opposed to failing to start.
+    started: bool,
+}
+
+// What a wait returns on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WaitCondition {
+    // Docker's default: at once for a container that isn't running.
+    NotRunning,
+    // The end of the next run.
+    NextExit,
+    // Podman's default: a container that has run and isn't running.
+    Exited,
+}
+
+// The runs of a container as the daemon sees them, so attach and wait can
+// follow runs started by later requests.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Runs {
+    // How many times the daemon has started the container.
+    count: u64,
+    // Set once the latest run ends.
+    exit: Option<Exit>,
+    auto_remove: bool,
+}
+
+impl Runs {
+    fn in_progress(&self) -> bool {
+        self.count > 0 && self.exit.is_none()
+    }
+}
+
+// A failed request, sent as docker's `{"message": ...}`.
+#[derive(Debug)]
+struct ApiError {
+    status: u16,
+    message: String,
+}
+
+impl ApiError {
+    fn new(status: u16, message: impl Into<String>) -> Self {
+        Self { status, message: message.into() }
+    }
+    
+    fn bad_request(e: impl ToString) -> Self {
+        Self::new(400, e.to_string())
+    }
+}
+
+impl From<Error> for ApiError {
+    fn from(e: Error) -> Self {
+        let status = match e {
+            Error::ContainerNotFound(_)
+            | Error::ImageNotFound { .. }
+            | Error::NetworkNotFound(_)
+            | Error::VolumeNotFound(_) => 404,
+            Error::ContainerRunning(_) | Error::NameInUse { .. } | Error::NetworkConflict(_) => 409,
+            _ => 500,
+        };
+        Self::new(status, e.to_string())
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(e: anyhow::Error) -> Self {
+        Error::from(e).into()
+    }
+}
+
+impl From<io::Error> for ApiError {
+    fn from(e: io::Error) -> Self {
+        Self::new(500, e.to_string())
+    }
+}
+
+struct Reply {
+    status: u16,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Reply {
+    fn json(status: u16, value: Value) -> Self {
+        Self { status, content_type: "application/json", body: value.to_string().into_bytes() }
+    }
+    
+    fn text(text: &str) -> Self {
+        Self { status: 200, content_type: "text/plain; charset=utf-8", body: text.as_bytes().to_vec() }
+    }
+    
+    fn empty(status: u16) -> Self {
+        Self { status, content_type: "text/plain; charset=utf-8", body: Vec::new() }
+    }
+    
+    fn to_bytes(&self, head_only: bool) -> Vec<u8> {
+        let mut bytes = response_head(self.status, &[
+            ("Content-Type", self.content_type),
+            ("Content-Length", &self.body.len().to_string()),
+        ]).into_bytes();
+        if !head_only {
+            bytes.extend_from_slice(&self.body);
+        }
+        bytes
+    }
+}
+
+impl From<ApiError> for Reply {
+    fn from(e: ApiError) -> Self {
+        Reply::json(e.status, json!({ "message": e.message }))
+    }
+}
+
+/// Serves enough of the Docker Engine API on a unix socket for docker
+/// clients' basic workflows: `ps`, `create`, `run` (attached or detached),
+/// `start`, `stop`, `rm`, `logs`, `wait` and `inspect`. The same is served
+/// under podman's libpod routes, along with the image, network and volume
+/// checks podman-remote and podman-compose make first.
+pub struct Daemon {
+    store: ContainerStore,
+    images: ImageManager,
+    volumes: VolumeManager,
+    config: RuntimeConfig,
+    // Shared by every container the daemon runs, so they reach each other
+    // over the bridge.
+    network_manager: NetworkManager,
+    runs: Mutex<HashMap<String, Runs>>,
+}
+
+impl Daemon {
+    pub async fn new(config: RuntimeConfig) -> Result<Self> {
+        let state_dir = match &config.state_dir {
+            Some(state_dir) => state_dir.clone(),
+            None => state::default_state_dir()?,
+        };
+        let cache_dir = match &config.cache_dir {
+            Some(cache_dir) => cache_dir.clone(),
+            None => state::default_cache_dir()?,
+        };
+        let runtime = WasmRuntime::with_config(&config)?;
+        runtime.join_mesh().await?;
+        
+        Ok(Self {
+            store: ContainerStore::open(&state_dir, config.state_store)?,
+            images: ImageManager::with_cache_dir(cache_dir.join("images"))?,
+            volumes: VolumeManager::with_dir(state_dir.join("volumes"))?,
+            network_manager: runtime.network_manager(),
+            config,
+            runs: Mutex::default(),
+        })
+    }
+    
+    /// Listens on `path` until accepting fails. A socket left behind by a
+    /// daemon that is gone is replaced; one still answering is an error.
+    pub async fn listen(self, path: &Path) -> Result<()> {
+        if path.exists() {
+            if UnixStream::connect(path).await.is_ok() {
+                return Err(anyhow!("A daemon is already listening on {}", path.display()));
+            }
+            fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)
+            .map_err(|e| anyhow!("Failed to listen on {}: {}", path.display(), e))?;
+        // Like docker's socket, usable by its owner and group only.
+        fs::set_permissions(path, fs::Permissions::from_mode(0o660))?;
+        info!("API listening on {}", path.display());
+        
+        let daemon = Arc::new(self);
+        loop {
+            let (stream, _) = listener.accept().await?;
+            let daemon = daemon.clone();
+            tokio::spawn(async move {
+                if let Err(e) = daemon.handle(stream).await {
+                    debug!("API connection failed: {}", e);
+                }
+            });
+        }
+    }
+    
+    // Each connection carries one request. Attach, wait and logs stream
+    // their replies; everything else is answered in one go.
+    async fn handle(self: Arc<Self>, mut stream: UnixStream) -> io::Result<()> {
+        let (head, mut body) = RequestHead::read(&mut stream).await?;
+        let length = match head.header("content-length").map(str::parse::<usize>).transpose() {
+            Ok(length) => length.unwrap_or(0),
+            Err(_) => return write_reply(&mut stream, ApiError::bad_request("Invalid Content-Length").into(), false).await,
+        };
+        if length > MAX_BODY_SIZE {
+            return write_reply(&mut stream, ApiError::new(413, "Request body too large").into(), false).await;
+        }
+        if head.header("transfer-encoding").is_some() {
+            return write_reply(&mut stream, ApiError::new(411, "Chunked request bodies aren't supported").into(), false).await;
+        }
+        while body.len() < length {
+            let mut chunk = vec![0u8; length - body.len()];
+            let len = stream.read(&mut chunk).await?;
+            if len == 0 {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+            }
+            body.extend_from_slice(&chunk[..len]);
+        }
+        body.truncate(length);
+        
+        let (path, query) = head.path().split_once('?').unwrap_or((head.path(), ""));
+        let query = parse_query(query);
+        let path = strip_version(path);
+        // Segments are decoded after splitting, as image references in
+        // paths have their slashes escaped.
+        let segments: Vec<String> = path.trim_matches('/').split('/').map(percent_decode).collect();
+        let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+        debug!("API request: {} {}", head.method, path);
+        
+        let reply = match (head.method.as_str(), segments.as_slice()) {
+            ("GET" | "HEAD", ["_ping"] | ["libpod", "_ping"]) => Ok(Reply::text("OK")),
+            ("GET", ["version"] | ["libpod", "version"]) => Ok(Reply::json(200, version())),
+            ("GET", ["libpod", "info"]) => self.info(),
+            ("GET", ["containers", "json"]) => self.list(&query, docker::summary),
+            ("GET", ["libpod", "containers", "json"]) => self.list(&query, libpod::summary),
+            ("POST", ["containers", "create"]) => self.create(&query, &body).await,
+            ("POST", ["libpod", "containers", "create"]) => self.create_libpod(&body).await,
+            ("GET", ["containers", reference, "json"]) => self.inspect(reference, docker::inspect),
+            ("GET", ["libpod", "containers", reference, "json"]) => self.inspect(reference, libpod::inspect),
+            ("GET", ["libpod", "containers", reference, "exists"]) => {
+                self.store.resolve(reference).map(|_| Reply::empty(204)).map_err(ApiError::from)
+            }
+            ("POST", ["containers", reference, "start"] | ["libpod", "containers", reference, "start"]) => self.start(reference).await,
+            ("POST", ["containers", reference, "stop"] | ["libpod", "containers", reference, "stop"]) => self.stop(reference).await,
+            ("DELETE", ["containers", reference]) => self.remove(reference, &query).await.map(|_| Reply::empty(204)),
+            ("DELETE", ["libpod", "containers", reference]) => self.remove(reference, &query).await
+                .map(|id| Reply::json(200, json!([{ "Id": id, "Err": null }]))),
+            ("POST", ["containers", reference, "attach"] | ["libpod", "containers", reference, "attach"]) => {
+                match self.store.resolve(reference) {
+                    Ok(id) => return self.attach(&mut stream, &id).await,
+                    Err(e) => Err(e.into()),
+                }
+            }
+            ("POST", ["containers", reference, "wait"]) => {
+                let condition = match query.get("condition").map_or("not-running", String::as_str) {
+                    "not-running" => Ok(WaitCondition::NotRunning),
+                    "next-exit" | "removed" => Ok(WaitCondition::NextExit),
+                    condition => Err(ApiError::bad_request(format!("Invalid wait condition: {}", condition))),
+                };
+                match condition.and_then(|condition| Ok((self.store.resolve(reference)?, condition))) {
+                    Ok((id, condition)) => return self.wait(&mut stream, &id, condition, docker::wait_body).await,
+                    Err(e) => Err(e),
+                }
+            }
+            ("POST", ["libpod", "containers", reference, "wait"]) => {
+                let condition = match query.get("condition").map_or("stopped", String::as_str) {
+                    "stopped" | "exited" => Ok(WaitCondition::Exited),
+                    condition => Err(ApiError::bad_request(format!("Unsupported wait condition: {}", condition))),
+                };
+                match condition.and_then(|condition| Ok((self.store.resolve(reference)?, condition))) {
+                    Ok((id, condition)) => return self.wait(&mut stream, &id, condition, libpod::wait_body).await,
+                    Err(e) => Err(e),
+                }
+            }
+            ("GET", ["containers", reference, "logs"] | ["libpod", "containers", reference, "logs"]) => {
+                match self.store.resolve(reference) {
+                    Ok(id) => return self.logs(&mut stream, &id, &query).await,
+                    Err(e) => Err(e.into()),
+                }
+            }
+            ("GET", ["libpod", "images", reference, "exists"]) => self.image_exists(reference),
+            ("POST", ["libpod", "images", "pull"]) => match query.get("reference") {
+                Some(reference) => return self.pull(&mut stream, reference).await,
+                None => Err(ApiError::bad_request("No image reference given")),
+            },
+            ("GET", ["libpod", "networks", "json"]) => self.networks().await,
+            ("POST", ["libpod", "networks", "create"]) => self.create_network(&body).await,
+            ("GET", ["libpod", "networks", name, "json"]) => self.network_manager.inspect_network(name).await
+                .map(|network| Reply::json(200, libpod::network(&network)))
+                .map_err(ApiError::from),
+            ("GET", ["libpod", "networks", name, "exists"]) => self.network_manager.inspect_network(name).await
+                .map(|_| Reply::empty(204))
+                .map_err(ApiError::from),
+            ("GET", ["libpod", "volumes", "json"]) => self.volumes.list()
+                .map(|volumes| Reply::json(200, volumes.iter().map(libpod::volume).collect()))
+                .map_err(ApiError::from),
+            ("POST", ["libpod", "volumes", "create"]) => self.create_volume(&body),
+            ("GET", ["libpod", "volumes", name, "json"]) => self.volumes.inspect(name)
+                .map(|volume| Reply::json(200, libpod::volume(&volume)))
+                .map_err(ApiError::from),
+            ("GET", ["libpod", "volumes", name, "exists"]) => self.volumes.inspect(name)
+                .map(|_| Reply::empty(204))
+                .map_err(ApiError::from),
+            _ => Err(not_found(&head.method, path)),
+        };
+        
+        let reply = reply.unwrap_or_else(Reply::from);
+        write_reply(&mut stream, reply, head.method == "HEAD").await
+    }
+    
+    // A runtime for one request, sharing the daemon's networks.
+    fn runtime(&self) -> Result<WasmRuntime> {
+        let mut runtime = WasmRuntime::with_config(&self.config)?;
+        runtime.set_network_manager(self.network_manager.clone());
+        Ok(runtime)
+    }
+    
+    fn runs(&self, container_id: &str) -> Runs {
+        self.runs.lock().unwrap().get(container_id).cloned().unwrap_or_default()
+    }
+    
+    fn update_runs(&self, container_id: &str, update: impl FnOnce(&mut Runs)) {
+        update(self.runs.lock().unwrap().entry(container_id.to_string()).or_default());
+    }
+    
+    fn list(&self, query: &HashMap<String, String>, summary: fn(&ContainerInfo, &Container) -> Value) -> Result<Reply, ApiError> {
+        let all = flag(query, "all");
+        let filters = match query.get("filters") {
+            Some(filters) => parse_filters(filters)?,
+            None => Vec::new(),
+        };
+        
+        let mut infos = self.store.list()?;
+        infos.retain(|info| all || docker::state(info) == "running");
+        infos.retain(|info| filters.iter().all(|filter| filter.matches(info)));
+        // Newest first, as docker lists them.
+        infos.sort_by_key(|info| std::cmp::Reverse(info.created_at));
+        
+        let mut containers = Vec::new();
+        for info in infos {
+            if let Ok(container) = self.store.load(&info.id) {
+                containers.push(summary(&info, &container));
+            }
+        }
+        Ok(Reply::json(200, Value::Array(containers)))
+    }
+    
+    async fn create(&self, query: &HashMap<String, String>, body: &[u8]) -> Result<Reply, ApiError> {
+        let request: docker::CreateRequest = serde_json::from_slice(body)
+            .map_err(|e| ApiError::bad_request(format!("Invalid container config: {}", e)))?;
+        let workdir = request.working_dir.clone().filter(|workdir| !workdir.is_empty());
+        let mut container = self.new_container(&request.image, request.command(), workdir, request.env.clone().unwrap_or_default()).await?;
+        if let Some(name) = query.get("name").filter(|name| !name.is_empty()) {
+            container.set_name(name).map_err(ApiError::bad_request)?;
+        }
+        request.apply(&mut container, &self.volumes).map_err(ApiError::bad_request)?;
+        self.register(&container, request.auto_remove()).await
+    }
+    
+    async fn create_libpod(&self, body: &[u8]) -> Result<Reply, ApiError> {
+        let spec: libpod::SpecGenerator = serde_json::from_slice(body)
+            .map_err(|e| ApiError::bad_request(format!("Invalid container spec: {}", e)))?;
+        let workdir = spec.work_dir.clone().filter(|workdir| !workdir.is_empty());
+        let mut container = self.new_container(&spec.image, spec.command(), workdir, spec.env()).await?;
+        spec.apply(&mut container, &self.volumes).map_err(ApiError::bad_request)?;
+        self.register(&container, spec.remove).await
+    }
+    
+    async fn new_container(&self, image: &str, command: Option<Vec<String>>, workdir: Option<String>, env: Vec<String>) -> Result<Container, ApiError> {
+        if image.is_empty() {
+            return Err(ApiError::bad_request("No image given"));
+        }
+        let image_data = self.images.get_or_pull(image).await
+            .map_err(|e| ApiError::new(404, format!("No such image: {}: {}", image, e)))?;
+        Container::new(image_data, command, workdir, env).map_err(ApiError::bad_request)
+    }
+    
+    // Records a new container, to be removed after its first run with
+    // `auto_remove`.
+    async fn register(&self, container: &Container, auto_remove: bool) -> Result<Reply, ApiError> {
+        self.runtime()?.create(container).await?;
+        self.update_runs(container.id(), |runs| runs.auto_remove = auto_remove);
+        info!("Created container: {}", container.id());
+        
+        Ok(Reply::json(201, json!({ "Id": container.id(), "Warnings": [] })))
+    }
+    
+    fn inspect(&self, reference: &str, inspect: fn(&ContainerInfo, &Container, bool) -> Value) -> Result<Reply, ApiError> {
+        let id = self.store.resolve(reference)?;
+        let info = self.store.load_info(&id)?;
+        let container = self.store.load(&id)?;
+        Ok(Reply::json(200, inspect(&info, &container, self.runs(&id).auto_remove)))
+    }
+    
+    // Runs the container in the background, replying once the guest is
+    // running or its run has ended, so failures to start fail the request.
+    async fn start(self: &Arc<Self>, reference: &str) -> Result<Reply, ApiError> {
+        let id = self.store.resolve(reference)?;
+        if docker::state(&self.store.load_info(&id)?) == "running" || self.runs(&id).in_progress() {
+            return Ok(Reply::empty(304));
+        }
+        
+        // Attached clients follow the log from the start, so the last run's
+        // output goes first.
+        fs::File::create(self.store.log_path(&id)?)?;
+        self.update_runs(&id, |runs| {
+            runs.count += 1;
+            runs.exit = None;
+        });
+        let daemon = self.clone();
+        let run_id = id.clone();
+        let run = tokio::spawn(async move { daemon.run(&run_id).await });
+        
+        // A run that panics never records its exit, so it's recorded here,
+        // for this request and those waiting on the container alike.
+        let daemon = self.clone();
+        let run_id = id.clone();
+        tokio::spawn(async move {
+            if let Err(e) = run.await {
+                warn!("Container {} run failed: {}", run_id, e);
+                let started = daemon.store.load_info(&run_id).is_ok_and(|info| info.started_at.is_some());
+                daemon.update_runs(&run_id, |runs| runs.exit = Some(Exit {
+                    code: EXIT_RUNTIME_ERROR.into(),
+                    error: Some(format!("Container run failed: {}", e)),
+                    started,
+                }));
+            }
+        });
+        
+        loop {
+            match self.runs(&id).exit {
+                Some(Exit { started: false, error, .. }) => {
+                    return Err(ApiError::new(500, error.unwrap_or_else(|| "Container failed to start".to_string())));
+                }
+                Some(_) => return Ok(Reply::empty(204)),
+                None => {}
+            }
+            if self.store.load_info(&id).is_ok_and(|info| info.status == "running") {
+                return Ok(Reply::empty(204));
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+    
+    async fn run(&self, container_id: &str) {
+        let result = match self.runtime() {
+            Ok(mut runtime) => runtime.start(container_id).await,
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = &result {
+            warn!("Container {} failed: {}", container_id, e);
+        }
+        
+        // A run whose guest never started has no start time.
+        let info = self.store.load_info(container_id).ok();
+        let started = info.as_ref().is_some_and(|info| info.started_at.is_some());
+        let code = info.and_then(|info| info.exit_code)
+            .map(i64::from)
+            .unwrap_or(if result.is_ok() { 0 } else { 1 });
+        
+        if self.runs(container_id).auto_remove {
+            let removed = match self.runtime() {
+                Ok(mut runtime) => runtime.remove(container_id, true).await,
+                Err(e) => Err(e.into()),
+            };
+            if let Err(e) = removed {
+                warn!("Failed to remove container {}: {}", container_id, e);
+            }
+        }
+        self.update_runs(container_id, |runs| runs.exit = Some(Exit {
+            code,
+            error: result.err().map(|e| e.to_string()),
+            started,
+        }));
+    }
+    
+    async fn stop(&self, reference: &str) -> Result<Reply, ApiError> {
+        let id = self.store.resolve(reference)?;
+        if docker::state(&self.store.load_info(&id)?) != "running" {
+            return Ok(Reply::empty(304));
+        }
+        self.runtime()?.stop(&id).await?;
+        Ok(Reply::empty(204))
+    }
+    
+    // Returns the ID of the container removed.
+    async fn remove(&self, reference: &str, query: &HashMap<String, String>) -> Result<String, ApiError> {
+        let id = self.store.resolve(reference)?;
+        self.runtime()?.remove(&id, flag(query, "force")).await?;
+        self.runs.lock().unwrap().remove(&id);
+        Ok(id)
+    }
+    
+    // Streams a run's output until it ends: the next run the daemon starts
+    // if the container isn't running, as `docker run` attaches before it
+    // starts the container, or else the current one from here on.
+    async fn attach(&self, stream: &mut UnixStream, container_id: &str) -> io::Result<()> {
+        let attached = self.runs(container_id);
+        let running = self.is_running(container_id);
+        stream.write_all(response_head(101, &[
+            ("Content-Type", "application/vnd.docker.raw-stream"),
+            ("Connection", "Upgrade"),
+            ("Upgrade", "tcp"),
+        ]).as_bytes()).await?;
+        
+        if running && attached.in_progress() {
+            let offset = log_len(&self.store, container_id);
+            let run = attached.count;
+            return self.follow(stream, container_id, offset, || self.run_ended(container_id, run)).await;
+        }
+        if running {
+            // Run by someone else, so only the container's status tells
+            // when it ends.
+            let offset = log_len(&self.store, container_id);
+            return self.follow(stream, container_id, offset, || !self.is_running(container_id)).await;
+        }
+        
+        let run = loop {
+            let runs = self.runs(container_id);
+            if runs.count > attached.count {
+                break runs.count;
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        };
+        self.follow(stream, container_id, 0, || self.run_ended(container_id, run)).await
+    }
+    
+    fn run_ended(&self, container_id: &str, run: u64) -> bool {
+        let runs = self.runs(container_id);
+        runs.count != run || runs.exit.is_some()
+    }
+    
+    fn is_running(&self, container_id: &str) -> bool {
+        self.store.load_info(container_id).is_ok_and(|info| docker::state(&info) == "running")
+    }
+    
+    // Sends what the log gains from `offset` on until `done`, checked
+    // before each read so output written just before the end still goes out.
+    async fn follow(&self, stream: &mut UnixStream, container_id: &str, mut offset: u64, done: impl Fn() -> bool) -> io::Result<()> {
+        let path = self.store.log_path(container_id).map_err(io::Error::other)?;
+        loop {
+            let finished = done();
+            match tokio::fs::File::open(&path).await {
+                Ok(mut log) => {
+                    log.seek(SeekFrom::Start(offset)).await?;
+                    let mut output = Vec::new();
+                    log.read_to_end(&mut output).await?;
+                    offset += output.len() as u64;
+                    stream.write_all(&frames(&output)).await?;
+                }
+                // Gone along with an auto-removed container.
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+            if finished {
+                return Ok(());
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+    
+    // The headers go out at once, since `docker run` waits for them before
+    // it starts the container, and the exit code follows once it's known.
+    async fn wait(&self, stream: &mut UnixStream, container_id: &str, condition: WaitCondition, body: fn(&Exit) -> String) -> io::Result<()> {
+        let waited = self.runs(container_id);
+        stream.write_all(response_head(200, &[
+            ("Content-Type", "application/json"),
+            ("Transfer-Encoding", "chunked"),
+        ]).as_bytes()).await?;
+        
+        let exit = loop {
+            let runs = self.runs(container_id);
+            if runs != waited {
+                if let Some(exit) = runs.exit {
+                    break exit;
+                }
+            }
+            if condition != WaitCondition::NextExit && !runs.in_progress() {
+                match self.store.load_info(container_id) {
+                    Ok(info) if docker::state(&info) == "exited" || (condition == WaitCondition::NotRunning && docker::state(&info) != "running") => {
+                        break Exit { code: info.exit_code.map_or(0, i64::from), error: None, started: true };
+                    }
+                    Ok(_) => {}
+                    Err(e) => break Exit { code: 0, error: Some(e.to_string()), started: false },
+                }
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        };
+        
+        let body = body(&exit);
+        stream.write_all(format!("{:x}\r\n{}\r\n0\r\n\r\n", body.len(), body).as_bytes()).await
+    }
+    
+    async fn logs(&self, stream: &mut UnixStream, container_id: &str, query: &HashMap<String, String>) -> io::Result<()> {
+        if !flag(query, "stdout") && !flag(query, "stderr") {
+            let reply = ApiError::bad_request("You must choose at least one stream").into();
+            return write_reply(stream, reply, false).await;
+        }
+        let tail = match query.get("tail").map(String::as_str) {
+            None | Some("all") | Some("") => None,
+            Some(tail) => match tail.parse::<usize>() {
+                Ok(tail) => Some(tail),
+                Err(_) => return write_reply(stream, ApiError::bad_request(format!("Invalid tail: {}", tail)).into(), false).await,
+            },
+        };
+        
+        stream.write_all(response_head(200, &[
+            ("Content-Type", "application/vnd.docker.multiplexed-stream"),
+        ]).as_bytes()).await?;
+        
+        let offset = log_len(&self.store, container_id);
+        let output = match tail {
+            Some(lines) => {
+                let lines = self.store.tail_log(container_id, lines).map_err(io::Error::other)?;
+                lines.iter().map(|line| format!("{}\n", line)).collect::<String>().into_bytes()
+            }
+            None => {
+                let mut output = fs::read(self.store.log_path(container_id).map_err(io::Error::other)?).unwrap_or_default();
+                output.truncate(offset as usize);
+                output
+            }
+        };
+        stream.write_all(&frames(&output)).await?;
+        
+        if flag(query, "follow") {
+            self.follow(stream, container_id, offset, || !self.is_running(container_id) && !self.runs(container_id).in_progress()).await?;
+        }
+        Ok(())
+    }
+    
+    // What `podman info` shows of the daemon.
+    fn info(&self) -> Result<Reply, ApiError> {
+        let containers = self.store.list()?;
+        let running = containers.iter().filter(|info| docker::state(info) == "running").count();
+        let (os, arch) = platform();
+        Ok(Reply::json(200, json!({
+            "host": {
+                "arch": arch,
+                "os": os,
+                "hostname": hostname(),
+                "ociRuntime": { "name": "wasmtime", "version": WASMTIME_VERSION },
+                "remoteSocket": { "exists": true },
+            },
+            "store": {
+                "containerStore": {
+                    "number": containers.len(),
+                    "running": running,
+                    "stopped": containers.len() - running,
+                },
+                "imageStore": { "number": self.images.list()?.len() },
+            },
+            "version": {
+                "APIVersion": libpod::LIBPOD_API_VERSION,
+                "Version": libpod::LIBPOD_API_VERSION,
+                "OsArch": format!("{}/{}", os, arch),
+            },
+        })))
+    }
+    
+    fn image_exists(&self, reference: &str) -> Result<Reply, ApiError> {
+        let reference = match reference.rsplit_once(':') {
+            Some((_, tag)) if !tag.contains('/') => reference.to_string(),
+            _ => format!("{}:latest", reference),
+        };
+        match self.images.list()?.contains(&reference) {
+            true => Ok(Reply::empty(204)),
+            false => Err(ApiError::new(404, format!("No such image: {}", reference))),
+        }
+    }
+    
+    // Streams podman's pull report: progress, then the image or an error.
+    async fn pull(&self, stream: &mut UnixStream, reference: &str) -> io::Result<()> {
+        stream.write_all(response_head(200, &[("Content-Type", "application/json")]).as_bytes()).await?;
+        stream.write_all(format!("{}\n", json!({ "stream": format!("Pulling {}\n", reference) })).as_bytes()).await?;
+        let report = match self.images.get_or_pull(reference).await {
+            Ok(image) => {
+                let id = format!("{}:{}", image.name, image.tag);
+                json!({ "images": [id], "id": id })
+            }
+            Err(e) => json!({ "error": e.to_string() }),
+        };
+        stream.write_all(format!("{}\n", report).as_bytes()).await
+    }
+    
+    async fn networks(&self) -> Result<Reply, ApiError> {
+        let networks = self.network_manager.list_networks().await?;
+        Ok(Reply::json(200, networks.iter().map(libpod::network).collect()))
+    }
+    
+    async fn create_network(&self, body: &[u8]) -> Result<Reply, ApiError> {
+        let request: libpod::NetworkCreate = serde_json::from_slice(body)
+            .map_err(|e| ApiError::bad_request(format!("Invalid network config: {}", e)))?;
+        let subnets: Vec<&str> = request.subnets.iter().map(|subnet| subnet.subnet.as_str()).collect();
+        let gateways: Vec<IpAddr> = request.subnets.iter().filter_map(|subnet| subnet.gateway).collect();
+        let network = self.network_manager.create_network(&request.name, &subnets, &gateways, None).await?;
+        Ok(Reply::json(200, libpod::network(&network)))
+    }
+    
+    fn create_volume(&self, body: &[u8]) -> Result<Reply, ApiError> {
+        let request: libpod::VolumeCreate = serde_json::from_slice(body)
+            .map_err(|e| ApiError::bad_request(format!("Invalid volume config: {}", e)))?;
+        let volume = self.volumes.create(&request.name).map_err(ApiError::bad_request)?;
+        Ok(Reply::json(201, libpod::volume(&volume)))
+    }
+}
+
+// The OS and architecture as Go names them, which clients expect.
+fn platform() -> (&'static str, &'static str) {
+    let arch = match std::env::consts::ARCH {
+        "x86_64" => "amd64",
+        "aarch64" => "arm64",
+        arch => arch,
+    };
+    (std::env::consts::OS, arch)
+}
+
+fn hostname() -> String {
+    fs::read_to_string("/proc/sys/kernel/hostname")
+        .map(|hostname| hostname.trim().to_string())
+        .unwrap_or_default()
+}
+
+fn version() -> Value {
+    let (os, arch) = platform();
+    json!({
+        "Platform": { "Name": "wasm-container" },
+        "Components": [{
+            "Name": "Engine",
+            "Version": env!("CARGO_PKG_VERSION"),
+            "Details": { "Wasmtime": WASMTIME_VERSION },
+        }],
+        "Version": env!("CARGO_PKG_VERSION"),
+        "ApiVersion": API_VERSION,
+        "MinAPIVersion": MIN_API_VERSION,
+        "Os": os,
+        "Arch": arch,
+        "KernelVersion": "",
+    })
+}
+
+fn response_head(status: u16, headers: &[(&str, &str)]) -> String {
+    let reason = match status {
+        101 => "UPGRADED",
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        304 => "Not Modified",
+        400 => "Bad Request",
+        404 => "Not Found",
+        409 => "Conflict",
+        411 => "Length Required",
+        413 => "Payload Too Large",
+        _ => "Internal Server Error",
+    };
+    let mut head = format!(
+        "HTTP/1.1 {} {}\r\nApi-Version: {}\r\nLibpod-Api-Version: {}\r\nOstype: linux\r\nDocker-Experimental: false\r\nServer: wasm-container/{}\r\n",
+        status, reason, API_VERSION, libpod::LIBPOD_API_VERSION, env!("CARGO_PKG_VERSION"),
+    );
+    for (name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    if status != 101 {
+        head.push_str("Connection: close\r\n");
+    }
+    head.push_str("\r\n");
+    head
+}
+
+fn not_found(method: &str, path: &str) -> ApiError {
+    ApiError::new(404, format!("page not found: {} {}", method, path))
+}
+
+async fn write_reply(stream: &mut UnixStream, reply: Reply, head_only: bool) -> io::Result<()> {
+    stream.write_all(&reply.to_bytes(head_only)).await
+}
+
+fn log_len(store: &ContainerStore, container_id: &str) -> u64 {
+    store.log_path(container_id)
+        .and_then(|path| Ok(fs::metadata(path)?.len()))
+        .unwrap_or(0)
+}
+
+// Output in docker's multiplexed framing: the stream type, three zero
+// bytes and the payload's length, big-endian, before each payload.
+fn frames(output: &[u8]) -> Vec<u8> {
+    let mut framed = Vec::with_capacity(output.len() + 8);
+    for payload in output.chunks(u32::MAX as usize) {
+        framed.extend_from_slice(&[STDOUT_STREAM, 0, 0, 0]);
+        framed.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        framed.extend_from_slice(payload);
+    }
+    framed
+}
+
+// Clients put the API version they speak first in the path, as in
+// `/v1.43/containers/json`.
+fn strip_version(path: &str) -> &str {
+    if let Some(rest) = path.strip_prefix("/v") {
+        if let Some(i) = rest.find('/') {
+            if rest[..i].chars().all(|c| c.is_ascii_digit() || c == '.') {
+                return &rest[i..];
+            }
+        }
+    }
+    path
+}
+
+fn parse_query(query: &str) -> HashMap<String, String> {
+    query.split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(&key.replace('+', " ")), percent_decode(&value.replace('+', " ")))
+        })
+        .collect()
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' if i + 2 < bytes.len() => match std::str::from_utf8(&bytes[i + 1..i + 3]).ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            {
+                Some(byte) => {
+                    decoded.push(byte);
+                    i += 2;
+                }
+                None => decoded.push(b'%'),
+            },
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+// Docker's boolean query parameters, which clients send as `1` or `true`.
+fn flag(query: &HashMap<String, String>, name: &str) -> bool {
+    matches!(query.get(name).map(String::as_str), Some("1" | "true" | "True"))
+}
+
+// `filters` maps each filter to its values, as a list or, from older
+// clients, as the keys of an object.
+fn parse_filters(filters: &str) -> Result<Vec<ContainerFilter>, ApiError> {
+    let invalid = |e: &dyn std::fmt::Display| ApiError::bad_request(format!("Invalid filters: {}", e));
+    let filters: HashMap<String, Value> = serde_json::from_str(filters).map_err(|e| invalid(&e))?;
+    let mut parsed = Vec::new();
+    for (name, values) in filters {
+        let values: Vec<String> = match values {
+            Value::Array(values) => values.iter().filter_map(Value::as_str).map(String::from).collect(),
+            Value::Object(values) => values.keys().cloned().collect(),
+            _ => return Err(invalid(&"expected a list of values")),
+        };
+        if name != "label" && name != "name" {
+            return Err(ApiError::bad_request(format!("Unsupported filter: {}", name)));
+        }
+        for value in values {
+            parsed.push(format!("{}={}", name, value).parse().map_err(ApiError::bad_request)?);
+        }
+    }
+    Ok(parsed)
+}
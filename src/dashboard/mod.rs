@@ -0,0 +1,231 @@
+use anyhow::Result;
+use ratatui::crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Modifier, Style};
+use ratatui::widgets::{Block, Borders, Cell, Clear, Paragraph, Row, Table, TableState, Wrap};
+use ratatui::Frame;
+
+use crate::format::format_bytes;
+use crate::network::throttle::NetworkStats;
+use crate::runtime::{ResourceUsage, WasmRuntime};
+
+// How many of the selected container's last log lines are kept.
+const LOG_LINES: usize = 200;
+
+// How much of a container's ID the table shows.
+const SHORT_ID: usize = 12;
+
+const HEADER: [&str; 8] = ["CONTAINER ID", "NAME", "IMAGE", "CPU %", "MEM USAGE / LIMIT", "MEM %", "NET RX/s", "NET TX/s"];
+
+const HELP: &str = "↑/↓ select   s stop   r restart   i inspect   q quit";
+
+// One running container as `top` shows it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ContainerRow {
+    pub id: String,
+    pub name: String,
+    pub image: String,
+    pub usage: ResourceUsage,
+    pub network: NetworkStats,
+}
+
+// What a key asks `top` to do to the selected container, or to itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    Quit,
+    Stop(String),
+    Restart(String),
+    Inspect(String),
+}
+
+// The state behind `top`: the running containers, which one is selected,
+// its recent log lines and whatever is shown over them.
+#[derive(Debug, Default)]
+pub struct Dashboard {
+    containers: Vec<ContainerRow>,
+    selected: usize,
+    logs: Vec<String>,
+    // Shown in a popup until dismissed, e.g. the output of inspect.
+    details: Option<String>,
+    // The outcome of the last action, shown in the footer.
+    message: Option<String>,
+}
+
+impl Dashboard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    // Reads the running containers' latest samples and the selected one's
+    // log. Containers that stop in between just show no usage.
+    pub async fn refresh(&mut self, runtime: &WasmRuntime) -> Result<()> {
+        let mut containers = Vec::new();
+        for info in runtime.list_containers(false).await? {
+            containers.push(ContainerRow {
+                usage: runtime.usage(&info.id).await.unwrap_or_default(),
+                network: runtime.stats(&info.id).await.unwrap_or_default(),
+                name: info.name.unwrap_or_default(),
+                image: info.image,
+                id: info.id,
+            });
+        }
+        self.set_containers(containers);
+        
+        let logs = match self.selected() {
+            Some(container) => runtime.logs(&container.id, LOG_LINES).await.unwrap_or_default(),
+            None => Vec::new(),
+        };
+        self.set_logs(logs);
+        
+        Ok(())
+    }
+    
+    // Replaces the containers shown, keeping the same one selected if it
+    // is still running.
+    pub fn set_containers(&mut self, mut containers: Vec<ContainerRow>) {
+        containers.sort_by(|a, b| (&a.name, &a.id).cmp(&(&b.name, &b.id)));
+        let selected = self.selected().map(|container| container.id.clone());
+        self.selected = selected
+            .and_then(|id| containers.iter().position(|container| container.id == id))
+            .unwrap_or(self.selected)
+            .min(containers.len().saturating_sub(1));
+        self.containers = containers;
+    }
+    
+    pub fn set_logs(&mut self, logs: Vec<String>) {
+        self.logs = logs;
+    }
+    
+    pub fn selected(&self) -> Option<&ContainerRow> {
+        self.containers.get(self.selected)
+    }
+    
+    pub fn show_details(&mut self, details: String) {
+        self.details = Some(details);
+    }
+    
+    pub fn set_message(&mut self, message: String) {
+        self.message = Some(message);
+    }
+    
+    pub fn handle_key(&mut self, key: KeyEvent) -> Option<Action> {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            return Some(Action::Quit);
+        }
+        // Any other key closes the popup first.
+        if self.details.take().is_some() {
+            return None;
+        }
+        
+        let selected = self.selected().map(|container| container.id.clone());
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Some(Action::Quit),
+            KeyCode::Up | KeyCode::Char('k') => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Down | KeyCode::Char('j') => {
+                self.selected = (self.selected + 1).min(self.containers.len().saturating_sub(1));
+            }
+            KeyCode::Char('s') => return selected.map(Action::Stop),
+            KeyCode::Char('r') => return selected.map(Action::Restart),
+            KeyCode::Char('i') | KeyCode::Enter => return selected.map(Action::Inspect),
+            _ => {}
+        }
+        None
+    }
+    
+    pub fn render(&self, frame: &mut Frame) {
+        let [table_area, logs_area, footer_area] = Layout::vertical([
+            Constraint::Percentage(50),
+            Constraint::Fill(1),
+            Constraint::Length(1),
+        ]).areas(frame.area());
+        
+        self.render_table(frame, table_area);
+        self.render_logs(frame, logs_area);
+        let footer = match &self.message {
+            Some(message) => format!("{}   {}", HELP, message),
+            None => HELP.to_string(),
+        };
+        frame.render_widget(Paragraph::new(footer), footer_area);
+        
+        if let Some(details) = &self.details {
+            let area = popup_area(frame.area());
+            frame.render_widget(Clear, area);
+            frame.render_widget(
+                Paragraph::new(details.as_str())
+                    .wrap(Wrap { trim: false })
+                    .block(Block::default().borders(Borders::ALL).title(" Inspect (any key to close) ")),
+                area,
+            );
+        }
+    }
+    
+    fn render_table(&self, frame: &mut Frame, area: Rect) {
+        let rows = self.containers.iter().map(|container| {
+            let usage = &container.usage;
+            let memory_percent = match usage.memory_limit {
+                0 => 0.0,
+                limit => usage.memory as f64 / limit as f64 * 100.0,
+            };
+            Row::new([
+                container.id.chars().take(SHORT_ID).collect(),
+                container.name.clone(),
+                container.image.clone(),
+                format!("{:.1}%", usage.cpu_percent),
+                format!("{} / {}", format_bytes(usage.memory), format_bytes(usage.memory_limit)),
+                format!("{:.1}%", memory_percent),
+                format!("{}/s", format_bytes(container.network.rx_bytes_per_sec)),
+                format!("{}/s", format_bytes(container.network.tx_bytes_per_sec)),
+            ].map(Cell::from))
+        });
+        let widths = [
+            Constraint::Length(SHORT_ID as u16),
+            Constraint::Fill(1),
+            Constraint::Fill(1),
+            Constraint::Length(7),
+            Constraint::Length(19),
+            Constraint::Length(7),
+            Constraint::Length(10),
+            Constraint::Length(10),
+        ];
+        let table = Table::new(rows, widths)
+            .header(Row::new(HEADER).style(Style::default().add_modifier(Modifier::BOLD)))
+            .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+            .block(Block::default().borders(Borders::ALL).title(format!(" Running containers ({}) ", self.containers.len())));
+        
+        let mut state = TableState::default().with_selected(self.selected().map(|_| self.selected));
+        frame.render_stateful_widget(table, area, &mut state);
+    }
+    
+    fn render_logs(&self, frame: &mut Frame, area: Rect) {
+        let title = match self.selected() {
+            Some(container) if !container.name.is_empty() => format!(" Logs: {} ", container.name),
+            Some(container) => format!(" Logs: {} ", container.id.chars().take(SHORT_ID).collect::<String>()),
+            None => " Logs ".to_string(),
+        };
+        // The last lines that fit inside the borders. Guests' control
+        // characters are dropped so they can't move the cursor around.
+        let shown = usize::from(area.height.saturating_sub(2));
+        let lines: Vec<String> = self.logs[self.logs.len().saturating_sub(shown)..].iter()
+            .map(|line| line.replace('\t', "    ").chars().filter(|c| !c.is_control()).collect())
+            .collect();
+        frame.render_widget(
+            Paragraph::new(lines.join("\n")).block(Block::default().borders(Borders::ALL).title(title)),
+            area,
+        );
+    }
+}
+
+// The middle of `area`, for popups.
+fn popup_area(area: Rect) -> Rect {
+    let [_, middle, _] = Layout::vertical([
+        Constraint::Percentage(10),
+        Constraint::Percentage(80),
+        Constraint::Percentage(10),
+    ]).areas(area);
+    let [_, center, _] = Layout::horizontal([
+        Constraint::Percentage(10),
+        Constraint::Percentage(80),
+        Constraint::Percentage(10),
+    ]).areas(middle);
+    center
+}
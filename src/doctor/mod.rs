@@ -0,0 +1,337 @@
+use anyhow::Result;
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::net::{Ipv4Addr, TcpListener, UdpSocket};
+use std::path::{Path, PathBuf};
+
+use crate::compose::ProjectRecord;
+use crate::config::RuntimeConfig;
+use crate::runtime;
+use crate::state::{self, ContainerStore};
+
+// Where the kernel picks ports for `-p 0:PORT` from.
+const EPHEMERAL_RANGE_FILE: &str = "/proc/sys/net/ipv4/ip_local_port_range";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[non_exhaustive]
+pub enum Severity {
+    Ok,
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Padded, so findings line up.
+        f.pad(match self {
+            Self::Ok => "ok",
+            Self::Warning => "warning",
+            Self::Error => "error",
+        })
+    }
+}
+
+// One finding: what was checked, how it went and, unless all is well, what
+// to do about it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Finding {
+    pub check: &'static str,
+    pub severity: Severity,
+    pub message: String,
+    pub fix: Option<String>,
+}
+
+impl Finding {
+    fn ok(check: &'static str, message: impl Into<String>) -> Self {
+        Self { check, severity: Severity::Ok, message: message.into(), fix: None }
+    }
+    
+    fn warning(check: &'static str, message: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self { check, severity: Severity::Warning, message: message.into(), fix: Some(fix.into()) }
+    }
+    
+    fn error(check: &'static str, message: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self { check, severity: Severity::Error, message: message.into(), fix: Some(fix.into()) }
+    }
+}
+
+// Checks the environment the runtime works in. Unlike creating a
+// `WasmRuntime`, it cleans nothing up, so it sees what earlier runs left
+// behind.
+pub struct Doctor {
+    state_dir: PathBuf,
+    cache_dir: PathBuf,
+    scratch_dir: PathBuf,
+}
+
+impl Doctor {
+    pub fn new(config: &RuntimeConfig) -> Result<Self> {
+        Ok(Self::with_dirs(
+            config.state_dir.clone().map_or_else(state::default_state_dir, Ok)?,
+            config.cache_dir.clone().map_or_else(state::default_cache_dir, Ok)?,
+            config.scratch_dir.clone().unwrap_or_else(state::default_scratch_dir),
+        ))
+    }
+    
+    pub fn with_dirs(state_dir: PathBuf, cache_dir: PathBuf, scratch_dir: PathBuf) -> Self {
+        Self { state_dir, cache_dir, scratch_dir }
+    }
+    
+    // Runs every check, in the order a problem in one is likely to explain
+    // those after it.
+    pub fn run(&self) -> Vec<Finding> {
+        let mut findings = Vec::new();
+        findings.extend(self.check_dirs());
+        findings.push(check_host());
+        match ContainerStore::new(&self.state_dir) {
+            Ok(store) => {
+                findings.extend(self.check_state(&store));
+                findings.extend(self.check_locks(&store));
+                findings.extend(check_ports(&store));
+                findings.extend(self.check_scratch(&store));
+            }
+            // The directory check has already said why.
+            Err(_) => return findings,
+        }
+        findings
+    }
+    
+    fn check_dirs(&self) -> Vec<Finding> {
+        [("state", &self.state_dir), ("image cache", &self.cache_dir), ("scratch", &self.scratch_dir)]
+            .into_iter()
+            .map(|(kind, dir)| match check_writable(dir) {
+                Ok(()) => Finding::ok("directories", format!("{} directory {} is writable", kind, dir.display())),
+                Err(e) => Finding::error(
+                    "directories",
+                    format!("{} directory {} is not writable: {}", kind, dir.display(), e),
+                    format!("chown -R $USER {} or point the config file at another directory", dir.display()),
+                ),
+            })
+            .collect()
+    }
+    
+    // Container metadata and compose records must parse, or commands
+    // touching them fail.
+    fn check_state(&self, store: &ContainerStore) -> Vec<Finding> {
+        let mut findings = Vec::new();
+        let mut containers = 0;
+        
+        for path in store.incomplete().unwrap_or_default() {
+            findings.push(Finding::warning(
+                "state",
+                format!("{} was left without metadata by an interrupted create", path.display()),
+                "wasm-container system prune removes it once it is an hour old",
+            ));
+        }
+        for id in container_ids(&self.state_dir) {
+            let dir = self.state_dir.join("containers").join(&id);
+            if !store.exists(&id).unwrap_or(false) {
+                continue;
+            }
+            match store.load_info(&id).and_then(|_| store.load(&id)) {
+                Ok(_) => containers += 1,
+                Err(e) => findings.push(Finding::error(
+                    "state",
+                    format!("container {} has unreadable metadata: {}", id, e),
+                    format!("rm -rf {}", dir.display()),
+                )),
+            }
+        }
+        for (project, path) in compose_records(&self.state_dir) {
+            if let Err(e) = load_record(&path) {
+                findings.push(Finding::error(
+                    "state",
+                    format!("compose project {} has an unreadable record: {}", project, e),
+                    format!("rm {} and remove its containers with wasm-container rm", path.display()),
+                ));
+            }
+        }
+        
+        if findings.is_empty() {
+            findings.push(Finding::ok("state", format!("{} containers with readable metadata", containers)));
+        }
+        findings
+    }
+    
+    // Runs and compose projects record the process holding them; ones whose
+    // process died hold them still.
+    fn check_locks(&self, store: &ContainerStore) -> Vec<Finding> {
+        let mut findings = Vec::new();
+        
+        for info in store.list().unwrap_or_default() {
+            if let Ok(Some(record)) = store.tracked_rootfs(&info.id) {
+                if !record.owner_alive() {
+                    findings.push(Finding::warning(
+                        "locks",
+                        format!("container {} is held by process {}, which died mid-run", info.id, record.pid),
+                        "run any wasm-container command, e.g. wasm-container list, to release it",
+                    ));
+                }
+            }
+        }
+        for (project, path) in compose_records(&self.state_dir) {
+            let Ok(record) = load_record(&path) else {
+                continue;
+            };
+            if !state::process_alive(record.pid) {
+                findings.push(Finding::warning(
+                    "locks",
+                    format!("compose project {} is held by process {}, which died", project, record.pid),
+                    format!("wasm-container compose -p {} down", project),
+                ));
+            }
+        }
+        
+        if findings.is_empty() {
+            findings.push(Finding::ok("locks", "no runs or projects are held by dead processes"));
+        }
+        findings
+    }
+    
+    // Scratch rootfs and tmpfs directories only live as long as their run.
+    fn check_scratch(&self, store: &ContainerStore) -> Vec<Finding> {
+        let mut live = HashSet::new();
+        for info in store.list().unwrap_or_default() {
+            if let Ok(Some(record)) = store.tracked_rootfs(&info.id) {
+                live.insert(record.rootfs);
+                live.insert(record.tmpfs);
+            }
+        }
+        
+        let mut findings = Vec::new();
+        let entries = fs::read_dir(&self.scratch_dir).into_iter().flatten().flatten();
+        for path in entries.map(|entry| entry.path()) {
+            if live.contains(&path) {
+                continue;
+            }
+            let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+            let id = name.strip_suffix("-tmpfs").unwrap_or(&name);
+            let fix = match store.load_info(id) {
+                Ok(_) => format!("rm -rf {} (container {} is not running)", path.display(), id),
+                Err(_) => format!("rm -rf {} (no container {} exists)", path.display(), id),
+            };
+            findings.push(Finding::warning("rootfs", format!("{} belongs to no run", path.display()), fix));
+        }
+        
+        if findings.is_empty() {
+            findings.push(Finding::ok("rootfs", "no dangling scratch directories"));
+        }
+        findings
+    }
+}
+
+fn check_writable(dir: &Path) -> Result<()> {
+    fs::create_dir_all(dir)?;
+    let probe = dir.join(format!(".doctor-{}", std::process::id()));
+    fs::write(&probe, b"")?;
+    fs::remove_file(probe)?;
+    Ok(())
+}
+
+fn check_host() -> Finding {
+    match runtime::check_host_support() {
+        Ok(()) => Finding::ok("wasmtime", format!(
+            "{} on {} supports {}",
+            runtime::WASMTIME_VERSION,
+            std::env::consts::ARCH,
+            runtime::wasm_features().join(", "),
+        )),
+        Err(e) => Finding::error(
+            "wasmtime",
+            format!("wasmtime can't compile guests on this CPU: {:#}", e),
+            "run on a CPU with the SIMD extensions wasmtime needs, e.g. SSE4.1 on x86_64",
+        ),
+    }
+}
+
+// Stopped containers bind their published ports again when started, and
+// `-p 0:PORT` needs ephemeral ports to pick from.
+fn check_ports(store: &ContainerStore) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    
+    for info in store.list().unwrap_or_default() {
+        if info.status == "running" {
+            continue;
+        }
+        let Ok(container) = store.load(&info.id) else {
+            continue;
+        };
+        for port in &container.network_config().ports {
+            if port.host_port == 0 || port_free(port.host_port, &port.protocol) {
+                continue;
+            }
+            findings.push(Finding::warning(
+                "ports",
+                format!(
+                    "port {}/{} of container {} is taken, so starting it will fail",
+                    port.host_port, port.protocol, info.name.as_deref().unwrap_or(&info.id),
+                ),
+                format!("stop what listens on it (ss -lnp 'sport = :{}') or recreate the container with another -p", port.host_port),
+            ));
+        }
+    }
+    
+    match fs::read_to_string(EPHEMERAL_RANGE_FILE) {
+        Ok(range) => {
+            let bounds: Vec<u16> = range.split_whitespace().filter_map(|bound| bound.parse().ok()).collect();
+            match bounds[..] {
+                [low, high] if low <= high && port_free(0, "tcp") => findings.push(Finding::ok(
+                    "ports",
+                    format!("{} ephemeral ports ({}-{}) for -p 0:PORT", high - low + 1, low, high),
+                )),
+                _ => findings.push(Finding::warning(
+                    "ports",
+                    format!("no ephemeral ports to pick from ({})", range.trim()),
+                    format!("sysctl -w net.ipv4.ip_local_port_range=\"32768 60999\" (now {})", EPHEMERAL_RANGE_FILE),
+                )),
+            }
+        }
+        // Not Linux; the OS picks as it does.
+        Err(_) if port_free(0, "tcp") => findings.push(Finding::ok("ports", "the OS can pick ports for -p 0:PORT")),
+        Err(e) => findings.push(Finding::warning(
+            "ports",
+            format!("no port can be bound: {}", e),
+            "check for a firewall or sandbox denying the process sockets",
+        )),
+    }
+    findings
+}
+
+fn port_free(port: u16, protocol: &str) -> bool {
+    let address = (Ipv4Addr::UNSPECIFIED, port);
+    match protocol {
+        "udp" => UdpSocket::bind(address).is_ok(),
+        _ => TcpListener::bind(address).is_ok(),
+    }
+}
+
+fn container_ids(state_dir: &Path) -> Vec<String> {
+    let mut ids: Vec<String> = fs::read_dir(state_dir.join("containers"))
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| entry.file_name().to_str().map(str::to_string))
+        .collect();
+    ids.sort();
+    ids
+}
+
+fn load_record(path: &Path) -> Result<ProjectRecord> {
+    Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+}
+
+fn compose_records(state_dir: &Path) -> Vec<(String, PathBuf)> {
+    let mut records: Vec<(String, PathBuf)> = fs::read_dir(state_dir.join("compose"))
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "json"))
+        .filter_map(|path| Some((path.file_stem()?.to_string_lossy().into_owned(), path)))
+        .collect();
+    records.sort();
+    records
+}
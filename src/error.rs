@@ -0,0 +1,144 @@
+use std::io;
+
+use crate::runtime::cancel::Cancelled;
+use crate::runtime::{self, StartError, EXIT_CANCELLED, EXIT_RUNTIME_ERROR};
+
+/// The errors of the embedding API, such as [`WasmRuntime`](crate::WasmRuntime),
+/// [`ImageManager`](crate::ImageManager), [`NetworkManager`](crate::NetworkManager)
+/// and [`VolumeManager`](crate::VolumeManager), for callers to match on the
+/// failure modes they handle. Anything else is [`Error::Other`], with the
+/// message and context it failed with.
+///
+/// ```no_run
+/// use wasm_container::{Container, Error, WasmRuntime};
+///
+/// # async fn example(mut runtime: WasmRuntime, container: Container) -> anyhow::Result<()> {
+/// match runtime.run(container).await {
+///     Ok(()) => {}
+///     Err(Error::Exit { exit_code }) => eprintln!("Guest exited with {}", exit_code),
+///     Err(Error::NetworkConflict(reason)) => eprintln!("Pick another port: {}", reason),
+///     Err(e) => return Err(e.into()),
+/// }
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`StateStore`](crate::state::store::StateStore) and
+/// [`ImageSource`](crate::ImageSource) implementations return
+/// `anyhow::Error`; the variants they convert into it, such as
+/// `Error::ImageNotFound`, reach callers as they are.
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum Error {
+    #[error("No such container: {0}")]
+    ContainerNotFound(String),
+    
+    /// The container is running, and has to be stopped first.
+    #[error("Container {0} is running; stop it first")]
+    ContainerRunning(String),
+    
+    /// Another container already goes by the name.
+    #[error("Container name {name} is already in use by container {container}")]
+    NameInUse { name: String, container: String },
+    
+    /// The image isn't in the cache, or its source doesn't have it.
+    #[error("No such image: {image}: {reason}")]
+    ImageNotFound { image: String, reason: String },
+    
+    /// The registry turned the stored credentials down, or they couldn't
+    /// be read.
+    #[error("Failed to authenticate to {registry}")]
+    RegistryAuth {
+        registry: String,
+        #[source]
+        source: anyhow::Error,
+    },
+    
+    /// The guest exited with a code other than zero.
+    #[error("Guest exited with code {exit_code}")]
+    Exit { exit_code: i32 },
+    
+    /// The guest trapped or couldn't be started, with the exit code docker
+    /// would report for it.
+    #[error("Guest failed with exit code {exit_code}")]
+    Trap {
+        exit_code: i32,
+        #[source]
+        source: anyhow::Error,
+    },
+    
+    /// The run was stopped by its [`CancellationToken`](crate::CancellationToken).
+    #[error("Run was cancelled")]
+    Cancelled,
+    
+    /// A host port, address or network name is already taken, or the
+    /// container is already connected.
+    #[error("{0}")]
+    NetworkConflict(String),
+    
+    #[error("No such network: {0}")]
+    NetworkNotFound(String),
+    
+    #[error("No such volume: {0}")]
+    VolumeNotFound(String),
+    
+    /// The disk holding the state directory, scratch space or image cache
+    /// is full, or over its quota.
+    #[error("Out of storage space")]
+    StorageFull {
+        #[source]
+        source: anyhow::Error,
+    },
+    
+    #[error(transparent)]
+    Other(anyhow::Error),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+    /// The exit code docker would report for the failure, as
+    /// [`exit_code`](crate::exit_code) gives.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::Exit { exit_code } | Self::Trap { exit_code, .. } => *exit_code,
+            Self::Cancelled => EXIT_CANCELLED,
+            _ => EXIT_RUNTIME_ERROR,
+        }
+    }
+}
+
+// Errors raised inside the crate are sorted into the variants they match
+// as they leave it; an `Error` raised earlier keeps its variant.
+impl From<anyhow::Error> for Error {
+    fn from(error: anyhow::Error) -> Self {
+        let error = match error.downcast::<Error>() {
+            Ok(error) => return error,
+            Err(error) => error,
+        };
+        if error.is::<Cancelled>() {
+            return Self::Cancelled;
+        }
+        if let Some(exit) = error.downcast_ref::<wasmtime_wasi::I32Exit>() {
+            return Self::Exit { exit_code: exit.0 };
+        }
+        if error.is::<StartError>() || error.is::<wasmtime::Trap>() {
+            return Self::Trap { exit_code: runtime::exit_code(&error), source: error };
+        }
+        if error.chain().any(is_storage_full) {
+            return Self::StorageFull { source: error };
+        }
+        Self::Other(error)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        anyhow::Error::from(error).into()
+    }
+}
+
+fn is_storage_full(error: &(dyn std::error::Error + 'static)) -> bool {
+    error.downcast_ref::<io::Error>()
+        .is_some_and(|e| matches!(e.kind(), io::ErrorKind::StorageFull | io::ErrorKind::QuotaExceeded))
+}
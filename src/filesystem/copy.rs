@@ -0,0 +1,130 @@
+use anyhow::{Result, anyhow};
+use std::collections::VecDeque;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+const MAX_SYMLINK_DEPTH: usize = 40;
+
+pub fn resolve_path(rootfs: &Path, container_path: &Path) -> Result<PathBuf> {
+    let mut resolved: Vec<OsString> = Vec::new();
+    let mut pending: VecDeque<OsString> = normal_components(container_path, &mut resolved);
+    let mut links = 0;
+    
+    while let Some(part) = pending.pop_front() {
+        if part == ".." {
+            resolved.pop();
+            continue;
+        }
+        
+        let candidate: PathBuf = std::iter::once(rootfs.as_os_str().to_owned())
+            .chain(resolved.iter().cloned())
+            .chain(std::iter::once(part.clone()))
+            .collect();
+        
+        match fs::symlink_metadata(&candidate) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                links += 1;
+                if links > MAX_SYMLINK_DEPTH {
+                    return Err(anyhow!("Too many levels of symbolic links: {:?}", container_path));
+                }
+                
+                let target = fs::read_link(&candidate)?;
+                let mut target_parts = normal_components(&target, &mut resolved);
+                target_parts.extend(pending);
+                pending = target_parts;
+            }
+            _ => resolved.push(part),
+        }
+    }
+    
+    Ok(std::iter::once(rootfs.as_os_str().to_owned()).chain(resolved).collect())
+}
+
+// Splits a path into its components, keeping `..` so the caller can clamp
+// it at the rootfs. Absolute paths restart resolution from the rootfs.
+fn normal_components(path: &Path, resolved: &mut Vec<OsString>) -> VecDeque<OsString> {
+    let mut parts = VecDeque::new();
+    
+    for component in path.components() {
+        match component {
+            Component::RootDir | Component::Prefix(_) => {
+                resolved.clear();
+                parts.clear();
+            }
+            Component::CurDir => {}
+            Component::ParentDir => parts.push_back(OsString::from("..")),
+            Component::Normal(part) => parts.push_back(part.to_owned()),
+        }
+    }
+    
+    parts
+}
+
+pub fn copy_from_container(rootfs: &Path, container_path: &Path, host_path: &Path) -> Result<()> {
+    let src = resolve_path(rootfs, container_path)?;
+    
+    if fs::symlink_metadata(&src).is_err() {
+        return Err(anyhow!("No such file or directory in container: {:?}", container_path));
+    }
+    
+    let dst = destination(host_path, &src)?;
+    copy_tree(&src, &dst)
+}
+
+pub fn copy_to_container(rootfs: &Path, host_path: &Path, container_path: &Path) -> Result<()> {
+    if fs::symlink_metadata(host_path).is_err() {
+        return Err(anyhow!("No such file or directory: {:?}", host_path));
+    }
+    
+    let dst = resolve_path(rootfs, container_path)?;
+    
+    if let Some(parent) = dst.parent() {
+        if !parent.is_dir() {
+            return Err(anyhow!("Destination directory does not exist in container: {:?}", container_path));
+        }
+    }
+    
+    let dst = destination(&dst, host_path)?;
+    copy_tree(host_path, &dst)
+}
+
+fn destination(dst: &Path, src: &Path) -> Result<PathBuf> {
+    if dst.is_dir() {
+        let name = src
+            .file_name()
+            .ok_or_else(|| anyhow!("Cannot determine file name of {:?}", src))?;
+        Ok(dst.join(name))
+    } else {
+        Ok(dst.to_path_buf())
+    }
+}
+
+// Symlinks are recreated rather than followed so a copied tree can never
+// pull in or write through files outside of it.
+pub(crate) fn copy_tree(src: &Path, dst: &Path) -> Result<()> {
+    let metadata = fs::symlink_metadata(src)?;
+    
+    if metadata.file_type().is_symlink() {
+        if fs::symlink_metadata(dst).is_ok() {
+            fs::remove_file(dst)?;
+        }
+        std::os::unix::fs::symlink(fs::read_link(src)?, dst)?;
+    } else if metadata.is_dir() {
+        if fs::symlink_metadata(dst).map(|m| m.file_type().is_symlink()).unwrap_or(false) {
+            return Err(anyhow!("Refusing to copy a directory over symlink {:?}", dst));
+        }
+        fs::create_dir_all(dst)?;
+        for entry in fs::read_dir(src)? {
+            let entry = entry?;
+            copy_tree(&entry.path(), &dst.join(entry.file_name()))?;
+        }
+    } else {
+        if fs::symlink_metadata(dst).map(|m| m.file_type().is_symlink()).unwrap_or(false) {
+            fs::remove_file(dst)?;
+        }
+        fs::copy(src, dst)?;
+    }
+    
+    Ok(())
+}
@@ -0,0 +1,158 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EntryKind {
+    File,
+    Dir,
+    Symlink(String),
+    Other,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexEntry {
+    pub kind: EntryKind,
+    pub size: u64,
+    pub modified: u64,
+    pub digest: Option<String>,
+}
+
+pub type FileIndex = BTreeMap<String, IndexEntry>;
+
+// Generated by the runtime on every start, so never part of the image.
+const DYNAMIC_PATHS: &[&str] = &["/proc"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    Added,
+    Changed,
+    Deleted,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub kind: ChangeKind,
+    pub path: String,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let marker = match self.kind {
+            ChangeKind::Added => 'A',
+            ChangeKind::Changed => 'C',
+            ChangeKind::Deleted => 'D',
+        };
+        write!(f, "{} {}", marker, self.path)
+    }
+}
+
+pub fn scan(root: &Path) -> Result<FileIndex> {
+    let mut index = FileIndex::new();
+    scan_dir(root, root, true, &mut index)?;
+    Ok(index)
+}
+
+// Skips content digests, so files are compared by size and mtime only.
+// Cheap enough to run repeatedly while a container is up.
+pub fn scan_metadata(root: &Path) -> Result<FileIndex> {
+    let mut index = FileIndex::new();
+    scan_dir(root, root, false, &mut index)?;
+    Ok(index)
+}
+
+fn scan_dir(root: &Path, dir: &Path, digests: bool, index: &mut FileIndex) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let metadata = fs::symlink_metadata(&path)?;
+        
+        let kind = if metadata.file_type().is_symlink() {
+            EntryKind::Symlink(fs::read_link(&path)?.to_string_lossy().into_owned())
+        } else if metadata.is_dir() {
+            EntryKind::Dir
+        } else if metadata.is_file() {
+            EntryKind::File
+        } else {
+            EntryKind::Other
+        };
+        
+        let digest = match kind {
+            EntryKind::File if digests => Some(file_digest(&path)?),
+            _ => None,
+        };
+        
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        
+        let relative = format!("/{}", path.strip_prefix(root)?.to_string_lossy());
+        if DYNAMIC_PATHS.contains(&relative.as_str()) {
+            continue;
+        }
+        
+        let is_dir = kind == EntryKind::Dir;
+        
+        index.insert(relative, IndexEntry {
+            kind,
+            size: metadata.len(),
+            modified,
+            digest,
+        });
+        
+        if is_dir {
+            scan_dir(root, &path, digests, index)?;
+        }
+    }
+    
+    Ok(())
+}
+
+// The hex SHA-256 of a file's contents.
+fn file_digest(path: &Path) -> Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+    Ok(hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+pub fn diff(pristine: &FileIndex, current: &FileIndex) -> Vec<Change> {
+    let mut changes = Vec::new();
+    
+    for (path, entry) in current {
+        let kind = match pristine.get(path) {
+            None => ChangeKind::Added,
+            Some(original) if is_changed(original, entry) => ChangeKind::Changed,
+            Some(_) => continue,
+        };
+        changes.push(Change { kind, path: path.clone() });
+    }
+    
+    for path in pristine.keys() {
+        if !current.contains_key(path) {
+            changes.push(Change { kind: ChangeKind::Deleted, path: path.clone() });
+        }
+    }
+    
+    changes.sort_by(|a, b| a.path.cmp(&b.path));
+    changes
+}
+
+fn is_changed(original: &IndexEntry, current: &IndexEntry) -> bool {
+    match (&original.kind, &current.kind) {
+        (EntryKind::Dir, EntryKind::Dir) => original.modified != current.modified,
+        (EntryKind::File, EntryKind::File) => match (&original.digest, &current.digest) {
+            (Some(a), Some(b)) => original.size != current.size || a != b,
+            _ => original.size != current.size || original.modified != current.modified,
+        },
+        (a, b) => a != b,
+    }
+}
@@ -0,0 +1,103 @@
+use anyhow::Result;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::debug;
+
+use super::copy::{copy_tree, resolve_path};
+use super::diff::{self, ChangeKind, FileIndex};
+use super::remove_path;
+use crate::container::{MountMode, VolumeMount};
+
+// A host directory copied into the rootfs instead of being preopened, so
+// the guest sees it as part of the image. In the sync modes both trees are
+// polled and changes are replayed onto the other side.
+pub struct DirMount {
+    host_path: PathBuf,
+    target: PathBuf,
+    mode: MountMode,
+    indexes: Mutex<(FileIndex, FileIndex)>,
+}
+
+impl DirMount {
+    pub fn new(volume: &VolumeMount, target: PathBuf) -> Result<Self> {
+        copy_tree(&volume.host_path, &target)?;
+        
+        Ok(Self {
+            indexes: Mutex::new((diff::scan_metadata(&volume.host_path)?, diff::scan_metadata(&target)?)),
+            host_path: volume.host_path.clone(),
+            target,
+            mode: volume.mode,
+        })
+    }
+    
+    pub fn host_path(&self) -> &Path {
+        &self.host_path
+    }
+    
+    pub fn target(&self) -> &Path {
+        &self.target
+    }
+    
+    // When both sides touched the same path since the last poll, the host
+    // copy wins.
+    pub fn poll(&self) -> Result<()> {
+        if self.mode == MountMode::Copy || !self.host_path.is_dir() {
+            return Ok(());
+        }
+        
+        let mut indexes = self.indexes.lock().unwrap_or_else(|e| e.into_inner());
+        let host_changes = diff::diff(&indexes.0, &diff::scan_metadata(&self.host_path)?);
+        
+        if self.mode == MountMode::SyncBack {
+            let guest_changes = diff::diff(&indexes.1, &diff::scan_metadata(&self.target)?);
+            for change in guest_changes {
+                if host_changes.iter().any(|c| c.path == change.path) {
+                    continue;
+                }
+                debug!("Syncing {} back to host", change.path);
+                propagate(&self.target, &self.host_path, &change.path, change.kind)?;
+            }
+        }
+        
+        for change in host_changes {
+            debug!("Syncing {} into container", change.path);
+            propagate(&self.host_path, &self.target, &change.path, change.kind)?;
+        }
+        
+        *indexes = (diff::scan_metadata(&self.host_path)?, diff::scan_metadata(&self.target)?);
+        
+        Ok(())
+    }
+}
+
+// Replays a single entry from one tree onto the other. Parents are resolved
+// inside each tree so symlinks created on one side can't redirect reads or
+// writes outside of it.
+fn propagate(from: &Path, to: &Path, path: &str, kind: ChangeKind) -> Result<()> {
+    let relative = Path::new(path.trim_start_matches('/'));
+    let Some(name) = relative.file_name() else {
+        return Ok(());
+    };
+    let relative_parent = relative.parent().unwrap_or_else(|| Path::new(""));
+    let src = resolve_path(from, relative_parent)?.join(name);
+    let parent = resolve_path(to, relative_parent)?;
+    let dst = parent.join(name);
+    
+    let metadata = match fs::symlink_metadata(&src) {
+        Ok(metadata) if kind != ChangeKind::Deleted => metadata,
+        _ => return remove_path(&dst),
+    };
+    
+    if metadata.is_dir() {
+        if fs::symlink_metadata(&dst).is_ok_and(|m| !m.is_dir()) {
+            remove_path(&dst)?;
+        }
+        fs::create_dir_all(&dst)?;
+        return Ok(());
+    }
+    
+    fs::create_dir_all(&parent)?;
+    remove_path(&dst)?;
+    copy_tree(&src, &dst)
+}
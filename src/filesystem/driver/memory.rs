@@ -0,0 +1,81 @@
+use anyhow::{Result, anyhow};
+use std::path::{Path, PathBuf};
+
+use super::{extract_layer, StorageDriver};
+use crate::filesystem::diff::{self, Change, FileIndex};
+use crate::filesystem::memory::{DecodedLayer, MemoryFs};
+use crate::filesystem::ownership::OwnershipMap;
+use crate::filesystem::parallel_map;
+use crate::image::Layer;
+
+// Layers are merged in memory straight from their tar entries and the
+// result staged on a scratch filesystem (ideally tmpfs). Nothing is kept
+// once the container exits.
+pub struct MemoryDriver {
+    scratch_dir: PathBuf,
+    size_limit: Option<u64>,
+}
+
+impl MemoryDriver {
+    pub fn new(scratch_dir: PathBuf) -> Self {
+        Self {
+            scratch_dir,
+            size_limit: None,
+        }
+    }
+    
+    // Caps the size of a rootfs, checked before anything is staged on the
+    // scratch filesystem.
+    pub fn with_size_limit(mut self, size_limit: Option<u64>) -> Self {
+        self.size_limit = size_limit;
+        self
+    }
+}
+
+impl StorageDriver for MemoryDriver {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+    
+    fn rootfs_path(&self, container_id: &str) -> Result<PathBuf> {
+        Ok(self.scratch_dir.join(container_id))
+    }
+    
+    // A lone layer can't be merged with what is already staged, so it is
+    // unpacked directly.
+    fn apply_layer(&self, rootfs: &Path, layer: &Layer, ownership: &mut OwnershipMap) -> Result<()> {
+        let mut memory_fs = MemoryFs::new();
+        memory_fs.merge(DecodedLayer::load(layer)?);
+        ownership.extend(memory_fs.ownership().clone());
+        extract_layer(rootfs, &layer.path)
+    }
+    
+    fn apply_layers(&self, rootfs: &Path, layers: &[Layer], ownership: &mut OwnershipMap) -> Result<()> {
+        let decoded = parallel_map(layers, DecodedLayer::load)?;
+        let mut memory_fs = MemoryFs::new();
+        for decoded in decoded {
+            memory_fs.merge(decoded);
+        }
+        
+        if let Some(limit) = self.size_limit.filter(|&limit| memory_fs.size() > limit) {
+            return Err(anyhow!(
+                "Rootfs at {:?} needs {} bytes, more than the {} byte scratch budget",
+                rootfs, memory_fs.size(), limit
+            ));
+        }
+        
+        memory_fs.materialize(rootfs)?;
+        ownership.extend(memory_fs.ownership().clone());
+        
+        Ok(())
+    }
+    
+    fn commit_diff(&self, container_id: &str, pristine: &FileIndex) -> Result<Vec<Change>> {
+        let current = diff::scan(&self.rootfs_path(container_id)?)?;
+        Ok(diff::diff(pristine, &current))
+    }
+    
+    fn persistent(&self) -> bool {
+        false
+    }
+}
@@ -0,0 +1,189 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use tar::Archive;
+use flate2::read::GzDecoder;
+use tracing::{debug, warn};
+
+use super::diff::{Change, FileIndex};
+use super::ownership::OwnershipMap;
+use super::{is_whiteout, remove_path, resolve_in_rootfs, OPAQUE_WHITEOUT, WHITEOUT_PREFIX};
+use crate::image::Layer;
+
+pub mod memory;
+#[cfg(target_os = "linux")]
+pub mod overlay;
+pub mod vfs;
+
+pub use self::memory::MemoryDriver;
+#[cfg(target_os = "linux")]
+pub use self::overlay::OverlayDriver;
+pub use self::vfs::VfsDriver;
+
+// How a container's rootfs is assembled from its image layers and where it
+// lives. The runtime only talks to drivers through this trait, so new ways
+// of building a rootfs don't need changes anywhere else.
+pub trait StorageDriver: Send + Sync {
+    fn name(&self) -> &'static str;
+    
+    // Where the rootfs of `container_id` lives, whether or not it exists yet.
+    fn rootfs_path(&self, container_id: &str) -> Result<PathBuf>;
+    
+    // Creates the rootfs directory if needed and returns it.
+    fn prepare_rootfs(&self, container_id: &str) -> Result<PathBuf> {
+        let rootfs = self.rootfs_path(container_id)?;
+        fs::create_dir_all(&rootfs)?;
+        Ok(rootfs)
+    }
+    
+    // Unpacks one layer on top of what is already in `rootfs`, recording
+    // the ownership of its entries.
+    fn apply_layer(&self, rootfs: &Path, layer: &Layer, ownership: &mut OwnershipMap) -> Result<()>;
+    
+    // Drivers that can do better than one layer at a time override this.
+    fn apply_layers(&self, rootfs: &Path, layers: &[Layer], ownership: &mut OwnershipMap) -> Result<()> {
+        for layer in layers {
+            self.apply_layer(rootfs, layer, ownership)?;
+        }
+        Ok(())
+    }
+    
+    // Changes the container made on top of its image layers, given the
+    // index taken right after they were applied.
+    fn commit_diff(&self, container_id: &str, pristine: &FileIndex) -> Result<Vec<Change>>;
+    
+    // Discards the rootfs of `container_id`.
+    fn cleanup(&self, container_id: &str) -> Result<()> {
+        remove_path(&self.rootfs_path(container_id)?)
+    }
+    
+    // Captures the container's rootfs as it is now into `snapshot`.
+    fn snapshot(&self, container_id: &str, snapshot: &Path) -> Result<()> {
+        clone_tree(&self.rootfs_path(container_id)?, snapshot, &mut HashMap::new())
+    }
+    
+    // Rolls the container's rootfs back to what `snapshot` captured.
+    fn restore(&self, container_id: &str, snapshot: &Path) -> Result<()> {
+        let rootfs = self.rootfs_path(container_id)?;
+        remove_path(&rootfs)?;
+        clone_tree(snapshot, &rootfs, &mut HashMap::new())
+    }
+    
+    // Makes the rootfs of an existing container available at its path, for
+    // drivers that only assemble it on demand. Returns whether it had to be
+    // mounted, in which case the caller unmounts it once done with it.
+    fn mount(&self, _container_id: &str) -> Result<bool> {
+        Ok(false)
+    }
+    
+    // Undoes `mount`, leaving the container's changes in place.
+    fn unmount(&self, _container_id: &str) -> Result<()> {
+        Ok(())
+    }
+    
+    // Data this driver may have left behind when a process died mid-way,
+    // such as partially extracted layers. Entries can also belong to work
+    // still in progress, so callers only remove ones that are old enough.
+    fn partial_data(&self) -> Result<Vec<PathBuf>> {
+        Ok(Vec::new())
+    }
+    
+    // Whether the rootfs survives the container exiting. Non-persistent
+    // rootfs trees are rebuilt on every start and cleaned up after.
+    fn persistent(&self) -> bool {
+        true
+    }
+}
+
+// Unpacks a layer tarball straight into `rootfs`, without going through
+// the layer cache.
+pub fn extract_layer(rootfs: &Path, layer_path: &Path) -> Result<()> {
+    debug!("Extracting layer: {:?}", layer_path);
+    
+    // Whiteouts only hide content from lower layers, so they are applied
+    // in a first pass before any of this layer's own entries are unpacked.
+    apply_whiteouts(rootfs, layer_path)?;
+    
+    let tar_gz = fs::File::open(layer_path)?;
+    let tar = GzDecoder::new(tar_gz);
+    let mut archive = Archive::new(tar);
+    
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        
+        if is_whiteout(&path) {
+            continue;
+        }
+        
+        entry.unpack_in(rootfs)?;
+    }
+    
+    Ok(())
+}
+
+// Copies a tree exactly: symlinks are recreated rather than followed,
+// files hardlinked to each other stay linked through `linked`, and
+// permissions are kept.
+pub(crate) fn clone_tree(src: &Path, dst: &Path, linked: &mut HashMap<(u64, u64), PathBuf>) -> Result<()> {
+    let metadata = fs::symlink_metadata(src)?;
+    
+    if metadata.file_type().is_symlink() {
+        std::os::unix::fs::symlink(fs::read_link(src)?, dst)?;
+    } else if metadata.is_dir() {
+        fs::create_dir_all(dst)?;
+        for entry in fs::read_dir(src)? {
+            let entry = entry?;
+            clone_tree(&entry.path(), &dst.join(entry.file_name()), linked)?;
+        }
+        fs::set_permissions(dst, metadata.permissions())?;
+    } else if let Some(first) = linked.get(&(metadata.dev(), metadata.ino())) {
+        fs::hard_link(first, dst)?;
+    } else {
+        fs::copy(src, dst)?;
+        if metadata.nlink() > 1 {
+            linked.insert((metadata.dev(), metadata.ino()), dst.to_path_buf());
+        }
+    }
+    
+    Ok(())
+}
+
+fn apply_whiteouts(rootfs: &Path, layer_path: &Path) -> Result<()> {
+    let tar_gz = fs::File::open(layer_path)?;
+    let tar = GzDecoder::new(tar_gz);
+    let mut archive = Archive::new(tar);
+    
+    for entry in archive.entries()? {
+        let entry = entry?;
+        let path = entry.path()?.into_owned();
+        
+        let Some(file_name) = path.file_name().and_then(|n| n.to_str()) else {
+            continue;
+        };
+        let parent = path.parent().unwrap_or_else(|| Path::new(""));
+        let Some(parent) = resolve_in_rootfs(rootfs, parent) else {
+            warn!("Ignoring whiteout outside rootfs: {:?}", path);
+            continue;
+        };
+        
+        if file_name == OPAQUE_WHITEOUT {
+            debug!("Applying opaque whiteout: {:?}", parent);
+            if parent.is_dir() {
+                for child in fs::read_dir(&parent)? {
+                    remove_path(&child?.path())?;
+                }
+            }
+        } else if let Some(hidden) = file_name.strip_prefix(WHITEOUT_PREFIX) {
+            if hidden.is_empty() || hidden == "." || hidden == ".." {
+                continue;
+            }
+            debug!("Applying whiteout: {:?}", parent.join(hidden));
+            remove_path(&parent.join(hidden))?;
+        }
+    }
+    
+    Ok(())
+}
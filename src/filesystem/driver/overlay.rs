@@ -0,0 +1,386 @@
+use anyhow::{Result, anyhow};
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::{Path, PathBuf};
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use super::{clone_tree, StorageDriver, VfsDriver};
+use crate::filesystem::diff::{Change, FileIndex};
+use crate::filesystem::ownership::OwnershipMap;
+use crate::filesystem::{remove_path, OPAQUE_WHITEOUT, WHITEOUT_PREFIX};
+use crate::image::Layer;
+
+const STAGING_PREFIX: &str = ".tmp-";
+const OPAQUE_XATTR: &str = "trusted.overlay.opaque";
+const LOWER_FILE: &str = "lower.json";
+
+// Cached layers are stacked with a real overlayfs mount, so a container's
+// rootfs takes no time or space to build and only grows with what the
+// container writes. Mounting needs privileges; whenever it fails, the
+// rootfs is built by copying through the vfs driver instead, and that
+// container keeps using copies from then on.
+pub struct OverlayDriver {
+    copy: VfsDriver,
+    layers_dir: PathBuf,
+}
+
+impl OverlayDriver {
+    // `layers_dir` holds the cached layers converted to overlayfs format.
+    pub fn new(copy: VfsDriver, layers_dir: PathBuf) -> Result<Self> {
+        fs::create_dir_all(&layers_dir)?;
+        
+        Ok(Self { copy, layers_dir })
+    }
+    
+    // Overlay mounts need CAP_SYS_ADMIN, which root has on the host as well
+    // as inside a user namespace it owns.
+    pub fn supported() -> bool {
+        let root = unsafe { libc::geteuid() } == 0;
+        root && fs::read_to_string("/proc/filesystems").is_ok_and(|filesystems| {
+            filesystems.lines().any(|line| line.split_whitespace().last() == Some("overlay"))
+        })
+    }
+    
+    // Cached layers keep OCI whiteout files, which overlayfs doesn't
+    // understand, so each layer is mirrored once in overlayfs format. Files
+    // are hardlinked rather than copied: lower layers are never written to.
+    fn lower_dir(&self, layer_dir: &Path) -> Result<PathBuf> {
+        let hex = layer_dir.file_name();
+        let algorithm = layer_dir.parent().and_then(Path::file_name);
+        let (Some(algorithm), Some(hex)) = (algorithm, hex) else {
+            return Err(anyhow!("Invalid cached layer: {:?}", layer_dir));
+        };
+        let lower = self.layers_dir.join(algorithm).join(hex);
+        
+        if lower.is_dir() {
+            return Ok(lower);
+        }
+        
+        debug!("Converting layer for overlayfs: {:?}", layer_dir);
+        
+        let parent = lower.parent().unwrap_or(&self.layers_dir);
+        fs::create_dir_all(parent)?;
+        
+        let staging = parent.join(format!("{}{}", STAGING_PREFIX, Uuid::new_v4()));
+        if let Err(e) = mirror_layer(layer_dir, &staging) {
+            remove_path(&staging).ok();
+            return Err(e);
+        }
+        
+        if fs::rename(&staging, &lower).is_err() {
+            remove_path(&staging)?;
+            if !lower.is_dir() {
+                return Err(anyhow!("Failed to convert layer for overlayfs: {:?}", layer_dir));
+            }
+        }
+        
+        Ok(lower)
+    }
+    
+    fn mount_layers(&self, rootfs: &Path, layer_dirs: &[PathBuf]) -> Result<()> {
+        let lowers = layer_dirs.iter()
+            .map(|layer_dir| self.lower_dir(layer_dir))
+            .collect::<Result<Vec<_>>>()?;
+        
+        let overlay = overlay_dir(rootfs);
+        fs::create_dir_all(overlay.join("upper"))?;
+        fs::create_dir_all(overlay.join("work"))?;
+        
+        mount_overlay(&lowers, &overlay, rootfs)?;
+        fs::write(overlay.join(LOWER_FILE), serde_json::to_string(&lowers)?)?;
+        
+        Ok(())
+    }
+    
+    // Containers whose rootfs was built by copying have no overlay state.
+    fn stacked(&self, container_id: &str) -> Result<bool> {
+        Ok(overlay_dir(&self.rootfs_path(container_id)?).join(LOWER_FILE).is_file())
+    }
+}
+
+impl StorageDriver for OverlayDriver {
+    fn name(&self) -> &'static str {
+        "overlay"
+    }
+    
+    fn rootfs_path(&self, container_id: &str) -> Result<PathBuf> {
+        self.copy.rootfs_path(container_id)
+    }
+    
+    fn prepare_rootfs(&self, container_id: &str) -> Result<PathBuf> {
+        let rootfs = self.rootfs_path(container_id)?;
+        fs::create_dir_all(&rootfs)?;
+        self.mount(container_id)?;
+        Ok(rootfs)
+    }
+    
+    // A lone layer can't be stacked under what is already there, so it is
+    // copied in, through the mount if there is one.
+    fn apply_layer(&self, rootfs: &Path, layer: &Layer, ownership: &mut OwnershipMap) -> Result<()> {
+        self.copy.apply_layer(rootfs, layer, ownership)
+    }
+    
+    // Only an empty rootfs can be mounted over without hiding anything.
+    fn apply_layers(&self, rootfs: &Path, layers: &[Layer], ownership: &mut OwnershipMap) -> Result<()> {
+        fs::create_dir_all(rootfs)?;
+        if layers.is_empty() || is_mounted(rootfs) || fs::read_dir(rootfs)?.next().is_some() {
+            return self.copy.apply_layers(rootfs, layers, ownership);
+        }
+        
+        let layer_cache = self.copy.layer_cache();
+        let layer_dirs = layer_cache.ensure_all(layers)?;
+        
+        if let Err(e) = self.mount_layers(rootfs, &layer_dirs) {
+            warn!("Overlay mount failed, copying layers into {:?} instead: {}", rootfs, e);
+            remove_path(&overlay_dir(rootfs))?;
+            return self.copy.apply_layers(rootfs, layers, ownership);
+        }
+        
+        for layer_dir in &layer_dirs {
+            layer_cache.merge_ownership(layer_dir, ownership)?;
+        }
+        
+        Ok(())
+    }
+    
+    fn commit_diff(&self, container_id: &str, pristine: &FileIndex) -> Result<Vec<Change>> {
+        let mounted = self.mount(container_id)?;
+        let changes = self.copy.commit_diff(container_id, pristine);
+        if mounted {
+            self.unmount(container_id)?;
+        }
+        changes
+    }
+    
+    fn cleanup(&self, container_id: &str) -> Result<()> {
+        let rootfs = self.rootfs_path(container_id)?;
+        self.unmount(container_id)?;
+        remove_path(&overlay_dir(&rootfs))?;
+        remove_path(&rootfs)
+    }
+    
+    // The lower layers never change, so only the upper directory needs
+    // capturing.
+    fn snapshot(&self, container_id: &str, snapshot: &Path) -> Result<()> {
+        if !self.stacked(container_id)? {
+            return self.copy.snapshot(container_id, snapshot);
+        }
+        
+        let upper = overlay_dir(&self.rootfs_path(container_id)?).join("upper");
+        clone_upper(&upper, snapshot, &mut HashMap::new())
+    }
+    
+    // The rootfs is left unmounted, and picks up the restored upper
+    // directory when it is next mounted.
+    fn restore(&self, container_id: &str, snapshot: &Path) -> Result<()> {
+        if !self.stacked(container_id)? {
+            return self.copy.restore(container_id, snapshot);
+        }
+        
+        self.unmount(container_id)?;
+        let overlay = overlay_dir(&self.rootfs_path(container_id)?);
+        for dir in ["upper", "work"] {
+            remove_path(&overlay.join(dir))?;
+        }
+        clone_upper(snapshot, &overlay.join("upper"), &mut HashMap::new())?;
+        fs::create_dir_all(overlay.join("work"))?;
+        
+        Ok(())
+    }
+    
+    fn partial_data(&self) -> Result<Vec<PathBuf>> {
+        let mut partial = self.copy.partial_data()?;
+        for algorithm in fs::read_dir(&self.layers_dir)? {
+            let algorithm = algorithm?.path();
+            if !algorithm.is_dir() {
+                continue;
+            }
+            for entry in fs::read_dir(&algorithm)? {
+                let path = entry?.path();
+                if path.file_name().is_some_and(|n| n.to_string_lossy().starts_with(STAGING_PREFIX)) {
+                    partial.push(path);
+                }
+            }
+        }
+        
+        Ok(partial)
+    }
+    
+    fn mount(&self, container_id: &str) -> Result<bool> {
+        let rootfs = self.rootfs_path(container_id)?;
+        let overlay = overlay_dir(&rootfs);
+        
+        if !self.stacked(container_id)? || is_mounted(&rootfs) {
+            return Ok(false);
+        }
+        
+        let lowers: Vec<PathBuf> = serde_json::from_str(&fs::read_to_string(overlay.join(LOWER_FILE))?)?;
+        fs::create_dir_all(&rootfs)?;
+        mount_overlay(&lowers, &overlay, &rootfs)?;
+        
+        Ok(true)
+    }
+    
+    fn unmount(&self, container_id: &str) -> Result<()> {
+        let rootfs = self.rootfs_path(container_id)?;
+        if !is_mounted(&rootfs) {
+            return Ok(());
+        }
+        
+        debug!("Unmounting overlay at {:?}", rootfs);
+        let target = c_path(&rootfs)?;
+        if unsafe { libc::umount2(target.as_ptr(), libc::MNT_DETACH) } != 0 {
+            return Err(anyhow!("Failed to unmount {:?}: {}", rootfs, io::Error::last_os_error()));
+        }
+        
+        Ok(())
+    }
+}
+
+// Upper and work directories live next to the rootfs they are mounted on.
+fn overlay_dir(rootfs: &Path) -> PathBuf {
+    rootfs.with_file_name("overlay")
+}
+
+// A mount point sits on a different device than the directory holding it.
+fn is_mounted(path: &Path) -> bool {
+    let parent = path.parent().unwrap_or(path);
+    match (fs::symlink_metadata(path), fs::symlink_metadata(parent)) {
+        (Ok(metadata), Ok(parent)) => metadata.dev() != parent.dev(),
+        _ => false,
+    }
+}
+
+// `lowers` are ordered like image layers, bottom first.
+fn mount_overlay(lowers: &[PathBuf], overlay: &Path, target: &Path) -> Result<()> {
+    let option_path = |path: &Path| {
+        path.to_str()
+            .filter(|path| !path.contains([',', ':']))
+            .map(str::to_owned)
+            .ok_or_else(|| anyhow!("Path can't be passed to overlayfs: {:?}", path))
+    };
+    
+    let lowerdir = lowers.iter()
+        .rev()
+        .map(|lower| option_path(lower))
+        .collect::<Result<Vec<_>>>()?
+        .join(":");
+    let options = format!(
+        "lowerdir={},upperdir={},workdir={}",
+        lowerdir,
+        option_path(&overlay.join("upper"))?,
+        option_path(&overlay.join("work"))?,
+    );
+    
+    debug!("Mounting overlay of {} layers at {:?}", lowers.len(), target);
+    
+    let options = CString::new(options)?;
+    let c_target = c_path(target)?;
+    let result = unsafe {
+        libc::mount(
+            c"overlay".as_ptr(),
+            c_target.as_ptr(),
+            c"overlay".as_ptr(),
+            0,
+            options.as_ptr().cast(),
+        )
+    };
+    if result != 0 {
+        return Err(anyhow!("Failed to mount overlay at {:?}: {}", target, io::Error::last_os_error()));
+    }
+    
+    Ok(())
+}
+
+fn mirror_layer(src: &Path, dst: &Path) -> Result<()> {
+    let metadata = fs::symlink_metadata(src)?;
+    fs::create_dir(dst)?;
+    
+    for entry in fs::read_dir(src)? {
+        let entry = entry?;
+        let path = entry.path();
+        let name = entry.file_name();
+        let target = dst.join(&name);
+        let file_type = entry.file_type()?;
+        let name = name.to_string_lossy();
+        
+        if name == OPAQUE_WHITEOUT {
+            set_opaque(dst)?;
+        } else if let Some(hidden) = name.strip_prefix(WHITEOUT_PREFIX) {
+            if !hidden.is_empty() && hidden != "." && hidden != ".." {
+                make_whiteout(&dst.join(hidden))?;
+            }
+        } else if file_type.is_dir() {
+            mirror_layer(&path, &target)?;
+        } else if file_type.is_symlink() {
+            std::os::unix::fs::symlink(fs::read_link(&path)?, &target)?;
+            let metadata = entry.metadata()?;
+            std::os::unix::fs::lchown(&target, Some(metadata.uid()), Some(metadata.gid())).ok();
+        } else if fs::hard_link(&path, &target).is_err() {
+            fs::copy(&path, &target)?;
+        }
+    }
+    
+    fs::set_permissions(dst, metadata.permissions())?;
+    std::os::unix::fs::lchown(dst, Some(metadata.uid()), Some(metadata.gid())).ok();
+    
+    Ok(())
+}
+
+// Like `clone_tree`, but keeps the whiteouts and opaque markers overlayfs
+// records deletions with in an upper directory.
+fn clone_upper(src: &Path, dst: &Path, linked: &mut HashMap<(u64, u64), PathBuf>) -> Result<()> {
+    let metadata = fs::symlink_metadata(src)?;
+    
+    if metadata.file_type().is_char_device() && metadata.rdev() == 0 {
+        make_whiteout(dst)
+    } else if metadata.is_dir() {
+        fs::create_dir_all(dst)?;
+        if is_opaque(src) {
+            set_opaque(dst)?;
+        }
+        for entry in fs::read_dir(src)? {
+            let entry = entry?;
+            clone_upper(&entry.path(), &dst.join(entry.file_name()), linked)?;
+        }
+        fs::set_permissions(dst, metadata.permissions())?;
+        Ok(())
+    } else {
+        clone_tree(src, dst, linked)
+    }
+}
+
+fn make_whiteout(path: &Path) -> Result<()> {
+    let c_path = c_path(path)?;
+    if unsafe { libc::mknod(c_path.as_ptr(), libc::S_IFCHR, libc::makedev(0, 0)) } != 0 {
+        return Err(anyhow!("Failed to create whiteout {:?}: {}", path, io::Error::last_os_error()));
+    }
+    Ok(())
+}
+
+fn set_opaque(dir: &Path) -> Result<()> {
+    let c_dir = c_path(dir)?;
+    let name = CString::new(OPAQUE_XATTR)?;
+    if unsafe { libc::lsetxattr(c_dir.as_ptr(), name.as_ptr(), c"y".as_ptr().cast(), 1, 0) } != 0 {
+        return Err(anyhow!("Failed to mark {:?} opaque: {}", dir, io::Error::last_os_error()));
+    }
+    Ok(())
+}
+
+fn is_opaque(dir: &Path) -> bool {
+    let (Ok(c_dir), Ok(name)) = (c_path(dir), CString::new(OPAQUE_XATTR)) else {
+        return false;
+    };
+    let mut value = [0u8; 1];
+    let len = unsafe { libc::lgetxattr(c_dir.as_ptr(), name.as_ptr(), value.as_mut_ptr().cast(), value.len()) };
+    len == 1 && value[0] == b'y'
+}
+
+fn c_path(path: &Path) -> Result<CString> {
+    Ok(CString::new(path.as_os_str().as_bytes())?)
+}
@@ -0,0 +1,77 @@
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+
+use super::StorageDriver;
+use crate::filesystem::diff::{self, Change, FileIndex};
+use crate::filesystem::layer_cache::LayerCache;
+use crate::filesystem::ownership::OwnershipMap;
+use crate::image::Layer;
+use crate::state::ContainerStore;
+
+// Plain directories: layers are extracted once into the layer cache and
+// copied into a per-container rootfs under the state directory, which is
+// kept between runs.
+pub struct VfsDriver {
+    store: ContainerStore,
+    layer_cache: LayerCache,
+    cache_budget: Option<u64>,
+}
+
+impl VfsDriver {
+    pub fn new(store: ContainerStore, layer_cache: LayerCache) -> Self {
+        Self {
+            store,
+            layer_cache,
+            cache_budget: None,
+        }
+    }
+    
+    // Least recently used layers are evicted once the cache grows past
+    // `budget` bytes, never those of the image just applied.
+    pub fn with_cache_budget(mut self, budget: Option<u64>) -> Self {
+        self.cache_budget = budget;
+        self
+    }
+    
+    pub fn layer_cache(&self) -> &LayerCache {
+        &self.layer_cache
+    }
+}
+
+impl StorageDriver for VfsDriver {
+    fn name(&self) -> &'static str {
+        "vfs"
+    }
+    
+    fn rootfs_path(&self, container_id: &str) -> Result<PathBuf> {
+        self.store.rootfs_dir(container_id)
+    }
+    
+    fn apply_layer(&self, rootfs: &Path, layer: &Layer, ownership: &mut OwnershipMap) -> Result<()> {
+        let layer_dir = self.layer_cache.ensure(layer)?;
+        self.layer_cache.apply(&layer_dir, rootfs, ownership)
+    }
+    
+    // Layers are extracted into the cache in parallel, then applied in order.
+    fn apply_layers(&self, rootfs: &Path, layers: &[Layer], ownership: &mut OwnershipMap) -> Result<()> {
+        let layer_dirs = self.layer_cache.ensure_all(layers)?;
+        for layer_dir in &layer_dirs {
+            self.layer_cache.apply(layer_dir, rootfs, ownership)?;
+        }
+        
+        if let Some(budget) = self.cache_budget {
+            self.layer_cache.prune(budget, &layer_dirs)?;
+        }
+        
+        Ok(())
+    }
+    
+    fn commit_diff(&self, container_id: &str, pristine: &FileIndex) -> Result<Vec<Change>> {
+        let current = diff::scan(&self.rootfs_path(container_id)?)?;
+        Ok(diff::diff(pristine, &current))
+    }
+    
+    fn partial_data(&self) -> Result<Vec<PathBuf>> {
+        self.layer_cache.partial_extractions()
+    }
+}
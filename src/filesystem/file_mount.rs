@@ -0,0 +1,113 @@
+use anyhow::Result;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+use tracing::debug;
+
+use crate::container::{Consistency, VolumeMount};
+
+// Size and mtime of a file, used to tell which side of a mount changed.
+type Stamp = Option<(u64, u128)>;
+
+// A single host file mounted into the rootfs. WASI can only preopen
+// directories, so the file is copied into place and kept in sync with the
+// host copy by polling for as long as the container runs.
+pub struct FileMount {
+    host_path: PathBuf,
+    target: PathBuf,
+    read_only: bool,
+    consistency: Consistency,
+    stamps: Mutex<(Stamp, Stamp)>,
+}
+
+impl FileMount {
+    pub fn new(volume: &VolumeMount, target: PathBuf) -> Result<Self> {
+        let mount = Self {
+            host_path: volume.host_path.clone(),
+            target,
+            read_only: volume.read_only,
+            consistency: volume.consistency,
+            stamps: Mutex::new((None, None)),
+        };
+        mount.copy_in()?;
+        
+        Ok(mount)
+    }
+    
+    pub fn host_path(&self) -> &Path {
+        &self.host_path
+    }
+    
+    pub fn target(&self) -> &Path {
+        &self.target
+    }
+    
+    // Called periodically while the container runs; only propagates what
+    // the mount's consistency mode allows before exit.
+    pub fn poll(&self) -> Result<()> {
+        match self.consistency {
+            Consistency::Consistent => self.sync_with(true),
+            Consistency::Cached => self.sync_with(false),
+            Consistency::Delegated => Ok(()),
+        }
+    }
+    
+    // Host changes always win. Guest changes are written back to the host
+    // for read-write mounts and reverted for read-only ones.
+    pub fn sync(&self) -> Result<()> {
+        self.sync_with(true)
+    }
+    
+    fn sync_with(&self, outbound: bool) -> Result<()> {
+        let (host, target) = *self.stamps.lock().unwrap_or_else(|e| e.into_inner());
+        let current = stamp(&self.host_path);
+        
+        if current.is_none() {
+            return Ok(());
+        }
+        
+        if current != host {
+            debug!("Syncing {:?} into container", self.host_path);
+            self.copy_in()?;
+        } else if outbound && stamp(&self.target) != target {
+            if self.read_only {
+                debug!("Reverting guest write to read-only mount {:?}", self.target);
+                self.copy_in()?;
+            } else if self.target.exists() {
+                debug!("Syncing {:?} back to host", self.target);
+                fs::copy(&self.target, &self.host_path)?;
+                self.record();
+            }
+        }
+        
+        Ok(())
+    }
+    
+    fn copy_in(&self) -> Result<()> {
+        if fs::symlink_metadata(&self.target).is_ok() {
+            fs::remove_file(&self.target)?;
+        }
+        fs::copy(&self.host_path, &self.target)?;
+        
+        if self.read_only {
+            let mut permissions = fs::metadata(&self.target)?.permissions();
+            permissions.set_mode(permissions.mode() & !0o222);
+            fs::set_permissions(&self.target, permissions)?;
+        }
+        
+        self.record();
+        Ok(())
+    }
+    
+    fn record(&self) {
+        *self.stamps.lock().unwrap_or_else(|e| e.into_inner()) = (stamp(&self.host_path), stamp(&self.target));
+    }
+}
+
+fn stamp(path: &Path) -> Stamp {
+    let metadata = fs::metadata(path).ok()?;
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some((metadata.len(), modified.as_nanos()))
+}
@@ -0,0 +1,366 @@
+use anyhow::{Result, anyhow};
+use std::collections::HashMap;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tar::Archive;
+use tracing::debug;
+use uuid::Uuid;
+
+use super::copy::resolve_path;
+use super::ownership::{Ownership, OwnershipMap};
+use super::memory::normalize;
+use super::{dir_size, is_whiteout, parallel_map, remove_path, OPAQUE_WHITEOUT, WHITEOUT_PREFIX};
+use crate::image::Layer;
+
+const STAGING_PREFIX: &str = ".tmp-";
+const OWNERSHIP_SUFFIX: &str = ".ownership.json";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LinkMode {
+    // Copies go through copy_file_range, which reflinks on filesystems that
+    // support it, so containers never share writable inodes with the cache.
+    #[default]
+    Copy,
+    // Shares inodes with the cache; only safe for rootfs trees that are
+    // never written to.
+    Hardlink,
+}
+
+pub struct LayerCache {
+    layers_dir: PathBuf,
+    link_mode: LinkMode,
+}
+
+impl LayerCache {
+    pub fn new(layers_dir: PathBuf) -> Result<Self> {
+        fs::create_dir_all(&layers_dir)?;
+        
+        Ok(Self {
+            layers_dir,
+            link_mode: LinkMode::default(),
+        })
+    }
+    
+    pub fn with_link_mode(mut self, link_mode: LinkMode) -> Self {
+        self.link_mode = link_mode;
+        self
+    }
+    
+    pub fn layer_dir(&self, digest: &str) -> Result<PathBuf> {
+        let (algorithm, hex) = digest
+            .split_once(':')
+            .ok_or_else(|| anyhow!("Invalid layer digest: {}", digest))?;
+        
+        let valid = |part: &str| !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric());
+        if !valid(algorithm) || !valid(hex) {
+            return Err(anyhow!("Invalid layer digest: {}", digest));
+        }
+        
+        Ok(self.layers_dir.join(algorithm).join(hex))
+    }
+    
+    pub fn ensure(&self, layer: &Layer) -> Result<PathBuf> {
+        let layer_dir = self.layer_dir(&layer.digest)?;
+        
+        if layer_dir.is_dir() {
+            debug!("Using cached layer: {}", layer.digest);
+            touch(&ownership_file(&layer_dir));
+            return Ok(layer_dir);
+        }
+        
+        debug!("Extracting layer into cache: {}", layer.digest);
+        
+        let parent = layer_dir.parent().unwrap_or(&self.layers_dir);
+        fs::create_dir_all(parent)?;
+        
+        // Unpack next to the final location and rename, so concurrent
+        // extractions of the same layer never observe a partial tree.
+        let staging = parent.join(format!("{}{}", STAGING_PREFIX, Uuid::new_v4()));
+        fs::create_dir_all(&staging)?;
+        
+        let result = unpack_layer(layer, &staging)
+            .and_then(|ownership| ownership.save(&ownership_file(&layer_dir)));
+        
+        if let Err(e) = result {
+            fs::remove_dir_all(&staging).ok();
+            return Err(e);
+        }
+        
+        if fs::rename(&staging, &layer_dir).is_err() {
+            fs::remove_dir_all(&staging)?;
+            if !layer_dir.is_dir() {
+                return Err(anyhow!("Failed to cache layer: {}", layer.digest));
+            }
+        }
+        
+        Ok(layer_dir)
+    }
+    
+    pub fn ensure_all(&self, layers: &[Layer]) -> Result<Vec<PathBuf>> {
+        parallel_map(layers, |layer| self.ensure(layer))
+    }
+    
+    // Staging trees and ownership files of extractions that are in
+    // progress, or were interrupted by a crash.
+    pub fn partial_extractions(&self) -> Result<Vec<PathBuf>> {
+        let mut partial = Vec::new();
+        for algorithm in fs::read_dir(&self.layers_dir)? {
+            let algorithm = algorithm?.path();
+            if !algorithm.is_dir() {
+                continue;
+            }
+            for entry in fs::read_dir(&algorithm)? {
+                let path = entry?.path();
+                let layer_dir = path.to_string_lossy()
+                    .strip_suffix(OWNERSHIP_SUFFIX)
+                    .map(PathBuf::from);
+                if is_staging(&path) || layer_dir.is_some_and(|dir| !dir.is_dir()) {
+                    partial.push(path);
+                }
+            }
+        }
+        
+        Ok(partial)
+    }
+    
+    // Evicts least recently used layers until the cache fits in `budget`
+    // bytes. Layers in `keep` are never evicted, even if that leaves the
+    // cache over budget. Returns the number of bytes freed.
+    pub fn prune(&self, budget: u64, keep: &[PathBuf]) -> Result<u64> {
+        let mut cached = Vec::new();
+        for algorithm in fs::read_dir(&self.layers_dir)? {
+            let algorithm = algorithm?.path();
+            if !algorithm.is_dir() {
+                continue;
+            }
+            for entry in fs::read_dir(&algorithm)? {
+                let path = entry?.path();
+                if !path.is_dir() || is_staging(&path) {
+                    continue;
+                }
+                let last_used = fs::metadata(ownership_file(&path))
+                    .or_else(|_| fs::metadata(&path))?
+                    .modified()?;
+                cached.push((last_used, dir_size(&path)?, path));
+            }
+        }
+        
+        let mut total: u64 = cached.iter().map(|(_, size, _)| size).sum();
+        let mut freed = 0;
+        cached.sort();
+        
+        for (_, size, path) in cached {
+            if total <= budget {
+                break;
+            }
+            if keep.contains(&path) {
+                continue;
+            }
+            debug!("Evicting cached layer: {:?}", path);
+            fs::remove_dir_all(&path)?;
+            fs::remove_file(ownership_file(&path)).ok();
+            total -= size;
+            freed += size;
+        }
+        
+        Ok(freed)
+    }
+    
+    pub fn apply(&self, layer_dir: &Path, rootfs: &Path, ownership: &mut OwnershipMap) -> Result<()> {
+        let layer_ownership = OwnershipMap::load(&ownership_file(layer_dir))?;
+        
+        // Whiteouts only hide lower-layer content, so they are applied before
+        // any of this layer's own entries.
+        self.apply_whiteouts(layer_dir, layer_dir, rootfs, ownership)?;
+        self.apply_entries(layer_dir, layer_dir, rootfs, &layer_ownership, &mut HashMap::new())?;
+        
+        ownership.extend(layer_ownership);
+        
+        Ok(())
+    }
+    
+    // Folds a cached layer's ownership into `ownership` the way `apply`
+    // does, for drivers that stack cached layers instead of copying them.
+    pub fn merge_ownership(&self, layer_dir: &Path, ownership: &mut OwnershipMap) -> Result<()> {
+        forget_whiteouts(layer_dir, layer_dir, ownership)?;
+        ownership.extend(OwnershipMap::load(&ownership_file(layer_dir))?);
+        Ok(())
+    }
+    
+    fn apply_whiteouts(&self, layer_dir: &Path, dir: &Path, rootfs: &Path, ownership: &mut OwnershipMap) -> Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            
+            let relative = dir.strip_prefix(layer_dir)?;
+            
+            if name == OPAQUE_WHITEOUT {
+                let target = resolve_path(rootfs, relative)?;
+                if target.is_dir() {
+                    for child in fs::read_dir(&target)? {
+                        remove_path(&child?.path())?;
+                    }
+                }
+                ownership.remove_children(relative);
+            } else if let Some(hidden) = name.strip_prefix(WHITEOUT_PREFIX) {
+                let parent = resolve_path(rootfs, relative)?;
+                remove_path(&parent.join(hidden))?;
+                ownership.remove_tree(&relative.join(hidden));
+            } else if entry.file_type()?.is_dir() {
+                self.apply_whiteouts(layer_dir, &path, rootfs, ownership)?;
+            }
+        }
+        
+        Ok(())
+    }
+    
+    // `copied` maps cached inodes to the first path created for them, so
+    // files hardlinked within a layer stay linked in the container.
+    fn apply_entries(
+        &self,
+        layer_dir: &Path,
+        dir: &Path,
+        rootfs: &Path,
+        ownership: &OwnershipMap,
+        copied: &mut HashMap<(u64, u64), PathBuf>,
+    ) -> Result<()> {
+        let relative = dir.strip_prefix(layer_dir)?;
+        let parent = resolve_path(rootfs, relative)?;
+        
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let src = entry.path();
+            let name = entry.file_name();
+            
+            if name.to_string_lossy().starts_with(WHITEOUT_PREFIX) {
+                continue;
+            }
+            
+            let dst = parent.join(&name);
+            let owner = ownership.get(&relative.join(&name));
+            let file_type = entry.file_type()?;
+            let existing = fs::symlink_metadata(&dst).ok();
+            
+            if file_type.is_dir() {
+                if existing.as_ref().is_some_and(|m| !m.is_dir()) {
+                    remove_path(&dst)?;
+                }
+                fs::create_dir_all(&dst)?;
+                if let Some(owner) = owner {
+                    owner.apply_to(&dst, true)?;
+                }
+                self.apply_entries(layer_dir, &src, rootfs, ownership, copied)?;
+                continue;
+            }
+            
+            if existing.is_some() {
+                remove_path(&dst)?;
+            }
+            
+            if file_type.is_symlink() {
+                std::os::unix::fs::symlink(fs::read_link(&src)?, &dst)?;
+            } else {
+                match self.link_mode {
+                    LinkMode::Copy => {
+                        let metadata = entry.metadata()?;
+                        let inode = (metadata.dev(), metadata.ino());
+                        if metadata.nlink() > 1 {
+                            if let Some(first) = copied.get(&inode) {
+                                if fs::hard_link(first, &dst).is_ok() {
+                                    continue;
+                                }
+                            }
+                        }
+                        fs::copy(&src, &dst)?;
+                        if let Some(owner) = owner {
+                            owner.apply_to(&dst, false)?;
+                        }
+                        if metadata.nlink() > 1 {
+                            copied.entry(inode).or_insert(dst);
+                        }
+                    }
+                    LinkMode::Hardlink => {
+                        if fs::hard_link(&src, &dst).is_err() {
+                            fs::copy(&src, &dst)?;
+                        }
+                    }
+                }
+            }
+        }
+        
+        Ok(())
+    }
+}
+
+fn forget_whiteouts(layer_dir: &Path, dir: &Path, ownership: &mut OwnershipMap) -> Result<()> {
+    let relative = dir.strip_prefix(layer_dir)?;
+    
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        
+        if name == OPAQUE_WHITEOUT {
+            ownership.remove_children(relative);
+        } else if let Some(hidden) = name.strip_prefix(WHITEOUT_PREFIX) {
+            ownership.remove_tree(&relative.join(hidden));
+        } else if entry.file_type()?.is_dir() {
+            forget_whiteouts(layer_dir, &entry.path(), ownership)?;
+        }
+    }
+    
+    Ok(())
+}
+
+// Marks a layer as recently used for `prune`. Best effort: a failure only
+// makes the layer an earlier eviction candidate.
+fn touch(path: &Path) {
+    if let Ok(file) = fs::File::options().append(true).open(path) {
+        file.set_modified(SystemTime::now()).ok();
+    }
+}
+
+fn is_staging(path: &Path) -> bool {
+    path.file_name().is_some_and(|n| n.to_string_lossy().starts_with(STAGING_PREFIX))
+}
+
+fn ownership_file(layer_dir: &Path) -> PathBuf {
+    let mut file_name = layer_dir.file_name().unwrap_or_default().to_owned();
+    file_name.push(OWNERSHIP_SUFFIX);
+    layer_dir.with_file_name(file_name)
+}
+
+// Verified against the layer's DiffID once unpacked, so a corrupt blob
+// never makes it into the cache.
+fn unpack_layer(layer: &Layer, dst: &Path) -> Result<OwnershipMap> {
+    let mut archive = Archive::new(layer.open()?);
+    let mut ownership = OwnershipMap::new();
+    
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        let owner = Ownership::from_header(entry.header());
+        let entry_type = entry.header().entry_type();
+        
+        if !entry.unpack_in(dst)? || is_whiteout(&path) {
+            continue;
+        }
+        let Some(relative) = normalize(&path) else {
+            continue;
+        };
+        
+        ownership.insert(&relative, owner);
+        
+        if entry_type.is_dir() || entry_type.is_file() {
+            owner.apply_to(&dst.join(&relative), entry_type.is_dir())?;
+        }
+    }
+    
+    archive.into_inner().verify()?;
+    
+    Ok(ownership)
+}
@@ -0,0 +1,241 @@
+use anyhow::Result;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use tar::{Archive, EntryType};
+use flate2::read::GzDecoder;
+use tracing::debug;
+
+use super::copy::resolve_path;
+use super::ownership::{Ownership, OwnershipMap};
+use super::{OPAQUE_WHITEOUT, WHITEOUT_PREFIX};
+use crate::image::Layer;
+
+#[derive(Debug, Clone)]
+pub enum Node {
+    Dir,
+    // Hardlinked paths share the same buffer, which `materialize` uses to
+    // recreate the link instead of writing a second copy.
+    File { data: Arc<Vec<u8>> },
+    Symlink { target: PathBuf },
+}
+
+#[derive(Debug, Default)]
+pub struct DecodedLayer {
+    whiteouts: Vec<PathBuf>,
+    opaque_dirs: Vec<PathBuf>,
+    nodes: Vec<(PathBuf, Node, Ownership)>,
+    links: Vec<(PathBuf, PathBuf)>,
+}
+
+impl DecodedLayer {
+    pub fn read(layer_path: &Path) -> Result<Self> {
+        debug!("Loading layer into memory: {:?}", layer_path);
+        
+        let tar_gz = fs::File::open(layer_path)?;
+        Self::decode(&mut Archive::new(GzDecoder::new(tar_gz)))
+    }
+    
+    // Like `read`, but checks the layer against its DiffID.
+    pub fn load(layer: &Layer) -> Result<Self> {
+        debug!("Loading layer into memory: {}", layer.digest);
+        
+        let mut archive = Archive::new(layer.open()?);
+        let decoded = Self::decode(&mut archive)?;
+        archive.into_inner().verify()?;
+        
+        Ok(decoded)
+    }
+    
+    fn decode<R: Read>(archive: &mut Archive<R>) -> Result<Self> {
+        let mut layer = Self::default();
+        
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let Some(path) = normalize(&entry.path()?) else {
+                continue;
+            };
+            let Some(file_name) = path.file_name().and_then(|n| n.to_str()) else {
+                continue;
+            };
+            let parent = path.parent().unwrap_or_else(|| Path::new("")).to_path_buf();
+            
+            if file_name == OPAQUE_WHITEOUT {
+                layer.opaque_dirs.push(parent);
+                continue;
+            }
+            if let Some(hidden) = file_name.strip_prefix(WHITEOUT_PREFIX) {
+                layer.whiteouts.push(parent.join(hidden));
+                continue;
+            }
+            
+            let owner = Ownership::from_header(entry.header());
+            let node = match entry.header().entry_type() {
+                EntryType::Directory => Node::Dir,
+                EntryType::Symlink => match entry.link_name()? {
+                    Some(target) => Node::Symlink { target: target.into_owned() },
+                    None => continue,
+                },
+                EntryType::Link => match entry.link_name()?.and_then(|t| normalize(&t)) {
+                    Some(target) => {
+                        layer.links.push((path, target));
+                        continue;
+                    }
+                    None => continue,
+                },
+                EntryType::Regular | EntryType::Continuous => {
+                    let mut data = Vec::with_capacity(entry.size() as usize);
+                    entry.read_to_end(&mut data)?;
+                    Node::File { data: Arc::new(data) }
+                }
+                _ => continue,
+            };
+            
+            layer.nodes.push((path, node, owner));
+        }
+        
+        Ok(layer)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct MemoryFs {
+    entries: BTreeMap<PathBuf, Node>,
+    ownership: OwnershipMap,
+}
+
+impl MemoryFs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    pub fn get(&self, path: &Path) -> Option<&Node> {
+        self.entries.get(&normalize(path)?)
+    }
+    
+    pub fn ownership(&self) -> &OwnershipMap {
+        &self.ownership
+    }
+    
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+    
+    // Bytes of file content, counting hardlinked data once.
+    pub fn size(&self) -> u64 {
+        let mut seen = std::collections::HashSet::new();
+        self.entries.values()
+            .filter_map(|node| match node {
+                Node::File { data } if seen.insert(Arc::as_ptr(data)) => Some(data.len() as u64),
+                _ => None,
+            })
+            .sum()
+    }
+    
+    pub fn apply_layer(&mut self, layer_path: &Path) -> Result<()> {
+        self.merge(DecodedLayer::read(layer_path)?);
+        Ok(())
+    }
+    
+    pub fn merge(&mut self, layer: DecodedLayer) {
+        // Whiteouts hide lower-layer content only, so they are applied before
+        // this layer's own entries are inserted.
+        for dir in layer.opaque_dirs {
+            self.remove_children(&dir);
+            self.ownership.remove_children(&dir);
+        }
+        for path in layer.whiteouts {
+            self.entries.remove(&path);
+            self.remove_children(&path);
+            self.ownership.remove_tree(&path);
+        }
+        for (path, node, owner) in layer.nodes {
+            if !matches!(node, Node::Dir) {
+                self.remove_children(&path);
+                self.ownership.remove_children(&path);
+            }
+            self.ownership.insert(&path, owner);
+            self.entries.insert(path, node);
+        }
+        for (path, target) in layer.links {
+            if let Some(node) = self.entries.get(&target).cloned() {
+                if let Some(owner) = self.ownership.get(&target).copied() {
+                    self.ownership.insert(&path, owner);
+                }
+                self.entries.insert(path, node);
+            }
+        }
+    }
+    
+    fn remove_children(&mut self, dir: &Path) {
+        self.entries.retain(|path, _| path == dir || !path.starts_with(dir));
+    }
+    
+    pub fn materialize(&self, root: &Path) -> Result<()> {
+        fs::create_dir_all(root)?;
+        let mut written: HashMap<*const Vec<u8>, PathBuf> = HashMap::new();
+        
+        for (path, node) in &self.entries {
+            // Parents are resolved inside the root so a symlinked directory
+            // from an earlier entry can't redirect writes onto the host.
+            let Some(name) = path.file_name() else {
+                continue;
+            };
+            let parent = resolve_path(root, path.parent().unwrap_or_else(|| Path::new("")))?;
+            fs::create_dir_all(&parent)?;
+            let target = parent.join(name);
+            
+            let owner = self.ownership.get(path);
+            
+            match node {
+                Node::Dir => {
+                    fs::create_dir_all(&target)?;
+                    if let Some(owner) = owner {
+                        owner.apply_to(&target, true)?;
+                    }
+                }
+                Node::File { data } => {
+                    if let Some(first) = written.get(&Arc::as_ptr(data)) {
+                        if fs::hard_link(first, &target).is_ok() {
+                            continue;
+                        }
+                    }
+                    fs::write(&target, data.as_slice())?;
+                    if let Some(owner) = owner {
+                        owner.apply_to(&target, false)?;
+                    }
+                    written.entry(Arc::as_ptr(data)).or_insert(target);
+                }
+                Node::Symlink { target: link } => {
+                    std::os::unix::fs::symlink(link, &target)?;
+                }
+            }
+        }
+        
+        Ok(())
+    }
+}
+
+pub(super) fn normalize(path: &Path) -> Option<PathBuf> {
+    let mut normalized = PathBuf::new();
+    
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => normalized.push(part),
+            Component::CurDir | Component::RootDir => {}
+            Component::ParentDir | Component::Prefix(_) => return None,
+        }
+    }
+    
+    if normalized.as_os_str().is_empty() {
+        None
+    } else {
+        Some(normalized)
+    }
+}
@@ -1,42 +1,116 @@
-use anyhow::Result;
-use std::path::{Path, PathBuf};
+use anyhow::{Result, anyhow};
+use std::path::{Component, Path, PathBuf};
 use std::fs;
-use tempfile::TempDir;
-use tar::Archive;
-use flate2::read::GzDecoder;
-use tracing::{info, debug};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tracing::info;
 
-use crate::container::Container;
+use crate::container::{Container, MountMode, VolumeMount};
+use crate::image::Layer;
+use crate::network::resolv::ResolvConf;
+
+pub mod copy;
+pub mod diff;
+pub mod dir_mount;
+pub mod driver;
+pub mod file_mount;
+pub mod layer_cache;
+pub mod memory;
+pub mod ownership;
+pub mod proc;
+pub mod watch;
+
+use self::dir_mount::DirMount;
+use self::file_mount::FileMount;
+use self::driver::StorageDriver;
+use self::ownership::OwnershipMap;
+use self::proc::{ProcFs, ProcInfo};
+
+const WHITEOUT_PREFIX: &str = ".wh.";
+const OPAQUE_WHITEOUT: &str = ".wh..wh..opq";
+
+// A volume whose contents live in the rootfs and are kept in sync with the
+// host by polling, rather than being shared through a preopen.
+pub enum CopiedMount {
+    File(FileMount),
+    Dir(DirMount),
+}
+
+impl CopiedMount {
+    pub fn host_path(&self) -> &Path {
+        match self {
+            Self::File(mount) => mount.host_path(),
+            Self::Dir(mount) => mount.host_path(),
+        }
+    }
+    
+    pub fn poll(&self) -> Result<()> {
+        match self {
+            Self::File(mount) => mount.poll(),
+            Self::Dir(mount) => mount.poll(),
+        }
+    }
+    
+    // Final sync once the guest has exited.
+    pub fn sync(&self) -> Result<()> {
+        match self {
+            Self::File(mount) => mount.sync(),
+            Self::Dir(mount) => mount.poll(),
+        }
+    }
+}
 
 pub struct Filesystem {
     container_id: String,
-    rootfs: TempDir,
+    hostname: String,
+    rootfs: PathBuf,
     layers: Vec<PathBuf>,
+    ownership: OwnershipMap,
+    tmpfs: Vec<(PathBuf, PathBuf)>,
+    resolv_conf: ResolvConf,
 }
 
 impl Filesystem {
-    pub fn new(container: &Container) -> Result<Self> {
-        let rootfs = TempDir::new()?;
+    pub fn new(container: &Container, rootfs: PathBuf) -> Result<Self> {
+        fs::create_dir_all(&rootfs)?;
         
         Ok(Self {
             container_id: container.id().to_string(),
+            hostname: container.network_config().hostname.clone(),
             rootfs,
             layers: Vec::new(),
+            ownership: OwnershipMap::new(),
+            tmpfs: Vec::new(),
+            // Public resolvers until the runtime picks the container's own.
+            resolv_conf: ResolvConf::layered(&[]),
         })
     }
     
+    pub fn set_resolv_conf(&mut self, resolv_conf: ResolvConf) {
+        self.resolv_conf = resolv_conf;
+    }
+    
     pub async fn setup(&self) -> Result<()> {
         info!("Setting up filesystem for container: {}", self.container_id);
         
         self.create_base_directories()?;
-        self.mount_proc_sys()?;
-        self.setup_resolv_conf()?;
+        self.create_device_nodes()?;
+        self.setup_etc_files()?;
         
         Ok(())
     }
     
     pub fn rootfs_path(&self) -> &Path {
-        self.rootfs.path()
+        &self.rootfs
+    }
+    
+    pub fn ownership(&self) -> &OwnershipMap {
+        &self.ownership
+    }
+    
+    // Host directories to preopen, paired with where the guest sees them.
+    pub fn tmpfs_mounts(&self) -> &[(PathBuf, PathBuf)] {
+        &self.tmpfs
     }
     
     fn create_base_directories(&self) -> Result<()> {
@@ -47,72 +121,97 @@ impl Filesystem {
         ];
         
         for dir in &dirs {
-            let path = self.rootfs.path().join(dir);
-            fs::create_dir_all(&path)?;
+            fs::create_dir_all(self.resolve_path(Path::new(dir))?)?;
         }
         
         let usr_dirs = ["bin", "sbin", "lib", "lib64", "local", "share", "include"];
         for dir in &usr_dirs {
-            let path = self.rootfs.path().join("usr").join(dir);
-            fs::create_dir_all(&path)?;
+            fs::create_dir_all(self.resolve_path(&Path::new("usr").join(dir))?)?;
         }
         
         let var_dirs = ["log", "cache", "lib", "run", "tmp"];
         for dir in &var_dirs {
-            let path = self.rootfs.path().join("var").join(dir);
-            fs::create_dir_all(&path)?;
+            fs::create_dir_all(self.resolve_path(&Path::new("var").join(dir))?)?;
         }
         
         Ok(())
     }
     
-    fn mount_proc_sys(&self) -> Result<()> {
-        fs::write(
-            self.rootfs.path().join("proc").join("cpuinfo"),
-            "processor\t: 0\nvendor_id\t: WASM\nmodel name\t: WASM Container Runtime\n",
-        )?;
-        
-        fs::write(
-            self.rootfs.path().join("proc").join("meminfo"),
-            "MemTotal:        8388608 kB\nMemFree:         4194304 kB\n",
-        )?;
+    // Resolved inside the rootfs, so an image can't point it at a host file.
+    pub fn hosts_file(&self) -> Option<PathBuf> {
+        self.resolve_in_rootfs(Path::new("/etc/hosts"))
+    }
+    
+    pub fn mount_proc(&self, info: ProcInfo, memory_used: Arc<AtomicU64>) -> Result<ProcFs> {
+        let proc_fs = ProcFs::new(self.rootfs.join("proc"), info, memory_used);
+        proc_fs.write_all()?;
         
-        Ok(())
+        Ok(proc_fs)
     }
     
-    fn setup_resolv_conf(&self) -> Result<()> {
-        fs::write(
-            self.rootfs.path().join("etc").join("resolv.conf"),
-            "nameserver 8.8.8.8\nnameserver 8.8.4.4\n",
+    fn setup_etc_files(&self) -> Result<()> {
+        self.write_file(
+            Path::new("/etc/resolv.conf"),
+            self.resolv_conf.to_string(),
         )?;
         
-        fs::write(
-            self.rootfs.path().join("etc").join("hostname"),
-            &self.container_id,
+        self.write_file(
+            Path::new("/etc/hostname"),
+            format!("{}\n", self.hostname),
         )?;
         
-        fs::write(
-            self.rootfs.path().join("etc").join("hosts"),
-            format!("127.0.0.1\tlocalhost\n127.0.1.1\t{}\n", self.container_id),
+        // Replaced by the network's own once the container joins one.
+        self.write_file(
+            Path::new("/etc/hosts"),
+            format!("127.0.0.1\tlocalhost\n127.0.1.1\t{}\n", self.hostname),
         )?;
         
         Ok(())
     }
     
-    pub async fn extract_layer(&mut self, layer_path: &Path) -> Result<()> {
-        debug!("Extracting layer: {:?}", layer_path);
+    pub async fn apply_layers(&mut self, driver: &dyn StorageDriver, layers: &[Layer]) -> Result<()> {
+        driver.apply_layers(&self.rootfs, layers, &mut self.ownership)?;
+        self.layers.extend(layers.iter().map(|layer| layer.path.clone()));
         
-        let tar_gz = fs::File::open(layer_path)?;
-        let tar = GzDecoder::new(tar_gz);
-        let mut archive = Archive::new(tar);
+        Ok(())
+    }
+    
+    pub async fn extract_layer(&mut self, layer_path: &Path) -> Result<()> {
+        driver::extract_layer(&self.rootfs, layer_path)?;
+        self.layers.push(layer_path.to_path_buf());
         
-        archive.unpack(self.rootfs.path())?;
+        Ok(())
+    }
+    
+    fn resolve_in_rootfs(&self, path: &Path) -> Option<PathBuf> {
+        resolve_in_rootfs(&self.rootfs, path)
+    }
+    
+    fn resolve_path(&self, path: &Path) -> Result<PathBuf> {
+        self.resolve_in_rootfs(path)
+            .ok_or_else(|| anyhow!("Invalid path in rootfs: {:?}", path))
+    }
+    
+    // Writes a file the runtime provides. Its directory is resolved inside
+    // the rootfs, and a symlink the image left in its place is replaced
+    // rather than followed.
+    fn write_file(&self, path: &Path, contents: impl AsRef<[u8]>) -> Result<()> {
+        let name = path.file_name()
+            .ok_or_else(|| anyhow!("Invalid path in rootfs: {:?}", path))?;
+        let dir = self.resolve_path(path.parent().unwrap_or(Path::new("/")))?;
+        fs::create_dir_all(&dir)?;
         
-        self.layers.push(layer_path.to_path_buf());
+        let target = dir.join(name);
+        if fs::symlink_metadata(&target).is_ok_and(|metadata| metadata.file_type().is_symlink()) {
+            fs::remove_file(&target)?;
+        }
+        fs::write(&target, contents)?;
         
         Ok(())
     }
     
+    // Placeholders so /dev entries show up in listings and stat calls. Opens
+    // of these paths are served by the runtime's virtual devices instead.
     pub fn create_device_nodes(&self) -> Result<()> {
         let devices = [
             ("null", 1, 3),
@@ -124,58 +223,130 @@ impl Filesystem {
         ];
         
         for (name, _major, _minor) in &devices {
-            let path = self.rootfs.path().join("dev").join(name);
-            fs::write(&path, "")?;
+            self.write_file(&Path::new("/dev").join(name), "")?;
         }
         
         Ok(())
     }
     
-    pub fn mount_volume(&self, host_path: &Path, container_path: &Path) -> Result<()> {
-        let target = self.rootfs.path().join(
-            container_path.strip_prefix("/").unwrap_or(container_path)
-        );
+    // Backs `container_path` with an empty host directory outside the rootfs,
+    // so it stays writable when the rootfs itself is mounted read-only.
+    pub fn mount_tmpfs(&mut self, container_path: &Path, host_dir: &Path) -> Result<()> {
+        let target = self.resolve_in_rootfs(container_path)
+            .ok_or_else(|| anyhow!("Invalid mount point: {:?}", container_path))?;
+        fs::create_dir_all(&target)?;
         
-        if host_path.is_dir() {
-            fs::create_dir_all(&target)?;
-            for entry in fs::read_dir(host_path)? {
-                let entry = entry?;
-                let file_name = entry.file_name();
-                let src = entry.path();
-                let dst = target.join(&file_name);
-                
-                if src.is_dir() {
-                    self.copy_dir_recursive(&src, &dst)?;
-                } else {
-                    fs::copy(&src, &dst)?;
-                }
-            }
-        } else {
-            if let Some(parent) = target.parent() {
-                fs::create_dir_all(parent)?;
-            }
-            fs::copy(host_path, &target)?;
-        }
+        remove_path(host_dir)?;
+        fs::create_dir_all(host_dir)?;
         
+        self.tmpfs.push((host_dir.to_path_buf(), container_path.to_path_buf()));
         Ok(())
     }
     
-    fn copy_dir_recursive(&self, src: &Path, dst: &Path) -> Result<()> {
-        fs::create_dir_all(dst)?;
+    // Bind-mounted directories only need a mount point, since they are
+    // shared with the guest through their own preopen. Anything else is
+    // copied in and returned for the caller to keep in sync.
+    pub fn mount_volume(&self, volume: &VolumeMount) -> Result<Option<CopiedMount>> {
+        let target = self.resolve_in_rootfs(&volume.container_path)
+            .ok_or_else(|| anyhow!("Invalid mount point: {:?}", volume.container_path))?;
         
-        for entry in fs::read_dir(src)? {
-            let entry = entry?;
-            let file_name = entry.file_name();
-            let src_path = entry.path();
-            let dst_path = dst.join(&file_name);
-            
-            if src_path.is_dir() {
-                self.copy_dir_recursive(&src_path, &dst_path)?;
-            } else {
-                fs::copy(&src_path, &dst_path)?;
+        if volume.host_path.is_dir() {
+            fs::create_dir_all(&target)?;
+            if volume.mode == MountMode::Bind {
+                return Ok(None);
             }
+            return Ok(Some(CopiedMount::Dir(DirMount::new(volume, target)?)));
         }
         
-        Ok(())
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        
+        Ok(Some(CopiedMount::File(FileMount::new(volume, target)?)))
     }
-}
\ No newline at end of file
+}
+
+// Runs `f` over `items` on a small pool of scoped worker threads, returning
+// results in input order so callers can apply them sequentially.
+pub(crate) fn parallel_map<T, R, F>(items: &[T], f: F) -> Result<Vec<R>>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> Result<R> + Sync,
+{
+    let workers = std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+        .min(items.len());
+    
+    if workers <= 1 {
+        return items.iter().map(f).collect();
+    }
+    
+    let next = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<Result<R>>>> = Mutex::new(items.iter().map(|_| None).collect());
+    
+    std::thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(item) = items.get(index) else {
+                    break;
+                };
+                let result = f(item);
+                results.lock().unwrap_or_else(|e| e.into_inner())[index] = Some(result);
+            });
+        }
+    });
+    
+    results
+        .into_inner()
+        .unwrap_or_else(|e| e.into_inner())
+        .into_iter()
+        .map(|result| result.unwrap_or_else(|| Err(anyhow!("Layer worker did not complete"))))
+        .collect()
+}
+
+pub(crate) fn dir_size(path: &Path) -> Result<u64> {
+    let metadata = fs::symlink_metadata(path)?;
+    if !metadata.is_dir() {
+        return Ok(metadata.len());
+    }
+    
+    let mut size = 0;
+    for entry in fs::read_dir(path)? {
+        size += dir_size(&entry?.path())?;
+    }
+    
+    Ok(size)
+}
+
+// Rejects `..` outright, then follows any symlinks the way the guest would
+// see them so links in the rootfs can't point writes at the host.
+pub(crate) fn resolve_in_rootfs(rootfs: &Path, path: &Path) -> Option<PathBuf> {
+    let escapes = path.components()
+        .any(|c| matches!(c, Component::ParentDir | Component::Prefix(_)));
+    if escapes {
+        return None;
+    }
+    
+    copy::resolve_path(rootfs, path).ok()
+}
+
+fn is_whiteout(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|n| n.to_str())
+        .map(|n| n.starts_with(WHITEOUT_PREFIX))
+        .unwrap_or(false)
+}
+
+pub(crate) fn remove_path(path: &Path) -> Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(path)?,
+        Ok(_) => fs::remove_file(path)?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+    
+    Ok(())
+}
@@ -0,0 +1,110 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Component, Path};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ownership {
+    pub uid: u64,
+    pub gid: u64,
+    pub mode: u32,
+}
+
+impl Ownership {
+    pub fn from_header(header: &tar::Header) -> Self {
+        Self {
+            uid: header.uid().unwrap_or(0),
+            gid: header.gid().unwrap_or(0),
+            mode: header.mode().unwrap_or(0o644) & 0o7777,
+        }
+    }
+    
+    // Image modes are applied best-effort: the owner always keeps enough
+    // access to traverse, copy and delete the tree, and ownership is only
+    // changed when the host allows it (i.e. when running as root). The
+    // original values remain available from the OwnershipMap sidecar.
+    pub fn apply_to(&self, path: &Path, is_dir: bool) -> Result<()> {
+        let owner_bits = if is_dir { 0o700 } else { 0o600 };
+        fs::set_permissions(path, fs::Permissions::from_mode(self.mode | owner_bits))?;
+        
+        if let (Ok(uid), Ok(gid)) = (u32::try_from(self.uid), u32::try_from(self.gid)) {
+            std::os::unix::fs::lchown(path, Some(uid), Some(gid)).ok();
+        }
+        
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct OwnershipMap {
+    entries: BTreeMap<String, Ownership>,
+}
+
+impl OwnershipMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::new());
+        }
+        
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+    
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, serde_json::to_string(self)?)?;
+        Ok(())
+    }
+    
+    pub fn get(&self, path: &Path) -> Option<&Ownership> {
+        self.entries.get(&key(path))
+    }
+    
+    pub fn insert(&mut self, path: &Path, ownership: Ownership) {
+        self.entries.insert(key(path), ownership);
+    }
+    
+    pub fn remove_tree(&mut self, path: &Path) {
+        let root = key(path);
+        let prefix = format!("{}/", root.trim_end_matches('/'));
+        self.entries.retain(|entry, _| *entry != root && !entry.starts_with(&prefix));
+    }
+    
+    pub fn remove_children(&mut self, path: &Path) {
+        let prefix = format!("{}/", key(path).trim_end_matches('/'));
+        self.entries.retain(|entry, _| !entry.starts_with(&prefix));
+    }
+    
+    pub fn extend(&mut self, other: OwnershipMap) {
+        self.entries.extend(other.entries);
+    }
+    
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+fn key(path: &Path) -> String {
+    let mut key = String::new();
+    
+    for component in path.components() {
+        if let Component::Normal(part) = component {
+            key.push('/');
+            key.push_str(&part.to_string_lossy());
+        }
+    }
+    
+    if key.is_empty() {
+        key.push('/');
+    }
+    
+    key
+}
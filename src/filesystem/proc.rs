@@ -0,0 +1,110 @@
+use anyhow::Result;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+const KIB: u64 = 1024;
+
+pub struct ProcInfo {
+    pub cpus: usize,
+    pub memory_limit: u64,
+    pub environ: Vec<(String, String)>,
+    pub cmdline: Vec<String>,
+}
+
+pub struct ProcFs {
+    root: PathBuf,
+    info: ProcInfo,
+    memory_used: Arc<AtomicU64>,
+    started: Instant,
+}
+
+impl ProcFs {
+    pub fn new(root: PathBuf, info: ProcInfo, memory_used: Arc<AtomicU64>) -> Self {
+        Self {
+            root,
+            info,
+            memory_used,
+            started: Instant::now(),
+        }
+    }
+    
+    pub fn write_all(&self) -> Result<()> {
+        fs::create_dir_all(self.root.join("self"))?;
+        
+        fs::write(self.root.join("cpuinfo"), self.cpuinfo())?;
+        fs::write(self.root.join("self").join("environ"), self.environ())?;
+        fs::write(self.root.join("self").join("cmdline"), self.cmdline())?;
+        
+        self.refresh()
+    }
+    
+    pub fn refresh(&self) -> Result<()> {
+        fs::write(self.root.join("meminfo"), self.meminfo())?;
+        fs::write(self.root.join("uptime"), self.uptime())?;
+        fs::write(self.root.join("self").join("status"), self.status())?;
+        
+        Ok(())
+    }
+    
+    fn cpuinfo(&self) -> String {
+        (0..self.info.cpus)
+            .map(|cpu| {
+                format!(
+                    "processor\t: {}\nvendor_id\t: WASM\nmodel name\t: WASM Container Runtime\ncpu cores\t: {}\n\n",
+                    cpu, self.info.cpus
+                )
+            })
+            .collect()
+    }
+    
+    fn meminfo(&self) -> String {
+        let total = self.info.memory_limit / KIB;
+        let used = self.memory_used.load(Ordering::Relaxed) / KIB;
+        let free = total.saturating_sub(used);
+        
+        format!(
+            "MemTotal:       {:>8} kB\nMemFree:        {:>8} kB\nMemAvailable:   {:>8} kB\n",
+            total, free, free
+        )
+    }
+    
+    fn uptime(&self) -> String {
+        format!("{:.2} 0.00\n", self.started.elapsed().as_secs_f64())
+    }
+    
+    fn status(&self) -> String {
+        let used = self.memory_used.load(Ordering::Relaxed) / KIB;
+        let name = self.info.cmdline
+            .first()
+            .and_then(|arg| arg.rsplit('/').next())
+            .unwrap_or("wasm");
+        
+        format!(
+            "Name:\t{}\nState:\tR (running)\nPid:\t1\nPPid:\t0\nThreads:\t1\nVmSize:\t{:>8} kB\nVmRSS:\t{:>8} kB\n",
+            name, used, used
+        )
+    }
+    
+    fn environ(&self) -> Vec<u8> {
+        let mut environ = Vec::new();
+        for (key, value) in &self.info.environ {
+            environ.extend_from_slice(key.as_bytes());
+            environ.push(b'=');
+            environ.extend_from_slice(value.as_bytes());
+            environ.push(0);
+        }
+        environ
+    }
+    
+    fn cmdline(&self) -> Vec<u8> {
+        let mut cmdline = Vec::new();
+        for arg in &self.info.cmdline {
+            cmdline.extend_from_slice(arg.as_bytes());
+            cmdline.push(0);
+        }
+        cmdline
+    }
+}
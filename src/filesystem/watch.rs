@@ -0,0 +1,42 @@
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use super::diff::{self, Change, FileIndex};
+
+pub type WatchCallback = Arc<dyn Fn(&Change) + Send + Sync>;
+
+// Polls the rootfs and reports what changed since the previous poll. WASI
+// gives the host no hook into guest writes, so this is a periodic scan
+// rather than a real inotify watch.
+pub struct FsWatcher {
+    root: PathBuf,
+    index: Mutex<FileIndex>,
+    callbacks: Vec<WatchCallback>,
+}
+
+impl FsWatcher {
+    pub fn new(root: &Path, callbacks: Vec<WatchCallback>) -> Result<Self> {
+        Ok(Self {
+            root: root.to_path_buf(),
+            index: Mutex::new(diff::scan_metadata(root)?),
+            callbacks,
+        })
+    }
+    
+    pub fn poll(&self) -> Result<Vec<Change>> {
+        let current = diff::scan_metadata(&self.root)?;
+        let mut index = self.index.lock().unwrap_or_else(|e| e.into_inner());
+        let changes = diff::diff(&index, &current);
+        *index = current;
+        drop(index);
+        
+        for change in &changes {
+            for callback in &self.callbacks {
+                callback(change);
+            }
+        }
+        
+        Ok(changes)
+    }
+}
@@ -0,0 +1,204 @@
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub mod schema;
+
+// Space between aligned columns.
+const COLUMN_GAP: usize = 3;
+
+// A `--format` template such as `{{.ID}}\t{{.Ports}}`. Templates starting
+// with `table` print a header row and line their tab-separated columns up.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    table: bool,
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    Field(String),
+}
+
+impl Template {
+    // `fields` are the names the template may use.
+    pub fn parse(template: &str, fields: &[&str]) -> Result<Self> {
+        let (table, mut rest) = match template.strip_prefix("table") {
+            Some(rest) if rest.is_empty() || rest.starts_with(char::is_whitespace) => (true, rest.trim_start()),
+            _ => (false, template),
+        };
+        
+        let mut parts = Vec::new();
+        while let Some(start) = rest.find("{{") {
+            parts.push(Part::Text(unescape(&rest[..start])));
+            let end = rest[start..].find("}}")
+                .ok_or_else(|| anyhow!("Unclosed {{{{ in format {:?}", template))?;
+            let field = rest[start + 2..start + end].trim();
+            let Some(field) = field.strip_prefix('.').filter(|field| fields.contains(field)) else {
+                return Err(anyhow!("Unknown field {:?} in format (expected one of .{})", field, fields.join(", .")));
+            };
+            parts.push(Part::Field(field.to_string()));
+            rest = &rest[start + end + 2..];
+        }
+        parts.push(Part::Text(unescape(rest)));
+        parts.retain(|part| *part != Part::Text(String::new()));
+        
+        Ok(Self { table, parts })
+    }
+    
+    pub fn is_table(&self) -> bool {
+        self.table
+    }
+    
+    // Fills the template in with each field's value from `value`.
+    pub fn render(&self, value: impl Fn(&str) -> String) -> String {
+        self.parts.iter()
+            .map(|part| match part {
+                Part::Text(text) => text.clone(),
+                Part::Field(field) => value(field),
+            })
+            .collect()
+    }
+}
+
+// `\t` and `\n` as typed on a command line.
+fn unescape(text: &str) -> String {
+    text.replace("\\t", "\t").replace("\\n", "\n")
+}
+
+// A `--format` for scripts: read commands print their rows, the structs in
+// `schema`, or what `inspect` shows, as JSON or YAML.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Json,
+    Yaml,
+}
+
+impl OutputFormat {
+    // `json` or `yaml`; `None` leaves other formats, e.g. templates, to the
+    // caller.
+    pub fn parse(format: &str) -> Option<Self> {
+        match format {
+            "json" => Some(Self::Json),
+            "yaml" => Some(Self::Yaml),
+            _ => None,
+        }
+    }
+    
+    // `value` as a document ending in a newline.
+    pub fn render<T: Serialize + ?Sized>(self, value: &T) -> Result<String> {
+        match self {
+            Self::Json => Ok(serde_json::to_string_pretty(value)? + "\n"),
+            Self::Yaml => Ok(serde_yaml::to_string(value)?),
+        }
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+    
+    fn from_str(s: &str) -> Result<Self> {
+        Self::parse(s).ok_or_else(|| anyhow!("Unknown format: {} (expected json or yaml)", s))
+    }
+}
+
+// Pads tab-separated lines so their columns line up.
+pub fn align_columns(lines: &[String]) -> String {
+    let rows: Vec<Vec<&str>> = lines.iter().map(|line| line.split('\t').collect()).collect();
+    let mut widths: Vec<usize> = Vec::new();
+    for row in &rows {
+        for (column, cell) in row.iter().enumerate() {
+            let width = cell.chars().count();
+            match widths.get_mut(column) {
+                Some(widest) => *widest = (*widest).max(width),
+                None => widths.push(width),
+            }
+        }
+    }
+    
+    let mut output = String::new();
+    for row in rows {
+        let mut line = String::new();
+        for (column, cell) in row.iter().enumerate() {
+            line.push_str(cell);
+            if column + 1 < row.len() {
+                let padding = widths[column] - cell.chars().count() + COLUMN_GAP;
+                line.extend(std::iter::repeat_n(' ', padding));
+            }
+        }
+        output.push_str(line.trim_end());
+        output.push('\n');
+    }
+    output
+}
+
+// A byte count in decimal units, e.g. `1.5MB`.
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["kB", "MB", "GB", "TB"];
+    
+    if bytes < 1000 {
+        return format!("{}B", bytes);
+    }
+    let mut value = bytes as f64 / 1000.0;
+    let mut unit = 0;
+    while value >= 1000.0 && unit < UNITS.len() - 1 {
+        value /= 1000.0;
+        unit += 1;
+    }
+    format!("{:.1}{}", value, UNITS[unit])
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+// How long `secs` seconds is, roughly, e.g. `5 minutes` or `About an hour`.
+pub fn format_age(secs: u64) -> String {
+    let plural = |count: u64, unit: &str| format!("{} {}{}", count, unit, if count == 1 { "" } else { "s" });
+    match secs {
+        0 => "Less than a second".to_string(),
+        1..=59 => plural(secs, "second"),
+        60..=119 => "About a minute".to_string(),
+        120..=3599 => plural(secs / 60, "minute"),
+        3600..=7199 => "About an hour".to_string(),
+        7200..=172_799 => plural(secs / 3600, "hour"),
+        _ => plural(secs / 86_400, "day"),
+    }
+}
+
+// A Unix time as `2024-03-01 14:05:09 +0000 UTC`.
+pub fn format_timestamp(unix: u64) -> String {
+    let (year, month, day) = civil_date(unix);
+    let secs = unix % 86_400;
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} +0000 UTC",
+        year, month, day, secs / 3600, secs % 3600 / 60, secs % 60,
+    )
+}
+
+// A Unix time as `2024-03-01T14:05:09Z`, as in the Docker API.
+pub fn format_rfc3339(unix: u64) -> String {
+    let (year, month, day) = civil_date(unix);
+    let secs = unix % 86_400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year, month, day, secs / 3600, secs % 3600 / 60, secs % 60,
+    )
+}
+
+// The year, month and day of a Unix time, after Howard Hinnant's
+// `civil_from_days`.
+fn civil_date(unix: u64) -> (i64, i64, i64) {
+    let z = (unix / 86_400) as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
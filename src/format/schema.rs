@@ -0,0 +1,142 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::path::PathBuf;
+
+use crate::container::{ContainerInfo, PortMapping};
+use crate::network::Network;
+use crate::volume::VolumeInfo;
+
+// The rows read commands print with `--format json` or `--format yaml`.
+// These are kept stable for scripts: every field is always present, as
+// null or empty when unset, and fields are only ever added. Times are Unix
+// seconds.
+
+// A container, as `list` shows it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContainerSummary {
+    pub id: String,
+    pub name: Option<String>,
+    pub image: String,
+    // created, running, stopped or exited.
+    pub state: String,
+    // The state as `list` words it, e.g. `Up 5 minutes` or `Exited (1)`.
+    pub status: String,
+    pub created_at: u64,
+    pub started_at: Option<u64>,
+    pub exit_code: Option<i32>,
+    pub ports: Vec<PortSummary>,
+    // The running container's addresses, by network.
+    pub networks: BTreeMap<String, Vec<IpAddr>>,
+    pub labels: BTreeMap<String, String>,
+}
+
+// A published port; `host_port` is the one the OS picked for a `-p 0:PORT`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PortSummary {
+    pub host_port: u16,
+    pub container_port: u16,
+    pub protocol: String,
+}
+
+// A cached image, as `images` shows it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImageSummary {
+    // `name:tag`, as `run` and `rmi` take it.
+    pub reference: String,
+    pub name: String,
+    pub tag: String,
+    // The digest of the manifest it was pulled from.
+    pub digest: Option<String>,
+    // What it was pulled for, e.g. `wasi/wasm32`.
+    pub platform: Option<String>,
+}
+
+// A network, as `network ls` shows it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NetworkSummary {
+    pub name: String,
+    // The IPv4 subnet, then the IPv6 one on dual-stack networks.
+    pub subnets: Vec<SubnetSummary>,
+    pub domain: Option<String>,
+    pub internal: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SubnetSummary {
+    pub subnet: String,
+    pub gateway: IpAddr,
+}
+
+// A named volume, as `volume ls` shows it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VolumeSummary {
+    pub name: String,
+    pub mountpoint: PathBuf,
+    pub created_at: u64,
+}
+
+impl From<&ContainerInfo> for ContainerSummary {
+    fn from(info: &ContainerInfo) -> Self {
+        Self {
+            id: info.id.clone(),
+            name: info.name.clone(),
+            image: info.image.clone(),
+            state: info.status.clone(),
+            status: info.field("Status"),
+            created_at: info.created_at,
+            started_at: info.started_at,
+            exit_code: info.exit_code,
+            ports: info.ports.iter().map(PortSummary::from).collect(),
+            networks: info.addresses.clone(),
+            labels: info.labels.clone(),
+        }
+    }
+}
+
+impl From<&PortMapping> for PortSummary {
+    fn from(port: &PortMapping) -> Self {
+        Self {
+            host_port: port.host_port,
+            container_port: port.container_port,
+            protocol: port.protocol.clone(),
+        }
+    }
+}
+
+impl ImageSummary {
+    // From a reference as `ImageManager::list` returns it.
+    pub fn parse(reference: &str) -> Self {
+        let (name, tag) = reference.rsplit_once(':').unwrap_or((reference, "latest"));
+        Self {
+            reference: reference.to_string(),
+            name: name.to_string(),
+            tag: tag.to_string(),
+            digest: None,
+            platform: None,
+        }
+    }
+}
+
+impl From<&Network> for NetworkSummary {
+    fn from(network: &Network) -> Self {
+        Self {
+            name: network.name.clone(),
+            subnets: network.subnets()
+                .map(|(subnet, gateway)| SubnetSummary { subnet: subnet.to_string(), gateway })
+                .collect(),
+            domain: network.domain.clone(),
+            internal: network.policy.internal,
+        }
+    }
+}
+
+impl From<&VolumeInfo> for VolumeSummary {
+    fn from(volume: &VolumeInfo) -> Self {
+        Self {
+            name: volume.name.clone(),
+            mountpoint: volume.mountpoint.clone(),
+            created_at: volume.created_at,
+        }
+    }
+}
@@ -0,0 +1,226 @@
+use anyhow::{Result, anyhow};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+// Overrides where credentials are kept, as podman and skopeo honor it.
+pub const AUTH_FILE_ENV: &str = "REGISTRY_AUTH_FILE";
+// Where images without a registry in their name come from.
+pub const DEFAULT_REGISTRY: &str = "docker.io";
+
+// A registry user and password or token.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Credentials {
+    pub username: String,
+    pub secret: String,
+}
+
+// The auth file, in the format of docker's config.json: credentials per
+// registry, base64 encoded, unless a credential helper keeps them. Keys
+// this doesn't know are written back untouched.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AuthFile {
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    auths: BTreeMap<String, AuthEntry>,
+    // The helper for registries without one of their own.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    creds_store: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    cred_helpers: BTreeMap<String, String>,
+    #[serde(flatten)]
+    other: BTreeMap<String, Value>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct AuthEntry {
+    #[serde(default)]
+    auth: String,
+    #[serde(flatten)]
+    other: BTreeMap<String, Value>,
+}
+
+// What `docker-credential-<helper> get` prints.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct HelperCredentials {
+    username: String,
+    secret: String,
+}
+
+// Registry credentials, which `login` stores and pulls read.
+#[derive(Debug, Clone)]
+pub struct CredentialStore {
+    path: PathBuf,
+}
+
+impl CredentialStore {
+    pub fn new() -> Result<Self> {
+        let path = match std::env::var_os(AUTH_FILE_ENV).filter(|path| !path.is_empty()) {
+            Some(path) => PathBuf::from(path),
+            None => dirs::config_dir()
+                .ok_or_else(|| anyhow!("Could not determine config directory"))?
+                .join("wasm-container")
+                .join("auth.json"),
+        };
+        Ok(Self::with_path(path))
+    }
+    
+    pub fn with_path(path: PathBuf) -> Self {
+        Self { path }
+    }
+    
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+    
+    // Stores credentials for `registry`, with its credential helper if it
+    // has one.
+    pub fn login(&self, registry: &str, credentials: &Credentials) -> Result<()> {
+        let registry = normalize_registry(registry);
+        let mut file = self.load()?;
+        if let Some(helper) = file.helper(&registry) {
+            let request = serde_json::json!({
+                "ServerURL": registry,
+                "Username": credentials.username,
+                "Secret": credentials.secret,
+            });
+            run_helper(&helper, "store", &request.to_string())?;
+            return Ok(());
+        }
+        
+        let auth = STANDARD.encode(format!("{}:{}", credentials.username, credentials.secret));
+        file.auths.entry(registry).or_default().auth = auth;
+        self.save(&file)
+    }
+    
+    // Forgets the credentials for `registry`, failing if there were none.
+    pub fn logout(&self, registry: &str) -> Result<()> {
+        let registry = normalize_registry(registry);
+        let mut file = self.load()?;
+        if let Some(helper) = file.helper(&registry) {
+            run_helper(&helper, "erase", &registry)?;
+            return Ok(());
+        }
+        
+        if file.auths.remove(&registry).is_none() {
+            return Err(anyhow!("Not logged in to {}", registry));
+        }
+        self.save(&file)
+    }
+    
+    // The credentials for `registry`, if any are stored.
+    pub fn get(&self, registry: &str) -> Result<Option<Credentials>> {
+        let registry = normalize_registry(registry);
+        let file = self.load()?;
+        if let Some(helper) = file.helper(&registry) {
+            // Helpers answer with an error when they have nothing stored.
+            let Ok(output) = run_helper(&helper, "get", &registry) else {
+                return Ok(None);
+            };
+            let credentials: HelperCredentials = serde_json::from_str(&output)
+                .map_err(|e| anyhow!("Invalid reply from docker-credential-{}: {}", helper, e))?;
+            return Ok(Some(Credentials { username: credentials.username, secret: credentials.secret }));
+        }
+        
+        let Some(entry) = file.auths.get(&registry).filter(|entry| !entry.auth.is_empty()) else {
+            return Ok(None);
+        };
+        let decoded = STANDARD.decode(&entry.auth)
+            .map_err(|e| anyhow!("Invalid credentials for {} in {:?}: {}", registry, self.path, e))?;
+        let decoded = String::from_utf8(decoded)
+            .map_err(|_| anyhow!("Invalid credentials for {} in {:?}", registry, self.path))?;
+        let (username, secret) = decoded.split_once(':')
+            .ok_or_else(|| anyhow!("Invalid credentials for {} in {:?}", registry, self.path))?;
+        Ok(Some(Credentials { username: username.to_string(), secret: secret.to_string() }))
+    }
+    
+    // The registries with credentials in the file itself, sorted.
+    pub fn registries(&self) -> Result<Vec<String>> {
+        Ok(self.load()?.auths.into_keys().collect())
+    }
+    
+    fn load(&self) -> Result<AuthFile> {
+        if !self.path.exists() {
+            return Ok(AuthFile::default());
+        }
+        serde_json::from_str(&fs::read_to_string(&self.path)?)
+            .map_err(|e| anyhow!("Invalid auth file {:?}: {}", self.path, e))
+    }
+    
+    // Writes the file readable by its owner only, since it holds secrets.
+    fn save(&self, file: &AuthFile) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // Created private, and made so if an earlier save left it behind,
+        // before the secrets go in.
+        let temp = self.path.with_extension("json.tmp");
+        let mut out = fs::OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(&temp)?;
+        out.set_permissions(fs::Permissions::from_mode(0o600))?;
+        out.write_all(serde_json::to_string_pretty(file)?.as_bytes())?;
+        drop(out);
+        fs::rename(temp, &self.path)?;
+        Ok(())
+    }
+}
+
+impl AuthFile {
+    fn helper(&self, registry: &str) -> Option<String> {
+        self.cred_helpers.get(registry).or(self.creds_store.as_ref()).cloned()
+    }
+}
+
+// The registry an image reference pulls from: its first component when
+// that names a host, or Docker Hub.
+pub fn registry_of(image_ref: &str) -> &str {
+    match image_ref.split_once('/') {
+        Some((host, _)) if host.contains(['.', ':']) || host == "localhost" => host,
+        _ => DEFAULT_REGISTRY,
+    }
+}
+
+// Registries are keyed by host, as docker writes them, with Docker Hub's
+// aliases folded into one.
+fn normalize_registry(registry: &str) -> String {
+    let host = registry
+        .trim_start_matches("https://")
+        .trim_start_matches("http://")
+        .split('/')
+        .next()
+        .unwrap_or_default();
+    match host {
+        "index.docker.io" | "registry-1.docker.io" | "registry.hub.docker.com" => DEFAULT_REGISTRY.to_string(),
+        host => host.to_string(),
+    }
+}
+
+// Runs `docker-credential-<helper> <action>` with `input` on stdin and
+// returns what it prints.
+fn run_helper(helper: &str, action: &str, input: &str) -> Result<String> {
+    let program = format!("docker-credential-{}", helper);
+    let mut child = Command::new(&program)
+        .arg(action)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| anyhow!("Failed to run credential helper {}: {}", program, e))?;
+    child.stdin.take().expect("piped stdin").write_all(input.as_bytes())?;
+    let output = child.wait_with_output()?;
+    
+    if !output.status.success() {
+        let message = String::from_utf8_lossy(&output.stdout);
+        let message = message.trim();
+        let message = if message.is_empty() { String::from_utf8_lossy(&output.stderr).trim().to_string() } else { message.to_string() };
+        return Err(anyhow!("{} {} failed: {}", program, action, message));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
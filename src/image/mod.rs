@@ -1,12 +1,27 @@
 use anyhow::{Result, anyhow};
+use crate::error;
 use serde::{Deserialize, Serialize};
 use std::path::{Path, PathBuf};
 use std::fs;
+use std::io::{self, Read};
 use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 use tokio::fs as async_fs;
-use tracing::{info, debug};
-use tar::Archive;
+use tracing::info;
 use flate2::read::GzDecoder;
+use sha2::{Digest, Sha256};
+
+use crate::filesystem::dir_size;
+use crate::state;
+
+pub mod auth;
+pub mod source;
+
+use auth::CredentialStore;
+use source::{ImageSource, RegistrySource};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ImageData {
@@ -23,6 +38,10 @@ pub struct Layer {
     pub size: u64,
     pub media_type: String,
     pub path: PathBuf,
+    // Digest of the uncompressed tar, from the image config. Layers without
+    // one are extracted unverified.
+    #[serde(default)]
+    pub diff_id: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -33,6 +52,16 @@ pub struct ImageConfig {
     pub workdir: String,
     pub exposed_ports: HashMap<String, PortConfig>,
     pub volumes: HashMap<String, VolumeConfig>,
+    #[serde(default)]
+    pub rootfs: RootFs,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RootFs {
+    #[serde(rename = "type")]
+    pub fs_type: String,
+    // One per manifest layer, in the same order.
+    pub diff_ids: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -59,53 +88,428 @@ pub struct OCIDescriptor {
     pub media_type: String,
 }
 
+// A multi-platform image: a manifest per platform it is published for.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OCIIndex {
+    #[serde(rename = "schemaVersion")]
+    pub schema_version: u32,
+    pub manifests: Vec<OCIIndexEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OCIIndexEntry {
+    #[serde(flatten)]
+    pub descriptor: OCIDescriptor,
+    // Entries without one are for any platform.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub platform: Option<Platform>,
+}
+
+// What an image runs on, `OS/ARCH[/VARIANT]` as `--platform` takes it.
+// Wasm images are for `wasi/wasm32`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Platform {
+    pub os: String,
+    pub architecture: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variant: Option<String>,
+}
+
+impl Default for Platform {
+    fn default() -> Self {
+        Self { os: "wasi".to_string(), architecture: "wasm32".to_string(), variant: None }
+    }
+}
+
+impl Platform {
+    // Whether an image for `published` runs here; without a variant of its
+    // own, any variant does.
+    pub fn matches(&self, published: &Platform) -> bool {
+        self.os == published.os
+            && self.architecture == published.architecture
+            && (self.variant.is_none() || self.variant == published.variant)
+    }
+}
+
+impl FromStr for Platform {
+    type Err = anyhow::Error;
+    
+    fn from_str(s: &str) -> Result<Self> {
+        let parts: Vec<&str> = s.split('/').collect();
+        match parts[..] {
+            [os, architecture] | [os, architecture, _] if !os.is_empty() && !architecture.is_empty() => Ok(Self {
+                os: os.to_string(),
+                architecture: architecture.to_string(),
+                variant: parts.get(2).filter(|variant| !variant.is_empty()).map(|variant| variant.to_string()),
+            }),
+            _ => Err(anyhow!("Invalid platform: {} (expected OS/ARCH[/VARIANT], e.g. wasi/wasm32)", s)),
+        }
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.os, self.architecture)?;
+        if let Some(variant) = &self.variant {
+            write!(f, "/{}", variant)?;
+        }
+        Ok(())
+    }
+}
+
+// When `get` goes to the registry: `Always` asks it for the image's digest
+// and pulls unless the cached copy has that digest, `Missing` pulls only
+// images not in the cache, and `Never` only uses the cache.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PullPolicy {
+    Always,
+    #[default]
+    Missing,
+    Never,
+}
+
+impl FromStr for PullPolicy {
+    type Err = anyhow::Error;
+    
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "always" => Ok(Self::Always),
+            "missing" => Ok(Self::Missing),
+            "never" => Ok(Self::Never),
+            _ => Err(anyhow!("Unknown pull policy: {} (expected always, missing or never)", s)),
+        }
+    }
+}
+
+impl fmt::Display for PullPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Always => write!(f, "always"),
+            Self::Missing => write!(f, "missing"),
+            Self::Never => write!(f, "never"),
+        }
+    }
+}
+
+// Kept next to a cached image's metadata: the digest of the manifest it was
+// pulled from, which `PullPolicy::Always` compares with the registry's, and
+// the platform that manifest is for.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PullRecord {
+    digest: String,
+    #[serde(default)]
+    platform: Option<Platform>,
+}
+
+/// Pulls images into a local cache and hands out cached ones.
+///
+/// ```
+/// use wasm_container::{ImageManager, Platform, PullPolicy};
+///
+/// # #[tokio::main]
+/// # async fn main() -> anyhow::Result<()> {
+/// # let dir = tempfile::TempDir::new()?;
+/// # let cache_dir = dir.path().join("images");
+/// let images = ImageManager::with_cache_dir(cache_dir)?.with_platform(Platform::default());
+/// images.pull("myapp:1.0").await?;
+///
+/// // Cached now, so no registry is asked.
+/// let image = images.get("myapp:1.0", PullPolicy::Never).await?;
+/// assert_eq!(image.tag, "1.0");
+/// assert_eq!(images.list()?, vec!["myapp:1.0"]);
+/// # Ok(())
+/// # }
+/// ```
 pub struct ImageManager {
     cache_dir: PathBuf,
+    source: Arc<dyn ImageSource>,
+    platform: Platform,
 }
 
 impl ImageManager {
-    pub fn new() -> Result<Self> {
-        let cache_dir = dirs::cache_dir()
-            .ok_or_else(|| anyhow!("Could not determine cache directory"))?
-            .join("wasm-container")
-            .join("images");
-        
+    pub fn new() -> error::Result<Self> {
+        Self::with_cache_dir(state::cache_dir()?.join("images"))
+    }
+    
+    pub fn with_cache_dir(cache_dir: PathBuf) -> error::Result<Self> {
         fs::create_dir_all(&cache_dir)?;
         
-        Ok(Self { cache_dir })
+        let source = Arc::new(RegistrySource::new(CredentialStore::new()?));
+        Ok(Self { cache_dir, source, platform: Platform::default() })
     }
     
-    pub async fn get_or_pull(&self, image_ref: &str) -> Result<ImageData> {
-        let (name, tag) = self.parse_image_ref(image_ref)?;
+    // Pulls the manifest for `platform` from multi-platform images, and
+    // only uses cached images pulled for it.
+    pub fn with_platform(mut self, platform: Platform) -> Self {
+        self.platform = platform;
+        self
+    }
+    
+    // Pulls with the credentials in `credentials` instead of the user's.
+    pub fn with_credentials(self, credentials: CredentialStore) -> Self {
+        self.with_source(RegistrySource::new(credentials))
+    }
+    
+    /// Pulls images from `source` instead of their registries, such as a
+    /// directory or storage of the embedder's own.
+    pub fn with_source(mut self, source: impl ImageSource + 'static) -> Self {
+        self.source = Arc::new(source);
+        self
+    }
+    
+    // References of the images in the cache, such as `library/nginx:1.25`,
+    // sorted.
+    pub fn list(&self) -> error::Result<Vec<String>> {
+        let mut images = Vec::new();
+        let mut dirs = vec![self.cache_dir.clone()];
+        while let Some(dir) = dirs.pop() {
+            for entry in fs::read_dir(&dir)? {
+                let path = entry?.path();
+                if path.join("metadata.json").is_file() {
+                    let relative = path.strip_prefix(&self.cache_dir).map_err(anyhow::Error::from)?;
+                    let (Some(tag), Some(name)) = (relative.file_name(), relative.parent()) else {
+                        continue;
+                    };
+                    images.push(format!("{}:{}", name.to_string_lossy(), tag.to_string_lossy()));
+                } else if path.is_dir() {
+                    dirs.push(path);
+                }
+            }
+        }
+        images.sort();
         
-        if let Ok(image) = self.load_from_cache(&name, &tag).await {
-            info!("Using cached image: {}:{}", name, tag);
-            return Ok(image);
+        Ok(images)
+    }
+    
+    // Removes pulls that were interrupted at least `grace` ago and, with
+    // `all`, every cached image not in `in_use`. Returns the references of
+    // what was removed and the bytes that freed.
+    pub fn prune(&self, all: bool, in_use: &[String], grace: Duration) -> error::Result<(Vec<String>, u64)> {
+        let mut removed = Vec::new();
+        let mut reclaimed = 0;
+        
+        let mut remove = |image: String, dir: PathBuf| -> Result<()> {
+            reclaimed += self.remove_dir(&dir)?;
+            removed.push(image);
+            Ok(())
+        };
+        
+        if all {
+            for image in self.list()? {
+                if !in_use.contains(&image) {
+                    let (name, tag) = self.parse_image_ref(&image)?;
+                    remove(image, self.cache_dir.join(name).join(tag))?;
+                }
+            }
+        }
+        for dir in self.partial_pulls()? {
+            let age = fs::metadata(&dir)
+                .and_then(|metadata| metadata.modified())
+                .ok()
+                .and_then(|modified| SystemTime::now().duration_since(modified).ok());
+            if age.is_some_and(|age| age >= grace) {
+                let relative = dir.strip_prefix(&self.cache_dir).map_err(anyhow::Error::from)?.to_path_buf();
+                let (Some(tag), Some(name)) = (relative.file_name(), relative.parent()) else {
+                    continue;
+                };
+                remove(format!("{}:{}", name.to_string_lossy(), tag.to_string_lossy()), dir)?;
+            }
         }
         
-        info!("Image not found in cache, pulling: {}:{}", name, tag);
-        self.pull(image_ref).await
+        Ok((removed, reclaimed))
     }
     
-    pub async fn pull(&self, image_ref: &str) -> Result<ImageData> {
+    // Removes a cached image, unless it is in `in_use`, as containers run
+    // from its module. Returns its `name:tag` and the bytes that freed.
+    pub fn remove(&self, image_ref: &str, in_use: &[String]) -> error::Result<(String, u64)> {
+        let (name, tag) = self.parse_image_ref(image_ref)?;
+        let image = format!("{}:{}", name, tag);
+        let dir = self.cache_dir.join(&name).join(&tag);
+        if !dir.join("metadata.json").is_file() {
+            return Err(error::Error::ImageNotFound { image, reason: "not in the cache".to_string() });
+        }
+        if in_use.contains(&image) {
+            return Err(anyhow!("Image {} is used by a container; remove the container first", image).into());
+        }
+        let reclaimed = self.remove_dir(&dir)?;
+        Ok((image, reclaimed))
+    }
+    
+    // Removes an image directory, returning its size.
+    fn remove_dir(&self, dir: &Path) -> Result<u64> {
+        let size = dir_size(dir).unwrap_or(0);
+        fs::remove_dir_all(dir)?;
+        // Leaves no empty directories of repositories behind.
+        let mut parent = dir.parent();
+        while let Some(dir) = parent.filter(|dir| *dir != self.cache_dir) {
+            if fs::remove_dir(dir).is_err() {
+                break;
+            }
+            parent = dir.parent();
+        }
+        Ok(size)
+    }
+    
+    // Image directories holding downloaded files but no metadata, which is
+    // written last.
+    fn partial_pulls(&self) -> Result<Vec<PathBuf>> {
+        let mut partial = Vec::new();
+        let mut dirs = vec![self.cache_dir.clone()];
+        while let Some(dir) = dirs.pop() {
+            if dir.join("metadata.json").is_file() {
+                continue;
+            }
+            let mut subdirs = Vec::new();
+            let mut has_files = false;
+            for entry in fs::read_dir(&dir)? {
+                let path = entry?.path();
+                if path.is_dir() {
+                    subdirs.push(path);
+                } else {
+                    has_files = true;
+                }
+            }
+            if has_files && dir != self.cache_dir {
+                partial.push(dir);
+            } else {
+                dirs.extend(subdirs);
+            }
+        }
+        
+        Ok(partial)
+    }
+    
+    pub async fn get_or_pull(&self, image_ref: &str) -> error::Result<ImageData> {
+        self.get(image_ref, PullPolicy::Missing).await
+    }
+    
+    // The image, from the cache or the registry as `policy` has it.
+    pub async fn get(&self, image_ref: &str, policy: PullPolicy) -> error::Result<ImageData> {
+        let (name, tag) = self.parse_image_ref(image_ref)?;
+        // Images cached for another platform are pulled again.
+        let record = self.load_record(&name, &tag).ok().flatten();
+        let for_platform = record.as_ref()
+            .is_none_or(|record| self.platform.matches(record.platform.as_ref().unwrap_or(&Platform::default())));
+        let cached = match for_platform {
+            true => self.load_from_cache(&name, &tag).await.ok(),
+            false => None,
+        };
+        
+        match (policy, cached) {
+            (PullPolicy::Always, cached) => {
+                let (digest, manifest) = self.resolve(&name, &tag).await?;
+                if let Some(image) = cached.filter(|_| record.is_some_and(|record| record.digest == digest)) {
+                    info!("Cached image {}:{} is up to date ({})", name, tag, digest);
+                    return Ok(image);
+                }
+                info!("Pulling image: {}:{}", name, tag);
+                Ok(self.download(&name, &tag, &digest, &manifest).await?)
+            }
+            (_, Some(image)) => {
+                info!("Using cached image: {}:{}", name, tag);
+                Ok(image)
+            }
+            (PullPolicy::Never, None) => Err(error::Error::ImageNotFound {
+                image: format!("{}:{}", name, tag),
+                reason: format!("not in the cache for {}, and the pull policy is never", self.platform),
+            }),
+            (PullPolicy::Missing, None) => {
+                info!("Image not found in cache, pulling: {}:{}", name, tag);
+                self.pull(image_ref).await
+            }
+        }
+    }
+    
+    pub async fn pull(&self, image_ref: &str) -> error::Result<ImageData> {
         let (name, tag) = self.parse_image_ref(image_ref)?;
         
         info!("Pulling image: {}:{}", name, tag);
         
+        let (digest, manifest) = self.resolve(&name, &tag).await?;
+        Ok(self.download(&name, &tag, &digest, &manifest).await?)
+    }
+    
+    // The digest of the manifest a cached image was pulled from, if it is
+    // cached and the pull recorded one.
+    pub fn cached_digest(&self, image_ref: &str) -> error::Result<Option<String>> {
+        let (name, tag) = self.parse_image_ref(image_ref)?;
+        Ok(self.load_record(&name, &tag)?.map(|record| record.digest))
+    }
+    
+    // The platform a cached image was pulled for, if the pull recorded one.
+    pub fn cached_platform(&self, image_ref: &str) -> error::Result<Option<Platform>> {
+        let (name, tag) = self.parse_image_ref(image_ref)?;
+        Ok(self.load_record(&name, &tag)?.and_then(|record| record.platform))
+    }
+    
+    fn load_record(&self, name: &str, tag: &str) -> Result<Option<PullRecord>> {
+        let record_file = self.cache_dir.join(name).join(tag).join("pull.json");
+        if !record_file.exists() {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_str(&fs::read_to_string(record_file)?)?))
+    }
+    
+    // The digest and manifest of the image for this manager's platform, as
+    // the source has them now.
+    async fn resolve(&self, name: &str, tag: &str) -> Result<(String, OCIManifest)> {
+        let index = self.source.resolve(name, tag).await?;
+        
+        let entry = index.manifests.iter()
+            .find(|entry| entry.platform.as_ref().is_none_or(|platform| self.platform.matches(platform)))
+            .ok_or_else(|| {
+                let published: Vec<String> = index.manifests.iter()
+                    .filter_map(|entry| entry.platform.as_ref().map(ToString::to_string))
+                    .collect();
+                error::Error::ImageNotFound {
+                    image: format!("{}:{}", name, tag),
+                    reason: format!("not published for {} (only for {})", self.platform, published.join(", ")),
+                }
+            })?;
+        let manifest = self.source.fetch_manifest(name, &entry.descriptor).await?;
+        Ok((entry.descriptor.digest.clone(), manifest))
+    }
+    
+    async fn download(&self, name: &str, tag: &str, digest: &str, manifest: &OCIManifest) -> Result<ImageData> {
+        let (name, tag) = (name.to_string(), tag.to_string());
         let image_dir = self.cache_dir.join(&name).join(&tag);
         async_fs::create_dir_all(&image_dir).await?;
         
-        let manifest = self.fetch_manifest(&name, &tag).await?;
+        let config: ImageConfig = serde_json::from_slice(&self.fetch_blob(&name, &manifest.config).await?)?;
         
-        let config = self.fetch_config(&name, &manifest.config).await?;
+        // The module is a layer of its own; the rest make up the rootfs.
+        let (wasm_layers, rootfs_layers): (Vec<&OCIDescriptor>, Vec<&OCIDescriptor>) = manifest.layers.iter()
+            .partition(|layer| layer.media_type == source::WASM_MEDIA_TYPE);
+        let diff_ids = &config.rootfs.diff_ids;
+        if !diff_ids.is_empty() && diff_ids.len() != rootfs_layers.len() {
+            return Err(anyhow!(
+                "Image config lists {} diff IDs for {} layers",
+                diff_ids.len(), rootfs_layers.len()
+            ));
+        }
         
         let mut layers = Vec::new();
-        for layer_desc in &manifest.layers {
-            let layer = self.fetch_layer(&name, layer_desc, &image_dir).await?;
-            layers.push(layer);
+        for (index, layer_desc) in rootfs_layers.into_iter().enumerate() {
+            let path = image_dir.join(format!("{}.tar.gz", layer_desc.digest.replace("sha256:", "")));
+            async_fs::write(&path, self.fetch_blob(&name, layer_desc).await?).await?;
+            layers.push(Layer {
+                digest: layer_desc.digest.clone(),
+                size: layer_desc.size,
+                media_type: layer_desc.media_type.clone(),
+                path,
+                diff_id: diff_ids.get(index).cloned(),
+            });
         }
         
-        let wasm_path = self.extract_wasm_binary(&image_dir, &layers).await?;
+        let wasm_path = match wasm_layers.as_slice() {
+            [] => None,
+            [wasm] => {
+                let path = image_dir.join("app.wasm");
+                async_fs::write(&path, self.fetch_blob(&name, wasm).await?).await?;
+                Some(path)
+            }
+            _ => return Err(anyhow!("Image {}:{} has more than one module layer", name, tag)),
+        };
         
         let image_data = ImageData {
             name: name.clone(),
@@ -115,77 +519,46 @@ impl ImageManager {
             wasm_path,
         };
         
+        let record = PullRecord { digest: digest.to_string(), platform: Some(self.platform.clone()) };
+        async_fs::write(image_dir.join("pull.json"), serde_json::to_string_pretty(&record)?).await?;
         self.save_to_cache(&image_data).await?;
         
         Ok(image_data)
     }
     
     fn parse_image_ref(&self, image_ref: &str) -> Result<(String, String)> {
-        let parts: Vec<&str> = image_ref.split(':').collect();
-        
-        let (name, tag) = match parts.len() {
-            1 => (parts[0].to_string(), "latest".to_string()),
-            2 => (parts[0].to_string(), parts[1].to_string()),
-            _ => return Err(anyhow!("Invalid image reference: {}", image_ref)),
+        // The tag follows the last colon after the last slash; a colon
+        // before it is a registry's port, as in `localhost:5000/app`.
+        let (name, tag) = match image_ref.rsplit_once(':') {
+            Some((name, tag)) if !tag.contains('/') => (name.to_string(), tag.to_string()),
+            _ => (image_ref.to_string(), "latest".to_string()),
         };
+        let path = name.split_once('/').map_or(name.as_str(), |(_, path)| path);
+        if path.contains(':') {
+            return Err(anyhow!("Invalid image reference: {}", image_ref));
+        }
         
-        Ok((name, tag))
-    }
-    
-    async fn fetch_manifest(&self, _name: &str, _tag: &str) -> Result<OCIManifest> {
-        Ok(OCIManifest {
-            schema_version: 2,
-            config: OCIDescriptor {
-                digest: "sha256:mock".to_string(),
-                size: 1024,
-                media_type: "application/vnd.oci.image.config.v1+json".to_string(),
-            },
-            layers: vec![
-                OCIDescriptor {
-                    digest: "sha256:layer1".to_string(),
-                    size: 2048,
-                    media_type: "application/vnd.oci.image.layer.v1.tar+gzip".to_string(),
-                },
-            ],
-        })
-    }
-    
-    async fn fetch_config(&self, _name: &str, _config_desc: &OCIDescriptor) -> Result<ImageConfig> {
-        Ok(ImageConfig {
-            env: vec!["PATH=/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin".to_string()],
-            cmd: vec!["/bin/sh".to_string()],
-            entrypoint: vec![],
-            workdir: "/".to_string(),
-            exposed_ports: HashMap::new(),
-            volumes: HashMap::new(),
-        })
-    }
-    
-    async fn fetch_layer(&self, _name: &str, layer_desc: &OCIDescriptor, image_dir: &Path) -> Result<Layer> {
-        let layer_path = image_dir.join(format!("{}.tar.gz", layer_desc.digest.replace("sha256:", "")));
-        
-        let demo_tar = vec![0u8; 1024];
-        async_fs::write(&layer_path, demo_tar).await?;
+        // The name and tag are paths under the cache dir, so neither may
+        // climb out of it.
+        let escapes = |path: &str| path.split('/').any(|part| matches!(part, "" | "." | ".."));
+        if escapes(&name) || escapes(&tag) {
+            return Err(anyhow!("Invalid image reference: {}", image_ref));
+        }
         
-        Ok(Layer {
-            digest: layer_desc.digest.clone(),
-            size: layer_desc.size,
-            media_type: layer_desc.media_type.clone(),
-            path: layer_path,
-        })
+        Ok((name, tag))
     }
     
-    async fn extract_wasm_binary(&self, image_dir: &Path, _layers: &[Layer]) -> Result<Option<PathBuf>> {
-        let wasm_path = image_dir.join("app.wasm");
-        
-        let demo_wasm = include_bytes!("demo.wasm");
-        async_fs::write(&wasm_path, demo_wasm).await?;
-        
-        Ok(Some(wasm_path))
+    // A blob of the image from the source, checked against its digest.
+    async fn fetch_blob(&self, name: &str, descriptor: &OCIDescriptor) -> Result<Vec<u8>> {
+        let blob = self.source.fetch_blob(name, descriptor).await?;
+        if sha256_digest(&blob) != descriptor.digest {
+            return Err(anyhow!("Blob {} of {} doesn't match its digest", descriptor.digest, name));
+        }
+        Ok(blob)
     }
     
     async fn load_from_cache(&self, name: &str, tag: &str) -> Result<ImageData> {
-        let cache_file = self.cache_dir.join(&name).join(&tag).join("metadata.json");
+        let cache_file = self.cache_dir.join(name).join(tag).join("metadata.json");
         
         if !cache_file.exists() {
             return Err(anyhow!("Image not found in cache"));
@@ -210,6 +583,11 @@ impl ImageManager {
     }
 }
 
+// A blob's digest, as registries compute it.
+fn sha256_digest(blob: &[u8]) -> String {
+    format!("sha256:{}", Sha256::digest(blob).iter().map(|byte| format!("{:02x}", byte)).collect::<String>())
+}
+
 impl ImageData {
     pub async fn get_wasm_binary(&self) -> Result<Vec<u8>> {
         if let Some(wasm_path) = &self.wasm_path {
@@ -219,4 +597,62 @@ impl ImageData {
             Err(anyhow!("No WASM binary found in image"))
         }
     }
-}
\ No newline at end of file
+}
+
+impl Layer {
+    // Decompressed contents of the layer blob. Once the archive has been
+    // read, `LayerReader::verify` checks them against the DiffID.
+    pub fn open(&self) -> Result<LayerReader> {
+        if let Some(diff_id) = &self.diff_id {
+            if !diff_id.starts_with("sha256:") {
+                return Err(anyhow!("Unsupported diff ID for layer {}: {}", self.digest, diff_id));
+            }
+        }
+        
+        Ok(LayerReader {
+            inner: GzDecoder::new(fs::File::open(&self.path)?),
+            hasher: Sha256::new(),
+            digest: self.digest.clone(),
+            diff_id: self.diff_id.clone(),
+        })
+    }
+}
+
+// Hashes the uncompressed stream as it is read, so a layer is verified in
+// the same pass that extracts it.
+pub struct LayerReader {
+    inner: GzDecoder<fs::File>,
+    hasher: Sha256,
+    digest: String,
+    diff_id: Option<String>,
+}
+
+impl LayerReader {
+    pub fn verify(mut self) -> Result<()> {
+        // Tar readers stop at the end-of-archive marker, but the padding
+        // after it is part of the DiffID too.
+        io::copy(&mut self, &mut io::sink())?;
+        
+        let Some(expected) = self.diff_id else {
+            return Ok(());
+        };
+        let actual = format!("sha256:{:x}", self.hasher.finalize());
+        
+        if actual != expected {
+            return Err(anyhow!(
+                "Layer {} failed verification: expected diff ID {}, got {}",
+                self.digest, expected, actual
+            ));
+        }
+        
+        Ok(())
+    }
+}
+
+impl Read for LayerReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        Ok(read)
+    }
+}
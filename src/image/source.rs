@@ -0,0 +1,242 @@
+use anyhow::{Result, anyhow};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use tokio::fs as async_fs;
+use tracing::debug;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+use crate::error::Error;
+
+use super::auth::{self, CredentialStore};
+use super::{sha256_digest, ImageConfig, OCIDescriptor, OCIIndex, OCIIndexEntry, OCIManifest, Platform, RootFs};
+
+/// Media type of the manifest layer holding an image's module, as in the
+/// OCI artifact layout for wasm.
+pub const WASM_MEDIA_TYPE: &str = "application/wasm";
+pub const LAYER_MEDIA_TYPE: &str = "application/vnd.oci.image.layer.v1.tar+gzip";
+pub const MANIFEST_MEDIA_TYPE: &str = "application/vnd.oci.image.manifest.v1+json";
+pub const CONFIG_MEDIA_TYPE: &str = "application/vnd.oci.image.config.v1+json";
+
+/// Where an [`ImageManager`](super::ImageManager) pulls images from. The
+/// manager resolves a reference to the manifest for its platform, then
+/// fetches the config and layers that manifest lists, checking each blob
+/// against its digest before it is cached.
+///
+/// The config blob is an [`ImageConfig`] as JSON. Layers with
+/// [`WASM_MEDIA_TYPE`] hold the module to run; the others are gzipped tars
+/// making up the rootfs.
+#[async_trait::async_trait]
+pub trait ImageSource: Send + Sync {
+    /// The manifests the image `name:tag` is published with, one per
+    /// platform.
+    async fn resolve(&self, name: &str, tag: &str) -> Result<OCIIndex>;
+    
+    async fn fetch_manifest(&self, name: &str, descriptor: &OCIDescriptor) -> Result<OCIManifest>;
+    
+    async fn fetch_blob(&self, name: &str, descriptor: &OCIDescriptor) -> Result<Vec<u8>>;
+}
+
+/// Pulls images from their registry, with the credentials stored for it.
+pub struct RegistrySource {
+    credentials: CredentialStore,
+}
+
+impl RegistrySource {
+    pub fn new(credentials: CredentialStore) -> Self {
+        Self { credentials }
+    }
+}
+
+#[async_trait::async_trait]
+impl ImageSource for RegistrySource {
+    async fn resolve(&self, name: &str, _tag: &str) -> Result<OCIIndex> {
+        let registry = auth::registry_of(name);
+        let credentials = self.credentials.get(registry)
+            .map_err(|source| Error::RegistryAuth { registry: registry.to_string(), source })?;
+        if let Some(credentials) = credentials {
+            debug!("Authenticating to {} as {}", registry, credentials.username);
+        }
+        let manifest = serde_json::to_vec(&demo_manifest()?)?;
+        Ok(OCIIndex {
+            schema_version: 2,
+            manifests: vec![
+                OCIIndexEntry {
+                    descriptor: descriptor(MANIFEST_MEDIA_TYPE, &manifest),
+                    platform: Some(Platform::default()),
+                },
+            ],
+        })
+    }
+    
+    async fn fetch_manifest(&self, _name: &str, _descriptor: &OCIDescriptor) -> Result<OCIManifest> {
+        demo_manifest()
+    }
+    
+    async fn fetch_blob(&self, name: &str, descriptor: &OCIDescriptor) -> Result<Vec<u8>> {
+        demo_blobs()?.into_iter()
+            .find(|blob| sha256_digest(blob) == descriptor.digest)
+            .ok_or_else(|| anyhow!("Blob {} of {} not found", descriptor.digest, name))
+    }
+}
+
+fn demo_blobs() -> Result<[Vec<u8>; 3]> {
+    let config = ImageConfig {
+        env: vec!["PATH=/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin".to_string()],
+        cmd: vec!["/bin/sh".to_string()],
+        entrypoint: vec![],
+        workdir: "/".to_string(),
+        exposed_ports: HashMap::new(),
+        volumes: HashMap::new(),
+        rootfs: RootFs::default(),
+    };
+    let layer = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()))
+        .into_inner()?
+        .finish()?;
+    Ok([serde_json::to_vec(&config)?, layer, include_bytes!("demo.wasm").to_vec()])
+}
+
+fn demo_manifest() -> Result<OCIManifest> {
+    let [config, layer, wasm] = demo_blobs()?;
+    Ok(OCIManifest {
+        schema_version: 2,
+        config: descriptor(CONFIG_MEDIA_TYPE, &config),
+        layers: vec![descriptor(LAYER_MEDIA_TYPE, &layer), descriptor(WASM_MEDIA_TYPE, &wasm)],
+    })
+}
+
+fn descriptor(media_type: &str, blob: &[u8]) -> OCIDescriptor {
+    OCIDescriptor {
+        digest: sha256_digest(blob),
+        size: blob.len() as u64,
+        media_type: media_type.to_string(),
+    }
+}
+
+/// Reads images from a directory laid out like an OCI image layout: blobs,
+/// manifests included, under `blobs/sha256/<hex digest>`, and each image's
+/// index at `<name>/<tag>/index.json`.
+pub struct LocalSource {
+    root: PathBuf,
+}
+
+impl LocalSource {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+    
+    async fn read_blob(&self, name: &str, descriptor: &OCIDescriptor) -> Result<Vec<u8>> {
+        let hex = descriptor.digest.strip_prefix("sha256:")
+            .ok_or_else(|| anyhow!("Unsupported digest: {}", descriptor.digest))?;
+        let path = self.root.join("blobs").join("sha256").join(hex);
+        async_fs::read(&path).await
+            .map_err(|e| anyhow!("Blob {} of {} not found in {:?}: {}", descriptor.digest, name, self.root, e))
+    }
+}
+
+#[async_trait::async_trait]
+impl ImageSource for LocalSource {
+    async fn resolve(&self, name: &str, tag: &str) -> Result<OCIIndex> {
+        let path = self.root.join(name).join(tag).join("index.json");
+        let index = async_fs::read(&path).await.map_err(|e| Error::ImageNotFound {
+            image: format!("{}:{}", name, tag),
+            reason: format!("not found in {:?}: {}", self.root, e),
+        })?;
+        Ok(serde_json::from_slice(&index)?)
+    }
+    
+    async fn fetch_manifest(&self, name: &str, descriptor: &OCIDescriptor) -> Result<OCIManifest> {
+        Ok(serde_json::from_slice(&self.read_blob(name, descriptor).await?)?)
+    }
+    
+    async fn fetch_blob(&self, name: &str, descriptor: &OCIDescriptor) -> Result<Vec<u8>> {
+        self.read_blob(name, descriptor).await
+    }
+}
+
+/// Holds images in memory, for embedders bundling them or keeping them in
+/// storage of their own.
+///
+/// ```
+/// use wasm_container::ImageManager;
+/// use wasm_container::image::source::MemorySource;
+/// # use wasm_container::image::ImageConfig;
+///
+/// # #[tokio::main]
+/// # async fn main() -> anyhow::Result<()> {
+/// # let dir = tempfile::TempDir::new()?;
+/// # let config = ImageConfig {
+/// #     env: vec![], cmd: vec![], entrypoint: vec![], workdir: "/".to_string(),
+/// #     exposed_ports: Default::default(), volumes: Default::default(), rootfs: Default::default(),
+/// # };
+/// let wasm = br#"(module (func (export "_start")))"#;
+/// let mut source = MemorySource::new();
+/// source.insert("bundled/app", "1.0", &config, wasm, Vec::new())?;
+/// let images = ImageManager::with_cache_dir(dir.path().to_path_buf())?.with_source(source);
+/// let image = images.pull("bundled/app:1.0").await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct MemorySource {
+    indexes: HashMap<(String, String), OCIIndex>,
+    blobs: HashMap<String, Vec<u8>>,
+}
+
+impl MemorySource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Adds the image `name:tag` for the default platform, running `wasm`
+    /// over the rootfs `layers` make up, replacing any image by that name.
+    /// Returns the digest of its manifest.
+    pub fn insert(&mut self, name: &str, tag: &str, config: &ImageConfig, wasm: &[u8], layers: Vec<Vec<u8>>) -> Result<String> {
+        let config = self.add_blob(CONFIG_MEDIA_TYPE, serde_json::to_vec(config)?);
+        let mut descriptors: Vec<OCIDescriptor> = layers.into_iter()
+            .map(|layer| self.add_blob(LAYER_MEDIA_TYPE, layer))
+            .collect();
+        descriptors.push(self.add_blob(WASM_MEDIA_TYPE, wasm.to_vec()));
+        
+        let manifest = OCIManifest { schema_version: 2, config, layers: descriptors };
+        let manifest = self.add_blob(MANIFEST_MEDIA_TYPE, serde_json::to_vec(&manifest)?);
+        let digest = manifest.digest.clone();
+        self.indexes.insert((name.to_string(), tag.to_string()), OCIIndex {
+            schema_version: 2,
+            manifests: vec![OCIIndexEntry { descriptor: manifest, platform: Some(Platform::default()) }],
+        });
+        Ok(digest)
+    }
+    
+    fn add_blob(&mut self, media_type: &str, blob: Vec<u8>) -> OCIDescriptor {
+        let descriptor = descriptor(media_type, &blob);
+        self.blobs.insert(descriptor.digest.clone(), blob);
+        descriptor
+    }
+    
+    fn blob(&self, name: &str, descriptor: &OCIDescriptor) -> Result<&[u8]> {
+        self.blobs.get(&descriptor.digest)
+            .map(Vec::as_slice)
+            .ok_or_else(|| anyhow!("Blob {} of {} not found", descriptor.digest, name))
+    }
+}
+
+#[async_trait::async_trait]
+impl ImageSource for MemorySource {
+    async fn resolve(&self, name: &str, tag: &str) -> Result<OCIIndex> {
+        self.indexes.get(&(name.to_string(), tag.to_string()))
+            .cloned()
+            .ok_or_else(|| Error::ImageNotFound {
+                image: format!("{}:{}", name, tag),
+                reason: "not found in the memory source".to_string(),
+            }.into())
+    }
+    
+    async fn fetch_manifest(&self, name: &str, descriptor: &OCIDescriptor) -> Result<OCIManifest> {
+        Ok(serde_json::from_slice(self.blob(name, descriptor)?)?)
+    }
+    
+    async fn fetch_blob(&self, name: &str, descriptor: &OCIDescriptor) -> Result<Vec<u8>> {
+        Ok(self.blob(name, descriptor)?.to_vec())
+    }
+}
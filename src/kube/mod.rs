@@ -0,0 +1,466 @@
+use anyhow::{Result, anyhow};
+use serde::Deserialize;
+use serde_yaml::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+use crate::compose::{Compose, ComposeFile, Definition, Service};
+use crate::container::PortMapping;
+use crate::image::ImageData;
+use crate::volume::{VolumeManager, VolumeSpec};
+
+// A Kubernetes object, of which Pods, Deployments and ConfigMaps are used
+// and everything else, such as Services, is skipped.
+#[derive(Debug, Deserialize)]
+struct Object {
+    #[serde(default)]
+    kind: String,
+    #[serde(default)]
+    metadata: Metadata,
+    #[serde(default)]
+    spec: Value,
+    #[serde(default)]
+    data: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Metadata {
+    #[serde(default)]
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DeploymentSpec {
+    #[serde(default)]
+    replicas: Option<u32>,
+    template: PodTemplate,
+}
+
+#[derive(Debug, Deserialize)]
+struct PodTemplate {
+    spec: PodSpec,
+}
+
+#[derive(Debug, Deserialize)]
+struct PodSpec {
+    containers: Vec<KubeContainer>,
+    #[serde(default)]
+    volumes: Vec<KubeVolume>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct KubeContainer {
+    name: String,
+    image: String,
+    #[serde(default)]
+    command: Vec<String>,
+    #[serde(default)]
+    args: Vec<String>,
+    #[serde(default)]
+    working_dir: Option<String>,
+    #[serde(default)]
+    env: Vec<EnvVar>,
+    #[serde(default)]
+    env_from: Vec<EnvFromSource>,
+    #[serde(default)]
+    ports: Vec<ContainerPort>,
+    #[serde(default)]
+    volume_mounts: Vec<KubeVolumeMount>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EnvVar {
+    name: String,
+    #[serde(default)]
+    value: Option<String>,
+    #[serde(default)]
+    value_from: Option<EnvVarSource>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EnvVarSource {
+    #[serde(default)]
+    config_map_key_ref: Option<KeyRef>,
+}
+
+#[derive(Debug, Deserialize)]
+struct KeyRef {
+    name: String,
+    key: String,
+    #[serde(default)]
+    optional: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EnvFromSource {
+    #[serde(default)]
+    prefix: String,
+    #[serde(default)]
+    config_map_ref: Option<NameRef>,
+}
+
+#[derive(Debug, Deserialize)]
+struct NameRef {
+    name: String,
+    #[serde(default)]
+    optional: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ContainerPort {
+    container_port: u16,
+    #[serde(default)]
+    host_port: Option<u16>,
+    #[serde(default)]
+    protocol: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct KubeVolumeMount {
+    name: String,
+    mount_path: String,
+    #[serde(default)]
+    read_only: bool,
+    #[serde(default)]
+    sub_path: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct KubeVolume {
+    name: String,
+    #[serde(default)]
+    empty_dir: Option<Value>,
+    #[serde(default)]
+    host_path: Option<HostPathSource>,
+    #[serde(default)]
+    config_map: Option<ConfigMapSource>,
+}
+
+#[derive(Debug, Deserialize)]
+struct HostPathSource {
+    path: PathBuf,
+    #[serde(default, rename = "type")]
+    kind: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ConfigMapSource {
+    name: String,
+    #[serde(default)]
+    items: Vec<KeyToPath>,
+}
+
+#[derive(Debug, Deserialize)]
+struct KeyToPath {
+    key: String,
+    path: String,
+}
+
+// Where a pod's volume comes from once translated.
+#[derive(Debug, Clone)]
+enum PodVolume {
+    // A project volume, removed along with the pod.
+    EmptyDir,
+    HostPath(PathBuf),
+    // Files written out before the pod starts, by their path in the volume.
+    ConfigMap(BTreeMap<String, String>),
+}
+
+/// A Pod, or a Deployment's pod template, run as a Compose project named
+/// after it: each container becomes a service on the project's default
+/// network, reaching the others by container name.
+#[derive(Debug, Clone)]
+pub struct KubePod {
+    compose: Compose,
+    // Host directories for configMap volumes, with the files in each.
+    config_files: Vec<(PathBuf, BTreeMap<String, String>)>,
+    // hostPath directories created if missing.
+    create_dirs: Vec<PathBuf>,
+    // The volumes created for emptyDirs.
+    empty_dirs: Vec<String>,
+}
+
+impl KubePod {
+    /// Reads the pods in the YAML documents of `path`. ConfigMaps may be in
+    /// the same file or in `config_maps`.
+    pub fn load(path: &Path, config_maps: &[PathBuf], state_dir: &Path) -> Result<Vec<Self>> {
+        let read = |path: &Path| fs::read_to_string(path).map_err(|e| anyhow!("Failed to read {:?}: {}", path, e));
+        let mut yaml = read(path)?;
+        for config_map in config_maps {
+            yaml.push_str("\n---\n");
+            yaml.push_str(&read(config_map)?);
+        }
+        let dir = std::path::absolute(path)?
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        Self::parse(&yaml, &dir, state_dir)
+    }
+    
+    /// Translates the Pods and Deployments among the YAML documents in
+    /// `yaml`. ConfigMap files are kept under `state_dir`.
+    pub fn parse(yaml: &str, dir: &Path, state_dir: &Path) -> Result<Vec<Self>> {
+        let mut objects = Vec::new();
+        for document in serde_yaml::Deserializer::from_str(yaml) {
+            let value = Value::deserialize(document).map_err(|e| anyhow!("Invalid Kubernetes YAML: {}", e))?;
+            if value.is_null() {
+                continue;
+            }
+            let object: Object = serde_yaml::from_value(value).map_err(|e| anyhow!("Invalid Kubernetes YAML: {}", e))?;
+            objects.push(object);
+        }
+        
+        let config_maps: HashMap<&str, &BTreeMap<String, String>> = objects.iter()
+            .filter(|object| object.kind == "ConfigMap")
+            .map(|object| (object.metadata.name.as_str(), &object.data))
+            .collect();
+        
+        let mut pods = Vec::new();
+        for object in &objects {
+            let invalid = |e: serde_yaml::Error| anyhow!("Invalid {} {}: {}", object.kind, object.metadata.name, e);
+            let spec: PodSpec = match object.kind.as_str() {
+                "Pod" => serde_yaml::from_value(object.spec.clone()).map_err(invalid)?,
+                "Deployment" => {
+                    let deployment: DeploymentSpec = serde_yaml::from_value(object.spec.clone()).map_err(invalid)?;
+                    if deployment.replicas.is_some_and(|replicas| replicas > 1) {
+                        warn!("Deployment {} runs as a single pod", object.metadata.name);
+                    }
+                    deployment.template.spec
+                }
+                "ConfigMap" => continue,
+                kind => {
+                    warn!("Skipping {} {}: only Pods and Deployments are run", kind, object.metadata.name);
+                    continue;
+                }
+            };
+            pods.push(Self::translate(&object.metadata.name, spec, &config_maps, dir, state_dir)?);
+        }
+        if pods.is_empty() {
+            return Err(anyhow!("No Pods or Deployments to run"));
+        }
+        Ok(pods)
+    }
+    
+    fn translate(
+        name: &str,
+        spec: PodSpec,
+        config_maps: &HashMap<&str, &BTreeMap<String, String>>,
+        dir: &Path,
+        state_dir: &Path,
+    ) -> Result<Self> {
+        // Kubernetes names may have dots, which projects can't.
+        let project = name.replace('.', "-");
+        let config_map = |map: &str| config_maps.get(map).copied()
+            .ok_or_else(|| anyhow!("Pod {} uses undefined ConfigMap {}", name, map));
+        
+        let mut volumes = HashMap::new();
+        for volume in &spec.volumes {
+            // Volume names become directory names under the state dir.
+            validate_volume_name(&volume.name)
+                .map_err(|e| anyhow!("Pod {}: {}", name, e))?;
+            let source = match (&volume.empty_dir, &volume.host_path, &volume.config_map) {
+                (Some(_), None, None) => PodVolume::EmptyDir,
+                (None, Some(host_path), None) => PodVolume::HostPath(host_path.path.clone()),
+                (None, None, Some(source)) => {
+                    let data = config_map(&source.name)?;
+                    let files = match source.items.as_slice() {
+                        [] => data.clone(),
+                        items => items.iter()
+                            .map(|item| {
+                                let value = data.get(&item.key)
+                                    .ok_or_else(|| anyhow!("ConfigMap {} has no key {}", source.name, item.key))?;
+                                Ok((item.path.clone(), value.clone()))
+                            })
+                            .collect::<Result<_>>()?,
+                    };
+                    PodVolume::ConfigMap(files)
+                }
+                _ => return Err(anyhow!(
+                    "Volume {} of pod {} is not supported (use emptyDir, hostPath or configMap)",
+                    volume.name, name
+                )),
+            };
+            volumes.insert(volume.name.as_str(), source);
+        }
+        
+        let mut file = ComposeFile::default();
+        let mut config_files = Vec::new();
+        let mut create_dirs = Vec::new();
+        let mut empty_dirs = Vec::new();
+        for volume in &spec.volumes {
+            match &volumes[volume.name.as_str()] {
+                PodVolume::EmptyDir => {
+                    file.volumes.insert(volume.name.clone(), Definition::default());
+                    empty_dirs.push(format!("{}_{}", project, volume.name));
+                }
+                PodVolume::HostPath(path) => {
+                    let kind = volume.host_path.as_ref().map_or("", |host_path| host_path.kind.as_str());
+                    if kind == "DirectoryOrCreate" {
+                        create_dirs.push(dir.join(path));
+                    }
+                }
+                PodVolume::ConfigMap(files) => {
+                    config_files.push((config_dir(state_dir, &project).join(&volume.name), files.clone()));
+                }
+            }
+        }
+        
+        for container in spec.containers {
+            let mut environment = Vec::new();
+            for source in &container.env_from {
+                let Some(map) = &source.config_map_ref else {
+                    return Err(anyhow!("Container {} of pod {}: only configMapRef is supported in envFrom", container.name, name));
+                };
+                match config_maps.get(map.name.as_str()) {
+                    Some(data) => environment.extend(data.iter().map(|(key, value)| format!("{}{}={}", source.prefix, key, value))),
+                    None if map.optional => {}
+                    None => return Err(anyhow!("Pod {} uses undefined ConfigMap {}", name, map.name)),
+                }
+            }
+            for var in &container.env {
+                let value = match (&var.value, &var.value_from) {
+                    (_, Some(EnvVarSource { config_map_key_ref: Some(key_ref) })) => {
+                        match config_maps.get(key_ref.name.as_str()).and_then(|data| data.get(&key_ref.key)) {
+                            Some(value) => value.clone(),
+                            None if key_ref.optional => continue,
+                            None => return Err(anyhow!("Pod {} uses undefined ConfigMap key {}/{}", name, key_ref.name, key_ref.key)),
+                        }
+                    }
+                    (_, Some(_)) => {
+                        return Err(anyhow!("Env {} of container {}: only configMapKeyRef is supported in valueFrom", var.name, container.name));
+                    }
+                    (value, None) => value.clone().unwrap_or_default(),
+                };
+                environment.push(format!("{}={}", var.name, value));
+            }
+            
+            // Only ports with a hostPort are published, as for a pod.
+            let mut ports = Vec::new();
+            for port in &container.ports {
+                if let Some(host_port) = port.host_port {
+                    let protocol = port.protocol.as_deref().unwrap_or("TCP").to_ascii_lowercase();
+                    ports.extend(PortMapping::parse(&format!("{}:{}/{}", host_port, port.container_port, protocol))?);
+                }
+            }
+            
+            let mut mounts = Vec::new();
+            for mount in &container.volume_mounts {
+                if mount.sub_path.is_some() {
+                    return Err(anyhow!("Volume mount {} of container {}: subPath is not supported", mount.name, container.name));
+                }
+                let source = match volumes.get(mount.name.as_str()) {
+                    Some(PodVolume::EmptyDir) => mount.name.clone(),
+                    Some(PodVolume::HostPath(path)) => dir.join(path).to_string_lossy().into_owned(),
+                    Some(PodVolume::ConfigMap(_)) => config_dir(state_dir, &project).join(&mount.name).to_string_lossy().into_owned(),
+                    None => return Err(anyhow!("Container {} mounts undefined volume {}", container.name, mount.name)),
+                };
+                // ConfigMaps are always read-only in a pod.
+                let read_only = mount.read_only || matches!(volumes.get(mount.name.as_str()), Some(PodVolume::ConfigMap(_)));
+                let spec = format!("{}:{}{}", source, mount.mount_path, if read_only { ":ro" } else { "" });
+                mounts.push(VolumeSpec::parse(&spec)?);
+            }
+            
+            let command: Vec<String> = container.command.into_iter().chain(container.args).collect();
+            file.services.insert(container.name, Service {
+                image: container.image,
+                command: Some(command).filter(|command| !command.is_empty()),
+                environment,
+                ports,
+                volumes: mounts,
+                depends_on: Vec::new(),
+                networks: Vec::new(),
+                working_dir: container.working_dir,
+            });
+        }
+        
+        Ok(Self {
+            compose: Compose::new(file, dir, &project).map_err(|e| anyhow!("Pod {}: {}", name, e))?,
+            config_files,
+            create_dirs,
+            empty_dirs,
+        })
+    }
+    
+    pub fn compose(&self) -> &Compose {
+        &self.compose
+    }
+    
+    /// Writes out the pod's ConfigMap volumes and runs it until its
+    /// containers exit. `images` holds each container's image.
+    pub async fn up(&self, state_dir: &Path, images: &HashMap<String, ImageData>) -> Result<()> {
+        for dir in &self.create_dirs {
+            fs::create_dir_all(dir)?;
+        }
+        let config_dir = config_dir(state_dir, self.compose.project());
+        for (dir, files) in &self.config_files {
+            if !dir.starts_with(&config_dir) {
+                return Err(anyhow!("ConfigMap volume {:?} is outside {:?}", dir, config_dir));
+            }
+            if dir.exists() {
+                fs::remove_dir_all(dir)?;
+            }
+            for (path, contents) in files {
+                let path = dir.join(path);
+                if !path.starts_with(dir) || path.components().any(|component| component == std::path::Component::ParentDir) {
+                    return Err(anyhow!("ConfigMap path {:?} leaves its volume", path));
+                }
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::write(&path, contents)?;
+            }
+        }
+        info!("Starting pod {}", self.compose.project());
+        self.compose.up(state_dir, images).await
+    }
+    
+    /// Removes the pod's containers, network, emptyDir volumes and
+    /// ConfigMap files.
+    pub async fn down(&self, state_dir: &Path) -> Result<()> {
+        let project = self.compose.project();
+        Compose::down(state_dir, project).await?;
+        
+        let volumes = VolumeManager::with_dir(state_dir.join("volumes"))?;
+        for volume in &self.empty_dirs {
+            if volumes.inspect(volume).is_ok() {
+                volumes.remove(volume)?;
+            }
+        }
+        let config_dir = config_dir(state_dir, project);
+        if config_dir.exists() {
+            fs::remove_dir_all(config_dir)?;
+        }
+        Ok(())
+    }
+}
+
+fn config_dir(state_dir: &Path, project: &str) -> PathBuf {
+    state_dir.join("kube").join(project)
+}
+
+// Pod volume names must be DNS-1123 labels, as in Kubernetes.
+fn validate_volume_name(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && name.len() <= 63
+        && name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        && !name.starts_with('-')
+        && !name.ends_with('-');
+    
+    if !valid {
+        return Err(anyhow!(
+            "Invalid volume name: {:?} (must be a DNS-1123 label: [a-z0-9]([a-z0-9-]*[a-z0-9])?)",
+            name
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,69 @@
+//! Runs WebAssembly modules as containers: images are pulled into a local
+//! cache, and each container gets its own WASI environment, rootfs,
+//! volumes and network addresses, with its state kept on disk so other
+//! processes, such as the `wasm-container` CLI, see it.
+//!
+//! The types needed to embed the runtime are re-exported at the crate root;
+//! the modules hold the rest, such as compose projects and the Docker API
+//! server.
+//!
+//! ```
+//! use wasm_container::{Container, ImageManager, WasmRuntime};
+//!
+//! # #[tokio::main]
+//! # async fn main() -> anyhow::Result<()> {
+//! # let dir = tempfile::TempDir::new()?;
+//! # let (cache_dir, state_dir) = (dir.path().join("images"), dir.path().join("state"));
+//! let images = ImageManager::with_cache_dir(cache_dir)?;
+//! let image = images.get_or_pull("demo:latest").await?;
+//!
+//! let mut container = Container::new(image, None, None, vec!["GREETING=hello".to_string()])?;
+//! container.set_name("demo")?;
+//! container.add_port_mapping(0, 8080, "tcp".to_string());
+//!
+//! let mut runtime = WasmRuntime::with_state_dir(&state_dir)?;
+//! runtime.run(container).await?;
+//!
+//! let containers = runtime.list_containers(true).await?;
+//! assert_eq!(containers[0].name.as_deref(), Some("demo"));
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The embedding API fails with [`Error`], whose variants tell failure
+//! modes apart. A failed run's error maps to the exit code docker would
+//! report with [`Error::exit_code`], or [`exit_code`] once it is an
+//! `anyhow::Error`.
+
+pub mod runtime;
+pub mod error;
+pub mod config;
+pub mod container;
+pub mod image;
+pub mod filesystem;
+pub mod network;
+pub mod state;
+pub mod volume;
+pub mod compose;
+pub mod kube;
+pub mod app;
+pub mod systemd;
+pub mod format;
+pub mod dashboard;
+pub mod daemon;
+pub mod doctor;
+
+pub use config::RuntimeConfig;
+pub use container::builder::ContainerBuilder;
+pub use error::Error;
+pub use container::{Container, ContainerFilter, ContainerInfo, Cpus, PortMapping, Resources, RootfsBackend, VolumeMount};
+pub use image::source::ImageSource;
+pub use image::{ImageData, ImageManager, Platform, PullPolicy};
+pub use network::{Network, NetworkManager};
+pub use runtime::builder::{EngineOptions, RuntimeBuilder};
+pub use runtime::cancel::{CancellationToken, Cancelled};
+pub use runtime::events::ContainerEvent;
+pub use runtime::handle::{ContainerHandle, ExitStatus};
+pub use runtime::host::HostFunctions;
+pub use runtime::{exit_code, ResourceUsage, RuntimeInfo, StartError, WasmRuntime};
+pub use volume::{VolumeManager, VolumeSource, VolumeSpec};
@@ -1,17 +1,60 @@
-use anyhow::Result;
-use clap::{Parser, Subcommand};
-use tracing::{info, error};
-use tracing_subscriber;
+use anyhow::{Result, anyhow};
+use clap::{Args, CommandFactory, Parser, Subcommand};
+use clap_complete::env::Shells;
+use clap_complete::{ArgValueCandidates, CompleteEnv, CompletionCandidate};
+use ratatui::DefaultTerminal;
+use ratatui::crossterm::event::{self, Event, KeyEventKind};
+use std::collections::HashMap;
+use std::io::{IsTerminal, Read, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::os::unix::process::CommandExt;
+use std::process::Stdio;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+use tokio::task::JoinSet;
+use tracing::{info, warn};
+use wasmtime_wasi::I32Exit;
 
-mod runtime;
-mod container;
-mod image;
-mod filesystem;
-mod network;
+use wasm_container::runtime::{exit_code, wasm_features, WasmRuntime, ORPHAN_GRACE_PERIOD, WASMTIME_VERSION};
+use wasm_container::runtime::cancel::CancellationToken;
+use wasm_container::app::{App, DEFAULT_FILE as DEFAULT_APP_FILE};
+use wasm_container::compose::Compose;
+use wasm_container::dashboard::{Action, Dashboard};
+use wasm_container::config::{parse_size, RuntimeConfig};
+use wasm_container::daemon::{Daemon, DEFAULT_SOCKET};
+use wasm_container::doctor::{Doctor, Severity};
+use wasm_container::error::Error;
+use wasm_container::container::{parse_label, Container, ContainerFilter, ContainerInfo, Cpus, PortMapping, Resources, RootfsBackend, VolumeMount};
+use wasm_container::format::schema::{ContainerSummary, ImageSummary, NetworkSummary, VolumeSummary};
+use wasm_container::format::{align_columns, format_bytes, OutputFormat, Template};
+use wasm_container::image::{ImageManager, Platform, PullPolicy};
+use wasm_container::image::auth::{CredentialStore, Credentials, DEFAULT_REGISTRY};
+use wasm_container::kube::KubePod;
+use wasm_container::network::{activation, HOST_NETWORK, NO_NETWORK};
+use wasm_container::network::firewall::{LinkRule, NetworkPolicy};
+use wasm_container::network::forward::ForwardOptions;
+use wasm_container::network::ingress::IngressRule;
+use wasm_container::network::netem::Netem;
+use wasm_container::network::policy::{EgressDefault, EgressPolicy, HostRule};
+use wasm_container::network::proxy::ProxySettings;
+use wasm_container::network::throttle::Rate;
+use wasm_container::state::{self, ContainerStore};
+use wasm_container::systemd::{RestartPolicy, Unit, UnitOptions};
+use wasm_container::volume::{VolumeManager, VolumeSource, VolumeSpec};
 
-use crate::runtime::WasmRuntime;
-use crate::container::Container;
-use crate::image::ImageManager;
+// How long `netdump` waits for the run to start writing the capture.
+const NETDUMP_START_TIMEOUT: Duration = Duration::from_secs(5);
+// How long `network connect` waits for a running container to join.
+const NETWORK_JOIN_TIMEOUT: Duration = Duration::from_secs(5);
+// Set by the completion scripts when they call back into the binary.
+const COMPLETE_VAR: &str = "WASM_CONTAINER_COMPLETE";
+// How often `top` reads the containers' samples, which runs take once a
+// second, and how long it waits for a key in between redraws.
+const TOP_REFRESH: Duration = Duration::from_secs(1);
+const TOP_INPUT_WAIT: Duration = Duration::from_millis(200);
+// What `run --rm` exits with when Ctrl-C stops it, as docker does.
+const EXIT_INTERRUPTED: i32 = 128 + libc::SIGINT;
 
 #[derive(Parser)]
 #[command(name = "wasm-container")]
@@ -21,104 +64,1700 @@ struct Cli {
     command: Commands,
 }
 
+const DEFAULT_LIST_FORMAT: &str = "table {{.ID}}\t{{.Names}}\t{{.Image}}\t{{.RunningFor}}\t{{.Status}}\t{{.Ports}}";
+
 #[derive(Subcommand)]
 enum Commands {
-    Run {
-        #[arg(help = "Container image to run")]
+    Run(Box<RunArgs>),
+    
+    Pull {
+        #[arg(help = "Image to pull", add = ArgValueCandidates::new(image_candidates))]
         image: String,
         
-        #[arg(short, long, help = "Command to execute in container")]
-        command: Option<Vec<String>>,
-        
-        #[arg(short, long, help = "Working directory")]
-        workdir: Option<String>,
+        #[arg(long, default_value = "wasi/wasm32", help = "Platform to pull from multi-platform images, OS/ARCH[/VARIANT]")]
+        platform: Platform,
+    },
+    
+    Images {
+        #[arg(long, help = "Print the images as json or yaml")]
+        format: Option<OutputFormat>,
         
-        #[arg(short, long, help = "Environment variables")]
-        env: Vec<String>,
+        #[arg(short, long, help = "Only print image references")]
+        quiet: bool,
     },
     
-    Pull {
-        #[arg(help = "Image to pull")]
-        image: String,
+    Image {
+        #[command(subcommand)]
+        command: ImageCommands,
     },
     
+    #[command(about = "Alias for image rm")]
+    Rmi {
+        #[arg(required = true, help = "Images to remove", add = ArgValueCandidates::new(image_candidates))]
+        images: Vec<String>,
+        
+        #[arg(short, long, help = "Don't ask for confirmation")]
+        force: bool,
+    },
+    
+    #[command(visible_alias = "ps")]
     List {
         #[arg(short, long, help = "List all containers including stopped")]
         all: bool,
+        
+        #[arg(short, long, help = "Only list containers matching label=KEY[=VALUE] or name=NAME; repeat to require several")]
+        filter: Vec<ContainerFilter>,
+        
+        #[arg(long, help = "Print each container with a template like '{{.ID}}\\t{{.Ports}}', starting it with 'table' for aligned columns under headers, or as json or yaml")]
+        format: Option<String>,
+        
+        #[arg(short, long, help = "Only print container IDs")]
+        quiet: bool,
+    },
+    
+    Port {
+        #[arg(help = "Container ID", add = ArgValueCandidates::new(container_candidates))]
+        container_id: String,
+    },
+    
+    Top,
+    
+    Stats {
+        #[arg(required = true, help = "Running containers to report on", add = ArgValueCandidates::new(container_candidates))]
+        container_ids: Vec<String>,
+    },
+    
+    Inspect {
+        #[arg(required = true, help = "Container IDs to inspect", add = ArgValueCandidates::new(container_candidates))]
+        container_ids: Vec<String>,
+        
+        #[arg(long, default_value = "json", help = "Print the details as json or yaml")]
+        format: OutputFormat,
+    },
+    
+    Start {
+        #[arg(help = "Container ID to start", add = ArgValueCandidates::new(container_candidates))]
+        container_id: String,
+        
+        #[command(flatten)]
+        ingress: IngressArgs,
     },
     
     Stop {
-        #[arg(help = "Container ID to stop")]
+        #[arg(help = "Container ID to stop", add = ArgValueCandidates::new(container_candidates))]
+        container_id: String,
+    },
+    
+    Rm {
+        #[arg(required = true, help = "Container IDs to remove", add = ArgValueCandidates::new(container_candidates))]
+        container_ids: Vec<String>,
+        
+        #[arg(short, long, help = "Don't ask for confirmation, and stop running containers before removing them")]
+        force: bool,
+    },
+    
+    Diff {
+        #[arg(help = "Container ID to inspect for filesystem changes", add = ArgValueCandidates::new(container_candidates))]
+        container_id: String,
+    },
+    
+    Export {
+        #[arg(help = "Container ID to export", add = ArgValueCandidates::new(container_candidates))]
+        container_id: String,
+        
+        #[arg(short, long, help = "Write the tar archive to a file instead of stdout")]
+        output: Option<PathBuf>,
+    },
+    
+    Netdump {
+        #[arg(help = "Running container to capture the traffic of", add = ArgValueCandidates::new(container_candidates))]
+        container_id: String,
+        
+        #[arg(short, long, help = "The pcap file to write, e.g. out.pcap")]
+        output: PathBuf,
+    },
+    
+    Cp {
+        #[arg(help = "Source: CONTAINER:PATH or a host path")]
+        src: String,
+        
+        #[arg(help = "Destination: CONTAINER:PATH or a host path")]
+        dest: String,
+    },
+    
+    Volume {
+        #[command(subcommand)]
+        command: VolumeCommands,
+    },
+    
+    System {
+        #[command(subcommand)]
+        command: SystemCommands,
+    },
+    
+    Network {
+        #[command(subcommand)]
+        command: NetworkCommands,
+    },
+    
+    Snapshot {
+        #[command(subcommand)]
+        command: SnapshotCommands,
+    },
+    
+    Compose {
+        #[arg(short, long, global = true, help = "Compose file; defaults to compose.yaml or docker-compose.yml in the current directory")]
+        file: Option<PathBuf>,
+        
+        #[arg(short, long, global = true, help = "Project name; defaults to the compose file's name or its directory")]
+        project_name: Option<String>,
+        
+        #[command(subcommand)]
+        command: ComposeCommands,
+    },
+    
+    Play {
+        #[command(subcommand)]
+        command: PlayCommands,
+    },
+    
+    Apply {
+        #[arg(default_value = DEFAULT_APP_FILE, help = "App manifest to run")]
+        file: PathBuf,
+        
+        #[arg(short, long, help = "App name; defaults to the manifest's name or its directory")]
+        project_name: Option<String>,
+    },
+    
+    Destroy {
+        #[arg(default_value = DEFAULT_APP_FILE, help = "App manifest to take down")]
+        file: PathBuf,
+        
+        #[arg(short, long, help = "App name; defaults to the manifest's name or its directory")]
+        project_name: Option<String>,
+        
+        #[arg(long, help = "Also remove the app's named volumes")]
+        volumes: bool,
+    },
+    
+    Status {
+        #[arg(default_value = DEFAULT_APP_FILE, help = "App manifest to show the components of")]
+        file: PathBuf,
+        
+        #[arg(short, long, help = "App name; defaults to the manifest's name or its directory")]
+        project_name: Option<String>,
+    },
+    
+    Generate {
+        #[command(subcommand)]
+        command: GenerateCommands,
+    },
+    
+    Completions {
+        #[arg(value_parser = ["bash", "zsh", "fish", "powershell", "elvish"], help = "Shell to print the completion script for")]
+        shell: String,
+    },
+    
+    Login {
+        #[arg(default_value = DEFAULT_REGISTRY, help = "Registry to log in to, e.g. ghcr.io")]
+        registry: String,
+        
+        #[arg(short, long, help = "Username; asked for when not given")]
+        username: Option<String>,
+        
+        #[arg(short, long, conflicts_with = "password_stdin", help = "Password or token; prefer --password-stdin, as this shows in the process list")]
+        password: Option<String>,
+        
+        #[arg(long, help = "Read the password or token from stdin")]
+        password_stdin: bool,
+    },
+    
+    Logout {
+        #[arg(default_value = DEFAULT_REGISTRY, help = "Registry to log out of")]
+        registry: String,
+        
+        #[arg(short, long, conflicts_with = "registry", help = "Log out of every registry in the auth file")]
+        all: bool,
+    },
+    
+    Daemon {
+        #[arg(long, default_value = DEFAULT_SOCKET, help = "Unix socket to serve the Docker API on")]
+        socket: PathBuf,
+    },
+    
+    Version,
+    
+    Info,
+    
+    Doctor,
+}
+
+#[derive(Args)]
+struct RunArgs {
+    #[arg(help = "Container image to run", add = ArgValueCandidates::new(image_candidates))]
+    image: String,
+    
+    #[arg(long, default_value = "missing", help = "When to pull the image: always (unless the cached copy is the registry's latest), missing, or never")]
+    pull: PullPolicy,
+    
+    #[arg(long, default_value = "wasi/wasm32", help = "Platform to run the image for, OS/ARCH[/VARIANT]; cached images pulled for another are pulled again")]
+    platform: Platform,
+    
+    #[arg(long, help = "Name for the container, unique and usable wherever its ID is; also its hostname")]
+    name: Option<String>,
+    
+    #[arg(long, help = "Hostname for the container instead of its name or ID")]
+    hostname: Option<String>,
+    
+    #[arg(short, long, help = "Command to execute in container")]
+    command: Option<Vec<String>>,
+    
+    #[arg(short, long, help = "Working directory")]
+    workdir: Option<String>,
+    
+    #[arg(short, long, help = "Environment variables")]
+    env: Vec<String>,
+    
+    #[arg(long, value_parser = parse_label, help = "Label the container with KEY=VALUE, to pick it out with list --filter")]
+    label: Vec<(String, String)>,
+    
+    #[arg(short, long = "volume", help = "Mount a volume (name:/path or /host/path:/path, optionally :ro, :cached, :sync, ...)")]
+    volume: Vec<String>,
+    
+    #[arg(long, help = "Remove the container, its logs and rootfs once the run exits")]
+    rm: bool,
+    
+    #[arg(long, default_value = "disk", help = "Rootfs backend: disk (persistent) or memory (tmpfs, discarded on exit)")]
+    rootfs: RootfsBackend,
+    
+    #[arg(long, help = "Mount the rootfs read-only; only volumes and --tmpfs paths are writable")]
+    read_only: bool,
+    
+    #[arg(long, value_parser = parse_size, help = "Limit the guest's memory, e.g. 128m or 2g")]
+    memory: Option<u64>,
+    
+    #[arg(long, help = "Limit the guest to a share of the CPUs, e.g. 0.5")]
+    cpus: Option<Cpus>,
+    
+    #[arg(long, help = "Mount an empty writable directory, discarded on exit")]
+    tmpfs: Vec<PathBuf>,
+    
+    #[arg(long, help = "Nameserver for the container's /etc/resolv.conf")]
+    dns: Vec<IpAddr>,
+    
+    #[arg(long, help = "DNS search domain for the container's /etc/resolv.conf")]
+    dns_search: Vec<String>,
+    
+    #[arg(long, help = "Hide a path from the container: files read as empty, directories list as empty")]
+    mask: Vec<PathBuf>,
+    
+    #[arg(long, help = "Connect to a network instead of the default bridge; repeat to join several, pass host to share the host's network, or none to disable networking")]
+    network: Vec<String>,
+    
+    #[arg(short, long, help = "Publish container ports on the host: HOST:CONTAINER[/tcp|udp], where either side can be a range like 8000-8010")]
+    publish: Vec<String>,
+    
+    #[arg(short = 'P', long, help = "Publish every port the image exposes on a free host port")]
+    publish_all: bool,
+    
+    #[arg(long, help = "Publish on the next free host port when one asked for is in use")]
+    publish_retry: bool,
+    
+    #[arg(long, help = "Static address on the first network, e.g. 172.17.0.50")]
+    ip: Option<IpAddr>,
+    
+    #[arg(long, help = "Allow outbound connections to host[:port]; the host can be a name, address or CIDR")]
+    allow_host: Vec<HostRule>,
+    
+    #[arg(long, help = "Block outbound connections to host[:port]; takes precedence over --allow-host")]
+    deny_host: Vec<HostRule>,
+    
+    #[arg(long, default_value = "allow", help = "Outbound connections no rule matches: allow or deny")]
+    egress_default: EgressDefault,
+    
+    #[arg(long, help = "Limit traffic through published ports each way, e.g. 10mbit or 500kbps")]
+    network_rate: Option<Rate>,
+    
+    #[arg(long, help = "Impair traffic through published ports for testing, e.g. delay=100ms,jitter=10ms,loss=1%,rate=1mbit")]
+    netem: Option<Netem>,
+    
+    #[arg(long, help = "Limits for each published port, e.g. idle-timeout=5m,keepalive=30s,max-connections=100")]
+    port_options: Option<ForwardOptions>,
+    
+    #[arg(long, help = "Proxy for plain HTTP, passed to the guest as HTTP_PROXY; defaults to http_proxy in the config file")]
+    http_proxy: Option<String>,
+    
+    #[arg(long, help = "Proxy for HTTPS, passed to the guest as HTTPS_PROXY and used for connections the host opens for it")]
+    https_proxy: Option<String>,
+    
+    #[arg(long, help = "Comma-separated destinations that skip the proxy, passed to the guest as NO_PROXY")]
+    no_proxy: Option<String>,
+    
+    #[arg(long, help = "Advertise published TCP ports on the local network over mDNS/DNS-SD, named after the container")]
+    mdns: bool,
+    
+    #[command(flatten)]
+    ingress: IngressArgs,
+}
+
+#[derive(Args)]
+struct IngressArgs {
+    #[arg(long, help = "Route HTTP requests on --ingress-listen to a container by Host and path prefix: [HOST][/PATH]->CONTAINER:PORT, e.g. app.local->web:80")]
+    ingress_rule: Vec<IngressRule>,
+    
+    #[arg(long, default_value = "0.0.0.0:80", help = "Address the ingress takes HTTP requests on")]
+    ingress_listen: SocketAddr,
+}
+
+#[derive(Subcommand)]
+enum ComposeCommands {
+    Up,
+    
+    Down,
+}
+
+#[derive(Subcommand)]
+enum PlayCommands {
+    Kube {
+        #[arg(help = "Kubernetes YAML with Pods, Deployments and ConfigMaps")]
+        file: PathBuf,
+        
+        #[arg(long = "configmap", help = "YAML file with more ConfigMaps")]
+        configmaps: Vec<PathBuf>,
+        
+        #[arg(long, help = "Stop and remove the pods instead")]
+        down: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum GenerateCommands {
+    Systemd {
+        #[arg(help = "Container to run, or a compose file to run the project of", add = ArgValueCandidates::new(container_candidates))]
+        target: String,
+        
+        #[arg(short, long, help = "Project name for a compose file; defaults to the file's name or its directory")]
+        project_name: Option<String>,
+        
+        #[arg(long, default_value = "on-failure", help = "When systemd restarts the unit: no, on-success, on-failure, on-abnormal, on-abort or always")]
+        restart_policy: RestartPolicy,
+        
+        #[arg(long, default_value_t = 1, help = "Seconds to wait before restarting")]
+        restart_sec: u64,
+        
+        #[arg(long, help = "Container whose unit must be running first; repeat for several")]
+        requires: Vec<String>,
+        
+        #[arg(long, help = "Container whose unit is started first if enabled; repeat for several")]
+        wants: Vec<String>,
+        
+        #[arg(long, help = "Write the unit to a .service file in the current directory instead of stdout")]
+        files: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum SnapshotCommands {
+    Create {
+        #[arg(help = "Container ID to snapshot", add = ArgValueCandidates::new(container_candidates))]
+        container_id: String,
+        
+        #[arg(help = "Snapshot name")]
+        name: String,
+    },
+    
+    Restore {
+        #[arg(help = "Container ID to roll back", add = ArgValueCandidates::new(container_candidates))]
+        container_id: String,
+        
+        #[arg(help = "Snapshot name")]
+        name: String,
+    },
+    
+    Ls {
+        #[arg(help = "Container ID", add = ArgValueCandidates::new(container_candidates))]
+        container_id: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum SystemCommands {
+    Prune {
+        #[arg(short, long, help = "Also remove cached images no container uses")]
+        all: bool,
+        
+        #[arg(short, long, help = "Don't ask for confirmation")]
+        force: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum ImageCommands {
+    Ls {
+        #[arg(long, help = "Print the images as json or yaml")]
+        format: Option<OutputFormat>,
+        
+        #[arg(short, long, help = "Only print image references")]
+        quiet: bool,
+    },
+    
+    Rm {
+        #[arg(required = true, help = "Images to remove", add = ArgValueCandidates::new(image_candidates))]
+        images: Vec<String>,
+        
+        #[arg(short, long, help = "Don't ask for confirmation")]
+        force: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum VolumeCommands {
+    Create {
+        #[arg(help = "Volume name")]
+        name: String,
+    },
+    
+    Ls {
+        #[arg(long, help = "Print the volumes as json or yaml")]
+        format: Option<OutputFormat>,
+        
+        #[arg(short, long, help = "Only print volume names")]
+        quiet: bool,
+    },
+    
+    Rm {
+        #[arg(required = true, help = "Volumes to remove")]
+        names: Vec<String>,
+        
+        #[arg(short, long, help = "Don't ask for confirmation")]
+        force: bool,
+    },
+    
+    Inspect {
+        #[arg(required = true, help = "Volumes to inspect")]
+        names: Vec<String>,
+        
+        #[arg(long, default_value = "json", help = "Print the details as json or yaml")]
+        format: OutputFormat,
+    },
+}
+
+#[derive(Subcommand)]
+enum NetworkCommands {
+    Create {
+        #[arg(help = "Network name")]
+        name: String,
+        
+        #[arg(long, help = "Subnet in CIDR form, e.g. 10.5.0.0/24; repeat with an IPv6 subnet for a dual-stack network")]
+        subnet: Vec<String>,
+        
+        #[arg(long, help = "Gateway address instead of the subnet's first host; repeat for the IPv6 subnet")]
+        gateway: Vec<IpAddr>,
+        
+        #[arg(long, help = "Domain appended to container names in DNS and searched by containers, e.g. internal")]
+        domain: Option<String>,
+        
+        #[arg(long, help = "Keep containers that are only on this network from reaching the host or anything beyond")]
+        internal: bool,
+        
+        #[arg(long, help = "Allow or deny connections between containers, first match wins: allow|deny FROM->TO[:PORT], e.g. 'allow web->db:5432'")]
+        rule: Vec<LinkRule>,
+    },
+    
+    Ls {
+        #[arg(long, help = "Print the networks as json or yaml")]
+        format: Option<OutputFormat>,
+        
+        #[arg(short, long, help = "Only print network names")]
+        quiet: bool,
+    },
+    
+    Rm {
+        #[arg(required = true, help = "Networks to remove")]
+        names: Vec<String>,
+        
+        #[arg(short, long, help = "Don't ask for confirmation")]
+        force: bool,
+    },
+    
+    Inspect {
+        #[arg(required = true, help = "Networks to inspect")]
+        names: Vec<String>,
+        
+        #[arg(long, default_value = "json", help = "Print the details as json or yaml")]
+        format: OutputFormat,
+    },
+    
+    Connect {
+        #[arg(help = "Network name")]
+        network: String,
+        
+        #[arg(help = "Container ID", add = ArgValueCandidates::new(container_candidates))]
+        container_id: String,
+    },
+    
+    Disconnect {
+        #[arg(help = "Network name")]
+        network: String,
+        
+        #[arg(help = "Container ID", add = ArgValueCandidates::new(container_candidates))]
         container_id: String,
     },
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    tracing_subscriber::fmt::init();
+    // Answers the shell when it asks for completions, before anything else
+    // can write to stdout.
+    CompleteEnv::with_factory(Cli::command).var(COMPLETE_VAR).complete();
+    tracing_subscriber::fmt().with_writer(std::io::stderr).init();
     
     let cli = Cli::parse();
     
     match cli.command {
-        Commands::Run { image, command, workdir, env } => {
-            info!("Running container from image: {}", image);
-            run_container(image, command, workdir, env).await?;
+        Commands::Run(args) => {
+            info!("Running container from image: {}", args.image);
+            if let Err(e) = run_container(*args).await {
+                exit_with(e);
+            }
         }
-        Commands::Pull { image } => {
+        Commands::Pull { image, platform } => {
             info!("Pulling image: {}", image);
-            pull_image(image).await?;
+            pull_image(image, platform).await?;
+        }
+        Commands::Images { format, quiet } => {
+            list_images(format, quiet)?;
+        }
+        Commands::Image { command } => {
+            image_command(command)?;
+        }
+        Commands::Rmi { images, force } => {
+            remove_images(images, force)?;
         }
-        Commands::List { all } => {
-            list_containers(all).await?;
+        Commands::List { all, filter, format, quiet } => {
+            list_containers(all, filter, format, quiet).await?;
+        }
+        Commands::Port { container_id } => {
+            container_ports(container_id).await?;
+        }
+        Commands::Top => {
+            top().await?;
+        }
+        Commands::Stats { container_ids } => {
+            container_stats(container_ids).await?;
+        }
+        Commands::Inspect { container_ids, format } => {
+            inspect_containers(container_ids, format).await?;
+        }
+        Commands::Start { container_id, ingress } => {
+            if let Err(e) = start_container(container_id, ingress).await {
+                exit_with(e);
+            }
         }
         Commands::Stop { container_id } => {
             stop_container(container_id).await?;
         }
+        Commands::Rm { container_ids, force } => {
+            remove_containers(container_ids, force).await?;
+        }
+        Commands::Diff { container_id } => {
+            diff_container(container_id).await?;
+        }
+        Commands::Export { container_id, output } => {
+            export_container(container_id, output).await?;
+        }
+        Commands::Netdump { container_id, output } => {
+            netdump(container_id, output).await?;
+        }
+        Commands::Cp { src, dest } => {
+            copy_files(src, dest).await?;
+        }
+        Commands::Volume { command } => {
+            volume_command(command)?;
+        }
+        Commands::System { command } => {
+            system_command(command).await?;
+        }
+        Commands::Network { command } => {
+            network_command(command).await?;
+        }
+        Commands::Snapshot { command } => {
+            snapshot_command(command).await?;
+        }
+        Commands::Compose { file, project_name, command } => {
+            compose_command(file, project_name, command).await?;
+        }
+        Commands::Play { command } => {
+            play_command(command).await?;
+        }
+        Commands::Apply { file, project_name } => {
+            apply_app(file, project_name).await?;
+        }
+        Commands::Destroy { file, project_name, volumes } => {
+            destroy_app(file, project_name, volumes).await?;
+        }
+        Commands::Status { file, project_name } => {
+            app_status(file, project_name).await?;
+        }
+        Commands::Generate { command } => {
+            generate_command(command).await?;
+        }
+        Commands::Completions { shell } => {
+            print_completions(&shell)?;
+        }
+        Commands::Login { registry, username, password, password_stdin } => {
+            login(registry, username, password, password_stdin)?;
+        }
+        Commands::Logout { registry, all } => {
+            logout(registry, all)?;
+        }
+        Commands::Daemon { socket } => {
+            Daemon::new(RuntimeConfig::load()?).await?.listen(&socket).await?;
+        }
+        Commands::Version => {
+            print_version();
+        }
+        Commands::Info => {
+            print_info().await?;
+        }
+        Commands::Doctor => {
+            doctor()?;
+        }
     }
     
     Ok(())
 }
 
-async fn run_container(
-    image: String, 
-    command: Option<Vec<String>>,
-    workdir: Option<String>,
-    env: Vec<String>
-) -> Result<()> {
+async fn run_container(args: RunArgs) -> Result<()> {
     let mut runtime = WasmRuntime::new()?;
-    let image_manager = ImageManager::new()?;
-    
-    let image_data = image_manager.get_or_pull(&image).await?;
-    
-    let container = Container::new(image_data, command, workdir, env)?;
+    adopt_activated_sockets(&runtime).await?;
+    runtime.join_mesh().await?;
+    start_ingress(&runtime, args.ingress).await?;
+    let image_manager = ImageManager::new()?.with_platform(args.platform);
     
-    runtime.run(container).await?;
+    let image_data = image_manager.get(&args.image, args.pull).await?;
     
-    Ok(())
-}
-
-async fn pull_image(image: String) -> Result<()> {
-    let image_manager = ImageManager::new()?;
-    image_manager.pull(&image).await?;
-    info!("Successfully pulled image: {}", image);
-    Ok(())
-}
+    let mut container = Container::new(image_data, args.command, args.workdir, args.env)?;
+    if let Some(name) = &args.name {
+        container.set_name(name)?;
+    }
+    if let Some(hostname) = &args.hostname {
+        container.set_hostname(hostname)?;
+    }
+    for (key, value) in &args.label {
+        container.set_label(key, value);
+    }
+    container.set_rootfs_backend(args.rootfs);
+    container.set_read_only(args.read_only);
+    container.set_resources(Resources {
+        memory: args.memory,
+        cpus: args.cpus,
+    });
+    for path in args.tmpfs {
+        container.add_tmpfs(path);
+    }
+    for server in args.dns {
+        container.add_dns_server(server);
+    }
+    for domain in args.dns_search {
+        container.add_dns_search(domain);
+    }
+    for path in args.mask {
+        container.add_masked_path(path);
+    }
+    if let Some(mode) = args.network.iter().find(|network| *network == NO_NETWORK || *network == HOST_NETWORK) {
+        if args.network.len() > 1 {
+            return Err(anyhow!("--network {} can't be combined with other networks", mode));
+        }
+    } else {
+        for network in &args.network {
+            runtime.network_manager().inspect_network(network).await?;
+        }
+    }
+    if !args.network.is_empty() {
+        container.set_networks(args.network);
+    }
+    container.set_egress_policy(EgressPolicy {
+        default: args.egress_default,
+        allow: args.allow_host,
+        deny: args.deny_host,
+    });
+    for spec in &args.publish {
+        for port_map in PortMapping::parse(spec)? {
+            container.add_port_mapping(port_map.host_port, port_map.container_port, port_map.protocol);
+        }
+    }
+    if args.publish_all {
+        container.publish_exposed_ports()?;
+    }
+    container.set_proxy(ProxySettings {
+        http_proxy: args.http_proxy,
+        https_proxy: args.https_proxy,
+        no_proxy: args.no_proxy,
+    });
+    if let Some(rate) = args.network_rate {
+        container.set_network_rate(rate);
+    }
+    if let Some(netem) = args.netem {
+        container.set_netem(netem);
+    }
+    if let Some(options) = args.port_options {
+        container.set_port_options(options);
+    }
+    container.set_mdns(args.mdns);
+    container.set_publish_retry(args.publish_retry);
+    if let Some(ip) = args.ip {
+        let Some(network) = container.networks().first().filter(|network| *network != NO_NETWORK && *network != HOST_NETWORK) else {
+            return Err(anyhow!("--ip needs a network to assign the address on"));
+        };
+        runtime.network_manager().inspect_network(network).await?.check_address(ip)?;
+        container.set_ip(ip);
+    }
+    
+    if !args.volume.is_empty() {
+        let volume_manager = VolumeManager::new()?;
+        for spec in &args.volume {
+            let spec = VolumeSpec::parse(spec)?;
+            let host_path = match spec.source {
+                VolumeSource::HostPath(path) => std::fs::canonicalize(&path)
+                    .map_err(|e| anyhow!("Volume source {:?} is not accessible: {}", path, e))?,
+                VolumeSource::Named(name) => volume_manager.create(&name)?.mountpoint,
+            };
+            container.add_mount(VolumeMount {
+                host_path,
+                container_path: spec.container_path,
+                read_only: spec.options.read_only,
+                consistency: spec.options.consistency,
+                mode: spec.options.mode,
+            });
+        }
+    }
+    
+    if !args.rm {
+        runtime.run(container).await?;
+        return Ok(());
+    }
+    
+    // The container goes however the run ends, Ctrl-C included. Ctrl-C
+    // stops the guest, and the run still cleans up after it. It's watched
+    // for in a task of its own, as a busy guest holds up the one running it.
+    let container_id = container.id().to_string();
+    let cancel = CancellationToken::new();
+    let interrupt = tokio::spawn({
+        let cancel = cancel.clone();
+        async move {
+            match tokio::signal::ctrl_c().await {
+                Ok(()) => cancel.cancel(),
+                Err(e) => warn!("Failed to listen for Ctrl-C: {}", e),
+            }
+        }
+    });
+    let run = runtime.run_cancellable(container, cancel.clone()).await;
+    interrupt.abort();
+    let run = match cancel.is_cancelled() {
+        true => Err(Error::Exit { exit_code: EXIT_INTERRUPTED }),
+        false => run,
+    };
+    if let Err(e) = runtime.remove(&container_id, true).await {
+        warn!("Failed to remove container {}: {}", container_id, e);
+    }
+    Ok(run?)
+}
+
+// Under systemd socket activation, the sockets systemd bound are published
+// for the container being run or started, on their host ports.
+async fn adopt_activated_sockets(runtime: &WasmRuntime) -> Result<()> {
+    let sockets = activation::listen_fds()?;
+    if !sockets.is_empty() {
+        info!("Socket activated with {} sockets", sockets.len());
+        runtime.network_manager().adopt_activated_sockets(sockets).await;
+    }
+    Ok(())
+}
+
+// Serves the ingress for as long as the container runs, if given rules.
+async fn start_ingress(runtime: &WasmRuntime, ingress: IngressArgs) -> Result<()> {
+    if !ingress.ingress_rule.is_empty() {
+        runtime.network_manager().start_ingress(ingress.ingress_listen, ingress.ingress_rule).await?;
+    }
+    Ok(())
+}
+
+async fn pull_image(image: String, platform: Platform) -> Result<()> {
+    let image_manager = ImageManager::new()?.with_platform(platform);
+    image_manager.pull(&image).await?;
+    info!("Successfully pulled image: {}", image);
+    Ok(())
+}
+
+async fn list_containers(all: bool, filters: Vec<ContainerFilter>, format: Option<String>, quiet: bool) -> Result<()> {
+    let structured = format.as_deref().and_then(OutputFormat::parse).filter(|_| !quiet);
+    let fields: Vec<&str> = ContainerInfo::FIELDS.iter().map(|(field, _)| *field).collect();
+    let template = match (quiet, format) {
+        (true, _) => "{{.ID}}".to_string(),
+        (false, Some(format)) if structured.is_none() => format,
+        (false, _) => DEFAULT_LIST_FORMAT.to_string(),
+    };
+    let template = Template::parse(&template, &fields)?;
+    
+    let runtime = WasmRuntime::new()?;
+    let mut containers = runtime.list_containers(all).await?;
+    containers.retain(|container| filters.iter().all(|filter| filter.matches(container)));
+    
+    if let Some(format) = structured {
+        let containers: Vec<ContainerSummary> = containers.iter().map(ContainerSummary::from).collect();
+        print!("{}", format.render(&containers)?);
+        return Ok(());
+    }
+    
+    let rows = containers.iter().map(|container| template.render(|field| container.field(field)));
+    if template.is_table() {
+        let header = template.render(|field| {
+            ContainerInfo::FIELDS.iter().find(|(name, _)| *name == field).map_or(field, |(_, header)| header).to_string()
+        });
+        let lines: Vec<String> = std::iter::once(header).chain(rows).collect();
+        print!("{}", align_columns(&lines));
+    } else {
+        for row in rows {
+            println!("{}", row);
+        }
+    }
+    
+    Ok(())
+}
+
+fn list_images(format: Option<OutputFormat>, quiet: bool) -> Result<()> {
+    let image_manager = ImageManager::new()?;
+    let mut images = Vec::new();
+    for reference in image_manager.list()? {
+        let mut image = ImageSummary::parse(&reference);
+        image.digest = image_manager.cached_digest(&reference)?;
+        image.platform = image_manager.cached_platform(&reference)?.map(|platform| platform.to_string());
+        images.push(image);
+    }
+    if quiet {
+        for image in images {
+            println!("{}", image.reference);
+        }
+        return Ok(());
+    }
+    if let Some(format) = format {
+        print!("{}", format.render(&images)?);
+        return Ok(());
+    }
+    
+    let lines: Vec<String> = std::iter::once("REPOSITORY\tTAG\tPLATFORM".to_string())
+        .chain(images.iter().map(|image| format!("{}\t{}\t{}", image.name, image.tag, image.platform.as_deref().unwrap_or("-"))))
+        .collect();
+    print!("{}", align_columns(&lines));
+    
+    Ok(())
+}
+
+async fn container_ports(container_id: String) -> Result<()> {
+    let runtime = WasmRuntime::new()?;
+    
+    println!("CONTAINER PORT\tHOST PORT\tCONNECTIONS\tRX / TX");
+    for port in runtime.port_stats(&container_id).await? {
+        println!(
+            "{}/{}\t{}\t{}\t{} / {}",
+            port.container_port,
+            port.protocol,
+            port.host_port,
+            port.connections,
+            format_bytes(port.rx_bytes),
+            format_bytes(port.tx_bytes),
+        );
+    }
+    
+    Ok(())
+}
+
+async fn inspect_containers(container_ids: Vec<String>, format: OutputFormat) -> Result<()> {
+    let runtime = WasmRuntime::new()?;
+    
+    let mut containers = Vec::new();
+    for container_id in &container_ids {
+        containers.push(runtime.inspect(container_id).await?);
+    }
+    print!("{}", format.render(&containers)?);
+    
+    Ok(())
+}
 
-async fn list_containers(all: bool) -> Result<()> {
+// Shows the running containers with their resource use and the selected
+// one's recent logs, refreshing once a second until `q` is pressed.
+async fn top() -> Result<()> {
+    let mut runtime = WasmRuntime::new()?;
+    let mut dashboard = Dashboard::new();
+    let mut terminal = ratatui::try_init()?;
+    let result = run_dashboard(&mut runtime, &mut dashboard, &mut terminal).await;
+    ratatui::restore();
+    result
+}
+
+async fn run_dashboard(runtime: &mut WasmRuntime, dashboard: &mut Dashboard, terminal: &mut DefaultTerminal) -> Result<()> {
+    let mut refreshed: Option<Instant> = None;
+    loop {
+        if refreshed.is_none_or(|at| at.elapsed() >= TOP_REFRESH) {
+            dashboard.refresh(runtime).await?;
+            refreshed = Some(Instant::now());
+        }
+        terminal.draw(|frame| dashboard.render(frame))?;
+        
+        if !event::poll(TOP_INPUT_WAIT)? {
+            continue;
+        }
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        match dashboard.handle_key(key) {
+            Some(Action::Quit) => return Ok(()),
+            Some(Action::Stop(container_id)) => {
+                let message = match runtime.stop(&container_id).await {
+                    Ok(()) => format!("Stopped {}", container_id),
+                    Err(e) => format!("Failed to stop {}: {}", container_id, e),
+                };
+                dashboard.set_message(message);
+                refreshed = None;
+            }
+            Some(Action::Restart(container_id)) => {
+                let message = match restart_detached(runtime, &container_id).await {
+                    Ok(()) => format!("Restarted {}", container_id),
+                    Err(e) => format!("Failed to restart {}: {}", container_id, e),
+                };
+                dashboard.set_message(message);
+                refreshed = None;
+            }
+            Some(Action::Inspect(container_id)) => match runtime.inspect(&container_id).await {
+                Ok(details) => dashboard.show_details(serde_json::to_string_pretty(&details)?),
+                Err(e) => dashboard.set_message(format!("Failed to inspect {}: {}", container_id, e)),
+            },
+            None => {}
+        }
+    }
+}
+
+// Stops a container and starts it again in a process of its own, in its
+// own process group so it outlives `top` and the terminal. Its output
+// still goes to its log.
+async fn restart_detached(runtime: &mut WasmRuntime, container_id: &str) -> Result<()> {
+    runtime.stop(container_id).await?;
+    std::process::Command::new(std::env::current_exe()?)
+        .args(["start", container_id])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .process_group(0)
+        .spawn()?;
+    
+    Ok(())
+}
+
+async fn container_stats(container_ids: Vec<String>) -> Result<()> {
     let runtime = WasmRuntime::new()?;
-    let containers = runtime.list_containers(all).await?;
     
-    println!("CONTAINER ID\tIMAGE\tSTATUS");
+    println!("CONTAINER ID\tNET RX / TX\tRX RATE\tTX RATE\tLIMIT");
+    for container_id in container_ids {
+        let stats = runtime.stats(&container_id).await?;
+        println!(
+            "{}\t{} / {}\t{}/s\t{}/s\t{}",
+            container_id,
+            format_bytes(stats.rx_bytes),
+            format_bytes(stats.tx_bytes),
+            format_bytes(stats.rx_bytes_per_sec),
+            format_bytes(stats.tx_bytes_per_sec),
+            stats.limit.map_or("-".to_string(), |rate| rate.to_string()),
+        );
+    }
+    
+    Ok(())
+}
+
+// Prints the script that has `shell` complete commands, flags and the
+// names of containers and cached images by calling back into this binary.
+fn print_completions(shell: &str) -> Result<()> {
+    let shells = Shells::builtins();
+    let completer = shells.completer(shell)
+        .ok_or_else(|| anyhow!("Unsupported shell: {}", shell))?;
+    let bin = std::env::current_exe()?;
+    let mut stdout = std::io::stdout();
+    completer.write_registration(COMPLETE_VAR, "wasm-container", "wasm-container", &bin.to_string_lossy(), &mut stdout)?;
+    
+    Ok(())
+}
+
+// Names and IDs of known containers, described by image and status.
+fn container_candidates() -> Vec<CompletionCandidate> {
+    let containers = state::state_dir()
+        .and_then(|dir| ContainerStore::new(&dir))
+        .and_then(|store| store.list());
+    let Ok(containers) = containers else {
+        return Vec::new();
+    };
+    
+    let mut candidates = Vec::new();
     for container in containers {
-        println!("{}\t{}\t{}", container.id, container.image, container.status);
+        let help = format!("{} ({})", container.image, container.status);
+        if let Some(name) = &container.name {
+            candidates.push(CompletionCandidate::new(name).help(Some(help.clone().into())));
+        }
+        candidates.push(CompletionCandidate::new(container.id).help(Some(help.into())));
+    }
+    candidates
+}
+
+fn image_candidates() -> Vec<CompletionCandidate> {
+    ImageManager::new()
+        .and_then(|images| images.list())
+        .unwrap_or_default()
+        .into_iter()
+        .map(CompletionCandidate::new)
+        .collect()
+}
+
+fn print_version() {
+    println!("Version:        {}", env!("CARGO_PKG_VERSION"));
+    println!("Wasmtime:       {}", WASMTIME_VERSION);
+    println!("Wasm features:  {}", wasm_features().join(", "));
+    println!("OS/Arch:        {}/{}", std::env::consts::OS, std::env::consts::ARCH);
+}
+
+// Fails when any check found an error, so scripts can gate on it.
+fn doctor() -> Result<()> {
+    let findings = Doctor::new(&RuntimeConfig::load()?)?.run();
+    for finding in &findings {
+        println!("{:<8}{:<13}{}", finding.severity, finding.check, finding.message);
+        if let Some(fix) = &finding.fix {
+            println!("{:21}fix: {}", "", fix);
+        }
+    }
+    
+    let count = |severity| findings.iter().filter(|finding| finding.severity == severity).count();
+    let (errors, warnings) = (count(Severity::Error), count(Severity::Warning));
+    if errors > 0 {
+        return Err(anyhow!("{} and {} found", plural(errors, "error"), plural(warnings, "warning")));
     }
+    println!();
+    println!("{} found", plural(warnings, "warning"));
+    Ok(())
+}
+
+async fn print_info() -> Result<()> {
+    let runtime = WasmRuntime::new()?;
+    let info = runtime.info().await?;
+    let images = ImageManager::new()?.list()?;
+    
+    let drivers: Vec<String> = info.storage_drivers.iter()
+        .map(|(backend, driver)| format!("{} ({})", driver, backend))
+        .collect();
+    println!("Storage drivers:  {}", drivers.join(", "));
+    println!("State dir:        {}", info.state_dir.display());
+    println!("Scratch dir:      {}", info.scratch_dir.display());
+    println!("Image cache:      {}", state::cache_dir()?.display());
+    println!("Containers:       {} ({} running, {} stopped)", info.containers, info.running, info.containers - info.running);
+    println!("Images:           {}", images.len());
+    println!("Default network:  {} ({})", info.default_network.name, info.default_network.subnet);
+    println!("Networks:         {}", info.networks);
+    println!("Max memory:       {}", format_bytes(info.max_memory));
+    println!("Max CPUs:         {}", info.max_cpus);
+    println!("OS/Arch:          {}/{}", std::env::consts::OS, std::env::consts::ARCH);
     
     Ok(())
 }
 
+// Ends a failed `run` or `start` with docker's exit code for the failure.
+// A guest that exited with a code of its own has already said why.
+fn exit_with(error: anyhow::Error) -> ! {
+    let exited = error.is::<I32Exit>() || matches!(error.downcast_ref::<Error>(), Some(Error::Exit { .. }));
+    if !exited {
+        eprintln!("Error: {:?}", error);
+    }
+    let _ = std::io::stdout().flush();
+    std::process::exit(exit_code(&error))
+}
+
+async fn start_container(container_id: String, ingress: IngressArgs) -> Result<()> {
+    let mut runtime = WasmRuntime::new()?;
+    adopt_activated_sockets(&runtime).await?;
+    runtime.join_mesh().await?;
+    start_ingress(&runtime, ingress).await?;
+    runtime.start(&container_id).await?;
+    Ok(())
+}
+
 async fn stop_container(container_id: String) -> Result<()> {
     let mut runtime = WasmRuntime::new()?;
     runtime.stop(&container_id).await?;
     info!("Container {} stopped", container_id);
     Ok(())
-}
\ No newline at end of file
+}
+
+async fn remove_containers(container_ids: Vec<String>, force: bool) -> Result<()> {
+    if !force && !confirm(&format!("Remove {}?", plural(container_ids.len(), "container")))? {
+        return Ok(());
+    }
+    
+    let mut runtime = WasmRuntime::new()?;
+    for container_id in container_ids {
+        runtime.remove(&container_id, force).await?;
+        println!("{}", container_id);
+    }
+    Ok(())
+}
+
+async fn diff_container(container_id: String) -> Result<()> {
+    let runtime = WasmRuntime::new()?;
+    for change in runtime.diff(&container_id).await? {
+        println!("{}", change);
+    }
+    Ok(())
+}
+
+async fn export_container(container_id: String, output: Option<PathBuf>) -> Result<()> {
+    let runtime = WasmRuntime::new()?;
+    
+    match output {
+        Some(path) => {
+            let file = std::fs::File::create(&path)?;
+            runtime.export(&container_id, file).await?.sync_all()?;
+            info!("Exported container {} to {}", container_id, path.display());
+        }
+        None => {
+            runtime.export(&container_id, std::io::stdout().lock()).await?.flush()?;
+        }
+    }
+    
+    Ok(())
+}
+
+// A running container joins the network in its own process, which records
+// its new addresses once it has.
+async fn wait_for_network(runtime: &WasmRuntime, network: &str, container_id: &str) -> Result<()> {
+    let started = tokio::time::Instant::now();
+    loop {
+        let info = runtime.inspect(container_id).await?.info;
+        if let Some(addresses) = info.addresses.get(network) {
+            let addresses: Vec<String> = addresses.iter().map(IpAddr::to_string).collect();
+            println!("{} is on network {} at {}", container_id, network, addresses.join(", "));
+            return Ok(());
+        }
+        if info.status != "running" {
+            return Ok(());
+        }
+        if started.elapsed() > NETWORK_JOIN_TIMEOUT {
+            return Err(anyhow!("Container {} didn't join network {}; see its logs", container_id, network));
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+// Captures until Ctrl-C or the container stopping. The run picks the
+// request up within a second, and may write for up to a second after it is
+// withdrawn; every packet is written whole, so the file stays readable.
+async fn netdump(container_id: String, output: PathBuf) -> Result<()> {
+    let runtime = WasmRuntime::new()?;
+    
+    // Removed first, so the run creating it shows the capture has started.
+    match std::fs::remove_file(&output) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+    runtime.start_capture(&container_id, &output).await?;
+    
+    let started = tokio::time::Instant::now();
+    while !output.exists() {
+        if started.elapsed() > NETDUMP_START_TIMEOUT {
+            runtime.stop_capture(&container_id).await?;
+            return Err(anyhow!("Container {} didn't start capturing; is it on a network?", container_id));
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    eprintln!("Capturing traffic of {} to {}, press Ctrl-C to stop", container_id, output.display());
+    
+    loop {
+        tokio::select! {
+            signal = tokio::signal::ctrl_c() => {
+                signal?;
+                break;
+            }
+            _ = tokio::time::sleep(Duration::from_secs(1)) => {
+                if runtime.inspect(&container_id).await?.info.status != "running" {
+                    break;
+                }
+            }
+        }
+    }
+    runtime.stop_capture(&container_id).await?;
+    
+    Ok(())
+}
+
+async fn copy_files(src: String, dest: String) -> Result<()> {
+    let runtime = WasmRuntime::new()?;
+    
+    match (split_container_path(&src), split_container_path(&dest)) {
+        (Some((container_id, container_path)), None) => {
+            runtime.copy_from_container(container_id, Path::new(container_path), Path::new(&dest)).await?;
+        }
+        (None, Some((container_id, container_path))) => {
+            runtime.copy_to_container(container_id, Path::new(&src), Path::new(container_path)).await?;
+        }
+        _ => {
+            return Err(anyhow!("Exactly one of source and destination must be CONTAINER:PATH"));
+        }
+    }
+    
+    Ok(())
+}
+
+fn split_container_path(arg: &str) -> Option<(&str, &str)> {
+    if arg.starts_with('/') || arg.starts_with('.') {
+        return None;
+    }
+    
+    arg.split_once(':')
+}
+
+async fn compose_command(file: Option<PathBuf>, project_name: Option<String>, command: ComposeCommands) -> Result<()> {
+    let state_dir = state::state_dir()?;
+    let load = || {
+        let path = match &file {
+            Some(path) => path.clone(),
+            None => Compose::find(&std::env::current_dir()?)?,
+        };
+        Compose::load(&path, project_name.as_deref())
+    };
+    
+    match command {
+        ComposeCommands::Up => {
+            let compose = load()?;
+            let image_manager = ImageManager::new()?;
+            let mut images = HashMap::new();
+            for (name, service) in compose.services() {
+                info!("Pulling image {} for service {}", service.image, name);
+                images.insert(name.clone(), image_manager.get_or_pull(&service.image).await?);
+            }
+            tokio::select! {
+                up = compose.up(&state_dir, &images) => up?,
+                signal = tokio::signal::ctrl_c() => signal?,
+            }
+        }
+        ComposeCommands::Down => {
+            // Without a compose file the project can still be named with -p.
+            let project = match (&project_name, &file) {
+                (Some(project), None) => project.clone(),
+                _ => load()?.project().to_string(),
+            };
+            Compose::down(&state_dir, &project).await?;
+            println!("{}", project);
+        }
+    }
+    
+    Ok(())
+}
+
+async fn play_command(command: PlayCommands) -> Result<()> {
+    let state_dir = state::state_dir()?;
+    
+    match command {
+        PlayCommands::Kube { file, configmaps, down } => {
+            let pods = KubePod::load(&file, &configmaps, &state_dir)?;
+            if down {
+                for pod in &pods {
+                    pod.down(&state_dir).await?;
+                    println!("{}", pod.compose().project());
+                }
+                return Ok(());
+            }
+            
+            let image_manager = ImageManager::new()?;
+            let mut ups = JoinSet::new();
+            for pod in pods {
+                let mut images = HashMap::new();
+                for (name, service) in pod.compose().services() {
+                    info!("Pulling image {} for container {}", service.image, name);
+                    images.insert(name.clone(), image_manager.get_or_pull(&service.image).await?);
+                }
+                let state_dir = state_dir.clone();
+                ups.spawn(async move { pod.up(&state_dir, &images).await });
+            }
+            tokio::select! {
+                up = async {
+                    while let Some(up) = ups.join_next().await {
+                        up??;
+                    }
+                    anyhow::Ok(())
+                } => up?,
+                signal = tokio::signal::ctrl_c() => signal?,
+            }
+        }
+    }
+    
+    Ok(())
+}
+
+fn login(registry: String, username: Option<String>, password: Option<String>, password_stdin: bool) -> Result<()> {
+    let username = match username {
+        Some(username) => username,
+        None if password_stdin => return Err(anyhow!("--password-stdin needs --username")),
+        None => prompt("Username: ", true)?,
+    };
+    let secret = match password {
+        Some(password) => password,
+        None if password_stdin => {
+            let mut password = String::new();
+            std::io::stdin().read_to_string(&mut password)?;
+            password.trim_end_matches(['\r', '\n']).to_string()
+        }
+        None => prompt("Password: ", false)?,
+    };
+    if username.is_empty() || secret.is_empty() {
+        return Err(anyhow!("Username and password must not be empty"));
+    }
+    
+    // Pulls are not authenticated against the registry yet, so the
+    // credentials are stored unchecked.
+    CredentialStore::new()?.login(&registry, &Credentials { username, secret })?;
+    println!("Login Succeeded");
+    Ok(())
+}
+
+fn logout(registry: String, all: bool) -> Result<()> {
+    let store = CredentialStore::new()?;
+    let registries = if all { store.registries()? } else { vec![registry] };
+    for registry in registries {
+        store.logout(&registry)?;
+        println!("Removed login credentials for {}", registry);
+    }
+    Ok(())
+}
+
+// Asks before a destructive command goes ahead. Only a terminal is asked;
+// without one, e.g. in scripts, the command fails unless `--force` skipped
+// asking.
+fn confirm(question: &str) -> Result<bool> {
+    let stdin = std::io::stdin();
+    if !stdin.is_terminal() {
+        return Err(anyhow!("Not a terminal to confirm on; pass --force to go ahead without asking"));
+    }
+    eprint!("{} [y/N] ", question);
+    std::io::stderr().flush()?;
+    let mut answer = String::new();
+    stdin.read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+fn plural(count: usize, noun: &str) -> String {
+    format!("{} {}{}", count, noun, if count == 1 { "" } else { "s" })
+}
+
+// Reads a line from the terminal after printing `prompt`, without echoing
+// it unless `echo`.
+fn prompt(prompt: &str, echo: bool) -> Result<String> {
+    let stdin = std::io::stdin();
+    if !stdin.is_terminal() {
+        return Err(anyhow!("Not a terminal to ask on; pass the credentials as options"));
+    }
+    eprint!("{}", prompt);
+    std::io::stderr().flush()?;
+    
+    // The terminal's settings are only put back if they could be read, and
+    // Ctrl-C puts them back before it ends the process.
+    let fd = libc::STDIN_FILENO;
+    let mut saved = None;
+    let mut interrupt = libc::SIG_DFL;
+    if !echo {
+        let mut termios = unsafe { std::mem::zeroed::<libc::termios>() };
+        if unsafe { libc::tcgetattr(fd, &mut termios) } == 0 {
+            let termios = *PROMPT_TERMIOS.get_or_init(|| termios);
+            let mut quiet = termios;
+            quiet.c_lflag &= !libc::ECHO;
+            interrupt = unsafe { libc::signal(libc::SIGINT, restore_terminal as *const () as libc::sighandler_t) };
+            unsafe { libc::tcsetattr(fd, libc::TCSANOW, &quiet) };
+            saved = Some(termios);
+        }
+    }
+    let mut line = String::new();
+    let read = stdin.read_line(&mut line);
+    if let Some(termios) = saved {
+        unsafe {
+            libc::tcsetattr(fd, libc::TCSANOW, &termios);
+            libc::signal(libc::SIGINT, interrupt);
+        }
+    }
+    if !echo {
+        eprintln!();
+    }
+    read?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+// The terminal's settings before `prompt` turned echo off.
+static PROMPT_TERMIOS: OnceLock<libc::termios> = OnceLock::new();
+
+// Turns echo back on when Ctrl-C interrupts a prompt, then lets SIGINT end
+// the process as it would have.
+extern "C" fn restore_terminal(signal: libc::c_int) {
+    if let Some(termios) = PROMPT_TERMIOS.get() {
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, termios) };
+    }
+    unsafe {
+        libc::signal(signal, libc::SIG_DFL);
+        libc::raise(signal);
+    }
+}
+
+async fn apply_app(file: PathBuf, project_name: Option<String>) -> Result<()> {
+    let state_dir = state::state_dir()?;
+    let app = App::load(&file, project_name.as_deref())?;
+    let images = app.images(&ImageManager::new()?).await?;
+    tokio::select! {
+        apply = app.apply(&state_dir, &images) => apply?,
+        signal = tokio::signal::ctrl_c() => signal?,
+    }
+    Ok(())
+}
+
+async fn destroy_app(file: PathBuf, project_name: Option<String>, volumes: bool) -> Result<()> {
+    let app = App::load(&file, project_name.as_deref())?;
+    app.destroy(&state::state_dir()?, volumes).await?;
+    println!("{}", app.project());
+    Ok(())
+}
+
+async fn app_status(file: PathBuf, project_name: Option<String>) -> Result<()> {
+    let app = App::load(&file, project_name.as_deref())?;
+    let mut lines = vec!["COMPONENT\tCONTAINER\tSTATUS\tPORTS".to_string()];
+    for status in app.status(&state::state_dir()?).await? {
+        lines.push(format!("{}\t{}\t{}\t{}", status.component, status.container, status.status, status.ports));
+    }
+    print!("{}", align_columns(&lines));
+    Ok(())
+}
+
+async fn generate_command(command: GenerateCommands) -> Result<()> {
+    match command {
+        GenerateCommands::Systemd { target, project_name, restart_policy, restart_sec, requires, wants, files } => {
+            let options = UnitOptions {
+                executable: std::env::current_exe()?,
+                restart: restart_policy,
+                restart_sec,
+                requires,
+                wants,
+            };
+            // A path to an existing file is a compose file; anything else
+            // names a container.
+            let path = Path::new(&target);
+            let unit = if path.is_file() {
+                let file = std::fs::canonicalize(path)?;
+                let compose = Compose::load(&file, project_name.as_deref())?;
+                Unit::compose(&file, compose.project(), &options)
+            } else {
+                let store = ContainerStore::new(&state::state_dir()?)?;
+                Unit::container(&store.load(&store.resolve(&target)?)?, &options)
+            };
+            
+            if files {
+                std::fs::write(&unit.name, unit.to_string())?;
+                println!("{}", std::env::current_dir()?.join(&unit.name).display());
+            } else {
+                print!("{}", unit);
+            }
+        }
+    }
+    
+    Ok(())
+}
+
+async fn snapshot_command(command: SnapshotCommands) -> Result<()> {
+    let runtime = WasmRuntime::new()?;
+    
+    match command {
+        SnapshotCommands::Create { container_id, name } => {
+            runtime.create_snapshot(&container_id, &name).await?;
+            println!("{}", name);
+        }
+        SnapshotCommands::Restore { container_id, name } => {
+            runtime.restore_snapshot(&container_id, &name).await?;
+            println!("{}", name);
+        }
+        SnapshotCommands::Ls { container_id } => {
+            for name in runtime.list_snapshots(&container_id).await? {
+                println!("{}", name);
+            }
+        }
+    }
+    
+    Ok(())
+}
+
+fn image_command(command: ImageCommands) -> Result<()> {
+    match command {
+        ImageCommands::Ls { format, quiet } => {
+            list_images(format, quiet)?;
+        }
+        ImageCommands::Rm { images, force } => {
+            remove_images(images, force)?;
+        }
+    }
+    
+    Ok(())
+}
+
+fn remove_images(images: Vec<String>, force: bool) -> Result<()> {
+    if !force && !confirm(&format!("Remove {}?", plural(images.len(), "image")))? {
+        return Ok(());
+    }
+    
+    let in_use = WasmRuntime::new()?.images_in_use()?;
+    let image_manager = ImageManager::new()?;
+    for image in images {
+        let (reference, _) = image_manager.remove(&image, &in_use)?;
+        println!("{}", reference);
+    }
+    Ok(())
+}
+
+fn volume_command(command: VolumeCommands) -> Result<()> {
+    let volume_manager = VolumeManager::new()?;
+    
+    match command {
+        VolumeCommands::Create { name } => {
+            let volume = volume_manager.create(&name)?;
+            println!("{}", volume.name);
+        }
+        VolumeCommands::Ls { quiet: true, .. } => {
+            for volume in volume_manager.list()? {
+                println!("{}", volume.name);
+            }
+        }
+        VolumeCommands::Ls { format: Some(format), .. } => {
+            let volumes: Vec<VolumeSummary> = volume_manager.list()?.iter().map(VolumeSummary::from).collect();
+            print!("{}", format.render(&volumes)?);
+        }
+        VolumeCommands::Ls { format: None, .. } => {
+            println!("VOLUME NAME\tMOUNTPOINT");
+            for volume in volume_manager.list()? {
+                println!("{}\t{}", volume.name, volume.mountpoint.display());
+            }
+        }
+        VolumeCommands::Rm { names, force } => {
+            if !force && !confirm(&format!("Remove {} and the data in it?", plural(names.len(), "volume")))? {
+                return Ok(());
+            }
+            for name in names {
+                volume_manager.remove(&name)?;
+                println!("{}", name);
+            }
+        }
+        VolumeCommands::Inspect { names, format } => {
+            let volumes = names
+                .iter()
+                .map(|name| volume_manager.inspect(name))
+                .collect::<Result<Vec<_>, _>>()?;
+            print!("{}", format.render(&volumes)?);
+        }
+    }
+    
+    Ok(())
+}
+
+async fn system_command(command: SystemCommands) -> Result<()> {
+    match command {
+        SystemCommands::Prune { all, force } => {
+            if !force {
+                let images = if all { "all images without a container" } else { "incomplete image pulls" };
+                eprintln!("This will remove all stopped containers, networks without containers, {} and data left behind by interrupted runs.", images);
+                if !confirm("Continue?")? {
+                    return Ok(());
+                }
+            }
+            
+            let mut runtime = WasmRuntime::new()?;
+            let report = runtime.prune().await?;
+            let images = ImageManager::new()?;
+            let (removed_images, image_bytes) = images.prune(all, &runtime.images_in_use()?, ORPHAN_GRACE_PERIOD)?;
+            
+            for (kind, removed) in [("containers", &report.containers), ("networks", &report.networks), ("images", &removed_images)] {
+                if !removed.is_empty() {
+                    println!("Deleted {}:", kind);
+                    for item in removed {
+                        println!("{}", item);
+                    }
+                    println!();
+                }
+            }
+            if !report.orphans.is_empty() {
+                println!("Removed {} leftovers of interrupted runs\n", report.orphans.len());
+            }
+            println!("Total reclaimed space: {}", format_bytes(report.reclaimed + image_bytes));
+        }
+    }
+    
+    Ok(())
+}
+
+async fn network_command(command: NetworkCommands) -> Result<()> {
+    let runtime = WasmRuntime::new()?;
+    let network_manager = runtime.network_manager();
+    
+    match command {
+        NetworkCommands::Create { name, subnet, gateway, domain, internal, rule } => {
+            let subnets: Vec<&str> = subnet.iter().map(String::as_str).collect();
+            let network = network_manager.create_network(&name, &subnets, &gateway, domain.as_deref()).await?;
+            let policy = NetworkPolicy { internal, rules: rule };
+            if !policy.is_empty() {
+                network_manager.set_network_policy(&name, policy).await?;
+            }
+            println!("{}", network.name);
+        }
+        NetworkCommands::Ls { quiet: true, .. } => {
+            for network in network_manager.list_networks().await? {
+                println!("{}", network.name);
+            }
+        }
+        NetworkCommands::Ls { format: Some(format), .. } => {
+            let networks: Vec<NetworkSummary> = network_manager.list_networks().await?.iter().map(NetworkSummary::from).collect();
+            print!("{}", format.render(&networks)?);
+        }
+        NetworkCommands::Ls { format: None, .. } => {
+            println!("NETWORK NAME\tSUBNET\tGATEWAY");
+            for network in network_manager.list_networks().await? {
+                let subnets: Vec<&str> = network.subnets().map(|(subnet, _)| subnet).collect();
+                let gateways: Vec<String> = network.subnets().map(|(_, gateway)| gateway.to_string()).collect();
+                println!("{}\t{}\t{}", network.name, subnets.join(","), gateways.join(","));
+            }
+        }
+        NetworkCommands::Rm { names, force } => {
+            if !force && !confirm(&format!("Remove {}?", plural(names.len(), "network")))? {
+                return Ok(());
+            }
+            for name in names {
+                runtime.remove_network(&name).await?;
+                println!("{}", name);
+            }
+        }
+        NetworkCommands::Inspect { names, format } => {
+            let mut networks = Vec::new();
+            for name in &names {
+                networks.push(runtime.inspect_network(name).await?);
+            }
+            print!("{}", format.render(&networks)?);
+        }
+        NetworkCommands::Connect { network, container_id } => {
+            runtime.connect_network(&network, &container_id).await?;
+            if runtime.inspect(&container_id).await?.info.status == "running" {
+                wait_for_network(&runtime, &network, &container_id).await?;
+            }
+        }
+        NetworkCommands::Disconnect { network, container_id } => {
+            runtime.disconnect_network(&network, &container_id).await?;
+        }
+    }
+    
+    Ok(())
+}
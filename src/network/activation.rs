@@ -0,0 +1,89 @@
+use anyhow::{Result, anyhow};
+use std::env;
+use std::net::{TcpListener, UdpSocket};
+use std::os::fd::{FromRawFd, RawFd};
+
+// systemd passes sockets from the first descriptor after stdin, stdout and
+// stderr on.
+const LISTEN_FDS_START: RawFd = 3;
+
+// A listening socket systemd bound on the runtime's behalf.
+#[derive(Debug)]
+pub enum ActivatedSocket {
+    Tcp(TcpListener),
+    Udp(UdpSocket),
+}
+
+impl ActivatedSocket {
+    pub fn port(&self) -> Result<u16> {
+        let addr = match self {
+            Self::Tcp(listener) => listener.local_addr()?,
+            Self::Udp(socket) => socket.local_addr()?,
+        };
+        Ok(addr.port())
+    }
+    
+    pub fn protocol(&self) -> &'static str {
+        match self {
+            Self::Tcp(_) => "tcp",
+            Self::Udp(_) => "udp",
+        }
+    }
+}
+
+// Takes the sockets systemd passed to this process, as sd_listen_fds(3)
+// does: only when LISTEN_PID names this process, which keeps children that
+// inherit the variables from taking them too. The variables are left set,
+// since changing the environment isn't sound once other threads run, so
+// this is called once per process. Empty when the process wasn't socket
+// activated.
+pub fn listen_fds() -> Result<Vec<ActivatedSocket>> {
+    let pid = env::var("LISTEN_PID").ok();
+    let fds = env::var("LISTEN_FDS").ok();
+    
+    let (Some(pid), Some(fds)) = (pid, fds) else {
+        return Ok(Vec::new());
+    };
+    if pid.parse::<u32>().ok() != Some(std::process::id()) {
+        return Ok(Vec::new());
+    }
+    let count: RawFd = fds.parse()
+        .map_err(|_| anyhow!("Invalid LISTEN_FDS: {:?}", fds))?;
+    
+    (LISTEN_FDS_START..LISTEN_FDS_START + count)
+        .map(adopt)
+        .collect()
+}
+
+fn adopt(fd: RawFd) -> Result<ActivatedSocket> {
+    let mut socket_type: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    let result = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_TYPE,
+            (&mut socket_type as *mut libc::c_int).cast(),
+            &mut len,
+        )
+    };
+    if result != 0 {
+        return Err(anyhow!("Activated descriptor {} is not a socket: {}", fd, std::io::Error::last_os_error()));
+    }
+    
+    // The descriptor was handed to this process and nothing else owns it.
+    let socket = match socket_type {
+        libc::SOCK_STREAM => ActivatedSocket::Tcp(unsafe { TcpListener::from_raw_fd(fd) }),
+        libc::SOCK_DGRAM => ActivatedSocket::Udp(unsafe { UdpSocket::from_raw_fd(fd) }),
+        _ => return Err(anyhow!("Activated descriptor {} is neither a stream nor a datagram socket", fd)),
+    };
+    match &socket {
+        ActivatedSocket::Tcp(listener) => listener.set_nonblocking(true)?,
+        ActivatedSocket::Udp(socket) => socket.set_nonblocking(true)?,
+    }
+    // Unix sockets have no port to publish.
+    socket.port()
+        .map_err(|_| anyhow!("Activated descriptor {} is not an IP socket", fd))?;
+    
+    Ok(socket)
+}
@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU16, Ordering};
+use tokio::io::{AsyncRead, AsyncWrite, DuplexStream};
+use tokio::sync::mpsc;
+
+// How much a connection buffers in each direction before writes wait for
+// the peer to read.
+const STREAM_BUFFER_SIZE: usize = 64 * 1024;
+
+// Source ports of outgoing connections come from the IANA dynamic range.
+const EPHEMERAL_PORT_START: u16 = 49152;
+
+type Incoming = mpsc::UnboundedSender<(DuplexStream, SocketAddr)>;
+
+// Connections between containers that never touch the host's network: a
+// connection is a pair of in-memory streams, handed to whoever listens on
+// the container and port it was made to. Listeners are keyed by container
+// rather than address, as a container on several networks takes
+// connections on all of its addresses.
+#[derive(Debug, Clone)]
+pub struct Bridge {
+    listeners: Arc<Mutex<HashMap<(String, u16), Incoming>>>,
+    next_port: Arc<AtomicU16>,
+}
+
+impl Default for Bridge {
+    fn default() -> Self {
+        Self {
+            listeners: Arc::new(Mutex::new(HashMap::new())),
+            next_port: Arc::new(AtomicU16::new(EPHEMERAL_PORT_START)),
+        }
+    }
+}
+
+impl Bridge {
+    pub fn listen(&self, container_id: &str, port: u16) -> io::Result<BridgeListener> {
+        let key = (container_id.to_string(), port);
+        let mut listeners = self.listeners.lock().unwrap_or_else(|e| e.into_inner());
+        if listeners.contains_key(&key) {
+            return Err(io::Error::from(io::ErrorKind::AddrInUse));
+        }
+        
+        let (incoming, receiver) = mpsc::unbounded_channel();
+        listeners.insert(key.clone(), incoming);
+        
+        Ok(BridgeListener {
+            key,
+            incoming: receiver,
+            listeners: self.listeners.clone(),
+        })
+    }
+    
+    // Connects from `from` to whoever listens on `port` of `container_id`.
+    // The connection is queued for the listener to accept, so writes can
+    // start right away.
+    pub fn connect(&self, from: IpAddr, container_id: &str, port: u16) -> io::Result<DuplexStream> {
+        let listeners = self.listeners.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(incoming) = listeners.get(&(container_id.to_string(), port)) else {
+            return Err(io::Error::from(io::ErrorKind::ConnectionRefused));
+        };
+        
+        let source_port = self.next_port
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |port| {
+                Some(port.checked_add(1).unwrap_or(EPHEMERAL_PORT_START))
+            })
+            .unwrap_or(EPHEMERAL_PORT_START);
+        let (local, remote) = tokio::io::duplex(STREAM_BUFFER_SIZE);
+        incoming.send((remote, SocketAddr::new(from, source_port)))
+            .map_err(|_| io::Error::from(io::ErrorKind::ConnectionRefused))?;
+        
+        Ok(local)
+    }
+}
+
+#[derive(Debug)]
+pub struct BridgeListener {
+    key: (String, u16),
+    incoming: mpsc::UnboundedReceiver<(DuplexStream, SocketAddr)>,
+    listeners: Arc<Mutex<HashMap<(String, u16), Incoming>>>,
+}
+
+impl BridgeListener {
+    pub fn port(&self) -> u16 {
+        self.key.1
+    }
+    
+    // Waits for the next connection, returning it with the peer's address.
+    pub async fn accept(&mut self) -> Option<(DuplexStream, SocketAddr)> {
+        self.incoming.recv().await
+    }
+}
+
+// Connections not accepted yet are closed, and new ones refused.
+impl Drop for BridgeListener {
+    fn drop(&mut self) {
+        self.listeners.lock().unwrap_or_else(|e| e.into_inner()).remove(&self.key);
+    }
+}
+
+// Relays a stream from elsewhere, such as a host connection, into an
+// in-memory one for as long as both ends stay open.
+pub fn splice<S>(mut stream: S) -> DuplexStream
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (local, mut remote) = tokio::io::duplex(STREAM_BUFFER_SIZE);
+    tokio::spawn(async move {
+        let _ = tokio::io::copy_bidirectional(&mut remote, &mut stream).await;
+    });
+    local
+}
@@ -0,0 +1,298 @@
+use std::fs::File;
+use std::io::{self, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::debug;
+
+const PCAP_MAGIC: u32 = 0xa1b2_c3d4;
+const LINKTYPE_ETHERNET: u32 = 1;
+const SNAPLEN: u32 = 65535;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const PROTO_TCP: u8 = 6;
+const PROTO_UDP: u8 = 17;
+
+const TCP_FIN: u8 = 0x01;
+const TCP_SYN: u8 = 0x02;
+const TCP_PSH: u8 = 0x08;
+const TCP_ACK: u8 = 0x10;
+
+// The most a synthesized IPv4 packet can carry after its headers.
+const MAX_PAYLOAD: usize = 65535 - 20 - 20;
+
+// Records what a container's relays carry as a pcap file, for Wireshark and
+// tcpdump. Relays see byte streams rather than packets, so each chunk they
+// pass on becomes one packet between the client and the container's own
+// address, with Ethernet, IP and TCP or UDP headers made up around it.
+#[derive(Debug)]
+pub struct Capture {
+    file: Mutex<File>,
+    address: IpAddr,
+    ip_id: AtomicU16,
+}
+
+impl Capture {
+    // Creates `path`, truncating it, and writes the pcap header. `address`
+    // is the container's side of every packet.
+    pub fn create(path: &Path, address: IpAddr) -> io::Result<Self> {
+        let mut file = File::create(path)?;
+        let mut header = Vec::with_capacity(24);
+        header.extend_from_slice(&PCAP_MAGIC.to_le_bytes());
+        header.extend_from_slice(&2u16.to_le_bytes());
+        header.extend_from_slice(&4u16.to_le_bytes());
+        header.extend_from_slice(&0i32.to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes());
+        header.extend_from_slice(&SNAPLEN.to_le_bytes());
+        header.extend_from_slice(&LINKTYPE_ETHERNET.to_le_bytes());
+        file.write_all(&header)?;
+        
+        Ok(Self {
+            file: Mutex::new(file),
+            address,
+            ip_id: AtomicU16::new(0),
+        })
+    }
+    
+    // A datagram between `client` and the container's `port`.
+    pub fn udp(&self, client: SocketAddr, port: u16, inbound: bool, payload: &[u8]) {
+        let (client, container) = self.endpoints(client, port);
+        let (from, to) = if inbound { (client, container) } else { (container, client) };
+        let payload = &payload[..payload.len().min(MAX_PAYLOAD)];
+        
+        let mut segment = Vec::with_capacity(8 + payload.len());
+        segment.extend_from_slice(&from.port().to_be_bytes());
+        segment.extend_from_slice(&to.port().to_be_bytes());
+        segment.extend_from_slice(&((8 + payload.len()) as u16).to_be_bytes());
+        segment.extend_from_slice(&[0, 0]);
+        segment.extend_from_slice(payload);
+        let checksum = transport_checksum(from.ip(), to.ip(), PROTO_UDP, &segment);
+        // Zero means no checksum for UDP, so a computed zero is sent as ones.
+        let checksum = if checksum == 0 { 0xffff } else { checksum };
+        segment[6..8].copy_from_slice(&checksum.to_be_bytes());
+        
+        self.packet(from.ip(), to.ip(), PROTO_UDP, &segment);
+    }
+    
+    // A TCP connection between `client` and the container's `port`,
+    // starting with a handshake.
+    pub fn tcp(self: &Arc<Self>, client: SocketAddr, port: u16) -> TcpFlow {
+        let (client, container) = self.endpoints(client, port);
+        let flow = TcpFlow {
+            capture: self.clone(),
+            client,
+            container,
+            seq: Mutex::new((0, 0)),
+        };
+        flow.segment(true, TCP_SYN, 0, 0, &[]);
+        flow.segment(false, TCP_SYN | TCP_ACK, 0, 1, &[]);
+        *flow.seq.lock().unwrap_or_else(|e| e.into_inner()) = (1, 1);
+        flow.segment(true, TCP_ACK, 1, 1, &[]);
+        flow
+    }
+    
+    // Both ends in the same family, mapping IPv4 into IPv6 where they
+    // differ.
+    fn endpoints(&self, client: SocketAddr, port: u16) -> (SocketAddr, SocketAddr) {
+        let (client_ip, container_ip) = match (client.ip().to_canonical(), self.address) {
+            (IpAddr::V4(client), IpAddr::V6(container)) => (IpAddr::V6(client.to_ipv6_mapped()), IpAddr::V6(container)),
+            (IpAddr::V6(client), IpAddr::V4(container)) => (IpAddr::V6(client), IpAddr::V6(container.to_ipv6_mapped())),
+            addresses => addresses,
+        };
+        (SocketAddr::new(client_ip, client.port()), SocketAddr::new(container_ip, port))
+    }
+    
+    fn packet(&self, from: IpAddr, to: IpAddr, protocol: u8, segment: &[u8]) {
+        let mut frame = Vec::with_capacity(14 + 40 + segment.len());
+        frame.extend_from_slice(&mac(to));
+        frame.extend_from_slice(&mac(from));
+        match (from, to) {
+            (IpAddr::V4(from), IpAddr::V4(to)) => {
+                frame.extend_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+                let mut header = [0u8; 20];
+                header[0] = 0x45;
+                header[2..4].copy_from_slice(&((20 + segment.len()) as u16).to_be_bytes());
+                header[4..6].copy_from_slice(&self.ip_id.fetch_add(1, Ordering::Relaxed).to_be_bytes());
+                // Don't fragment.
+                header[6] = 0x40;
+                header[8] = 64;
+                header[9] = protocol;
+                header[12..16].copy_from_slice(&from.octets());
+                header[16..20].copy_from_slice(&to.octets());
+                let checksum = !fold(sum(&header));
+                header[10..12].copy_from_slice(&checksum.to_be_bytes());
+                frame.extend_from_slice(&header);
+            }
+            (IpAddr::V6(from), IpAddr::V6(to)) => {
+                frame.extend_from_slice(&ETHERTYPE_IPV6.to_be_bytes());
+                frame.extend_from_slice(&[0x60, 0, 0, 0]);
+                frame.extend_from_slice(&(segment.len() as u16).to_be_bytes());
+                frame.extend_from_slice(&[protocol, 64]);
+                frame.extend_from_slice(&from.octets());
+                frame.extend_from_slice(&to.octets());
+            }
+            _ => unreachable!("endpoints are in the same family"),
+        }
+        frame.extend_from_slice(segment);
+        self.record(&frame);
+    }
+    
+    fn record(&self, frame: &[u8]) {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let mut record = Vec::with_capacity(16 + frame.len());
+        record.extend_from_slice(&(now.as_secs() as u32).to_le_bytes());
+        record.extend_from_slice(&now.subsec_micros().to_le_bytes());
+        record.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        record.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        record.extend_from_slice(frame);
+        
+        // Written whole and unbuffered, so the file is readable while the
+        // capture goes on and complete whenever it stops.
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        if let Err(e) = file.write_all(&record) {
+            debug!("Failed to write captured packet: {}", e);
+        }
+    }
+}
+
+// One relayed TCP connection in a capture, keeping the sequence numbers of
+// each side. Dropping it records both sides closing.
+#[derive(Debug)]
+pub struct TcpFlow {
+    capture: Arc<Capture>,
+    client: SocketAddr,
+    container: SocketAddr,
+    // The next sequence number of the client and of the container.
+    seq: Mutex<(u32, u32)>,
+}
+
+impl TcpFlow {
+    pub fn data(&self, inbound: bool, payload: &[u8]) {
+        for chunk in payload.chunks(MAX_PAYLOAD) {
+            let (seq, ack) = self.advance(inbound, chunk.len() as u32);
+            self.segment(inbound, TCP_PSH | TCP_ACK, seq, ack, chunk);
+        }
+    }
+    
+    // Takes `len` from the sender's sequence space, returning the sequence
+    // and acknowledgement numbers of the segment.
+    fn advance(&self, inbound: bool, len: u32) -> (u32, u32) {
+        let mut seq = self.seq.lock().unwrap_or_else(|e| e.into_inner());
+        let (client, container) = &mut *seq;
+        let (sender, receiver) = if inbound { (client, *container) } else { (container, *client) };
+        let sent = *sender;
+        *sender = sender.wrapping_add(len);
+        (sent, receiver)
+    }
+    
+    fn segment(&self, inbound: bool, flags: u8, seq: u32, ack: u32, payload: &[u8]) {
+        let (from, to) = if inbound { (self.client, self.container) } else { (self.container, self.client) };
+        let mut segment = Vec::with_capacity(20 + payload.len());
+        segment.extend_from_slice(&from.port().to_be_bytes());
+        segment.extend_from_slice(&to.port().to_be_bytes());
+        segment.extend_from_slice(&seq.to_be_bytes());
+        segment.extend_from_slice(&ack.to_be_bytes());
+        segment.extend_from_slice(&[5 << 4, flags]);
+        segment.extend_from_slice(&u16::MAX.to_be_bytes());
+        segment.extend_from_slice(&[0, 0, 0, 0]);
+        segment.extend_from_slice(payload);
+        let checksum = transport_checksum(from.ip(), to.ip(), PROTO_TCP, &segment);
+        segment[16..18].copy_from_slice(&checksum.to_be_bytes());
+        
+        self.capture.packet(from.ip(), to.ip(), PROTO_TCP, &segment);
+    }
+}
+
+impl Drop for TcpFlow {
+    fn drop(&mut self) {
+        let (seq, ack) = self.advance(true, 1);
+        self.segment(true, TCP_FIN | TCP_ACK, seq, ack, &[]);
+        let (seq, ack) = self.advance(false, 1);
+        self.segment(false, TCP_FIN | TCP_ACK, seq, ack, &[]);
+    }
+}
+
+// A relayed connection's place in whichever capture is running, so
+// captures started while it is open pick it up from there.
+#[derive(Debug)]
+pub struct TcpTap {
+    client: SocketAddr,
+    port: u16,
+    flow: Mutex<Option<TcpFlow>>,
+}
+
+impl TcpTap {
+    pub fn new(client: SocketAddr, port: u16) -> Self {
+        Self {
+            client,
+            port,
+            flow: Mutex::new(None),
+        }
+    }
+    
+    pub fn record(&self, capture: Option<Arc<Capture>>, inbound: bool, payload: &[u8]) {
+        let mut flow = self.flow.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(capture) = capture else {
+            *flow = None;
+            return;
+        };
+        if !flow.as_ref().is_some_and(|flow| Arc::ptr_eq(&flow.capture, &capture)) {
+            *flow = Some(capture.tcp(self.client, self.port));
+        }
+        if let Some(flow) = flow.as_ref() {
+            flow.data(inbound, payload);
+        }
+    }
+}
+
+// A locally administered MAC address made from the end of an IP address,
+// like Docker's bridge hands out.
+fn mac(ip: IpAddr) -> [u8; 6] {
+    let tail = match ip {
+        IpAddr::V4(ip) => ip.octets(),
+        IpAddr::V6(ip) => {
+            let octets = ip.octets();
+            [octets[12], octets[13], octets[14], octets[15]]
+        }
+    };
+    [0x02, 0x42, tail[0], tail[1], tail[2], tail[3]]
+}
+
+fn transport_checksum(from: IpAddr, to: IpAddr, protocol: u8, segment: &[u8]) -> u16 {
+    let mut pseudo = Vec::with_capacity(40);
+    match (from, to) {
+        (IpAddr::V4(from), IpAddr::V4(to)) => {
+            pseudo.extend_from_slice(&from.octets());
+            pseudo.extend_from_slice(&to.octets());
+            pseudo.extend_from_slice(&[0, protocol]);
+            pseudo.extend_from_slice(&(segment.len() as u16).to_be_bytes());
+        }
+        (from, to) => {
+            for ip in [from, to] {
+                if let IpAddr::V6(ip) = ip {
+                    pseudo.extend_from_slice(&ip.octets());
+                }
+            }
+            pseudo.extend_from_slice(&(segment.len() as u32).to_be_bytes());
+            pseudo.extend_from_slice(&[0, 0, 0, protocol]);
+        }
+    }
+    !fold(sum(&pseudo) + sum(segment))
+}
+
+// The ones' complement sum of big-endian 16-bit words, odd bytes padded.
+fn sum(bytes: &[u8]) -> u32 {
+    bytes.chunks(2)
+        .map(|word| u32::from(word[0]) << 8 | word.get(1).copied().map_or(0, u32::from))
+        .sum()
+}
+
+fn fold(mut sum: u32) -> u16 {
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    sum as u16
+}
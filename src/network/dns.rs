@@ -0,0 +1,289 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::task::{JoinHandle, JoinSet};
+use tracing::debug;
+
+pub const DNS_PORT: u16 = 53;
+
+// The TTL docker's embedded DNS gives container records.
+const RECORD_TTL: u32 = 600;
+const UPSTREAM_TIMEOUT: Duration = Duration::from_secs(2);
+const MAX_MESSAGE_SIZE: usize = 4096;
+const HEADER_LEN: usize = 12;
+
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+
+const RCODE_FORMERR: u8 = 1;
+const RCODE_SERVFAIL: u8 = 2;
+
+// A resolver for one network, like docker's embedded DNS: names of the
+// containers on the network are answered from `records`, anything else is
+// passed on to the upstream nameservers in turn.
+#[derive(Debug)]
+pub struct DnsServer {
+    addr: SocketAddr,
+    records: Arc<RwLock<HashMap<String, Vec<IpAddr>>>>,
+    upstream: Arc<RwLock<Vec<SocketAddr>>>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl DnsServer {
+    pub async fn bind(addr: SocketAddr, upstream: Vec<SocketAddr>) -> Result<Self> {
+        let socket = UdpSocket::bind(addr).await?;
+        let addr = socket.local_addr()?;
+        let records = Arc::new(RwLock::new(HashMap::new()));
+        let upstream = Arc::new(RwLock::new(upstream));
+        let task = tokio::spawn(serve(socket, records.clone(), upstream.clone()));
+        
+        Ok(Self {
+            addr,
+            records,
+            upstream,
+            task: Some(task),
+        })
+    }
+    
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+    
+    // Replaces the names this server answers for. Names are matched
+    // case-insensitively.
+    pub fn set_records(&self, records: HashMap<String, Vec<IpAddr>>) {
+        let records = records.into_iter()
+            .map(|(name, ips)| (name.to_ascii_lowercase(), ips))
+            .collect();
+        *self.records.write().unwrap_or_else(|e| e.into_inner()) = records;
+    }
+    
+    // Containers sharing the network may each bring their own nameservers;
+    // they are all tried, in the order they were added.
+    pub fn add_upstream(&self, servers: &[SocketAddr]) {
+        let mut upstream = self.upstream.write().unwrap_or_else(|e| e.into_inner());
+        for server in servers {
+            if !upstream.contains(server) {
+                upstream.push(*server);
+            }
+        }
+    }
+    
+    // Waits until the server has stopped so its address is free again once
+    // this returns.
+    pub async fn shutdown(mut self) {
+        if let Some(task) = self.task.take() {
+            task.abort();
+            task.await.ok();
+        }
+    }
+}
+
+impl Drop for DnsServer {
+    fn drop(&mut self) {
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+    }
+}
+
+async fn serve(
+    socket: UdpSocket,
+    records: Arc<RwLock<HashMap<String, Vec<IpAddr>>>>,
+    upstream: Arc<RwLock<Vec<SocketAddr>>>,
+) {
+    let socket = Arc::new(socket);
+    let own_addr = socket.local_addr().ok();
+    let mut forwarded = JoinSet::new();
+    let mut buf = vec![0u8; MAX_MESSAGE_SIZE];
+    
+    loop {
+        tokio::select! {
+            received = socket.recv_from(&mut buf) => {
+                let (len, client) = match received {
+                    Ok(received) => received,
+                    Err(e) => {
+                        debug!("Failed to receive DNS query: {}", e);
+                        continue;
+                    }
+                };
+                let query = &buf[..len];
+                
+                let Some(question) = Question::parse(query) else {
+                    if query.len() >= HEADER_LEN {
+                        send_reply(&socket, &reply(query, None, RCODE_FORMERR, &[]), client).await;
+                    }
+                    continue;
+                };
+                
+                let local = records.read().unwrap_or_else(|e| e.into_inner())
+                    .get(&question.name)
+                    .cloned();
+                if let Some(ips) = local.filter(|_| question.class == CLASS_IN) {
+                    let answers: Vec<IpAddr> = ips.into_iter()
+                        .filter(|ip| question.matches(ip))
+                        .collect();
+                    send_reply(&socket, &reply(query, Some(&question), 0, &answers), client).await;
+                    continue;
+                }
+                
+                // Forwarding to ourselves would only loop.
+                let servers: Vec<SocketAddr> = upstream.read().unwrap_or_else(|e| e.into_inner())
+                    .iter()
+                    .filter(|server| Some(**server) != own_addr)
+                    .copied()
+                    .collect();
+                let query = query.to_vec();
+                let socket = socket.clone();
+                forwarded.spawn(async move {
+                    let response = match forward(&query, &servers).await {
+                        Some(response) => response,
+                        None => {
+                            debug!("No upstream nameserver answered for {}", question.name);
+                            reply(&query, Some(&question), RCODE_SERVFAIL, &[])
+                        }
+                    };
+                    send_reply(&socket, &response, client).await;
+                });
+            }
+            Some(_) = forwarded.join_next() => {}
+        }
+    }
+}
+
+async fn send_reply(socket: &UdpSocket, reply: &[u8], client: SocketAddr) {
+    if let Err(e) = socket.send_to(reply, client).await {
+        debug!("Failed to send DNS reply to {}: {}", client, e);
+    }
+}
+
+// Returns the first response from an upstream server that matches the
+// query's ID.
+async fn forward(query: &[u8], servers: &[SocketAddr]) -> Option<Vec<u8>> {
+    for server in servers {
+        let bind = match server {
+            SocketAddr::V4(_) => SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
+            SocketAddr::V6(_) => SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0),
+        };
+        let exchange = async {
+            let socket = UdpSocket::bind(bind).await?;
+            socket.connect(server).await?;
+            socket.send(query).await?;
+            
+            let mut buf = vec![0u8; MAX_MESSAGE_SIZE];
+            loop {
+                let len = socket.recv(&mut buf).await?;
+                if len >= HEADER_LEN && buf[..2] == query[..2] {
+                    buf.truncate(len);
+                    return Ok::<_, std::io::Error>(buf);
+                }
+            }
+        };
+        
+        match tokio::time::timeout(UPSTREAM_TIMEOUT, exchange).await {
+            Ok(Ok(response)) => return Some(response),
+            Ok(Err(e)) => debug!("Failed to query {}: {}", server, e),
+            Err(_) => debug!("Timed out querying {}", server),
+        }
+    }
+    
+    None
+}
+
+// The single question of a standard query.
+#[derive(Debug)]
+struct Question {
+    // Lowercased, without the trailing dot.
+    name: String,
+    qtype: u16,
+    class: u16,
+    // Where the question ends in the query, so it can be echoed back.
+    end: usize,
+}
+
+impl Question {
+    fn parse(query: &[u8]) -> Option<Self> {
+        if query.len() < HEADER_LEN {
+            return None;
+        }
+        // Responses and anything but standard queries aren't answered.
+        if query[2] & 0x80 != 0 || query[2] & 0x78 != 0 {
+            return None;
+        }
+        if u16::from_be_bytes([query[4], query[5]]) != 1 {
+            return None;
+        }
+        
+        let mut labels = Vec::new();
+        let mut pos = HEADER_LEN;
+        loop {
+            let len = *query.get(pos)? as usize;
+            pos += 1;
+            if len == 0 {
+                break;
+            }
+            // Compression pointers have no place in a question.
+            if len & 0xC0 != 0 {
+                return None;
+            }
+            let label = query.get(pos..pos + len)?;
+            labels.push(String::from_utf8_lossy(label).to_ascii_lowercase());
+            pos += len;
+        }
+        
+        let fields = query.get(pos..pos + 4)?;
+        Some(Self {
+            name: labels.join("."),
+            qtype: u16::from_be_bytes([fields[0], fields[1]]),
+            class: u16::from_be_bytes([fields[2], fields[3]]),
+            end: pos + 4,
+        })
+    }
+    
+    fn matches(&self, ip: &IpAddr) -> bool {
+        match self.qtype {
+            TYPE_A => ip.is_ipv4(),
+            TYPE_AAAA => ip.is_ipv6(),
+            TYPE_ANY => true,
+            _ => false,
+        }
+    }
+}
+
+// Builds an authoritative reply to `query`, echoing its question when it
+// could be parsed.
+fn reply(query: &[u8], question: Option<&Question>, rcode: u8, answers: &[IpAddr]) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(&query[..2]);
+    // QR and AA, keeping the query's opcode and RD bit; RA as upstream
+    // servers recurse for us.
+    out.push(0x80 | 0x04 | (query[2] & 0x79));
+    out.push(0x80 | rcode);
+    out.extend_from_slice(&(question.is_some() as u16).to_be_bytes());
+    out.extend_from_slice(&(answers.len() as u16).to_be_bytes());
+    out.extend_from_slice(&[0, 0, 0, 0]);
+    
+    if let Some(question) = question {
+        out.extend_from_slice(&query[HEADER_LEN..question.end]);
+    }
+    for ip in answers {
+        // The name is a pointer to the question's.
+        out.extend_from_slice(&[0xC0, HEADER_LEN as u8]);
+        let (qtype, data) = match ip {
+            IpAddr::V4(ip) => (TYPE_A, ip.octets().to_vec()),
+            IpAddr::V6(ip) => (TYPE_AAAA, ip.octets().to_vec()),
+        };
+        out.extend_from_slice(&qtype.to_be_bytes());
+        out.extend_from_slice(&CLASS_IN.to_be_bytes());
+        out.extend_from_slice(&RECORD_TTL.to_be_bytes());
+        out.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        out.extend_from_slice(&data);
+    }
+    
+    out
+}
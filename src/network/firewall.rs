@@ -0,0 +1,116 @@
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+// Matches every container in a rule.
+pub const ANY: &str = "*";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Allow,
+    Deny,
+}
+
+// Whether one container on a network may connect to another, written
+// `allow web->db:5432` or `deny *->db`. Containers are named by hostname or
+// ID, or `*` for any; without a port, every port matches.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct LinkRule {
+    pub action: Action,
+    pub from: String,
+    pub to: String,
+    pub port: Option<u16>,
+}
+
+impl LinkRule {
+    fn matches(&self, from: &[&str], to: &[&str], port: u16) -> bool {
+        let named = |pattern: &str, names: &[&str]| pattern == ANY || names.contains(&pattern);
+        named(&self.from, from) && named(&self.to, to) && self.port.is_none_or(|rule_port| rule_port == port)
+    }
+}
+
+impl FromStr for LinkRule {
+    type Err = anyhow::Error;
+    
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || anyhow!("Invalid network rule: {:?} (expected allow|deny FROM->TO[:PORT])", s);
+        let (action, link) = s.trim().split_once(char::is_whitespace).ok_or_else(invalid)?;
+        let action = match action {
+            "allow" => Action::Allow,
+            "deny" => Action::Deny,
+            _ => return Err(invalid()),
+        };
+        let (from, to) = link.trim().split_once("->").ok_or_else(invalid)?;
+        let (to, port) = match to.rsplit_once(':') {
+            Some((to, port)) => (to, Some(port.parse::<u16>().ok().filter(|port| *port != 0).ok_or_else(invalid)?)),
+            None => (to, None),
+        };
+        let (from, to) = (from.trim(), to.trim());
+        if from.is_empty() || to.is_empty() || from.contains(char::is_whitespace) || to.contains(char::is_whitespace) {
+            return Err(invalid());
+        }
+        
+        Ok(Self {
+            action,
+            from: from.to_string(),
+            to: to.to_string(),
+            port,
+        })
+    }
+}
+
+impl TryFrom<String> for LinkRule {
+    type Error = anyhow::Error;
+    
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<LinkRule> for String {
+    fn from(rule: LinkRule) -> Self {
+        rule.to_string()
+    }
+}
+
+impl fmt::Display for LinkRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let action = match self.action {
+            Action::Allow => "allow",
+            Action::Deny => "deny",
+        };
+        write!(f, "{} {}->{}", action, self.from, self.to)?;
+        if let Some(port) = self.port {
+            write!(f, ":{}", port)?;
+        }
+        Ok(())
+    }
+}
+
+// Who may talk to whom on a network. Containers on internal networks only
+// reach other containers: not the host, its published ports, or anything
+// beyond, unless another network of theirs allows it.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NetworkPolicy {
+    pub internal: bool,
+    // Checked in order, the first match deciding; connections no rule
+    // matches are allowed.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<LinkRule>,
+}
+
+impl NetworkPolicy {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+    
+    // `from` and `to` are each container's names: its ID and hostname.
+    pub fn allows(&self, from: &[&str], to: &[&str], port: u16) -> bool {
+        self.rules.iter()
+            .find(|rule| rule.matches(from, to, port))
+            .is_none_or(|rule| rule.action == Action::Allow)
+    }
+}
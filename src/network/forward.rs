@@ -0,0 +1,108 @@
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use super::netem::{format_duration, parse_duration};
+
+// Limits on each of a container's published ports, written like `--netem`:
+// `idle-timeout=5m,keepalive=30s,max-connections=100`. Unset options keep
+// the runtime's defaults: UDP relays expire after 30 seconds, TCP
+// connections never do, and neither is capped.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct ForwardOptions {
+    // Connections, or UDP clients, with no traffic either way for this long
+    // are closed.
+    pub idle_timeout: Option<Duration>,
+    // TCP keep-alive probes to clients once their connection has been quiet
+    // this long, so dead peers are noticed.
+    pub keepalive: Option<Duration>,
+    // Connections, or UDP clients, beyond this many are turned away.
+    pub max_connections: Option<usize>,
+}
+
+impl ForwardOptions {
+    // Options left unset here are taken from `defaults`.
+    pub fn or(&self, defaults: &ForwardOptions) -> ForwardOptions {
+        ForwardOptions {
+            idle_timeout: self.idle_timeout.or(defaults.idle_timeout),
+            keepalive: self.keepalive.or(defaults.keepalive),
+            max_connections: self.max_connections.or(defaults.max_connections),
+        }
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+    
+    // Whether another connection may be taken while `active` are open.
+    pub fn admits(&self, active: usize) -> bool {
+        self.max_connections.is_none_or(|max| active < max)
+    }
+}
+
+impl FromStr for ForwardOptions {
+    type Err = anyhow::Error;
+    
+    fn from_str(s: &str) -> Result<Self> {
+        let mut options = Self::default();
+        
+        for option in s.split(',').map(str::trim).filter(|option| !option.is_empty()) {
+            let invalid = || anyhow!(
+                "Invalid port option: {:?} (expected idle-timeout=, keepalive= or max-connections=)",
+                option
+            );
+            let (key, value) = option.split_once('=').ok_or_else(invalid)?;
+            let positive = |duration: Duration| match duration.is_zero() {
+                true => Err(anyhow!("Port option {} must be above zero", key)),
+                false => Ok(duration),
+            };
+            match key {
+                "idle-timeout" => options.idle_timeout = Some(positive(parse_duration(value)?)?),
+                "keepalive" => options.keepalive = Some(positive(parse_duration(value)?)?),
+                "max-connections" => {
+                    let max: usize = value.parse().map_err(|_| invalid())?;
+                    if max == 0 {
+                        return Err(anyhow!("Port option {} must be above zero", key));
+                    }
+                    options.max_connections = Some(max);
+                }
+                _ => return Err(invalid()),
+            }
+        }
+        
+        Ok(options)
+    }
+}
+
+impl TryFrom<String> for ForwardOptions {
+    type Error = anyhow::Error;
+    
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<ForwardOptions> for String {
+    fn from(options: ForwardOptions) -> Self {
+        options.to_string()
+    }
+}
+
+impl fmt::Display for ForwardOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut options = Vec::new();
+        if let Some(timeout) = self.idle_timeout {
+            options.push(format!("idle-timeout={}", format_duration(timeout)));
+        }
+        if let Some(keepalive) = self.keepalive {
+            options.push(format!("keepalive={}", format_duration(keepalive)));
+        }
+        if let Some(max) = self.max_connections {
+            options.push(format!("max-connections={}", max));
+        }
+        write!(f, "{}", options.join(","))
+    }
+}
@@ -0,0 +1,219 @@
+use anyhow::{Result, anyhow};
+use std::fmt;
+use std::io;
+use std::net::IpAddr;
+use std::str::FromStr;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+// Requests whose line and headers don't fit are turned away.
+pub const MAX_HEAD_SIZE: usize = 16 * 1024;
+
+// Headers that only apply to the hop from the client; the ingress sets its
+// own `Connection`.
+const HOP_BY_HOP: &[&str] = &["connection", "keep-alive", "proxy-connection"];
+
+// Routes requests for a host, a path prefix or both to a container's port,
+// written `app.local->web:80`, `/api->api:8080` or
+// `app.local/api->api:8080`. Containers are named by hostname or ID.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IngressRule {
+    pub host: Option<String>,
+    pub path: String,
+    pub container: String,
+    pub port: u16,
+}
+
+impl FromStr for IngressRule {
+    type Err = anyhow::Error;
+    
+    fn from_str(rule: &str) -> Result<Self> {
+        let invalid = || anyhow!("Invalid ingress rule: {:?} (expected [HOST][/PATH]->CONTAINER:PORT)", rule);
+        let (matching, target) = rule.split_once("->").ok_or_else(invalid)?;
+        let (container, port) = target.trim().rsplit_once(':').ok_or_else(invalid)?;
+        let port: u16 = port.parse().ok().filter(|port| *port != 0).ok_or_else(invalid)?;
+        if container.is_empty() {
+            return Err(invalid());
+        }
+        
+        let matching = matching.trim();
+        let (host, path) = match matching.find('/') {
+            Some(i) => (&matching[..i], &matching[i..]),
+            None => (matching, "/"),
+        };
+        if matching.is_empty() {
+            return Err(invalid());
+        }
+        if host.contains(|c: char| c.is_whitespace() || c == ':') || path.contains(char::is_whitespace) {
+            return Err(invalid());
+        }
+        
+        Ok(Self {
+            host: Some(host.to_ascii_lowercase()).filter(|host| !host.is_empty()),
+            path: path.to_string(),
+            container: container.to_string(),
+            port,
+        })
+    }
+}
+
+impl fmt::Display for IngressRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.host {
+            Some(host) if self.path == "/" => write!(f, "{}", host)?,
+            Some(host) => write!(f, "{}{}", host, self.path)?,
+            None => write!(f, "{}", self.path)?,
+        }
+        write!(f, "->{}:{}", self.container, self.port)
+    }
+}
+
+impl IngressRule {
+    // Prefixes match whole path segments, so `/api` takes `/api/users` and
+    // `/api?q=1` but not `/apis`.
+    fn matches(&self, host: Option<&str>, path: &str) -> bool {
+        let host_matches = match &self.host {
+            Some(rule_host) => host.is_some_and(|host| host.eq_ignore_ascii_case(rule_host)),
+            None => true,
+        };
+        let prefix = self.path.trim_end_matches('/');
+        let path_matches = match path.strip_prefix(prefix) {
+            Some(rest) => rest.is_empty() || rest.starts_with(['/', '?']),
+            None => false,
+        };
+        host_matches && path_matches
+    }
+}
+
+// The rule for a request: rules naming its host win over those that don't,
+// then the longest matching path prefix.
+pub fn route<'a>(rules: &'a [IngressRule], host: Option<&str>, path: &str) -> Option<&'a IngressRule> {
+    rules.iter()
+        .filter(|rule| rule.matches(host, path))
+        .max_by_key(|rule| (rule.host.is_some(), rule.path.trim_end_matches('/').len()))
+}
+
+// The request line and headers of an HTTP/1 request.
+#[derive(Debug)]
+pub struct RequestHead {
+    pub method: String,
+    pub target: String,
+    pub version: String,
+    pub headers: Vec<(String, String)>,
+}
+
+impl RequestHead {
+    // Reads up to the blank line ending the headers, returning the head and
+    // whatever of the body was read along with it.
+    pub async fn read<S: AsyncRead + Unpin>(stream: &mut S) -> io::Result<(Self, Vec<u8>)> {
+        let mut buf = Vec::new();
+        let mut chunk = [0u8; 4096];
+        let end = loop {
+            if let Some(i) = buf.windows(4).position(|window| window == b"\r\n\r\n") {
+                break i;
+            }
+            if buf.len() > MAX_HEAD_SIZE {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "Request headers too large"));
+            }
+            let len = stream.read(&mut chunk).await?;
+            if len == 0 {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+            }
+            buf.extend_from_slice(&chunk[..len]);
+        };
+        
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Malformed request");
+        let head = std::str::from_utf8(&buf[..end]).map_err(|_| invalid())?;
+        let mut lines = head.split("\r\n");
+        let mut request_line = lines.next().unwrap_or_default().split(' ');
+        let (Some(method), Some(target), Some(version), None) =
+            (request_line.next(), request_line.next(), request_line.next(), request_line.next())
+        else {
+            return Err(invalid());
+        };
+        if !version.starts_with("HTTP/1.") {
+            return Err(invalid());
+        }
+        let headers = lines
+            .map(|line| {
+                let (name, value) = line.split_once(':').ok_or_else(invalid)?;
+                Ok((name.trim().to_string(), value.trim().to_string()))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        
+        let head = Self {
+            method: method.to_string(),
+            target: target.to_string(),
+            version: version.to_string(),
+            headers,
+        };
+        Ok((head, buf[end + 4..].to_vec()))
+    }
+    
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+    
+    // The Host header without its port.
+    pub fn host(&self) -> Option<&str> {
+        let host = self.header("host")?;
+        match host.rsplit_once(':') {
+            Some((name, port)) if port.chars().all(|c| c.is_ascii_digit()) => Some(name),
+            _ => Some(host),
+        }
+    }
+    
+    // The path, for absolute-form targets as well.
+    pub fn path(&self) -> &str {
+        match self.target.split_once("://") {
+            Some((_, rest)) => rest.find('/').map_or("/", |i| &rest[i..]),
+            None => &self.target,
+        }
+    }
+    
+    // The head as sent on to the container. Connections carry one request,
+    // as the next on a kept-alive connection could be routed elsewhere;
+    // upgrades keep their `Connection` header, since the connection is
+    // handed over for good.
+    pub fn forwarded(&self, client: IpAddr) -> Vec<u8> {
+        let upgrade = self.header("upgrade").is_some();
+        let mut head = format!("{} {} {}\r\n", self.method, self.target, self.version);
+        let mut forwarded_for = None;
+        for (name, value) in &self.headers {
+            let name_lower = name.to_ascii_lowercase();
+            if name_lower == "x-forwarded-for" {
+                forwarded_for = Some(value.as_str());
+                continue;
+            }
+            if !upgrade && HOP_BY_HOP.contains(&name_lower.as_str()) {
+                continue;
+            }
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        
+        let client = client.to_canonical().to_string();
+        match forwarded_for {
+            Some(earlier) => head.push_str(&format!("X-Forwarded-For: {}, {}\r\n", earlier, client)),
+            None => head.push_str(&format!("X-Forwarded-For: {}\r\n", client)),
+        }
+        head.push_str("X-Forwarded-Proto: http\r\n");
+        if !upgrade {
+            head.push_str("Connection: close\r\n");
+        }
+        head.push_str("\r\n");
+        head.into_bytes()
+    }
+}
+
+// A plain-text reply from the ingress itself.
+pub fn error_response(status: u16, reason: &str) -> Vec<u8> {
+    let body = format!("{} {}\n", status, reason);
+    format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        reason,
+        body.len(),
+        body
+    ).into_bytes()
+}
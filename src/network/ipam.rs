@@ -0,0 +1,161 @@
+use anyhow::{Result, anyhow};
+use ipnet::IpNet;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use crate::error::Error;
+
+// The most addresses a pool tracks. IPv6 subnets are far larger than any
+// number of containers, so only their first addresses are handed out.
+const MAX_POOL_SIZE: u128 = 1 << 20;
+
+// The addresses of one network's subnet, tracked in a bitmap with one bit
+// per address. The network and broadcast addresses and the gateway are
+// never handed out. Addresses go back to the pool when their container
+// leaves the network, so churn doesn't use the subnet up.
+#[derive(Debug)]
+pub struct AddressPool {
+    subnet: IpNet,
+    size: u128,
+    used: Vec<u64>,
+    allocations: HashMap<String, IpAddr>,
+}
+
+impl AddressPool {
+    pub fn new(subnet: IpNet, gateway: IpAddr) -> Result<Self> {
+        if !subnet.contains(&gateway) {
+            return Err(anyhow!("Gateway {} is outside subnet {}", gateway, subnet));
+        }
+        
+        let host_bits = subnet.max_prefix_len() - subnet.prefix_len();
+        let size = 1u128.checked_shl(host_bits as u32).unwrap_or(u128::MAX).min(MAX_POOL_SIZE);
+        let mut pool = Self {
+            subnet,
+            size,
+            used: vec![0; size.div_ceil(64) as usize],
+            allocations: HashMap::new(),
+        };
+        // Bits past the end of a subnet smaller than a word don't exist.
+        if size < 64 {
+            pool.used[0] = u64::MAX << size;
+        }
+        pool.mark(subnet.network());
+        if subnet.network().is_ipv4() {
+            pool.mark(subnet.broadcast());
+        }
+        pool.mark(gateway);
+        
+        Ok(pool)
+    }
+    
+    // Hands `container_id` the lowest free address, or None once the subnet
+    // is full. A container already holding an address keeps it.
+    pub fn allocate(&mut self, container_id: &str) -> Option<IpAddr> {
+        if let Some(ip) = self.allocations.get(container_id) {
+            return Some(*ip);
+        }
+        
+        let (word, bits) = self.used.iter()
+            .enumerate()
+            .find(|(_, bits)| **bits != u64::MAX)?;
+        let ip = self.address(word as u128 * 64 + bits.trailing_ones() as u128);
+        self.mark(ip);
+        self.allocations.insert(container_id.to_string(), ip);
+        Some(ip)
+    }
+    
+    // Hands `container_id` exactly `ip`, e.g. for a service other configs
+    // refer to by address.
+    pub fn reserve(&mut self, container_id: &str, ip: IpAddr) -> Result<IpAddr> {
+        if self.allocations.get(container_id) == Some(&ip) {
+            return Ok(ip);
+        }
+        
+        let Some((word, bit)) = self.position(ip) else {
+            return Err(anyhow!("Address {} is outside subnet {}", ip, self.subnet));
+        };
+        if self.used[word] & (1 << bit) != 0 {
+            return Err(match self.allocations.iter().find(|(_, used)| **used == ip) {
+                Some((holder, _)) => Error::NetworkConflict(format!("Address {} is already in use by container {}", ip, holder)).into(),
+                None => anyhow!("Address {} is reserved in subnet {}", ip, self.subnet),
+            });
+        }
+        
+        self.release(container_id);
+        self.mark(ip);
+        self.allocations.insert(container_id.to_string(), ip);
+        Ok(ip)
+    }
+    
+    // Frees the container's address for the next container to join.
+    pub fn release(&mut self, container_id: &str) -> Option<IpAddr> {
+        let ip = self.allocations.remove(container_id)?;
+        if let Some((word, bit)) = self.position(ip) {
+            self.used[word] &= !(1 << bit);
+        }
+        Some(ip)
+    }
+    
+    // Addresses beyond what the pool tracks are never handed out anyway.
+    fn mark(&mut self, ip: IpAddr) {
+        if let Some((word, bit)) = self.position(ip) {
+            self.used[word] |= 1 << bit;
+        }
+    }
+    
+    fn position(&self, ip: IpAddr) -> Option<(usize, u32)> {
+        let offset = to_u128(ip).checked_sub(to_u128(self.subnet.network()))?;
+        (offset < self.size).then_some(((offset / 64) as usize, (offset % 64) as u32))
+    }
+    
+    fn address(&self, offset: u128) -> IpAddr {
+        let ip = to_u128(self.subnet.network()) + offset;
+        match self.subnet {
+            IpNet::V4(_) => IpAddr::V4(Ipv4Addr::from(ip as u32)),
+            IpNet::V6(_) => IpAddr::V6(Ipv6Addr::from(ip)),
+        }
+    }
+}
+
+// Parses a CIDR such as `10.5.0.0/24` or `fd00:5::/64`. Host bits must be
+// zero, and there must be room for a gateway and at least one container.
+pub fn parse_subnet(subnet: &str) -> Result<IpNet> {
+    let net: IpNet = subnet.parse()
+        .map_err(|_| anyhow!("Invalid subnet {:?}: expected a CIDR like 10.5.0.0/24 or fd00:5::/64", subnet))?;
+    let max_prefix = net.max_prefix_len() - 2;
+    if net.prefix_len() > max_prefix {
+        return Err(anyhow!("Subnet {} is too small; the prefix can be at most /{}", subnet, max_prefix));
+    }
+    if net != net.trunc() {
+        return Err(anyhow!("Subnet {} has host bits set; did you mean {}?", subnet, net.trunc()));
+    }
+    
+    Ok(net)
+}
+
+// The gateway takes the first address after the network's own.
+pub fn first_host(subnet: &IpNet) -> IpAddr {
+    match subnet {
+        IpNet::V4(net) => IpAddr::V4(Ipv4Addr::from(u32::from(net.network()) + 1)),
+        IpNet::V6(net) => IpAddr::V6(Ipv6Addr::from(u128::from(net.network()) + 1)),
+    }
+}
+
+// A gateway given instead of the first host must be one of the subnet's
+// own host addresses.
+pub fn check_gateway(subnet: &IpNet, gateway: IpAddr) -> Result<()> {
+    if !subnet.contains(&gateway) {
+        return Err(anyhow!("Gateway {} is outside subnet {}", gateway, subnet));
+    }
+    if gateway == subnet.network() || (gateway.is_ipv4() && gateway == subnet.broadcast()) {
+        return Err(anyhow!("Gateway {} can't be the network or broadcast address of {}", gateway, subnet));
+    }
+    Ok(())
+}
+
+fn to_u128(ip: IpAddr) -> u128 {
+    match ip {
+        IpAddr::V4(ip) => u32::from(ip) as u128,
+        IpAddr::V6(ip) => u128::from(ip),
+    }
+}
@@ -0,0 +1,424 @@
+use anyhow::Result;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, RwLock};
+use tokio::net::UdpSocket;
+use tokio::task::JoinHandle;
+use tracing::debug;
+
+pub const MDNS_PORT: u16 = 5353;
+pub const MDNS_GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+
+// RFC 6762's recommendation for records naming hosts; used for all of them.
+const RECORD_TTL: u32 = 120;
+const MAX_MESSAGE_SIZE: usize = 9000;
+const HEADER_LEN: usize = 12;
+// Compression pointers followed while reading a name, so a message can't
+// send the parser round in circles.
+const MAX_POINTERS: usize = 16;
+
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_AAAA: u16 = 28;
+const TYPE_SRV: u16 = 33;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+// The top bit of a question's class asks for a unicast reply; in a
+// record's, it marks the record as the only one with its name and type.
+const CLASS_FLAG: u16 = 0x8000;
+
+const SERVICES_NAME: &str = "_services._dns-sd._udp.local";
+
+// A published port announced as a DNS-SD service, e.g. instance `web` of
+// `_http._tcp` on port 8080, reachable at `web.local`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Service {
+    pub instance: String,
+    pub service_type: String,
+    pub port: u16,
+}
+
+impl Service {
+    // Guesses the service from the container port: HTTPS for 443 and 8443
+    // and HTTP for any other TCP port, as web services are what's usually
+    // published. UDP ports aren't advertised.
+    pub fn for_port(instance: &str, container_port: u16, host_port: u16, protocol: &str) -> Option<Self> {
+        if !protocol.eq_ignore_ascii_case("tcp") {
+            return None;
+        }
+        let service_type = match container_port {
+            443 | 8443 => "_https._tcp",
+            _ => "_http._tcp",
+        };
+        Some(Self {
+            instance: instance.to_string(),
+            service_type: service_type.to_string(),
+            port: host_port,
+        })
+    }
+    
+    fn type_name(&self) -> String {
+        format!("{}.local", self.service_type)
+    }
+    
+    fn instance_name(&self) -> String {
+        format!("{}.{}.local", self.instance, self.service_type)
+    }
+    
+    fn host_name(&self) -> String {
+        format!("{}.local", self.instance)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Record {
+    name: String,
+    rtype: u16,
+    data: Vec<u8>,
+    // Shared records such as PTRs may have answers from other hosts too.
+    unique: bool,
+}
+
+// Answers mDNS queries for the services of containers that asked to be
+// advertised, and announces them as they come and go. The host's own
+// addresses are given for the services' host names, since that's where
+// the ports are published.
+#[derive(Debug)]
+pub struct MdnsResponder {
+    addr: SocketAddr,
+    socket: Arc<UdpSocket>,
+    host_ips: Vec<IpAddr>,
+    services: Arc<RwLock<HashMap<String, Vec<Service>>>>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl MdnsResponder {
+    // Binding the mDNS port shares it with other responders such as avahi,
+    // and joins the multicast group. Any other address is answered on
+    // directly, with the services at that address.
+    pub async fn bind(addr: SocketAddr) -> Result<Self> {
+        let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+        socket.set_reuse_address(true)?;
+        #[cfg(unix)]
+        socket.set_reuse_port(true)?;
+        socket.bind(&addr.into())?;
+        if addr.port() == MDNS_PORT && addr.ip().is_unspecified() {
+            socket.join_multicast_v4(&MDNS_GROUP, &Ipv4Addr::UNSPECIFIED)?;
+            socket.set_multicast_loop_v4(true)?;
+        }
+        socket.set_nonblocking(true)?;
+        
+        let socket = Arc::new(UdpSocket::from_std(socket.into())?);
+        let addr = socket.local_addr()?;
+        let host_ips = if addr.ip().is_unspecified() {
+            lan_addresses().await
+        } else {
+            vec![addr.ip()]
+        };
+        let services = Arc::new(RwLock::new(HashMap::new()));
+        let task = tokio::spawn(serve(socket.clone(), host_ips.clone(), services.clone()));
+        
+        Ok(Self {
+            addr,
+            socket,
+            host_ips,
+            services,
+            task: Some(task),
+        })
+    }
+    
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+    
+    pub async fn advertise(&self, container_id: &str, services: Vec<Service>) {
+        if services.is_empty() {
+            return;
+        }
+        let records = records(&services, &self.host_ips);
+        self.services.write().unwrap_or_else(|e| e.into_inner())
+            .insert(container_id.to_string(), services);
+        self.announce(&records, RECORD_TTL).await;
+    }
+    
+    // Tells listeners the container's services are gone, with a TTL of 0.
+    pub async fn withdraw(&self, container_id: &str) {
+        let removed = self.services.write().unwrap_or_else(|e| e.into_inner()).remove(container_id);
+        if let Some(services) = removed {
+            self.announce(&records(&services, &self.host_ips), 0).await;
+        }
+    }
+    
+    async fn announce(&self, records: &[Record], ttl: u32) {
+        let message = response(0, &[], records, &[], ttl);
+        let group = SocketAddr::new(IpAddr::V4(MDNS_GROUP), MDNS_PORT);
+        if let Err(e) = self.socket.send_to(&message, group).await {
+            debug!("Failed to announce mDNS records: {}", e);
+        }
+    }
+}
+
+impl Drop for MdnsResponder {
+    fn drop(&mut self) {
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+    }
+}
+
+async fn serve(
+    socket: Arc<UdpSocket>,
+    host_ips: Vec<IpAddr>,
+    services: Arc<RwLock<HashMap<String, Vec<Service>>>>,
+) {
+    let mut buf = vec![0u8; MAX_MESSAGE_SIZE];
+    
+    loop {
+        let (len, from) = match socket.recv_from(&mut buf).await {
+            Ok(received) => received,
+            Err(e) => {
+                debug!("Failed to receive mDNS query: {}", e);
+                continue;
+            }
+        };
+        let query = &buf[..len];
+        let Some(questions) = parse_query(query) else {
+            continue;
+        };
+        
+        let services: Vec<Service> = services.read().unwrap_or_else(|e| e.into_inner())
+            .values()
+            .flatten()
+            .cloned()
+            .collect();
+        let known = records(&services, &host_ips);
+        
+        let mut answers: Vec<Record> = Vec::new();
+        for question in &questions {
+            for record in &known {
+                let matches = record.name.eq_ignore_ascii_case(&question.name)
+                    && (question.qtype == TYPE_ANY || question.qtype == record.rtype);
+                if matches && !answers.iter().any(|answer| same_record(answer, record)) {
+                    answers.push(record.clone());
+                }
+            }
+        }
+        if answers.is_empty() {
+            continue;
+        }
+        // Browsers asking for a service type get the rest of what they need
+        // to connect along with it.
+        let browsing = answers.iter().any(|answer| answer.rtype == TYPE_PTR);
+        let additional: Vec<Record> = known.iter()
+            .filter(|record| browsing && record.rtype != TYPE_PTR)
+            .filter(|record| !answers.iter().any(|answer| same_record(answer, record)))
+            .cloned()
+            .collect();
+        
+        // Queries not from the mDNS port come from simple resolvers, which
+        // get a regular DNS reply; everyone else hears the answer on the
+        // group unless the question asked for it to be sent back directly.
+        let legacy = from.port() != MDNS_PORT;
+        let unicast = legacy || questions.iter().all(|question| question.unicast);
+        let (message, to) = if legacy {
+            let id = u16::from_be_bytes([query[0], query[1]]);
+            (response(id, &questions, &answers, &additional, RECORD_TTL.min(10)), from)
+        } else if unicast {
+            (response(0, &[], &answers, &additional, RECORD_TTL), from)
+        } else {
+            let group = SocketAddr::new(IpAddr::V4(MDNS_GROUP), MDNS_PORT);
+            (response(0, &[], &answers, &additional, RECORD_TTL), group)
+        };
+        if let Err(e) = socket.send_to(&message, to).await {
+            debug!("Failed to send mDNS reply to {}: {}", to, e);
+        }
+    }
+}
+
+// The addresses other machines reach the host on: whatever the route to
+// the mDNS group leaves from.
+async fn lan_addresses() -> Vec<IpAddr> {
+    let Ok(socket) = UdpSocket::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0)).await else {
+        return Vec::new();
+    };
+    match socket.connect(SocketAddr::new(IpAddr::V4(MDNS_GROUP), MDNS_PORT)).await {
+        Ok(()) => socket.local_addr().map(|addr| vec![addr.ip()]).unwrap_or_default(),
+        Err(_) => Vec::new(),
+    }
+}
+
+// Services of the same type share PTR records, and instances of the same
+// container share address records, so each is given once.
+fn records(services: &[Service], host_ips: &[IpAddr]) -> Vec<Record> {
+    let mut records = Vec::new();
+    
+    for service in services {
+        records.push(Record {
+            name: SERVICES_NAME.to_string(),
+            rtype: TYPE_PTR,
+            data: encode_name(&service.type_name()),
+            unique: false,
+        });
+        records.push(Record {
+            name: service.type_name(),
+            rtype: TYPE_PTR,
+            data: encode_name(&service.instance_name()),
+            unique: false,
+        });
+        
+        let mut srv = vec![0, 0, 0, 0];
+        srv.extend_from_slice(&service.port.to_be_bytes());
+        srv.extend(encode_name(&service.host_name()));
+        records.push(Record {
+            name: service.instance_name(),
+            rtype: TYPE_SRV,
+            data: srv,
+            unique: true,
+        });
+        // No attributes: a single empty string.
+        records.push(Record {
+            name: service.instance_name(),
+            rtype: TYPE_TXT,
+            data: vec![0],
+            unique: true,
+        });
+        
+        for ip in host_ips {
+            let (rtype, data) = match ip {
+                IpAddr::V4(ip) => (TYPE_A, ip.octets().to_vec()),
+                IpAddr::V6(ip) => (TYPE_AAAA, ip.octets().to_vec()),
+            };
+            records.push(Record {
+                name: service.host_name(),
+                rtype,
+                data,
+                unique: true,
+            });
+        }
+    }
+    
+    let mut unique: Vec<Record> = Vec::new();
+    for record in records {
+        if !unique.iter().any(|seen| same_record(seen, &record)) {
+            unique.push(record);
+        }
+    }
+    unique
+}
+
+fn same_record(a: &Record, b: &Record) -> bool {
+    a.name.eq_ignore_ascii_case(&b.name) && a.rtype == b.rtype && a.data == b.data
+}
+
+#[derive(Debug)]
+struct Question {
+    name: String,
+    qtype: u16,
+    unicast: bool,
+}
+
+// The questions of a query, or None for responses and anything malformed.
+// Known answers are ignored; listeners with the records cached just get
+// them again.
+fn parse_query(message: &[u8]) -> Option<Vec<Question>> {
+    if message.len() < HEADER_LEN || message[2] & 0x80 != 0 || message[2] & 0x78 != 0 {
+        return None;
+    }
+    let count = u16::from_be_bytes([message[4], message[5]]);
+    
+    let mut questions = Vec::new();
+    let mut pos = HEADER_LEN;
+    for _ in 0..count {
+        let (name, end) = read_name(message, pos)?;
+        let fields = message.get(end..end + 4)?;
+        let class = u16::from_be_bytes([fields[2], fields[3]]);
+        if class & !CLASS_FLAG == CLASS_IN {
+            questions.push(Question {
+                name,
+                qtype: u16::from_be_bytes([fields[0], fields[1]]),
+                unicast: class & CLASS_FLAG != 0,
+            });
+        }
+        pos = end + 4;
+    }
+    
+    Some(questions)
+}
+
+// Reads the name at `pos`, following compression pointers. Returns it
+// without the trailing dot, and where the name ends at `pos`.
+fn read_name(message: &[u8], mut pos: usize) -> Option<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut end = None;
+    let mut pointers = 0;
+    
+    loop {
+        let len = *message.get(pos)? as usize;
+        if len & 0xC0 == 0xC0 {
+            pointers += 1;
+            if pointers > MAX_POINTERS {
+                return None;
+            }
+            let target = ((len & 0x3F) << 8) | *message.get(pos + 1)? as usize;
+            end.get_or_insert(pos + 2);
+            pos = target;
+            continue;
+        }
+        if len & 0xC0 != 0 {
+            return None;
+        }
+        pos += 1;
+        if len == 0 {
+            break;
+        }
+        labels.push(String::from_utf8_lossy(message.get(pos..pos + len)?).into_owned());
+        pos += len;
+    }
+    
+    Some((labels.join("."), end.unwrap_or(pos)))
+}
+
+// Instance names may hold dots of their own, but ours come from host names
+// and container IDs, which split into labels at them just fine.
+fn encode_name(name: &str) -> Vec<u8> {
+    let mut out = Vec::new();
+    for label in name.split('.').filter(|label| !label.is_empty()) {
+        let label = &label.as_bytes()[..label.len().min(63)];
+        out.push(label.len() as u8);
+        out.extend_from_slice(label);
+    }
+    out.push(0);
+    out
+}
+
+fn response(id: u16, questions: &[Question], answers: &[Record], additional: &[Record], ttl: u32) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(&id.to_be_bytes());
+    // QR and AA.
+    out.extend_from_slice(&[0x84, 0]);
+    out.extend_from_slice(&(questions.len() as u16).to_be_bytes());
+    out.extend_from_slice(&(answers.len() as u16).to_be_bytes());
+    out.extend_from_slice(&[0, 0]);
+    out.extend_from_slice(&(additional.len() as u16).to_be_bytes());
+    
+    for question in questions {
+        out.extend(encode_name(&question.name));
+        out.extend_from_slice(&question.qtype.to_be_bytes());
+        out.extend_from_slice(&CLASS_IN.to_be_bytes());
+    }
+    // Legacy replies echo the question and mustn't set the cache flush bit.
+    let legacy = !questions.is_empty();
+    for record in answers.iter().chain(additional) {
+        out.extend(encode_name(&record.name));
+        out.extend_from_slice(&record.rtype.to_be_bytes());
+        let class = if record.unique && !legacy { CLASS_IN | CLASS_FLAG } else { CLASS_IN };
+        out.extend_from_slice(&class.to_be_bytes());
+        out.extend_from_slice(&ttl.to_be_bytes());
+        out.extend_from_slice(&(record.data.len() as u16).to_be_bytes());
+        out.extend_from_slice(&record.data);
+    }
+    
+    out
+}
@@ -0,0 +1,263 @@
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::task::AbortHandle;
+use uuid::Uuid;
+
+// How often each peer is asked which containers it runs.
+pub const SYNC_INTERVAL: Duration = Duration::from_secs(2);
+
+// Containers of a peer that hasn't answered in this long are forgotten.
+const PEER_TIMEOUT: Duration = Duration::from_secs(10);
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_MESSAGE_SIZE: u64 = 1024 * 1024;
+
+// Peering with the runtimes on other hosts: each listens on `listen` and
+// lists the others in `peers`, as `host:port`. Every connection proves
+// knowledge of the shared `key` both ways before anything else is sent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MeshConfig {
+    pub listen: SocketAddr,
+    pub peers: Vec<String>,
+    pub key: String,
+}
+
+// A container as peers see it: the networks it is on, by name, and its
+// addresses there.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RemoteEndpoint {
+    pub id: String,
+    pub hostname: String,
+    pub addresses: Vec<(String, IpAddr)>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Hello {
+    node: String,
+    nonce: String,
+    // Sent back by the side accepting, for the nonce it was greeted with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    proof: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Open {
+    proof: String,
+    request: Request,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Request {
+    // Trades the containers each side runs.
+    Sync { endpoints: Vec<RemoteEndpoint> },
+    // Opens a connection to a listener of one of the peer's containers. The
+    // stream carries the connection's bytes once the peer answers.
+    Connect { container_id: String, from: IpAddr, port: u16 },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Response {
+    Sync { endpoints: Vec<RemoteEndpoint> },
+    Connected,
+    Refused,
+}
+
+// One host's membership in the mesh, and what it knows of the others.
+#[derive(Debug)]
+pub struct Mesh {
+    node: String,
+    key: Vec<u8>,
+    local_addr: SocketAddr,
+    peers: Vec<String>,
+    // The containers each peer last reported, by peer address.
+    remote: RwLock<HashMap<String, (Instant, Vec<RemoteEndpoint>)>>,
+    tasks: Mutex<Vec<AbortHandle>>,
+}
+
+impl Mesh {
+    pub fn new(config: &MeshConfig, local_addr: SocketAddr) -> Result<Self> {
+        if config.key.is_empty() {
+            return Err(anyhow!("The mesh needs a shared key"));
+        }
+        
+        Ok(Self {
+            node: Uuid::new_v4().to_string(),
+            key: config.key.as_bytes().to_vec(),
+            local_addr,
+            peers: config.peers.clone(),
+            remote: RwLock::new(HashMap::new()),
+            tasks: Mutex::new(Vec::new()),
+        })
+    }
+    
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+    
+    pub fn peers(&self) -> &[String] {
+        &self.peers
+    }
+    
+    pub fn add_task(&self, task: AbortHandle) {
+        self.tasks.lock().unwrap_or_else(|e| e.into_inner()).push(task);
+    }
+    
+    pub fn shutdown(&self) {
+        for task in self.tasks.lock().unwrap_or_else(|e| e.into_inner()).drain(..) {
+            task.abort();
+        }
+    }
+    
+    // Every container peers reported recently, with the peer running it.
+    pub fn endpoints(&self) -> Vec<(String, RemoteEndpoint)> {
+        self.remote.read().unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .flat_map(|(peer, (_, endpoints))| endpoints.iter().map(|endpoint| (peer.clone(), endpoint.clone())))
+            .collect()
+    }
+    
+    // Trades container lists with `peer`. Returns whether what it runs
+    // changed since it last said.
+    pub async fn sync(&self, peer: &str, local: Vec<RemoteEndpoint>) -> io::Result<bool> {
+        let (_, response) = self.open(peer, Request::Sync { endpoints: local }).await?;
+        let Response::Sync { endpoints } = response else {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Unexpected mesh response"));
+        };
+        
+        let mut remote = self.remote.write().unwrap_or_else(|e| e.into_inner());
+        let changed = remote.get(peer).is_none_or(|(_, known)| *known != endpoints);
+        remote.insert(peer.to_string(), (Instant::now(), endpoints));
+        Ok(changed)
+    }
+    
+    // Forgets peers that stopped answering. Returns whether any were.
+    pub fn expire(&self) -> bool {
+        let mut remote = self.remote.write().unwrap_or_else(|e| e.into_inner());
+        let before = remote.len();
+        remote.retain(|_, (seen, _)| seen.elapsed() < PEER_TIMEOUT);
+        remote.len() != before
+    }
+    
+    pub async fn connect(&self, peer: &str, container_id: &str, from: IpAddr, port: u16) -> io::Result<BufReader<TcpStream>> {
+        let request = Request::Connect {
+            container_id: container_id.to_string(),
+            from,
+            port,
+        };
+        match self.open(peer, request).await? {
+            (stream, Response::Connected) => Ok(stream),
+            _ => Err(io::Error::from(io::ErrorKind::ConnectionRefused)),
+        }
+    }
+    
+    // The dialing side of the handshake.
+    async fn open(&self, peer: &str, request: Request) -> io::Result<(BufReader<TcpStream>, Response)> {
+        let mut stream = BufReader::new(TcpStream::connect(peer).await?);
+        
+        let nonce = Uuid::new_v4().to_string();
+        let hello = Hello {
+            node: self.node.clone(),
+            nonce: nonce.clone(),
+            proof: None,
+        };
+        write_message(&mut stream, &hello).await?;
+        let reply: Hello = tokio::time::timeout(HANDSHAKE_TIMEOUT, read_message(&mut stream)).await
+            .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+        if reply.node == self.node {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Mesh peer is this host"));
+        }
+        if !reply.proof.is_some_and(|proof| self.verify("accept", &nonce, &reply.nonce, &proof)) {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, "Mesh peer doesn't know the key"));
+        }
+        
+        let open = Open {
+            proof: self.prove("open", &nonce, &reply.nonce),
+            request,
+        };
+        write_message(&mut stream, &open).await?;
+        let response = read_message(&mut stream).await?;
+        Ok((stream, response))
+    }
+    
+    // The accepting side of the handshake. Returns the peer's request,
+    // for the caller to answer with `respond`.
+    pub async fn accept(&self, stream: TcpStream) -> io::Result<(BufReader<TcpStream>, Request)> {
+        let mut stream = BufReader::new(stream);
+        
+        let hello: Hello = tokio::time::timeout(HANDSHAKE_TIMEOUT, read_message(&mut stream)).await
+            .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+        let nonce = Uuid::new_v4().to_string();
+        let reply = Hello {
+            node: self.node.clone(),
+            nonce: nonce.clone(),
+            proof: Some(self.prove("accept", &hello.nonce, &nonce)),
+        };
+        write_message(&mut stream, &reply).await?;
+        
+        let open: Open = tokio::time::timeout(HANDSHAKE_TIMEOUT, read_message(&mut stream)).await
+            .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+        if !self.verify("open", &hello.nonce, &nonce, &open.proof) {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, "Mesh peer doesn't know the key"));
+        }
+        Ok((stream, open.request))
+    }
+    
+    // Each proof covers both nonces, so it can't be replayed on another
+    // connection, and which step it is, so one side's can't stand in for
+    // the other's.
+    fn prove(&self, step: &str, dialer_nonce: &str, acceptor_nonce: &str) -> String {
+        hex(&self.mac(step, dialer_nonce, acceptor_nonce).finalize().into_bytes())
+    }
+    
+    // Compared in constant time, so a peer can't find the proof bit by bit.
+    fn verify(&self, step: &str, dialer_nonce: &str, acceptor_nonce: &str, proof: &str) -> bool {
+        unhex(proof).is_some_and(|proof| self.mac(step, dialer_nonce, acceptor_nonce).verify_slice(&proof).is_ok())
+    }
+    
+    fn mac(&self, step: &str, dialer_nonce: &str, acceptor_nonce: &str) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC takes keys of any length");
+        mac.update(format!("{}\n{}\n{}", step, dialer_nonce, acceptor_nonce).as_bytes());
+        mac
+    }
+}
+
+pub async fn respond(stream: &mut BufReader<TcpStream>, response: &Response) -> io::Result<()> {
+    write_message(stream, response).await
+}
+
+// Messages are JSON, one per line.
+async fn write_message<T: Serialize>(stream: &mut BufReader<TcpStream>, message: &T) -> io::Result<()> {
+    let mut line = serde_json::to_vec(message)?;
+    line.push(b'\n');
+    stream.get_mut().write_all(&line).await
+}
+
+async fn read_message<T: DeserializeOwned>(stream: &mut BufReader<TcpStream>) -> io::Result<T> {
+    let mut line = Vec::new();
+    (&mut *stream).take(MAX_MESSAGE_SIZE).read_until(b'\n', &mut line).await?;
+    if !line.ends_with(b"\n") {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Mesh message truncated or too large"));
+    }
+    serde_json::from_slice(&line).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok()).collect()
+}
@@ -1,24 +1,219 @@
-use anyhow::Result;
+use anyhow::{Result, anyhow};
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
-use tokio::net::{TcpListener, UdpSocket};
+use std::collections::hash_map::Entry;
+use std::fs;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
 use tokio::sync::Mutex;
+use tokio::task::{AbortHandle, JoinHandle, JoinSet};
 use std::sync::Arc;
-use tracing::{info, debug, error};
+use tracing::{info, debug, error, warn};
 
-use crate::container::Container;
+use crate::container::{Container, PortMapping};
+use crate::error::{self, Error};
 
+pub mod activation;
+pub mod bridge;
+pub mod capture;
+pub mod dns;
+pub mod firewall;
+pub mod forward;
+pub mod ingress;
+pub mod ipam;
+pub mod mdns;
+pub mod mesh;
+pub mod netem;
+pub mod policy;
+pub mod proxy;
+pub mod resolv;
+pub mod throttle;
+
+use activation::ActivatedSocket;
+use bridge::{Bridge, BridgeListener};
+use capture::{Capture, TcpTap};
+use dns::{DnsServer, DNS_PORT};
+use firewall::NetworkPolicy;
+use forward::ForwardOptions;
+use ingress::{IngressRule, RequestHead};
+use ipam::{AddressPool, check_gateway, first_host, parse_subnet};
+use mdns::{MdnsResponder, MDNS_PORT, Service};
+use mesh::{Mesh, MeshConfig, RemoteEndpoint, Request, Response, SYNC_INTERVAL};
+use throttle::{PortStats, Traffic};
+
+// The network containers join unless told otherwise. It always exists and
+// can't be removed.
+pub const DEFAULT_NETWORK: &str = "bridge";
+
+// Containers on this network have no networking at all: no address, no
+// sockets to other containers and no access to the host's network. It
+// can't be combined with other networks.
+pub const NO_NETWORK: &str = "none";
+
+// Containers on this network use the host's network directly: they get no
+// address of their own, listen on host ports without publishing them and
+// can't reach containers on bridges. It can't be combined with other
+// networks either.
+pub const HOST_NETWORK: &str = "host";
+
+const DEFAULT_UDP_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+// The shortest wait between sweeps for idle UDP clients, however short
+// their timeout.
+const MIN_UDP_SWEEP: Duration = Duration::from_millis(1);
+const MAX_DATAGRAM_SIZE: usize = 65535;
+const INGRESS_HEAD_TIMEOUT: Duration = Duration::from_secs(10);
+// How many ports past a taken one `--publish-retry` tries.
+const PUBLISH_RETRY_LIMIT: u16 = 100;
+
+// Clones share the same networks, so one can be handed to each container.
+#[derive(Clone)]
 pub struct NetworkManager {
     networks: Arc<Mutex<HashMap<String, Network>>>,
-    port_forwards: Arc<Mutex<HashMap<u16, PortForward>>>,
+    // Keyed by host port and protocol, as TCP and UDP can share a port.
+    port_forwards: Arc<Mutex<HashMap<(u16, String), PortForward>>>,
+    endpoints: Arc<Mutex<HashMap<String, Endpoint>>>,
+    // Keyed by subnet, and built the first time a container joins the
+    // network.
+    address_pools: Arc<Mutex<HashMap<String, AddressPool>>>,
+    dns_servers: Arc<Mutex<HashMap<String, DnsServer>>>,
+    // What each container's published ports carry.
+    traffic: Arc<Mutex<HashMap<String, Arc<Traffic>>>>,
+    // Sockets systemd bound for the process, used instead of binding the
+    // host ports they are on.
+    activated: Arc<Mutex<Vec<ActivatedSocket>>>,
+    // Bound the first time a container asks for its ports to be advertised.
+    mdns: Arc<Mutex<Option<Arc<MdnsResponder>>>>,
+    mdns_addr: SocketAddr,
+    // Peering with the runtimes on other hosts, once joined.
+    mesh: Arc<Mutex<Option<Arc<Mesh>>>>,
+    // Routes HTTP requests from the host to containers, once started.
+    ingress: Arc<Mutex<Option<AbortHandle>>>,
+    bridge: Bridge,
+    // Where user-defined networks are kept, if they outlive the manager.
+    networks_dir: Option<PathBuf>,
+    udp_idle_timeout: Duration,
+    // For containers that don't set their own port options.
+    forward_defaults: ForwardOptions,
 }
 
+// A container's address on its networks, and the /etc/hosts file that is
+// rewritten whenever a peer joins or leaves one of them.
 #[derive(Debug, Clone)]
+struct Endpoint {
+    hostname: String,
+    // The IPv4 address and, on dual-stack networks, the IPv6 address on
+    // each network; the first is the container's own.
+    addresses: Vec<(String, IpAddr)>,
+    hosts_file: Option<PathBuf>,
+}
+
+impl Endpoint {
+    fn remote(remote: &RemoteEndpoint) -> Self {
+        Self {
+            hostname: remote.hostname.clone(),
+            addresses: remote.addresses.clone(),
+            hosts_file: None,
+        }
+    }
+    
+    fn ip(&self) -> Option<IpAddr> {
+        self.addresses.first().map(|(_, ip)| *ip)
+    }
+    
+    // The addresses `peer` reaches this endpoint at, on the first network
+    // they share; IPv4 first.
+    fn addresses_for(&self, peer: &Endpoint) -> Vec<IpAddr> {
+        let Some((shared, _)) = self.addresses.iter()
+            .find(|(network, _)| peer.addresses.iter().any(|(other, _)| other == network))
+        else {
+            return Vec::new();
+        };
+        self.addresses.iter()
+            .filter(|(network, _)| network == shared)
+            .map(|(_, ip)| *ip)
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Network {
     pub name: String,
     pub subnet: String,
     pub gateway: IpAddr,
+    // Set on dual-stack networks.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subnet6: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gateway6: Option<IpAddr>,
+    // Appended to container names in DNS answers and hosts files, and
+    // searched by the containers on the network, e.g. `internal` makes
+    // `web` answer to `web.internal` too.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub domain: Option<String>,
+    #[serde(default, skip_serializing_if = "NetworkPolicy::is_empty")]
+    pub policy: NetworkPolicy,
+    // Containers currently on the network; not persisted.
+    #[serde(skip)]
     pub containers: Vec<String>,
+    // The containers on the network, with their addresses while they run;
+    // only filled in by `inspect_network`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub members: Vec<NetworkMember>,
+}
+
+// A container as `network inspect` shows it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NetworkMember {
+    pub id: String,
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub addresses: Vec<IpAddr>,
+    // The other names it answers to on the network.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+}
+
+impl NetworkMember {
+    pub fn new(id: &str, hostname: &str, addresses: Vec<IpAddr>, domain: Option<&str>) -> Self {
+        let aliases = container_names(id, hostname, domain)
+            .into_iter()
+            .filter(|name| name != hostname)
+            .collect();
+        Self {
+            id: id.to_string(),
+            name: hostname.to_string(),
+            addresses,
+            aliases,
+        }
+    }
+}
+
+impl Network {
+    // The IPv4 subnet and its gateway, then the IPv6 ones if set.
+    pub fn subnets(&self) -> impl Iterator<Item = (&str, IpAddr)> {
+        std::iter::once((self.subnet.as_str(), self.gateway))
+            .chain(self.subnet6.as_deref().zip(self.gateway6))
+    }
+    
+    // Checks that a container could be given `ip` on this network, leaving
+    // whether it is free to allocation.
+    pub fn check_address(&self, ip: IpAddr) -> error::Result<()> {
+        let subnet = self.subnets()
+            .filter_map(|(subnet, _)| parse_subnet(subnet).ok())
+            .find(|subnet| subnet.contains(&ip))
+            .ok_or_else(|| anyhow!("Address {} is outside the subnets of network {}", ip, self.name))?;
+        let broadcast = ip.is_ipv4() && ip == subnet.broadcast();
+        if ip == subnet.network() || broadcast || self.subnets().any(|(_, gateway)| gateway == ip) {
+            return Err(anyhow!("Address {} is reserved in network {}", ip, self.name).into());
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug)]
@@ -27,196 +222,1636 @@ pub struct PortForward {
     pub container_id: String,
     pub container_port: u16,
     pub protocol: String,
-    pub listener: Option<TcpListener>,
+    connections: Arc<AtomicUsize>,
+    traffic: Arc<Traffic>,
+    proxy: Option<JoinHandle<()>>,
+}
+
+impl PortForward {
+    pub fn active_connections(&self) -> usize {
+        self.connections.load(Ordering::Relaxed)
+    }
+    
+    pub fn stats(&self) -> PortStats {
+        PortStats {
+            host_port: self.host_port,
+            container_port: self.container_port,
+            protocol: self.protocol.clone(),
+            connections: self.active_connections(),
+            rx_bytes: self.traffic.received(),
+            tx_bytes: self.traffic.sent(),
+        }
+    }
+    
+    // Like dropping the forward, but waits until the proxy has stopped so
+    // the host port is free again once this returns.
+    async fn shutdown(mut self) {
+        if let Some(proxy) = self.proxy.take() {
+            proxy.abort();
+            proxy.await.ok();
+        }
+    }
+}
+
+// Stops accepting and closes every connection still being proxied.
+impl Drop for PortForward {
+    fn drop(&mut self) {
+        if let Some(proxy) = self.proxy.take() {
+            proxy.abort();
+        }
+    }
+}
+
+impl Default for NetworkManager {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl NetworkManager {
     pub fn new() -> Self {
-        let mut networks = HashMap::new();
+        Self {
+            networks: Arc::new(Mutex::new(predefined_networks())),
+            port_forwards: Arc::new(Mutex::new(HashMap::new())),
+            endpoints: Arc::new(Mutex::new(HashMap::new())),
+            address_pools: Arc::new(Mutex::new(HashMap::new())),
+            dns_servers: Arc::new(Mutex::new(HashMap::new())),
+            traffic: Arc::new(Mutex::new(HashMap::new())),
+            activated: Arc::new(Mutex::new(Vec::new())),
+            mdns: Arc::new(Mutex::new(None)),
+            mdns_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), MDNS_PORT),
+            mesh: Arc::new(Mutex::new(None)),
+            ingress: Arc::new(Mutex::new(None)),
+            bridge: Bridge::default(),
+            networks_dir: None,
+            udp_idle_timeout: DEFAULT_UDP_IDLE_TIMEOUT,
+            forward_defaults: ForwardOptions::default(),
+        }
+    }
+    
+    // Keeps user-defined networks in `networks_dir`, loading the ones
+    // created earlier.
+    pub fn with_dir(networks_dir: PathBuf) -> error::Result<Self> {
+        fs::create_dir_all(&networks_dir)?;
         
-        networks.insert(
-            "bridge".to_string(),
-            Network {
-                name: "bridge".to_string(),
-                subnet: "172.17.0.0/16".to_string(),
-                gateway: IpAddr::V4(Ipv4Addr::new(172, 17, 0, 1)),
-                containers: Vec::new(),
+        let mut networks = predefined_networks();
+        for entry in fs::read_dir(&networks_dir)? {
+            let path = entry?.path();
+            if path.extension().is_none_or(|ext| ext != "json") {
+                continue;
             }
-        );
+            match serde_json::from_str::<Network>(&fs::read_to_string(&path)?) {
+                Ok(network) => {
+                    networks.entry(network.name.clone()).or_insert(network);
+                }
+                Err(e) => error!("Ignoring unreadable network {:?}: {}", path, e),
+            }
+        }
         
-        Self {
+        Ok(Self {
             networks: Arc::new(Mutex::new(networks)),
-            port_forwards: Arc::new(Mutex::new(HashMap::new())),
-        }
+            networks_dir: Some(networks_dir),
+            ..Self::new()
+        })
     }
     
-    pub async fn setup_container_network(&self, container: &Container) -> Result<ContainerNetwork> {
+    // UDP has no connections to close, so a client's relay is dropped once
+    // no datagram has passed in either direction for `timeout`.
+    pub fn with_udp_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.udp_idle_timeout = timeout;
+        self
+    }
+    
+    // Port options for containers that leave them unset.
+    pub fn with_forward_defaults(mut self, defaults: ForwardOptions) -> Self {
+        self.forward_defaults = defaults;
+        self
+    }
+    
+    // Answers mDNS queries on `addr` instead of the mDNS port.
+    pub fn with_mdns_addr(mut self, addr: SocketAddr) -> Self {
+        self.mdns_addr = addr;
+        self
+    }
+    
+    pub async fn setup_container_network(&self, container: &Container) -> error::Result<ContainerNetwork> {
         debug!("Setting up network for container: {}", container.id());
         
-        let ip = self.allocate_ip(container.id()).await?;
+        let mut addresses = Vec::new();
+        for (i, name) in container.networks().iter().enumerate() {
+            // A static address is for the container's first network.
+            let static_ip = container.network_config().ip.filter(|_| i == 0);
+            match self.allocate_ips(name, container.id(), static_ip).await {
+                Ok(ips) => addresses.extend(ips.into_iter().map(|ip| (name.to_string(), ip))),
+                Err(e) => {
+                    self.leave_networks(container.id()).await;
+                    return Err(e.into());
+                }
+            }
+        }
+        let Some(&(_, ip)) = addresses.first() else {
+            return Err(anyhow!("Container {} is not connected to any network", container.id()).into());
+        };
+        let ip6 = addresses.iter().map(|(_, ip)| *ip).find(IpAddr::is_ipv6);
         
-        let mut port_mappings = Vec::new();
+        // Every host port is bound before any is forwarded, so one that's
+        // taken fails the start instead of leaving the rest published.
+        let mut sockets = Vec::new();
         for port_map in &container.network_config().ports {
-            self.setup_port_forward(
-                container.id(),
-                port_map.host_port,
-                port_map.container_port,
-                &port_map.protocol,
-            ).await?;
+            match self.publish(port_map, ip6.is_some(), container.network_config().publish_retry).await {
+                Ok(socket) => sockets.push(socket),
+                Err(e) => {
+                    self.leave_networks(container.id()).await;
+                    return Err(e.into());
+                }
+            }
+        }
+        if let Some(socket) = self.activated.lock().await.first() {
+            self.leave_networks(container.id()).await;
+            return Err(anyhow!(
+                "Activated socket on port {}/{} isn't published by container {}",
+                socket.port()?,
+                socket.protocol(),
+                container.id()
+            ).into());
+        }
+        
+        self.endpoints.lock().await.insert(
+            container.id().to_string(),
+            Endpoint {
+                hostname: container.network_config().hostname.clone(),
+                addresses: addresses.clone(),
+                hosts_file: None,
+            },
+        );
+        self.refresh_dns_records().await;
+        
+        self.traffic.lock().await.insert(
+            container.id().to_string(),
+            Arc::new(Traffic::new(
+                container.network_config().rate,
+                container.network_config().netem.unwrap_or_default(),
+            )),
+        );
+        let options = container.network_config().port_options.or(&self.forward_defaults);
+        let mut port_mappings = Vec::new();
+        for (port_map, socket) in container.network_config().ports.iter().zip(sockets) {
+            let host_port = self.setup_port_forward(container.id(), socket, port_map.container_port, options).await?;
             
-            port_mappings.push((*port_map).clone());
+            port_mappings.push(PortMapping {
+                host_port,
+                ..(*port_map).clone()
+            });
+        }
+        if container.network_config().mdns {
+            self.advertise(container, &port_mappings).await;
         }
         
         Ok(ContainerNetwork {
             container_id: container.id().to_string(),
             ip_address: ip,
+            ip6_address: ip6,
+            addresses,
             hostname: container.network_config().hostname.clone(),
             port_mappings,
         })
     }
     
-    pub async fn cleanup_container_network(&self, container_id: &str) -> Result<()> {
+    // Connects a running container to another network. It gets an address
+    // there, which peers on the network reach straight away, and resolvers
+    // and hosts files are updated with it. Networks created by another
+    // process since this one started are read from disk.
+    pub async fn join_network(&self, container_id: &str, network: &str) -> error::Result<Vec<IpAddr>> {
+        if network == NO_NETWORK || network == HOST_NETWORK {
+            return Err(anyhow!("Network {} can't be connected to", network).into());
+        }
+        self.load_network(network).await?;
+        let joined = self.endpoints.lock().await
+            .get(container_id)
+            .map(|endpoint| endpoint.addresses.iter().any(|(name, _)| name == network));
+        match joined {
+            Some(false) => {}
+            Some(true) => return Err(Error::NetworkConflict(format!("Container {} is already connected to network {}", container_id, network))),
+            None => return Err(anyhow!("Container {} is not on a network", container_id).into()),
+        }
+        
+        let ips = self.allocate_ips(network, container_id, None).await?;
+        let mut endpoints = self.endpoints.lock().await;
+        let Some(endpoint) = endpoints.get_mut(container_id) else {
+            drop(endpoints);
+            self.leave_networks(container_id).await;
+            return Err(anyhow!("Container {} is not on a network", container_id).into());
+        };
+        endpoint.addresses.extend(ips.iter().map(|ip| (network.to_string(), *ip)));
+        drop(endpoints);
+        
+        self.refresh_dns_records().await;
+        self.write_hosts_file(container_id).await?;
+        for peer in self.peers(container_id).await {
+            self.write_hosts_file(&peer).await?;
+        }
+        
+        info!("Connected container {} to network {}", container_id, network);
+        Ok(ips)
+    }
+    
+    async fn load_network(&self, name: &str) -> Result<()> {
+        let mut networks = self.networks.lock().await;
+        if networks.contains_key(name) {
+            return Ok(());
+        }
+        let Some(path) = self.networks_dir.as_ref().map(|dir| network_file(dir, name)).filter(|path| path.exists()) else {
+            return Err(Error::NetworkNotFound(name.to_string()).into());
+        };
+        let network: Network = serde_json::from_str(&fs::read_to_string(&path)?)?;
+        networks.insert(name.to_string(), network);
+        Ok(())
+    }
+    
+    // Advertising is best effort: a host without multicast still runs the
+    // container, just without its services being discoverable.
+    async fn advertise(&self, container: &Container, port_mappings: &[PortMapping]) {
+        let hostname = &container.network_config().hostname;
+        let services: Vec<Service> = port_mappings.iter()
+            .filter_map(|port_map| Service::for_port(
+                hostname,
+                port_map.container_port,
+                port_map.host_port,
+                &port_map.protocol,
+            ))
+            .collect();
+        if services.is_empty() {
+            return;
+        }
+        
+        let responder = {
+            let mut mdns = self.mdns.lock().await;
+            match &*mdns {
+                Some(responder) => responder.clone(),
+                None => match MdnsResponder::bind(self.mdns_addr).await {
+                    Ok(responder) => mdns.insert(Arc::new(responder)).clone(),
+                    Err(e) => {
+                        warn!("Not advertising container {} over mDNS: {}", container.id(), e);
+                        return;
+                    }
+                },
+            }
+        };
+        for service in &services {
+            info!("Advertising {}.{}.local on port {}", service.instance, service.service_type, service.port);
+        }
+        responder.advertise(container.id(), services).await;
+    }
+    
+    async fn leave_networks(&self, container_id: &str) {
+        for network in self.networks.lock().await.values_mut() {
+            network.containers.retain(|id| id != container_id);
+        }
+        for (name, pool) in self.address_pools.lock().await.iter_mut() {
+            if let Some(ip) = pool.release(container_id) {
+                debug!("Released {} on network {}", ip, name);
+            }
+        }
+    }
+    
+    pub async fn cleanup_container_network(&self, container_id: &str) -> error::Result<()> {
         info!("Cleaning up network for container: {}", container_id);
         
         let mut port_forwards = self.port_forwards.lock().await;
-        let forwards_to_remove: Vec<u16> = port_forwards
+        let forwards_to_remove: Vec<(u16, String)> = port_forwards
             .iter()
             .filter(|(_, forward)| forward.container_id == container_id)
-            .map(|(&port, _)| port)
+            .map(|(key, _)| key.clone())
             .collect();
         
-        for port in forwards_to_remove {
-            port_forwards.remove(&port);
-            debug!("Removed port forward for port: {}", port);
+        for key in forwards_to_remove {
+            if let Some(forward) = port_forwards.remove(&key) {
+                forward.shutdown().await;
+            }
+            debug!("Removed port forward for port: {}/{}", key.0, key.1);
+        }
+        
+        self.traffic.lock().await.remove(container_id);
+        if let Some(responder) = self.mdns.lock().await.clone() {
+            responder.withdraw(container_id).await;
         }
+        let peers = self.peers(container_id).await;
+        self.leave_networks(container_id).await;
         
-        let mut networks = self.networks.lock().await;
-        for network in networks.values_mut() {
-            network.containers.retain(|id| id != container_id);
+        self.endpoints.lock().await.remove(container_id);
+        for peer in peers {
+            self.write_hosts_file(&peer).await?;
+        }
+        
+        // Networks nobody is on anymore don't need a resolver.
+        let empty: Vec<String> = self.networks.lock().await.values()
+            .filter(|network| network.containers.is_empty())
+            .map(|network| network.name.clone())
+            .collect();
+        let mut dns_servers = self.dns_servers.lock().await;
+        for name in empty {
+            if let Some(server) = dns_servers.remove(&name) {
+                debug!("Stopping DNS server for network {}", name);
+                server.shutdown().await;
+            }
         }
+        drop(dns_servers);
+        self.refresh_dns_records().await;
         
         Ok(())
     }
     
-    async fn allocate_ip(&self, container_id: &str) -> Result<IpAddr> {
-        let mut networks = self.networks.lock().await;
+    // Makes sure the container's network runs a resolver on its gateway and
+    // returns the gateway, for the container's resolv.conf to point at.
+    // Queries for other names go to `upstream`. Returns None when the
+    // gateway address can't be bound, e.g. when it isn't assigned to any
+    // host interface or binding port 53 isn't allowed, in which case the
+    // container should use `upstream` directly.
+    pub async fn embedded_dns(&self, container_id: &str, upstream: &[IpAddr]) -> Option<IpAddr> {
+        let name = self.endpoints.lock().await.get(container_id)?
+            .addresses.first()
+            .map(|(network, _)| network.clone())?;
+        let gateway = self.networks.lock().await.get(&name)?.gateway;
         
-        if let Some(bridge_network) = networks.get_mut("bridge") {
-            let container_count = bridge_network.containers.len();
-            let ip = IpAddr::V4(Ipv4Addr::new(172, 17, 0, (container_count + 2) as u8));
-            
-            bridge_network.containers.push(container_id.to_string());
-            
-            Ok(ip)
+        let upstream: Vec<SocketAddr> = upstream.iter()
+            .map(|ip| SocketAddr::new(*ip, DNS_PORT))
+            .collect();
+        match self.start_dns(&name, SocketAddr::new(gateway, DNS_PORT), &upstream).await {
+            Ok(_) => Some(gateway),
+            Err(e) => {
+                debug!("Not running a DNS server for network {}: {}", name, e);
+                None
+            }
+        }
+    }
+    
+    // The domains of the container's networks, in the order it joined
+    // them, for its resolv.conf to search.
+    pub async fn search_domains(&self, container_id: &str) -> Vec<String> {
+        let names: Vec<String> = match self.endpoints.lock().await.get(container_id) {
+            Some(endpoint) => endpoint.addresses.iter().map(|(network, _)| network.clone()).collect(),
+            None => return Vec::new(),
+        };
+        let networks = self.networks.lock().await;
+        
+        let mut domains: Vec<String> = Vec::new();
+        for name in names {
+            if let Some(domain) = networks.get(&name).and_then(|network| network.domain.clone()) {
+                if !domains.contains(&domain) {
+                    domains.push(domain);
+                }
+            }
+        }
+        domains
+    }
+    
+    // Starts the resolver for `network` on `addr` unless it is already
+    // running, and returns the address it listens on.
+    pub async fn start_dns(&self, network: &str, addr: SocketAddr, upstream: &[SocketAddr]) -> error::Result<SocketAddr> {
+        if !self.networks.lock().await.contains_key(network) {
+            return Err(Error::NetworkNotFound(network.to_string()));
+        }
+        
+        let mut dns_servers = self.dns_servers.lock().await;
+        let addr = match dns_servers.entry(network.to_string()) {
+            Entry::Occupied(entry) => {
+                entry.get().add_upstream(upstream);
+                return Ok(entry.get().local_addr());
+            }
+            Entry::Vacant(entry) => {
+                let server = DnsServer::bind(addr, upstream.to_vec()).await?;
+                info!("DNS server for network {} listening on {}", network, server.local_addr());
+                entry.insert(server).local_addr()
+            }
+        };
+        drop(dns_servers);
+        
+        self.refresh_dns_records().await;
+        Ok(addr)
+    }
+    
+    // Each resolver answers for the hostnames and IDs of the containers on
+    // its network.
+    async fn refresh_dns_records(&self) {
+        let remote = self.remote_endpoints().await;
+        let networks = self.networks.lock().await.clone();
+        let endpoints = self.endpoints.lock().await;
+        let dns_servers = self.dns_servers.lock().await;
+        
+        for (name, server) in dns_servers.iter() {
+            let mut records: HashMap<String, Vec<IpAddr>> = HashMap::new();
+            let containers = networks.get(name).map(|network| network.containers.as_slice()).unwrap_or_default();
+            let domain = networks.get(name).and_then(|network| network.domain.as_deref());
+            let local = containers.iter().filter_map(|id| Some((id, endpoints.get(id)?)));
+            for (id, endpoint) in local.chain(remote.iter().map(|(id, endpoint)| (id, endpoint))) {
+                for (_, ip) in endpoint.addresses.iter().filter(|(network, _)| network == name) {
+                    for container_name in container_names(id, &endpoint.hostname, domain) {
+                        records.entry(container_name).or_default().push(*ip);
+                    }
+                }
+            }
+            server.set_records(records);
+        }
+    }
+    
+    // The containers on other hosts of the mesh, minus addresses a local
+    // container already has.
+    async fn remote_endpoints(&self) -> Vec<(String, Endpoint)> {
+        let Some(mesh) = self.mesh.lock().await.clone() else {
+            return Vec::new();
+        };
+        let endpoints = self.endpoints.lock().await;
+        let taken: Vec<(&String, &IpAddr)> = endpoints.values()
+            .flat_map(|endpoint| endpoint.addresses.iter().map(|(network, ip)| (network, ip)))
+            .collect();
+        
+        mesh.endpoints()
+            .into_iter()
+            .map(|(_, remote)| {
+                let mut endpoint = Endpoint::remote(&remote);
+                endpoint.addresses.retain(|(network, ip)| !taken.contains(&(network, ip)));
+                (remote.id, endpoint)
+            })
+            .filter(|(_, endpoint)| !endpoint.addresses.is_empty())
+            .collect()
+    }
+    
+    // Keeps `path` filled with the names and addresses of every container
+    // sharing a network with this one, so peers can be reached by name.
+    pub async fn attach_hosts_file(&self, container_id: &str, path: PathBuf) -> error::Result<()> {
+        match self.endpoints.lock().await.get_mut(container_id) {
+            Some(endpoint) => endpoint.hosts_file = Some(path),
+            None => return Err(anyhow!("Container {} is not on a network", container_id).into()),
+        }
+        
+        self.write_hosts_file(container_id).await?;
+        for peer in self.peers(container_id).await {
+            self.write_hosts_file(&peer).await?;
+        }
+        
+        Ok(())
+    }
+    
+    // Listens for bridged connections on `port` of every address the
+    // container has.
+    pub async fn listen(&self, container_id: &str, port: u16) -> std::io::Result<BridgeListener> {
+        if !self.endpoints.lock().await.contains_key(container_id) {
+            return Err(std::io::Error::from(std::io::ErrorKind::NetworkUnreachable));
+        }
+        self.bridge.listen(container_id, port)
+    }
+    
+    // Opens an in-memory connection from a container to a listener of
+    // itself or of a container it shares a network with. Loopback addresses
+    // reach the container's own listeners. The policy of the network the
+    // destination is on decides whether the connection is allowed.
+    pub async fn connect(&self, container_id: &str, to: SocketAddr) -> std::io::Result<tokio::io::DuplexStream> {
+        let (gateways, policies): (Vec<(String, IpAddr)>, HashMap<String, NetworkPolicy>) = {
+            let networks = self.networks.lock().await;
+            let gateways = networks.values()
+                .filter(|network| !network.policy.internal)
+                .flat_map(|network| network.subnets().map(|(_, gateway)| (network.name.clone(), gateway)))
+                .collect();
+            let policies = networks.values()
+                .map(|network| (network.name.clone(), network.policy.clone()))
+                .collect();
+            (gateways, policies)
+        };
+        let endpoints = self.endpoints.lock().await;
+        let Some(endpoint) = endpoints.get(container_id) else {
+            return Err(std::io::Error::from(std::io::ErrorKind::NetworkUnreachable));
+        };
+        // Checks a connection to `peer`, known by `peer_id`, under the policy
+        // of the network it is reached on.
+        let allowed = |peer_id: &str, peer: &Endpoint| {
+            let Some((network, _)) = peer.addresses.iter().find(|(_, ip)| *ip == to.ip()) else {
+                return true;
+            };
+            let from = [container_id, endpoint.hostname.as_str()];
+            let target = [peer_id, peer.hostname.as_str()];
+            policies.get(network).is_none_or(|policy| policy.allows(&from, &target, to.port()))
+        };
+        // Containers only on internal networks don't reach the host.
+        let internal = endpoint.addresses.iter()
+            .all(|(network, _)| policies.get(network).is_some_and(|policy| policy.internal));
+        
+        // Connections come from an address of the same family where the
+        // container has one.
+        let same_family = |ips: Vec<IpAddr>| ips.iter()
+            .find(|ip| ip.is_ipv6() == to.is_ipv6())
+            .or(ips.first())
+            .copied();
+        let own = to.ip().is_loopback() || endpoint.addresses.iter().any(|(_, ip)| *ip == to.ip());
+        // The host, at its address on one of the container's networks.
+        let host = gateways.iter()
+            .any(|(network, gateway)| *gateway == to.ip() && endpoint.addresses.iter().any(|(name, _)| name == network));
+        let (target, from) = if own {
+            (container_id, same_family(endpoint.addresses.iter().map(|(_, ip)| *ip).collect()))
+        } else if host {
+            let from = same_family(endpoint.addresses.iter().map(|(_, ip)| *ip).collect())
+                .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::NetworkUnreachable))?;
+            drop(endpoints);
+            return self.hairpin(from, to.port()).await;
         } else {
-            Ok(IpAddr::V4(Ipv4Addr::new(172, 17, 0, 2)))
+            // Only addresses on a shared network are reachable.
+            let peer = endpoints.iter()
+                .filter(|(id, _)| id.as_str() != container_id)
+                .find(|(_, peer)| peer.addresses_for(endpoint).contains(&to.ip()));
+            match peer {
+                Some((id, peer)) if !allowed(id, peer) => {
+                    return Err(std::io::Error::from(std::io::ErrorKind::PermissionDenied));
+                }
+                Some((id, peer)) => (id.as_str(), same_family(endpoint.addresses_for(peer))),
+                None => {
+                    // Containers on other hosts of the mesh come last, so
+                    // local ones win when addresses clash.
+                    let Some((mesh, host, remote)) = self.find_remote(endpoint, to.ip()).await else {
+                        return Err(std::io::Error::from(std::io::ErrorKind::HostUnreachable));
+                    };
+                    if !allowed(&remote.id, &Endpoint::remote(&remote)) {
+                        return Err(std::io::Error::from(std::io::ErrorKind::PermissionDenied));
+                    }
+                    let from = same_family(endpoint.addresses_for(&Endpoint::remote(&remote)))
+                        .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::NetworkUnreachable))?;
+                    drop(endpoints);
+                    let stream = mesh.connect(&host, &remote.id, from, to.port()).await?;
+                    return Ok(bridge::splice(stream));
+                }
+            }
+        };
+        
+        let from = from.ok_or_else(|| std::io::Error::from(std::io::ErrorKind::NetworkUnreachable))?;
+        let connected = self.bridge.connect(from, target, to.port());
+        drop(endpoints);
+        match connected {
+            Err(e) if e.kind() == std::io::ErrorKind::ConnectionRefused && !internal => {
+                self.hairpin(from, to.port()).await.map_err(|_| e)
+            }
+            connected => connected,
         }
     }
     
+    // Like Docker's hairpin NAT: a container connecting to a published host
+    // port, at the host or at any container it reaches, gets the container
+    // publishing it. Its own listeners on the port win.
+    async fn hairpin(&self, from: IpAddr, host_port: u16) -> std::io::Result<tokio::io::DuplexStream> {
+        let (container_id, container_port) = self.port_forwards.lock().await
+            .get(&(host_port, "tcp".to_string()))
+            .map(|forward| (forward.container_id.clone(), forward.container_port))
+            .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::ConnectionRefused))?;
+        self.bridge.connect(from, &container_id, container_port)
+    }
+    
+    // The container on another host of the mesh that `endpoint` reaches at
+    // `ip`, with the host running it.
+    async fn find_remote(&self, endpoint: &Endpoint, ip: IpAddr) -> Option<(Arc<Mesh>, String, RemoteEndpoint)> {
+        let mesh = self.mesh.lock().await.clone()?;
+        let (host, remote) = mesh.endpoints()
+            .into_iter()
+            .find(|(_, remote)| Endpoint::remote(remote).addresses_for(endpoint).contains(&ip))?;
+        Some((mesh, host, remote))
+    }
+    
+    // Containers sharing at least one network with `container_id`.
+    async fn peers(&self, container_id: &str) -> Vec<String> {
+        let networks = self.networks.lock().await;
+        let mut peers: Vec<String> = networks.values()
+            .filter(|network| network.containers.iter().any(|id| id == container_id))
+            .flat_map(|network| network.containers.iter())
+            .filter(|id| *id != container_id)
+            .cloned()
+            .collect();
+        peers.sort();
+        peers.dedup();
+        peers
+    }
+    
+    async fn write_hosts_file(&self, container_id: &str) -> Result<()> {
+        let peers = self.peers(container_id).await;
+        let remote = self.remote_endpoints().await;
+        let domains: HashMap<String, String> = self.networks.lock().await.values()
+            .filter_map(|network| Some((network.name.clone(), network.domain.clone()?)))
+            .collect();
+        let endpoints = self.endpoints.lock().await;
+        let Some(endpoint) = endpoints.get(container_id) else {
+            return Ok(());
+        };
+        let Some(path) = &endpoint.hosts_file else {
+            return Ok(());
+        };
+        
+        let mut hosts = String::from("127.0.0.1\tlocalhost\n::1\tlocalhost ip6-localhost ip6-loopback\n");
+        for ip in endpoint.addresses_for(endpoint) {
+            hosts.push_str(&hosts_entry(container_id, endpoint, ip, &domains));
+        }
+        for peer in &peers {
+            let Some(peer_endpoint) = endpoints.get(peer) else {
+                continue;
+            };
+            for ip in peer_endpoint.addresses_for(endpoint) {
+                hosts.push_str(&hosts_entry(peer, peer_endpoint, ip, &domains));
+            }
+        }
+        for (id, remote_endpoint) in &remote {
+            for ip in remote_endpoint.addresses_for(endpoint) {
+                hosts.push_str(&hosts_entry(id, remote_endpoint, ip, &domains));
+            }
+        }
+        
+        debug!("Updating hosts file for {} with {} peers", container_id, peers.len());
+        fs::write(path, hosts)?;
+        
+        Ok(())
+    }
+    
+    // Allocates the container an address from each of the network's
+    // subnets, IPv4 first, using `static_ip` for the subnet it belongs to.
+    async fn allocate_ips(&self, network: &str, container_id: &str, static_ip: Option<IpAddr>) -> Result<Vec<IpAddr>> {
+        let mut networks = self.networks.lock().await;
+        let network = networks.get_mut(network)
+            .ok_or_else(|| Error::NetworkNotFound(network.to_string()))?;
+        if let Some(ip) = static_ip {
+            network.check_address(ip)?;
+        }
+        
+        let mut pools = self.address_pools.lock().await;
+        let mut ips = Vec::new();
+        for (subnet, gateway) in network.subnets() {
+            let pool = match pools.entry(subnet.to_string()) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => entry.insert(AddressPool::new(parse_subnet(subnet)?, gateway)?),
+            };
+            let ip = match static_ip.filter(|ip| ip.is_ipv4() == gateway.is_ipv4()) {
+                Some(ip) => pool.reserve(container_id, ip)?,
+                None => pool.allocate(container_id)
+                    .ok_or_else(|| anyhow!("No addresses left in network {} ({})", network.name, subnet))?,
+            };
+            ips.push(ip);
+        }
+        
+        if !network.containers.iter().any(|id| id == container_id) {
+            network.containers.push(container_id.to_string());
+        }
+        
+        Ok(ips)
+    }
+    
+    // Hands over sockets passed in by systemd; see `activation::listen_fds`.
+    // The next container to start publishing their ports takes them over.
+    pub async fn adopt_activated_sockets(&self, sockets: Vec<ActivatedSocket>) {
+        self.activated.lock().await.extend(sockets);
+    }
+    
+    // Peers with the runtimes on other hosts, so containers here reach the
+    // ones there that are on a network of the same name, by name and
+    // address, and the other way round. Returns the address peers dial.
+    pub async fn join_mesh(&self, config: MeshConfig) -> error::Result<SocketAddr> {
+        let mut joined = self.mesh.lock().await;
+        if joined.is_some() {
+            return Err(anyhow!("Already in a mesh").into());
+        }
+        
+        let listener = TcpListener::bind(config.listen).await
+            .map_err(|e| anyhow!("Failed to listen for mesh peers on {}: {}", config.listen, e))?;
+        let mesh = Arc::new(Mesh::new(&config, listener.local_addr()?)?);
+        
+        let manager = self.clone();
+        let server = mesh.clone();
+        mesh.add_task(tokio::spawn(async move {
+            loop {
+                let stream = match listener.accept().await {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        debug!("Failed to accept mesh peer: {}", e);
+                        continue;
+                    }
+                };
+                let (manager, server) = (manager.clone(), server.clone());
+                tokio::spawn(async move {
+                    if let Err(e) = manager.serve_mesh_peer(&server, stream).await {
+                        debug!("Mesh peer connection failed: {}", e);
+                    }
+                });
+            }
+        }).abort_handle());
+        
+        let manager = self.clone();
+        let syncer = mesh.clone();
+        mesh.add_task(tokio::spawn(async move {
+            let mut interval = tokio::time::interval(SYNC_INTERVAL);
+            loop {
+                interval.tick().await;
+                let mut changed = syncer.expire();
+                for peer in syncer.peers() {
+                    match syncer.sync(peer, manager.local_endpoints().await).await {
+                        Ok(peer_changed) => changed |= peer_changed,
+                        Err(e) => debug!("Failed to sync with mesh peer {}: {}", peer, e),
+                    }
+                }
+                if changed {
+                    manager.refresh_remote_endpoints().await;
+                }
+            }
+        }).abort_handle());
+        
+        info!("Joined mesh on {} with {} peers", mesh.local_addr(), config.peers.len());
+        let addr = mesh.local_addr();
+        *joined = Some(mesh);
+        Ok(addr)
+    }
+    
+    pub async fn leave_mesh(&self) {
+        let mesh = self.mesh.lock().await.take();
+        if let Some(mesh) = mesh {
+            mesh.shutdown();
+            self.refresh_remote_endpoints().await;
+        }
+    }
+    
+    async fn serve_mesh_peer(&self, mesh: &Mesh, stream: TcpStream) -> io::Result<()> {
+        let (mut stream, request) = mesh.accept(stream).await?;
+        
+        match request {
+            Request::Sync { .. } => {
+                let endpoints = self.local_endpoints().await;
+                mesh::respond(&mut stream, &Response::Sync { endpoints }).await
+            }
+            Request::Connect { container_id, from, port } => {
+                let running = self.endpoints.lock().await.contains_key(&container_id);
+                let connected = match running {
+                    true => self.bridge.connect(from, &container_id, port),
+                    false => Err(io::Error::from(io::ErrorKind::HostUnreachable)),
+                };
+                match connected {
+                    Ok(mut local) => {
+                        mesh::respond(&mut stream, &Response::Connected).await?;
+                        tokio::io::copy_bidirectional(&mut stream, &mut local).await.map(|_| ())
+                    }
+                    Err(_) => mesh::respond(&mut stream, &Response::Refused).await,
+                }
+            }
+        }
+    }
+    
+    // Takes HTTP requests on `addr` and hands each to the container the
+    // first matching rule names, local or on another host of the mesh.
+    // Returns the address bound.
+    pub async fn start_ingress(&self, addr: SocketAddr, rules: Vec<IngressRule>) -> error::Result<SocketAddr> {
+        let mut ingress = self.ingress.lock().await;
+        if ingress.is_some() {
+            return Err(anyhow!("Ingress already started").into());
+        }
+        if rules.is_empty() {
+            return Err(anyhow!("Ingress needs at least one rule").into());
+        }
+        
+        let listener = TcpListener::bind(addr).await
+            .map_err(|e| anyhow!("Failed to listen for ingress on {}: {}", addr, e))?;
+        let local_addr = listener.local_addr()?;
+        info!("Ingress listening on {} with {} rules", local_addr, rules.len());
+        let rules = Arc::new(rules);
+        let manager = self.clone();
+        let task = tokio::spawn(async move {
+            let mut active = JoinSet::new();
+            loop {
+                tokio::select! {
+                    accepted = listener.accept() => {
+                        let (client, peer) = match accepted {
+                            Ok(accepted) => accepted,
+                            Err(e) => {
+                                debug!("Failed to accept on ingress: {}", e);
+                                tokio::time::sleep(Duration::from_millis(100)).await;
+                                continue;
+                            }
+                        };
+                        let (manager, rules) = (manager.clone(), rules.clone());
+                        active.spawn(async move {
+                            if let Err(e) = manager.serve_ingress(client, peer.ip(), &rules).await {
+                                debug!("Ingress connection from {} ended: {}", peer, e);
+                            }
+                        });
+                    }
+                    Some(_) = active.join_next() => {}
+                }
+            }
+        });
+        *ingress = Some(task.abort_handle());
+        Ok(local_addr)
+    }
+    
+    pub async fn stop_ingress(&self) {
+        if let Some(task) = self.ingress.lock().await.take() {
+            task.abort();
+        }
+    }
+    
+    async fn serve_ingress(&self, mut client: TcpStream, from: IpAddr, rules: &[IngressRule]) -> io::Result<()> {
+        let read = tokio::time::timeout(INGRESS_HEAD_TIMEOUT, RequestHead::read(&mut client)).await
+            .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))?;
+        let (head, body) = match read {
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                client.write_all(&ingress::error_response(400, "Bad Request")).await?;
+                return Err(e);
+            }
+            Err(e) => return Err(e),
+        };
+        let Some(rule) = ingress::route(rules, head.host(), head.path()) else {
+            return client.write_all(&ingress::error_response(404, "Not Found")).await;
+        };
+        
+        let mut upstream = match self.ingress_upstream(from, &rule.container, rule.port).await {
+            Ok(upstream) => upstream,
+            Err(e) => {
+                debug!("Ingress rule {} failed: {}", rule, e);
+                return client.write_all(&ingress::error_response(502, "Bad Gateway")).await;
+            }
+        };
+        upstream.write_all(&head.forwarded(from)).await?;
+        upstream.write_all(&body).await?;
+        tokio::io::copy_bidirectional(&mut client, &mut upstream).await.map(|_| ())
+    }
+    
+    // A connection to `port` of the container with hostname or ID
+    // `container`. Guests not listening with `net_listen` share the host's
+    // network stack, as with published ports.
+    async fn ingress_upstream(&self, from: IpAddr, container: &str, port: u16) -> io::Result<tokio::io::DuplexStream> {
+        let from = from.to_canonical();
+        let local = self.endpoints.lock().await
+            .iter()
+            .find(|(id, endpoint)| id.as_str() == container || endpoint.hostname == container)
+            .map(|(id, _)| id.clone());
+        if let Some(id) = local {
+            if let Ok(stream) = self.bridge.connect(from, &id, port) {
+                return Ok(stream);
+            }
+            let stream = TcpStream::connect(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port)).await?;
+            return Ok(bridge::splice(stream));
+        }
+        
+        let mesh = self.mesh.lock().await.clone()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::HostUnreachable))?;
+        let (host, remote) = mesh.endpoints()
+            .into_iter()
+            .find(|(_, remote)| remote.id == container || remote.hostname == container)
+            .ok_or_else(|| io::Error::from(io::ErrorKind::HostUnreachable))?;
+        let stream = mesh.connect(&host, &remote.id, from, port).await?;
+        Ok(bridge::splice(stream))
+    }
+    
+    // The containers here, as peers are told about them.
+    async fn local_endpoints(&self) -> Vec<RemoteEndpoint> {
+        self.endpoints.lock().await
+            .iter()
+            .map(|(id, endpoint)| RemoteEndpoint {
+                id: id.clone(),
+                hostname: endpoint.hostname.clone(),
+                addresses: endpoint.addresses.clone(),
+            })
+            .collect()
+    }
+    
+    // Brings resolvers and hosts files up to date with what runs on the
+    // other hosts.
+    async fn refresh_remote_endpoints(&self) {
+        self.refresh_dns_records().await;
+        let ids: Vec<String> = self.endpoints.lock().await.keys().cloned().collect();
+        for id in ids {
+            if let Err(e) = self.write_hosts_file(&id).await {
+                debug!("Failed to update hosts file for {}: {}", id, e);
+            }
+        }
+    }
+    
+    // Takes the activated socket for the host port if there is one, and
+    // binds it otherwise. With `retry`, a host port in use is swapped for
+    // the next free one after it.
+    async fn publish(&self, port_map: &PortMapping, ipv6: bool, retry: bool) -> Result<PublishedSocket> {
+        let mut activated = self.activated.lock().await;
+        let matching = activated.iter().position(|socket| {
+            socket.protocol().eq_ignore_ascii_case(&port_map.protocol)
+                && socket.port().is_ok_and(|port| port == port_map.host_port)
+        });
+        if let Some(i) = matching {
+            info!("Using activated socket for host port {}/{}", port_map.host_port, port_map.protocol);
+            return Ok(PublishedSocket::from_activated(activated.remove(i))?);
+        }
+        drop(activated);
+        
+        let protocol = port_map.protocol.to_lowercase();
+        if protocol != "tcp" && protocol != "udp" {
+            return Err(anyhow!("Unsupported protocol: {}", port_map.protocol));
+        }
+        let mut host_port = port_map.host_port;
+        loop {
+            match bind_published(host_port, &protocol, ipv6).await {
+                Ok(socket) => {
+                    if host_port != port_map.host_port {
+                        info!("Host port {}/{} is in use, publishing on {} instead", port_map.host_port, protocol, host_port);
+                    }
+                    return Ok(socket);
+                }
+                Err(e) if e.kind() == io::ErrorKind::AddrInUse => {
+                    let tried = host_port - port_map.host_port;
+                    if retry && host_port != 0 && host_port < u16::MAX && tried < PUBLISH_RETRY_LIMIT {
+                        host_port += 1;
+                        continue;
+                    }
+                    if retry && host_port != port_map.host_port {
+                        return Err(Error::NetworkConflict(format!("Host ports {}-{}/{} are all in use", port_map.host_port, host_port, protocol)).into());
+                    }
+                    return Err(self.port_conflict(host_port, &protocol).await);
+                }
+                Err(e) => return Err(anyhow!("Failed to bind host port {}/{}: {}", host_port, protocol, e)),
+            }
+        }
+    }
+    
+    // Why `host_port` can't be bound, naming the container here that
+    // publishes it if there is one.
+    async fn port_conflict(&self, host_port: u16, protocol: &str) -> anyhow::Error {
+        let owner = self.port_forwards.lock().await
+            .get(&(host_port, protocol.to_string()))
+            .map(|forward| forward.container_id.clone());
+        match owner {
+            Some(container_id) => Error::NetworkConflict(format!(
+                "Host port {}/{} is already published by container {}; publish another port or use --publish-retry",
+                host_port,
+                protocol,
+                container_id
+            )),
+            None => Error::NetworkConflict(format!(
+                "Host port {}/{} is already in use by another process; publish another port or use --publish-retry",
+                host_port,
+                protocol
+            )),
+        }.into()
+    }
+    
+    // Returns the host port actually bound, which is picked by the OS when
+    // the mapping asked for port 0.
     async fn setup_port_forward(
         &self,
         container_id: &str,
-        host_port: u16,
+        socket: PublishedSocket,
         container_port: u16,
-        protocol: &str,
-    ) -> Result<()> {
-        debug!(
-            "Setting up port forward: {}:{} -> {}:{}",
-            host_port, protocol, container_id, container_port
-        );
+        options: ForwardOptions,
+    ) -> Result<u16> {
+        let container_traffic = self.traffic.lock().await
+            .entry(container_id.to_string())
+            .or_default()
+            .clone();
+        let traffic = Arc::new(Traffic::port(container_traffic));
         
-        match protocol.to_lowercase().as_str() {
-            "tcp" => {
-                let listener = TcpListener::bind(SocketAddr::new(
-                    IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)),
-                    host_port,
-                )).await?;
+        match socket {
+            PublishedSocket::Tcp(listener) => {
+                let host_port = listener.local_addr()?.port();
+                
+                let connections = Arc::new(AtomicUsize::new(0));
+                let upstream = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), container_port);
+                let proxy = tokio::spawn(proxy_tcp(
+                    listener,
+                    self.bridge.clone(),
+                    container_id.to_string(),
+                    upstream,
+                    connections.clone(),
+                    traffic.clone(),
+                    options,
+                ));
                 
                 let port_forward = PortForward {
                     host_port,
                     container_id: container_id.to_string(),
                     container_port,
-                    protocol: protocol.to_string(),
-                    listener: Some(listener),
+                    protocol: "tcp".to_string(),
+                    connections,
+                    traffic: traffic.clone(),
+                    proxy: Some(proxy),
                 };
                 
-                self.port_forwards.lock().await.insert(host_port, port_forward);
+                self.port_forwards.lock().await.insert((host_port, "tcp".to_string()), port_forward);
                 
                 info!("TCP port forward established: {} -> {}", host_port, container_port);
+                Ok(host_port)
             }
-            "udp" => {
-                let _socket = UdpSocket::bind(SocketAddr::new(
-                    IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)),
-                    host_port,
-                )).await?;
+            PublishedSocket::Udp(socket) => {
+                let host_port = socket.local_addr()?.port();
+                
+                let connections = Arc::new(AtomicUsize::new(0));
+                let upstream = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), container_port);
+                let proxy = tokio::spawn(proxy_udp(
+                    socket,
+                    upstream,
+                    connections.clone(),
+                    options.idle_timeout.unwrap_or(self.udp_idle_timeout),
+                    options.max_connections,
+                    traffic.clone(),
+                ));
                 
                 let port_forward = PortForward {
                     host_port,
                     container_id: container_id.to_string(),
                     container_port,
-                    protocol: protocol.to_string(),
-                    listener: None,
+                    protocol: "udp".to_string(),
+                    connections,
+                    traffic: traffic.clone(),
+                    proxy: Some(proxy),
                 };
                 
-                self.port_forwards.lock().await.insert(host_port, port_forward);
+                self.port_forwards.lock().await.insert((host_port, "udp".to_string()), port_forward);
                 
                 info!("UDP port forward established: {} -> {}", host_port, container_port);
-            }
-            _ => {
-                error!("Unsupported protocol: {}", protocol);
+                Ok(host_port)
             }
         }
+    }
+    
+    // Connections currently proxied through the forward on `host_port` over
+    // `protocol`; for UDP, clients that haven't been idle for too long.
+    pub async fn active_connections(&self, host_port: u16, protocol: &str) -> Option<usize> {
+        self.port_forwards.lock().await
+            .get(&(host_port, protocol.to_string()))
+            .map(PortForward::active_connections)
+    }
+    
+    // What each of the container's published ports carried, by host port.
+    pub async fn port_stats(&self, container_id: &str) -> Vec<PortStats> {
+        let mut stats: Vec<PortStats> = self.port_forwards.lock().await
+            .values()
+            .filter(|forward| forward.container_id == container_id)
+            .map(PortForward::stats)
+            .collect();
+        stats.sort_by_key(|stats| (stats.host_port, stats.protocol.clone()));
+        stats
+    }
+    
+    // Counters for everything the container's published ports carried
+    // since it joined its networks.
+    pub async fn traffic(&self, container_id: &str) -> Option<Arc<Traffic>> {
+        self.traffic.lock().await.get(container_id).cloned()
+    }
+    
+    // Records what the container's published ports carry into a pcap file
+    // at `path` until `stop_capture`, replacing any capture already running.
+    // The container's side of each packet is its own address.
+    pub async fn start_capture(&self, container_id: &str, path: &Path) -> error::Result<()> {
+        let traffic = self.traffic(container_id).await
+            .ok_or_else(|| anyhow!("Container {} has no network", container_id))?;
+        let address = self.endpoints.lock().await
+            .get(container_id)
+            .and_then(|endpoint| endpoint.addresses.first())
+            .map_or(IpAddr::V4(Ipv4Addr::LOCALHOST), |(_, address)| *address);
+        let capture = Capture::create(path, address)
+            .map_err(|e| anyhow!("Failed to create capture file {:?}: {}", path, e))?;
+        traffic.set_capture(Some(Arc::new(capture)));
         
+        info!("Capturing traffic of {} to {:?}", container_id, path);
         Ok(())
     }
     
-    pub async fn create_network(&self, name: &str, subnet: &str) -> Result<()> {
+    pub async fn stop_capture(&self, container_id: &str) {
+        if let Some(traffic) = self.traffic(container_id).await {
+            traffic.set_capture(None);
+        }
+    }
+    
+    // Creates a user-defined network from up to one IPv4 and one IPv6
+    // subnet; giving both makes it dual-stack. Without an IPv4 subnet, the
+    // first free 172.x.0.0/16 after the default bridge's is picked. Each
+    // subnet's gateway is its first host unless one in it is given.
+    pub async fn create_network(
+        &self,
+        name: &str,
+        subnets: &[&str],
+        gateways: &[IpAddr],
+        domain: Option<&str>,
+    ) -> error::Result<Network> {
+        validate_network_name(name)?;
+        let domain = domain.map(normalize_domain).transpose()?;
         let mut networks = self.networks.lock().await;
         
-        if networks.contains_key(name) {
-            return Err(anyhow::anyhow!("Network {} already exists", name));
+        if networks.contains_key(name) || name == NO_NETWORK || name == HOST_NETWORK {
+            return Err(Error::NetworkConflict(format!("Network {} already exists", name)));
+        }
+        
+        let taken: Vec<IpNet> = networks.values()
+            .flat_map(|network| network.subnets().filter_map(|(subnet, _)| parse_subnet(subnet).ok()))
+            .collect();
+        let overlaps = |subnet: &IpNet| taken.iter()
+            .find(|other| other.contains(&subnet.network()) || subnet.contains(&other.network()))
+            .copied();
+        
+        let mut subnet4 = None;
+        let mut subnet6 = None;
+        for subnet in subnets {
+            let subnet = parse_subnet(subnet)?;
+            if let Some(other) = overlaps(&subnet) {
+                return Err(anyhow!("Subnet {} overlaps {} of another network", subnet, other).into());
+            }
+            let family = if subnet.network().is_ipv4() { &mut subnet4 } else { &mut subnet6 };
+            if family.replace(subnet).is_some() {
+                return Err(anyhow!("Network {} can have only one IPv4 and one IPv6 subnet", name).into());
+            }
+        }
+        let subnet = match subnet4 {
+            Some(subnet) => subnet,
+            None => (18..=31)
+                .map(|octet| IpNet::new(IpAddr::V4(Ipv4Addr::new(172, octet, 0, 0)), 16).unwrap())
+                .find(|subnet| overlaps(subnet).is_none())
+                .ok_or_else(|| anyhow!("No free subnet left; pass one with --subnet"))?,
+        };
+        
+        let mut gateway = None;
+        let mut gateway6 = None;
+        for &ip in gateways {
+            let (family, family_subnet) = match ip {
+                IpAddr::V4(_) => (&mut gateway, Some(subnet)),
+                IpAddr::V6(_) => (&mut gateway6, subnet6),
+            };
+            let family_subnet = family_subnet
+                .ok_or_else(|| anyhow!("Gateway {} needs an IPv6 subnet on network {}", ip, name))?;
+            check_gateway(&family_subnet, ip)?;
+            if family.replace(ip).is_some() {
+                return Err(anyhow!("Network {} can have only one IPv4 and one IPv6 gateway", name).into());
+            }
         }
         
         let network = Network {
             name: name.to_string(),
             subnet: subnet.to_string(),
-            gateway: IpAddr::V4(Ipv4Addr::new(172, 18, 0, 1)),
+            gateway: gateway.unwrap_or_else(|| first_host(&subnet)),
+            subnet6: subnet6.map(|subnet| subnet.to_string()),
+            gateway6: subnet6.map(|subnet6| gateway6.unwrap_or_else(|| first_host(&subnet6))),
+            domain,
+            policy: NetworkPolicy::default(),
             containers: Vec::new(),
+            members: Vec::new(),
         };
         
-        networks.insert(name.to_string(), network);
+        if let Some(dir) = &self.networks_dir {
+            fs::write(network_file(dir, name), serde_json::to_string_pretty(&network).map_err(anyhow::Error::from)?)?;
+        }
+        networks.insert(name.to_string(), network.clone());
         
-        info!("Created network: {} with subnet: {}", name, subnet);
+        info!("Created network: {} with subnet: {}", name, network.subnet);
         
-        Ok(())
+        Ok(network)
     }
     
-    pub async fn list_networks(&self) -> Result<Vec<Network>> {
-        let networks = self.networks.lock().await;
-        Ok(networks.values().cloned().collect())
+    // Replaces the network's policy. Connections are checked as they are
+    // made, so open ones are left alone.
+    pub async fn set_network_policy(&self, name: &str, policy: NetworkPolicy) -> error::Result<Network> {
+        if name == DEFAULT_NETWORK || name == NO_NETWORK || name == HOST_NETWORK {
+            return Err(anyhow!("Network {} is predefined and its policy can't be changed", name).into());
+        }
+        let mut networks = self.networks.lock().await;
+        let network = networks.get_mut(name)
+            .ok_or_else(|| Error::NetworkNotFound(name.to_string()))?;
+        network.policy = policy;
+        
+        if let Some(dir) = &self.networks_dir {
+            fs::write(network_file(dir, name), serde_json::to_string_pretty(&network).map_err(anyhow::Error::from)?)?;
+        }
+        
+        Ok(network.clone())
     }
     
-    pub async fn get_container_ip(&self, container_id: &str) -> Result<Option<IpAddr>> {
+    // Whether every network of the container is internal, leaving it no
+    // way out to the host or beyond.
+    pub async fn internal(&self, container_id: &str) -> bool {
+        let Some(names) = self.endpoints.lock().await
+            .get(container_id)
+            .map(|endpoint| endpoint.addresses.iter().map(|(network, _)| network.clone()).collect::<Vec<_>>())
+        else {
+            return false;
+        };
         let networks = self.networks.lock().await;
+        names.iter().all(|name| networks.get(name).is_some_and(|network| network.policy.internal))
+    }
+    
+    pub async fn remove_network(&self, name: &str) -> error::Result<()> {
+        if name == DEFAULT_NETWORK {
+            return Err(anyhow!("Network {} is predefined and can't be removed", name).into());
+        }
+        
+        let mut networks = self.networks.lock().await;
+        let network = networks.get(name).ok_or_else(|| Error::NetworkNotFound(name.to_string()))?;
+        if !network.containers.is_empty() {
+            return Err(anyhow!("Network {} has active containers: {}", name, network.containers.join(", ")).into());
+        }
+        
+        if let Some(dir) = &self.networks_dir {
+            fs::remove_file(network_file(dir, name))?;
+        }
+        let network = networks.remove(name);
+        drop(networks);
+        let mut pools = self.address_pools.lock().await;
+        for (subnet, _) in network.iter().flat_map(Network::subnets) {
+            pools.remove(subnet);
+        }
+        drop(pools);
+        
+        if let Some(server) = self.dns_servers.lock().await.remove(name) {
+            server.shutdown().await;
+        }
+        
+        info!("Removed network: {}", name);
+        
+        Ok(())
+    }
+    
+    pub async fn inspect_network(&self, name: &str) -> error::Result<Network> {
+        let mut network = self.networks.lock().await
+            .get(name)
+            .cloned()
+            .ok_or_else(|| Error::NetworkNotFound(name.to_string()))?;
         
-        for network in networks.values() {
-            if let Some(index) = network.containers.iter().position(|id| id == container_id) {
-                let ip = match network.name.as_str() {
-                    "bridge" => IpAddr::V4(Ipv4Addr::new(172, 17, 0, (index + 2) as u8)),
-                    _ => IpAddr::V4(Ipv4Addr::new(172, 18, 0, (index + 2) as u8)),
+        for (id, endpoint) in self.endpoints.lock().await.iter() {
+            let ips: Vec<IpAddr> = endpoint.addresses.iter()
+                .filter(|(network, _)| network == name)
+                .map(|(_, ip)| *ip)
+                .collect();
+            if !ips.is_empty() {
+                network.members.push(NetworkMember::new(id, &endpoint.hostname, ips, network.domain.as_deref()));
+            }
+        }
+        network.members.sort_by(|a, b| a.id.cmp(&b.id));
+        
+        Ok(network)
+    }
+    
+    pub async fn list_networks(&self) -> error::Result<Vec<Network>> {
+        let networks = self.networks.lock().await;
+        let mut networks: Vec<Network> = networks.values().cloned().collect();
+        networks.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(networks)
+    }
+    
+    pub async fn get_container_ip(&self, container_id: &str) -> error::Result<Option<IpAddr>> {
+        Ok(self.endpoints.lock().await.get(container_id).and_then(Endpoint::ip))
+    }
+}
+
+fn predefined_networks() -> HashMap<String, Network> {
+    let mut networks = HashMap::new();
+    
+    networks.insert(
+        DEFAULT_NETWORK.to_string(),
+        Network {
+            name: DEFAULT_NETWORK.to_string(),
+            subnet: "172.17.0.0/16".to_string(),
+            gateway: IpAddr::V4(Ipv4Addr::new(172, 17, 0, 1)),
+            subnet6: None,
+            gateway6: None,
+            domain: None,
+            policy: NetworkPolicy::default(),
+            containers: Vec::new(),
+            members: Vec::new(),
+        }
+    );
+    
+    networks
+}
+
+fn network_file(networks_dir: &Path, name: &str) -> PathBuf {
+    networks_dir.join(format!("{}.json", name))
+}
+
+// Network names end up in file names, so they follow the volume name rules.
+// Domains are kept lowercase and without the dots around them, so
+// `.Internal.` and `internal` are the same domain.
+fn normalize_domain(domain: &str) -> Result<String> {
+    let normalized = domain.trim_matches('.').to_ascii_lowercase();
+    let valid = !normalized.is_empty()
+        && normalized.len() <= 253
+        && normalized.split('.').all(|label| {
+            (1..=63).contains(&label.len())
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+        });
+    
+    if !valid {
+        return Err(anyhow!("Invalid network domain: {:?}", domain));
+    }
+    
+    Ok(normalized)
+}
+
+fn validate_network_name(name: &str) -> Result<()> {
+    let mut chars = name.chars();
+    
+    let valid = match chars.next() {
+        Some(first) => first.is_ascii_alphanumeric()
+            && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-')),
+        None => false,
+    };
+    
+    if !valid {
+        return Err(anyhow!(
+            "Invalid network name: {:?} (must match [a-zA-Z0-9][a-zA-Z0-9_.-]*)",
+            name
+        ));
+    }
+    
+    Ok(())
+}
+
+enum PublishedSocket {
+    Tcp(TcpListener),
+    Udp(UdpSocket),
+}
+
+impl PublishedSocket {
+    fn from_activated(socket: ActivatedSocket) -> io::Result<Self> {
+        match socket {
+            ActivatedSocket::Tcp(listener) => TcpListener::from_std(listener).map(Self::Tcp),
+            ActivatedSocket::Udp(socket) => UdpSocket::from_std(socket).map(Self::Udp),
+        }
+    }
+}
+
+async fn bind_published(port: u16, protocol: &str, ipv6: bool) -> io::Result<PublishedSocket> {
+    debug!("Binding host port {}/{}", port, protocol);
+    
+    let addrs = published_addrs(port, ipv6);
+    match protocol {
+        "udp" => UdpSocket::bind(&addrs[..]).await.map(PublishedSocket::Udp),
+        _ => TcpListener::bind(&addrs[..]).await.map(PublishedSocket::Tcp),
+    }
+}
+
+// Where a port is published. Containers with an IPv6 address get theirs on
+// [::], which takes IPv4 connections too; 0.0.0.0 is the fallback for hosts
+// without IPv6.
+fn published_addrs(port: u16, ipv6: bool) -> Vec<SocketAddr> {
+    let mut addrs = vec![SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port)];
+    if ipv6 {
+        addrs.insert(0, SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), port));
+    }
+    addrs
+}
+
+// Pipes every connection accepted on `listener` to the guest's own listener.
+// Guests listening with `net_listen` are reached over the bridge, without
+// leaving the sandbox; otherwise the guest shares the host's network stack
+// and is reached on loopback at the container port. Connections live in a
+// JoinSet owned by this task, so aborting it closes them all. Connections
+// over `options.max_connections` are closed as soon as they are accepted.
+async fn proxy_tcp(
+    listener: TcpListener,
+    bridge: Bridge,
+    container_id: String,
+    upstream: SocketAddr,
+    connections: Arc<AtomicUsize>,
+    traffic: Arc<Traffic>,
+    options: ForwardOptions,
+) {
+    let mut active = JoinSet::new();
+    
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let (client, peer) = match accepted {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        debug!("Failed to accept on {:?}: {}", listener.local_addr(), e);
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                        continue;
+                    }
                 };
-                return Ok(Some(ip));
+                if !options.admits(connections.load(Ordering::Relaxed)) {
+                    debug!("Turning away {}: {:?} is at its connection limit", peer, listener.local_addr());
+                    continue;
+                }
+                if let Some(keepalive) = options.keepalive {
+                    let probes = socket2::TcpKeepalive::new().with_time(keepalive).with_interval(keepalive);
+                    if let Err(e) = socket2::SockRef::from(&client).set_tcp_keepalive(&probes) {
+                        debug!("Failed to enable keep-alive for {}: {}", peer, e);
+                    }
+                }
+                
+                let connections = connections.clone();
+                connections.fetch_add(1, Ordering::Relaxed);
+                // Connected before spawning, so the guest's listener sees
+                // connections in the order they were accepted.
+                let bridged = bridge.connect(peer.ip().to_canonical(), &container_id, upstream.port());
+                let traffic = traffic.clone();
+                let tap = TcpTap::new(peer, upstream.port());
+                active.spawn(async move {
+                    if let Ok(server) = bridged {
+                        if let Err(e) = throttle::relay(client, server, &traffic, options.idle_timeout, &tap).await {
+                            debug!("Bridged connection from {} ended: {}", peer, e);
+                        }
+                        connections.fetch_sub(1, Ordering::Relaxed);
+                        return;
+                    }
+                    
+                    match TcpStream::connect(upstream).await {
+                        Ok(server) => {
+                            if let Err(e) = throttle::relay(client, server, &traffic, options.idle_timeout, &tap).await {
+                                debug!("Proxied connection from {} ended: {}", peer, e);
+                            }
+                        }
+                        Err(e) => debug!("Failed to reach {} for {}: {}", upstream, peer, e),
+                    }
+                    connections.fetch_sub(1, Ordering::Relaxed);
+                });
             }
+            Some(_) = active.join_next() => {}
+        }
+    }
+}
+
+// A UDP client of a forwarded port. Each client gets its own socket towards
+// the guest, so replies can be told apart and sent back to the right client.
+struct UdpSession {
+    upstream: Arc<UdpSocket>,
+    last_seen: Arc<std::sync::Mutex<Instant>>,
+    relay: AbortHandle,
+}
+
+// Relays datagrams between clients of the host port and the guest's socket
+// on the container port, reached on loopback like the TCP proxy's. Datagrams
+// from new clients are dropped while `max_clients` have relays.
+async fn proxy_udp(
+    socket: UdpSocket,
+    upstream: SocketAddr,
+    connections: Arc<AtomicUsize>,
+    idle_timeout: Duration,
+    max_clients: Option<usize>,
+    traffic: Arc<Traffic>,
+) {
+    let socket = Arc::new(socket);
+    let mut sessions: HashMap<SocketAddr, UdpSession> = HashMap::new();
+    let mut relays = JoinSet::new();
+    let mut sweep = tokio::time::interval((idle_timeout / 2).max(MIN_UDP_SWEEP));
+    let mut buf = vec![0u8; MAX_DATAGRAM_SIZE];
+    
+    loop {
+        tokio::select! {
+            received = socket.recv_from(&mut buf) => {
+                let (len, client) = match received {
+                    Ok(received) => received,
+                    Err(e) => {
+                        debug!("Failed to receive on {:?}: {}", socket.local_addr(), e);
+                        continue;
+                    }
+                };
+                
+                let full = max_clients.is_some_and(|max| sessions.len() >= max);
+                let session = match sessions.entry(client) {
+                    Entry::Occupied(entry) => entry.into_mut(),
+                    Entry::Vacant(_) if full => {
+                        debug!("Dropping datagram from {}: too many UDP clients", client);
+                        continue;
+                    }
+                    Entry::Vacant(entry) => {
+                        match open_udp_session(&socket, client, upstream, traffic.clone(), &mut relays).await {
+                            Ok(session) => entry.insert(session),
+                            Err(e) => {
+                                debug!("Failed to relay datagrams from {}: {}", client, e);
+                                continue;
+                            }
+                        }
+                    }
+                };
+                *session.last_seen.lock().unwrap_or_else(|e| e.into_inner()) = Instant::now();
+                if let Some(capture) = traffic.capture() {
+                    capture.udp(client, upstream.port(), true, &buf[..len]);
+                }
+                traffic.receive(len).await;
+                if traffic.netem().lose() {
+                    continue;
+                }
+                let delay = traffic.netem().next_delay();
+                if delay.is_zero() {
+                    if let Err(e) = session.upstream.send(&buf[..len]).await {
+                        debug!("Failed to relay datagram from {} to {}: {}", client, upstream, e);
+                    }
+                } else {
+                    let (to, datagram) = (session.upstream.clone(), buf[..len].to_vec());
+                    relays.spawn(async move {
+                        tokio::time::sleep(delay).await;
+                        if let Err(e) = to.send(&datagram).await {
+                            debug!("Failed to relay datagram from {} to {}: {}", client, upstream, e);
+                        }
+                    });
+                }
+            }
+            _ = sweep.tick() => {
+                sessions.retain(|client, session| {
+                    let idle = session.last_seen.lock().unwrap_or_else(|e| e.into_inner()).elapsed();
+                    if idle < idle_timeout {
+                        return true;
+                    }
+                    debug!("Dropping idle UDP relay for {}", client);
+                    session.relay.abort();
+                    false
+                });
+            }
+            Some(_) = relays.join_next() => {}
         }
         
-        Ok(None)
+        connections.store(sessions.len(), Ordering::Relaxed);
+    }
+}
+
+async fn open_udp_session(
+    socket: &Arc<UdpSocket>,
+    client: SocketAddr,
+    upstream: SocketAddr,
+    traffic: Arc<Traffic>,
+    relays: &mut JoinSet<()>,
+) -> Result<UdpSession> {
+    let upstream_socket = Arc::new(UdpSocket::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0)).await?);
+    upstream_socket.connect(upstream).await?;
+    let last_seen = Arc::new(std::sync::Mutex::new(Instant::now()));
+    
+    let replies = upstream_socket.clone();
+    let seen = last_seen.clone();
+    let socket = socket.clone();
+    let relay = relays.spawn(async move {
+        let mut buf = vec![0u8; MAX_DATAGRAM_SIZE];
+        // Replies held back by netem, dropped with the session.
+        let mut delayed = JoinSet::new();
+        loop {
+            // Errors here are mostly ICMP unreachables while the guest isn't
+            // listening yet, which don't end the session.
+            let len = tokio::select! {
+                received = replies.recv(&mut buf) => match received {
+                    Ok(len) => len,
+                    Err(e) => {
+                        debug!("Failed to receive reply for {}: {}", client, e);
+                        continue;
+                    }
+                },
+                Some(_) = delayed.join_next() => continue,
+            };
+            *seen.lock().unwrap_or_else(|e| e.into_inner()) = Instant::now();
+            if let Some(capture) = traffic.capture() {
+                capture.udp(client, upstream.port(), false, &buf[..len]);
+            }
+            traffic.send(len).await;
+            if traffic.netem().lose() {
+                continue;
+            }
+            let delay = traffic.netem().next_delay();
+            if delay.is_zero() {
+                if let Err(e) = socket.send_to(&buf[..len], client).await {
+                    debug!("Failed to relay reply to {}: {}", client, e);
+                }
+            } else {
+                let (socket, datagram) = (socket.clone(), buf[..len].to_vec());
+                delayed.spawn(async move {
+                    tokio::time::sleep(delay).await;
+                    if let Err(e) = socket.send_to(&datagram, client).await {
+                        debug!("Failed to relay reply to {}: {}", client, e);
+                    }
+                });
+            }
+        }
+    });
+    
+    Ok(UdpSession {
+        upstream: upstream_socket,
+        last_seen,
+        relay,
+    })
+}
+
+// The names of the container at `ip`, qualified by the domain of the
+// network the address is on.
+fn hosts_entry(container_id: &str, endpoint: &Endpoint, ip: IpAddr, domains: &HashMap<String, String>) -> String {
+    let domain = endpoint.addresses.iter()
+        .find(|(_, address)| *address == ip)
+        .and_then(|(network, _)| domains.get(network))
+        .map(String::as_str);
+    format!("{}\t{}\n", ip, container_names(container_id, &endpoint.hostname, domain).join(" "))
+}
+
+// Hostname first, then the ID if it differs, each also under `domain`.
+fn container_names(container_id: &str, hostname: &str, domain: Option<&str>) -> Vec<String> {
+    let mut names = vec![hostname.to_string()];
+    if hostname != container_id {
+        names.push(container_id.to_string());
+    }
+    if let Some(domain) = domain {
+        let qualified: Vec<String> = names.iter().map(|name| format!("{}.{}", name, domain)).collect();
+        names.splice(0..0, qualified);
     }
+    names
 }
 
 #[derive(Debug)]
 pub struct ContainerNetwork {
     pub container_id: String,
     pub ip_address: IpAddr,
+    // The first IPv6 address, when one of the container's networks is
+    // dual-stack.
+    pub ip6_address: Option<IpAddr>,
+    // The container's address on each of its networks.
+    pub addresses: Vec<(String, IpAddr)>,
     pub hostname: String,
     pub port_mappings: Vec<crate::container::PortMapping>,
 }
@@ -0,0 +1,147 @@
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::str::FromStr;
+use std::time::Duration;
+
+use super::throttle::Rate;
+
+// How late a lost TCP segment arrives: Linux's minimum retransmission
+// timeout.
+pub const RETRANSMIT_DELAY: Duration = Duration::from_millis(200);
+
+// Impairments for testing how a service copes with a bad network, written
+// like `tc netem` options: `delay=100ms,jitter=20ms,loss=1%,rate=1mbit`.
+// Datagrams that are lost are dropped; TCP can't lose bytes, so lost chunks
+// of a stream are delivered late instead, as if retransmitted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Netem {
+    pub delay: Duration,
+    // Each packet's delay varies by up to this much either way.
+    pub jitter: Duration,
+    // Percent of packets lost.
+    pub loss: f64,
+    pub rate: Option<Rate>,
+}
+
+impl Netem {
+    pub fn delays(&self) -> bool {
+        !self.delay.is_zero() || !self.jitter.is_zero()
+    }
+    
+    // The delay for the next packet, jitter included.
+    pub fn next_delay(&self) -> Duration {
+        if self.jitter.is_zero() {
+            return self.delay;
+        }
+        let offset = self.jitter.mul_f64(random() * 2.0);
+        (self.delay + offset).saturating_sub(self.jitter)
+    }
+    
+    pub fn lose(&self) -> bool {
+        self.loss > 0.0 && random() * 100.0 < self.loss
+    }
+}
+
+impl FromStr for Netem {
+    type Err = anyhow::Error;
+    
+    fn from_str(s: &str) -> Result<Self> {
+        let mut netem = Self::default();
+        
+        for option in s.split(',').map(str::trim).filter(|option| !option.is_empty()) {
+            let invalid = || anyhow!("Invalid netem option: {:?} (expected delay=, jitter=, loss= or rate=)", option);
+            let (key, value) = option.split_once('=').ok_or_else(invalid)?;
+            match key {
+                "delay" => netem.delay = parse_duration(value)?,
+                "jitter" => netem.jitter = parse_duration(value)?,
+                "loss" => {
+                    let loss: f64 = value.strip_suffix('%').unwrap_or(value).parse().map_err(|_| invalid())?;
+                    if !(0.0..=100.0).contains(&loss) {
+                        return Err(anyhow!("Netem loss must be between 0% and 100%: {}", value));
+                    }
+                    netem.loss = loss;
+                }
+                "rate" => netem.rate = Some(value.parse()?),
+                _ => return Err(invalid()),
+            }
+        }
+        
+        Ok(netem)
+    }
+}
+
+impl TryFrom<String> for Netem {
+    type Error = anyhow::Error;
+    
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<Netem> for String {
+    fn from(netem: Netem) -> Self {
+        netem.to_string()
+    }
+}
+
+impl fmt::Display for Netem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut options = Vec::new();
+        if !self.delay.is_zero() {
+            options.push(format!("delay={}", format_duration(self.delay)));
+        }
+        if !self.jitter.is_zero() {
+            options.push(format!("jitter={}", format_duration(self.jitter)));
+        }
+        if self.loss > 0.0 {
+            options.push(format!("loss={}%", self.loss));
+        }
+        if let Some(rate) = self.rate {
+            options.push(format!("rate={}", rate));
+        }
+        write!(f, "{}", options.join(","))
+    }
+}
+
+// `250us`, `100ms`, `1.5s`, `5m`, `1h`.
+pub(super) fn parse_duration(value: &str) -> Result<Duration> {
+    let invalid = || anyhow!("Invalid duration: {:?} (expected e.g. 100ms, 1s or 5m)", value);
+    let (number, scale) = [("us", 1e-6), ("ms", 1e-3), ("s", 1.0), ("m", 60.0), ("h", 3600.0)]
+        .into_iter()
+        .find_map(|(unit, scale)| value.strip_suffix(unit).map(|number| (number, scale)))
+        .ok_or_else(invalid)?;
+    let number: f64 = number.parse().map_err(|_| invalid())?;
+    Duration::try_from_secs_f64(number * scale).map_err(|_| invalid())
+}
+
+pub(super) fn format_duration(duration: Duration) -> String {
+    if !duration.subsec_micros().is_multiple_of(1000) {
+        format!("{}us", duration.as_micros())
+    } else if duration.subsec_millis() == 0 && !duration.is_zero() {
+        format!("{}s", duration.as_secs())
+    } else {
+        format!("{}ms", duration.as_millis())
+    }
+}
+
+// A uniform sample from [0, 1). xorshift is plenty for picking which
+// packets to impair.
+fn random() -> f64 {
+    thread_local! {
+        static STATE: Cell<u64> = Cell::new(RandomState::new().build_hasher().finish() | 1);
+    }
+    
+    STATE.with(|state| {
+        let mut x = state.get();
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        state.set(x);
+        (x >> 11) as f64 / (1u64 << 53) as f64
+    })
+}
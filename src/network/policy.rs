@@ -0,0 +1,157 @@
+use anyhow::{Result, anyhow};
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+
+// What happens to connections no rule matches.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EgressDefault {
+    #[default]
+    Allow,
+    Deny,
+}
+
+impl FromStr for EgressDefault {
+    type Err = anyhow::Error;
+    
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "allow" => Ok(Self::Allow),
+            "deny" => Ok(Self::Deny),
+            _ => Err(anyhow!("Unknown egress default: {} (expected allow or deny)", s)),
+        }
+    }
+}
+
+impl fmt::Display for EgressDefault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Allow => write!(f, "allow"),
+            Self::Deny => write!(f, "deny"),
+        }
+    }
+}
+
+// A destination in an egress rule, written `host[:port]`. The host is a
+// name, an address or a CIDR; IPv6 addresses take brackets when a port is
+// given, e.g. `[2001:db8::1]:443`. Without a port, every port matches.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HostRule {
+    pub host: String,
+    pub port: Option<u16>,
+}
+
+impl FromStr for HostRule {
+    type Err = anyhow::Error;
+    
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || anyhow!("Invalid host: {:?} (expected host[:port])", s);
+        
+        let (host, port) = if let Some(rest) = s.strip_prefix('[') {
+            let (host, rest) = rest.split_once(']').ok_or_else(invalid)?;
+            match rest {
+                "" => (host, None),
+                _ => (host, Some(rest.strip_prefix(':').ok_or_else(invalid)?)),
+            }
+        } else if s.matches(':').count() > 1 {
+            // A bare IPv6 address or CIDR.
+            (s, None)
+        } else {
+            match s.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (s, None),
+            }
+        };
+        
+        if host.is_empty() {
+            return Err(invalid());
+        }
+        let port = match port {
+            Some(port) => Some(port.parse::<u16>().ok().filter(|port| *port != 0).ok_or_else(invalid)?),
+            None => None,
+        };
+        
+        Ok(Self {
+            host: host.to_string(),
+            port,
+        })
+    }
+}
+
+impl fmt::Display for HostRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.port {
+            Some(port) if self.host.contains(':') => write!(f, "[{}]:{}", self.host, port),
+            Some(port) => write!(f, "{}:{}", self.host, port),
+            None => write!(f, "{}", self.host),
+        }
+    }
+}
+
+// Where a container may open connections to. Deny rules win over allow
+// rules, and destinations neither matches get the default.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EgressPolicy {
+    #[serde(default)]
+    pub default: EgressDefault,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allow: Vec<HostRule>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deny: Vec<HostRule>,
+}
+
+impl EgressPolicy {
+    // Looks up the names in the rules once, at start, so connections can be
+    // checked by address.
+    pub async fn resolve(&self) -> Result<ResolvedEgress> {
+        Ok(ResolvedEgress {
+            default: self.default,
+            allow: resolve_rules(&self.allow).await?,
+            deny: resolve_rules(&self.deny).await?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ResolvedEgress {
+    default: EgressDefault,
+    allow: Vec<(IpNet, Option<u16>)>,
+    deny: Vec<(IpNet, Option<u16>)>,
+}
+
+impl ResolvedEgress {
+    pub fn allows(&self, addr: SocketAddr) -> bool {
+        let ip = addr.ip().to_canonical();
+        let matches = |rules: &[(IpNet, Option<u16>)]| rules.iter()
+            .any(|(net, port)| net.contains(&ip) && port.is_none_or(|port| port == addr.port()));
+        
+        if matches(&self.deny) {
+            false
+        } else if matches(&self.allow) {
+            true
+        } else {
+            self.default == EgressDefault::Allow
+        }
+    }
+}
+
+async fn resolve_rules(rules: &[HostRule]) -> Result<Vec<(IpNet, Option<u16>)>> {
+    let mut resolved = Vec::new();
+    
+    for rule in rules {
+        if let Ok(net) = rule.host.parse::<IpNet>() {
+            resolved.push((net.trunc(), rule.port));
+        } else if let Ok(ip) = rule.host.parse::<IpAddr>() {
+            resolved.push((IpNet::from(ip), rule.port));
+        } else {
+            let addrs = tokio::net::lookup_host((rule.host.as_str(), 0)).await
+                .map_err(|e| anyhow!("Failed to resolve egress host {}: {}", rule.host, e))?;
+            resolved.extend(addrs.map(|addr| (IpNet::from(addr.ip()), rule.port)));
+        }
+    }
+    
+    Ok(resolved)
+}
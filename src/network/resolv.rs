@@ -0,0 +1,85 @@
+use std::fmt;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr};
+
+const HOST_RESOLV_CONF: &str = "/etc/resolv.conf";
+
+// Used when no source names a nameserver the guest can reach.
+const FALLBACK_NAMESERVERS: [IpAddr; 2] = [
+    IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8)),
+    IpAddr::V4(Ipv4Addr::new(8, 8, 4, 4)),
+];
+
+// The parts of resolv.conf the runtime manages for a container.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ResolvConf {
+    pub nameservers: Vec<IpAddr>,
+    pub search: Vec<String>,
+}
+
+impl ResolvConf {
+    pub fn new(nameservers: Vec<IpAddr>, search: Vec<String>) -> Self {
+        Self { nameservers, search }
+    }
+    
+    // Reads `nameserver`, `search` and `domain` lines; everything else,
+    // including unparseable addresses, is ignored.
+    pub fn parse(contents: &str) -> Self {
+        let mut conf = Self::default();
+        
+        for line in contents.lines() {
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some("nameserver") => {
+                    if let Some(ip) = words.next().and_then(|ip| ip.parse().ok()) {
+                        conf.nameservers.push(ip);
+                    }
+                }
+                // The last search or domain line wins, as in the resolver.
+                Some("search") | Some("domain") => {
+                    conf.search = words.map(str::to_string).collect();
+                }
+                _ => {}
+            }
+        }
+        
+        conf
+    }
+    
+    // The host's settings, minus loopback nameservers such as systemd-resolved's
+    // 127.0.0.53, which the guest can't reach.
+    pub fn from_host() -> Self {
+        let mut conf = fs::read_to_string(HOST_RESOLV_CONF)
+            .map(|contents| Self::parse(&contents))
+            .unwrap_or_default();
+        conf.nameservers.retain(|ip| !ip.is_loopback());
+        conf
+    }
+    
+    // Nameservers and search domains are each taken from the first source
+    // that sets them, e.g. `[container flags, config file, host]`.
+    pub fn layered(sources: &[ResolvConf]) -> Self {
+        let nameservers = sources.iter()
+            .find(|source| !source.nameservers.is_empty())
+            .map(|source| source.nameservers.clone())
+            .unwrap_or_else(|| FALLBACK_NAMESERVERS.to_vec());
+        let search = sources.iter()
+            .find(|source| !source.search.is_empty())
+            .map(|source| source.search.clone())
+            .unwrap_or_default();
+        
+        Self { nameservers, search }
+    }
+}
+
+impl fmt::Display for ResolvConf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.search.is_empty() {
+            writeln!(f, "search {}", self.search.join(" "))?;
+        }
+        for nameserver in &self.nameservers {
+            writeln!(f, "nameserver {}", nameserver)?;
+        }
+        Ok(())
+    }
+}
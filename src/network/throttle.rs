@@ -0,0 +1,414 @@
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc;
+
+use super::capture::{Capture, TcpTap};
+use super::netem::{Netem, RETRANSMIT_DELAY};
+
+const RELAY_BUFFER_SIZE: usize = 16 * 1024;
+
+// Chunks a delayed stream holds before reading from the sender stops.
+const DELAY_QUEUE_SIZE: usize = 256;
+
+// Units as `tc` writes them: `bit` suffixes count bits, `bps` suffixes
+// bytes, and the prefixes are decimal.
+const UNITS: [(&str, f64); 8] = [
+    ("gbit", 1e9 / 8.0),
+    ("mbit", 1e6 / 8.0),
+    ("kbit", 1e3 / 8.0),
+    ("bit", 1.0 / 8.0),
+    ("gbps", 1e9),
+    ("mbps", 1e6),
+    ("kbps", 1e3),
+    ("bps", 1.0),
+];
+
+// A bandwidth, e.g. `10mbit`, kept in bytes per second.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Rate(u64);
+
+impl Rate {
+    pub fn bytes_per_sec(&self) -> u64 {
+        self.0
+    }
+}
+
+impl FromStr for Rate {
+    type Err = anyhow::Error;
+    
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || anyhow!("Invalid rate: {:?} (expected e.g. 500kbit, 10mbit or 1mbps)", s);
+        let lower = s.to_ascii_lowercase();
+        let (number, scale) = UNITS.iter()
+            .find_map(|(unit, scale)| lower.strip_suffix(unit).map(|number| (number, *scale)))
+            .ok_or_else(invalid)?;
+        let number: f64 = number.parse().map_err(|_| invalid())?;
+        
+        let bytes = (number * scale).round();
+        if !bytes.is_finite() || bytes < 1.0 || bytes > u64::MAX as f64 {
+            return Err(invalid());
+        }
+        Ok(Self(bytes as u64))
+    }
+}
+
+impl TryFrom<String> for Rate {
+    type Error = anyhow::Error;
+    
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<Rate> for String {
+    fn from(rate: Rate) -> Self {
+        rate.to_string()
+    }
+}
+
+// In bits with the largest prefix that keeps the number whole, falling back
+// to bytes for rates that aren't a whole number of bits per second.
+impl fmt::Display for Rate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bits = self.0 as u128 * 8;
+        for (unit, scale) in [("gbit", 1_000_000_000), ("mbit", 1_000_000), ("kbit", 1_000)] {
+            if bits.is_multiple_of(scale) {
+                return write!(f, "{}{}", bits / scale, unit);
+            }
+        }
+        write!(f, "{}bps", self.0)
+    }
+}
+
+// Lets bytes through at `rate` on average, in bursts of up to a tenth of a
+// second's worth. Callers going over wait until the debt is paid off, so a
+// single large write is delayed rather than refused.
+#[derive(Debug)]
+pub struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    state: Mutex<(f64, Instant)>,
+}
+
+impl TokenBucket {
+    pub fn new(rate: Rate) -> Self {
+        let rate = rate.bytes_per_sec() as f64;
+        let burst = (rate / 10.0).max(RELAY_BUFFER_SIZE as f64);
+        Self {
+            rate,
+            burst,
+            state: Mutex::new((burst, Instant::now())),
+        }
+    }
+    
+    pub async fn take(&self, bytes: usize) {
+        let wait = {
+            let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+            let (tokens, last) = &mut *state;
+            let now = Instant::now();
+            *tokens = (*tokens + now.duration_since(*last).as_secs_f64() * self.rate).min(self.burst);
+            *last = now;
+            *tokens -= bytes as f64;
+            if *tokens < 0.0 {
+                Duration::from_secs_f64(-*tokens / self.rate)
+            } else {
+                Duration::ZERO
+            }
+        };
+        
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+// What passed through a container's published ports, shared by all of
+// them. `received` counts bytes from clients to the guest, `sent` the
+// replies, each limited to the container's rate if it has one and impaired
+// as its netem settings say.
+#[derive(Debug, Default)]
+pub struct Traffic {
+    received: AtomicU64,
+    sent: AtomicU64,
+    inbound: Option<TokenBucket>,
+    outbound: Option<TokenBucket>,
+    limit: Option<Rate>,
+    netem: Netem,
+    // Set for a single port's traffic, which is throttled and counted as
+    // the container's too.
+    container: Option<Arc<Traffic>>,
+    // Where the container's traffic is being recorded, if anywhere.
+    capture: Mutex<Option<Arc<Capture>>>,
+}
+
+impl Traffic {
+    // A netem rate caps the traffic like a network rate, the lower winning.
+    pub fn new(limit: Option<Rate>, netem: Netem) -> Self {
+        let limit = match (limit, netem.rate) {
+            (Some(limit), Some(rate)) => Some(limit.min(rate)),
+            (limit, rate) => limit.or(rate),
+        };
+        Self {
+            inbound: limit.map(TokenBucket::new),
+            outbound: limit.map(TokenBucket::new),
+            limit,
+            netem,
+            ..Self::default()
+        }
+    }
+    
+    // Counts what one of the container's published ports carries.
+    pub fn port(container: Arc<Traffic>) -> Self {
+        Self {
+            limit: container.limit,
+            netem: container.netem,
+            container: Some(container),
+            ..Self::default()
+        }
+    }
+    
+    pub fn netem(&self) -> &Netem {
+        &self.netem
+    }
+    
+    pub fn received(&self) -> u64 {
+        self.received.load(Ordering::Relaxed)
+    }
+    
+    pub fn sent(&self) -> u64 {
+        self.sent.load(Ordering::Relaxed)
+    }
+    
+    pub fn limit(&self) -> Option<Rate> {
+        self.limit
+    }
+    
+    // Starts recording all of the container's ports into `capture`, or
+    // stops with `None`.
+    pub fn set_capture(&self, capture: Option<Arc<Capture>>) {
+        let container = self.container.as_deref().unwrap_or(self);
+        *container.capture.lock().unwrap_or_else(|e| e.into_inner()) = capture;
+    }
+    
+    pub fn capture(&self) -> Option<Arc<Capture>> {
+        let container = self.container.as_deref().unwrap_or(self);
+        container.capture.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+    
+    // The totals now, with the throughput since `previous` was taken
+    // `elapsed` ago.
+    pub fn stats(&self, previous: &NetworkStats, elapsed: Duration) -> NetworkStats {
+        let (rx_bytes, tx_bytes) = (self.received(), self.sent());
+        let per_sec = |now: u64, before: u64| match elapsed.as_secs_f64() {
+            secs if secs > 0.0 => (now.saturating_sub(before) as f64 / secs) as u64,
+            _ => 0,
+        };
+        
+        NetworkStats {
+            rx_bytes,
+            tx_bytes,
+            rx_bytes_per_sec: per_sec(rx_bytes, previous.rx_bytes),
+            tx_bytes_per_sec: per_sec(tx_bytes, previous.tx_bytes),
+            limit: self.limit,
+            ports: Vec::new(),
+        }
+    }
+    
+    // Waits until `bytes` may be passed on to the guest, and counts them.
+    pub async fn receive(&self, bytes: usize) {
+        let container = self.container.as_deref().unwrap_or(self);
+        if let Some(bucket) = &container.inbound {
+            bucket.take(bytes).await;
+        }
+        if !std::ptr::eq(container, self) {
+            container.received.fetch_add(bytes as u64, Ordering::Relaxed);
+        }
+        self.received.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+    
+    // Waits until `bytes` may be passed back to a client, and counts them.
+    pub async fn send(&self, bytes: usize) {
+        let container = self.container.as_deref().unwrap_or(self);
+        if let Some(bucket) = &container.outbound {
+            bucket.take(bytes).await;
+        }
+        if !std::ptr::eq(container, self) {
+            container.sent.fetch_add(bytes as u64, Ordering::Relaxed);
+        }
+        self.sent.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+}
+
+// Like `tokio::io::copy_bidirectional` between a client and the guest, but
+// every chunk is counted and throttled on the way. With `idle_timeout`, the
+// connection is dropped once nothing has passed either way for that long.
+// Chunks are recorded through `tap` while the container is being captured.
+pub async fn relay<C, S>(
+    client: C,
+    server: S,
+    traffic: &Traffic,
+    idle_timeout: Option<Duration>,
+    tap: &TcpTap,
+) -> io::Result<()>
+where
+    C: AsyncRead + AsyncWrite,
+    S: AsyncRead + AsyncWrite,
+{
+    let (client_read, client_write) = tokio::io::split(client);
+    let (server_read, server_write) = tokio::io::split(server);
+    let activity = Activity::new();
+    
+    let transfer = async {
+        tokio::try_join!(
+            pipe(client_read, server_write, traffic, &activity, tap, Direction::Inbound),
+            pipe(server_read, client_write, traffic, &activity, tap, Direction::Outbound),
+        ).map(|_| ())
+    };
+    let Some(timeout) = idle_timeout else {
+        return transfer.await;
+    };
+    tokio::select! {
+        relayed = transfer => relayed,
+        _ = activity.idle(timeout) => Err(io::Error::new(io::ErrorKind::TimedOut, "Connection idle")),
+    }
+}
+
+// When a relay last read anything, in milliseconds since it started.
+struct Activity {
+    start: Instant,
+    last: AtomicU64,
+}
+
+impl Activity {
+    fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            last: AtomicU64::new(0),
+        }
+    }
+    
+    fn touch(&self) {
+        self.last.store(self.start.elapsed().as_millis() as u64, Ordering::Relaxed);
+    }
+    
+    // Resolves once nothing was read for `timeout`.
+    async fn idle(&self, timeout: Duration) {
+        loop {
+            let last = self.start + Duration::from_millis(self.last.load(Ordering::Relaxed));
+            if last.elapsed() >= timeout {
+                return;
+            }
+            tokio::time::sleep_until((last + timeout).into()).await;
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Direction {
+    Inbound,
+    Outbound,
+}
+
+async fn pipe<R, W>(
+    mut reader: R,
+    mut writer: W,
+    traffic: &Traffic,
+    activity: &Activity,
+    tap: &TcpTap,
+    direction: Direction,
+) -> io::Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let count = |chunk: &[u8]| {
+        let n = chunk.len();
+        activity.touch();
+        tap.record(traffic.capture(), matches!(direction, Direction::Inbound), chunk);
+        async move {
+            match direction {
+                Direction::Inbound => traffic.receive(n).await,
+                Direction::Outbound => traffic.send(n).await,
+            }
+        }
+    };
+    
+    if !traffic.netem.delays() && traffic.netem.loss == 0.0 {
+        let mut buf = vec![0u8; RELAY_BUFFER_SIZE];
+        loop {
+            let n = reader.read(&mut buf).await?;
+            if n == 0 {
+                return writer.shutdown().await;
+            }
+            count(&buf[..n]).await;
+            writer.write_all(&buf[..n]).await?;
+        }
+    }
+    
+    // Chunks wait in a queue until they are due, so delaying them doesn't
+    // slow the stream down. Nothing overtakes a chunk that is held back.
+    let (queue, mut due) = mpsc::channel::<(Instant, Vec<u8>)>(DELAY_QUEUE_SIZE);
+    let read = async move {
+        let mut buf = vec![0u8; RELAY_BUFFER_SIZE];
+        let mut last = Instant::now();
+        loop {
+            let n = reader.read(&mut buf).await?;
+            if n == 0 {
+                return Ok(());
+            }
+            count(&buf[..n]).await;
+            
+            let mut delay = traffic.netem.next_delay();
+            if traffic.netem.lose() {
+                delay += RETRANSMIT_DELAY;
+            }
+            last = last.max(Instant::now() + delay);
+            if queue.send((last, buf[..n].to_vec())).await.is_err() {
+                return Ok(());
+            }
+        }
+    };
+    let write = async move {
+        while let Some((deadline, chunk)) = due.recv().await {
+            tokio::time::sleep_until(deadline.into()).await;
+            writer.write_all(&chunk).await?;
+        }
+        writer.shutdown().await
+    };
+    
+    tokio::try_join!(read, write).map(|_| ())
+}
+
+// A snapshot of a container's traffic, with the throughput over the last
+// interval it was sampled at.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct NetworkStats {
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+    pub rx_bytes_per_sec: u64,
+    pub tx_bytes_per_sec: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit: Option<Rate>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ports: Vec<PortStats>,
+}
+
+// One published port's share of the traffic, and the clients it is
+// relaying for right now.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PortStats {
+    pub host_port: u16,
+    pub container_port: u16,
+    pub protocol: String,
+    pub connections: usize,
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+}
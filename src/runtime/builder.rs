@@ -0,0 +1,317 @@
+use anyhow::{Result, anyhow};
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::broadcast;
+use tracing::{info, warn};
+use wasmtime::{Config, Engine, InstanceAllocationStrategy, IntoFunc, PoolingAllocationConfig};
+use wasmtime_wasi::WasiCtxBuilder;
+
+use crate::config::RuntimeConfig;
+use crate::error;
+use crate::container::{Cpus, RootfsBackend};
+use crate::filesystem::driver::{MemoryDriver, StorageDriver, VfsDriver};
+#[cfg(target_os = "linux")]
+use crate::filesystem::driver::OverlayDriver;
+use crate::filesystem::layer_cache::LayerCache;
+use crate::network::NetworkManager;
+use crate::network::mesh::MeshConfig;
+use crate::network::proxy::ProxySettings;
+use crate::network::resolv::ResolvConf;
+use crate::state::{self, ContainerStore};
+use crate::state::store::StateStore;
+
+use super::events::EVENT_BUFFER;
+use super::host::HostFunctions;
+use super::{ContainerState, WasiHook, WasmRuntime, DEFAULT_MEMORY_LIMIT, ORPHAN_GRACE_PERIOD};
+
+/// How the runtime's wasmtime engine compiles and runs guests.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct EngineOptions {
+    /// The threads proposal: shared memories and atomics.
+    pub threads: bool,
+    pub simd: bool,
+    /// Instructions' worth of fuel each run gets; guests trap once it is
+    /// used up. Unlimited if unset.
+    pub fuel: Option<u64>,
+    /// Whether instances are allocated from a pool reserved up front, which
+    /// makes starting many short-lived containers cheaper.
+    pub pooling: bool,
+    /// Where compiled modules are cached across runs and processes, so an
+    /// image is only compiled once. Nothing is cached if unset.
+    pub cache_dir: Option<PathBuf>,
+}
+
+impl Default for EngineOptions {
+    fn default() -> Self {
+        Self { threads: true, simd: true, fuel: None, pooling: false, cache_dir: None }
+    }
+}
+
+impl EngineOptions {
+    pub(super) fn config(&self) -> Result<Config> {
+        let mut config = Config::new();
+        config.wasm_threads(self.threads);
+        config.wasm_simd(self.simd);
+        config.async_support(true);
+        config.epoch_interruption(true);
+        config.consume_fuel(self.fuel.is_some());
+        if self.pooling {
+            config.allocation_strategy(InstanceAllocationStrategy::Pooling(PoolingAllocationConfig::default()));
+        }
+        if let Some(cache_dir) = &self.cache_dir {
+            config.cache_config_load(cache_config(cache_dir)?)?;
+        }
+        Ok(config)
+    }
+}
+
+// wasmtime only takes its cache's directory from a config file, written
+// next to the cached modules.
+fn cache_config(cache_dir: &Path) -> Result<PathBuf> {
+    let cache_dir = std::path::absolute(cache_dir)?;
+    let modules = cache_dir.join("modules");
+    fs::create_dir_all(&modules)?;
+    let directory = toml::Value::String(modules.to_string_lossy().into_owned());
+    let path = cache_dir.join("cache.toml");
+    fs::write(&path, format!("[cache]\nenabled = true\ndirectory = {}\n", directory))?;
+    Ok(path)
+}
+
+/// Sets up a [`WasmRuntime`] beyond what the config file covers: the
+/// engine's options, storage drivers and the network manager.
+///
+/// ```
+/// use wasm_container::{RuntimeBuilder, RuntimeConfig};
+///
+/// # fn main() -> anyhow::Result<()> {
+/// # let dir = tempfile::TempDir::new()?;
+/// let runtime = RuntimeBuilder::new()
+///     .config(RuntimeConfig { state_dir: Some(dir.path().join("state")), ..RuntimeConfig::default() })
+///     .fuel(10_000_000)
+///     .cache_dir(dir.path().join("modules"))
+///     .build()?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct RuntimeBuilder {
+    config: RuntimeConfig,
+    engine: EngineOptions,
+    drivers: HashMap<RootfsBackend, Box<dyn StorageDriver>>,
+    network_manager: Option<NetworkManager>,
+    wasi_hooks: Vec<WasiHook>,
+    host_functions: HostFunctions,
+    state_store: Option<Arc<dyn StateStore>>,
+}
+
+impl Default for RuntimeBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RuntimeBuilder {
+    /// Starts from the defaults rather than the config file; pass
+    /// `RuntimeConfig::load()?` to `config` to honor it.
+    pub fn new() -> Self {
+        Self {
+            config: RuntimeConfig::default(),
+            engine: EngineOptions::default(),
+            drivers: HashMap::new(),
+            network_manager: None,
+            wasi_hooks: Vec::new(),
+            host_functions: HostFunctions::default(),
+            state_store: None,
+        }
+    }
+    
+    pub fn config(mut self, config: RuntimeConfig) -> Self {
+        self.config = config;
+        self
+    }
+    
+    pub fn engine(mut self, engine: EngineOptions) -> Self {
+        self.engine = engine;
+        self
+    }
+    
+    pub fn threads(mut self, enable: bool) -> Self {
+        self.engine.threads = enable;
+        self
+    }
+    
+    pub fn simd(mut self, enable: bool) -> Self {
+        self.engine.simd = enable;
+        self
+    }
+    
+    pub fn fuel(mut self, fuel: u64) -> Self {
+        self.engine.fuel = Some(fuel);
+        self
+    }
+    
+    pub fn pooling(mut self, enable: bool) -> Self {
+        self.engine.pooling = enable;
+        self
+    }
+    
+    pub fn cache_dir(mut self, cache_dir: impl Into<PathBuf>) -> Self {
+        self.engine.cache_dir = Some(cache_dir.into());
+        self
+    }
+    
+    /// Builds the rootfs of containers using `backend` with `driver`
+    /// instead of the built-in one.
+    pub fn storage_driver(mut self, backend: RootfsBackend, driver: impl StorageDriver + 'static) -> Self {
+        self.drivers.insert(backend, Box::new(driver));
+        self
+    }
+    
+    /// Shares networks with another runtime using the same manager, instead
+    /// of loading them from the state directory.
+    pub fn network_manager(mut self, network_manager: NetworkManager) -> Self {
+        self.network_manager = Some(network_manager);
+        self
+    }
+    
+    /// Keeps container records and events in `store` instead of the
+    /// configured `state_store`. Other processes using the state directory
+    /// only see them if the store is shared with them.
+    pub fn state_store(mut self, store: impl StateStore + 'static) -> Self {
+        self.state_store = Some(Arc::new(store));
+        self
+    }
+    
+    /// Calls `configure` with each guest's WASI context once the runtime has
+    /// set up its stdio, environment, sockets and preopens, to add preopens,
+    /// replace its clocks or stdin, and the like. Hooks run in the order
+    /// they were added.
+    pub fn wasi_configure(mut self, configure: impl Fn(&mut WasiCtxBuilder) + Send + Sync + 'static) -> Self {
+        self.wasi_hooks.push(Arc::new(configure));
+        self
+    }
+    
+    /// Lets guests import `func` as `module.name`, replacing the runtime's
+    /// own function by that name, such as `env.container_log`. `func` is
+    /// anything [`Linker::func_wrap`](wasmtime::Linker::func_wrap) takes,
+    /// and is cloned for each run.
+    ///
+    /// ```
+    /// use std::sync::atomic::{AtomicI32, Ordering};
+    /// use std::sync::Arc;
+    /// use wasm_container::RuntimeBuilder;
+    /// use wasm_container::runtime::ContainerState;
+    /// use wasmtime::Caller;
+    ///
+    /// let total = Arc::new(AtomicI32::new(0));
+    /// let sum = total.clone();
+    /// let builder = RuntimeBuilder::new()
+    ///     .host_fn("app", "add", move |_caller: Caller<'_, ContainerState>, n: i32| {
+    ///         sum.fetch_add(n, Ordering::SeqCst);
+    ///     });
+    /// ```
+    pub fn host_fn<Params, Results>(
+        mut self,
+        module: &str,
+        name: &str,
+        func: impl IntoFunc<ContainerState, Params, Results> + Clone,
+    ) -> Self {
+        self.host_functions.func_wrap(module, name, func);
+        self
+    }
+    
+    /// Defines `functions` in guests' linkers instead of the runtime's
+    /// host functions; start from [`HostFunctions::empty`] to leave out
+    /// `env.container_log`.
+    pub fn host_functions(mut self, functions: HostFunctions) -> Self {
+        self.host_functions = functions;
+        self
+    }
+    
+    pub fn build(self) -> error::Result<WasmRuntime> {
+        let settings = self.config;
+        let state_dir = match &settings.state_dir {
+            Some(state_dir) => state_dir.clone(),
+            None => state::default_state_dir()?,
+        };
+        
+        let engine = Engine::new(&self.engine.config()?)?;
+        let network_manager = match self.network_manager {
+            Some(network_manager) => network_manager,
+            None => NetworkManager::with_dir(state_dir.join("networks"))?
+                .with_forward_defaults(settings.port_options),
+        };
+        let scratch_dir = settings.scratch_dir.clone().unwrap_or_else(state::default_scratch_dir);
+        
+        let store = match self.state_store {
+            Some(records) => ContainerStore::with_records(&state_dir, records)?,
+            None => ContainerStore::open(&state_dir, settings.state_store)?,
+        };
+        
+        let mut drivers = self.drivers;
+        if let Entry::Vacant(entry) = drivers.entry(RootfsBackend::Disk) {
+            let vfs = VfsDriver::new(store.clone(), LayerCache::new(state_dir.join("layers"))?)
+                .with_cache_budget(settings.layer_cache_size);
+            #[cfg(target_os = "linux")]
+            let disk: Box<dyn StorageDriver> = if OverlayDriver::supported() {
+                Box::new(OverlayDriver::new(vfs, state_dir.join("overlay"))?)
+            } else {
+                Box::new(vfs)
+            };
+            #[cfg(not(target_os = "linux"))]
+            let disk: Box<dyn StorageDriver> = Box::new(vfs);
+            entry.insert(disk);
+        }
+        drivers.entry(RootfsBackend::Memory).or_insert_with(|| {
+            Box::new(MemoryDriver::new(scratch_dir.clone()).with_size_limit(settings.scratch_size))
+        });
+        
+        let mesh = match (settings.mesh_listen, &settings.mesh_key) {
+            (Some(listen), Some(key)) => Some(MeshConfig {
+                listen,
+                peers: settings.mesh_peers.clone(),
+                key: key.clone(),
+            }),
+            (Some(_), None) => return Err(anyhow!("mesh_listen needs mesh_key or WASM_CONTAINER_MESH_KEY").into()),
+            (None, _) => None,
+        };
+        
+        let runtime = WasmRuntime {
+            engine,
+            fuel: self.engine.fuel,
+            store,
+            drivers: Arc::new(drivers),
+            state_dir,
+            scratch_dir,
+            network_manager,
+            dns: ResolvConf::new(settings.dns.clone(), settings.dns_search.clone()),
+            proxy: ProxySettings {
+                http_proxy: settings.http_proxy.clone(),
+                https_proxy: settings.https_proxy.clone(),
+                no_proxy: settings.no_proxy.clone(),
+            },
+            mesh,
+            masked_paths: settings.masked_paths.clone(),
+            fs_watchers: Vec::new(),
+            wasi_hooks: self.wasi_hooks,
+            host_functions: self.host_functions,
+            attached: Arc::default(),
+            events: broadcast::channel(EVENT_BUFFER).0,
+            max_memory: settings.max_memory.unwrap_or(DEFAULT_MEMORY_LIMIT),
+            max_cpus: settings.max_cpus.unwrap_or_else(Cpus::host),
+        };
+        
+        match runtime.prune_orphans(ORPHAN_GRACE_PERIOD) {
+            Ok(report) if !report.removed.is_empty() => {
+                info!("Reclaimed {} bytes left behind by earlier runs", report.reclaimed);
+            }
+            Err(e) => warn!("Failed to clean up after earlier runs: {}", e),
+            _ => {}
+        }
+        
+        Ok(runtime)
+    }
+}
@@ -0,0 +1,109 @@
+use std::fmt;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+use wasmtime::Engine;
+
+/// Cancels the runs it is passed to, for embedders to enforce their own
+/// deadlines or shut down. Clones cancel the same runs.
+///
+/// A cancelled run's guest is interrupted wherever it is, even in a loop
+/// or waiting on the host, and the run is cleaned up as if the guest had
+/// trapped. It fails with [`Error::Cancelled`](crate::Error::Cancelled),
+/// reported with exit code [`EXIT_CANCELLED`](super::EXIT_CANCELLED).
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use wasm_container::{CancellationToken, Container, WasmRuntime};
+///
+/// # async fn example(mut runtime: WasmRuntime, container: Container) {
+/// let token = CancellationToken::new();
+/// let deadline = token.clone();
+/// tokio::spawn(async move {
+///     tokio::time::sleep(Duration::from_secs(30)).await;
+///     deadline.cancel();
+/// });
+/// let result = runtime.run_cancellable(container, token).await;
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    cancelled: AtomicBool,
+    notify: Notify,
+    // Engines running guests for the token, whose epochs are advanced on
+    // cancelling so busy guests reach their epoch callbacks.
+    engines: Mutex<Vec<Engine>>,
+}
+
+impl fmt::Debug for Inner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Inner").field("cancelled", &self.cancelled).finish_non_exhaustive()
+    }
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    pub fn cancel(&self) {
+        if self.inner.cancelled.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        self.inner.notify.notify_waiters();
+        for engine in self.inner.engines.lock().unwrap().iter() {
+            engine.increment_epoch();
+        }
+    }
+    
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+    
+    /// Resolves once the token is cancelled.
+    pub async fn cancelled(&self) {
+        loop {
+            let notified = self.inner.notify.notified();
+            if self.is_cancelled() {
+                return;
+            }
+            notified.await;
+        }
+    }
+    
+    // Drives `call` until it finishes or the token is cancelled, whichever
+    // comes first, so guests waiting on the host are stopped too.
+    pub(crate) async fn run<T, F: Future<Output = anyhow::Result<T>>>(&self, call: F) -> anyhow::Result<T> {
+        tokio::select! {
+            biased;
+            output = call => output,
+            _ = self.cancelled() => Err(Cancelled.into()),
+        }
+    }
+    
+    pub(crate) fn watch_engine(&self, engine: &Engine) {
+        let mut engines = self.inner.engines.lock().unwrap();
+        if !engines.iter().any(|watched| Engine::same(watched, engine)) {
+            engines.push(engine.clone());
+        }
+    }
+}
+
+/// The error a guest is interrupted with once its [`CancellationToken`] is
+/// cancelled, which runs report as [`Error::Cancelled`](crate::Error::Cancelled).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Run was cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
@@ -0,0 +1,173 @@
+use std::future::Future;
+use std::pin::{pin, Pin};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Poll};
+use std::time::{Duration, Instant};
+use wasmtime::{Engine, Store, UpdateDeadline};
+
+use crate::container::Cpus;
+
+use super::cancel::{CancellationToken, Cancelled};
+
+// How often the engine's epoch advances while a throttled guest runs, and
+// so how finely its running time is measured.
+const TICK: Duration = Duration::from_millis(10);
+
+// Holds a guest to a share of one CPU. While it runs, the engine's epoch
+// ticks every TICK; at each tick the guest reaches, it is paused for long
+// enough that the time it ran since it last resumed is its share of the
+// whole. Guests are single-threaded, so a CPU or more needs no throttle.
+pub struct CpuThrottle {
+    share: f64,
+    // When the paused guest may resume, set by the epoch callback and
+    // taken by `run`.
+    pause: Arc<Mutex<Option<Instant>>>,
+}
+
+impl CpuThrottle {
+    pub fn new(cpus: Option<Cpus>) -> Option<Self> {
+        let cpus = cpus.filter(|cpus| cpus.millis() < 1000)?;
+        Some(Self {
+            share: cpus.as_f64(),
+            pause: Arc::default(),
+        })
+    }
+    
+    // Has the guest in `store` pause at each tick. Guests without a
+    // throttle still see the ticks of other guests' throttles on the same
+    // engine, and just yield to the executor at them. Either way, the guest
+    // traps at the first tick after `cancel` is cancelled.
+    pub fn install<T>(throttle: Option<&Self>, store: &mut Store<T>, cancel: CancellationToken) {
+        store.set_epoch_deadline(1);
+        let Some(throttle) = throttle else {
+            store.epoch_deadline_callback(move |_| {
+                if cancel.is_cancelled() {
+                    return Err(Cancelled.into());
+                }
+                Ok(UpdateDeadline::Yield(1))
+            });
+            return;
+        };
+        
+        let share = throttle.share;
+        let pause = throttle.pause.clone();
+        let mut resumed = Instant::now();
+        store.epoch_deadline_callback(move |_| {
+            if cancel.is_cancelled() {
+                return Err(Cancelled.into());
+            }
+            let now = Instant::now();
+            let ran = now.saturating_duration_since(resumed);
+            let until = now + ran.mul_f64((1.0 - share) / share);
+            *pause.lock().unwrap() = Some(until);
+            resumed = until;
+            Ok(UpdateDeadline::Yield(1))
+        });
+    }
+    
+    // Drives `call`, the guest's entry point, sitting out the pauses it is
+    // given. The epoch ticks on a thread of its own, as the guest may be
+    // keeping the executor's threads busy.
+    pub async fn run<F: Future>(&self, engine: &Engine, call: F) -> F::Output {
+        let ticker = Ticker::start(engine.clone());
+        
+        let mut call = pin!(call);
+        let mut paused: Option<Pin<Box<tokio::time::Sleep>>> = None;
+        let output = std::future::poll_fn(|cx| loop {
+            if let Some(pause) = paused.as_mut() {
+                ready!(pause.as_mut().poll(cx));
+                paused = None;
+            }
+            let until = match call.as_mut().poll(cx) {
+                Poll::Ready(output) => return Poll::Ready(output),
+                Poll::Pending => self.pause.lock().unwrap().take(),
+            };
+            match until {
+                Some(until) => paused = Some(Box::pin(tokio::time::sleep_until(until.into()))),
+                None => return Poll::Pending,
+            }
+        }).await;
+        
+        drop(ticker);
+        output
+    }
+}
+
+// How long a guest has run, rather than waited on the host or sat out a
+// throttle's pauses. The guest runs while its call is polled, so this is
+// its CPU time; it can be read from other threads while the guest runs.
+pub struct CpuTime {
+    since: Instant,
+    // Nanoseconds spent in polls that have returned.
+    busy: AtomicU64,
+    // When the poll under way started, in nanoseconds after `since`, or 0.
+    polling: AtomicU64,
+}
+
+impl CpuTime {
+    pub fn new() -> Self {
+        Self {
+            since: Instant::now(),
+            busy: AtomicU64::new(0),
+            polling: AtomicU64::new(0),
+        }
+    }
+    
+    // Drives `call`, the guest's entry point, timing each poll.
+    pub async fn run<F: Future>(&self, call: F) -> F::Output {
+        let mut call = pin!(call);
+        std::future::poll_fn(|cx| {
+            let polled = self.now().max(1);
+            self.polling.store(polled, Ordering::Relaxed);
+            let poll = call.as_mut().poll(cx);
+            self.polling.store(0, Ordering::Relaxed);
+            self.busy.fetch_add(self.now() - polled, Ordering::Relaxed);
+            poll
+        }).await
+    }
+    
+    // Nanoseconds run so far, counting the poll under way.
+    pub fn nanos(&self) -> u64 {
+        let busy = self.busy.load(Ordering::Relaxed);
+        match self.polling.load(Ordering::Relaxed) {
+            0 => busy,
+            polled => busy + self.now().saturating_sub(polled),
+        }
+    }
+    
+    fn now(&self) -> u64 {
+        self.since.elapsed().as_nanos() as u64
+    }
+}
+
+impl Default for CpuTime {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Advances an engine's epoch every TICK until dropped.
+struct Ticker {
+    stopped: Arc<AtomicBool>,
+}
+
+impl Ticker {
+    fn start(engine: Engine) -> Self {
+        let stopped = Arc::new(AtomicBool::new(false));
+        let stop = stopped.clone();
+        std::thread::spawn(move || {
+            while !stop.load(Ordering::Relaxed) {
+                std::thread::sleep(TICK);
+                engine.increment_epoch();
+            }
+        });
+        Self { stopped }
+    }
+}
+
+impl Drop for Ticker {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Relaxed);
+    }
+}
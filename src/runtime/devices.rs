@@ -0,0 +1,433 @@
+use anyhow::{Result, anyhow};
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use wasmtime::{AsContextMut, Caller, Linker, Memory};
+use wasmtime_wasi::preview1::wasi_snapshot_preview1::{self as wasi, WasiSnapshotPreview1};
+use wiggle::GuestMemory;
+
+use super::ContainerState;
+use super::masks::Mask;
+
+const WASI_MODULE: &str = "wasi_snapshot_preview1";
+
+// Virtual descriptors are handed out far above anything the WASI fd table
+// allocates, so the two can never collide.
+const DEVICE_FD_BASE: u32 = 1 << 30;
+
+// The rootfs is always the first preopen.
+const ROOT_PREOPEN_FD: i32 = 3;
+
+const ERRNO_SUCCESS: i32 = 0;
+const ERRNO_BUSY: i32 = 10;
+const ERRNO_FAULT: i32 = 21;
+const ERRNO_IO: i32 = 29;
+const ERRNO_NOENT: i32 = 44;
+const ERRNO_SPIPE: i32 = 70;
+
+const FILETYPE_CHARACTER_DEVICE: u8 = 2;
+const RIGHTS_FD_READ: u64 = 1 << 1;
+const RIGHTS_FD_WRITE: u64 = 1 << 6;
+const RIGHTS_FD_FILESTAT_GET: u64 = 1 << 21;
+
+// Hands a call on to the regular WASI implementation, giving it the guest's
+// memory the same way the generated linker bindings do.
+macro_rules! passthrough {
+    ($caller:expr, $func:ident($($arg:expr),*)) => {{
+        let memory = memory(&mut $caller)?;
+        let fuel = $caller.as_context_mut().hostcall_fuel();
+        let (mem, state) = memory.data_and_store_mut(&mut $caller);
+        state.wasi.set_hostcall_fuel(fuel);
+        wasi::$func(&mut state.wasi, &mut GuestMemory::Unshared(mem), $($arg),*).await
+    }};
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Device {
+    Null,
+    Zero,
+    Random,
+    Tty,
+}
+
+impl Device {
+    // Matches a path relative to the root preopen, e.g. `dev/urandom`.
+    pub fn from_path(path: &str) -> Option<Self> {
+        let mut parts = path.split('/').filter(|part| !part.is_empty() && *part != ".");
+        if parts.next() != Some("dev") {
+            return None;
+        }
+        let device = match parts.next()? {
+            "null" => Device::Null,
+            "zero" => Device::Zero,
+            "random" | "urandom" => Device::Random,
+            "tty" | "console" => Device::Tty,
+            _ => return None,
+        };
+        parts.next().is_none().then_some(device)
+    }
+    
+    pub fn read(&self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Device::Null => Ok(0),
+            Device::Zero => {
+                buf.fill(0);
+                Ok(buf.len())
+            }
+            Device::Random => {
+                std::fs::File::open("/dev/urandom")?.read_exact(buf)?;
+                Ok(buf.len())
+            }
+            Device::Tty => std::io::stdin().read(buf),
+        }
+    }
+    
+    pub fn write(&self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Device::Tty => {
+                let mut stdout = std::io::stdout();
+                stdout.write_all(buf)?;
+                stdout.flush()?;
+                Ok(buf.len())
+            }
+            _ => Ok(buf.len()),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct DeviceTable {
+    open: HashMap<u32, Device>,
+    next: u32,
+}
+
+impl DeviceTable {
+    pub fn open(&mut self, device: Device) -> u32 {
+        let fd = DEVICE_FD_BASE + self.next;
+        self.next += 1;
+        self.open.insert(fd, device);
+        fd
+    }
+    
+    pub fn get(&self, fd: i32) -> Option<Device> {
+        self.open.get(&(fd as u32)).copied()
+    }
+    
+    pub fn close(&mut self, fd: i32) -> bool {
+        self.open.remove(&(fd as u32)).is_some()
+    }
+}
+
+// Shadows the WASI calls that touch file descriptors so opens of /dev
+// devices get a virtual descriptor served by the host, and masked paths
+// can't be read, listed, moved or linked elsewhere. Everything else is
+// passed through to the regular WASI implementation.
+pub fn add_to_linker(linker: &mut Linker<ContainerState>) -> Result<()> {
+    linker.allow_shadowing(true);
+    
+    linker.func_wrap_async(
+        WASI_MODULE,
+        "path_open",
+        move |mut caller: Caller<'_, ContainerState>, params: (i32, i32, i32, i32, i32, i64, i64, i32, i32)| {
+            Box::new(async move {
+                let (dir_fd, flags, path_ptr, path_len, oflags, base, inheriting, fdflags, opened_fd_ptr) = params;
+                let memory = memory(&mut caller)?;
+                let path = read_bytes(&memory, &caller, path_ptr, path_len)
+                    .and_then(|path| std::str::from_utf8(path).ok())
+                    .map(str::to_owned);
+                let target = path.as_deref().and_then(|path| caller.data().masks.resolve(dir_fd, path));
+                
+                let device = match target.as_deref().and_then(|target| caller.data().masks.check(target)) {
+                    Some(Mask::Hidden) => return Ok(ERRNO_NOENT),
+                    Some(Mask::Empty) => Some(Device::Null),
+                    _ => path.as_deref()
+                        .filter(|_| dir_fd == ROOT_PREOPEN_FD)
+                        .and_then(Device::from_path),
+                };
+                
+                if let Some(device) = device {
+                    let fd = caller.data_mut().devices.open(device);
+                    return Ok(write_bytes(&memory, &mut caller, opened_fd_ptr, &fd.to_le_bytes()));
+                }
+                
+                let errno = passthrough!(caller, path_open(dir_fd, flags, path_ptr, path_len, oflags, base, inheriting, fdflags, opened_fd_ptr))?;
+                if let (ERRNO_SUCCESS, Some(target)) = (errno, target) {
+                    if let Some(fd) = read_bytes(&memory, &caller, opened_fd_ptr, 4) {
+                        let fd = i32::from_le_bytes([fd[0], fd[1], fd[2], fd[3]]);
+                        caller.data_mut().masks.opened(fd, target);
+                    }
+                }
+                Ok(errno)
+            })
+        },
+    )?;
+    
+    linker.func_wrap_async(
+        WASI_MODULE,
+        "fd_readdir",
+        move |mut caller: Caller<'_, ContainerState>, params: (i32, i32, i32, i64, i32)| {
+            Box::new(async move {
+                let (fd, buf, buf_len, cookie, bufused_ptr) = params;
+                if !caller.data().masks.hides_listing(fd) {
+                    return passthrough!(caller, fd_readdir(fd, buf, buf_len, cookie, bufused_ptr));
+                }
+                
+                let memory = memory(&mut caller)?;
+                Ok(write_bytes(&memory, &mut caller, bufused_ptr, &0u32.to_le_bytes()))
+            })
+        },
+    )?;
+    
+    linker.func_wrap_async(
+        WASI_MODULE,
+        "path_unlink_file",
+        move |mut caller: Caller<'_, ContainerState>, params: (i32, i32, i32)| {
+            Box::new(async move {
+                let (fd, path_ptr, path_len) = params;
+                if let Some(errno) = masked_errno(&mut caller, fd, path_ptr, path_len)? {
+                    return Ok(errno);
+                }
+                passthrough!(caller, path_unlink_file(fd, path_ptr, path_len))
+            })
+        },
+    )?;
+    
+    linker.func_wrap_async(
+        WASI_MODULE,
+        "path_remove_directory",
+        move |mut caller: Caller<'_, ContainerState>, params: (i32, i32, i32)| {
+            Box::new(async move {
+                let (fd, path_ptr, path_len) = params;
+                if let Some(errno) = masked_errno(&mut caller, fd, path_ptr, path_len)? {
+                    return Ok(errno);
+                }
+                passthrough!(caller, path_remove_directory(fd, path_ptr, path_len))
+            })
+        },
+    )?;
+    
+    linker.func_wrap_async(
+        WASI_MODULE,
+        "path_rename",
+        move |mut caller: Caller<'_, ContainerState>, params: (i32, i32, i32, i32, i32, i32)| {
+            Box::new(async move {
+                let (fd, path_ptr, path_len, new_fd, new_path_ptr, new_path_len) = params;
+                for (fd, path_ptr, path_len) in [(fd, path_ptr, path_len), (new_fd, new_path_ptr, new_path_len)] {
+                    if let Some(errno) = masked_errno(&mut caller, fd, path_ptr, path_len)? {
+                        return Ok(errno);
+                    }
+                }
+                passthrough!(caller, path_rename(fd, path_ptr, path_len, new_fd, new_path_ptr, new_path_len))
+            })
+        },
+    )?;
+    
+    linker.func_wrap_async(
+        WASI_MODULE,
+        "path_link",
+        move |mut caller: Caller<'_, ContainerState>, params: (i32, i32, i32, i32, i32, i32, i32)| {
+            Box::new(async move {
+                let (fd, flags, path_ptr, path_len, new_fd, new_path_ptr, new_path_len) = params;
+                if let Some(errno) = masked_errno(&mut caller, fd, path_ptr, path_len)? {
+                    return Ok(errno);
+                }
+                passthrough!(caller, path_link(fd, flags, path_ptr, path_len, new_fd, new_path_ptr, new_path_len))
+            })
+        },
+    )?;
+    
+    linker.func_wrap_async(
+        WASI_MODULE,
+        "fd_read",
+        move |mut caller: Caller<'_, ContainerState>, params: (i32, i32, i32, i32)| {
+            Box::new(async move {
+                let (fd, iovs, iovs_len, nread_ptr) = params;
+                let Some(device) = caller.data().devices.get(fd) else {
+                    return passthrough!(caller, fd_read(fd, iovs, iovs_len, nread_ptr));
+                };
+                
+                let memory = memory(&mut caller)?;
+                let mut total: u32 = 0;
+                let Some(iovecs) = iovecs(&memory, &caller, iovs, iovs_len) else {
+                    return Ok(ERRNO_FAULT);
+                };
+                for (buf, len) in iovecs {
+                    let mut data = vec![0; len as usize];
+                    let read = match device.read(&mut data) {
+                        Ok(read) => read,
+                        Err(_) => return Ok(ERRNO_IO),
+                    };
+                    if write_bytes(&memory, &mut caller, buf, &data[..read]) != ERRNO_SUCCESS {
+                        return Ok(ERRNO_FAULT);
+                    }
+                    total += read as u32;
+                    // Stop at short reads, as the tty may not have more yet.
+                    if read < len as usize {
+                        break;
+                    }
+                }
+                
+                Ok(write_bytes(&memory, &mut caller, nread_ptr, &total.to_le_bytes()))
+            })
+        },
+    )?;
+    
+    linker.func_wrap_async(
+        WASI_MODULE,
+        "fd_write",
+        move |mut caller: Caller<'_, ContainerState>, params: (i32, i32, i32, i32)| {
+            Box::new(async move {
+                let (fd, iovs, iovs_len, nwritten_ptr) = params;
+                let Some(device) = caller.data().devices.get(fd) else {
+                    return passthrough!(caller, fd_write(fd, iovs, iovs_len, nwritten_ptr));
+                };
+                
+                let memory = memory(&mut caller)?;
+                let mut total: u32 = 0;
+                let Some(iovecs) = iovecs(&memory, &caller, iovs, iovs_len) else {
+                    return Ok(ERRNO_FAULT);
+                };
+                for (buf, len) in iovecs {
+                    let Some(data) = read_bytes(&memory, &caller, buf, len as i32) else {
+                        return Ok(ERRNO_FAULT);
+                    };
+                    match device.write(data) {
+                        Ok(written) => total += written as u32,
+                        Err(_) => return Ok(ERRNO_IO),
+                    }
+                }
+                
+                Ok(write_bytes(&memory, &mut caller, nwritten_ptr, &total.to_le_bytes()))
+            })
+        },
+    )?;
+    
+    linker.func_wrap_async(
+        WASI_MODULE,
+        "fd_close",
+        move |mut caller: Caller<'_, ContainerState>, (fd,): (i32,)| {
+            Box::new(async move {
+                if caller.data_mut().devices.close(fd) {
+                    return Ok(ERRNO_SUCCESS);
+                }
+                caller.data_mut().masks.closed(fd);
+                passthrough!(caller, fd_close(fd))
+            })
+        },
+    )?;
+    
+    linker.func_wrap_async(
+        WASI_MODULE,
+        "fd_seek",
+        move |mut caller: Caller<'_, ContainerState>, params: (i32, i64, i32, i32)| {
+            Box::new(async move {
+                let (fd, offset, whence, offset_ptr) = params;
+                match caller.data().devices.get(fd) {
+                    Some(Device::Tty) => Ok(ERRNO_SPIPE),
+                    Some(_) => {
+                        let memory = memory(&mut caller)?;
+                        Ok(write_bytes(&memory, &mut caller, offset_ptr, &0u64.to_le_bytes()))
+                    }
+                    None => passthrough!(caller, fd_seek(fd, offset, whence, offset_ptr)),
+                }
+            })
+        },
+    )?;
+    
+    linker.func_wrap_async(
+        WASI_MODULE,
+        "fd_fdstat_get",
+        move |mut caller: Caller<'_, ContainerState>, params: (i32, i32)| {
+            Box::new(async move {
+                let (fd, stat_ptr) = params;
+                if caller.data().devices.get(fd).is_none() {
+                    return passthrough!(caller, fd_fdstat_get(fd, stat_ptr));
+                }
+                
+                let rights = RIGHTS_FD_READ | RIGHTS_FD_WRITE | RIGHTS_FD_FILESTAT_GET;
+                let mut stat = [0u8; 24];
+                stat[0] = FILETYPE_CHARACTER_DEVICE;
+                stat[8..16].copy_from_slice(&rights.to_le_bytes());
+                
+                let memory = memory(&mut caller)?;
+                Ok(write_bytes(&memory, &mut caller, stat_ptr, &stat))
+            })
+        },
+    )?;
+    
+    linker.func_wrap_async(
+        WASI_MODULE,
+        "fd_filestat_get",
+        move |mut caller: Caller<'_, ContainerState>, params: (i32, i32)| {
+            Box::new(async move {
+                let (fd, stat_ptr) = params;
+                if caller.data().devices.get(fd).is_none() {
+                    return passthrough!(caller, fd_filestat_get(fd, stat_ptr));
+                }
+                
+                let mut stat = [0u8; 64];
+                stat[16] = FILETYPE_CHARACTER_DEVICE;
+                stat[24..32].copy_from_slice(&1u64.to_le_bytes());
+                
+                let memory = memory(&mut caller)?;
+                Ok(write_bytes(&memory, &mut caller, stat_ptr, &stat))
+            })
+        },
+    )?;
+    
+    linker.allow_shadowing(false);
+    Ok(())
+}
+
+// Masked paths behave like docker's mounts over them: they can't be moved,
+// removed or linked, and nothing inside a masked directory exists.
+fn masked_errno(caller: &mut Caller<'_, ContainerState>, fd: i32, path_ptr: i32, path_len: i32) -> wasmtime::Result<Option<i32>> {
+    let memory = memory(caller)?;
+    let target = read_bytes(&memory, caller, path_ptr, path_len)
+        .and_then(|path| std::str::from_utf8(path).ok())
+        .and_then(|path| caller.data().masks.resolve(fd, path));
+    
+    Ok(match target.and_then(|target| caller.data().masks.check(&target)) {
+        Some(Mask::Hidden) => Some(ERRNO_NOENT),
+        Some(_) => Some(ERRNO_BUSY),
+        None => None,
+    })
+}
+
+pub(super) fn memory(caller: &mut Caller<'_, ContainerState>) -> wasmtime::Result<Memory> {
+    caller.get_export("memory")
+        .and_then(|export| export.into_memory())
+        .ok_or_else(|| anyhow!("failed to get memory"))
+}
+
+pub(super) fn read_bytes<'a>(memory: &Memory, caller: &'a Caller<'_, ContainerState>, ptr: i32, len: i32) -> Option<&'a [u8]> {
+    let start = usize::try_from(ptr).ok()?;
+    let end = start.checked_add(usize::try_from(len).ok()?)?;
+    memory.data(caller).get(start..end)
+}
+
+pub(super) fn write_bytes(memory: &Memory, caller: &mut Caller<'_, ContainerState>, ptr: i32, bytes: &[u8]) -> i32 {
+    let target = usize::try_from(ptr).ok()
+        .and_then(|start| Some(start..start.checked_add(bytes.len())?))
+        .and_then(|range| memory.data_mut(&mut *caller).get_mut(range));
+    
+    match target {
+        Some(target) => {
+            target.copy_from_slice(bytes);
+            ERRNO_SUCCESS
+        }
+        None => ERRNO_FAULT,
+    }
+}
+
+// Reads a WASI iovec array as (buffer pointer, length) pairs.
+fn iovecs(memory: &Memory, caller: &Caller<'_, ContainerState>, ptr: i32, len: i32) -> Option<Vec<(i32, u32)>> {
+    let raw = len.checked_mul(8).and_then(|size| read_bytes(memory, caller, ptr, size))?;
+    
+    Some(raw.chunks_exact(8)
+        .map(|iov| {
+            let buf = u32::from_le_bytes([iov[0], iov[1], iov[2], iov[3]]) as i32;
+            let len = u32::from_le_bytes([iov[4], iov[5], iov[6], iov[7]]);
+            (buf, len)
+        })
+        .collect())
+}
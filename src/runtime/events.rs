@@ -0,0 +1,57 @@
+use serde::{Deserialize, Serialize};
+
+// Events kept for subscribers that fall behind; older ones are dropped and
+// the subscriber told how many it missed.
+pub(crate) const EVENT_BUFFER: usize = 256;
+
+/// A change in a container's lifecycle, as a runtime's subscribers see it.
+/// Only changes made through the subscribed runtime are seen, not those by
+/// other processes sharing its state directory.
+///
+/// ```no_run
+/// use wasm_container::{ContainerEvent, WasmRuntime};
+///
+/// # async fn example(runtime: WasmRuntime) {
+/// let mut events = runtime.subscribe();
+/// while let Ok(event) = events.recv().await {
+///     if let ContainerEvent::Failed { id, exit_code, error } = event {
+///         eprintln!("{} failed with {}: {}", id, exit_code, error);
+///     }
+/// }
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum ContainerEvent {
+    Created { id: String },
+    /// The guest was instantiated and its export is being called.
+    Started { id: String },
+    /// The guest returned or exited with an exit code of its own.
+    Exited { id: String, exit_code: i32 },
+    /// The run failed otherwise, e.g. on a trap or before the guest could
+    /// start, with the exit code docker would report.
+    Failed { id: String, exit_code: i32, error: String },
+    /// The guest tried to grow its memory past its limit and was refused.
+    Oom { id: String },
+    Stopped { id: String },
+    Removed { id: String },
+    NetworkConnected { id: String, network: String },
+    NetworkDisconnected { id: String, network: String },
+}
+
+impl ContainerEvent {
+    pub fn container_id(&self) -> &str {
+        match self {
+            Self::Created { id }
+            | Self::Started { id }
+            | Self::Exited { id, .. }
+            | Self::Failed { id, .. }
+            | Self::Oom { id }
+            | Self::Stopped { id }
+            | Self::Removed { id }
+            | Self::NetworkConnected { id, .. }
+            | Self::NetworkDisconnected { id, .. } => id,
+        }
+    }
+}
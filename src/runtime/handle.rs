@@ -0,0 +1,250 @@
+use anyhow::anyhow;
+use bytes::Bytes;
+use std::fmt;
+use tokio::io::{AsyncWrite, DuplexStream};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_stream::Stream;
+use tokio_stream::wrappers::ReceiverStream;
+use wasmtime_wasi::pipe::AsyncReadStream;
+use wasmtime_wasi::{AsyncStdinStream, HostOutputStream, StdoutStream, StreamError, StreamResult, Subscribe};
+
+use crate::error::{self, Error};
+
+use super::cancel::CancellationToken;
+use super::{ResourceUsage, WasmRuntime};
+
+// Chunks of output buffered for a reader before the guest's writes wait.
+const OUTPUT_CHUNKS: usize = 64;
+// Bytes of input buffered before the embedder's writes wait.
+const INPUT_BUFFER: usize = 64 * 1024;
+// The most the guest is allowed to write in one go.
+const WRITE_BUDGET: usize = 64 * 1024;
+
+/// A container's stdio, for embedders to feed its stdin and read its
+/// output as it runs, instead of the host's stdio being inherited. Get one
+/// with [`WasmRuntime::attach`](super::WasmRuntime::attach) before running
+/// or starting the container, or with
+/// [`WasmRuntime::spawn`](super::WasmRuntime::spawn), whose handles also
+/// wait for the run, kill it and sample its resource usage.
+///
+/// Output still goes to the container's log. A guest blocks writing once
+/// 64 of its writes wait to be read, unless the stream was dropped, which
+/// discards them. Dropping stdin closes the guest's stdin.
+///
+/// ```no_run
+/// use tokio::io::AsyncWriteExt;
+/// use tokio_stream::StreamExt;
+/// use wasm_container::{Container, ImageData, WasmRuntime};
+///
+/// # async fn example(mut runtime: WasmRuntime, image: ImageData) -> anyhow::Result<()> {
+/// let container = Container::new(image, None, None, vec![])?;
+/// let mut handle = runtime.attach(container.id());
+/// let mut stdin = handle.stdin().unwrap();
+/// let mut stdout = handle.stdout().unwrap();
+///
+/// let run = tokio::spawn(async move { runtime.run(container).await });
+/// stdin.write_all(b"ping\n").await?;
+/// drop(stdin);
+/// while let Some(chunk) = stdout.next().await {
+///     print!("{}", String::from_utf8_lossy(&chunk));
+/// }
+/// run.await??;
+/// # Ok(())
+/// # }
+/// ```
+pub struct ContainerHandle {
+    id: String,
+    stdin: Option<DuplexStream>,
+    stdout: Option<mpsc::Receiver<Bytes>>,
+    stderr: Option<mpsc::Receiver<Bytes>>,
+    // The run of a spawned container.
+    run: Option<SpawnedRun>,
+}
+
+struct SpawnedRun {
+    runtime: WasmRuntime,
+    cancel: CancellationToken,
+    // Taken once the run has finished.
+    task: Option<JoinHandle<error::Result<()>>>,
+    status: Option<ExitStatus>,
+}
+
+impl fmt::Debug for ContainerHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ContainerHandle")
+            .field("id", &self.id)
+            .field("spawned", &self.run.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+/// How a spawned container's guest ended: the code it exited with, or
+/// [`EXIT_CANCELLED`](super::EXIT_CANCELLED) if it was killed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExitStatus(i32);
+
+impl ExitStatus {
+    pub fn code(self) -> i32 {
+        self.0
+    }
+    
+    pub fn success(self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl fmt::Display for ExitStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "exit code {}", self.0)
+    }
+}
+
+impl ContainerHandle {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+    
+    /// The guest's stdin; `None` once taken.
+    pub fn stdin(&mut self) -> Option<impl AsyncWrite + Send + Unpin> {
+        self.stdin.take()
+    }
+    
+    /// What the guest writes to stdout, in the chunks it writes; `None` once
+    /// taken. Ends when the guest exits.
+    pub fn stdout(&mut self) -> Option<impl Stream<Item = Bytes> + Send + Unpin> {
+        self.stdout.take().map(ReceiverStream::new)
+    }
+    
+    /// Like [`stdout`](Self::stdout), for stderr.
+    pub fn stderr(&mut self) -> Option<impl Stream<Item = Bytes> + Send + Unpin> {
+        self.stderr.take().map(ReceiverStream::new)
+    }
+    
+    /// Waits for a spawned container's guest to exit. Stdio not taken by
+    /// then is closed, so the guest reads the end of its stdin and its
+    /// output is discarded rather than waiting to be read.
+    ///
+    /// Traps, failures to start and anything else going wrong come back as
+    /// errors, as [`WasmRuntime::run`] returns them; later calls then fail
+    /// too. Waiting can be given up on, e.g. in `tokio::select!`, and
+    /// resumed.
+    pub async fn wait(&mut self) -> error::Result<ExitStatus> {
+        self.stdin = None;
+        self.stdout = None;
+        self.stderr = None;
+        let id = &self.id;
+        let run = self.run.as_mut().ok_or_else(|| not_spawned(id))?;
+        if let Some(task) = &mut run.task {
+            let joined = task.await;
+            run.task = None;
+            run.status = Some(match joined.map_err(anyhow::Error::from)? {
+                Ok(()) => ExitStatus(0),
+                Err(error @ (Error::Exit { .. } | Error::Cancelled)) => ExitStatus(error.exit_code()),
+                Err(error) => return Err(error),
+            });
+        }
+        run.status.ok_or_else(|| anyhow!("Container {}'s run failed", id).into())
+    }
+    
+    /// Stops a spawned container's guest wherever it is, as cancelling its
+    /// run does; [`wait`](Self::wait) then reports
+    /// [`EXIT_CANCELLED`](super::EXIT_CANCELLED). Does nothing once the run
+    /// has finished, or for handles from `attach`.
+    pub fn kill(&self) {
+        if let Some(run) = &self.run {
+            run.cancel.cancel();
+        }
+    }
+    
+    /// A spawned container's CPU and memory use, as of the last second.
+    pub async fn stats(&self) -> error::Result<ResourceUsage> {
+        let run = self.run.as_ref().ok_or_else(|| not_spawned(&self.id))?;
+        run.runtime.usage(&self.id).await
+    }
+    
+    pub(crate) fn with_run(mut self, runtime: WasmRuntime, cancel: CancellationToken, task: JoinHandle<error::Result<()>>) -> Self {
+        self.run = Some(SpawnedRun { runtime, cancel, task: Some(task), status: None });
+        self
+    }
+}
+
+fn not_spawned(container_id: &str) -> Error {
+    anyhow!("Container {} wasn't spawned; wait on its run instead", container_id).into()
+}
+
+// The guest's ends of an attached container's stdio, kept by the runtime
+// until the container runs.
+pub(crate) struct PipedStdio {
+    pub stdin: AsyncStdinStream,
+    pub stdout: PipedOutput,
+    pub stderr: PipedOutput,
+}
+
+pub(crate) fn pipe(container_id: &str) -> (ContainerHandle, PipedStdio) {
+    let (stdin_writer, stdin_reader) = tokio::io::duplex(INPUT_BUFFER);
+    let (stdout_sender, stdout) = mpsc::channel(OUTPUT_CHUNKS);
+    let (stderr_sender, stderr) = mpsc::channel(OUTPUT_CHUNKS);
+    let handle = ContainerHandle {
+        id: container_id.to_string(),
+        stdin: Some(stdin_writer),
+        stdout: Some(stdout),
+        stderr: Some(stderr),
+        run: None,
+    };
+    let stdio = PipedStdio {
+        stdin: AsyncStdinStream::new(AsyncReadStream::new(stdin_reader)),
+        stdout: PipedOutput { sender: stdout_sender },
+        stderr: PipedOutput { sender: stderr_sender },
+    };
+    (handle, stdio)
+}
+
+// A guest output sending what it writes to a `ContainerHandle`.
+#[derive(Clone)]
+pub(crate) struct PipedOutput {
+    sender: mpsc::Sender<Bytes>,
+}
+
+impl StdoutStream for PipedOutput {
+    fn stream(&self) -> Box<dyn HostOutputStream> {
+        Box::new(self.clone())
+    }
+    
+    fn isatty(&self) -> bool {
+        false
+    }
+}
+
+#[async_trait::async_trait]
+impl Subscribe for PipedOutput {
+    async fn ready(&mut self) {
+        // Waits for the reader to make room; the slot is given back at once
+        // and taken by the write that follows.
+        let _ = self.sender.reserve().await;
+    }
+}
+
+impl HostOutputStream for PipedOutput {
+    fn write(&mut self, bytes: Bytes) -> StreamResult<()> {
+        match self.sender.try_send(bytes) {
+            // Nobody reads the stream any more.
+            Ok(()) | Err(mpsc::error::TrySendError::Closed(_)) => Ok(()),
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                Err(StreamError::Trap(anyhow::anyhow!("write without checking for room first")))
+            }
+        }
+    }
+    
+    fn flush(&mut self) -> StreamResult<()> {
+        Ok(())
+    }
+    
+    fn check_write(&mut self) -> StreamResult<usize> {
+        if self.sender.is_closed() || self.sender.capacity() > 0 {
+            Ok(WRITE_BUDGET)
+        } else {
+            Ok(0)
+        }
+    }
+}
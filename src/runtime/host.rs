@@ -0,0 +1,74 @@
+use anyhow::{Result, anyhow};
+use std::sync::Arc;
+use tracing::info;
+use wasmtime::{Caller, IntoFunc, Linker};
+
+use super::devices::{memory, read_bytes};
+use super::ContainerState;
+
+// Defines a host function in a run's linker, cloning it for that run.
+type Definition = Arc<dyn Fn(&mut Linker<ContainerState>) -> Result<()> + Send + Sync>;
+
+/// The host functions guests can import besides WASI, defined in every
+/// run's linker. The runtime provides `env.container_log`, which logs a
+/// UTF-8 string from guest memory; embedders add their own, or replace it,
+/// with [`RuntimeBuilder::host_fn`](super::builder::RuntimeBuilder::host_fn).
+#[derive(Clone)]
+pub struct HostFunctions {
+    definitions: Vec<Definition>,
+}
+
+impl Default for HostFunctions {
+    fn default() -> Self {
+        let mut functions = Self::empty();
+        functions.func_wrap("env", "container_log", |mut caller: Caller<'_, ContainerState>, ptr: i32, len: i32| {
+            let message = String::from_utf8(guest_bytes(&mut caller, ptr, len)?)
+                .map_err(|_| anyhow!("invalid UTF-8"))?;
+            info!("[{}]: {}", caller.data().container_id, message);
+            Ok(())
+        });
+        functions
+    }
+}
+
+impl HostFunctions {
+    /// No host functions, not even the runtime's own.
+    pub fn empty() -> Self {
+        Self { definitions: Vec::new() }
+    }
+    
+    /// Defines `module.name` as `func`, as [`Linker::func_wrap`] does,
+    /// replacing any function defined under that name before. `func` is
+    /// cloned for each run, so state it shares between runs belongs behind
+    /// an `Arc`.
+    pub fn func_wrap<Params, Results>(
+        &mut self,
+        module: &str,
+        name: &str,
+        func: impl IntoFunc<ContainerState, Params, Results> + Clone,
+    ) -> &mut Self {
+        let (module, name) = (module.to_string(), name.to_string());
+        self.definitions.push(Arc::new(move |linker: &mut Linker<ContainerState>| {
+            linker.func_wrap(&module, &name, func.clone())?;
+            Ok(())
+        }));
+        self
+    }
+    
+    pub(crate) fn add_to_linker(&self, linker: &mut Linker<ContainerState>) -> Result<()> {
+        linker.allow_shadowing(true);
+        for define in &self.definitions {
+            define(linker)?;
+        }
+        Ok(())
+    }
+}
+
+/// Copies `len` bytes at `ptr` out of the calling guest's memory, failing
+/// the call if they are out of bounds.
+pub fn guest_bytes(caller: &mut Caller<'_, ContainerState>, ptr: i32, len: i32) -> wasmtime::Result<Vec<u8>> {
+    let memory = memory(caller)?;
+    read_bytes(&memory, caller, ptr, len)
+        .map(<[u8]>::to_vec)
+        .ok_or_else(|| anyhow!("invalid memory access"))
+}
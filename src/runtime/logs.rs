@@ -0,0 +1,62 @@
+use bytes::Bytes;
+use std::fs::File;
+use std::io::Write;
+use std::sync::Arc;
+use tracing::debug;
+use wasmtime_wasi::{HostOutputStream, StdoutStream, StreamResult, Subscribe};
+
+// A guest's stdout or stderr, passed through to the host's and copied to
+// the container's log file so other processes can show what it printed.
+pub struct LoggedOutput<S> {
+    output: S,
+    log: Arc<File>,
+}
+
+impl<S: StdoutStream> LoggedOutput<S> {
+    pub fn new(output: S, log: File) -> Self {
+        Self { output, log: Arc::new(log) }
+    }
+}
+
+impl<S: StdoutStream> StdoutStream for LoggedOutput<S> {
+    fn stream(&self) -> Box<dyn HostOutputStream> {
+        Box::new(LoggedStream {
+            output: self.output.stream(),
+            log: self.log.clone(),
+        })
+    }
+    
+    fn isatty(&self) -> bool {
+        self.output.isatty()
+    }
+}
+
+struct LoggedStream {
+    output: Box<dyn HostOutputStream>,
+    log: Arc<File>,
+}
+
+#[async_trait::async_trait]
+impl Subscribe for LoggedStream {
+    async fn ready(&mut self) {
+        self.output.ready().await;
+    }
+}
+
+impl HostOutputStream for LoggedStream {
+    fn write(&mut self, bytes: Bytes) -> StreamResult<()> {
+        // Losing the copy is no reason to fail the guest's write.
+        if let Err(e) = self.log.as_ref().write_all(&bytes) {
+            debug!("Failed to write container log: {}", e);
+        }
+        self.output.write(bytes)
+    }
+    
+    fn flush(&mut self) -> StreamResult<()> {
+        self.output.flush()
+    }
+    
+    fn check_write(&mut self) -> StreamResult<usize> {
+        self.output.check_write()
+    }
+}
@@ -0,0 +1,97 @@
+use anyhow::{Result, anyhow};
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+
+use crate::filesystem::copy::resolve_path;
+
+// The first preopen gets the descriptor right after stdio.
+const FIRST_PREOPEN_FD: i32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mask {
+    // A masked file, which reads as empty and swallows writes.
+    Empty,
+    // A masked directory: it can be opened, but lists as empty.
+    EmptyDir,
+    // Anything inside a masked directory, which doesn't exist for the guest.
+    Hidden,
+}
+
+// Paths the guest must not see, like the /proc entries docker masks.
+// Masks are matched against the host paths guest paths resolve to, so a
+// broad volume or a symlink can't be used to reach a masked file by
+// another name.
+#[derive(Debug, Default)]
+pub struct PathMasks {
+    masked: Vec<PathBuf>,
+    // The host directory behind every open directory descriptor.
+    dirs: HashMap<i32, PathBuf>,
+}
+
+impl PathMasks {
+    // `preopens` pairs each preopened host directory with where the guest
+    // sees it, in preopen order; masked guest paths resolve through the
+    // preopen with the longest matching mount point.
+    pub fn new(preopens: &[(PathBuf, PathBuf)], masked: &[PathBuf]) -> Result<Self> {
+        let dirs = preopens.iter()
+            .enumerate()
+            .map(|(i, (host, _))| (FIRST_PREOPEN_FD + i as i32, host.clone()))
+            .collect();
+        
+        let mut resolved = Vec::new();
+        for path in masked {
+            if path.components().any(|c| matches!(c, Component::ParentDir | Component::Prefix(_))) {
+                return Err(anyhow!("Invalid masked path: {:?}", path));
+            }
+            let path = Path::new("/").join(path);
+            
+            let preopen = preopens.iter()
+                .filter(|(_, guest)| path.starts_with(Path::new("/").join(guest)))
+                .max_by_key(|(_, guest)| guest.components().count());
+            if let Some((host, guest)) = preopen {
+                let relative = path.strip_prefix(Path::new("/").join(guest))?;
+                resolved.push(resolve_path(host, relative)?);
+            }
+        }
+        
+        Ok(Self {
+            masked: resolved,
+            dirs,
+        })
+    }
+    
+    // Where `path`, relative to the directory open as `fd`, lands on the
+    // host. Descriptors the masks don't know about resolve to nothing.
+    pub fn resolve(&self, fd: i32, path: &str) -> Option<PathBuf> {
+        let dir = self.dirs.get(&fd)?;
+        resolve_path(dir, Path::new(path)).ok()
+    }
+    
+    pub fn check(&self, host_path: &Path) -> Option<Mask> {
+        let masked = self.masked.iter().find(|masked| host_path.starts_with(masked))?;
+        
+        if host_path != masked {
+            Some(Mask::Hidden)
+        } else if host_path.is_dir() {
+            Some(Mask::EmptyDir)
+        } else {
+            Some(Mask::Empty)
+        }
+    }
+    
+    // Directories are tracked so paths relative to them can be resolved.
+    pub fn opened(&mut self, fd: i32, host_path: PathBuf) {
+        if host_path.is_dir() {
+            self.dirs.insert(fd, host_path);
+        }
+    }
+    
+    pub fn closed(&mut self, fd: i32) {
+        self.dirs.remove(&fd);
+    }
+    
+    pub fn hides_listing(&self, fd: i32) -> bool {
+        self.dirs.get(&fd)
+            .is_some_and(|dir| self.check(dir).is_some())
+    }
+}
@@ -1,79 +1,857 @@
-use anyhow::Result;
-use wasmtime::{Config, Engine, Linker, Module, Store};
-use wasmtime_wasi::WasiCtxBuilder;
-use std::sync::Arc;
-use tokio::sync::Mutex;
-use tracing::{info, debug};
+use anyhow::{Context, Result, anyhow};
+use serde::{Deserialize, Serialize};
+use wasmtime::{Engine, Linker, Module, ResourceLimiter, Store};
+use wasmtime_wasi::{SocketAddrUse, WasiCtxBuilder};
+use wasmtime_wasi::preview1::WasiP1Ctx;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::io::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::broadcast;
+use tracing::{info, debug, warn};
 
-use crate::container::{Container, ContainerInfo};
-use crate::filesystem::Filesystem;
-use crate::network::{NetworkManager, ContainerNetwork};
+use crate::config::RuntimeConfig;
+use crate::error::{self, Error};
+use crate::container::{Container, ContainerDetails, ContainerInfo, Cpus, MountMode, PortMapping, RootfsBackend};
+use crate::filesystem::{dir_size, remove_path, Filesystem};
+use crate::filesystem::copy;
+use crate::filesystem::diff::{self, Change};
+use crate::filesystem::driver::StorageDriver;
+use crate::format;
+use crate::filesystem::proc::ProcInfo;
+use crate::filesystem::watch::{FsWatcher, WatchCallback};
+use crate::network::{Network, NetworkManager, NetworkMember, ContainerNetwork, DEFAULT_NETWORK, HOST_NETWORK, NO_NETWORK};
+use crate::network::mesh::MeshConfig;
+use crate::network::proxy::ProxySettings;
+use crate::network::throttle::{NetworkStats, PortStats};
+use crate::network::policy::ResolvedEgress;
+use crate::network::resolv::ResolvConf;
+use crate::state::{ContainerStore, RootfsRecord};
 
+pub mod builder;
+pub mod cancel;
+pub mod cpu;
+pub mod devices;
+pub mod events;
+pub mod handle;
+pub mod host;
+pub mod logs;
+pub mod masks;
+pub mod sockets;
+
+use self::builder::{EngineOptions, RuntimeBuilder};
+use self::cancel::{CancellationToken, Cancelled};
+use self::cpu::{CpuThrottle, CpuTime};
+use self::devices::DeviceTable;
+use self::events::ContainerEvent;
+use self::handle::{ContainerHandle, PipedStdio};
+use self::host::HostFunctions;
+use self::logs::LoggedOutput;
+use self::masks::PathMasks;
+use self::sockets::SocketTable;
+
+const DEFAULT_MEMORY_LIMIT: u64 = 4 * 1024 * 1024 * 1024;
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+const STATS_INTERVAL: Duration = Duration::from_secs(1);
+
+// Leftovers not tied to a dead run may belong to work still in progress in
+// another process, so they are only reclaimed once they are this old.
+pub const ORPHAN_GRACE_PERIOD: Duration = Duration::from_secs(60 * 60);
+
+// Exit codes for runs that failed without the guest exiting with one of
+// its own, as docker's: the runtime failed, the guest couldn't be started,
+// or the export to call is missing. Traps abort the guest, as wasmtime's
+// CLI reports them, and cancelled guests are killed.
+pub const EXIT_RUNTIME_ERROR: i32 = 125;
+pub const EXIT_NOT_INVOKABLE: i32 = 126;
+pub const EXIT_NOT_FOUND: i32 = 127;
+pub const EXIT_TRAP: i32 = 128 + libc::SIGABRT;
+pub const EXIT_CANCELLED: i32 = 128 + libc::SIGKILL;
+
+/// The version of wasmtime the runtime is built against.
+pub const WASMTIME_VERSION: &str = env!("WASMTIME_VERSION");
+
+pub struct ContainerState {
+    pub container_id: String,
+    pub wasi: WasiP1Ctx,
+    pub limits: MemoryTracker,
+    pub devices: DeviceTable,
+    pub masks: PathMasks,
+    pub sockets: SocketTable,
+}
+
+pub struct MemoryTracker {
+    used: Arc<AtomicU64>,
+    limit: u64,
+    on_limit: Option<Box<dyn Fn() + Send + Sync>>,
+}
+
+impl MemoryTracker {
+    pub fn new(limit: u64) -> Self {
+        Self {
+            used: Arc::new(AtomicU64::new(0)),
+            limit,
+            on_limit: None,
+        }
+    }
+    
+    /// Calls `callback` whenever the guest is refused memory past the limit.
+    pub fn on_limit(mut self, callback: impl Fn() + Send + Sync + 'static) -> Self {
+        self.on_limit = Some(Box::new(callback));
+        self
+    }
+    
+    pub fn used(&self) -> Arc<AtomicU64> {
+        self.used.clone()
+    }
+}
+
+impl ResourceLimiter for MemoryTracker {
+    fn memory_growing(&mut self, current: usize, desired: usize, _maximum: Option<usize>) -> wasmtime::Result<bool> {
+        if desired as u64 > self.limit {
+            if let Some(on_limit) = &self.on_limit {
+                on_limit();
+            }
+            return Ok(false);
+        }
+        
+        self.used.fetch_add((desired - current) as u64, Ordering::Relaxed);
+        Ok(true)
+    }
+    
+    fn table_growing(&mut self, _current: u32, _desired: u32, _maximum: Option<u32>) -> wasmtime::Result<bool> {
+        Ok(true)
+    }
+}
+
+// A running container's share of the CPU and its memory, sampled once a
+// second by its run.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ResourceUsage {
+    // Percent of one CPU over the last second.
+    pub cpu_percent: f64,
+    pub memory: u64,
+    pub memory_limit: u64,
+}
+
+#[derive(Debug, Default)]
+#[non_exhaustive]
+pub struct PruneReport {
+    pub removed: Vec<PathBuf>,
+    pub reclaimed: u64,
+}
+
+// What `info` reports about the runtime's setup and its containers.
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct RuntimeInfo {
+    pub state_dir: PathBuf,
+    pub scratch_dir: PathBuf,
+    // The driver behind each rootfs backend.
+    pub storage_drivers: BTreeMap<RootfsBackend, &'static str>,
+    pub containers: usize,
+    pub running: usize,
+    pub default_network: Network,
+    pub networks: usize,
+    pub max_memory: u64,
+    pub max_cpus: Cpus,
+}
+
+// What `prune` removed, and the bytes that freed.
+#[derive(Debug, Default)]
+#[non_exhaustive]
+pub struct SystemPruneReport {
+    pub containers: Vec<String>,
+    pub networks: Vec<String>,
+    // Data left behind by interrupted runs.
+    pub orphans: Vec<PathBuf>,
+    pub reclaimed: u64,
+}
+
+/// Why a guest couldn't be started, attached as context to the source of
+/// the [`Error::Trap`] `run` and `start` fail with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum StartError {
+    /// The module didn't compile or couldn't be instantiated, e.g. over an
+    /// import the runtime doesn't provide.
+    Instantiate,
+    /// The module has no function exported under this name to call.
+    MissingExport(String),
+}
+
+impl fmt::Display for StartError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Instantiate => write!(f, "Failed to instantiate module"),
+            Self::MissingExport(export) => write!(f, "Failed to call export {}", export),
+        }
+    }
+}
+
+/// The exit code of a failed run, as docker reports them: the guest's own
+/// code if it exited with one, `EXIT_NOT_INVOKABLE` or `EXIT_NOT_FOUND`
+/// if it couldn't be started, `EXIT_TRAP` if it trapped, `EXIT_CANCELLED`
+/// if it was cancelled, and `EXIT_RUNTIME_ERROR` for anything else.
+pub fn exit_code(error: &anyhow::Error) -> i32 {
+    if let Some(error) = error.downcast_ref::<Error>() {
+        return error.exit_code();
+    }
+    if let Some(exit) = error.downcast_ref::<wasmtime_wasi::I32Exit>() {
+        return exit.0;
+    }
+    if error.is::<Cancelled>() {
+        return EXIT_CANCELLED;
+    }
+    match error.downcast_ref::<StartError>() {
+        Some(StartError::Instantiate) => EXIT_NOT_INVOKABLE,
+        Some(StartError::MissingExport(_)) => EXIT_NOT_FOUND,
+        None if error.is::<wasmtime::Trap>() => EXIT_TRAP,
+        None => EXIT_RUNTIME_ERROR,
+    }
+}
+
+impl PruneReport {
+    fn remove(&mut self, path: &Path) -> Result<()> {
+        if fs::symlink_metadata(path).is_err() {
+            return Ok(());
+        }
+        
+        debug!("Removing orphaned {:?}", path);
+        self.reclaimed += dir_size(path).unwrap_or(0);
+        remove_path(path)?;
+        self.removed.push(path.to_path_buf());
+        
+        Ok(())
+    }
+}
+
+/// Adjusts a guest's WASI context after the runtime has set it up; see
+/// [`RuntimeBuilder::wasi_configure`].
+pub type WasiHook = Arc<dyn Fn(&mut WasiCtxBuilder) + Send + Sync>;
+
+/// Creates, runs and manages containers, keeping their state under its
+/// state directory. Clones share its containers, storage drivers, network
+/// manager and events, so one can be moved into a task to run a container
+/// there, as [`spawn`](Self::spawn) does.
+///
+/// Settings not given to [`WasmRuntime::with_config`] come from the
+/// config file, as for the CLI:
+///
+/// ```no_run
+/// use wasm_container::{RuntimeConfig, WasmRuntime};
+///
+/// # fn main() -> anyhow::Result<()> {
+/// let mut config = RuntimeConfig::load()?;
+/// config.state_dir = Some("/var/lib/myapp/containers".into());
+/// let runtime = WasmRuntime::with_config(&config)?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
 pub struct WasmRuntime {
     engine: Engine,
-    containers: Arc<Mutex<Vec<ContainerInfo>>>,
+    // Fuel each run's store starts with, if the engine meters it.
+    fuel: Option<u64>,
+    store: ContainerStore,
+    drivers: Arc<HashMap<RootfsBackend, Box<dyn StorageDriver>>>,
+    state_dir: PathBuf,
+    scratch_dir: PathBuf,
     network_manager: NetworkManager,
+    dns: ResolvConf,
+    proxy: ProxySettings,
+    mesh: Option<MeshConfig>,
+    masked_paths: Vec<PathBuf>,
+    fs_watchers: Vec<WatchCallback>,
+    wasi_hooks: Vec<WasiHook>,
+    host_functions: HostFunctions,
+    // Stdio of attached containers, until they run.
+    attached: Arc<Mutex<HashMap<String, PipedStdio>>>,
+    events: broadcast::Sender<ContainerEvent>,
+    max_memory: u64,
+    max_cpus: Cpus,
 }
 
 impl WasmRuntime {
-    pub fn new() -> Result<Self> {
-        let mut config = Config::new();
-        config.wasm_threads(true);
-        config.wasm_simd(true);
-        config.async_support(true);
-        
-        let engine = Engine::new(&config)?;
-        let network_manager = NetworkManager::new();
-        
-        Ok(Self {
-            engine,
-            containers: Arc::new(Mutex::new(Vec::new())),
-            network_manager,
-        })
+    pub fn new() -> error::Result<Self> {
+        Self::with_config(&RuntimeConfig::load()?)
+    }
+    
+    pub fn with_state_dir(state_dir: &Path) -> error::Result<Self> {
+        let mut config = RuntimeConfig::load()?;
+        config.state_dir = Some(state_dir.to_path_buf());
+        Self::with_config(&config)
+    }
+    
+    pub fn with_config(settings: &RuntimeConfig) -> error::Result<Self> {
+        RuntimeBuilder::new().config(settings.clone()).build()
+    }
+    
+    pub fn builder() -> RuntimeBuilder {
+        RuntimeBuilder::new()
+    }
+    
+    /// Registers a callback invoked for every file added, changed or deleted
+    /// in a container's rootfs while it runs. Changes are picked up by
+    /// polling, with a final check once the guest exits.
+    pub fn on_fs_change<F>(&mut self, callback: F)
+    where
+        F: Fn(&Change) + Send + Sync + 'static,
+    {
+        self.fs_watchers.push(Arc::new(callback));
+    }
+    
+    /// Pipes the stdio of the container's next run or start to the
+    /// returned handle instead of the host's. Attaching again replaces the
+    /// earlier handle's pipes.
+    pub fn attach(&self, container_id: &str) -> ContainerHandle {
+        let (handle, stdio) = handle::pipe(container_id);
+        self.attached.lock().unwrap().insert(container_id.to_string(), stdio);
+        handle
+    }
+    
+    /// Runs the container in a task of its own, returning at once with a
+    /// handle to wait for it, kill it, sample its resource usage and talk
+    /// to its stdio, which is piped to the handle as with
+    /// [`attach`](Self::attach). Failures to set the run up surface from
+    /// [`ContainerHandle::wait`]. Must be called within a tokio runtime.
+    ///
+    /// ```no_run
+    /// use wasm_container::{Container, WasmRuntime};
+    ///
+    /// # async fn example(runtime: WasmRuntime, container: Container) -> anyhow::Result<()> {
+    /// let mut handle = runtime.spawn(container);
+    /// println!("Running {}", handle.id());
+    /// let status = handle.wait().await?;
+    /// assert!(status.success());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn spawn(&self, container: Container) -> ContainerHandle {
+        let handle = self.attach(container.id());
+        let cancel = CancellationToken::new();
+        let runtime = self.clone();
+        let run = {
+            let cancel = cancel.clone();
+            tokio::spawn(async move { runtime.run_cancellable(container, cancel).await })
+        };
+        handle.with_run(self.clone(), cancel, run)
+    }
+    
+    /// Lifecycle events of the containers this runtime creates, runs, stops
+    /// and removes from now on. A receiver more than 256 events behind
+    /// misses the oldest, and is told how many with `RecvError::Lagged`.
+    pub fn subscribe(&self) -> broadcast::Receiver<ContainerEvent> {
+        self.events.subscribe()
+    }
+    
+    /// The events recorded for a container, oldest first, including those
+    /// from other runtimes sharing its state store.
+    pub fn container_events(&self, container_id: &str) -> error::Result<Vec<ContainerEvent>> {
+        Ok(self.store.events(&self.store.resolve(container_id)?)?)
+    }
+    
+    fn emit(&self, event: ContainerEvent) {
+        record_event(&self.store, &event);
+        // Nobody may be subscribed.
+        let _ = self.events.send(event);
+    }
+    
+    /// The networks this runtime's containers join. Clones share their
+    /// state, so handing one to another runtime with `set_network_manager`
+    /// lets containers run by both reach each other over the bridge.
+    pub fn network_manager(&self) -> NetworkManager {
+        self.network_manager.clone()
+    }
+    
+    pub fn set_network_manager(&mut self, network_manager: NetworkManager) {
+        self.network_manager = network_manager;
+    }
+    
+    /// Peers with the runtimes on other hosts if the config sets up a mesh,
+    /// for as long as this runtime's network manager is in use.
+    pub async fn join_mesh(&self) -> error::Result<()> {
+        if let Some(config) = &self.mesh {
+            self.network_manager.join_mesh(config.clone()).await?;
+        }
+        Ok(())
     }
     
-    pub async fn run(&mut self, mut container: Container) -> Result<()> {
+    /// Records a container without running it, for `start` to run later.
+    pub async fn create(&self, container: &Container) -> error::Result<()> {
+        self.check_resources(container)?;
+        self.store.save(container, &self.created_info(container))?;
+        self.emit(ContainerEvent::Created { id: container.id().to_string() });
+        Ok(())
+    }
+    
+    /// Runs the container until its guest exits; see [`spawn`](Self::spawn)
+    /// to run it in the background instead.
+    pub async fn run(&self, container: Container) -> error::Result<()> {
+        self.run_cancellable(container, CancellationToken::new()).await
+    }
+    
+    /// Like [`run`](Self::run), stopping the guest and cleaning up after it
+    /// once `cancel` is cancelled, with [`Error::Cancelled`].
+    pub async fn run_cancellable(&self, container: Container, cancel: CancellationToken) -> error::Result<()> {
         info!("Starting container: {}", container.id());
+        // Taken first, so the handle's streams end however the run does.
+        let stdio = self.attached.lock().unwrap().remove(container.id());
+        self.check_resources(&container)?;
         
-        let filesystem = Filesystem::new(&container)?;
-        filesystem.setup().await?;
-        
-        let network = self.network_manager.setup_container_network(&container).await?;
-        
-        let wasi_ctx = self.build_wasi_context(&container, &filesystem, &network)?;
-        
-        let mut store = Store::new(&self.engine, wasi_ctx);
-        
-        let module = self.compile_container(&container).await?;
-        
-        let mut linker = Linker::new(&self.engine);
-        wasmtime_wasi::preview1::add_to_linker_sync(&mut linker, |s| s)?;
+        let created = self.store.load_info(container.id()).is_err();
+        let mut container_info = self.created_info(&container);
+        self.store.save(&container, &container_info)?;
+        if created {
+            self.emit(ContainerEvent::Created { id: container.id().to_string() });
+        }
         
-        self.add_custom_host_functions(&mut linker)?;
+        let driver = self.driver(container.rootfs_backend())?;
+        debug!("Using {} storage driver for {}", driver.name(), container.id());
+        let rootfs = driver.prepare_rootfs(container.id())?;
+        self.store.track_rootfs(container.id(), &RootfsRecord {
+            rootfs: rootfs.clone(),
+            tmpfs: self.tmpfs_dir(container.id()),
+            pid: std::process::id(),
+            persistent: driver.persistent(),
+        })?;
         
-        let instance = linker.instantiate_async(&mut store, &module).await?;
+        let result = self.execute(&container, &mut container_info, rootfs, stdio, &cancel).await;
         
-        let start = instance.get_typed_func::<(), ()>(&mut store, "_start")?;
+        // Drivers without a persistent rootfs rebuild it from the image
+        // layers on every start, so it is dropped once the guest exits.
+        let driver = self.driver(container.rootfs_backend())?;
+        if driver.persistent() {
+            driver.unmount(container.id())?;
+        } else {
+            driver.cleanup(container.id())?;
+        }
+        let tmpfs = self.tmpfs_dir(container.id());
+        if tmpfs.exists() {
+            fs::remove_dir_all(&tmpfs)?;
+        }
+        self.store.untrack_rootfs(container.id())?;
         
-        let container_info = ContainerInfo {
+        let id = container.id().to_string();
+        self.emit(match &result {
+            Ok(()) => ContainerEvent::Exited { id, exit_code: 0 },
+            Err(e) => match e.downcast_ref::<wasmtime_wasi::I32Exit>() {
+                Some(exit) => ContainerEvent::Exited { id, exit_code: exit.0 },
+                None => ContainerEvent::Failed { id, exit_code: exit_code(e), error: format!("{:#}", e) },
+            },
+        });
+        Ok(result?)
+    }
+    
+    fn created_info(&self, container: &Container) -> ContainerInfo {
+        ContainerInfo {
             id: container.id().to_string(),
+            name: container.name().map(str::to_string),
             image: container.image_name().to_string(),
-            status: "running".to_string(),
-        };
-        
-        self.containers.lock().await.push(container_info);
+            status: "created".to_string(),
+            ports: Vec::new(),
+            addresses: BTreeMap::new(),
+            labels: container.labels().clone(),
+            // Restarts keep the time the container was first created.
+            created_at: self.store.load_info(container.id())
+                .map(|info| info.created_at)
+                .ok()
+                .filter(|created_at| *created_at != 0)
+                .unwrap_or_else(format::unix_now),
+            started_at: None,
+            exit_code: None,
+        }
+    }
+    
+    async fn execute(
+        &self,
+        container: &Container,
+        container_info: &mut ContainerInfo,
+        rootfs: PathBuf,
+        stdio: Option<PipedStdio>,
+        cancel: &CancellationToken,
+    ) -> Result<()> {
+        self.check_port_conflicts(container)?;
+        let driver = self.driver(container.rootfs_backend())?;
+        let fresh = !driver.persistent() || !self.store.has_pristine_index(container.id())?;
         
-        let result = start.call_async(&mut store, ()).await;
+        let mut filesystem = Filesystem::new(container, rootfs)?;
+        if fresh {
+            filesystem.apply_layers(driver, &container.image_data().layers).await?;
+            self.store.save_ownership(container.id(), filesystem.ownership())?;
+        }
+        let network_config = container.network_config();
+        let egress = network_config.egress.resolve().await?;
+        let proxy = network_config.proxy.or(&self.proxy);
+        let outbound = if container.network_disabled() {
+            None
+        } else {
+            proxy.outbound()?
+        };
+        let network = if container.network_disabled() {
+            if !network_config.ports.is_empty() {
+                return Err(anyhow!("Container {} has networking disabled and can't publish ports", container.id()));
+            }
+            None
+        } else if container.host_network() {
+            if !network_config.ports.is_empty() {
+                return Err(anyhow!(
+                    "Container {} uses the host's network, where it listens on host ports directly; drop -p",
+                    container.id()
+                ));
+            }
+            None
+        } else {
+            Some(self.network_manager.setup_container_network(container).await?)
+        };
+        // Whatever fails once the container has joined its networks, they
+        // are left and the container is marked failed.
+        let result = async {
+            // Containers only on internal networks reach nothing beyond them,
+            // not even through the proxy.
+            let internal = network.is_some() && self.network_manager.internal(container.id()).await;
+            let outbound = outbound.filter(|_| !internal);
+            if let Some(network) = &network {
+                container_info.ports = network.port_mappings.clone();
+                for (name, ip) in &network.addresses {
+                    container_info.addresses.entry(name.clone()).or_default().push(*ip);
+                }
+            }
+            
+            let resolv_conf = ResolvConf::layered(&[
+                ResolvConf::new(network_config.dns.clone(), network_config.dns_search.clone()),
+                self.dns.clone(),
+                ResolvConf::from_host(),
+            ]);
+            // Peers are resolved by the network's own DNS server, which passes
+            // everything else on to the nameservers the container would have used.
+            let embedded_dns = match network {
+                Some(_) => self.network_manager.embedded_dns(container.id(), &resolv_conf.nameservers).await,
+                None => None,
+            };
+            let mut resolv_conf = match embedded_dns {
+                Some(server) => ResolvConf::new(vec![server], resolv_conf.search),
+                None => resolv_conf,
+            };
+            // The networks' own domains are searched before any other.
+            let mut search = self.network_manager.search_domains(container.id()).await;
+            for domain in resolv_conf.search {
+                if !search.contains(&domain) {
+                    search.push(domain);
+                }
+            }
+            resolv_conf.search = search;
+            filesystem.set_resolv_conf(resolv_conf);
+            filesystem.setup().await?;
+            
+            if let (Some(hosts_file), Some(_)) = (filesystem.hosts_file(), &network) {
+                self.network_manager.attach_hosts_file(container.id(), hosts_file).await?;
+            }
+            
+            if !self.store.has_pristine_index(container.id())? {
+                let index = diff::scan(filesystem.rootfs_path())?;
+                self.store.save_pristine_index(container.id(), &index)?;
+            }
+            
+            let env = container_env(container, network.as_ref(), &proxy);
+            let args = container_args(container);
+            
+            let resources = container.resources();
+            let memory_limit = resources.memory.unwrap_or(self.max_memory);
+            let limits = MemoryTracker::new(memory_limit).on_limit({
+                let events = self.events.clone();
+                let store = self.store.clone();
+                let id = container.id().to_string();
+                move || {
+                    let event = ContainerEvent::Oom { id: id.clone() };
+                    record_event(&store, &event);
+                    let _ = events.send(event);
+                }
+            });
+            let memory_used = limits.used();
+            let proc_fs = Arc::new(filesystem.mount_proc(
+                ProcInfo {
+                    cpus: resources.cpus.unwrap_or(self.max_cpus).count(),
+                    memory_limit,
+                    environ: env.clone(),
+                    cmdline: args.clone(),
+                },
+                limits.used(),
+            )?);
+            
+            let tmpfs = self.tmpfs_dir(container.id());
+            for (i, path) in container.tmpfs().iter().enumerate() {
+                filesystem.mount_tmpfs(path, &tmpfs.join(i.to_string()))?;
+            }
+            
+            let mut file_mounts = Vec::new();
+            for volume in container.volumes() {
+                file_mounts.extend(filesystem.mount_volume(volume)?);
+            }
+            let file_mounts = Arc::new(file_mounts);
+            
+            let preopens = preopens(container, &filesystem);
+            let sockets = network.is_some() || container.host_network();
+            let log = fs::File::create(self.store.log_path(container.id())?)?;
+            let mut wasi = build_wasi_context(&preopens, &env, &args, sockets, internal, &egress, GuestStdio { log, piped: stdio })?;
+            for hook in &self.wasi_hooks {
+                hook(&mut wasi);
+            }
+            let wasi = wasi.build_p1();
+            
+            let mut masked_paths = self.masked_paths.clone();
+            masked_paths.extend(container.masked_paths().iter().cloned());
+            let guest_preopens: Vec<(PathBuf, PathBuf)> = preopens.iter()
+                .map(|preopen| (preopen.host.clone(), preopen.guest.clone()))
+                .collect();
+            let masks = PathMasks::new(&guest_preopens, &masked_paths)?;
+            
+            let mut store = Store::new(&self.engine, ContainerState {
+                container_id: container.id().to_string(),
+                wasi,
+                limits,
+                devices: DeviceTable::default(),
+                masks,
+                sockets: SocketTable::new(self.network_manager.clone(), container.id(), egress, outbound, container.host_network()),
+            });
+            store.limiter(|state| &mut state.limits);
+            if let Some(fuel) = self.fuel {
+                store.set_fuel(fuel)?;
+            }
+            let throttle = CpuThrottle::new(resources.cpus);
+            CpuThrottle::install(throttle.as_ref(), &mut store, cancel.clone());
+            cancel.watch_engine(&self.engine);
+            
+            let module = self.compile_container(container).await?;
+            
+            let mut linker = Linker::new(&self.engine);
+            wasmtime_wasi::preview1::add_to_linker_async(&mut linker, |state: &mut ContainerState| &mut state.wasi)?;
+            devices::add_to_linker(&mut linker)?;
+            sockets::add_to_linker(&mut linker)?;
+            
+            self.host_functions.add_to_linker(&mut linker)?;
+            
+            let instance = linker.instantiate_async(&mut store, &module).await.context(StartError::Instantiate)?;
+            
+            let start = instance.get_typed_func::<(), ()>(&mut store, container.export())
+                .context(StartError::MissingExport(container.export().to_string()))?;
+            if cancel.is_cancelled() {
+                return Err(Cancelled.into());
+            }
+            
+            container_info.status = "running".to_string();
+            container_info.started_at = Some(format::unix_now());
+            self.store.save_info(container_info)?;
+            self.emit(ContainerEvent::Started { id: container.id().to_string() });
+            
+            let refresher = proc_fs.clone();
+            let proc_refresh = tokio::spawn(async move {
+                let mut interval = tokio::time::interval(Duration::from_secs(1));
+                loop {
+                    interval.tick().await;
+                    if let Err(e) = refresher.refresh() {
+                        debug!("Failed to refresh /proc: {}", e);
+                    }
+                }
+            });
+            
+            // CPU time and memory are sampled on the same beat as throughput,
+            // for `top`.
+            let cpu_time = Arc::new(CpuTime::new());
+            let usage_sampler = {
+                let store = self.store.clone();
+                let container_id = container.id().to_string();
+                let cpu_time = cpu_time.clone();
+                tokio::spawn(async move {
+                    let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + STATS_INTERVAL, STATS_INTERVAL);
+                    let mut last = (0, Instant::now());
+                    loop {
+                        interval.tick().await;
+                        let ran = cpu_time.nanos();
+                        let usage = ResourceUsage {
+                            cpu_percent: ran.saturating_sub(last.0) as f64 / last.1.elapsed().as_nanos() as f64 * 100.0,
+                            memory: memory_used.load(Ordering::Relaxed),
+                            memory_limit,
+                        };
+                        if let Err(e) = store.save_usage(&container_id, &usage) {
+                            debug!("Failed to save resource usage: {}", e);
+                        }
+                        last = (ran, Instant::now());
+                    }
+                })
+            };
+            
+            // Throughput is sampled once a second for `stats`, which reads it
+            // from the container's directory. Captures `netdump` asks for there
+            // are started and stopped on the same beat.
+            let stats_sampler = match self.network_manager.traffic(container.id()).await {
+                Some(traffic) => {
+                    let store = self.store.clone();
+                    let network_manager = self.network_manager.clone();
+                    let container_id = container.id().to_string();
+                    Some(tokio::spawn(async move {
+                        let mut interval = tokio::time::interval(STATS_INTERVAL);
+                        let mut last = (NetworkStats::default(), Instant::now());
+                        let mut capturing = None;
+                        loop {
+                            interval.tick().await;
+                            let mut stats = traffic.stats(&last.0, last.1.elapsed());
+                            stats.ports = network_manager.port_stats(&container_id).await;
+                            if let Err(e) = store.save_stats(&container_id, &stats) {
+                                debug!("Failed to save network stats: {}", e);
+                            }
+                            last = (stats, Instant::now());
+                            
+                            let requested = store.capture_request(&container_id).unwrap_or_else(|e| {
+                                debug!("Failed to read capture request: {}", e);
+                                None
+                            });
+                            if requested != capturing {
+                                match &requested {
+                                    Some(output) => {
+                                        if let Err(e) = network_manager.start_capture(&container_id, output).await {
+                                            warn!("{}", e);
+                                        }
+                                    }
+                                    None => network_manager.stop_capture(&container_id).await,
+                                }
+                                capturing = requested;
+                            }
+                        }
+                    }))
+                }
+                None => None,
+            };
+            
+            // `network connect` adds networks to the stored container; ones
+            // added while it runs are joined on the next beat, and recorded with
+            // their addresses for `network inspect` in other processes.
+            let network_watch = network.as_ref().map(|_| {
+                let store = self.store.clone();
+                let network_manager = self.network_manager.clone();
+                let container_id = container.id().to_string();
+                let mut joined = container.networks().to_vec();
+                tokio::spawn(async move {
+                    let mut interval = tokio::time::interval(STATS_INTERVAL);
+                    loop {
+                        interval.tick().await;
+                        let added: Vec<String> = match store.load(&container_id) {
+                            Ok(container) => container.networks().iter()
+                                .filter(|network| !joined.contains(network))
+                                .cloned()
+                                .collect(),
+                            Err(e) => {
+                                debug!("Failed to reload container {}: {}", container_id, e);
+                                continue;
+                            }
+                        };
+                        for network in added {
+                            match network_manager.join_network(&container_id, &network).await {
+                                Ok(ips) => {
+                                    let saved = store.load_info(&container_id).and_then(|mut info| {
+                                        info.addresses.insert(network.clone(), ips);
+                                        store.save_info(&info)
+                                    });
+                                    if let Err(e) = saved {
+                                        debug!("Failed to record addresses on network {}: {}", network, e);
+                                    }
+                                }
+                                Err(e) => warn!("Failed to connect container {} to network {}: {}", container_id, network, e),
+                            }
+                            joined.push(network);
+                        }
+                    }
+                })
+            });
+            
+            let watcher = if self.fs_watchers.is_empty() {
+                None
+            } else {
+                let watcher = Arc::new(FsWatcher::new(filesystem.rootfs_path(), self.fs_watchers.clone())?);
+                let poller = watcher.clone();
+                let watch = tokio::spawn(async move {
+                    let mut interval = tokio::time::interval(POLL_INTERVAL);
+                    loop {
+                        interval.tick().await;
+                        if let Err(e) = poller.poll() {
+                            debug!("Failed to poll rootfs changes: {}", e);
+                        }
+                    }
+                });
+                Some((watcher, watch))
+            };
+            
+            let mounts = file_mounts.clone();
+            let file_sync = tokio::spawn(async move {
+                let mut interval = tokio::time::interval(POLL_INTERVAL);
+                loop {
+                    interval.tick().await;
+                    for mount in mounts.iter() {
+                        if let Err(e) = mount.poll() {
+                            debug!("Failed to sync {:?}: {}", mount.host_path(), e);
+                        }
+                    }
+                }
+            });
+            
+            let call = cpu_time.run(start.call_async(&mut store, ()));
+            let call = cancel.run(async {
+                match &throttle {
+                    Some(throttle) => throttle.run(&self.engine, call).await,
+                    None => call.await,
+                }
+            }).await;
+            proc_refresh.abort();
+            usage_sampler.abort();
+            if let Some(stats_sampler) = stats_sampler {
+                stats_sampler.abort();
+            }
+            if let Some(network_watch) = network_watch {
+                network_watch.abort();
+            }
+            self.store.remove_stats(container.id())?;
+            self.store.remove_usage(container.id())?;
+            self.store.cancel_capture(container.id())?;
+            proc_fs.refresh()?;
+            file_sync.abort();
+            for mount in file_mounts.iter() {
+                mount.sync()?;
+            }
+            
+            if let Some((watcher, watch)) = watcher {
+                watch.abort();
+                watcher.poll()?;
+            }
+            
+            match call {
+                Err(e) => match e.downcast_ref::<wasmtime_wasi::I32Exit>() {
+                    Some(exit) if exit.0 == 0 => Ok(()),
+                    _ => Err(e),
+                },
+                ok => ok,
+            }
+        }.await;
         
         self.network_manager.cleanup_container_network(container.id()).await?;
         
+        // Traps and other errors leave no exit code; cancelled guests are
+        // recorded as killed.
+        let exit_code = match &result {
+            Ok(_) => Some(0),
+            Err(e) if e.is::<Cancelled>() => Some(EXIT_CANCELLED),
+            Err(e) => e.downcast_ref::<wasmtime_wasi::I32Exit>().map(|exit| exit.0),
+        };
         match result {
             Ok(_) => {
-                self.update_container_status(&container.id(), "exited").await?;
+                self.update_container_status(container.id(), "exited", exit_code).await?;
                 info!("Container {} exited successfully", container.id());
             }
             Err(e) => {
-                self.update_container_status(&container.id(), "failed").await?;
+                self.update_container_status(container.id(), "failed", exit_code).await?;
                 info!("Container {} failed: {}", container.id(), e);
                 return Err(e);
             }
@@ -82,77 +860,521 @@ impl WasmRuntime {
         Ok(())
     }
     
-    pub async fn stop(&mut self, container_id: &str) -> Result<()> {
-        self.update_container_status(container_id, "stopping").await?;
-        self.network_manager.cleanup_container_network(container_id).await?;
-        self.update_container_status(container_id, "stopped").await?;
+    /// Removes data left behind by runs whose process died: their scratch
+    /// rootfs and tmpfs directories, half-created containers and partially
+    /// extracted layers. Leftovers not tied to a dead run are only removed
+    /// once they are older than `grace`, as they may belong to work still
+    /// in progress elsewhere. Runs automatically when a runtime is created.
+    pub fn prune_orphans(&self, grace: Duration) -> error::Result<PruneReport> {
+        let mut report = PruneReport::default();
+        
+        for mut info in self.store.list()? {
+            let Some(record) = self.store.tracked_rootfs(&info.id)? else {
+                continue;
+            };
+            if record.owner_alive() {
+                continue;
+            }
+            
+            info!("Cleaning up after interrupted run of {}", info.id);
+            if record.persistent {
+                // The dead run never got to release the rootfs it mounted.
+                self.driver(self.store.load(&info.id)?.rootfs_backend())?.unmount(&info.id)?;
+            } else {
+                report.remove(&record.rootfs)?;
+            }
+            report.remove(&record.tmpfs)?;
+            self.store.untrack_rootfs(&info.id)?;
+            
+            if info.status == "running" {
+                info.status = "failed".to_string();
+                info.ports.clear();
+                info.addresses.clear();
+                self.store.save_info(&info)?;
+            }
+        }
+        
+        let mut leftovers = self.store.incomplete()?;
+        for driver in self.drivers.values() {
+            leftovers.extend(driver.partial_data()?);
+        }
+        for path in leftovers {
+            let age = fs::symlink_metadata(&path)
+                .and_then(|metadata| metadata.modified())
+                .ok()
+                .and_then(|modified| SystemTime::now().duration_since(modified).ok());
+            if age.is_some_and(|age| age >= grace) {
+                report.remove(&path)?;
+            }
+        }
+        
+        Ok(report)
+    }
+    
+    pub async fn info(&self) -> error::Result<RuntimeInfo> {
+        let containers = self.store.list()?;
+        let networks = self.network_manager.list_networks().await?;
+        Ok(RuntimeInfo {
+            state_dir: self.state_dir.clone(),
+            scratch_dir: self.scratch_dir.clone(),
+            storage_drivers: self.drivers.iter().map(|(backend, driver)| (*backend, driver.name())).collect(),
+            containers: containers.len(),
+            running: containers.iter().filter(|info| info.status == "running").count(),
+            default_network: self.network_manager.inspect_network(DEFAULT_NETWORK).await?,
+            networks: networks.len(),
+            max_memory: self.max_memory,
+            max_cpus: self.max_cpus,
+        })
+    }
+    
+    /// Removes what no running container needs: data left behind by dead
+    /// runs, stopped containers, and user-defined networks none of the
+    /// remaining containers are on. Containers another process is still
+    /// starting or stopping are left alone.
+    pub async fn prune(&mut self) -> error::Result<SystemPruneReport> {
+        // Dead runs are marked failed first, giving up their published
+        // ports, so they are removed with the other stopped containers.
+        let orphans = self.prune_orphans(ORPHAN_GRACE_PERIOD)?;
+        let mut report = SystemPruneReport {
+            orphans: orphans.removed,
+            reclaimed: orphans.reclaimed,
+            ..SystemPruneReport::default()
+        };
+        
+        let mut networks_in_use = HashSet::new();
+        for info in self.store.list()? {
+            let busy = self.store.tracked_rootfs(&info.id)?.is_some_and(|record| record.owner_alive());
+            if info.status == "running" || busy {
+                networks_in_use.extend(self.store.load(&info.id)?.networks().iter().cloned());
+                continue;
+            }
+            let size = dir_size(&self.store.container_dir(&info.id)?).unwrap_or(0);
+            self.remove(&info.id, false).await?;
+            report.reclaimed += size;
+            report.containers.push(info.id);
+        }
+        
+        for network in self.network_manager.list_networks().await? {
+            if network.name == DEFAULT_NETWORK || networks_in_use.contains(&network.name) || !network.containers.is_empty() {
+                continue;
+            }
+            self.network_manager.remove_network(&network.name).await?;
+            report.networks.push(network.name);
+        }
+        
+        Ok(report)
+    }
+    
+    // The `name:tag` of every container's image.
+    pub fn images_in_use(&self) -> error::Result<Vec<String>> {
+        let mut images = Vec::new();
+        for info in self.store.list()? {
+            let container = self.store.load(&info.id)?;
+            images.push(format!("{}:{}", container.image_data().name, container.image_data().tag));
+        }
+        images.sort();
+        images.dedup();
+        Ok(images)
+    }
+    
+    // Host ports published by other running containers, here or in other
+    // processes, would only fail to bind once the layers are applied, so
+    // they are checked first.
+    fn check_port_conflicts(&self, container: &Container) -> Result<()> {
+        if container.network_config().publish_retry {
+            return Ok(());
+        }
+        let running: Vec<ContainerInfo> = self.store.list()?
+            .into_iter()
+            .filter(|info| info.status == "running" && info.id != container.id())
+            .collect();
+        
+        for port_map in container.network_config().ports.iter().filter(|port_map| port_map.host_port != 0) {
+            let owner = running.iter().find(|info| {
+                info.ports.iter().any(|published| {
+                    published.host_port == port_map.host_port && published.protocol.eq_ignore_ascii_case(&port_map.protocol)
+                })
+            });
+            if let Some(owner) = owner {
+                return Err(anyhow!(
+                    "Host port {}/{} is already published by container {}; publish another port or use --publish-retry",
+                    port_map.host_port,
+                    port_map.protocol.to_lowercase(),
+                    owner.id
+                ));
+            }
+        }
+        
         Ok(())
     }
     
-    pub async fn list_containers(&self, all: bool) -> Result<Vec<ContainerInfo>> {
-        let containers = self.containers.lock().await;
+    fn driver(&self, backend: RootfsBackend) -> Result<&dyn StorageDriver> {
+        self.drivers.get(&backend)
+            .map(|driver| driver.as_ref())
+            .ok_or_else(|| anyhow!("No storage driver for the {} rootfs backend", backend))
+    }
+    
+    // Runs `f` on the rootfs of a container that may not be running,
+    // mounting it for the duration if its driver needs to.
+    fn with_rootfs<T>(&self, container_id: &str, f: impl FnOnce(&Path) -> Result<T>) -> Result<T> {
+        let container = self.store.load(container_id)?;
+        let driver = self.driver(container.rootfs_backend())?;
         
-        if all {
-            Ok(containers.clone())
-        } else {
-            Ok(containers
-                .iter()
-                .filter(|c| c.status == "running")
-                .cloned()
-                .collect())
+        let mounted = driver.mount(container_id)?;
+        let result = f(&driver.rootfs_path(container_id)?);
+        if mounted {
+            driver.unmount(container_id)?;
         }
+        
+        result
     }
     
-    fn build_wasi_context(&self, container: &Container, filesystem: &Filesystem, network: &ContainerNetwork) -> Result<wasmtime_wasi::preview1::WasiP1Ctx> {
-        let mut builder = WasiCtxBuilder::new();
+    // Limits above the host's maximums are refused rather than lowered.
+    fn check_resources(&self, container: &Container) -> Result<()> {
+        let resources = container.resources();
+        if resources.memory == Some(0) {
+            return Err(anyhow!("Memory limit must be above zero"));
+        }
+        if let Some(memory) = resources.memory.filter(|memory| *memory > self.max_memory) {
+            return Err(anyhow!(
+                "Memory limit of {} bytes is above the host maximum of {} bytes",
+                memory, self.max_memory
+            ));
+        }
+        if let Some(cpus) = resources.cpus.filter(|cpus| *cpus > self.max_cpus) {
+            return Err(anyhow!("CPU limit of {} is above the host maximum of {}", cpus, self.max_cpus));
+        }
+        Ok(())
+    }
+    
+    fn tmpfs_dir(&self, container_id: &str) -> PathBuf {
+        self.scratch_dir.join(format!("{}-tmpfs", container_id))
+    }
+    
+    pub async fn start(&mut self, container_id: &str) -> error::Result<()> {
+        let container_id = &self.store.resolve(container_id)?;
+        let info = self.store.load_info(container_id)?;
         
-        builder
-            .inherit_stdio()
-            .inherit_network();
+        if info.status == "running" {
+            return Err(anyhow!("Container {} is already running", container_id).into());
+        }
         
-        for (key, value) in container.env_vars() {
-            builder.env(&key, &value);
+        let container = self.store.load(container_id)?;
+        self.run(container).await
+    }
+    
+    pub async fn stop(&mut self, container_id: &str) -> error::Result<()> {
+        // Stopping a container that is already gone is not an error.
+        let container_id = &self.store.resolve(container_id).unwrap_or_else(|_| container_id.to_string());
+        self.update_container_status(container_id, "stopping", None).await?;
+        self.network_manager.cleanup_container_network(container_id).await?;
+        self.update_container_status(container_id, "stopped", None).await?;
+        self.emit(ContainerEvent::Stopped { id: container_id.clone() });
+        Ok(())
+    }
+    
+    pub async fn remove(&mut self, container_id: &str, force: bool) -> error::Result<()> {
+        let container_id = &self.store.resolve(container_id)?;
+        let info = self.store.load_info(container_id)?;
+        
+        if info.status == "running" {
+            if !force {
+                return Err(Error::ContainerRunning(container_id.clone()));
+            }
+            self.stop(container_id).await?;
         }
         
-        builder.env("CONTAINER_IP", &network.get_ip().to_string());
-        builder.env("HOSTNAME", network.get_hostname());
+        let container = self.store.load(container_id)?;
+        self.driver(container.rootfs_backend())?.cleanup(container_id)?;
+        self.store.remove(container_id)?;
+        info!("Removed container: {}", container_id);
+        self.emit(ContainerEvent::Removed { id: container_id.clone() });
         
-        use wasmtime_wasi::{DirPerms, FilePerms};
+        Ok(())
+    }
+    
+    /// Connects a container to `network`. A running container's run joins
+    /// it within a second, recording its addresses there in the container's
+    /// info; others join it when they next start.
+    pub async fn connect_network(&self, network: &str, container_id: &str) -> error::Result<()> {
+        let container_id = &self.store.resolve(container_id)?;
+        if network == NO_NETWORK || network == HOST_NETWORK {
+            return Err(anyhow!("Network {} can't be connected to; run the container with --network {}", network, network).into());
+        }
+        self.network_manager.inspect_network(network).await?;
         
-        if let Some(workdir) = container.workdir() {
-            builder.preopened_dir(
-                filesystem.rootfs_path().join(workdir.trim_start_matches('/')),
-                "/",
-                DirPerms::all(),
-                FilePerms::all()
-            )?;
-        } else {
-            builder.preopened_dir(
-                filesystem.rootfs_path(),
-                "/",
-                DirPerms::all(),
-                FilePerms::all()
-            )?;
+        let mut container = self.store.load(container_id)?;
+        if container.network_disabled() {
+            return Err(anyhow!("Container {} has networking disabled", container_id).into());
+        }
+        if container.host_network() {
+            return Err(anyhow!("Container {} uses the host's network", container_id).into());
+        }
+        if !container.connect_network(network) {
+            return Err(Error::NetworkConflict(format!("Container {} is already connected to network {}", container_id, network)));
         }
+        self.store.save(&container, &self.store.load_info(container_id)?)?;
         
-        for volume in container.volumes() {
-            filesystem.mount_volume(&volume.host_path, &volume.container_path)?;
+        info!("Connected container {} to network {}", container_id, network);
+        self.emit(ContainerEvent::NetworkConnected { id: container_id.clone(), network: network.to_string() });
+        Ok(())
+    }
+    
+    /// Disconnects a container from `network` from its next start on.
+    pub async fn disconnect_network(&self, network: &str, container_id: &str) -> error::Result<()> {
+        let container_id = &self.store.resolve(container_id)?;
+        let mut container = self.store.load(container_id)?;
+        if !container.disconnect_network(network) {
+            return Err(anyhow!("Container {} is not connected to network {}", container_id, network).into());
+        }
+        if container.networks().is_empty() {
+            return Err(anyhow!("Container {} must stay connected to at least one network", container_id).into());
         }
+        self.store.save(&container, &self.store.load_info(container_id)?)?;
         
-        if let Some(args) = container.command() {
-            builder.args(&args);
-        } else {
-            let config = &container.image_data().config;
-            if !config.entrypoint.is_empty() {
-                let mut all_args = config.entrypoint.clone();
-                all_args.extend(config.cmd.clone());
-                builder.args(&all_args);
-            } else if !config.cmd.is_empty() {
-                builder.args(&config.cmd);
+        info!("Disconnected container {} from network {}", container_id, network);
+        self.emit(ContainerEvent::NetworkDisconnected { id: container_id.clone(), network: network.to_string() });
+        Ok(())
+    }
+    
+    /// Removes a user-defined network no container is connected to.
+    pub async fn remove_network(&self, network: &str) -> error::Result<()> {
+        if let Some(container_id) = self.network_containers(network)?.first() {
+            return Err(Error::NetworkConflict(format!("Network {} is in use by container {}", network, container_id)));
+        }
+        self.network_manager.remove_network(network).await
+    }
+    
+    /// Describes `network`, listing every container connected to it rather
+    /// than just the ones running in this process. Containers running
+    /// elsewhere are shown with the addresses their process recorded.
+    pub async fn inspect_network(&self, network: &str) -> error::Result<Network> {
+        let mut info = self.network_manager.inspect_network(network).await?;
+        let running = std::mem::take(&mut info.members);
+        for container_id in self.network_containers(network)? {
+            let member = match running.iter().find(|member| member.id == container_id) {
+                Some(member) => member.clone(),
+                None => {
+                    let hostname = self.store.load(&container_id)?.network_config().hostname.clone();
+                    let addresses = self.store.load_info(&container_id)?.addresses
+                        .remove(network)
+                        .unwrap_or_default();
+                    NetworkMember::new(&container_id, &hostname, addresses, info.domain.as_deref())
+                }
+            };
+            info.members.push(member);
+        }
+        Ok(info)
+    }
+    
+    fn network_containers(&self, network: &str) -> Result<Vec<String>> {
+        let mut containers = Vec::new();
+        for info in self.store.list()? {
+            if self.store.load(&info.id)?.networks().iter().any(|name| name == network) {
+                containers.push(info.id);
             }
         }
+        Ok(containers)
+    }
+    
+    pub async fn diff(&self, container_id: &str) -> error::Result<Vec<Change>> {
+        let container_id = &self.store.resolve(container_id)?;
+        let container = self.store.load(container_id)?;
+        let pristine = self.store.load_pristine_index(container_id)?;
         
-        Ok(builder.build_p1())
+        Ok(self.driver(container.rootfs_backend())?.commit_diff(container_id, &pristine)?)
+    }
+    
+    /// Captures a stopped container's rootfs under `name`, so it can later
+    /// be rolled back with `restore_snapshot`.
+    pub async fn create_snapshot(&self, container_id: &str, name: &str) -> error::Result<()> {
+        let container_id = &self.store.resolve(container_id)?;
+        let driver = self.snapshot_driver(container_id)?;
+        let snapshot = self.store.snapshot_dir(container_id, name)?;
+        
+        if snapshot.exists() {
+            return Err(anyhow!("Snapshot {} already exists for container {}", name, container_id).into());
+        }
+        
+        // Captured next to its final location and renamed into place, so an
+        // interrupted capture never looks like a complete snapshot.
+        let staging = snapshot.with_file_name(format!(".tmp-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(self.store.snapshots_dir(container_id)?)?;
+        if let Err(e) = driver.snapshot(container_id, &staging) {
+            remove_path(&staging).ok();
+            return Err(e.into());
+        }
+        fs::rename(&staging, &snapshot)?;
+        
+        info!("Created snapshot {} of container {}", name, container_id);
+        Ok(())
+    }
+    
+    pub async fn restore_snapshot(&self, container_id: &str, name: &str) -> error::Result<()> {
+        let container_id = &self.store.resolve(container_id)?;
+        let driver = self.snapshot_driver(container_id)?;
+        let snapshot = self.store.snapshot_dir(container_id, name)?;
+        
+        if !snapshot.is_dir() {
+            return Err(anyhow!("No such snapshot {} for container {}", name, container_id).into());
+        }
+        
+        driver.restore(container_id, &snapshot)?;
+        
+        info!("Restored container {} to snapshot {}", container_id, name);
+        Ok(())
+    }
+    
+    pub async fn list_snapshots(&self, container_id: &str) -> error::Result<Vec<String>> {
+        let container_id = &self.store.resolve(container_id)?;
+        self.store.load_info(container_id)?;
+        Ok(self.store.list_snapshots(container_id)?)
+    }
+    
+    // Snapshots need a rootfs that outlives the run, and one that isn't
+    // being written to while it is copied.
+    fn snapshot_driver(&self, container_id: &str) -> Result<&dyn StorageDriver> {
+        let info = self.store.load_info(container_id)?;
+        if info.status == "running" {
+            return Err(Error::ContainerRunning(container_id.to_string()).into());
+        }
+        
+        let container = self.store.load(container_id)?;
+        let driver = self.driver(container.rootfs_backend())?;
+        if !driver.persistent() {
+            return Err(anyhow!(
+                "Container {} has a {} rootfs, which is not kept between runs",
+                container_id, container.rootfs_backend()
+            ));
+        }
+        
+        Ok(driver)
+    }
+    
+    pub async fn export<W: Write>(&self, container_id: &str, writer: W) -> error::Result<W> {
+        let container_id = &self.store.resolve(container_id)?;
+        self.store.load_info(container_id)?;
+        let writer = self.with_rootfs(container_id, |rootfs| {
+            let mut builder = tar::Builder::new(writer);
+            builder.follow_symlinks(false);
+            builder.append_dir_all(".", rootfs)?;
+            
+            Ok(builder.into_inner()?)
+        })?;
+        Ok(writer)
+    }
+    
+    pub async fn copy_from_container(&self, container_id: &str, container_path: &Path, host_path: &Path) -> error::Result<()> {
+        let container_id = &self.store.resolve(container_id)?;
+        self.store.load_info(container_id)?;
+        Ok(self.with_rootfs(container_id, |rootfs| copy::copy_from_container(rootfs, container_path, host_path))?)
+    }
+    
+    pub async fn copy_to_container(&self, container_id: &str, host_path: &Path, container_path: &Path) -> error::Result<()> {
+        let container_id = &self.store.resolve(container_id)?;
+        self.store.load_info(container_id)?;
+        Ok(self.with_rootfs(container_id, |rootfs| copy::copy_to_container(rootfs, host_path, container_path))?)
+    }
+    
+    /// The network traffic through a running container's published ports,
+    /// as of the last second.
+    pub async fn stats(&self, container_id: &str) -> error::Result<NetworkStats> {
+        let container_id = &self.store.resolve(container_id)?;
+        let info = self.store.load_info(container_id)?;
+        if info.status != "running" {
+            return Err(anyhow!("Container {} is not running", container_id).into());
+        }
+        
+        Ok(self.store.load_stats(container_id)?.unwrap_or_default())
+    }
+    
+    /// A running container's CPU and memory use, as of the last second.
+    pub async fn usage(&self, container_id: &str) -> error::Result<ResourceUsage> {
+        let container_id = &self.store.resolve(container_id)?;
+        let info = self.store.load_info(container_id)?;
+        if info.status != "running" {
+            return Err(anyhow!("Container {} is not running", container_id).into());
+        }
+        
+        Ok(self.store.load_usage(container_id)?.unwrap_or_default())
+    }
+    
+    /// The last `lines` lines a container printed on its latest run.
+    pub async fn logs(&self, container_id: &str, lines: usize) -> error::Result<Vec<String>> {
+        let container_id = &self.store.resolve(container_id)?;
+        Ok(self.store.tail_log(container_id, lines)?)
+    }
+    
+    /// Asks a running container's run to record the traffic through its
+    /// published ports to a pcap file at `output`, which it creates within
+    /// a second.
+    pub async fn start_capture(&self, container_id: &str, output: &Path) -> error::Result<()> {
+        let container_id = &self.store.resolve(container_id)?;
+        let info = self.store.load_info(container_id)?;
+        if info.status != "running" {
+            return Err(anyhow!("Container {} is not running", container_id).into());
+        }
+        
+        Ok(self.store.request_capture(container_id, &std::path::absolute(output)?)?)
+    }
+    
+    pub async fn stop_capture(&self, container_id: &str) -> error::Result<()> {
+        let container_id = &self.store.resolve(container_id)?;
+        Ok(self.store.cancel_capture(container_id)?)
+    }
+    
+    /// The host ports a running container is published on.
+    pub async fn ports(&self, container_id: &str) -> error::Result<Vec<PortMapping>> {
+        let container_id = &self.store.resolve(container_id)?;
+        Ok(self.store.load_info(container_id)?.ports)
+    }
+    
+    /// Each published port with its open connections and the bytes it has
+    /// carried, as of the last second.
+    pub async fn port_stats(&self, container_id: &str) -> error::Result<Vec<PortStats>> {
+        let container_id = &self.store.resolve(container_id)?;
+        let info = self.store.load_info(container_id)?;
+        let sampled = self.store.load_stats(container_id)?.unwrap_or_default().ports;
+        
+        Ok(info.ports.iter()
+            .map(|port| {
+                sampled.iter()
+                    .find(|stats| stats.host_port == port.host_port && stats.protocol == port.protocol)
+                    .cloned()
+                    .unwrap_or_else(|| PortStats {
+                        host_port: port.host_port,
+                        container_port: port.container_port,
+                        protocol: port.protocol.clone(),
+                        ..PortStats::default()
+                    })
+            })
+            .collect())
+    }
+    
+    /// A container's info, with its network traffic if it is running.
+    pub async fn inspect(&self, container_id: &str) -> error::Result<ContainerDetails> {
+        let container_id = &self.store.resolve(container_id)?;
+        let info = self.store.load_info(container_id)?;
+        let network = if info.status == "running" {
+            Some(self.store.load_stats(container_id)?.unwrap_or_default())
+        } else {
+            None
+        };
+        
+        Ok(ContainerDetails { info, network })
+    }
+    
+    pub async fn list_containers(&self, all: bool) -> error::Result<Vec<ContainerInfo>> {
+        let containers = self.store.list()?;
+        
+        if all {
+            Ok(containers)
+        } else {
+            Ok(containers
+                .into_iter()
+                .filter(|c| c.status == "running")
+                .collect())
+        }
     }
     
     async fn compile_container(&self, container: &Container) -> Result<Module> {
@@ -160,52 +1382,201 @@ impl WasmRuntime {
         
         let wasm_bytes = container.get_wasm_binary().await?;
         
-        let module = Module::new(&self.engine, &wasm_bytes)?;
+        let module = Module::new(&self.engine, &wasm_bytes).context(StartError::Instantiate)?;
         
         Ok(module)
     }
     
-    fn add_custom_host_functions(&self, linker: &mut Linker<wasmtime_wasi::preview1::WasiP1Ctx>) -> Result<()> {
-        linker.func_wrap(
-            "env",
-            "container_log",
-            |mut caller: wasmtime::Caller<'_, wasmtime_wasi::preview1::WasiP1Ctx>, ptr: i32, len: i32| -> wasmtime::Result<()> {
-                let memory = caller.get_export("memory")
-                    .and_then(|e| e.into_memory())
-                    .ok_or_else(|| anyhow::anyhow!("failed to get memory"))?;
-                
-                let data = memory.data(&caller);
-                if ptr < 0 || len < 0 || (ptr + len) as usize > data.len() {
-                    return Err(anyhow::anyhow!("invalid memory access").into());
-                }
-                
-                let message = std::str::from_utf8(&data[ptr as usize..(ptr + len) as usize])
-                    .map_err(|_| anyhow::anyhow!("invalid UTF-8"))?;
-                
-                info!("[Container]: {}", message);
-                
-                Ok(())
-            }
-        )?;
-        
-        linker.func_wrap(
-            "env", 
-            "get_container_info",
-            |_caller: wasmtime::Caller<'_, wasmtime_wasi::preview1::WasiP1Ctx>| -> wasmtime::Result<i32> {
-                Ok(42)
+    async fn update_container_status(&self, container_id: &str, status: &str, exit_code: Option<i32>) -> Result<()> {
+        if let Ok(mut info) = self.store.load_info(container_id) {
+            info.status = status.to_string();
+            info.exit_code = exit_code;
+            // Published ports and addresses are given up when the container
+            // stops.
+            if status != "running" {
+                info.ports.clear();
+                info.addresses.clear();
             }
-        )?;
+            self.store.save_info(&info)?;
+        }
         
         Ok(())
     }
+}
+
+// Containers without networking get no address variables.
+fn container_env(container: &Container, network: Option<&ContainerNetwork>, proxy: &ProxySettings) -> Vec<(String, String)> {
+    let mut env: Vec<(String, String)> = container.env_vars()
+        .iter()
+        .filter(|(key, _)| key.as_str() != "HOSTNAME")
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
+    env.sort();
     
-    async fn update_container_status(&self, container_id: &str, status: &str) -> Result<()> {
-        let mut containers = self.containers.lock().await;
-        
-        if let Some(container) = containers.iter_mut().find(|c| c.id == container_id) {
-            container.status = status.to_string();
+    if let Some(network) = network {
+        env.push(("CONTAINER_IP".to_string(), network.get_ip().to_string()));
+        if let Some(ip6) = network.ip6_address {
+            env.push(("CONTAINER_IP6".to_string(), ip6.to_string()));
         }
+        // Proxy variables the container sets itself are left alone.
+        env.extend(proxy.env()
+            .into_iter()
+            .filter(|(key, _)| !container.env_vars().contains_key(key)));
+    }
+    env.push(("HOSTNAME".to_string(), container.network_config().hostname.clone()));
+    
+    env
+}
+
+/// Checks that wasmtime can compile guests for this host's CPU with the
+/// proposals they may use, SIMD among them.
+pub fn check_host_support() -> Result<()> {
+    let engine = Engine::new(&EngineOptions::default().config()?)?;
+    Module::new(&engine, "(module (func (result v128) v128.const i64x2 0 0))")?;
+    Ok(())
+}
+
+/// The WebAssembly proposals guests may use, such as `simd` and `threads`.
+pub fn wasm_features() -> Vec<String> {
+    let Ok(config) = EngineOptions::default().config() else {
+        return Vec::new();
+    };
+    // Wasmtime has no getter for these, but its Config's Debug output
+    // lists every one as `wasm_<feature>: <enabled>`.
+    format!("{:#?}", config)
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("wasm_")?.strip_suffix(": true,"))
+        .map(|feature| feature.replace('_', "-"))
+        .collect()
+}
+
+fn container_args(container: &Container) -> Vec<String> {
+    if let Some(args) = container.command() {
+        return args.clone();
+    }
+    
+    let config = &container.image_data().config;
+    let mut args = config.entrypoint.clone();
+    args.extend(config.cmd.clone());
+    args
+}
+
+// A directory shared with the guest through a WASI preopen.
+struct Preopen {
+    host: PathBuf,
+    guest: PathBuf,
+    read_only: bool,
+}
+
+// Everything the guest can reach, in preopen order: the rootfs first, then
+// bind-mounted volumes, then tmpfs directories. Volume preopens come before
+// tmpfs ones so they keep stable descriptors.
+fn preopens(container: &Container, filesystem: &Filesystem) -> Vec<Preopen> {
+    let root = match container.workdir() {
+        Some(workdir) => filesystem.rootfs_path().join(workdir.trim_start_matches('/')),
+        None => filesystem.rootfs_path().to_path_buf(),
+    };
+    let mut preopens = vec![Preopen {
+        host: root,
+        guest: PathBuf::from("/"),
+        read_only: container.read_only(),
+    }];
+    
+    for volume in container.volumes() {
+        if volume.host_path.is_dir() && volume.mode == MountMode::Bind {
+            preopens.push(Preopen {
+                host: volume.host_path.clone(),
+                guest: volume.container_path.clone(),
+                read_only: volume.read_only,
+            });
+        }
+    }
+    
+    for (host_dir, container_path) in filesystem.tmpfs_mounts() {
+        preopens.push(Preopen {
+            host: host_dir.clone(),
+            guest: container_path.clone(),
+            read_only: false,
+        });
+    }
+    
+    preopens
+}
+
+// Keeps `event` with its container's records; a removed container's are
+// gone, and with them its earlier events.
+fn record_event(store: &ContainerStore, event: &ContainerEvent) {
+    if matches!(event, ContainerEvent::Removed { .. }) {
+        return;
+    }
+    if let Err(e) = store.record_event(event) {
+        debug!("Failed to record event for {}: {}", event.container_id(), e);
+    }
+}
+
+// Where a guest's stdio leads: the host's, or an attached handle's pipes.
+// Output is copied to the log either way.
+struct GuestStdio {
+    log: fs::File,
+    piped: Option<PipedStdio>,
+}
+
+fn build_wasi_context(
+    preopens: &[Preopen],
+    env: &[(String, String)],
+    args: &[String],
+    network: bool,
+    internal: bool,
+    egress: &ResolvedEgress,
+    stdio: GuestStdio,
+) -> Result<WasiCtxBuilder> {
+    use wasmtime_wasi::{DirPerms, FilePerms};
+    
+    let mut builder = WasiCtxBuilder::new();
+    
+    // Output goes to the host's stdout and stderr, or an attached handle,
+    // as well as the log.
+    let log = stdio.log;
+    match stdio.piped {
+        Some(piped) => {
+            builder.stdin(piped.stdin);
+            builder.stdout(LoggedOutput::new(piped.stdout, log.try_clone()?));
+            builder.stderr(LoggedOutput::new(piped.stderr, log));
+        }
+        None => {
+            builder.inherit_stdin();
+            builder.stdout(LoggedOutput::new(wasmtime_wasi::stdout(), log.try_clone()?));
+            builder.stderr(LoggedOutput::new(wasmtime_wasi::stderr(), log));
+        }
+    }
+    if network {
+        // Binding stays open so guests can listen; where they connect or
+        // send to is up to the container's egress policy. Host sockets lead
+        // out of the container's networks, so internal ones only keep
+        // loopback.
+        let egress = egress.clone();
+        builder.inherit_network().socket_addr_check(move |addr, addr_use| {
+            let allowed = match addr_use {
+                SocketAddrUse::TcpBind | SocketAddrUse::UdpBind => true,
+                _ if internal => addr.ip().is_loopback(),
+                _ => egress.allows(addr),
+            };
+            Box::pin(async move { allowed })
+        });
+    }
+    
+    builder.envs(env);
+    builder.args(args);
+    
+    for preopen in preopens {
+        let (dir_perms, file_perms) = if preopen.read_only {
+            (DirPerms::READ, FilePerms::READ)
+        } else {
+            (DirPerms::all(), FilePerms::all())
+        };
         
-        Ok(())
+        builder.preopened_dir(&preopen.host, preopen.guest.to_string_lossy(), dir_perms, file_perms)?;
     }
-}
\ No newline at end of file
+    
+    Ok(builder)
+}
@@ -0,0 +1,300 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+use tokio::net::{TcpListener, TcpStream};
+use wasmtime::{Caller, Linker};
+
+use crate::network::NetworkManager;
+use crate::network::policy::ResolvedEgress;
+use crate::network::proxy::OutboundProxy;
+use crate::network::bridge::{self, BridgeListener};
+use super::ContainerState;
+use super::devices::{memory, read_bytes, write_bytes};
+
+const NET_MODULE: &str = "env";
+
+// Below the device descriptors and still far above anything the WASI fd
+// table allocates.
+const SOCKET_FD_BASE: u32 = 1 << 29;
+
+// The most a single send or receive moves, so a guest can't make the host
+// allocate arbitrarily large buffers.
+const MAX_TRANSFER: usize = 64 * 1024;
+
+const ERRNO_SUCCESS: i32 = 0;
+const ERRNO_ACCES: i32 = 2;
+const ERRNO_ADDRINUSE: i32 = 3;
+const ERRNO_BADF: i32 = 8;
+const ERRNO_CONNREFUSED: i32 = 14;
+const ERRNO_FAULT: i32 = 21;
+const ERRNO_HOSTUNREACH: i32 = 23;
+const ERRNO_INVAL: i32 = 28;
+const ERRNO_IO: i32 = 29;
+const ERRNO_NETUNREACH: i32 = 40;
+const ERRNO_PIPE: i32 = 64;
+
+// The guest's listeners and connections on the in-memory bridge between
+// containers, or on the host itself for containers on the host's network.
+pub struct SocketTable {
+    network: NetworkManager,
+    container_id: String,
+    egress: ResolvedEgress,
+    // Where connections outside the container's networks go, if anywhere.
+    proxy: Option<OutboundProxy>,
+    host: bool,
+    listeners: HashMap<u32, Listener>,
+    streams: HashMap<u32, DuplexStream>,
+    next: u32,
+}
+
+impl SocketTable {
+    pub fn new(
+        network: NetworkManager,
+        container_id: &str,
+        egress: ResolvedEgress,
+        proxy: Option<OutboundProxy>,
+        host: bool,
+    ) -> Self {
+        Self {
+            network,
+            container_id: container_id.to_string(),
+            egress,
+            proxy,
+            host,
+            listeners: HashMap::new(),
+            streams: HashMap::new(),
+            next: 0,
+        }
+    }
+    
+    // Cloned out so calls into the network don't hold on to the store.
+    fn endpoint(&self) -> (NetworkManager, String) {
+        (self.network.clone(), self.container_id.clone())
+    }
+    
+    fn allocate(&mut self) -> u32 {
+        let fd = SOCKET_FD_BASE + self.next;
+        self.next += 1;
+        fd
+    }
+    
+    fn add_listener(&mut self, listener: Listener) -> u32 {
+        let fd = self.allocate();
+        self.listeners.insert(fd, listener);
+        fd
+    }
+    
+    fn add_stream(&mut self, stream: DuplexStream) -> u32 {
+        let fd = self.allocate();
+        self.streams.insert(fd, stream);
+        fd
+    }
+    
+    fn close(&mut self, fd: i32) -> bool {
+        let fd = fd as u32;
+        self.listeners.remove(&fd).is_some() || self.streams.remove(&fd).is_some()
+    }
+}
+
+enum Listener {
+    Bridge(BridgeListener),
+    Host(TcpListener),
+}
+
+impl Listener {
+    async fn accept(&mut self) -> Option<DuplexStream> {
+        match self {
+            Listener::Bridge(listener) => listener.accept().await.map(|(stream, _)| stream),
+            Listener::Host(listener) => listener.accept().await.ok().map(|(stream, _)| bridge::splice(stream)),
+        }
+    }
+}
+
+// Host functions giving guests TCP-like sockets to other containers on
+// their networks. WASI preview1 can't open connections, so these live next
+// to `container_log` rather than behind the WASI socket calls. Every call
+// returns a WASI errno; descriptors and byte counts are written to the
+// pointer passed last.
+pub fn add_to_linker(linker: &mut Linker<ContainerState>) -> Result<()> {
+    linker.func_wrap_async(
+        NET_MODULE,
+        "net_listen",
+        move |mut caller: Caller<'_, ContainerState>, (port, fd_ptr): (i32, i32)| {
+            Box::new(async move {
+                let Some(port) = u16::try_from(port).ok().filter(|port| *port != 0) else {
+                    return Ok(ERRNO_INVAL);
+                };
+                let (network, container_id) = caller.data().sockets.endpoint();
+                let listener = if caller.data().sockets.host {
+                    TcpListener::bind(("0.0.0.0", port)).await.map(Listener::Host)
+                } else {
+                    network.listen(&container_id, port).await.map(Listener::Bridge)
+                };
+                let listener = match listener {
+                    Ok(listener) => listener,
+                    Err(e) => return Ok(errno(&e)),
+                };
+                
+                let fd = caller.data_mut().sockets.add_listener(listener);
+                let memory = memory(&mut caller)?;
+                Ok(write_bytes(&memory, &mut caller, fd_ptr, &fd.to_le_bytes()))
+            })
+        },
+    )?;
+    
+    linker.func_wrap_async(
+        NET_MODULE,
+        "net_accept",
+        move |mut caller: Caller<'_, ContainerState>, (fd, conn_ptr): (i32, i32)| {
+            Box::new(async move {
+                let Some(listener) = caller.data_mut().sockets.listeners.get_mut(&(fd as u32)) else {
+                    return Ok(ERRNO_BADF);
+                };
+                let Some(stream) = listener.accept().await else {
+                    return Ok(ERRNO_IO);
+                };
+                
+                let conn = caller.data_mut().sockets.add_stream(stream);
+                let memory = memory(&mut caller)?;
+                Ok(write_bytes(&memory, &mut caller, conn_ptr, &conn.to_le_bytes()))
+            })
+        },
+    )?;
+    
+    linker.func_wrap_async(
+        NET_MODULE,
+        "net_connect",
+        move |mut caller: Caller<'_, ContainerState>, params: (i32, i32, i32, i32)| {
+            Box::new(async move {
+                let (ip_ptr, ip_len, port, conn_ptr) = params;
+                let memory = memory(&mut caller)?;
+                // The address is passed as text, e.g. `172.17.0.3`.
+                let ip = read_bytes(&memory, &caller, ip_ptr, ip_len)
+                    .and_then(|ip| std::str::from_utf8(ip).ok())
+                    .and_then(|ip| ip.parse::<IpAddr>().ok());
+                let (Some(ip), Ok(port)) = (ip, u16::try_from(port)) else {
+                    return Ok(ERRNO_INVAL);
+                };
+                
+                // Loopback stays inside the container, so only other
+                // destinations are subject to the egress policy.
+                let to = SocketAddr::new(ip, port);
+                if !ip.is_loopback() && !caller.data().sockets.egress.allows(to) {
+                    return Ok(ERRNO_ACCES);
+                }
+                
+                // On the host's network, connections go straight out.
+                if caller.data().sockets.host {
+                    let stream = match TcpStream::connect(to).await {
+                        Ok(stream) => bridge::splice(stream),
+                        Err(e) => return Ok(errno(&e)),
+                    };
+                    let conn = caller.data_mut().sockets.add_stream(stream);
+                    return Ok(write_bytes(&memory, &mut caller, conn_ptr, &conn.to_le_bytes()));
+                }
+                
+                let (network, container_id) = caller.data().sockets.endpoint();
+                let proxy = caller.data().sockets.proxy.clone();
+                let stream = match network.connect(&container_id, to).await {
+                    Ok(stream) => stream,
+                    // Anything beyond the container's networks is only
+                    // reachable through its proxy.
+                    Err(e) if e.kind() == io::ErrorKind::HostUnreachable => {
+                        match proxy.filter(|proxy| !proxy.bypasses(ip)) {
+                            Some(proxy) => match proxy.connect(to).await {
+                                Ok(tunnel) => bridge::splice(tunnel),
+                                Err(e) => return Ok(errno(&e)),
+                            },
+                            None => return Ok(errno(&e)),
+                        }
+                    }
+                    Err(e) => return Ok(errno(&e)),
+                };
+                
+                let conn = caller.data_mut().sockets.add_stream(stream);
+                Ok(write_bytes(&memory, &mut caller, conn_ptr, &conn.to_le_bytes()))
+            })
+        },
+    )?;
+    
+    linker.func_wrap_async(
+        NET_MODULE,
+        "net_send",
+        move |mut caller: Caller<'_, ContainerState>, params: (i32, i32, i32, i32)| {
+            Box::new(async move {
+                let (fd, buf, len, nsent_ptr) = params;
+                let memory = memory(&mut caller)?;
+                let Some(len) = usize::try_from(len).ok() else {
+                    return Ok(ERRNO_INVAL);
+                };
+                let Some(data) = read_bytes(&memory, &caller, buf, len.min(MAX_TRANSFER) as i32) else {
+                    return Ok(ERRNO_FAULT);
+                };
+                let data = data.to_vec();
+                
+                let Some(stream) = caller.data_mut().sockets.streams.get_mut(&(fd as u32)) else {
+                    return Ok(ERRNO_BADF);
+                };
+                let sent = match stream.write(&data).await {
+                    Ok(sent) => sent as u32,
+                    Err(e) => return Ok(errno(&e)),
+                };
+                
+                Ok(write_bytes(&memory, &mut caller, nsent_ptr, &sent.to_le_bytes()))
+            })
+        },
+    )?;
+    
+    linker.func_wrap_async(
+        NET_MODULE,
+        "net_recv",
+        move |mut caller: Caller<'_, ContainerState>, params: (i32, i32, i32, i32)| {
+            Box::new(async move {
+                let (fd, buf, len, nread_ptr) = params;
+                let memory = memory(&mut caller)?;
+                let Some(len) = usize::try_from(len).ok() else {
+                    return Ok(ERRNO_INVAL);
+                };
+                
+                let Some(stream) = caller.data_mut().sockets.streams.get_mut(&(fd as u32)) else {
+                    return Ok(ERRNO_BADF);
+                };
+                // Reads 0 bytes once the peer has closed the connection.
+                let mut data = vec![0; len.min(MAX_TRANSFER)];
+                let read = match stream.read(&mut data).await {
+                    Ok(read) => read,
+                    Err(e) => return Ok(errno(&e)),
+                };
+                
+                if write_bytes(&memory, &mut caller, buf, &data[..read]) != ERRNO_SUCCESS {
+                    return Ok(ERRNO_FAULT);
+                }
+                Ok(write_bytes(&memory, &mut caller, nread_ptr, &(read as u32).to_le_bytes()))
+            })
+        },
+    )?;
+    
+    linker.func_wrap(
+        NET_MODULE,
+        "net_close",
+        |mut caller: Caller<'_, ContainerState>, fd: i32| -> wasmtime::Result<i32> {
+            Ok(if caller.data_mut().sockets.close(fd) { ERRNO_SUCCESS } else { ERRNO_BADF })
+        },
+    )?;
+    
+    Ok(())
+}
+
+fn errno(error: &io::Error) -> i32 {
+    match error.kind() {
+        io::ErrorKind::PermissionDenied => ERRNO_ACCES,
+        io::ErrorKind::AddrInUse => ERRNO_ADDRINUSE,
+        io::ErrorKind::ConnectionRefused => ERRNO_CONNREFUSED,
+        io::ErrorKind::HostUnreachable => ERRNO_HOSTUNREACH,
+        io::ErrorKind::NetworkUnreachable => ERRNO_NETUNREACH,
+        io::ErrorKind::BrokenPipe => ERRNO_PIPE,
+        _ => ERRNO_IO,
+    }
+}
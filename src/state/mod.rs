@@ -0,0 +1,485 @@
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::fs;
+use std::sync::Arc;
+
+use crate::config::RuntimeConfig;
+use crate::container::{Container, ContainerInfo};
+use crate::error::Error;
+use crate::filesystem::diff::FileIndex;
+use crate::filesystem::ownership::OwnershipMap;
+use crate::network::throttle::NetworkStats;
+use crate::runtime::ResourceUsage;
+use crate::runtime::events::ContainerEvent;
+
+pub mod sqlite;
+pub mod store;
+
+use sqlite::{SqliteStateStore, SQLITE_FILE};
+use store::{JsonStateStore, StateBackend, StateStore};
+
+// How much of the end of a container's log `tail_log` reads.
+const LOG_TAIL_BYTES: u64 = 64 * 1024;
+
+pub fn state_dir() -> Result<PathBuf> {
+    if let Some(state_dir) = RuntimeConfig::load()?.state_dir {
+        return Ok(state_dir);
+    }
+    default_state_dir()
+}
+
+pub fn default_state_dir() -> Result<PathBuf> {
+    let state_dir = dirs::data_local_dir()
+        .ok_or_else(|| anyhow!("Could not determine state directory"))?
+        .join("wasm-container");
+    
+    Ok(state_dir)
+}
+
+pub fn cache_dir() -> Result<PathBuf> {
+    if let Some(cache_dir) = RuntimeConfig::load()?.cache_dir {
+        return Ok(cache_dir);
+    }
+    default_cache_dir()
+}
+
+pub fn default_cache_dir() -> Result<PathBuf> {
+    let cache_dir = dirs::cache_dir()
+        .ok_or_else(|| anyhow!("Could not determine cache directory"))?
+        .join("wasm-container");
+    
+    Ok(cache_dir)
+}
+
+pub fn scratch_dir() -> Result<PathBuf> {
+    if let Some(scratch_dir) = RuntimeConfig::load()?.scratch_dir {
+        return Ok(scratch_dir);
+    }
+    Ok(default_scratch_dir())
+}
+
+pub fn default_scratch_dir() -> PathBuf {
+    let shm = Path::new("/dev/shm");
+    
+    if shm.is_dir() {
+        shm.join("wasm-container")
+    } else {
+        std::env::temp_dir().join("wasm-container")
+    }
+}
+
+// Written when a container starts and removed once it exits, so the scratch
+// directories of a run whose process died can be found and cleaned up.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RootfsRecord {
+    pub rootfs: PathBuf,
+    pub tmpfs: PathBuf,
+    pub pid: u32,
+    // Persistent rootfs trees outlive the run and are only removed with
+    // the container.
+    pub persistent: bool,
+}
+
+impl RootfsRecord {
+    // An owner that can't be checked is assumed alive, so nothing is
+    // reclaimed.
+    pub fn owner_alive(&self) -> bool {
+        process_alive(self.pid)
+    }
+}
+
+// Whether process `pid` is still running. Without /proc there is no cheap
+// way to tell, so it is assumed to be.
+pub fn process_alive(pid: u32) -> bool {
+    let proc = Path::new("/proc");
+    !proc.join("self").exists() || proc.join(pid.to_string()).exists()
+}
+
+// A state directory's containers: their records, kept by a `StateStore`,
+// and the directory each gets for its rootfs, logs and the like.
+#[derive(Clone)]
+pub struct ContainerStore {
+    containers_dir: PathBuf,
+    records: Arc<dyn StateStore>,
+}
+
+impl ContainerStore {
+    // Keeps records the way the state directory already does: in its
+    // database if it has one, in this process's memory store for it if one
+    // was opened, and in JSON files otherwise.
+    pub fn new(state_dir: &Path) -> Result<Self> {
+        Self::open(state_dir, None)
+    }
+    
+    // Keeps records with `backend`, or as `new` does if unset.
+    pub fn open(state_dir: &Path, backend: Option<StateBackend>) -> Result<Self> {
+        let backend = backend.unwrap_or_else(|| {
+            if state_dir.join(SQLITE_FILE).exists() {
+                StateBackend::Sqlite
+            } else if store::memory_store(state_dir, false).is_some() {
+                StateBackend::Memory
+            } else {
+                StateBackend::Json
+            }
+        });
+        let records: Arc<dyn StateStore> = match backend {
+            StateBackend::Json => Arc::new(JsonStateStore::new(state_dir.join("containers"))),
+            StateBackend::Memory => store::memory_store(state_dir, true).unwrap(),
+            StateBackend::Sqlite => {
+                fs::create_dir_all(state_dir)?;
+                Arc::new(SqliteStateStore::open(&state_dir.join(SQLITE_FILE))?)
+            }
+        };
+        Self::with_records(state_dir, records)
+    }
+    
+    pub fn with_records(state_dir: &Path, records: Arc<dyn StateStore>) -> Result<Self> {
+        let containers_dir = state_dir.join("containers");
+        fs::create_dir_all(&containers_dir)?;
+        
+        Ok(Self { containers_dir, records })
+    }
+    
+    pub fn container_dir(&self, container_id: &str) -> Result<PathBuf> {
+        validate_id(container_id)?;
+        Ok(self.containers_dir.join(container_id))
+    }
+    
+    pub fn rootfs_dir(&self, container_id: &str) -> Result<PathBuf> {
+        Ok(self.container_dir(container_id)?.join("rootfs"))
+    }
+    
+    pub fn save(&self, container: &Container, info: &ContainerInfo) -> Result<()> {
+        if let Some(name) = container.name() {
+            let taken = self.list()?
+                .into_iter()
+                .find(|other| other.id != container.id() && other.name.as_deref() == Some(name));
+            if let Some(other) = taken {
+                return Err(Error::NameInUse { name: name.to_string(), container: other.id }.into());
+            }
+        }
+        fs::create_dir_all(self.container_dir(container.id())?)?;
+        self.records.save(container, info)
+    }
+    
+    pub fn save_info(&self, info: &ContainerInfo) -> Result<()> {
+        self.records.save_info(info)
+    }
+    
+    pub fn load(&self, container_id: &str) -> Result<Container> {
+        validate_id(container_id)?;
+        self.records.load(container_id)
+    }
+    
+    pub fn load_info(&self, container_id: &str) -> Result<ContainerInfo> {
+        validate_id(container_id)?;
+        self.records.load_info(container_id)
+    }
+    
+    pub fn exists(&self, container_id: &str) -> Result<bool> {
+        validate_id(container_id)?;
+        self.records.exists(container_id)
+    }
+    
+    pub fn record_event(&self, event: &ContainerEvent) -> Result<()> {
+        self.records.record_event(event)
+    }
+    
+    pub fn events(&self, container_id: &str) -> Result<Vec<ContainerEvent>> {
+        validate_id(container_id)?;
+        self.records.events(container_id)
+    }
+    
+    // The ID of the container with `reference` as its ID or name, or else
+    // the one container whose ID or name starts with it.
+    pub fn resolve(&self, reference: &str) -> Result<String> {
+        if reference.is_empty() {
+            return Err(Error::ContainerNotFound(reference.to_string()).into());
+        }
+        if let Ok(info) = self.load_info(reference) {
+            return Ok(info.id);
+        }
+        
+        let containers = self.list()?;
+        if let Some(info) = containers.iter().find(|info| info.name.as_deref() == Some(reference)) {
+            return Ok(info.id.clone());
+        }
+        let matches: Vec<&str> = containers.iter()
+            .filter(|info| {
+                info.id.starts_with(reference) || info.name.as_deref().is_some_and(|name| name.starts_with(reference))
+            })
+            .map(|info| info.id.as_str())
+            .collect();
+        match matches.as_slice() {
+            [id] => Ok(id.to_string()),
+            [] => Err(Error::ContainerNotFound(reference.to_string()).into()),
+            _ => Err(anyhow!("{} matches several containers: {}", reference, matches.join(", "))),
+        }
+    }
+    
+    pub fn snapshots_dir(&self, container_id: &str) -> Result<PathBuf> {
+        Ok(self.container_dir(container_id)?.join("snapshots"))
+    }
+    
+    pub fn snapshot_dir(&self, container_id: &str, name: &str) -> Result<PathBuf> {
+        validate_snapshot_name(name)?;
+        Ok(self.snapshots_dir(container_id)?.join(name))
+    }
+    
+    pub fn list_snapshots(&self, container_id: &str) -> Result<Vec<String>> {
+        let snapshots_dir = self.snapshots_dir(container_id)?;
+        
+        if !snapshots_dir.is_dir() {
+            return Ok(Vec::new());
+        }
+        
+        let mut snapshots: Vec<String> = fs::read_dir(&snapshots_dir)?
+            .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+            .filter(|name| validate_snapshot_name(name).is_ok())
+            .collect();
+        snapshots.sort();
+        
+        Ok(snapshots)
+    }
+    
+    pub fn has_pristine_index(&self, container_id: &str) -> Result<bool> {
+        Ok(self.container_dir(container_id)?.join("pristine.json").exists())
+    }
+    
+    pub fn save_pristine_index(&self, container_id: &str, index: &FileIndex) -> Result<()> {
+        fs::write(
+            self.container_dir(container_id)?.join("pristine.json"),
+            serde_json::to_string(index)?,
+        )?;
+        
+        Ok(())
+    }
+    
+    pub fn load_pristine_index(&self, container_id: &str) -> Result<FileIndex> {
+        let index_file = self.container_dir(container_id)?.join("pristine.json");
+        
+        if !index_file.exists() {
+            return Err(anyhow!("No image file list recorded for container: {}", container_id));
+        }
+        
+        let index = fs::read_to_string(&index_file)?;
+        let index: FileIndex = serde_json::from_str(&index)?;
+        
+        Ok(index)
+    }
+    
+    pub fn save_ownership(&self, container_id: &str, ownership: &OwnershipMap) -> Result<()> {
+        ownership.save(&self.container_dir(container_id)?.join("ownership.json"))
+    }
+    
+    pub fn load_ownership(&self, container_id: &str) -> Result<OwnershipMap> {
+        OwnershipMap::load(&self.container_dir(container_id)?.join("ownership.json"))
+    }
+    
+    pub fn track_rootfs(&self, container_id: &str, record: &RootfsRecord) -> Result<()> {
+        fs::write(
+            self.container_dir(container_id)?.join("rootfs.json"),
+            serde_json::to_string_pretty(record)?,
+        )?;
+        
+        Ok(())
+    }
+    
+    pub fn untrack_rootfs(&self, container_id: &str) -> Result<()> {
+        match fs::remove_file(self.container_dir(container_id)?.join("rootfs.json")) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+    
+    pub fn tracked_rootfs(&self, container_id: &str) -> Result<Option<RootfsRecord>> {
+        let record_file = self.container_dir(container_id)?.join("rootfs.json");
+        
+        if !record_file.exists() {
+            return Ok(None);
+        }
+        
+        let record = fs::read_to_string(&record_file)?;
+        Ok(Some(serde_json::from_str(&record)?))
+    }
+    
+    // Written by the run while the container is running.
+    pub fn save_stats(&self, container_id: &str, stats: &NetworkStats) -> Result<()> {
+        fs::write(
+            self.container_dir(container_id)?.join("stats.json"),
+            serde_json::to_string_pretty(stats)?,
+        )?;
+        
+        Ok(())
+    }
+    
+    pub fn load_stats(&self, container_id: &str) -> Result<Option<NetworkStats>> {
+        let stats_file = self.container_dir(container_id)?.join("stats.json");
+        
+        if !stats_file.exists() {
+            return Ok(None);
+        }
+        
+        let stats = fs::read_to_string(&stats_file)?;
+        Ok(Some(serde_json::from_str(&stats)?))
+    }
+    
+    pub fn remove_stats(&self, container_id: &str) -> Result<()> {
+        match fs::remove_file(self.container_dir(container_id)?.join("stats.json")) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+    
+    // Written by the run while the container is running, next to its
+    // network stats.
+    pub fn save_usage(&self, container_id: &str, usage: &ResourceUsage) -> Result<()> {
+        fs::write(
+            self.container_dir(container_id)?.join("usage.json"),
+            serde_json::to_string_pretty(usage)?,
+        )?;
+        
+        Ok(())
+    }
+    
+    pub fn load_usage(&self, container_id: &str) -> Result<Option<ResourceUsage>> {
+        let usage_file = self.container_dir(container_id)?.join("usage.json");
+        
+        if !usage_file.exists() {
+            return Ok(None);
+        }
+        
+        let usage = fs::read_to_string(&usage_file)?;
+        Ok(Some(serde_json::from_str(&usage)?))
+    }
+    
+    pub fn remove_usage(&self, container_id: &str) -> Result<()> {
+        match fs::remove_file(self.container_dir(container_id)?.join("usage.json")) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+    
+    // What the container printed to stdout and stderr on its last run.
+    pub fn log_path(&self, container_id: &str) -> Result<PathBuf> {
+        Ok(self.container_dir(container_id)?.join("container.log"))
+    }
+    
+    // The last `lines` lines of the container's log, read from its end so
+    // long-running containers' logs aren't read whole.
+    pub fn tail_log(&self, container_id: &str, lines: usize) -> Result<Vec<String>> {
+        let mut log = match fs::File::open(self.log_path(container_id)?) {
+            Ok(log) => log,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let start = log.metadata()?.len().saturating_sub(LOG_TAIL_BYTES);
+        log.seek(SeekFrom::Start(start))?;
+        let mut tail = Vec::new();
+        log.read_to_end(&mut tail)?;
+        
+        let tail = String::from_utf8_lossy(&tail);
+        let mut tail_lines: Vec<&str> = tail.lines().collect();
+        // The first line is likely cut short when reading from the middle.
+        if start > 0 && !tail_lines.is_empty() {
+            tail_lines.remove(0);
+        }
+        let skip = tail_lines.len().saturating_sub(lines);
+        Ok(tail_lines[skip..].iter().map(ToString::to_string).collect())
+    }
+    
+    // Written by `netdump` for the run to pick up, naming the pcap file to
+    // record the container's traffic into.
+    pub fn request_capture(&self, container_id: &str, output: &Path) -> Result<()> {
+        fs::write(
+            self.container_dir(container_id)?.join("capture.json"),
+            serde_json::to_string(output)?,
+        )?;
+        
+        Ok(())
+    }
+    
+    pub fn capture_request(&self, container_id: &str) -> Result<Option<PathBuf>> {
+        let request_file = self.container_dir(container_id)?.join("capture.json");
+        
+        if !request_file.exists() {
+            return Ok(None);
+        }
+        
+        let output = fs::read_to_string(&request_file)?;
+        Ok(Some(serde_json::from_str(&output)?))
+    }
+    
+    pub fn cancel_capture(&self, container_id: &str) -> Result<()> {
+        match fs::remove_file(self.container_dir(container_id)?.join("capture.json")) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+    
+    // Container directories a crash left without any metadata.
+    pub fn incomplete(&self) -> Result<Vec<PathBuf>> {
+        let mut incomplete = Vec::new();
+        
+        for entry in fs::read_dir(&self.containers_dir)? {
+            let entry = entry?;
+            let recorded = match entry.file_name().to_str() {
+                Some(id) => self.exists(id)?,
+                None => false,
+            };
+            if entry.path().is_dir() && !recorded {
+                incomplete.push(entry.path());
+            }
+        }
+        
+        Ok(incomplete)
+    }
+    
+    pub fn list(&self) -> Result<Vec<ContainerInfo>> {
+        self.records.list()
+    }
+    
+    pub fn remove(&self, container_id: &str) -> Result<()> {
+        self.load_info(container_id)?;
+        
+        self.records.remove(container_id)?;
+        let container_dir = self.container_dir(container_id)?;
+        if container_dir.exists() {
+            fs::remove_dir_all(container_dir)?;
+        }
+        
+        Ok(())
+    }
+}
+
+fn validate_snapshot_name(name: &str) -> Result<()> {
+    let mut chars = name.chars();
+    
+    let valid = match chars.next() {
+        Some(first) => first.is_ascii_alphanumeric()
+            && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-')),
+        None => false,
+    };
+    
+    if !valid {
+        return Err(anyhow!(
+            "Invalid snapshot name: {:?} (must match [a-zA-Z0-9][a-zA-Z0-9_.-]*)",
+            name
+        ));
+    }
+    
+    Ok(())
+}
+
+fn validate_id(container_id: &str) -> Result<()> {
+    let valid = !container_id.is_empty()
+        && container_id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    
+    if !valid {
+        return Err(anyhow!("Invalid container ID: {:?}", container_id));
+    }
+    
+    Ok(())
+}
@@ -0,0 +1,126 @@
+use anyhow::{Result, anyhow};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::container::{Container, ContainerInfo};
+use crate::error::Error;
+use crate::runtime::events::ContainerEvent;
+
+use super::store::StateStore;
+use super::validate_id;
+
+/// The database file of the `sqlite` state store, in the state directory.
+pub const SQLITE_FILE: &str = "state.db";
+
+// How long to wait on another process holding the database's lock.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS containers (
+        id TEXT PRIMARY KEY,
+        container TEXT NOT NULL,
+        info TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS events (
+        seq INTEGER PRIMARY KEY AUTOINCREMENT,
+        container_id TEXT NOT NULL,
+        event TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS events_by_container ON events (container_id, seq);
+";
+
+/// Keeps records in an SQLite database, which processes sharing the state
+/// directory read and write under its locks.
+pub struct SqliteStateStore {
+    connection: Mutex<Connection>,
+}
+
+impl SqliteStateStore {
+    pub fn open(path: &Path) -> Result<Self> {
+        let connection = Connection::open(path)
+            .map_err(|e| anyhow!("Failed to open state database {:?}: {}", path, e))?;
+        connection.busy_timeout(BUSY_TIMEOUT)?;
+        connection.execute_batch(SCHEMA)?;
+        Ok(Self { connection: Mutex::new(connection) })
+    }
+    
+    fn load_column(&self, container_id: &str, column: &str) -> Result<String> {
+        let connection = self.connection.lock().unwrap();
+        connection
+            .query_row(&format!("SELECT {} FROM containers WHERE id = ?1", column), [container_id], |row| row.get(0))
+            .optional()?
+            .ok_or_else(|| Error::ContainerNotFound(container_id.to_string()).into())
+    }
+}
+
+impl StateStore for SqliteStateStore {
+    fn save(&self, container: &Container, info: &ContainerInfo) -> Result<()> {
+        validate_id(container.id())?;
+        self.connection.lock().unwrap().execute(
+            "INSERT INTO containers (id, container, info) VALUES (?1, ?2, ?3)
+             ON CONFLICT (id) DO UPDATE SET container = excluded.container, info = excluded.info",
+            params![container.id(), serde_json::to_string(container)?, serde_json::to_string(info)?],
+        )?;
+        Ok(())
+    }
+    
+    fn save_info(&self, info: &ContainerInfo) -> Result<()> {
+        let updated = self.connection.lock().unwrap().execute(
+            "UPDATE containers SET info = ?2 WHERE id = ?1",
+            params![info.id, serde_json::to_string(info)?],
+        )?;
+        if updated == 0 {
+            return Err(Error::ContainerNotFound(info.id.clone()).into());
+        }
+        Ok(())
+    }
+    
+    fn load(&self, container_id: &str) -> Result<Container> {
+        Ok(serde_json::from_str(&self.load_column(container_id, "container")?)?)
+    }
+    
+    fn load_info(&self, container_id: &str) -> Result<ContainerInfo> {
+        Ok(serde_json::from_str(&self.load_column(container_id, "info")?)?)
+    }
+    
+    fn exists(&self, container_id: &str) -> Result<bool> {
+        let connection = self.connection.lock().unwrap();
+        let found = connection
+            .query_row("SELECT 1 FROM containers WHERE id = ?1", [container_id], |_| Ok(()))
+            .optional()?;
+        Ok(found.is_some())
+    }
+    
+    fn list(&self) -> Result<Vec<ContainerInfo>> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare("SELECT info FROM containers ORDER BY id")?;
+        let infos = statement.query_map([], |row| row.get::<_, String>(0))?;
+        infos.map(|info| Ok(serde_json::from_str(&info?)?)).collect()
+    }
+    
+    fn remove(&self, container_id: &str) -> Result<()> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction()?;
+        transaction.execute("DELETE FROM containers WHERE id = ?1", [container_id])?;
+        transaction.execute("DELETE FROM events WHERE container_id = ?1", [container_id])?;
+        transaction.commit()?;
+        Ok(())
+    }
+    
+    fn record_event(&self, event: &ContainerEvent) -> Result<()> {
+        self.connection.lock().unwrap().execute(
+            "INSERT INTO events (container_id, event) VALUES (?1, ?2)",
+            params![event.container_id(), serde_json::to_string(event)?],
+        )?;
+        Ok(())
+    }
+    
+    fn events(&self, container_id: &str) -> Result<Vec<ContainerEvent>> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare("SELECT event FROM events WHERE container_id = ?1 ORDER BY seq")?;
+        let events = statement.query_map([container_id], |row| row.get::<_, String>(0))?;
+        events.map(|event| Ok(serde_json::from_str(&event?)?)).collect()
+    }
+}
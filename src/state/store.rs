@@ -0,0 +1,296 @@
+use anyhow::{Result, anyhow};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use crate::container::{Container, ContainerInfo};
+use crate::error::Error;
+use crate::runtime::events::ContainerEvent;
+
+use super::validate_id;
+
+/// Where containers' records are kept: their settings, their info and the
+/// lifecycle events seen for them. A container's events go with it when it
+/// is removed.
+///
+/// Runtimes and the daemon use the JSON files in the state directory by
+/// default; embedders can pass their own store, such as one backed by
+/// their database, to [`RuntimeBuilder::state_store`](crate::RuntimeBuilder::state_store).
+pub trait StateStore: Send + Sync {
+    fn save(&self, container: &Container, info: &ContainerInfo) -> Result<()>;
+    
+    /// Replaces the info of a container already saved.
+    fn save_info(&self, info: &ContainerInfo) -> Result<()>;
+    
+    fn load(&self, container_id: &str) -> Result<Container>;
+    
+    fn load_info(&self, container_id: &str) -> Result<ContainerInfo>;
+    
+    /// Whether the container is saved, even if its records can't be read.
+    fn exists(&self, container_id: &str) -> Result<bool>;
+    
+    /// The info of every container, sorted by ID.
+    fn list(&self) -> Result<Vec<ContainerInfo>>;
+    
+    /// Forgets a container and its events.
+    fn remove(&self, container_id: &str) -> Result<()>;
+    
+    fn record_event(&self, event: &ContainerEvent) -> Result<()>;
+    
+    /// A container's events, oldest first.
+    fn events(&self, container_id: &str) -> Result<Vec<ContainerEvent>>;
+}
+
+/// The built-in state stores, as the `state_store` setting names them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StateBackend {
+    /// JSON files in each container's directory, readable by every process
+    /// using the state directory.
+    Json,
+    /// Kept in memory, shared by the runtimes of one process using the same
+    /// state directory and lost when it exits.
+    Memory,
+    /// An SQLite database in the state directory.
+    Sqlite,
+}
+
+impl FromStr for StateBackend {
+    type Err = anyhow::Error;
+    
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "json" => Ok(Self::Json),
+            "memory" => Ok(Self::Memory),
+            "sqlite" => Ok(Self::Sqlite),
+            _ => Err(anyhow!("Invalid state store: {} (expected json, memory or sqlite)", s)),
+        }
+    }
+}
+
+impl fmt::Display for StateBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Json => write!(f, "json"),
+            Self::Memory => write!(f, "memory"),
+            Self::Sqlite => write!(f, "sqlite"),
+        }
+    }
+}
+
+// Memory stores by the state directory they stand in for, so the runtimes
+// of one process share theirs.
+static MEMORY_STORES: OnceLock<Mutex<HashMap<PathBuf, Arc<MemoryStateStore>>>> = OnceLock::new();
+
+// The memory store of `state_dir`, created if `create` is set.
+pub(super) fn memory_store(state_dir: &Path, create: bool) -> Option<Arc<MemoryStateStore>> {
+    let mut stores = MEMORY_STORES.get_or_init(Mutex::default).lock().unwrap();
+    match stores.get(state_dir) {
+        Some(store) => Some(store.clone()),
+        None if create => {
+            let store = Arc::new(MemoryStateStore::new());
+            stores.insert(state_dir.to_path_buf(), store.clone());
+            Some(store)
+        }
+        None => None,
+    }
+}
+
+/// Keeps `container.json`, `info.json` and `events.jsonl` in a directory
+/// per container.
+pub struct JsonStateStore {
+    containers_dir: PathBuf,
+}
+
+impl JsonStateStore {
+    pub fn new(containers_dir: impl Into<PathBuf>) -> Self {
+        Self { containers_dir: containers_dir.into() }
+    }
+    
+    fn container_dir(&self, container_id: &str) -> Result<PathBuf> {
+        validate_id(container_id)?;
+        Ok(self.containers_dir.join(container_id))
+    }
+}
+
+impl StateStore for JsonStateStore {
+    fn save(&self, container: &Container, info: &ContainerInfo) -> Result<()> {
+        let container_dir = self.container_dir(container.id())?;
+        fs::create_dir_all(&container_dir)?;
+        
+        fs::write(
+            container_dir.join("container.json"),
+            serde_json::to_string_pretty(container)?,
+        )?;
+        
+        self.save_info(info)
+    }
+    
+    fn save_info(&self, info: &ContainerInfo) -> Result<()> {
+        let container_dir = self.container_dir(&info.id)?;
+        
+        fs::write(
+            container_dir.join("info.json"),
+            serde_json::to_string_pretty(info)?,
+        )?;
+        
+        Ok(())
+    }
+    
+    fn load(&self, container_id: &str) -> Result<Container> {
+        let config_file = self.container_dir(container_id)?.join("container.json");
+        
+        if !config_file.exists() {
+            return Err(Error::ContainerNotFound(container_id.to_string()).into());
+        }
+        
+        let config = fs::read_to_string(&config_file)?;
+        let container: Container = serde_json::from_str(&config)?;
+        
+        Ok(container)
+    }
+    
+    fn load_info(&self, container_id: &str) -> Result<ContainerInfo> {
+        let info_file = self.container_dir(container_id)?.join("info.json");
+        
+        if !info_file.exists() {
+            return Err(Error::ContainerNotFound(container_id.to_string()).into());
+        }
+        
+        let info = fs::read_to_string(&info_file)?;
+        let info: ContainerInfo = serde_json::from_str(&info)?;
+        
+        Ok(info)
+    }
+    
+    fn exists(&self, container_id: &str) -> Result<bool> {
+        Ok(self.container_dir(container_id)?.join("info.json").exists())
+    }
+    
+    fn list(&self) -> Result<Vec<ContainerInfo>> {
+        let mut containers = Vec::new();
+        
+        for entry in fs::read_dir(&self.containers_dir)? {
+            let entry = entry?;
+            if let Some(id) = entry.file_name().to_str() {
+                if let Ok(info) = self.load_info(id) {
+                    containers.push(info);
+                }
+            }
+        }
+        
+        containers.sort_by(|a, b| a.id.cmp(&b.id));
+        
+        Ok(containers)
+    }
+    
+    fn remove(&self, container_id: &str) -> Result<()> {
+        let container_dir = self.container_dir(container_id)?;
+        for file in ["container.json", "info.json", "events.jsonl"] {
+            match fs::remove_file(container_dir.join(file)) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+    
+    fn record_event(&self, event: &ContainerEvent) -> Result<()> {
+        let path = self.container_dir(event.container_id())?.join("events.jsonl");
+        let mut file = fs::OpenOptions::new().create(true).append(true).open(path)?;
+        writeln!(file, "{}", serde_json::to_string(event)?)?;
+        Ok(())
+    }
+    
+    fn events(&self, container_id: &str) -> Result<Vec<ContainerEvent>> {
+        let path = self.container_dir(container_id)?.join("events.jsonl");
+        let events = match fs::read_to_string(path) {
+            Ok(events) => events,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        events.lines()
+            .map(|line| Ok(serde_json::from_str(line)?))
+            .collect()
+    }
+}
+
+/// Keeps records in memory only, for embedders that track containers
+/// themselves and tests.
+#[derive(Default)]
+pub struct MemoryStateStore {
+    records: Mutex<BTreeMap<String, MemoryRecord>>,
+}
+
+// Containers are kept serialized, so they load as they would from disk.
+struct MemoryRecord {
+    container: String,
+    info: ContainerInfo,
+    events: Vec<ContainerEvent>,
+}
+
+impl MemoryStateStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    fn with_record<T>(&self, container_id: &str, f: impl FnOnce(&mut MemoryRecord) -> T) -> Result<T> {
+        let mut records = self.records.lock().unwrap();
+        let record = records.get_mut(container_id)
+            .ok_or_else(|| Error::ContainerNotFound(container_id.to_string()))?;
+        Ok(f(record))
+    }
+}
+
+impl StateStore for MemoryStateStore {
+    fn save(&self, container: &Container, info: &ContainerInfo) -> Result<()> {
+        validate_id(container.id())?;
+        let mut records = self.records.lock().unwrap();
+        let events = records.remove(container.id()).map(|record| record.events).unwrap_or_default();
+        records.insert(container.id().to_string(), MemoryRecord {
+            container: serde_json::to_string(container)?,
+            info: info.clone(),
+            events,
+        });
+        Ok(())
+    }
+    
+    fn save_info(&self, info: &ContainerInfo) -> Result<()> {
+        self.with_record(&info.id, |record| record.info = info.clone())
+    }
+    
+    fn load(&self, container_id: &str) -> Result<Container> {
+        let container = self.with_record(container_id, |record| record.container.clone())?;
+        Ok(serde_json::from_str(&container)?)
+    }
+    
+    fn load_info(&self, container_id: &str) -> Result<ContainerInfo> {
+        self.with_record(container_id, |record| record.info.clone())
+    }
+    
+    fn exists(&self, container_id: &str) -> Result<bool> {
+        Ok(self.records.lock().unwrap().contains_key(container_id))
+    }
+    
+    fn list(&self) -> Result<Vec<ContainerInfo>> {
+        Ok(self.records.lock().unwrap().values().map(|record| record.info.clone()).collect())
+    }
+    
+    fn remove(&self, container_id: &str) -> Result<()> {
+        self.records.lock().unwrap().remove(container_id);
+        Ok(())
+    }
+    
+    fn record_event(&self, event: &ContainerEvent) -> Result<()> {
+        self.with_record(event.container_id(), |record| record.events.push(event.clone()))
+    }
+    
+    fn events(&self, container_id: &str) -> Result<Vec<ContainerEvent>> {
+        Ok(self.with_record(container_id, |record| record.events.clone()).unwrap_or_default())
+    }
+}
@@ -0,0 +1,200 @@
+use anyhow::{Result, anyhow};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::container::Container;
+
+// When systemd restarts the unit, as its `Restart=` setting.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RestartPolicy {
+    No,
+    OnSuccess,
+    #[default]
+    OnFailure,
+    OnAbnormal,
+    OnAbort,
+    Always,
+}
+
+impl FromStr for RestartPolicy {
+    type Err = anyhow::Error;
+    
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "no" => Ok(Self::No),
+            "on-success" => Ok(Self::OnSuccess),
+            "on-failure" => Ok(Self::OnFailure),
+            "on-abnormal" => Ok(Self::OnAbnormal),
+            "on-abort" => Ok(Self::OnAbort),
+            "always" => Ok(Self::Always),
+            _ => Err(anyhow!(
+                "Unknown restart policy: {} (expected no, on-success, on-failure, on-abnormal, on-abort or always)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for RestartPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::No => write!(f, "no"),
+            Self::OnSuccess => write!(f, "on-success"),
+            Self::OnFailure => write!(f, "on-failure"),
+            Self::OnAbnormal => write!(f, "on-abnormal"),
+            Self::OnAbort => write!(f, "on-abort"),
+            Self::Always => write!(f, "always"),
+        }
+    }
+}
+
+// How the generated units run: the binary they call, how they restart and
+// the other containers' units they need started first.
+#[derive(Debug, Clone)]
+pub struct UnitOptions {
+    pub executable: PathBuf,
+    pub restart: RestartPolicy,
+    pub restart_sec: u64,
+    // Containers whose units must be running, and are started first.
+    pub requires: Vec<String>,
+    // Containers whose units are started first if they are enabled.
+    pub wants: Vec<String>,
+}
+
+// A systemd service unit running a container or compose project in the
+// foreground, so systemd sees it exit and can restart it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Unit {
+    pub name: String,
+    pub description: String,
+    pub after: Vec<String>,
+    pub requires: Vec<String>,
+    pub wants: Vec<String>,
+    pub requires_mounts_for: Vec<PathBuf>,
+    pub exec_start: Vec<String>,
+    pub exec_stop_post: Vec<String>,
+    pub restart: RestartPolicy,
+    pub restart_sec: u64,
+}
+
+impl Unit {
+    // A unit for an existing container, named `container-<name>.service`.
+    // `start` runs it in the foreground; stopping the unit kills that, and
+    // `stop` then marks the container stopped and frees its addresses, also
+    // after a crash.
+    pub fn container(container: &Container, options: &UnitOptions) -> Self {
+        let name = container.name().unwrap_or(container.id());
+        let mut unit = Self::new(
+            format!("container-{}.service", name),
+            format!("wasm-container {}", name),
+            options,
+        );
+        unit.exec_start = vec![executable(options), "start".to_string(), name.to_string()];
+        unit.exec_stop_post = vec![executable(options), "stop".to_string(), name.to_string()];
+        // Volumes, named ones included, live on the host and must be mounted.
+        unit.requires_mounts_for = container.volumes().iter().map(|volume| volume.host_path.clone()).collect();
+        unit
+    }
+    
+    // A unit for a compose project, named `compose-<project>.service`,
+    // running `compose up`, which starts the services in dependency order
+    // itself, and `compose down` once it exits.
+    pub fn compose(file: &Path, project: &str, options: &UnitOptions) -> Self {
+        let mut unit = Self::new(
+            format!("compose-{}.service", project),
+            format!("wasm-container compose project {}", project),
+            options,
+        );
+        let command = |action: &str| vec![
+            executable(options),
+            "compose".to_string(),
+            "-f".to_string(),
+            file.to_string_lossy().into_owned(),
+            "-p".to_string(),
+            project.to_string(),
+            action.to_string(),
+        ];
+        unit.exec_start = command("up");
+        unit.exec_stop_post = command("down");
+        // Relative host paths in the file resolve against its directory.
+        unit.requires_mounts_for = file.parent().map(Path::to_path_buf).into_iter().collect();
+        unit
+    }
+    
+    fn new(name: String, description: String, options: &UnitOptions) -> Self {
+        let unit_name = |container: &String| format!("container-{}.service", container);
+        let requires: Vec<String> = options.requires.iter().map(unit_name).collect();
+        let wants: Vec<String> = std::iter::once("network-online.target".to_string())
+            .chain(options.wants.iter().map(unit_name))
+            .collect();
+        Self {
+            name,
+            description,
+            after: wants.iter().chain(&requires).cloned().collect(),
+            requires,
+            wants,
+            requires_mounts_for: Vec::new(),
+            exec_start: Vec::new(),
+            exec_stop_post: Vec::new(),
+            restart: options.restart,
+            restart_sec: options.restart_sec,
+        }
+    }
+}
+
+impl fmt::Display for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let list = |values: &[String]| values.join(" ");
+        
+        writeln!(f, "# {}", self.name)?;
+        writeln!(f, "# Generated by wasm-container generate systemd")?;
+        writeln!(f)?;
+        writeln!(f, "[Unit]")?;
+        writeln!(f, "Description={}", escape(&self.description))?;
+        writeln!(f, "Wants={}", list(&self.wants))?;
+        if !self.requires.is_empty() {
+            writeln!(f, "Requires={}", list(&self.requires))?;
+        }
+        writeln!(f, "After={}", list(&self.after))?;
+        for path in &self.requires_mounts_for {
+            writeln!(f, "RequiresMountsFor={}", command_line(&[path.to_string_lossy().into_owned()]))?;
+        }
+        writeln!(f)?;
+        writeln!(f, "[Service]")?;
+        writeln!(f, "Type=simple")?;
+        writeln!(f, "Restart={}", self.restart)?;
+        writeln!(f, "RestartSec={}", self.restart_sec)?;
+        writeln!(f, "ExecStart={}", command_line(&self.exec_start))?;
+        // Failing here, e.g. on a container removed meanwhile, is ignored.
+        writeln!(f, "ExecStopPost=-{}", command_line(&self.exec_stop_post))?;
+        writeln!(f)?;
+        writeln!(f, "[Install]")?;
+        writeln!(f, "WantedBy=default.target")
+    }
+}
+
+fn executable(options: &UnitOptions) -> String {
+    options.executable.to_string_lossy().into_owned()
+}
+
+// Escapes the specifiers and variables systemd expands in a setting.
+fn escape(value: &str) -> String {
+    value.replace('%', "%%").replace('$', "$$")
+}
+
+// A command line as systemd splits it, quoting arguments with whitespace,
+// quotes or backslashes.
+fn command_line(args: &[String]) -> String {
+    args.iter()
+        .map(|arg| {
+            let arg = escape(arg);
+            if arg.is_empty() || arg.contains(|c: char| c.is_whitespace() || c == '"' || c == '\'' || c == '\\') {
+                format!("\"{}\"", arg.replace('\\', "\\\\").replace('"', "\\\""))
+            } else {
+                arg
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
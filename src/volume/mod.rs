@@ -0,0 +1,242 @@
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::fs;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{info, debug};
+
+use crate::container::{Consistency, MountMode};
+use crate::error::{self, Error};
+use crate::state;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VolumeInfo {
+    pub name: String,
+    pub mountpoint: PathBuf,
+    pub created_at: u64,
+}
+
+pub struct VolumeManager {
+    volumes_dir: PathBuf,
+}
+
+impl VolumeManager {
+    pub fn new() -> error::Result<Self> {
+        Self::with_dir(state::state_dir()?.join("volumes"))
+    }
+    
+    pub fn with_dir(volumes_dir: PathBuf) -> error::Result<Self> {
+        fs::create_dir_all(&volumes_dir)?;
+        
+        Ok(Self { volumes_dir })
+    }
+    
+    pub fn create(&self, name: &str) -> error::Result<VolumeInfo> {
+        validate_name(name)?;
+        
+        if let Ok(volume) = self.inspect(name) {
+            debug!("Volume already exists: {}", name);
+            return Ok(volume);
+        }
+        
+        let volume_dir = self.volumes_dir.join(name);
+        let mountpoint = volume_dir.join("_data");
+        fs::create_dir_all(&mountpoint)?;
+        
+        let created_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        
+        let volume = VolumeInfo {
+            name: name.to_string(),
+            mountpoint,
+            created_at,
+        };
+        
+        fs::write(
+            volume_dir.join("metadata.json"),
+            serde_json::to_string_pretty(&volume).map_err(anyhow::Error::from)?,
+        )?;
+        
+        info!("Created volume: {}", name);
+        
+        Ok(volume)
+    }
+    
+    pub fn inspect(&self, name: &str) -> error::Result<VolumeInfo> {
+        validate_name(name)?;
+        
+        let metadata_file = self.volumes_dir.join(name).join("metadata.json");
+        if !metadata_file.exists() {
+            return Err(Error::VolumeNotFound(name.to_string()));
+        }
+        
+        let metadata = fs::read_to_string(&metadata_file)?;
+        let volume: VolumeInfo = serde_json::from_str(&metadata).map_err(anyhow::Error::from)?;
+        
+        Ok(volume)
+    }
+    
+    pub fn list(&self) -> error::Result<Vec<VolumeInfo>> {
+        let mut volumes = Vec::new();
+        
+        for entry in fs::read_dir(&self.volumes_dir)? {
+            let entry = entry?;
+            if let Some(name) = entry.file_name().to_str() {
+                if let Ok(volume) = self.inspect(name) {
+                    volumes.push(volume);
+                }
+            }
+        }
+        
+        volumes.sort_by(|a, b| a.name.cmp(&b.name));
+        
+        Ok(volumes)
+    }
+    
+    pub fn remove(&self, name: &str) -> error::Result<()> {
+        self.inspect(name)?;
+        
+        fs::remove_dir_all(self.volumes_dir.join(name))?;
+        
+        info!("Removed volume: {}", name);
+        
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VolumeSource {
+    HostPath(PathBuf),
+    Named(String),
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MountOptions {
+    pub read_only: bool,
+    pub consistency: Consistency,
+    pub mode: MountMode,
+}
+
+impl MountOptions {
+    // Parses the comma-separated option list of a `src:dst:opts` spec.
+    pub fn parse(options: &str) -> Result<Self> {
+        let mut parsed = Self::default();
+        let mut access = None;
+        let mut consistency = None;
+        let mut mode = None;
+        
+        for option in options.split(',') {
+            match option {
+                "ro" | "rw" => {
+                    check_conflict(access.replace(option), option)?;
+                    parsed.read_only = option == "ro";
+                }
+                "consistent" | "cached" | "delegated" => {
+                    check_conflict(consistency.replace(option), option)?;
+                    parsed.consistency = match option {
+                        "cached" => Consistency::Cached,
+                        "delegated" => Consistency::Delegated,
+                        _ => Consistency::Consistent,
+                    };
+                }
+                "bind" | "copy" | "sync" | "sync-back" => {
+                    check_conflict(mode.replace(option), option)?;
+                    parsed.mode = match option {
+                        "copy" => MountMode::Copy,
+                        "sync" => MountMode::Sync,
+                        "sync-back" => MountMode::SyncBack,
+                        _ => MountMode::Bind,
+                    };
+                }
+                // SELinux relabeling has no meaning for a WASI sandbox, but
+                // the options are accepted so Docker command lines still work.
+                "z" | "Z" => {}
+                _ => return Err(anyhow!(
+                    "Unknown volume option: {:?} (expected ro, rw, z, Z, consistent, cached, delegated, bind, copy, sync or sync-back)",
+                    option
+                )),
+            }
+        }
+        
+        if parsed.read_only && parsed.mode == MountMode::SyncBack {
+            return Err(anyhow!("Conflicting volume options: ro and sync-back"));
+        }
+        
+        Ok(parsed)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VolumeSpec {
+    pub source: VolumeSource,
+    pub container_path: PathBuf,
+    pub options: MountOptions,
+}
+
+impl VolumeSpec {
+    pub fn parse(spec: &str) -> error::Result<Self> {
+        let mut parts = spec.splitn(3, ':');
+        let source = parts.next().unwrap_or_default();
+        let container_path = parts.next()
+            .ok_or_else(|| anyhow!("Invalid volume specification: {}", spec))?;
+        let options = match parts.next() {
+            Some(options) => MountOptions::parse(options)?,
+            None => MountOptions::default(),
+        };
+        
+        if source.is_empty() || container_path.is_empty() {
+            return Err(anyhow!("Invalid volume specification: {}", spec).into());
+        }
+        if !Path::new(container_path).is_absolute() {
+            return Err(anyhow!("Container path in volume {} must be absolute", spec).into());
+        }
+        
+        let source = if is_host_path(source) {
+            VolumeSource::HostPath(PathBuf::from(source))
+        } else {
+            validate_name(source)?;
+            VolumeSource::Named(source.to_string())
+        };
+        
+        Ok(Self {
+            source,
+            container_path: PathBuf::from(container_path),
+            options,
+        })
+    }
+}
+
+fn check_conflict(previous: Option<&str>, option: &str) -> Result<()> {
+    match previous {
+        Some(previous) if previous != option => {
+            Err(anyhow!("Conflicting volume options: {} and {}", previous, option))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn is_host_path(source: &str) -> bool {
+    source.starts_with('/') || source.starts_with('.') || source.starts_with('~')
+        || Path::new(source).components().count() > 1
+}
+
+fn validate_name(name: &str) -> Result<()> {
+    let mut chars = name.chars();
+    
+    let valid = match chars.next() {
+        Some(first) => first.is_ascii_alphanumeric()
+            && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-')),
+        None => false,
+    };
+    
+    if !valid {
+        return Err(anyhow!(
+            "Invalid volume name: {:?} (must match [a-zA-Z0-9][a-zA-Z0-9_.-]*)",
+            name
+        ));
+    }
+    
+    Ok(())
+}
@@ -0,0 +1,119 @@
+use std::path::PathBuf;
+use tempfile::TempDir;
+use wasm_container::app::App;
+use wasm_container::image::ImageManager;
+use wasm_container::runtime::WasmRuntime;
+use wasm_container::volume::VolumeManager;
+
+const MANIFEST: &str = r#"
+name = "shop"
+
+[components.db]
+image = "shop/db:1"
+grants = { dirs = ["data:/var/lib/db"] }
+limits = { memory = "64m", cpus = 0.5 }
+
+[components.api]
+module = "exports.wat"
+invoke = "serve"
+env = { LOG = "debug" }
+ports = ["8080:80"]
+uses = ["db:5432"]
+grants = { outbound = ["api.example.com:443"] }
+
+[components.batch]
+module = "exports.wat"
+after = ["api"]
+grants = { network = "none" }
+"#;
+
+#[test]
+fn test_app_translates_components() {
+    let dir = fixtures();
+    let app = App::parse(MANIFEST, &dir, None).unwrap();
+    let compose = app.compose();
+    assert_eq!(app.project(), "shop");
+    assert_eq!(compose.start_order().unwrap(), vec!["db", "api", "batch"]);
+    
+    let api = compose.service("api").unwrap();
+    assert_eq!(api.image, "exports.wat");
+    assert_eq!(api.environment, vec!["LOG=debug", "DB_HOST=db", "DB_ADDR=db:5432"]);
+    assert_eq!(api.depends_on, vec!["db"]);
+    assert_eq!(api.ports[0].to_string(), "8080->80/tcp");
+    assert_eq!(compose.volume_name("data"), "shop_data");
+    
+    // A project name given on the command line wins.
+    assert_eq!(App::parse(MANIFEST, &dir, Some("staging")).unwrap().project(), "staging");
+}
+
+#[test]
+fn test_app_rejects_invalid_manifests() {
+    let dir = fixtures();
+    let error = |toml: &str| App::parse(toml, &dir, None).unwrap_err().to_string();
+    
+    assert!(error("").contains("no components"));
+    assert!(error("[components.a]\nimage = \"a\"\nmodule = \"a.wasm\"\n").contains("one of image or module"));
+    assert!(error("[components.a]\nimage = \"a\"\nuses = [\"b\"]\n").contains("undefined component b"));
+    assert!(error("[components.a]\nimage = \"a\"\nuses = [\"b\"]\n[components.b]\nimage = \"b\"\ngrants = { network = \"host\" }\n").contains("app network"));
+    assert!(error("[components.a]\nimage = \"a\"\nports = [\"8080:80\"]\ngrants = { network = \"none\" }\n").contains("without network access"));
+    assert!(error("[components.a]\nimage = \"a\"\nlimits = { cpus = 0.0 }\n").contains("Invalid CPU count"));
+    assert!(error("[components.a]\nimage = \"a\"\nrestart = \"always\"\n").contains("unknown field"));
+}
+
+#[test]
+fn test_app_interpolates_variables() {
+    let dir = TempDir::new().unwrap();
+    std::fs::write(dir.path().join(".env"), "STAGE=staging\n").unwrap();
+    let toml = "name = \"shop-${STAGE}\"\n[components.api]\nimage = \"shop/api:${API_TAG:-1}\"\nenv = { STAGE = \"${STAGE}\" }\n";
+    let app = App::parse(toml, dir.path(), None).unwrap();
+    assert_eq!(app.project(), "shop-staging");
+    let api = app.compose().service("api").unwrap();
+    assert_eq!(api.image, "shop/api:1");
+    assert_eq!(api.environment, vec!["STAGE=staging"]);
+    
+    let error = App::parse("[components.api]\nimage = \"${API_IMAGE:?pick an image}\"\n", dir.path(), None).unwrap_err();
+    assert!(error.to_string().contains("pick an image"));
+}
+
+#[tokio::test]
+async fn test_app_apply_status_and_destroy() {
+    let state = TempDir::new().unwrap();
+    let toml = r#"
+[components.db]
+image = "shop/db:1"
+grants = { dirs = ["data:/data"] }
+
+[components.api]
+module = "exports.wat"
+invoke = "serve"
+uses = ["db:5432"]
+grants = { outbound = ["127.0.0.1:443"] }
+"#;
+    let app = App::parse(toml, &fixtures(), Some("shop")).unwrap();
+    assert!(app.status(state.path()).await.unwrap_err().to_string().contains("not applied"));
+    
+    // Pulling the db's image gives the demo module.
+    let images = app.images(&ImageManager::with_cache_dir(state.path().join("images")).unwrap()).await.unwrap();
+    assert_eq!(images["api"].wasm_path, Some(fixtures().join("exports.wat")));
+    
+    app.apply(state.path(), &images).await.unwrap();
+    
+    let runtime = WasmRuntime::with_state_dir(state.path()).unwrap();
+    let logs = runtime.logs("shop-api-1", 10).await.unwrap();
+    assert_eq!(logs, vec!["serving"]);
+    let statuses = app.status(state.path()).await.unwrap();
+    assert_eq!(statuses.iter().map(|status| status.component.as_str()).collect::<Vec<_>>(), vec!["db", "api"]);
+    assert_eq!(statuses[1].container, "shop-api-1");
+    assert!(statuses[1].status.starts_with("Exited (0)"));
+    
+    app.destroy(state.path(), true).await.unwrap();
+    let runtime = WasmRuntime::with_state_dir(state.path()).unwrap();
+    assert!(runtime.list_containers(true).await.unwrap().is_empty());
+    assert!(runtime.inspect_network("shop_default").await.is_err());
+    let volumes = VolumeManager::with_dir(state.path().join("volumes")).unwrap();
+    assert!(volumes.inspect("shop_data").is_err());
+}
+
+fn fixtures() -> PathBuf {
+    std::env::current_dir().unwrap().join("tests/fixtures")
+}
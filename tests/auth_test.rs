@@ -0,0 +1,80 @@
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use tempfile::TempDir;
+use wasm_container::image::auth::{registry_of, CredentialStore, Credentials};
+
+fn credentials(username: &str, secret: &str) -> Credentials {
+    Credentials { username: username.to_string(), secret: secret.to_string() }
+}
+
+#[test]
+fn test_login_and_logout() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("auth.json");
+    fs::write(&path, r#"{"auths":{"quay.io":{"auth":"dTpw","email":"u@example.com"}},"experimental":"enabled"}"#).unwrap();
+    let store = CredentialStore::with_path(path.clone());
+    
+    store.login("https://ghcr.io/v2/", &credentials("octocat", "p:ss")).unwrap();
+    assert_eq!(store.get("ghcr.io").unwrap(), Some(credentials("octocat", "p:ss")));
+    assert_eq!(store.get("quay.io").unwrap(), Some(credentials("u", "p")));
+    assert_eq!(store.get("docker.io").unwrap(), None);
+    assert_eq!(store.registries().unwrap(), vec!["ghcr.io", "quay.io"]);
+    assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+    
+    // Docker Hub's aliases share one entry, and unknown keys survive. A
+    // readable temp file left by an interrupted save isn't reused as is.
+    let temp = dir.path().join("auth.json.tmp");
+    fs::write(&temp, "").unwrap();
+    fs::set_permissions(&temp, fs::Permissions::from_mode(0o644)).unwrap();
+    store.login("index.docker.io", &credentials("me", "token")).unwrap();
+    assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+    assert_eq!(store.get("docker.io").unwrap(), Some(credentials("me", "token")));
+    let file = fs::read_to_string(&path).unwrap();
+    assert!(file.contains(r#""experimental": "enabled""#));
+    assert!(file.contains(r#""email": "u@example.com""#));
+    
+    store.logout("ghcr.io").unwrap();
+    assert_eq!(store.get("ghcr.io").unwrap(), None);
+    assert!(store.logout("ghcr.io").unwrap_err().to_string().contains("Not logged in"));
+}
+
+#[test]
+fn test_credential_helpers() {
+    let dir = TempDir::new().unwrap();
+    // A helper keeping one secret per server in files next to itself.
+    let helper = dir.path().join("docker-credential-files");
+    fs::write(&helper, format!(r#"#!/bin/sh
+store="{}/stored"
+case "$1" in
+    store) cat > "$store" ;;
+    get) read server; [ -f "$store" ] || {{ echo "credentials not found"; exit 1; }}; echo '{{"ServerURL":"'$server'","Username":"helper","Secret":"s3cret"}}' ;;
+    erase) rm "$store" ;;
+esac
+"#, dir.path().display())).unwrap();
+    fs::set_permissions(&helper, fs::Permissions::from_mode(0o755)).unwrap();
+    let path = std::env::var("PATH").unwrap_or_default();
+    std::env::set_var("PATH", format!("{}:{}", dir.path().display(), path));
+    
+    let auth = dir.path().join("auth.json");
+    fs::write(&auth, r#"{"credHelpers":{"registry.example.com":"files"}}"#).unwrap();
+    let store = CredentialStore::with_path(auth.clone());
+    
+    assert_eq!(store.get("registry.example.com").unwrap(), None);
+    store.login("registry.example.com", &credentials("helper", "s3cret")).unwrap();
+    assert!(fs::read_to_string(dir.path().join("stored")).unwrap().contains(r#""Secret":"s3cret""#));
+    assert_eq!(store.get("registry.example.com").unwrap(), Some(credentials("helper", "s3cret")));
+    // The secret stays out of the auth file.
+    assert!(!fs::read_to_string(&auth).unwrap().contains("s3cret"));
+    
+    store.logout("registry.example.com").unwrap();
+    assert!(!dir.path().join("stored").exists());
+}
+
+#[test]
+fn test_registry_of_image_references() {
+    assert_eq!(registry_of("nginx"), "docker.io");
+    assert_eq!(registry_of("library/nginx:1.25"), "docker.io");
+    assert_eq!(registry_of("ghcr.io/owner/app"), "ghcr.io");
+    assert_eq!(registry_of("localhost:5000/app"), "localhost:5000");
+    assert_eq!(registry_of("localhost/app"), "localhost");
+}
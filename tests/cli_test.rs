@@ -0,0 +1,76 @@
+mod common;
+
+use common::test_image;
+use std::process::{Command, Output, Stdio};
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+// Runs the binary with its state, cache and config under `home`.
+fn wasm_container(home: &TempDir, args: &[&str]) -> Output {
+    command(home, args).output().unwrap()
+}
+
+fn command(home: &TempDir, args: &[&str]) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_wasm-container"));
+    command
+        .args(args)
+        .env("HOME", home.path())
+        .env("XDG_DATA_HOME", home.path().join("data"))
+        .env("XDG_CACHE_HOME", home.path().join("cache"))
+        .env("XDG_CONFIG_HOME", home.path().join("config"))
+        .env("WASM_CONTAINER_SCRATCH_DIR", home.path().join("scratch"))
+        .env_remove("RUST_BACKTRACE");
+    command
+}
+
+#[test]
+fn test_docker_aliases() {
+    let home = TempDir::new().unwrap();
+    
+    let ps = wasm_container(&home, &["ps", "--all", "--format", "json"]);
+    assert!(ps.status.success());
+    assert_eq!(String::from_utf8_lossy(&ps.stdout), "[]\n");
+    
+    let rmi = wasm_container(&home, &["rmi", "--force", "nginx:latest"]);
+    assert_eq!(rmi.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&rmi.stderr).contains("No such image: nginx:latest"));
+}
+
+#[test]
+fn test_start_failures_exit_like_docker() {
+    let home = TempDir::new().unwrap();
+    
+    let start = wasm_container(&home, &["start", "missing"]);
+    assert_eq!(start.status.code(), Some(125));
+    assert!(String::from_utf8_lossy(&start.stderr).contains("No such container: missing"));
+}
+
+#[test]
+fn test_interrupted_run_rm_cleans_up() {
+    let home = TempDir::new().unwrap();
+    let image = home.path().join("cache/wasm-container/images/test-image/latest");
+    std::fs::create_dir_all(&image).unwrap();
+    let wasm = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/spin.wat");
+    std::fs::write(image.join("metadata.json"), serde_json::to_string(&test_image(Some(wasm))).unwrap()).unwrap();
+    
+    let mut run = command(&home, &["run", "--rm", "--tmpfs", "/scratch", "test-image"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let scratch = home.path().join("scratch");
+    let tmpfs_dirs = || std::fs::read_dir(&scratch).map_or(0, |entries| {
+        entries.filter(|entry| entry.as_ref().unwrap().file_name().to_string_lossy().ends_with("-tmpfs")).count()
+    });
+    let started = Instant::now();
+    while tmpfs_dirs() == 0 {
+        assert!(started.elapsed() < Duration::from_secs(30), "the run never started");
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    
+    unsafe { libc::kill(run.id() as libc::pid_t, libc::SIGINT) };
+    assert_eq!(run.wait().unwrap().code(), Some(130));
+    assert_eq!(tmpfs_dirs(), 0);
+    let ps = wasm_container(&home, &["ps", "--all", "--format", "json"]);
+    assert_eq!(String::from_utf8_lossy(&ps.stdout), "[]\n");
+}
@@ -0,0 +1,23 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use wasm_container::image::{ImageConfig, ImageData, RootFs};
+
+/// A `test-image:latest` image with no layers that runs the module at
+/// `wasm_path`, such as `src/image/demo.wasm`, if one is given.
+pub fn test_image(wasm_path: Option<&str>) -> ImageData {
+    ImageData {
+        name: "test-image".to_string(),
+        tag: "latest".to_string(),
+        layers: vec![],
+        config: ImageConfig {
+            env: vec!["PATH=/usr/bin".to_string()],
+            cmd: vec!["/bin/sh".to_string()],
+            entrypoint: vec![],
+            workdir: "/".to_string(),
+            exposed_ports: HashMap::new(),
+            volumes: HashMap::new(),
+            rootfs: RootFs::default(),
+        },
+        wasm_path: wasm_path.map(PathBuf::from),
+    }
+}
@@ -0,0 +1,177 @@
+mod common;
+
+use common::test_image;
+use wasm_container::compose::{Compose, ProjectRecord};
+use wasm_container::compose::interpolate::interpolate;
+use wasm_container::runtime::WasmRuntime;
+use wasm_container::volume::{VolumeManager, VolumeSource};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+const APP: &str = r#"
+services:
+  web:
+    image: web:latest
+    command: serve --port 8080
+    environment:
+      - MODE=production
+    ports:
+      - "8080:80"
+      - 9000/udp
+      - 3000
+    volumes:
+      - ./static:/srv/static:ro
+      - data:/data
+    depends_on:
+      db:
+        condition: service_started
+    networks:
+      - front
+      - back
+  db:
+    image: db:latest
+    command: ["db", "--listen", "0.0.0.0"]
+    environment:
+      USER: admin
+      PORT: 5432
+    networks: [back]
+networks:
+  front:
+  back:
+    internal: true
+volumes:
+  data:
+"#;
+
+#[test]
+fn test_compose_parses_services() {
+    let dir = TempDir::new().unwrap();
+    let compose = Compose::parse(APP, dir.path(), Some("app")).unwrap();
+    assert_eq!(compose.project(), "app");
+    
+    let web = compose.service("web").unwrap();
+    assert_eq!(web.command.clone().unwrap(), vec!["serve", "--port", "8080"]);
+    assert_eq!(web.environment, vec!["MODE=production"]);
+    let ports: Vec<String> = web.ports.iter().map(ToString::to_string).collect();
+    assert_eq!(ports, vec!["8080->80/tcp", "0->9000/udp", "0->3000/tcp"]);
+    assert_eq!(web.volumes[0].source, VolumeSource::HostPath(PathBuf::from("./static")));
+    assert!(web.volumes[0].options.read_only);
+    assert_eq!(web.volumes[1].source, VolumeSource::Named("data".to_string()));
+    assert_eq!(web.depends_on, vec!["db"]);
+    assert_eq!(web.networks(), vec!["front", "back"]);
+    
+    let db = compose.service("db").unwrap();
+    assert_eq!(db.command.clone().unwrap(), vec!["db", "--listen", "0.0.0.0"]);
+    assert_eq!(db.environment, vec!["PORT=5432", "USER=admin"]);
+    
+    assert_eq!(compose.start_order().unwrap(), vec!["db", "web"]);
+    assert_eq!(compose.network_name("back"), "app_back");
+    assert_eq!(compose.volume_name("data"), "app_data");
+}
+
+#[test]
+fn test_compose_project_name() {
+    let dir = TempDir::new().unwrap();
+    let project_dir = dir.path().join("My App");
+    let services = "services:\n  web:\n    image: web\n";
+    
+    let compose = Compose::parse(services, &project_dir, None).unwrap();
+    assert_eq!(compose.project(), "myapp");
+    let named = Compose::parse(&format!("name: shop\n{}", services), &project_dir, None).unwrap();
+    assert_eq!(named.project(), "shop");
+    assert!(Compose::parse(services, &project_dir, Some("Bad Name")).is_err());
+}
+
+#[test]
+fn test_compose_rejects_invalid_files() {
+    let dir = TempDir::new().unwrap();
+    let parse = |yaml: &str| Compose::parse(yaml, dir.path(), Some("app"));
+    
+    let cycle = parse("services:\n  a:\n    image: a\n    depends_on: [b]\n  b:\n    image: b\n    depends_on: [a]\n");
+    assert!(cycle.unwrap_err().to_string().contains("cycle"));
+    let missing = parse("services:\n  a:\n    image: a\n    depends_on: [b]\n");
+    assert!(missing.unwrap_err().to_string().contains("undefined service b"));
+    let network = parse("services:\n  a:\n    image: a\n    networks: [back]\n");
+    assert!(network.unwrap_err().to_string().contains("undefined network back"));
+    let volume = parse("services:\n  a:\n    image: a\n    volumes: [\"data:/data\"]\n");
+    assert!(volume.unwrap_err().to_string().contains("undefined volume data"));
+    assert!(parse("services:\n  a:\n    image: a\n    ports: [\"127.0.0.1:80:80\"]\n").is_err());
+    assert!(parse("services: {}\n").is_err());
+}
+
+#[test]
+fn test_compose_interpolates_variables() {
+    let variables = HashMap::from([("TAG".to_string(), "2".to_string()), ("EMPTY".to_string(), String::new())]);
+    let expand = |text: &str| interpolate(text, &variables);
+    
+    assert_eq!(expand("web:$TAG ${TAG}-${UNSET} $$TAG $1").unwrap(), "web:2 2- $TAG $1");
+    assert_eq!(expand("${EMPTY:-a} ${EMPTY-b} ${UNSET-c} ${UNSET:-${TAG}}").unwrap(), "a  c 2");
+    assert!(expand("${UNSET:?set it}").unwrap_err().to_string().contains("UNSET is not set: set it"));
+    assert!(expand("${EMPTY?}").is_ok());
+    assert!(expand("${TAG").is_err());
+    
+    // The environment wins over the .env file next to the compose file.
+    let dir = TempDir::new().unwrap();
+    std::fs::write(dir.path().join(".env"), "# staging\nexport WEB_TAG=\"3\"\nCOMPOSE_TEST_PORT=8080\n").unwrap();
+    std::env::set_var("COMPOSE_TEST_PORT", "9090");
+    let yaml = "services:\n  web:\n    image: web:${WEB_TAG:-1}\n    ports: [\"${COMPOSE_TEST_PORT}:80\"]\n    command: echo $$HOME\n";
+    let compose = Compose::parse(yaml, dir.path(), Some("app")).unwrap();
+    let web = compose.service("web").unwrap();
+    assert_eq!(web.image, "web:3");
+    assert_eq!(web.ports[0].to_string(), "9090->80/tcp");
+    assert_eq!(web.command.clone().unwrap(), vec!["echo", "$HOME"]);
+    
+    std::fs::write(dir.path().join(".env"), "not a variable\n").unwrap();
+    assert!(Compose::parse(yaml, dir.path(), Some("app")).is_err());
+}
+
+#[test]
+fn test_compose_builds_containers() {
+    let dir = TempDir::new().unwrap();
+    std::fs::create_dir(dir.path().join("static")).unwrap();
+    let compose = Compose::parse(APP, dir.path(), Some("app")).unwrap();
+    let volumes = VolumeManager::with_dir(dir.path().join("volumes")).unwrap();
+    
+    let container = compose.container("web", test_image(Some("src/image/demo.wasm")), &volumes).unwrap();
+    assert_eq!(container.network_config().hostname, "web");
+    assert_eq!(container.networks(), ["app_front", "app_back"]);
+    assert_eq!(container.network_config().ports.len(), 3);
+    assert_eq!(container.env_vars().get("MODE").unwrap(), "production");
+    assert_eq!(container.volumes()[0].host_path, dir.path().join("static").canonicalize().unwrap());
+    assert!(container.volumes()[0].read_only);
+    assert_eq!(container.volumes()[1].host_path, volumes.inspect("app_data").unwrap().mountpoint);
+}
+
+#[tokio::test]
+async fn test_compose_up_and_down() {
+    let state = TempDir::new().unwrap();
+    let yaml = "services:\n  web:\n    image: web\n    depends_on: [db]\n  db:\n    image: db\n    networks: [back]\nnetworks:\n  back:\n";
+    let compose = Compose::parse(yaml, Path::new("/srv/shop"), None).unwrap();
+    let images = HashMap::from([
+        ("web".to_string(), test_image(Some("src/image/demo.wasm"))),
+        ("db".to_string(), test_image(Some("src/image/demo.wasm"))),
+    ]);
+    
+    compose.up(state.path(), &images).await.unwrap();
+    
+    let record = ProjectRecord::load(state.path(), "shop").unwrap().unwrap();
+    let services: Vec<&str> = record.containers.iter().map(|container| container.service.as_str()).collect();
+    assert_eq!(services, vec!["db", "web"]);
+    assert_eq!(record.networks, vec!["shop_back", "shop_default"]);
+    let runtime = WasmRuntime::with_state_dir(state.path()).unwrap();
+    for container in &record.containers {
+        assert_eq!(runtime.inspect(&container.id).await.unwrap().info.status, "exited");
+    }
+    assert_eq!(runtime.inspect("shop-web-1").await.unwrap().info.id, record.containers[1].id);
+    assert!(runtime.inspect_network("shop_back").await.is_ok());
+    
+    Compose::down(state.path(), "shop").await.unwrap();
+    
+    assert!(ProjectRecord::load(state.path(), "shop").unwrap().is_none());
+    let runtime = WasmRuntime::with_state_dir(state.path()).unwrap();
+    assert!(runtime.list_containers(true).await.unwrap().is_empty());
+    assert!(runtime.inspect_network("shop_back").await.is_err());
+    assert!(runtime.inspect_network("shop_default").await.is_err());
+    assert!(Compose::down(state.path(), "shop").await.is_err());
+}
@@ -0,0 +1,138 @@
+use wasm_container::config::{parse_size, RuntimeConfig};
+use wasm_container::state::store::StateBackend;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use tempfile::TempDir;
+
+#[test]
+fn test_parse_size() {
+    assert_eq!(parse_size("1024").unwrap(), 1024);
+    assert_eq!(parse_size("512K").unwrap(), 512 * 1024);
+    assert_eq!(parse_size("64m").unwrap(), 64 * 1024 * 1024);
+    assert_eq!(parse_size("2G").unwrap(), 2 * 1024 * 1024 * 1024);
+    
+    assert!(parse_size("").is_err());
+    assert!(parse_size("10X").is_err());
+    assert!(parse_size("G").is_err());
+    assert!(parse_size("99999999999T").is_err());
+}
+
+#[test]
+fn test_storage_config_from_file_and_env() {
+    let dir = TempDir::new().unwrap();
+    let file = dir.path().join("config.json");
+    std::fs::write(&file, r#"{
+        "state_dir": "/srv/wasm/state",
+        "scratch_dir": "/mnt/ramdisk",
+        "scratch_size": "256M",
+        "layer_cache_size": 1048576
+    }"#).unwrap();
+    
+    let config = RuntimeConfig::from_sources(Some(&file), |_| None).unwrap();
+    assert_eq!(config.state_dir, Some(PathBuf::from("/srv/wasm/state")));
+    assert_eq!(config.cache_dir, None);
+    assert_eq!(config.scratch_size, Some(256 * 1024 * 1024));
+    assert_eq!(config.layer_cache_size, Some(1024 * 1024));
+    
+    let env: HashMap<&str, &str> = HashMap::from([
+        ("WASM_CONTAINER_SCRATCH_DIR", "/dev/shm/override"),
+        ("WASM_CONTAINER_CACHE_DIR", "/var/cache/wasm"),
+        ("WASM_CONTAINER_SCRATCH_SIZE", "1G"),
+    ]);
+    let config = RuntimeConfig::from_sources(Some(&file), |key| env.get(key).map(|v| v.to_string())).unwrap();
+    assert_eq!(config.state_dir, Some(PathBuf::from("/srv/wasm/state")));
+    assert_eq!(config.scratch_dir, Some(PathBuf::from("/dev/shm/override")));
+    assert_eq!(config.cache_dir, Some(PathBuf::from("/var/cache/wasm")));
+    assert_eq!(config.scratch_size, Some(1024 * 1024 * 1024));
+}
+
+#[test]
+fn test_runtime_config_dns_defaults() {
+    let dir = TempDir::new().unwrap();
+    let file = dir.path().join("config.json");
+    std::fs::write(&file, r#"{"dns": ["1.1.1.1", "2606:4700:4700::1111"], "dns_search": ["corp.example"]}"#).unwrap();
+    
+    let config = RuntimeConfig::from_sources(Some(&file), |_| None).unwrap();
+    assert_eq!(config.dns, vec!["1.1.1.1".parse::<IpAddr>().unwrap(), "2606:4700:4700::1111".parse().unwrap()]);
+    assert_eq!(config.dns_search, vec!["corp.example".to_string()]);
+    
+    std::fs::write(&file, r#"{"dns": ["resolver.local"]}"#).unwrap();
+    assert!(RuntimeConfig::from_sources(Some(&file), |_| None).is_err());
+}
+
+#[test]
+fn test_runtime_config_masked_paths() {
+    let dir = TempDir::new().unwrap();
+    let file = dir.path().join("config.json");
+    std::fs::write(&file, r#"{"masked_paths": ["/proc/self/environ", "/etc/secrets"]}"#).unwrap();
+    
+    let config = RuntimeConfig::from_sources(Some(&file), |_| None).unwrap();
+    assert_eq!(config.masked_paths, vec![PathBuf::from("/proc/self/environ"), PathBuf::from("/etc/secrets")]);
+    assert!(RuntimeConfig::default().masked_paths.is_empty());
+}
+
+#[test]
+fn test_runtime_config_port_options() {
+    let dir = TempDir::new().unwrap();
+    let file = dir.path().join("config.json");
+    std::fs::write(&file, r#"{"port_options": "idle-timeout=10m,max-connections=1000"}"#).unwrap();
+    
+    let config = RuntimeConfig::from_sources(Some(&file), |_| None).unwrap();
+    assert_eq!(config.port_options.to_string(), "idle-timeout=600s,max-connections=1000");
+    
+    std::fs::write(&file, r#"{"port_options": "max-connections=none"}"#).unwrap();
+    assert!(RuntimeConfig::from_sources(Some(&file), |_| None).is_err());
+}
+
+#[test]
+fn test_runtime_config_resource_maximums() {
+    let dir = TempDir::new().unwrap();
+    let file = dir.path().join("config.json");
+    std::fs::write(&file, r#"{"max_memory": "1G", "max_cpus": 1.5}"#).unwrap();
+    
+    let config = RuntimeConfig::from_sources(Some(&file), |_| None).unwrap();
+    assert_eq!(config.max_memory, Some(1024 * 1024 * 1024));
+    assert_eq!(config.max_cpus.unwrap().millis(), 1500);
+    
+    let env = |key: &str| (key == "WASM_CONTAINER_MAX_CPUS").then(|| "0.5".to_string());
+    let config = RuntimeConfig::from_sources(Some(&file), env).unwrap();
+    assert_eq!(config.max_cpus.unwrap().to_string(), "0.5");
+    
+    std::fs::write(&file, r#"{"max_cpus": 0}"#).unwrap();
+    assert!(RuntimeConfig::from_sources(Some(&file), |_| None).is_err());
+}
+
+#[test]
+fn test_runtime_config_state_store() {
+    let dir = TempDir::new().unwrap();
+    let file = dir.path().join("config.json");
+    std::fs::write(&file, r#"{"state_store": "sqlite"}"#).unwrap();
+    
+    let config = RuntimeConfig::from_sources(Some(&file), |_| None).unwrap();
+    assert_eq!(config.state_store, Some(StateBackend::Sqlite));
+    let env = |key: &str| (key == "WASM_CONTAINER_STATE_STORE").then(|| "memory".to_string());
+    assert_eq!(RuntimeConfig::from_sources(Some(&file), env).unwrap().state_store, Some(StateBackend::Memory));
+    
+    std::fs::write(&file, r#"{"state_store": "postgres"}"#).unwrap();
+    assert!(RuntimeConfig::from_sources(Some(&file), |_| None).is_err());
+}
+
+#[test]
+fn test_storage_config_rejects_bad_input() {
+    let dir = TempDir::new().unwrap();
+    
+    let missing = RuntimeConfig::from_sources(Some(&dir.path().join("missing.json")), |_| None).unwrap();
+    assert_eq!(missing, RuntimeConfig::default());
+    
+    let file = dir.path().join("config.json");
+    std::fs::write(&file, r#"{"scratch_dir": "/tmp", "unknown": true}"#).unwrap();
+    assert!(RuntimeConfig::from_sources(Some(&file), |_| None).is_err());
+    
+    std::fs::write(&file, r#"{"scratch_size": "lots"}"#).unwrap();
+    assert!(RuntimeConfig::from_sources(Some(&file), |_| None).is_err());
+    
+    assert!(RuntimeConfig::from_sources(None, |key| {
+        (key == "WASM_CONTAINER_LAYER_CACHE_SIZE").then(|| "ten".to_string())
+    }).is_err());
+}
@@ -0,0 +1,246 @@
+mod common;
+
+use common::test_image;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tempfile::TempDir;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+use wasm_container::config::RuntimeConfig;
+use wasm_container::daemon::Daemon;
+use wasm_container::runtime::EXIT_NOT_INVOKABLE;
+
+// What docker's CLI does for `docker run --name hello demo`: create, then
+// attach and wait before starting, then read the output and exit code.
+#[tokio::test(flavor = "multi_thread")]
+async fn test_docker_run_workflow() {
+    let dir = TempDir::new().unwrap();
+    let socket = start_daemon(&dir).await;
+    
+    let (status, head, body) = request(&socket, "GET", "/_ping", "").await;
+    assert_eq!(status, 200);
+    assert!(head.contains("Api-Version: 1.43"));
+    assert_eq!(body, b"OK");
+    
+    let (status, _, body) = request(&socket, "POST", "/v1.43/containers/create?name=hello", r#"{"Image":"demo","Cmd":null,"Labels":{"app":"demo"},"HostConfig":{"Memory":0}}"#).await;
+    assert_eq!(status, 201, "{}", String::from_utf8_lossy(&body));
+    let id = json(&body)["Id"].as_str().unwrap().to_string();
+    
+    let attach = tokio::spawn(request_owned(socket.clone(), "POST", format!("/v1.43/containers/{}/attach?stream=1&stdout=1&stderr=1", id)));
+    let wait = tokio::spawn(request_owned(socket.clone(), "POST", format!("/v1.43/containers/{}/wait?condition=next-exit", id)));
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    
+    let (status, _, _) = request(&socket, "POST", &format!("/v1.43/containers/{}/start", id), "").await;
+    assert_eq!(status, 204);
+    
+    let (status, _, output) = attach.await.unwrap();
+    assert_eq!(status, 101);
+    assert_eq!(&output[..4], &[1, 0, 0, 0]);
+    assert!(String::from_utf8_lossy(&output).contains("Hello from WASM Container!"));
+    
+    let (status, _, body) = wait.await.unwrap();
+    assert_eq!(status, 200);
+    assert!(String::from_utf8_lossy(&body).contains(r#""StatusCode":0"#));
+    
+    // Exited containers show up in `ps -a` only.
+    let (_, _, body) = request(&socket, "GET", "/containers/json", "").await;
+    assert_eq!(json(&body), Value::Array(vec![]));
+    let (_, _, body) = request(&socket, "GET", "/containers/json?all=1&filters=%7B%22label%22%3A%5B%22app%3Ddemo%22%5D%7D", "").await;
+    let containers = json(&body);
+    assert_eq!(containers[0]["Id"], id.as_str());
+    assert_eq!(containers[0]["Names"][0], "/hello");
+    assert_eq!(containers[0]["State"], "exited");
+    
+    let (status, _, output) = request(&socket, "GET", "/containers/hello/logs?stdout=1&tail=all", "").await;
+    assert_eq!(status, 200);
+    assert!(String::from_utf8_lossy(&output).contains("Hello from WASM Container!"));
+    
+    let (_, _, body) = request(&socket, "GET", "/containers/hello/json", "").await;
+    assert_eq!(json(&body)["State"]["Status"], "exited");
+    
+    let (status, _, _) = request(&socket, "DELETE", "/containers/hello", "").await;
+    assert_eq!(status, 204);
+    let (status, _, body) = request(&socket, "GET", "/containers/hello/json", "").await;
+    assert_eq!(status, 404);
+    assert!(json(&body)["message"].as_str().unwrap().contains("No such container"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_daemon_rejects_unsupported_requests() {
+    let dir = TempDir::new().unwrap();
+    let socket = start_daemon(&dir).await;
+    
+    let (status, _, body) = request(&socket, "POST", "/containers/create", r#"{"Image":"demo","Tty":true}"#).await;
+    assert_eq!(status, 400);
+    assert!(json(&body)["message"].as_str().unwrap().contains("TTY"));
+    
+    let (status, _, _) = request(&socket, "GET", "/containers/json?filters=%7B%22status%22%3A%5B%22running%22%5D%7D", "").await;
+    assert_eq!(status, 400);
+    
+    let (status, _, _) = request(&socket, "GET", "/images/json", "").await;
+    assert_eq!(status, 404);
+    
+    // Image references can't name paths outside the image cache.
+    let (status, _, body) = request(&socket, "POST", "/v4.9.0/libpod/images/pull?reference=..%2F..%2Fescaped", "").await;
+    assert_eq!(status, 200);
+    assert!(String::from_utf8_lossy(&body).contains("Invalid image reference"));
+    let (status, _, body) = request(&socket, "POST", "/containers/create", r#"{"Image":"/tmp/escaped"}"#).await;
+    assert_eq!(status, 404);
+    assert!(json(&body)["message"].as_str().unwrap().contains("Invalid image reference"));
+    assert!(!dir.path().join("escaped").exists());
+    
+    let spec = r#"{"image":"demo","portmappings":[{"container_port":65000,"range":600}]}"#;
+    let (status, _, body) = request(&socket, "POST", "/v4.9.0/libpod/containers/create", spec).await;
+    assert_eq!(status, 400);
+    assert!(json(&body)["message"].as_str().unwrap().contains("Port range 65000-65599 exceeds 65535"));
+    
+    // A second daemon can't take over a live socket.
+    let config = RuntimeConfig { state_dir: Some(dir.path().join("state")), ..RuntimeConfig::default() };
+    let daemon = Daemon::new(config).await.unwrap();
+    assert!(daemon.listen(&socket).await.is_err());
+}
+
+// A module that fails to start gives up its published port and address,
+// so it can be started again.
+#[tokio::test(flavor = "multi_thread")]
+async fn test_failed_start_releases_published_ports() {
+    let dir = TempDir::new().unwrap();
+    let image = dir.path().join("cache/images/test-image/latest");
+    std::fs::create_dir_all(&image).unwrap();
+    let metadata = serde_json::to_string(&test_image(Some("tests/fixtures/missing_import.wat"))).unwrap();
+    std::fs::write(image.join("metadata.json"), metadata).unwrap();
+    let socket = start_daemon(&dir).await;
+    
+    let host_port = std::net::TcpListener::bind("0.0.0.0:0").unwrap().local_addr().unwrap().port();
+    let spec = format!(r#"{{"Image":"test-image","HostConfig":{{"PortBindings":{{"80/tcp":[{{"HostPort":"{}"}}]}}}}}}"#, host_port);
+    let (status, _, body) = request(&socket, "POST", "/containers/create", &spec).await;
+    assert_eq!(status, 201, "{}", String::from_utf8_lossy(&body));
+    let id = json(&body)["Id"].as_str().unwrap().to_string();
+    
+    for _ in 0..2 {
+        let (status, _, body) = request(&socket, "POST", &format!("/containers/{}/start", id), "").await;
+        assert_eq!(status, 500);
+        let message = json(&body)["message"].as_str().unwrap().to_string();
+        assert!(message.contains(&format!("exit code {}", EXIT_NOT_INVOKABLE)), "{}", message);
+        std::net::TcpListener::bind(("0.0.0.0", host_port)).unwrap();
+    }
+    
+    let (_, _, body) = request(&socket, "GET", &format!("/containers/{}/json", id), "").await;
+    assert_eq!(json(&body)["State"]["Status"], "exited");
+}
+
+// What podman-remote and podman-compose do: check for the image and pull
+// it, set up a network and volume, then create, start and wait.
+#[tokio::test(flavor = "multi_thread")]
+async fn test_libpod_workflow() {
+    let dir = TempDir::new().unwrap();
+    let socket = start_daemon(&dir).await;
+    
+    let (status, head, _) = request(&socket, "GET", "/v4.9.0/libpod/_ping", "").await;
+    assert_eq!(status, 200);
+    assert!(head.contains("Libpod-Api-Version: 4.9.0"));
+    
+    let (status, _, _) = request(&socket, "GET", "/v4.9.0/libpod/images/docker.io%2Flibrary%2Fdemo/exists", "").await;
+    assert_eq!(status, 404);
+    let (status, _, body) = request(&socket, "POST", "/v4.9.0/libpod/images/pull?reference=docker.io%2Flibrary%2Fdemo", "").await;
+    assert_eq!(status, 200);
+    assert!(String::from_utf8_lossy(&body).contains(r#""id":"docker.io/library/demo:latest""#));
+    let (status, _, _) = request(&socket, "GET", "/v4.9.0/libpod/images/docker.io%2Flibrary%2Fdemo/exists", "").await;
+    assert_eq!(status, 204);
+    
+    let (status, _, _) = request(&socket, "GET", "/v4.9.0/libpod/networks/app_default/exists", "").await;
+    assert_eq!(status, 404);
+    let (status, _, body) = request(&socket, "POST", "/v4.9.0/libpod/networks/create", r#"{"name":"app_default"}"#).await;
+    assert_eq!(status, 200, "{}", String::from_utf8_lossy(&body));
+    assert_eq!(json(&body)["name"], "app_default");
+    let (status, _, _) = request(&socket, "POST", "/v4.9.0/libpod/networks/create", r#"{"name":"app_default"}"#).await;
+    assert_eq!(status, 409);
+    let (status, _, _) = request(&socket, "GET", "/v4.9.0/libpod/volumes/app_data/exists", "").await;
+    assert_eq!(status, 404);
+    let (status, _, body) = request(&socket, "POST", "/v4.9.0/libpod/volumes/create", r#"{"Name":"app_data"}"#).await;
+    assert_eq!(status, 201);
+    assert_eq!(json(&body)["Name"], "app_data");
+    
+    let spec = r#"{"image":"docker.io/library/demo","name":"web","env":{"MODE":"test"},"labels":{"io.podman.compose.project":"app"},"Networks":{"app_default":{}},"volumes":[{"Name":"app_data","Dest":"/data"}]}"#;
+    let (status, _, body) = request(&socket, "POST", "/v4.9.0/libpod/containers/create", spec).await;
+    assert_eq!(status, 201, "{}", String::from_utf8_lossy(&body));
+    let id = json(&body)["Id"].as_str().unwrap().to_string();
+    let (status, _, _) = request(&socket, "GET", "/v4.9.0/libpod/containers/web/exists", "").await;
+    assert_eq!(status, 204);
+    
+    let (status, _, _) = request(&socket, "POST", "/v4.9.0/libpod/containers/web/start", "").await;
+    assert_eq!(status, 204);
+    let (status, _, body) = request(&socket, "POST", "/v4.9.0/libpod/containers/web/wait?condition=stopped", "").await;
+    assert_eq!(status, 200);
+    assert_eq!(body, b"0");
+    
+    let (_, _, body) = request(&socket, "GET", "/v4.9.0/libpod/containers/json?all=true&filters=%7B%22label%22%3A%5B%22io.podman.compose.project%3Dapp%22%5D%7D", "").await;
+    let containers = json(&body);
+    assert_eq!(containers[0]["Names"][0], "web");
+    assert_eq!(containers[0]["Networks"][0], "app_default");
+    assert_eq!(containers[0]["Exited"], true);
+    
+    let (_, _, body) = request(&socket, "GET", "/v4.9.0/libpod/containers/web/json", "").await;
+    let inspect = json(&body);
+    assert_eq!(inspect["Name"], "web");
+    assert!(inspect["Config"]["Env"].as_array().unwrap().contains(&Value::from("MODE=test")));
+    
+    let (status, _, output) = request(&socket, "GET", "/v4.9.0/libpod/containers/web/logs?stdout=true&stderr=true", "").await;
+    assert_eq!(status, 200);
+    assert!(String::from_utf8_lossy(&output).contains("Hello from WASM Container!"));
+    
+    let (status, _, body) = request(&socket, "DELETE", "/v4.9.0/libpod/containers/web", "").await;
+    assert_eq!(status, 200);
+    assert_eq!(json(&body)[0]["Id"], id.as_str());
+}
+
+async fn start_daemon(dir: &TempDir) -> PathBuf {
+    let config = RuntimeConfig {
+        state_dir: Some(dir.path().join("state")),
+        cache_dir: Some(dir.path().join("cache")),
+        ..RuntimeConfig::default()
+    };
+    let socket = dir.path().join("docker.sock");
+    let daemon = Daemon::new(config).await.unwrap();
+    let path = socket.clone();
+    tokio::spawn(async move { daemon.listen(&path).await });
+    
+    while UnixStream::connect(&socket).await.is_err() {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    socket
+}
+
+async fn request_owned(socket: PathBuf, method: &'static str, target: String) -> (u16, String, Vec<u8>) {
+    request(&socket, method, &target, "").await
+}
+
+// Sends one request and reads until the daemon closes the connection,
+// returning the status, the head and the body, with chunked bodies decoded.
+async fn request(socket: &Path, method: &str, target: &str, body: &str) -> (u16, String, Vec<u8>) {
+    let mut stream = UnixStream::connect(socket).await.unwrap();
+    let request = format!(
+        "{} {} HTTP/1.1\r\nHost: docker\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+        method, target, body.len(), body,
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+    
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await.unwrap();
+    let end = response.windows(4).position(|window| window == b"\r\n\r\n").unwrap();
+    let head = String::from_utf8(response[..end].to_vec()).unwrap();
+    let mut body = response[end + 4..].to_vec();
+    let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+    
+    if head.contains("Transfer-Encoding: chunked") {
+        let text = String::from_utf8(body).unwrap();
+        let (size, rest) = text.split_once("\r\n").unwrap();
+        body = rest.as_bytes()[..usize::from_str_radix(size, 16).unwrap()].to_vec();
+    }
+    (status, head, body)
+}
+
+fn json(body: &[u8]) -> Value {
+    serde_json::from_slice(body).unwrap()
+}
@@ -0,0 +1,85 @@
+use ratatui::backend::TestBackend;
+use ratatui::crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use ratatui::Terminal;
+use wasm_container::dashboard::{Action, ContainerRow, Dashboard};
+use wasm_container::network::throttle::NetworkStats;
+use wasm_container::runtime::ResourceUsage;
+
+#[test]
+fn test_dashboard_shows_usage_and_logs() {
+    let mut dashboard = Dashboard::new();
+    dashboard.set_containers(vec![
+        row("b6c1d2e3f4a5b6c7", "worker", 12.5, 2_000_000),
+        row("a1b2c3d4e5f6a7b8", "api", 0.0, 500_000),
+    ]);
+    dashboard.set_logs(vec!["listening on :8080".to_string(), "\x1b[2Jrequest\tdone".to_string()]);
+    
+    let screen = render(&dashboard);
+    assert!(screen.contains("Running containers (2)"));
+    assert!(screen.contains("a1b2c3d4e5f6 "));
+    assert!(!screen.contains("a1b2c3d4e5f6a7"));
+    assert!(screen.contains("12.5%"));
+    assert!(screen.contains("2.0MB / 64.0MB"));
+    assert!(screen.contains("3.1%"));
+    assert!(screen.contains("1.5kB/s"));
+    // Sorted by name, with the first selected.
+    assert!(screen.find("api").unwrap() < screen.find("worker").unwrap());
+    assert!(screen.contains("Logs: api"));
+    assert!(screen.contains("listening on :8080"));
+    assert!(screen.contains("[2Jrequest    done"));
+}
+
+#[test]
+fn test_dashboard_keys() {
+    let mut dashboard = Dashboard::new();
+    assert_eq!(dashboard.handle_key(key(KeyCode::Char('s'))), None);
+    dashboard.set_containers(vec![row("aaaa", "api", 0.0, 0), row("bbbb", "worker", 0.0, 0)]);
+    
+    assert_eq!(dashboard.handle_key(key(KeyCode::Char('s'))), Some(Action::Stop("aaaa".to_string())));
+    dashboard.handle_key(key(KeyCode::Down));
+    dashboard.handle_key(key(KeyCode::Down));
+    assert_eq!(dashboard.handle_key(key(KeyCode::Char('r'))), Some(Action::Restart("bbbb".to_string())));
+    assert_eq!(dashboard.handle_key(key(KeyCode::Char('i'))), Some(Action::Inspect("bbbb".to_string())));
+    
+    // The selection follows the container when the list changes.
+    dashboard.set_containers(vec![row("cccc", "cache", 0.0, 0), row("bbbb", "worker", 0.0, 0)]);
+    assert_eq!(dashboard.selected().unwrap().id, "bbbb");
+    dashboard.set_containers(vec![row("cccc", "cache", 0.0, 0)]);
+    assert_eq!(dashboard.selected().unwrap().id, "cccc");
+    
+    // A popup swallows the next key.
+    dashboard.show_details("{}".to_string());
+    assert!(render(&dashboard).contains("Inspect"));
+    assert_eq!(dashboard.handle_key(key(KeyCode::Char('q'))), None);
+    assert!(!render(&dashboard).contains("Inspect (any key"));
+    assert_eq!(dashboard.handle_key(key(KeyCode::Char('q'))), Some(Action::Quit));
+    assert_eq!(dashboard.handle_key(KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL)), Some(Action::Quit));
+}
+
+fn row(id: &str, name: &str, cpu_percent: f64, memory: u64) -> ContainerRow {
+    ContainerRow {
+        id: id.to_string(),
+        name: name.to_string(),
+        image: "demo:latest".to_string(),
+        usage: ResourceUsage { cpu_percent, memory, memory_limit: 64_000_000 },
+        network: NetworkStats { rx_bytes_per_sec: 1500, ..NetworkStats::default() },
+    }
+}
+
+fn key(code: KeyCode) -> KeyEvent {
+    KeyEvent::new(code, KeyModifiers::NONE)
+}
+
+fn render(dashboard: &Dashboard) -> String {
+    let mut terminal = Terminal::new(TestBackend::new(140, 30)).unwrap();
+    terminal.draw(|frame| dashboard.render(frame)).unwrap();
+    let buffer = terminal.backend().buffer();
+    let mut screen = String::new();
+    for y in 0..buffer.area.height {
+        for x in 0..buffer.area.width {
+            screen.push_str(buffer[(x, y)].symbol());
+        }
+        screen.push('\n');
+    }
+    screen
+}
@@ -0,0 +1,58 @@
+use std::fs;
+use tempfile::TempDir;
+use wasm_container::doctor::{Doctor, Finding, Severity};
+
+fn doctor(root: &TempDir) -> Doctor {
+    Doctor::with_dirs(root.path().join("state"), root.path().join("cache"), root.path().join("scratch"))
+}
+
+fn problems(findings: &[Finding], check: &str) -> Vec<Finding> {
+    findings.iter().filter(|finding| finding.check == check && finding.severity != Severity::Ok).cloned().collect()
+}
+
+#[test]
+fn test_fresh_environment_is_healthy() {
+    let root = TempDir::new().unwrap();
+    let findings = doctor(&root).run();
+    
+    assert!(findings.iter().all(|finding| finding.severity == Severity::Ok), "{:#?}", findings);
+    for check in ["directories", "wasmtime", "state", "locks", "ports", "rootfs"] {
+        assert!(findings.iter().any(|finding| finding.check == check), "no {} check", check);
+    }
+    assert!(root.path().join("cache").is_dir());
+}
+
+#[test]
+fn test_corrupt_state_and_dead_locks_are_reported() {
+    let root = TempDir::new().unwrap();
+    let container = root.path().join("state/containers/abc123");
+    fs::create_dir_all(&container).unwrap();
+    fs::write(container.join("info.json"), "{ not json").unwrap();
+    fs::create_dir_all(root.path().join("state/compose")).unwrap();
+    fs::write(
+        root.path().join("state/compose/shop.json"),
+        r#"{"name": "shop", "pid": 4294967, "containers": [], "networks": []}"#,
+    ).unwrap();
+    
+    let findings = doctor(&root).run();
+    let state = problems(&findings, "state");
+    assert_eq!(state.len(), 1);
+    assert_eq!(state[0].severity, Severity::Error);
+    assert!(state[0].message.contains("abc123"));
+    assert!(state[0].fix.as_deref().unwrap().contains("rm -rf"));
+    
+    let locks = problems(&findings, "locks");
+    assert_eq!(locks.len(), 1);
+    assert_eq!(locks[0].fix.as_deref(), Some("wasm-container compose -p shop down"));
+}
+
+#[test]
+fn test_dangling_scratch_dirs_are_reported() {
+    let root = TempDir::new().unwrap();
+    fs::create_dir_all(root.path().join("scratch/gone-tmpfs")).unwrap();
+    
+    let rootfs = problems(&doctor(&root).run(), "rootfs");
+    assert_eq!(rootfs.len(), 1);
+    assert_eq!(rootfs[0].severity, Severity::Warning);
+    assert!(rootfs[0].fix.as_deref().unwrap().contains("no container gone exists"));
+}
@@ -0,0 +1,834 @@
+mod common;
+
+use common::test_image;
+use wasm_container::container::{Consistency, Container, MountMode, RootfsBackend, VolumeMount};
+use wasm_container::filesystem::{copy, Filesystem};
+use wasm_container::filesystem::dir_mount::DirMount;
+use wasm_container::filesystem::driver::{MemoryDriver, StorageDriver, VfsDriver};
+use wasm_container::filesystem::file_mount::FileMount;
+use wasm_container::filesystem::layer_cache::LayerCache;
+use wasm_container::filesystem::memory::MemoryFs;
+use wasm_container::filesystem::ownership::{Ownership, OwnershipMap};
+use wasm_container::image::Layer;
+use wasm_container::state::ContainerStore;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+enum Entry<'a> {
+    Dir(&'a str),
+    File(&'a str, &'a str),
+    Owned(&'a str, &'a str, u64, u32),
+    Symlink(&'a str, &'a str),
+    Hardlink(&'a str, &'a str),
+}
+
+#[tokio::test]
+async fn test_whiteout_removes_lower_layer_file() {
+    let fixtures = TempDir::new().unwrap();
+    let lower = write_layer(fixtures.path(), "lower", &[
+        Entry::Dir("app"),
+        Entry::File("app/keep.txt", "keep"),
+        Entry::File("app/remove.txt", "remove"),
+    ]);
+    let upper = write_layer(fixtures.path(), "upper", &[
+        Entry::File("app/.wh.remove.txt", ""),
+    ]);
+    
+    let (_state, mut filesystem) = test_filesystem();
+    filesystem.extract_layer(&lower).await.unwrap();
+    filesystem.extract_layer(&upper).await.unwrap();
+    
+    let app = filesystem.rootfs_path().join("app");
+    assert!(app.join("keep.txt").exists());
+    assert!(!app.join("remove.txt").exists());
+    assert!(!app.join(".wh.remove.txt").exists());
+}
+
+#[tokio::test]
+async fn test_whiteout_removes_lower_layer_directory() {
+    let fixtures = TempDir::new().unwrap();
+    let lower = write_layer(fixtures.path(), "lower", &[
+        Entry::Dir("data"),
+        Entry::Dir("data/cache"),
+        Entry::File("data/cache/blob", "blob"),
+    ]);
+    let upper = write_layer(fixtures.path(), "upper", &[
+        Entry::File("data/.wh.cache", ""),
+    ]);
+    
+    let (_state, mut filesystem) = test_filesystem();
+    filesystem.extract_layer(&lower).await.unwrap();
+    filesystem.extract_layer(&upper).await.unwrap();
+    
+    assert!(filesystem.rootfs_path().join("data").is_dir());
+    assert!(!filesystem.rootfs_path().join("data/cache").exists());
+}
+
+#[tokio::test]
+async fn test_opaque_whiteout_hides_lower_contents_only() {
+    let fixtures = TempDir::new().unwrap();
+    let lower = write_layer(fixtures.path(), "lower", &[
+        Entry::Dir("etc/conf.d"),
+        Entry::File("etc/conf.d/old.conf", "old"),
+    ]);
+    let upper = write_layer(fixtures.path(), "upper", &[
+        Entry::Dir("etc/conf.d"),
+        Entry::File("etc/conf.d/new.conf", "new"),
+        Entry::File("etc/conf.d/.wh..wh..opq", ""),
+    ]);
+    
+    let (_state, mut filesystem) = test_filesystem();
+    filesystem.extract_layer(&lower).await.unwrap();
+    filesystem.extract_layer(&upper).await.unwrap();
+    
+    let conf_d = filesystem.rootfs_path().join("etc/conf.d");
+    assert!(!conf_d.join("old.conf").exists());
+    assert_eq!(std::fs::read_to_string(conf_d.join("new.conf")).unwrap(), "new");
+    assert!(!conf_d.join(".wh..wh..opq").exists());
+}
+
+#[tokio::test]
+async fn test_whiteout_cannot_escape_rootfs() {
+    let fixtures = TempDir::new().unwrap();
+    let outside = fixtures.path().join("outside.txt");
+    std::fs::write(&outside, "host").unwrap();
+    
+    let hostile = format!(
+        "../../../../../../../..{}/.wh.outside.txt",
+        fixtures.path().display()
+    );
+    let upper = write_layer(fixtures.path(), "upper", &[
+        Entry::File(&hostile, ""),
+    ]);
+    
+    let (_state, mut filesystem) = test_filesystem();
+    filesystem.extract_layer(&upper).await.unwrap();
+    
+    assert!(outside.exists());
+}
+
+#[test]
+fn test_copy_resolves_paths_inside_rootfs() {
+    let rootfs = TempDir::new().unwrap();
+    let host = TempDir::new().unwrap();
+    std::fs::create_dir_all(rootfs.path().join("etc")).unwrap();
+    std::fs::write(rootfs.path().join("etc/passwd"), "container").unwrap();
+    std::os::unix::fs::symlink("/etc", rootfs.path().join("link")).unwrap();
+    
+    let resolved = copy::resolve_path(rootfs.path(), Path::new("/../../etc/passwd")).unwrap();
+    assert_eq!(resolved, rootfs.path().join("etc/passwd"));
+    
+    let resolved = copy::resolve_path(rootfs.path(), Path::new("/link/passwd")).unwrap();
+    assert_eq!(resolved, rootfs.path().join("etc/passwd"));
+    
+    let out = host.path().join("passwd");
+    copy::copy_from_container(rootfs.path(), Path::new("/link/../link/passwd"), &out).unwrap();
+    assert_eq!(std::fs::read_to_string(&out).unwrap(), "container");
+}
+
+#[test]
+fn test_copy_to_container_cannot_escape_through_symlink() {
+    let rootfs = TempDir::new().unwrap();
+    let host = TempDir::new().unwrap();
+    let outside = TempDir::new().unwrap();
+    std::os::unix::fs::symlink(outside.path(), rootfs.path().join("escape")).unwrap();
+    std::fs::create_dir_all(rootfs.path().join(outside.path().strip_prefix("/").unwrap())).unwrap();
+    
+    let src = host.path().join("payload.txt");
+    std::fs::write(&src, "payload").unwrap();
+    
+    copy::copy_to_container(rootfs.path(), &src, Path::new("/escape")).unwrap();
+    
+    assert!(!outside.path().join("payload.txt").exists());
+    let inside = copy::resolve_path(rootfs.path(), Path::new("/escape/payload.txt")).unwrap();
+    assert!(inside.starts_with(rootfs.path()));
+    assert_eq!(std::fs::read_to_string(inside).unwrap(), "payload");
+}
+
+#[test]
+fn test_copy_directory_round_trip() {
+    let rootfs = TempDir::new().unwrap();
+    let host = TempDir::new().unwrap();
+    std::fs::create_dir_all(rootfs.path().join("tmp")).unwrap();
+    
+    let src = host.path().join("config");
+    std::fs::create_dir_all(src.join("nested")).unwrap();
+    std::fs::write(src.join("nested/app.toml"), "port = 80").unwrap();
+    
+    copy::copy_to_container(rootfs.path(), &src, Path::new("/tmp")).unwrap();
+    assert!(rootfs.path().join("tmp/config/nested/app.toml").exists());
+    
+    let out = host.path().join("restored");
+    copy::copy_from_container(rootfs.path(), Path::new("/tmp/config"), &out).unwrap();
+    assert_eq!(std::fs::read_to_string(out.join("nested/app.toml")).unwrap(), "port = 80");
+    
+    assert!(copy::copy_from_container(rootfs.path(), Path::new("/missing"), &out).is_err());
+}
+
+#[test]
+fn test_memory_fs_merges_layers_with_whiteouts() {
+    let fixtures = TempDir::new().unwrap();
+    let lower = write_layer(fixtures.path(), "lower", &[
+        Entry::Dir("app"),
+        Entry::File("app/keep.txt", "keep"),
+        Entry::File("app/remove.txt", "remove"),
+        Entry::Dir("etc/conf.d"),
+        Entry::File("etc/conf.d/old.conf", "old"),
+    ]);
+    let upper = write_layer(fixtures.path(), "upper", &[
+        Entry::File("app/.wh.remove.txt", ""),
+        Entry::File("app/keep.txt", "updated"),
+        Entry::File("etc/conf.d/.wh..wh..opq", ""),
+        Entry::File("etc/conf.d/new.conf", "new"),
+    ]);
+    
+    let mut memory_fs = MemoryFs::new();
+    memory_fs.apply_layer(&lower).unwrap();
+    memory_fs.apply_layer(&upper).unwrap();
+    
+    assert!(memory_fs.get(Path::new("app/remove.txt")).is_none());
+    assert!(memory_fs.get(Path::new("/etc/conf.d/old.conf")).is_none());
+    assert!(memory_fs.get(Path::new("app/.wh.remove.txt")).is_none());
+    
+    let root = fixtures.path().join("rootfs");
+    memory_fs.materialize(&root).unwrap();
+    
+    assert_eq!(std::fs::read_to_string(root.join("app/keep.txt")).unwrap(), "updated");
+    assert_eq!(std::fs::read_to_string(root.join("etc/conf.d/new.conf")).unwrap(), "new");
+    assert!(!root.join("etc/conf.d/old.conf").exists());
+}
+
+#[test]
+fn test_layer_cache_applies_whiteouts() {
+    let fixtures = TempDir::new().unwrap();
+    let lower = write_layer(fixtures.path(), "lower", &[
+        Entry::Dir("app"),
+        Entry::File("app/keep.txt", "keep"),
+        Entry::File("app/remove.txt", "remove"),
+        Entry::Dir("etc/conf.d"),
+        Entry::File("etc/conf.d/old.conf", "old"),
+    ]);
+    let upper = write_layer(fixtures.path(), "upper", &[
+        Entry::File("app/.wh.remove.txt", ""),
+        Entry::Dir("etc/conf.d"),
+        Entry::File("etc/conf.d/.wh..wh..opq", ""),
+        Entry::File("etc/conf.d/new.conf", "new"),
+    ]);
+    
+    let cache = LayerCache::new(fixtures.path().join("layers")).unwrap();
+    let rootfs = fixtures.path().join("rootfs");
+    std::fs::create_dir_all(&rootfs).unwrap();
+    
+    for (digest, path) in [("sha256:lower", lower), ("sha256:upper", upper)] {
+        let layer_dir = cache.ensure(&layer(digest, path)).unwrap();
+        cache.apply(&layer_dir, &rootfs, &mut OwnershipMap::new()).unwrap();
+    }
+    
+    assert_eq!(std::fs::read_to_string(rootfs.join("app/keep.txt")).unwrap(), "keep");
+    assert!(!rootfs.join("app/remove.txt").exists());
+    assert!(!rootfs.join("app/.wh.remove.txt").exists());
+    assert!(!rootfs.join("etc/conf.d/old.conf").exists());
+    assert_eq!(std::fs::read_to_string(rootfs.join("etc/conf.d/new.conf")).unwrap(), "new");
+    
+    // The cached tree is untouched by containers writing to their copy.
+    std::fs::write(rootfs.join("app/keep.txt"), "modified").unwrap();
+    let cached = cache.layer_dir("sha256:lower").unwrap().join("app/keep.txt");
+    assert_eq!(std::fs::read_to_string(cached).unwrap(), "keep");
+}
+
+#[test]
+fn test_layer_cache_prunes_least_recently_used_layers() {
+    let fixtures = TempDir::new().unwrap();
+    let cache = LayerCache::new(fixtures.path().join("layers")).unwrap();
+    
+    let layers: Vec<Layer> = ["old", "used", "new"]
+        .iter()
+        .map(|name| {
+            let path = write_layer(fixtures.path(), name, &[Entry::File("data.bin", &"x".repeat(1000))]);
+            layer(&format!("sha256:{}", name), path)
+        })
+        .collect();
+    for layer in &layers {
+        cache.ensure(layer).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    // A cache hit marks "old" as the most recently used layer.
+    cache.ensure(&layers[0]).unwrap();
+    
+    let keep = vec![cache.layer_dir("sha256:new").unwrap()];
+    let freed = cache.prune(1500, &keep).unwrap();
+    
+    assert_eq!(freed, 2000);
+    assert!(!cache.layer_dir("sha256:used").unwrap().exists());
+    assert!(!cache.layer_dir("sha256:old").unwrap().exists());
+    assert!(cache.layer_dir("sha256:new").unwrap().exists());
+    assert_eq!(cache.prune(0, &keep).unwrap(), 0);
+}
+
+#[test]
+fn test_layer_cache_rejects_invalid_digests() {
+    let fixtures = TempDir::new().unwrap();
+    let cache = LayerCache::new(fixtures.path().join("layers")).unwrap();
+    
+    assert!(cache.layer_dir("sha256:../../etc").is_err());
+    assert!(cache.layer_dir("no-algorithm").is_err());
+}
+
+#[tokio::test]
+async fn test_parallel_extraction_preserves_layer_order() {
+    for backend in [RootfsBackend::Disk, RootfsBackend::Memory] {
+        let fixtures = TempDir::new().unwrap();
+        
+        let layers: Vec<Layer> = (0..12)
+            .map(|i| {
+                let order = i.to_string();
+                let unique = format!("layer-{}.txt", i);
+                let path = write_layer(fixtures.path(), &format!("layer{}", i), &[
+                    Entry::File("order.txt", &order),
+                    Entry::File(&unique, &order),
+                ]);
+                layer(&format!("sha256:layer{}", i), path)
+            })
+            .collect();
+        
+        let mut container = Container::new(test_image(None), None, None, vec![]).unwrap();
+        container.set_rootfs_backend(backend);
+        let rootfs = fixtures.path().join("rootfs");
+        let mut filesystem = Filesystem::new(&container, rootfs.clone()).unwrap();
+        let driver = test_driver(backend, fixtures.path());
+        
+        filesystem.apply_layers(driver.as_ref(), &layers).await.unwrap();
+        
+        assert_eq!(std::fs::read_to_string(rootfs.join("order.txt")).unwrap(), "11");
+        for i in 0..12 {
+            assert!(rootfs.join(format!("layer-{}.txt", i)).exists());
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_ownership_is_recorded_and_modes_stay_usable() {
+    use std::os::unix::fs::PermissionsExt;
+    
+    for backend in [RootfsBackend::Disk, RootfsBackend::Memory] {
+        let fixtures = TempDir::new().unwrap();
+        let path = write_layer(fixtures.path(), "owned", &[
+            Entry::Owned("locked/", "", 0, 0o555),
+            Entry::Owned("locked/secret", "s3cret", 0, 0o000),
+            Entry::Owned("home/app/", "", 1000, 0o750),
+            Entry::Owned("home/app/run.sh", "#!/bin/sh", 1000, 0o4755),
+        ]);
+        
+        let mut container = Container::new(test_image(None), None, None, vec![]).unwrap();
+        container.set_rootfs_backend(backend);
+        let rootfs = fixtures.path().join("rootfs");
+        let mut filesystem = Filesystem::new(&container, rootfs.clone()).unwrap();
+        let driver = test_driver(backend, fixtures.path());
+        
+        filesystem.apply_layers(driver.as_ref(), &[layer("sha256:owned", path)]).await.unwrap();
+        
+        let ownership = filesystem.ownership();
+        let secret = ownership.get(Path::new("/locked/secret")).unwrap();
+        assert_eq!((secret.uid, secret.gid, secret.mode), (0, 0, 0o000));
+        let script = ownership.get(Path::new("home/app/run.sh")).unwrap();
+        assert_eq!((script.uid, script.mode), (1000, 0o4755));
+        assert!(ownership.get(Path::new("locked")).is_some());
+        
+        let mode = |p: &str| std::fs::metadata(rootfs.join(p)).unwrap().permissions().mode() & 0o7777;
+        assert_eq!(mode("locked"), 0o755);
+        assert_eq!(mode("locked/secret"), 0o600);
+        assert_eq!(std::fs::read_to_string(rootfs.join("locked/secret")).unwrap(), "s3cret");
+        
+        // A rootless user must be able to delete the tree again.
+        std::fs::remove_dir_all(&rootfs).unwrap();
+    }
+}
+
+#[tokio::test]
+async fn test_links_are_preserved_on_both_backends() {
+    use std::os::unix::fs::MetadataExt;
+    
+    for backend in [RootfsBackend::Disk, RootfsBackend::Memory] {
+        let fixtures = TempDir::new().unwrap();
+        let path = write_layer(fixtures.path(), "links", &[
+            Entry::Dir("bin"),
+            Entry::File("bin/busybox", "multicall"),
+            Entry::Symlink("bin/sh", "busybox"),
+            Entry::Hardlink("bin/ls", "bin/busybox"),
+        ]);
+        
+        let mut container = Container::new(test_image(None), None, None, vec![]).unwrap();
+        container.set_rootfs_backend(backend);
+        let rootfs = fixtures.path().join("rootfs");
+        let mut filesystem = Filesystem::new(&container, rootfs.clone()).unwrap();
+        let driver = test_driver(backend, fixtures.path());
+        
+        filesystem.apply_layers(driver.as_ref(), &[layer("sha256:links", path)]).await.unwrap();
+        
+        let sh = rootfs.join("bin/sh");
+        assert!(std::fs::symlink_metadata(&sh).unwrap().file_type().is_symlink());
+        assert_eq!(std::fs::read_link(&sh).unwrap(), PathBuf::from("busybox"));
+        
+        let busybox = std::fs::metadata(rootfs.join("bin/busybox")).unwrap();
+        let ls = std::fs::metadata(rootfs.join("bin/ls")).unwrap();
+        assert_eq!(busybox.ino(), ls.ino());
+        assert_eq!(std::fs::read_to_string(rootfs.join("bin/ls")).unwrap(), "multicall");
+    }
+}
+
+#[test]
+fn test_drivers_apply_layers_one_at_a_time() {
+    use wasm_container::filesystem::ownership::OwnershipMap;
+    
+    for backend in [RootfsBackend::Disk, RootfsBackend::Memory] {
+        let fixtures = TempDir::new().unwrap();
+        let lower = write_layer(fixtures.path(), "lower", &[
+            Entry::Owned("app/", "", 1000, 0o755),
+            Entry::File("app/old.txt", "old"),
+        ]);
+        let upper = write_layer(fixtures.path(), "upper", &[
+            Entry::File("app/.wh.old.txt", ""),
+            Entry::File("app/new.txt", "new"),
+        ]);
+        
+        let driver = test_driver(backend, fixtures.path());
+        let rootfs = driver.prepare_rootfs("onebyone").unwrap();
+        assert!(rootfs.is_dir());
+        
+        let mut ownership = OwnershipMap::new();
+        driver.apply_layer(&rootfs, &layer("sha256:lower", lower), &mut ownership).unwrap();
+        driver.apply_layer(&rootfs, &layer("sha256:upper", upper), &mut ownership).unwrap();
+        
+        assert!(!rootfs.join("app/old.txt").exists(), "{}", driver.name());
+        assert_eq!(std::fs::read_to_string(rootfs.join("app/new.txt")).unwrap(), "new");
+        assert_eq!(ownership.get(Path::new("app")).unwrap().uid, 1000);
+        
+        driver.cleanup("onebyone").unwrap();
+        assert!(!rootfs.exists());
+    }
+}
+
+#[test]
+fn test_drivers_verify_layer_diff_ids() {
+    use std::io::Read;
+    
+    for backend in [RootfsBackend::Disk, RootfsBackend::Memory] {
+        let fixtures = TempDir::new().unwrap();
+        let path = write_layer(fixtures.path(), "layer", &[Entry::File("app/config.txt", "trusted")]);
+        
+        let mut uncompressed = Vec::new();
+        flate2::read::GzDecoder::new(std::fs::File::open(&path).unwrap())
+            .read_to_end(&mut uncompressed)
+            .unwrap();
+        
+        let mut verified = layer("sha256:verified", path.clone());
+        verified.diff_id = Some(diff_id(&uncompressed));
+        let mut tampered = layer("sha256:tampered", path);
+        tampered.diff_id = Some(diff_id(b"something else"));
+        
+        let driver = test_driver(backend, fixtures.path());
+        let rootfs = driver.prepare_rootfs("verify").unwrap();
+        let mut ownership = OwnershipMap::new();
+        
+        driver.apply_layers(&rootfs, &[verified], &mut ownership).unwrap();
+        assert_eq!(std::fs::read_to_string(rootfs.join("app/config.txt")).unwrap(), "trusted");
+        
+        let error = driver.apply_layers(&rootfs, &[tampered], &mut ownership).unwrap_err();
+        assert!(error.to_string().contains("failed verification"), "{}: {}", driver.name(), error);
+    }
+    
+    // A layer that fails verification is never cached.
+    let fixtures = TempDir::new().unwrap();
+    let path = write_layer(fixtures.path(), "layer", &[Entry::File("app/config.txt", "trusted")]);
+    let cache = LayerCache::new(fixtures.path().join("layers")).unwrap();
+    let mut tampered = layer("sha256:tampered", path);
+    tampered.diff_id = Some(diff_id(b"something else"));
+    
+    assert!(cache.ensure(&tampered).is_err());
+    assert!(!cache.layer_dir("sha256:tampered").unwrap().exists());
+    assert!(cache.partial_extractions().unwrap().is_empty());
+}
+
+#[test]
+fn test_overlay_driver_stacks_layers_without_copying() {
+    use std::os::unix::fs::MetadataExt;
+    use wasm_container::filesystem::diff::{self, Change, ChangeKind};
+    use wasm_container::filesystem::driver::OverlayDriver;
+    
+    if !OverlayDriver::supported() {
+        return;
+    }
+    
+    let fixtures = TempDir::new().unwrap();
+    let lower = write_layer(fixtures.path(), "lower", &[
+        Entry::Owned("app/", "", 1000, 0o755),
+        Entry::File("app/old.txt", "old"),
+        Entry::File("app/keep.txt", "keep"),
+        Entry::File("cache/stale.txt", "stale"),
+    ]);
+    let upper = write_layer(fixtures.path(), "upper", &[
+        Entry::File("app/.wh.old.txt", ""),
+        Entry::File("cache/.wh..wh..opq", ""),
+        Entry::File("cache/fresh.txt", "fresh"),
+    ]);
+    let layers = [layer("sha256:lower", lower), layer("sha256:upper", upper)];
+    
+    let store = ContainerStore::new(&fixtures.path().join("state")).unwrap();
+    let cache = LayerCache::new(fixtures.path().join("layers")).unwrap();
+    let driver = OverlayDriver::new(VfsDriver::new(store, cache), fixtures.path().join("overlay")).unwrap();
+    
+    let rootfs = driver.prepare_rootfs("stacked").unwrap();
+    let mut ownership = OwnershipMap::new();
+    driver.apply_layers(&rootfs, &layers, &mut ownership).unwrap();
+    
+    assert!(!rootfs.join("app/old.txt").exists());
+    assert!(!rootfs.join("cache/stale.txt").exists());
+    assert_eq!(std::fs::read_to_string(rootfs.join("app/keep.txt")).unwrap(), "keep");
+    assert_eq!(std::fs::read_to_string(rootfs.join("cache/fresh.txt")).unwrap(), "fresh");
+    assert_eq!(ownership.get(Path::new("app")).unwrap().uid, 1000);
+    assert!(ownership.get(Path::new("app/old.txt")).is_none());
+    
+    // The rootfs is a mount, not a copy.
+    let parent = std::fs::metadata(rootfs.parent().unwrap()).unwrap();
+    assert_ne!(std::fs::metadata(&rootfs).unwrap().dev(), parent.dev());
+    
+    let pristine = diff::scan(&rootfs).unwrap();
+    std::fs::write(rootfs.join("app/new.txt"), "new").unwrap();
+    driver.snapshot("stacked", &fixtures.path().join("snapshot")).unwrap();
+    std::fs::remove_file(rootfs.join("app/keep.txt")).unwrap();
+    
+    // Changes survive the rootfs being unmounted and mounted again.
+    driver.unmount("stacked").unwrap();
+    assert!(!rootfs.join("app").exists());
+    let changes = driver.commit_diff("stacked", &pristine).unwrap();
+    assert!(changes.contains(&Change { kind: ChangeKind::Added, path: "/app/new.txt".to_string() }));
+    assert!(changes.contains(&Change { kind: ChangeKind::Deleted, path: "/app/keep.txt".to_string() }));
+    assert!(!rootfs.join("app").exists());
+    
+    driver.restore("stacked", &fixtures.path().join("snapshot")).unwrap();
+    assert!(driver.mount("stacked").unwrap());
+    assert_eq!(std::fs::read_to_string(rootfs.join("app/new.txt")).unwrap(), "new");
+    assert_eq!(std::fs::read_to_string(rootfs.join("app/keep.txt")).unwrap(), "keep");
+    assert!(!rootfs.join("cache/stale.txt").exists());
+    
+    driver.cleanup("stacked").unwrap();
+    assert!(!rootfs.exists());
+}
+
+#[tokio::test]
+async fn test_whiteout_does_not_follow_symlink_out_of_rootfs() {
+    let fixtures = TempDir::new().unwrap();
+    let outside = fixtures.path().join("outside");
+    std::fs::create_dir_all(&outside).unwrap();
+    std::fs::write(outside.join("victim"), "host").unwrap();
+    
+    let lower = write_layer(fixtures.path(), "lower", &[
+        Entry::Symlink("escape", outside.to_str().unwrap()),
+    ]);
+    let upper = write_layer(fixtures.path(), "upper", &[
+        Entry::File("escape/.wh.victim", ""),
+    ]);
+    
+    let (_state, mut filesystem) = test_filesystem();
+    filesystem.extract_layer(&lower).await.unwrap();
+    filesystem.extract_layer(&upper).await.unwrap();
+    
+    assert!(outside.join("victim").exists());
+}
+
+#[tokio::test]
+async fn test_setup_does_not_follow_symlinks_out_of_rootfs() {
+    let fixtures = TempDir::new().unwrap();
+    let outside = fixtures.path().join("outside");
+    std::fs::create_dir_all(&outside).unwrap();
+    std::fs::write(outside.join("victim"), "host").unwrap();
+    std::fs::write(outside.join("null"), "host").unwrap();
+    
+    let path = write_layer(fixtures.path(), "hostile", &[
+        Entry::Symlink("etc/resolv.conf", outside.join("victim").to_str().unwrap()),
+        Entry::Symlink("dev/null", outside.join("null").to_str().unwrap()),
+        Entry::Symlink("var", outside.to_str().unwrap()),
+    ]);
+    
+    let (_state, mut filesystem) = test_filesystem();
+    filesystem.extract_layer(&path).await.unwrap();
+    filesystem.setup().await.unwrap();
+    
+    assert_eq!(std::fs::read_to_string(outside.join("victim")).unwrap(), "host");
+    assert_eq!(std::fs::read_to_string(outside.join("null")).unwrap(), "host");
+    assert!(!outside.join("log").exists());
+    let rootfs = filesystem.rootfs_path();
+    assert!(std::fs::read_to_string(rootfs.join("etc/resolv.conf")).unwrap().contains("nameserver"));
+    assert!(std::fs::symlink_metadata(rootfs.join("dev/null")).unwrap().is_file());
+}
+
+#[tokio::test]
+async fn test_memory_backend_does_not_write_through_symlinks() {
+    let fixtures = TempDir::new().unwrap();
+    let outside = fixtures.path().join("outside");
+    std::fs::create_dir_all(&outside).unwrap();
+    
+    let path = write_layer(fixtures.path(), "hostile", &[
+        Entry::Symlink("escape", outside.to_str().unwrap()),
+        Entry::File("escape/pwned", "pwned"),
+    ]);
+    
+    let mut container = Container::new(test_image(None), None, None, vec![]).unwrap();
+    container.set_rootfs_backend(RootfsBackend::Memory);
+    let rootfs = fixtures.path().join("rootfs");
+    let mut filesystem = Filesystem::new(&container, rootfs.clone()).unwrap();
+    let driver = test_driver(RootfsBackend::Memory, fixtures.path());
+    
+    filesystem.apply_layers(driver.as_ref(), &[layer("sha256:hostile", path)]).await.unwrap();
+    
+    assert!(!outside.join("pwned").exists());
+}
+
+#[test]
+fn test_volume_mount_point_stays_inside_rootfs() {
+    let fixtures = TempDir::new().unwrap();
+    let outside = fixtures.path().join("outside");
+    std::fs::create_dir_all(&outside).unwrap();
+    let host_file = fixtures.path().join("config.toml");
+    std::fs::write(&host_file, "key = 1").unwrap();
+    
+    let (_state, filesystem) = test_filesystem();
+    let rootfs = filesystem.rootfs_path();
+    std::os::unix::fs::symlink(&outside, rootfs.join("conf")).unwrap();
+    
+    filesystem.mount_volume(&mount(&host_file, "/conf/config.toml", false)).unwrap();
+    
+    assert!(!outside.join("config.toml").exists());
+    let mounted = copy::resolve_path(rootfs, Path::new("/conf/config.toml")).unwrap();
+    assert!(mounted.starts_with(rootfs));
+    assert_eq!(std::fs::read_to_string(mounted).unwrap(), "key = 1");
+    assert!(filesystem.mount_volume(&mount(&host_file, "../escape", false)).is_err());
+}
+
+#[test]
+fn test_file_mount_stays_in_sync_with_host() {
+    let fixtures = TempDir::new().unwrap();
+    let host_file = fixtures.path().join("app.conf");
+    let target = fixtures.path().join("mounted.conf");
+    std::fs::write(&host_file, "v1").unwrap();
+    
+    let mount = FileMount::new(&mount(&host_file, "/app.conf", false), target.clone()).unwrap();
+    assert_eq!(std::fs::read_to_string(&target).unwrap(), "v1");
+    
+    std::fs::write(&host_file, "host v2").unwrap();
+    mount.sync().unwrap();
+    assert_eq!(std::fs::read_to_string(&target).unwrap(), "host v2");
+    
+    std::fs::write(&target, "guest v3!").unwrap();
+    mount.sync().unwrap();
+    assert_eq!(std::fs::read_to_string(&host_file).unwrap(), "guest v3!");
+}
+
+#[test]
+fn test_file_mount_consistency_defers_guest_writes() {
+    let fixtures = TempDir::new().unwrap();
+    let host_file = fixtures.path().join("app.conf");
+    let target = fixtures.path().join("mounted.conf");
+    std::fs::write(&host_file, "v1").unwrap();
+    
+    let mut volume = mount(&host_file, "/app.conf", false);
+    volume.consistency = Consistency::Cached;
+    let mount = FileMount::new(&volume, target.clone()).unwrap();
+    
+    std::fs::write(&target, "guest v2").unwrap();
+    mount.poll().unwrap();
+    assert_eq!(std::fs::read_to_string(&host_file).unwrap(), "v1");
+    
+    mount.sync().unwrap();
+    assert_eq!(std::fs::read_to_string(&host_file).unwrap(), "guest v2");
+}
+
+#[test]
+fn test_read_only_file_mount_reverts_guest_writes() {
+    let fixtures = TempDir::new().unwrap();
+    let host_file = fixtures.path().join("app.conf");
+    let target = fixtures.path().join("mounted.conf");
+    std::fs::write(&host_file, "original").unwrap();
+    
+    let mount = FileMount::new(&mount(&host_file, "/app.conf", true), target.clone()).unwrap();
+    assert!(std::fs::metadata(&target).unwrap().permissions().readonly());
+    
+    std::fs::remove_file(&target).unwrap();
+    std::fs::write(&target, "tampered").unwrap();
+    mount.sync().unwrap();
+    
+    assert_eq!(std::fs::read_to_string(&target).unwrap(), "original");
+    assert_eq!(std::fs::read_to_string(&host_file).unwrap(), "original");
+}
+
+#[test]
+fn test_sync_dir_mount_propagates_host_changes() {
+    let fixtures = TempDir::new().unwrap();
+    let host = fixtures.path().join("src");
+    let target = fixtures.path().join("rootfs/app");
+    std::fs::create_dir_all(host.join("lib")).unwrap();
+    std::fs::write(host.join("main.js"), "v1").unwrap();
+    std::fs::write(host.join("lib/util.js"), "util").unwrap();
+    std::fs::create_dir_all(&target).unwrap();
+    
+    let mut volume = mount(&host, "/app", false);
+    volume.mode = MountMode::Sync;
+    let mount = DirMount::new(&volume, target.clone()).unwrap();
+    assert_eq!(std::fs::read_to_string(target.join("lib/util.js")).unwrap(), "util");
+    
+    std::fs::write(host.join("main.js"), "v2 from host").unwrap();
+    std::fs::create_dir_all(host.join("assets")).unwrap();
+    std::fs::write(host.join("assets/logo.svg"), "<svg/>").unwrap();
+    std::fs::remove_dir_all(host.join("lib")).unwrap();
+    std::fs::write(target.join("guest.log"), "guest").unwrap();
+    mount.poll().unwrap();
+    
+    assert_eq!(std::fs::read_to_string(target.join("main.js")).unwrap(), "v2 from host");
+    assert_eq!(std::fs::read_to_string(target.join("assets/logo.svg")).unwrap(), "<svg/>");
+    assert!(!target.join("lib").exists());
+    assert!(target.join("guest.log").exists());
+    assert!(!host.join("guest.log").exists());
+}
+
+#[test]
+fn test_sync_back_dir_mount_propagates_guest_changes() {
+    let fixtures = TempDir::new().unwrap();
+    let host = fixtures.path().join("src");
+    let target = fixtures.path().join("rootfs/app");
+    std::fs::create_dir_all(&host).unwrap();
+    std::fs::write(host.join("shared.txt"), "v1").unwrap();
+    std::fs::write(host.join("stale.txt"), "stale").unwrap();
+    std::fs::create_dir_all(&target).unwrap();
+    
+    let mut volume = mount(&host, "/app", false);
+    volume.mode = MountMode::SyncBack;
+    let mount = DirMount::new(&volume, target.clone()).unwrap();
+    
+    std::fs::create_dir_all(target.join("out")).unwrap();
+    std::fs::write(target.join("out/result.json"), "{}").unwrap();
+    std::fs::remove_file(target.join("stale.txt")).unwrap();
+    std::fs::write(target.join("shared.txt"), "guest edit").unwrap();
+    std::fs::write(host.join("shared.txt"), "host edit wins").unwrap();
+    mount.poll().unwrap();
+    
+    assert_eq!(std::fs::read_to_string(host.join("out/result.json")).unwrap(), "{}");
+    assert!(!host.join("stale.txt").exists());
+    assert_eq!(std::fs::read_to_string(host.join("shared.txt")).unwrap(), "host edit wins");
+    assert_eq!(std::fs::read_to_string(target.join("shared.txt")).unwrap(), "host edit wins");
+}
+
+#[test]
+fn test_ownership_map_whiteout_bookkeeping() {
+    let mut ownership = OwnershipMap::new();
+    let owner = Ownership { uid: 0, gid: 0, mode: 0o644 };
+    ownership.insert(Path::new("etc"), owner);
+    ownership.insert(Path::new("etc/conf.d/a.conf"), owner);
+    ownership.insert(Path::new("etc2"), owner);
+    
+    ownership.remove_children(Path::new("/etc"));
+    assert!(ownership.get(Path::new("etc")).is_some());
+    assert!(ownership.get(Path::new("etc/conf.d/a.conf")).is_none());
+    
+    ownership.remove_tree(Path::new("etc"));
+    assert!(ownership.get(Path::new("etc")).is_none());
+    assert!(ownership.get(Path::new("etc2")).is_some());
+}
+
+fn layer(digest: &str, path: PathBuf) -> Layer {
+    Layer {
+        digest: digest.to_string(),
+        size: 0,
+        media_type: "application/vnd.oci.image.layer.v1.tar+gzip".to_string(),
+        path,
+        diff_id: None,
+    }
+}
+
+fn mount(host_path: &Path, container_path: &str, read_only: bool) -> VolumeMount {
+    VolumeMount {
+        host_path: host_path.to_path_buf(),
+        container_path: PathBuf::from(container_path),
+        read_only,
+        consistency: Consistency::default(),
+        mode: MountMode::default(),
+    }
+}
+
+fn test_driver(backend: RootfsBackend, dir: &Path) -> Box<dyn StorageDriver> {
+    match backend {
+        RootfsBackend::Disk => Box::new(VfsDriver::new(
+            ContainerStore::new(&dir.join("state")).unwrap(),
+            LayerCache::new(dir.join("layers")).unwrap(),
+        )),
+        RootfsBackend::Memory => Box::new(MemoryDriver::new(dir.join("scratch"))),
+    }
+}
+
+fn test_filesystem() -> (TempDir, Filesystem) {
+    let state = TempDir::new().unwrap();
+    let container = Container::new(test_image(None), None, None, vec![]).unwrap();
+    let filesystem = Filesystem::new(&container, state.path().join("rootfs")).unwrap();
+    (state, filesystem)
+}
+
+fn write_layer(dir: &Path, name: &str, entries: &[Entry]) -> PathBuf {
+    let path = dir.join(format!("{}.tar.gz", name));
+    let file = std::fs::File::create(&path).unwrap();
+    let mut builder = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+    
+    for entry in entries {
+        let mut header = tar::Header::new_gnu();
+        match entry {
+            Entry::Dir(path) => {
+                header.set_entry_type(tar::EntryType::Directory);
+                header.set_mode(0o755);
+                header.set_size(0);
+                append(&mut builder, &mut header, path, &[][..]);
+            }
+            Entry::File(path, contents) => {
+                header.set_entry_type(tar::EntryType::Regular);
+                header.set_mode(0o644);
+                header.set_size(contents.len() as u64);
+                append(&mut builder, &mut header, path, contents.as_bytes());
+            }
+            Entry::Owned(path, contents, owner, mode) => {
+                let is_dir = path.ends_with('/');
+                header.set_entry_type(if is_dir { tar::EntryType::Directory } else { tar::EntryType::Regular });
+                header.set_mode(*mode);
+                header.set_uid(*owner);
+                header.set_gid(*owner);
+                header.set_size(contents.len() as u64);
+                append(&mut builder, &mut header, path, contents.as_bytes());
+            }
+            Entry::Symlink(path, target) | Entry::Hardlink(path, target) => {
+                let kind = if matches!(entry, Entry::Symlink(..)) { tar::EntryType::Symlink } else { tar::EntryType::Link };
+                header.set_entry_type(kind);
+                header.set_mode(0o777);
+                header.set_size(0);
+                header.set_link_name(target).unwrap();
+                append(&mut builder, &mut header, path, &[][..]);
+            }
+        }
+    }
+    
+    builder.into_inner().unwrap().finish().unwrap();
+    path
+}
+
+// `Builder::append_data` refuses `..` components, so names are written raw
+// to let fixtures describe hostile archives too.
+fn append<W: std::io::Write>(builder: &mut tar::Builder<W>, header: &mut tar::Header, path: &str, data: &[u8]) {
+    let name = &mut header.as_old_mut().name;
+    name[..path.len()].copy_from_slice(path.as_bytes());
+    header.set_cksum();
+    builder.append(header, data).unwrap();
+}
+
+// A layer's diff ID: the digest of its uncompressed tar.
+fn diff_id(tar: &[u8]) -> String {
+    let hex: String = Sha256::digest(tar).iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("sha256:{}", hex)
+}
@@ -0,0 +1,59 @@
+;; Listens on port 8080, connects to itself over loopback and exchanges a
+;; message each way, exiting with a distinct code for the first step that
+;; fails.
+(module
+  (import "env" "net_listen" (func $listen (param i32 i32) (result i32)))
+  (import "env" "net_accept" (func $accept (param i32 i32) (result i32)))
+  (import "env" "net_connect" (func $connect (param i32 i32 i32 i32) (result i32)))
+  (import "env" "net_send" (func $send (param i32 i32 i32 i32) (result i32)))
+  (import "env" "net_recv" (func $recv (param i32 i32 i32 i32) (result i32)))
+  (import "env" "net_close" (func $close (param i32) (result i32)))
+  (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+  
+  (memory 1)
+  (export "memory" (memory 0))
+  
+  ;; 0: listener, 4: client, 8: server side, 12: byte count, 64: buffer
+  (data (i32.const 128) "127.0.0.1")
+  (data (i32.const 144) "ping")
+  (data (i32.const 148) "pong")
+  
+  (func $main (export "_start")
+    (if (call $listen (i32.const 8080) (i32.const 0))
+      (then (call $proc_exit (i32.const 10))))
+    (if (call $connect (i32.const 128) (i32.const 9) (i32.const 8080) (i32.const 4))
+      (then (call $proc_exit (i32.const 20))))
+    
+    ;; The connection is usable before it is accepted.
+    (if (call $send (i32.load (i32.const 4)) (i32.const 144) (i32.const 4) (i32.const 12))
+      (then (call $proc_exit (i32.const 30))))
+    (if (call $accept (i32.load (i32.const 0)) (i32.const 8))
+      (then (call $proc_exit (i32.const 40))))
+    (if (call $recv (i32.load (i32.const 8)) (i32.const 64) (i32.const 32) (i32.const 12))
+      (then (call $proc_exit (i32.const 50))))
+    (if (i32.ne (i32.load (i32.const 12)) (i32.const 4))
+      (then (call $proc_exit (i32.const 51))))
+    (if (i32.ne (i32.load (i32.const 64)) (i32.load (i32.const 144)))
+      (then (call $proc_exit (i32.const 52))))
+    
+    (if (call $send (i32.load (i32.const 8)) (i32.const 148) (i32.const 4) (i32.const 12))
+      (then (call $proc_exit (i32.const 60))))
+    (if (call $recv (i32.load (i32.const 4)) (i32.const 64) (i32.const 32) (i32.const 12))
+      (then (call $proc_exit (i32.const 61))))
+    (if (i32.ne (i32.load (i32.const 64)) (i32.load (i32.const 148)))
+      (then (call $proc_exit (i32.const 62))))
+    
+    ;; Closing one end is seen as end of stream by the other.
+    (if (call $close (i32.load (i32.const 8)))
+      (then (call $proc_exit (i32.const 70))))
+    (if (call $recv (i32.load (i32.const 4)) (i32.const 64) (i32.const 32) (i32.const 12))
+      (then (call $proc_exit (i32.const 71))))
+    (if (i32.load (i32.const 12))
+      (then (call $proc_exit (i32.const 72))))
+    
+    ;; Nobody listens on 8081.
+    (if (i32.ne (call $connect (i32.const 128) (i32.const 9) (i32.const 8081) (i32.const 4)) (i32.const 14))
+      (then (call $proc_exit (i32.const 80))))
+    
+    (call $proc_exit (i32.const 0)))
+)
@@ -0,0 +1,15 @@
+;; Spins for a fixed number of iterations, so CPU limits show in how long
+;; it takes.
+(module
+  (memory 1)
+  (export "memory" (memory 0))
+  
+  (func $main (export "_start")
+    (local $i i64)
+    (local.set $i (i64.const 200000000))
+    (loop $spin
+      (local.set $i (i64.sub (local.get $i) (i64.const 1)))
+      (br_if $spin (i64.ne (local.get $i) (i64.const 0)))
+    )
+  )
+)
@@ -0,0 +1,35 @@
+;; Creates "out.txt" in the first volume preopen (fd 4), writes "hello"
+;; into it and exits with the WASI errno of the first failing call.
+(module
+  (import "wasi_snapshot_preview1" "path_open"
+    (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "fd_write"
+    (func $fd_write (param i32 i32 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+  
+  (memory 1)
+  (export "memory" (memory 0))
+  
+  (data (i32.const 16) "out.txt")
+  (data (i32.const 32) "hello")
+  
+  (func $main (export "_start")
+    (local $errno i32)
+    
+    ;; path_open(fd=4, dirflags=0, path, path_len, oflags=CREAT,
+    ;;           rights=FD_READ|FD_WRITE, inheriting=0, fdflags=0, opened_fd=0)
+    (local.set $errno
+      (call $path_open
+        (i32.const 4) (i32.const 0) (i32.const 16) (i32.const 7) (i32.const 1)
+        (i64.const 0x42) (i64.const 0) (i32.const 0) (i32.const 0)))
+    (if (local.get $errno)
+      (then (call $proc_exit (local.get $errno))))
+    
+    (i32.store (i32.const 8) (i32.const 32))
+    (i32.store (i32.const 12) (i32.const 5))
+    (local.set $errno
+      (call $fd_write (i32.load (i32.const 0)) (i32.const 8) (i32.const 1) (i32.const 64)))
+    
+    (call $proc_exit (local.get $errno))
+  )
+)
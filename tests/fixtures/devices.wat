@@ -0,0 +1,80 @@
+;; Exercises the virtual /dev devices through the root preopen (fd 3) and
+;; exits with a distinct code for the first check that fails.
+(module
+  (import "wasi_snapshot_preview1" "path_open"
+    (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "fd_read"
+    (func $fd_read (param i32 i32 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "fd_write"
+    (func $fd_write (param i32 i32 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "fd_close"
+    (func $fd_close (param i32) (result i32)))
+  (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+  
+  (memory 1)
+  (export "memory" (memory 0))
+  
+  ;; 0: opened fd, 4: nread/nwritten, 8: iovec, 64: 32-byte buffer
+  (data (i32.const 128) "dev/urandom")
+  (data (i32.const 144) "dev/zero")
+  (data (i32.const 160) "dev/null")
+  
+  (func $open (param $path i32) (param $len i32) (param $code i32) (result i32)
+    (if (call $path_open
+          (i32.const 3) (i32.const 0) (local.get $path) (local.get $len) (i32.const 0)
+          (i64.const 0x42) (i64.const 0) (i32.const 0) (i32.const 0))
+      (then (call $proc_exit (local.get $code))))
+    (i32.load (i32.const 0)))
+  
+  ;; Reads up to 32 bytes into the buffer and returns the byte count.
+  (func $read (param $fd i32) (param $code i32) (result i32)
+    (i32.store (i32.const 8) (i32.const 64))
+    (i32.store (i32.const 12) (i32.const 32))
+    (if (call $fd_read (local.get $fd) (i32.const 8) (i32.const 1) (i32.const 4))
+      (then (call $proc_exit (local.get $code))))
+    (i32.load (i32.const 4)))
+  
+  (func $buffer_is_zero (result i32)
+    (i64.eqz
+      (i64.or
+        (i64.or (i64.load (i32.const 64)) (i64.load (i32.const 72)))
+        (i64.or (i64.load (i32.const 80)) (i64.load (i32.const 88))))))
+  
+  (func $main (export "_start")
+    (local $fd i32)
+    
+    ;; /dev/urandom fills the whole buffer with something other than zeros.
+    (local.set $fd (call $open (i32.const 128) (i32.const 11) (i32.const 10)))
+    (if (i32.ne (call $read (local.get $fd) (i32.const 11)) (i32.const 32))
+      (then (call $proc_exit (i32.const 12))))
+    (if (call $buffer_is_zero)
+      (then (call $proc_exit (i32.const 13))))
+    (if (call $fd_close (local.get $fd))
+      (then (call $proc_exit (i32.const 14))))
+    
+    ;; /dev/zero overwrites the random bytes with zeros.
+    (local.set $fd (call $open (i32.const 144) (i32.const 8) (i32.const 20)))
+    (if (i32.ne (call $read (local.get $fd) (i32.const 21)) (i32.const 32))
+      (then (call $proc_exit (i32.const 22))))
+    (if (i32.eqz (call $buffer_is_zero))
+      (then (call $proc_exit (i32.const 23))))
+    
+    ;; /dev/null swallows writes and reads as empty.
+    (local.set $fd (call $open (i32.const 160) (i32.const 8) (i32.const 30)))
+    (i32.store (i32.const 8) (i32.const 64))
+    (i32.store (i32.const 12) (i32.const 32))
+    (if (call $fd_write (local.get $fd) (i32.const 8) (i32.const 1) (i32.const 4))
+      (then (call $proc_exit (i32.const 31))))
+    (if (i32.ne (i32.load (i32.const 4)) (i32.const 32))
+      (then (call $proc_exit (i32.const 32))))
+    (if (i32.ne (call $read (local.get $fd) (i32.const 33)) (i32.const 0))
+      (then (call $proc_exit (i32.const 34))))
+    
+    ;; Closing twice fails the second time.
+    (if (call $fd_close (local.get $fd))
+      (then (call $proc_exit (i32.const 35))))
+    (if (i32.eqz (call $fd_close (local.get $fd)))
+      (then (call $proc_exit (i32.const 36))))
+    
+    (call $proc_exit (i32.const 0)))
+)
@@ -0,0 +1,24 @@
+;; Checks that outbound connections are refused by the egress policy while
+;; loopback stays reachable, exiting with a distinct code for the first
+;; check that fails.
+(module
+  (import "env" "net_connect" (func $connect (param i32 i32 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+  
+  (memory 1)
+  (export "memory" (memory 0))
+  
+  ;; 0: descriptor out
+  (data (i32.const 128) "10.0.0.9")
+  (data (i32.const 144) "127.0.0.1")
+  
+  (func $main (export "_start")
+    ;; Denied with EACCES before the address is even looked at...
+    (if (i32.ne (call $connect (i32.const 128) (i32.const 8) (i32.const 80) (i32.const 0)) (i32.const 2))
+      (then (call $proc_exit (i32.const 10))))
+    ;; ...while loopback is only refused as nothing listens there.
+    (if (i32.ne (call $connect (i32.const 144) (i32.const 9) (i32.const 8080) (i32.const 0)) (i32.const 14))
+      (then (call $proc_exit (i32.const 20))))
+    
+    (call $proc_exit (i32.const 0)))
+)
@@ -0,0 +1,22 @@
+;; Prints which of its exports was called: `_start` or `serve`.
+(module
+  (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+  
+  (memory 1)
+  (export "memory" (memory 0))
+  
+  ;; 0: iovec, 8: bytes written
+  (data (i32.const 64) "started\n")
+  (data (i32.const 96) "serving\n")
+  
+  (func $print (param $text i32)
+    (i32.store (i32.const 0) (local.get $text))
+    (i32.store (i32.const 4) (i32.const 8))
+    (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8))))
+  
+  (func (export "_start")
+    (call $print (i32.const 64)))
+  
+  (func (export "serve")
+    (call $print (i32.const 96)))
+)
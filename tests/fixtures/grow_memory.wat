@@ -0,0 +1,7 @@
+;; Asks for 16 MiB more memory than it starts with, and carries on whether
+;; or not it gets it.
+(module
+  (memory 1)
+  (func (export "_start")
+    (drop (memory.grow (i32.const 256))))
+)
@@ -0,0 +1,51 @@
+;; Connects to the host's loopback on the port given as its first argument
+;; and exchanges a message there, exiting with a distinct code for the first
+;; step that fails.
+(module
+  (import "wasi_snapshot_preview1" "args_sizes_get" (func $args_sizes_get (param i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "args_get" (func $args_get (param i32 i32) (result i32)))
+  (import "env" "net_connect" (func $connect (param i32 i32 i32 i32) (result i32)))
+  (import "env" "net_send" (func $send (param i32 i32 i32 i32) (result i32)))
+  (import "env" "net_recv" (func $recv (param i32 i32 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+  
+  (memory 1)
+  (export "memory" (memory 0))
+  
+  ;; 0: connection, 12: byte count, 64: buffer, 256: argv, 512: arg data
+  (data (i32.const 128) "127.0.0.1")
+  (data (i32.const 176) "ping")
+  (data (i32.const 180) "pong")
+  
+  (func $main (export "_start")
+    (local $p i32)
+    (local $port i32)
+    (if (call $args_sizes_get (i32.const 16) (i32.const 20))
+      (then (call $proc_exit (i32.const 5))))
+    (if (i32.lt_u (i32.load (i32.const 16)) (i32.const 2))
+      (then (call $proc_exit (i32.const 6))))
+    (drop (call $args_get (i32.const 256) (i32.const 512)))
+    
+    ;; Parses the decimal port in argv[1].
+    (local.set $p (i32.load (i32.const 260)))
+    (block $done
+      (loop $digits
+        (br_if $done (i32.eqz (i32.load8_u (local.get $p))))
+        (local.set $port
+          (i32.add
+            (i32.mul (local.get $port) (i32.const 10))
+            (i32.sub (i32.load8_u (local.get $p)) (i32.const 48))))
+        (local.set $p (i32.add (local.get $p) (i32.const 1)))
+        (br $digits)))
+    
+    (if (call $connect (i32.const 128) (i32.const 9) (local.get $port) (i32.const 0))
+      (then (call $proc_exit (i32.const 10))))
+    (if (call $send (i32.load (i32.const 0)) (i32.const 176) (i32.const 4) (i32.const 12))
+      (then (call $proc_exit (i32.const 20))))
+    (if (call $recv (i32.load (i32.const 0)) (i32.const 64) (i32.const 32) (i32.const 12))
+      (then (call $proc_exit (i32.const 30))))
+    (if (i32.ne (i32.load (i32.const 64)) (i32.load (i32.const 180)))
+      (then (call $proc_exit (i32.const 31))))
+    
+    (call $proc_exit (i32.const 0)))
+)
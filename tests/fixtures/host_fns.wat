@@ -0,0 +1,16 @@
+;; Calls host functions an embedder provides: `app.record` with 21, then
+;; `env.container_log` with "hello".
+(module
+  (import "app" "record" (func $record (param i32)))
+  (import "env" "container_log" (func $log (param i32 i32)))
+  
+  (memory 1)
+  (export "memory" (memory 0))
+  
+  (data (i32.const 16) "hello")
+  
+  (func $main (export "_start")
+    (call $record (i32.const 21))
+    (call $log (i32.const 16) (i32.const 5))
+  )
+)
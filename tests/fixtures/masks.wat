@@ -0,0 +1,78 @@
+;; Checks masked paths through the root preopen (fd 3), a volume at /data
+;; (fd 4) and a volume exposing the whole rootfs again at /host (fd 5), and
+;; exits with a distinct code for the first check that fails.
+(module
+  (import "wasi_snapshot_preview1" "path_open"
+    (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "fd_read"
+    (func $fd_read (param i32 i32 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "fd_readdir"
+    (func $fd_readdir (param i32 i32 i32 i64 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "path_rename"
+    (func $path_rename (param i32 i32 i32 i32 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+  
+  (memory 1)
+  (export "memory" (memory 0))
+  
+  ;; 0: opened fd, 4: nread/bufused, 8: iovec, 64: 32-byte buffer
+  (data (i32.const 128) "etc/secret.conf")
+  (data (i32.const 144) "secrets/key.txt")
+  (data (i32.const 160) "secrets")
+  (data (i32.const 176) "public")
+  (data (i32.const 192) "public.txt")
+  
+  (func $open (param $dir i32) (param $path i32) (param $len i32) (param $oflags i32) (result i32)
+    (call $path_open
+      (local.get $dir) (i32.const 1) (local.get $path) (local.get $len) (local.get $oflags)
+      (i64.const 0x4042) (i64.const 0x4042) (i32.const 0) (i32.const 0)))
+  
+  ;; Reads up to 32 bytes into the buffer and returns the byte count.
+  (func $read (param $fd i32) (param $code i32) (result i32)
+    (i32.store (i32.const 8) (i32.const 64))
+    (i32.store (i32.const 12) (i32.const 32))
+    (if (call $fd_read (local.get $fd) (i32.const 8) (i32.const 1) (i32.const 4))
+      (then (call $proc_exit (local.get $code))))
+    (i32.load (i32.const 4)))
+  
+  (func $main (export "_start")
+    ;; A masked file reads as empty through the root preopen...
+    (if (call $open (i32.const 3) (i32.const 128) (i32.const 15) (i32.const 0))
+      (then (call $proc_exit (i32.const 10))))
+    (if (call $read (i32.load (i32.const 0)) (i32.const 11))
+      (then (call $proc_exit (i32.const 12))))
+    
+    ;; ...and through a volume that exposes the rootfs under another name.
+    (if (call $open (i32.const 5) (i32.const 128) (i32.const 15) (i32.const 0))
+      (then (call $proc_exit (i32.const 20))))
+    (if (call $read (i32.load (i32.const 0)) (i32.const 21))
+      (then (call $proc_exit (i32.const 22))))
+    
+    ;; Unmasked files are untouched.
+    (if (call $open (i32.const 4) (i32.const 192) (i32.const 10) (i32.const 0))
+      (then (call $proc_exit (i32.const 30))))
+    (if (i32.eqz (call $read (i32.load (i32.const 0)) (i32.const 31)))
+      (then (call $proc_exit (i32.const 32))))
+    
+    ;; Nothing inside a masked directory exists.
+    (if (i32.ne (call $open (i32.const 4) (i32.const 144) (i32.const 15) (i32.const 0)) (i32.const 44))
+      (then (call $proc_exit (i32.const 40))))
+    
+    ;; A masked directory opens, but lists as empty.
+    (if (call $path_open
+          (i32.const 4) (i32.const 1) (i32.const 160) (i32.const 7) (i32.const 2)
+          (i64.const 0x4000) (i64.const 0) (i32.const 0) (i32.const 0))
+      (then (call $proc_exit (i32.const 50))))
+    (if (call $fd_readdir (i32.load (i32.const 0)) (i32.const 64) (i32.const 32) (i64.const 0) (i32.const 4))
+      (then (call $proc_exit (i32.const 51))))
+    (if (i32.load (i32.const 4))
+      (then (call $proc_exit (i32.const 52))))
+    
+    ;; And it can't be moved out from under the mask.
+    (if (i32.ne
+          (call $path_rename (i32.const 4) (i32.const 160) (i32.const 7) (i32.const 4) (i32.const 176) (i32.const 6))
+          (i32.const 10))
+      (then (call $proc_exit (i32.const 60))))
+    
+    (call $proc_exit (i32.const 0)))
+)
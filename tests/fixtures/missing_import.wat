@@ -0,0 +1,7 @@
+;; Imports a function no runtime provides, so it can't be instantiated.
+(module
+  (import "env" "not_provided" (func $not_provided))
+  
+  (func (export "_start")
+    (call $not_provided))
+)
@@ -0,0 +1,22 @@
+;; Checks that a container without networking can neither listen nor
+;; connect, exiting with a distinct code for the first check that fails.
+(module
+  (import "env" "net_listen" (func $listen (param i32 i32) (result i32)))
+  (import "env" "net_connect" (func $connect (param i32 i32 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+  
+  (memory 1)
+  (export "memory" (memory 0))
+  
+  ;; 0: descriptor out
+  (data (i32.const 128) "127.0.0.1")
+  
+  (func $main (export "_start")
+    ;; Both fail with ENETUNREACH.
+    (if (i32.ne (call $listen (i32.const 8080) (i32.const 0)) (i32.const 40))
+      (then (call $proc_exit (i32.const 10))))
+    (if (i32.ne (call $connect (i32.const 128) (i32.const 9) (i32.const 8080) (i32.const 0)) (i32.const 40))
+      (then (call $proc_exit (i32.const 20))))
+    
+    (call $proc_exit (i32.const 0)))
+)
@@ -0,0 +1,38 @@
+;; Reaches an outside address through the container's proxy and exchanges a
+;; message with it, then checks that the egress policy and no_proxy still
+;; apply, exiting with a distinct code for the first step that fails.
+(module
+  (import "env" "net_connect" (func $connect (param i32 i32 i32 i32) (result i32)))
+  (import "env" "net_send" (func $send (param i32 i32 i32 i32) (result i32)))
+  (import "env" "net_recv" (func $recv (param i32 i32 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+  
+  (memory 1)
+  (export "memory" (memory 0))
+  
+  ;; 0: connection, 12: byte count, 64: buffer
+  (data (i32.const 128) "192.0.2.10")
+  (data (i32.const 144) "192.0.2.11")
+  (data (i32.const 160) "10.1.2.3")
+  (data (i32.const 176) "ping")
+  (data (i32.const 180) "pong")
+  
+  (func $main (export "_start")
+    (if (call $connect (i32.const 128) (i32.const 10) (i32.const 443) (i32.const 0))
+      (then (call $proc_exit (i32.const 10))))
+    (if (call $send (i32.load (i32.const 0)) (i32.const 176) (i32.const 4) (i32.const 12))
+      (then (call $proc_exit (i32.const 20))))
+    (if (call $recv (i32.load (i32.const 0)) (i32.const 64) (i32.const 32) (i32.const 12))
+      (then (call $proc_exit (i32.const 30))))
+    (if (i32.ne (i32.load (i32.const 64)) (i32.load (i32.const 180)))
+      (then (call $proc_exit (i32.const 31))))
+    
+    ;; Denied destinations are refused before the proxy is asked...
+    (if (i32.ne (call $connect (i32.const 144) (i32.const 10) (i32.const 443) (i32.const 0)) (i32.const 2))
+      (then (call $proc_exit (i32.const 40))))
+    ;; ...and ones in no_proxy stay unreachable.
+    (if (i32.ne (call $connect (i32.const 160) (i32.const 8) (i32.const 80) (i32.const 0)) (i32.const 23))
+      (then (call $proc_exit (i32.const 50))))
+    
+    (call $proc_exit (i32.const 0)))
+)
@@ -0,0 +1,21 @@
+;; Opens "in.txt" for reading in the first volume preopen (fd 4) and exits
+;; with the resulting WASI errno.
+(module
+  (import "wasi_snapshot_preview1" "path_open"
+    (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+  
+  (memory 1)
+  (export "memory" (memory 0))
+  
+  (data (i32.const 16) "in.txt")
+  
+  (func $main (export "_start")
+    ;; path_open(fd=4, dirflags=0, path, path_len, oflags=0,
+    ;;           rights=FD_READ, inheriting=0, fdflags=0, opened_fd=0)
+    (call $proc_exit
+      (call $path_open
+        (i32.const 4) (i32.const 0) (i32.const 16) (i32.const 6) (i32.const 0)
+        (i64.const 0x2) (i64.const 0) (i32.const 0) (i32.const 0)))
+  )
+)
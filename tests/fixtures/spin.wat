@@ -0,0 +1,6 @@
+;; Spins forever, until it is interrupted.
+(module
+  (func (export "_start")
+    (loop $spin
+      (br $spin)))
+)
@@ -0,0 +1,5 @@
+;; Traps as soon as it starts.
+(module
+  (func (export "_start")
+    unreachable)
+)
@@ -0,0 +1,100 @@
+use std::collections::BTreeMap;
+use wasm_container::container::{ContainerInfo, PortMapping};
+use wasm_container::format::schema::{ContainerSummary, ImageSummary};
+use wasm_container::format::{align_columns, format_age, format_timestamp, OutputFormat, Template};
+
+const FIELDS: &[&str] = &["ID", "Ports"];
+
+#[test]
+fn test_template_render() {
+    let template = Template::parse("{{.ID}}: {{ .Ports }}\\t!", FIELDS).unwrap();
+    assert!(!template.is_table());
+    assert_eq!(template.render(|field| field.to_lowercase()), "id: ports\t!");
+    
+    let table = Template::parse("table {{.ID}}\\t{{.Ports}}", FIELDS).unwrap();
+    assert!(table.is_table());
+    assert_eq!(table.render(|field| field.to_string()), "ID\tPorts");
+    assert!(!Template::parse("tables {{.ID}}", FIELDS).unwrap().is_table());
+    
+    assert!(Template::parse("{{.Image}}", FIELDS).unwrap_err().to_string().contains("Unknown field"));
+    assert!(Template::parse("{{ID}}", FIELDS).is_err());
+    assert!(Template::parse("{{.ID", FIELDS).is_err());
+}
+
+#[test]
+fn test_align_columns() {
+    let lines = vec![
+        "CONTAINER ID\tNAME\tPORTS".to_string(),
+        "abc\tweb-server\t".to_string(),
+        "defghi\tdb\t5432->5432/tcp".to_string(),
+    ];
+    assert_eq!(
+        align_columns(&lines),
+        "CONTAINER ID   NAME         PORTS\n\
+         abc            web-server\n\
+         defghi         db           5432->5432/tcp\n",
+    );
+}
+
+#[test]
+fn test_time_formatting() {
+    assert_eq!(format_timestamp(0), "1970-01-01 00:00:00 +0000 UTC");
+    assert_eq!(format_timestamp(951_782_400), "2000-02-29 00:00:00 +0000 UTC");
+    assert_eq!(format_timestamp(1_709_301_909), "2024-03-01 14:05:09 +0000 UTC");
+    
+    assert_eq!(format_age(0), "Less than a second");
+    assert_eq!(format_age(1), "1 second");
+    assert_eq!(format_age(90), "About a minute");
+    assert_eq!(format_age(300), "5 minutes");
+    assert_eq!(format_age(5400), "About an hour");
+    assert_eq!(format_age(3 * 86_400), "3 days");
+}
+
+#[test]
+fn test_output_format() {
+    assert_eq!("json".parse::<OutputFormat>().unwrap(), OutputFormat::Json);
+    assert_eq!(OutputFormat::parse("yaml"), Some(OutputFormat::Yaml));
+    assert_eq!(OutputFormat::parse("table {{.ID}}"), None);
+    assert!("xml".parse::<OutputFormat>().unwrap_err().to_string().contains("expected json or yaml"));
+    
+    let images = vec![ImageSummary::parse("localhost:5000/app:v1")];
+    assert_eq!(images[0].name, "localhost:5000/app");
+    assert_eq!(images[0].tag, "v1");
+    assert_eq!(
+        OutputFormat::Json.render(&images).unwrap(),
+        "[\n  {\n    \"reference\": \"localhost:5000/app:v1\",\n    \"name\": \"localhost:5000/app\",\n    \"tag\": \"v1\",\n    \"digest\": null,\n    \"platform\": null\n  }\n]\n",
+    );
+    assert_eq!(
+        OutputFormat::Yaml.render(&images).unwrap(),
+        "- reference: localhost:5000/app:v1\n  name: localhost:5000/app\n  tag: v1\n  digest: null\n  platform: null\n",
+    );
+}
+
+#[test]
+fn test_container_summary_keeps_every_field() {
+    let info = ContainerInfo {
+        id: "abc123".to_string(),
+        name: None,
+        image: "demo:latest".to_string(),
+        status: "exited".to_string(),
+        ports: vec![PortMapping { host_port: 8080, container_port: 80, protocol: "tcp".to_string() }],
+        addresses: BTreeMap::new(),
+        labels: BTreeMap::new(),
+        created_at: 1_709_301_909,
+        started_at: None,
+        exit_code: Some(1),
+    };
+    let summary = ContainerSummary::from(&info);
+    assert_eq!(summary.state, "exited");
+    assert_eq!(summary.status, "Exited (1)");
+    
+    // Unset fields are still there, so scripts can rely on the keys.
+    let json: serde_json::Value = serde_json::from_str(&OutputFormat::Json.render(&summary).unwrap()).unwrap();
+    assert!(json["name"].is_null());
+    assert!(json["started_at"].is_null());
+    assert_eq!(json["labels"], serde_json::json!({}));
+    assert_eq!(json["ports"][0]["host_port"], 8080);
+    
+    let yaml: ContainerSummary = serde_yaml::from_str(&OutputFormat::Yaml.render(&summary).unwrap()).unwrap();
+    assert_eq!(yaml, summary);
+}
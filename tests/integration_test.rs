@@ -1,16 +1,44 @@
-use wasm_container::runtime::WasmRuntime;
-use wasm_container::container::Container;
-use wasm_container::image::{ImageData, ImageConfig, Layer};
-use std::path::PathBuf;
-use std::collections::HashMap;
-use tokio_test;
+mod common;
+
+use common::test_image;
+use wasm_container::runtime::{exit_code, wasm_features, WasmRuntime, EXIT_NOT_FOUND, EXIT_NOT_INVOKABLE, EXIT_CANCELLED, EXIT_RUNTIME_ERROR, EXIT_TRAP, WASMTIME_VERSION};
+use wasm_container::runtime::cancel::CancellationToken;
+use wasm_container::runtime::events::ContainerEvent;
+use wasm_container::runtime::host::{guest_bytes, HostFunctions};
+use wasm_container::runtime::ContainerState;
+use wasm_container::config::RuntimeConfig;
+use wasm_container::error::Error;
+use wasm_container::container::{parse_label, Container, ContainerFilter, ContainerInfo, Cpus, PortMapping, Resources, RootfsBackend};
+use wasm_container::filesystem::diff::{Change, ChangeKind};
+use wasm_container::filesystem::driver::MemoryDriver;
+use wasm_container::image::{ImageData, ImageManager, Layer, Platform, PullPolicy};
+use wasm_container::image::source::{LocalSource, MemorySource, CONFIG_MEDIA_TYPE, LAYER_MEDIA_TYPE, MANIFEST_MEDIA_TYPE, WASM_MEDIA_TYPE};
+use wasm_container::network::NetworkManager;
+use wasm_container::network::policy::{EgressDefault, EgressPolicy};
+use wasm_container::network::proxy::ProxySettings;
+use wasm_container::state::{ContainerStore, RootfsRecord};
+use wasm_container::volume::VolumeManager;
+use wasm_container::state::store::{MemoryStateStore, StateBackend};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::collections::BTreeMap;
+use std::time::Duration;
+use sha2::{Digest, Sha256};
+use tempfile::TempDir;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio_stream::StreamExt;
+use bytes::Bytes;
+use wasmtime::Caller;
+use wasmtime_wasi::pipe::MemoryInputPipe;
 
 #[tokio::test]
 async fn test_basic_container_execution() {
-    let image_data = create_test_image();
+    let image_data = test_image(Some("src/image/demo.wasm"));
     let container = Container::new(image_data, None, None, vec![]).unwrap();
     
-    let mut runtime = WasmRuntime::new().unwrap();
+    let state = TempDir::new().unwrap();
+    let runtime = WasmRuntime::with_state_dir(state.path()).unwrap();
     
     let result = runtime.run(container).await;
     assert!(result.is_ok());
@@ -18,19 +46,38 @@ async fn test_basic_container_execution() {
 
 #[tokio::test]
 async fn test_container_with_env_vars() {
-    let image_data = create_test_image();
+    let image_data = test_image(Some("src/image/demo.wasm"));
     let env_vars = vec!["TEST_VAR=test_value".to_string()];
     let container = Container::new(image_data, None, None, env_vars).unwrap();
     
-    let mut runtime = WasmRuntime::new().unwrap();
+    let state = TempDir::new().unwrap();
+    let runtime = WasmRuntime::with_state_dir(state.path()).unwrap();
     
     let result = runtime.run(container).await;
     assert!(result.is_ok());
 }
 
+// What `run --rm` does once the run exits.
+#[tokio::test]
+async fn test_remove_after_run_leaves_no_state() {
+    let container = Container::new(test_image(Some("src/image/demo.wasm")), None, None, vec![]).unwrap();
+    let container_id = container.id().to_string();
+    let state = TempDir::new().unwrap();
+    let mut runtime = WasmRuntime::with_state_dir(state.path()).unwrap();
+    
+    runtime.run(container).await.unwrap();
+    assert!(!runtime.logs(&container_id, 10).await.unwrap().is_empty());
+    runtime.remove(&container_id, true).await.unwrap();
+    
+    assert!(runtime.inspect(&container_id).await.is_err());
+    assert!(runtime.list_containers(true).await.unwrap().is_empty());
+    assert!(!state.path().join("containers").join(&container_id).exists());
+}
+
 #[tokio::test]
 async fn test_container_listing() {
-    let mut runtime = WasmRuntime::new().unwrap();
+    let state = TempDir::new().unwrap();
+    let runtime = WasmRuntime::with_state_dir(state.path()).unwrap();
     
     let containers = runtime.list_containers(false).await.unwrap();
     assert_eq!(containers.len(), 0);
@@ -41,30 +88,1594 @@ async fn test_container_listing() {
 
 #[tokio::test]
 async fn test_container_stop() {
-    let mut runtime = WasmRuntime::new().unwrap();
+    let state = TempDir::new().unwrap();
+    let mut runtime = WasmRuntime::with_state_dir(state.path()).unwrap();
     
     let result = runtime.stop("nonexistent-id").await;
     assert!(result.is_ok());
 }
 
-fn create_test_image() -> ImageData {
-    ImageData {
-        name: "test-image".to_string(),
-        tag: "latest".to_string(),
-        layers: vec![Layer {
-            digest: "sha256:test".to_string(),
-            size: 1024,
-            media_type: "application/vnd.oci.image.layer.v1.tar+gzip".to_string(),
-            path: PathBuf::from("/tmp/test-layer.tar.gz"),
-        }],
-        config: ImageConfig {
-            env: vec!["PATH=/usr/bin".to_string()],
-            cmd: vec!["/bin/sh".to_string()],
-            entrypoint: vec![],
-            workdir: "/".to_string(),
-            exposed_ports: HashMap::new(),
-            volumes: HashMap::new(),
-        },
-        wasm_path: Some(PathBuf::from("src/image/demo.wasm")),
-    }
-}
\ No newline at end of file
+#[tokio::test]
+async fn test_read_only_volume_rejects_writes() {
+    let host_dir = TempDir::new().unwrap();
+    
+    let mut container = Container::new(create_fixture_image("create_file.wat"), None, None, vec![]).unwrap();
+    container.add_volume(host_dir.path().to_path_buf(), PathBuf::from("/data"), true);
+    
+    let state = TempDir::new().unwrap();
+    let runtime = WasmRuntime::with_state_dir(state.path()).unwrap();
+    
+    let result = runtime.run(container).await;
+    assert!(result.is_err());
+    assert!(!host_dir.path().join("out.txt").exists());
+}
+
+#[tokio::test]
+async fn test_read_only_volume_allows_reads() {
+    let host_dir = TempDir::new().unwrap();
+    std::fs::write(host_dir.path().join("in.txt"), "input").unwrap();
+    
+    let mut container = Container::new(create_fixture_image("read_file.wat"), None, None, vec![]).unwrap();
+    container.add_volume(host_dir.path().to_path_buf(), PathBuf::from("/data"), true);
+    
+    let state = TempDir::new().unwrap();
+    let runtime = WasmRuntime::with_state_dir(state.path()).unwrap();
+    
+    let result = runtime.run(container).await;
+    assert!(result.is_ok());
+}
+
+#[tokio::test]
+async fn test_volume_writes_reach_host() {
+    let host_dir = TempDir::new().unwrap();
+    
+    let mut container = Container::new(create_fixture_image("create_file.wat"), None, None, vec![]).unwrap();
+    container.add_volume(host_dir.path().to_path_buf(), PathBuf::from("/data"), false);
+    
+    let state = TempDir::new().unwrap();
+    let runtime = WasmRuntime::with_state_dir(state.path()).unwrap();
+    
+    let result = runtime.run(container).await;
+    assert!(result.is_ok());
+    assert_eq!(std::fs::read_to_string(host_dir.path().join("out.txt")).unwrap(), "hello");
+}
+
+#[tokio::test]
+async fn test_read_only_rootfs_rejects_writes() {
+    let mut container = Container::new(create_fixture_image("create_rootfs_file.wat"), None, None, vec![]).unwrap();
+    container.set_read_only(true);
+    let container_id = container.id().to_string();
+    
+    let state = TempDir::new().unwrap();
+    let runtime = WasmRuntime::with_state_dir(state.path()).unwrap();
+    
+    assert!(runtime.run(container).await.is_err());
+    let rootfs = state.path().join("containers").join(&container_id).join("rootfs");
+    assert!(!rootfs.join("out.txt").exists());
+}
+
+#[tokio::test]
+async fn test_read_only_rootfs_allows_volume_and_tmpfs_writes() {
+    let host_dir = TempDir::new().unwrap();
+    let state = TempDir::new().unwrap();
+    let scratch = TempDir::new().unwrap();
+    let runtime = WasmRuntime::with_config(&RuntimeConfig {
+        state_dir: Some(state.path().to_path_buf()),
+        scratch_dir: Some(scratch.path().to_path_buf()),
+        ..RuntimeConfig::default()
+    }).unwrap();
+    
+    let mut container = Container::new(create_fixture_image("create_file.wat"), None, None, vec![]).unwrap();
+    container.set_read_only(true);
+    container.add_volume(host_dir.path().to_path_buf(), PathBuf::from("/data"), false);
+    runtime.run(container).await.unwrap();
+    assert_eq!(std::fs::read_to_string(host_dir.path().join("out.txt")).unwrap(), "hello");
+    
+    let mut container = Container::new(create_fixture_image("create_file.wat"), None, None, vec![]).unwrap();
+    container.set_read_only(true);
+    container.add_tmpfs(PathBuf::from("/tmp"));
+    let container_id = container.id().to_string();
+    runtime.run(container).await.unwrap();
+    
+    let rootfs = state.path().join("containers").join(&container_id).join("rootfs");
+    assert!(!rootfs.join("tmp/out.txt").exists());
+    assert_eq!(std::fs::read_dir(scratch.path()).unwrap().count(), 0);
+}
+
+#[tokio::test]
+async fn test_dev_devices_have_device_semantics() {
+    let container = Container::new(create_fixture_image("devices.wat"), None, None, vec![]).unwrap();
+    
+    let state = TempDir::new().unwrap();
+    let runtime = WasmRuntime::with_state_dir(state.path()).unwrap();
+    
+    if let Err(e) = runtime.run(container).await {
+        panic!("device checks failed: {:?}", e);
+    }
+}
+
+#[tokio::test]
+async fn test_masked_paths_are_hidden_through_every_preopen() {
+    let state = TempDir::new().unwrap();
+    let runtime = WasmRuntime::with_config(&RuntimeConfig {
+        state_dir: Some(state.path().to_path_buf()),
+        masked_paths: vec![PathBuf::from("/etc/secret.conf")],
+        ..RuntimeConfig::default()
+    }).unwrap();
+    
+    let data = TempDir::new().unwrap();
+    std::fs::create_dir(data.path().join("secrets")).unwrap();
+    std::fs::write(data.path().join("secrets/key.txt"), "hunter2").unwrap();
+    std::fs::write(data.path().join("public.txt"), "hello").unwrap();
+    
+    let mut image = create_fixture_image("masks.wat");
+    image.layers = vec![write_layer(state.path(), "etc/secret.conf", "token=abc")];
+    let mut container = Container::new(image, None, None, vec![]).unwrap();
+    let rootfs = state.path().join("containers").join(container.id()).join("rootfs");
+    std::fs::create_dir_all(&rootfs).unwrap();
+    container.add_volume(data.path().to_path_buf(), PathBuf::from("/data"), false);
+    container.add_volume(rootfs, PathBuf::from("/host"), true);
+    container.add_masked_path(PathBuf::from("/data/secrets"));
+    
+    if let Err(e) = runtime.run(container).await {
+        panic!("mask checks failed: {:?}", e);
+    }
+    assert!(data.path().join("secrets/key.txt").exists());
+}
+
+#[tokio::test]
+async fn test_guest_connects_to_own_listener_over_bridge() {
+    let state = TempDir::new().unwrap();
+    let runtime = WasmRuntime::with_state_dir(state.path()).unwrap();
+    let container = Container::new(create_fixture_image("bridge_loopback.wat"), None, None, vec![]).unwrap();
+    
+    if let Err(e) = runtime.run(container).await {
+        panic!("bridge checks failed: {:?}", e);
+    }
+}
+
+#[tokio::test]
+async fn test_containers_without_networking() {
+    let state = TempDir::new().unwrap();
+    let runtime = WasmRuntime::with_state_dir(state.path()).unwrap();
+    let mut container = Container::new(create_fixture_image("no_network.wat"), None, None, vec![]).unwrap();
+    container.set_networks(vec!["none".to_string()]);
+    let container_id = container.id().to_string();
+    
+    if let Err(e) = runtime.run(container).await {
+        panic!("network checks failed: {:?}", e);
+    }
+    let proc = state.path().join("containers").join(&container_id).join("rootfs/proc");
+    let environ = std::fs::read(proc.join("self/environ")).unwrap();
+    assert!(!environ.split(|b| *b == 0).any(|var| var.starts_with(b"CONTAINER_IP")));
+    assert!(environ.split(|b| *b == 0).any(|var| var.starts_with(b"HOSTNAME=")));
+    assert!(runtime.connect_network("bridge", &container_id).await.is_err());
+    
+    // Nothing can be published without a network.
+    let mut container = Container::new(create_fixture_image("no_network.wat"), None, None, vec![]).unwrap();
+    container.set_networks(vec!["none".to_string()]);
+    container.add_port_mapping(0, 8080, "tcp".to_string());
+    assert!(runtime.run(container).await.is_err());
+}
+
+#[tokio::test]
+async fn test_egress_policy_blocks_guest_connections() {
+    let state = TempDir::new().unwrap();
+    let runtime = WasmRuntime::with_state_dir(state.path()).unwrap();
+    let mut container = Container::new(create_fixture_image("egress_denied.wat"), None, None, vec![]).unwrap();
+    container.set_egress_policy(EgressPolicy {
+        default: EgressDefault::Deny,
+        allow: vec!["10.0.0.8".parse().unwrap()],
+        deny: vec![],
+    });
+    
+    if let Err(e) = runtime.run(container).await {
+        panic!("egress checks failed: {:?}", e);
+    }
+}
+
+#[tokio::test]
+async fn test_guest_connections_go_through_the_proxy() {
+    // A proxy that opens one tunnel, answering the guest's ping itself.
+    let proxy = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy_addr = proxy.local_addr().unwrap();
+    let tunnel = tokio::spawn(async move {
+        let (mut stream, _) = proxy.accept().await.unwrap();
+        let mut request = Vec::new();
+        while !request.ends_with(b"\r\n\r\n") {
+            request.push(stream.read_u8().await.unwrap());
+        }
+        stream.write_all(b"HTTP/1.1 200 Connection established\r\n\r\n").await.unwrap();
+        let mut ping = [0u8; 4];
+        stream.read_exact(&mut ping).await.unwrap();
+        stream.write_all(b"pong").await.unwrap();
+        (String::from_utf8(request).unwrap(), ping)
+    });
+    
+    let state = TempDir::new().unwrap();
+    let runtime = WasmRuntime::with_state_dir(state.path()).unwrap();
+    let mut container = Container::new(create_fixture_image("proxy_connect.wat"), None, None, vec![]).unwrap();
+    container.set_proxy(ProxySettings {
+        https_proxy: Some(format!("http://{}", proxy_addr)),
+        no_proxy: Some("localhost,10.0.0.0/8".to_string()),
+        ..ProxySettings::default()
+    });
+    container.set_egress_policy(EgressPolicy {
+        deny: vec!["192.0.2.11".parse().unwrap()],
+        ..EgressPolicy::default()
+    });
+    
+    if let Err(e) = runtime.run(container).await {
+        panic!("proxy checks failed: {:?}", e);
+    }
+    let (request, ping) = tunnel.await.unwrap();
+    assert!(request.starts_with("CONNECT 192.0.2.10:443 HTTP/1.1\r\n"), "{}", request);
+    assert_eq!(&ping, b"ping");
+}
+
+#[tokio::test]
+async fn test_single_file_volume_stays_in_sync() {
+    let host_dir = TempDir::new().unwrap();
+    let output = host_dir.path().join("out.txt");
+    let config = host_dir.path().join("config.yaml");
+    std::fs::write(&output, "initial contents").unwrap();
+    std::fs::write(&config, "debug: true").unwrap();
+    
+    let mut container = Container::new(create_fixture_image("create_rootfs_file.wat"), None, None, vec![]).unwrap();
+    container.add_volume(output.clone(), PathBuf::from("/out.txt"), false);
+    container.add_volume(config.clone(), PathBuf::from("/etc/app/config.yaml"), true);
+    let container_id = container.id().to_string();
+    
+    let state = TempDir::new().unwrap();
+    let runtime = WasmRuntime::with_state_dir(state.path()).unwrap();
+    runtime.run(container).await.unwrap();
+    
+    // The guest overwrote the start of the mounted file in place.
+    assert_eq!(std::fs::read_to_string(&output).unwrap(), "helloal contents");
+    
+    let rootfs = state.path().join("containers").join(&container_id).join("rootfs");
+    assert_eq!(std::fs::read_to_string(rootfs.join("etc/app/config.yaml")).unwrap(), "debug: true");
+}
+
+#[tokio::test]
+async fn test_fs_change_callback_reports_guest_writes() {
+    let container = Container::new(create_fixture_image("create_rootfs_file.wat"), None, None, vec![]).unwrap();
+    
+    let state = TempDir::new().unwrap();
+    let mut runtime = WasmRuntime::with_state_dir(state.path()).unwrap();
+    
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let recorder = seen.clone();
+    runtime.on_fs_change(move |change| recorder.lock().unwrap().push(change.clone()));
+    
+    runtime.run(container).await.unwrap();
+    
+    let seen = seen.lock().unwrap();
+    assert!(seen.contains(&Change { kind: ChangeKind::Added, path: "/out.txt".to_string() }));
+    assert!(seen.iter().all(|change| !change.path.starts_with("/proc")));
+}
+
+#[tokio::test]
+async fn test_rootfs_persists_across_restart() {
+    let state = TempDir::new().unwrap();
+    let mut runtime = WasmRuntime::with_state_dir(state.path()).unwrap();
+    
+    let container = Container::new(test_image(Some("src/image/demo.wasm")), None, None, vec![]).unwrap();
+    let container_id = container.id().to_string();
+    runtime.run(container).await.unwrap();
+    
+    let rootfs = state.path().join("containers").join(&container_id).join("rootfs");
+    std::fs::write(rootfs.join("tmp").join("marker"), "kept").unwrap();
+    
+    runtime.stop(&container_id).await.unwrap();
+    runtime.start(&container_id).await.unwrap();
+    
+    assert_eq!(std::fs::read_to_string(rootfs.join("tmp").join("marker")).unwrap(), "kept");
+    
+    let containers = runtime.list_containers(true).await.unwrap();
+    assert_eq!(containers.len(), 1);
+    assert_eq!(containers[0].status, "exited");
+    
+    runtime.remove(&container_id, false).await.unwrap();
+    assert!(!rootfs.exists());
+    assert!(runtime.list_containers(true).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_snapshot_restore_rolls_rootfs_back() {
+    let state = TempDir::new().unwrap();
+    let runtime = WasmRuntime::with_state_dir(state.path()).unwrap();
+    
+    let container = Container::new(test_image(Some("src/image/demo.wasm")), None, None, vec![]).unwrap();
+    let container_id = container.id().to_string();
+    runtime.run(container).await.unwrap();
+    
+    let rootfs = state.path().join("containers").join(&container_id).join("rootfs");
+    std::fs::write(rootfs.join("tmp").join("warm"), "cache").unwrap();
+    std::os::unix::fs::symlink("warm", rootfs.join("tmp").join("link")).unwrap();
+    runtime.create_snapshot(&container_id, "warmed").await.unwrap();
+    assert!(runtime.create_snapshot(&container_id, "warmed").await.is_err());
+    assert!(runtime.create_snapshot(&container_id, "../escape").await.is_err());
+    
+    std::fs::write(rootfs.join("tmp").join("warm"), "dirty").unwrap();
+    std::fs::write(rootfs.join("tmp").join("leftover"), "from a test case").unwrap();
+    
+    runtime.restore_snapshot(&container_id, "warmed").await.unwrap();
+    
+    assert_eq!(std::fs::read_to_string(rootfs.join("tmp").join("warm")).unwrap(), "cache");
+    assert_eq!(std::fs::read_link(rootfs.join("tmp").join("link")).unwrap(), PathBuf::from("warm"));
+    assert!(!rootfs.join("tmp").join("leftover").exists());
+    assert_eq!(runtime.list_snapshots(&container_id).await.unwrap(), vec!["warmed".to_string()]);
+    assert!(runtime.restore_snapshot(&container_id, "missing").await.is_err());
+}
+
+#[tokio::test]
+async fn test_snapshots_need_a_persistent_rootfs() {
+    let state = TempDir::new().unwrap();
+    let scratch = TempDir::new().unwrap();
+    let runtime = WasmRuntime::with_config(&RuntimeConfig {
+        state_dir: Some(state.path().to_path_buf()),
+        scratch_dir: Some(scratch.path().to_path_buf()),
+        ..RuntimeConfig::default()
+    }).unwrap();
+    
+    let mut container = Container::new(test_image(Some("src/image/demo.wasm")), None, None, vec![]).unwrap();
+    container.set_rootfs_backend(RootfsBackend::Memory);
+    let container_id = container.id().to_string();
+    runtime.run(container).await.unwrap();
+    
+    let error = runtime.create_snapshot(&container_id, "warmed").await.unwrap_err();
+    assert!(error.to_string().contains("not kept between runs"));
+}
+
+#[tokio::test]
+async fn test_diff_reports_rootfs_changes() {
+    let state = TempDir::new().unwrap();
+    let runtime = WasmRuntime::with_state_dir(state.path()).unwrap();
+    
+    let container = Container::new(test_image(Some("src/image/demo.wasm")), None, None, vec![]).unwrap();
+    let container_id = container.id().to_string();
+    runtime.run(container).await.unwrap();
+    
+    assert!(runtime.diff(&container_id).await.unwrap().is_empty());
+    
+    let rootfs = state.path().join("containers").join(&container_id).join("rootfs");
+    std::fs::write(rootfs.join("tmp").join("added.txt"), "new").unwrap();
+    std::fs::write(rootfs.join("etc").join("resolv.conf"), "nameserver 1.1.1.1\n").unwrap();
+    std::fs::remove_dir(rootfs.join("srv")).unwrap();
+    
+    let changes: Vec<String> = runtime
+        .diff(&container_id)
+        .await
+        .unwrap()
+        .iter()
+        .map(|c| c.to_string())
+        .collect();
+    
+    assert!(changes.contains(&"A /tmp/added.txt".to_string()));
+    assert!(changes.contains(&"C /etc/resolv.conf".to_string()));
+    assert!(changes.contains(&"D /srv".to_string()));
+}
+
+#[tokio::test]
+async fn test_export_archives_rootfs() {
+    let state = TempDir::new().unwrap();
+    let runtime = WasmRuntime::with_state_dir(state.path()).unwrap();
+    
+    let container = Container::new(test_image(Some("src/image/demo.wasm")), None, None, vec![]).unwrap();
+    let container_id = container.id().to_string();
+    runtime.run(container).await.unwrap();
+    
+    let archive = runtime.export(&container_id, Vec::new()).await.unwrap();
+    
+    let mut paths = Vec::new();
+    for entry in tar::Archive::new(archive.as_slice()).entries().unwrap() {
+        paths.push(entry.unwrap().path().unwrap().to_string_lossy().into_owned());
+    }
+    
+    assert!(paths.iter().any(|p| p.trim_start_matches("./") == "etc/hostname"));
+    assert!(paths.iter().any(|p| p.trim_start_matches("./") == "proc/cpuinfo"));
+    assert!(runtime.export("missing", Vec::new()).await.is_err());
+}
+
+#[tokio::test]
+async fn test_disk_backend_applies_image_layers() {
+    let state = TempDir::new().unwrap();
+    let runtime = WasmRuntime::with_state_dir(state.path()).unwrap();
+    
+    let mut image = test_image(Some("src/image/demo.wasm"));
+    image.layers = vec![write_layer(state.path(), "app/config.txt", "from-layer")];
+    
+    let container = Container::new(image, None, None, vec![]).unwrap();
+    let container_id = container.id().to_string();
+    runtime.run(container).await.unwrap();
+    
+    // Read back through the runtime, which mounts the rootfs if its driver
+    // released it when the container exited.
+    let copied = state.path().join("config.txt");
+    runtime.copy_from_container(&container_id, Path::new("/app/config.txt"), &copied).await.unwrap();
+    assert_eq!(std::fs::read_to_string(copied).unwrap(), "from-layer");
+}
+
+#[tokio::test]
+async fn test_layers_are_extracted_once_per_digest() {
+    let state = TempDir::new().unwrap();
+    let runtime = WasmRuntime::with_state_dir(state.path()).unwrap();
+    
+    let mut image = test_image(Some("src/image/demo.wasm"));
+    image.layers = vec![write_layer(state.path(), "app/config.txt", "from-layer")];
+    let layer_blob = image.layers[0].path.clone();
+    
+    let first = Container::new(image.clone(), None, None, vec![]).unwrap();
+    runtime.run(first).await.unwrap();
+    
+    // The second container must be served from the extracted cache.
+    std::fs::remove_file(&layer_blob).unwrap();
+    
+    let second = Container::new(image, None, None, vec![]).unwrap();
+    let second_id = second.id().to_string();
+    runtime.run(second).await.unwrap();
+    
+    let copied = state.path().join("config.txt");
+    runtime.copy_from_container(&second_id, Path::new("/app/config.txt"), &copied).await.unwrap();
+    assert_eq!(std::fs::read_to_string(copied).unwrap(), "from-layer");
+    assert!(state.path().join("layers/sha256/test/app/config.txt").exists());
+}
+
+#[tokio::test]
+async fn test_memory_backend_leaves_no_rootfs_behind() {
+    let state = TempDir::new().unwrap();
+    let scratch = TempDir::new().unwrap();
+    let runtime = WasmRuntime::with_config(&RuntimeConfig {
+        state_dir: Some(state.path().to_path_buf()),
+        scratch_dir: Some(scratch.path().join("rootfs")),
+        ..RuntimeConfig::default()
+    }).unwrap();
+    
+    let mut image = test_image(Some("src/image/demo.wasm"));
+    image.layers = vec![write_layer(state.path(), "app/config.txt", "from-layer")];
+    
+    let mut container = Container::new(image, None, None, vec![]).unwrap();
+    container.set_rootfs_backend(RootfsBackend::Memory);
+    let container_id = container.id().to_string();
+    runtime.run(container).await.unwrap();
+    
+    assert!(!state.path().join("containers").join(&container_id).join("rootfs").exists());
+    assert!(scratch.path().join("rootfs").is_dir());
+    assert!(!scratch.path().join("rootfs").join(&container_id).exists());
+    assert_eq!(runtime.list_containers(true).await.unwrap()[0].status, "exited");
+}
+
+#[tokio::test]
+async fn test_memory_backend_respects_scratch_budget() {
+    let state = TempDir::new().unwrap();
+    let scratch = TempDir::new().unwrap();
+    let runtime = WasmRuntime::with_config(&RuntimeConfig {
+        state_dir: Some(state.path().to_path_buf()),
+        scratch_dir: Some(scratch.path().to_path_buf()),
+        scratch_size: Some(4),
+        ..RuntimeConfig::default()
+    }).unwrap();
+    
+    let mut image = test_image(Some("src/image/demo.wasm"));
+    image.layers = vec![write_layer(state.path(), "app/config.txt", "too large")];
+    
+    let mut container = Container::new(image, None, None, vec![]).unwrap();
+    container.set_rootfs_backend(RootfsBackend::Memory);
+    let container_id = container.id().to_string();
+    
+    let error = runtime.run(container).await.unwrap_err();
+    assert!(error.to_string().contains("scratch budget"));
+    assert!(!scratch.path().join(&container_id).exists());
+}
+
+fn write_layer(dir: &std::path::Path, path: &str, contents: &str) -> Layer {
+    let layer_path = dir.join("layer.tar.gz");
+    let file = std::fs::File::create(&layer_path).unwrap();
+    let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(file, flate2::Compression::default()));
+    
+    let mut header = tar::Header::new_gnu();
+    header.set_size(contents.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    builder.append_data(&mut header, path, contents.as_bytes()).unwrap();
+    builder.into_inner().unwrap().finish().unwrap();
+    
+    Layer {
+        digest: "sha256:test".to_string(),
+        size: contents.len() as u64,
+        media_type: "application/vnd.oci.image.layer.v1.tar+gzip".to_string(),
+        path: layer_path,
+        diff_id: None,
+    }
+}
+
+#[tokio::test]
+async fn test_proc_reflects_container() {
+    let state = TempDir::new().unwrap();
+    let runtime = WasmRuntime::with_state_dir(state.path()).unwrap();
+    
+    let command = Some(vec!["app".to_string(), "--flag".to_string()]);
+    let env_vars = vec!["TEST_VAR=test_value".to_string()];
+    let container = Container::new(test_image(Some("src/image/demo.wasm")), command, None, env_vars).unwrap();
+    let container_id = container.id().to_string();
+    runtime.run(container).await.unwrap();
+    
+    let proc = state.path().join("containers").join(&container_id).join("rootfs/proc");
+    
+    let environ = std::fs::read(proc.join("self/environ")).unwrap();
+    assert!(environ.split(|b| *b == 0).any(|var| var == b"TEST_VAR=test_value"));
+    assert_eq!(std::fs::read(proc.join("self/cmdline")).unwrap(), b"app\0--flag\0");
+    
+    let cpuinfo = std::fs::read_to_string(proc.join("cpuinfo")).unwrap();
+    assert!(cpuinfo.contains("processor\t: 0"));
+    
+    // The demo module declares a single 64 KiB page of linear memory.
+    let meminfo = std::fs::read_to_string(proc.join("meminfo")).unwrap();
+    assert!(meminfo.contains("MemTotal:        4194304 kB"));
+    assert!(meminfo.contains("MemFree:         4194240 kB"));
+    
+    let status = std::fs::read_to_string(proc.join("self/status")).unwrap();
+    assert!(status.contains("Name:\tapp"));
+    assert!(std::fs::read_to_string(proc.join("uptime")).unwrap().ends_with(" 0.00\n"));
+}
+
+#[tokio::test]
+async fn test_peers_are_listed_in_hosts_file() {
+    let manager = NetworkManager::new();
+    let dir = TempDir::new().unwrap();
+    
+    let web = Container::new(test_image(Some("src/image/demo.wasm")), None, None, vec![]).unwrap();
+    let db = Container::new(test_image(Some("src/image/demo.wasm")), None, None, vec![]).unwrap();
+    let web_net = manager.setup_container_network(&web).await.unwrap();
+    let db_net = manager.setup_container_network(&db).await.unwrap();
+    
+    let web_hosts = dir.path().join("web-hosts");
+    let db_hosts = dir.path().join("db-hosts");
+    manager.attach_hosts_file(web.id(), web_hosts.clone()).await.unwrap();
+    manager.attach_hosts_file(db.id(), db_hosts.clone()).await.unwrap();
+    
+    let db_entry = format!("{}\t{}\n", db_net.get_ip(), db.id());
+    let web_entry = format!("{}\t{}\n", web_net.get_ip(), web.id());
+    let hosts = std::fs::read_to_string(&web_hosts).unwrap();
+    assert!(hosts.contains(&web_entry), "{}", hosts);
+    assert!(hosts.contains(&db_entry), "{}", hosts);
+    assert!(std::fs::read_to_string(&db_hosts).unwrap().contains(&web_entry));
+    
+    manager.cleanup_container_network(db.id()).await.unwrap();
+    let hosts = std::fs::read_to_string(&web_hosts).unwrap();
+    assert!(!hosts.contains(db.id()), "{}", hosts);
+    assert!(hosts.contains("localhost"));
+}
+
+#[tokio::test]
+async fn test_port_conflicts_name_the_container() {
+    let state = TempDir::new().unwrap();
+    let runtime = WasmRuntime::with_state_dir(state.path()).unwrap();
+    let host_port = std::net::TcpListener::bind("0.0.0.0:0").unwrap().local_addr().unwrap().port();
+    
+    // Stands in for a container another process runs with the port.
+    let web = Container::new(test_image(Some("src/image/demo.wasm")), None, None, vec![]).unwrap();
+    let web_id = web.id().to_string();
+    runtime.run(web).await.unwrap();
+    let store = ContainerStore::new(state.path()).unwrap();
+    let mut info = store.load_info(&web_id).unwrap();
+    info.status = "running".to_string();
+    info.ports.push(PortMapping {
+        host_port,
+        container_port: 80,
+        protocol: "tcp".to_string(),
+    });
+    store.save_info(&info).unwrap();
+    
+    let api = |retry| {
+        let mut container = Container::new(test_image(Some("src/image/demo.wasm")), None, None, vec![]).unwrap();
+        container.add_port_mapping(host_port, 8080, "tcp".to_string());
+        container.set_publish_retry(retry);
+        container
+    };
+    let error = runtime.run(api(false)).await.unwrap_err().to_string();
+    assert!(error.contains(&format!("Host port {}/tcp is already published by container {}", host_port, web_id)), "{}", error);
+    assert!(error.contains("--publish-retry"), "{}", error);
+    runtime.run(api(true)).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_containers_connect_to_user_defined_networks() {
+    let state = TempDir::new().unwrap();
+    let mut runtime = WasmRuntime::with_state_dir(state.path()).unwrap();
+    runtime.network_manager().create_network("backend", &["10.5.0.0/24"], &[], None).await.unwrap();
+    
+    let container = Container::new(test_image(Some("src/image/demo.wasm")), None, None, vec![]).unwrap();
+    let container_id = container.id().to_string();
+    runtime.run(container).await.unwrap();
+    
+    runtime.connect_network("backend", &container_id).await.unwrap();
+    assert!(runtime.connect_network("backend", &container_id).await.is_err());
+    assert!(runtime.connect_network("missing", &container_id).await.is_err());
+    let members = runtime.inspect_network("backend").await.unwrap().members;
+    assert_eq!(members.len(), 1);
+    assert_eq!((members[0].id.as_str(), members[0].name.as_str()), (container_id.as_str(), container_id.as_str()));
+    assert!(members[0].addresses.is_empty());
+    assert!(runtime.remove_network("backend").await.is_err());
+    
+    // Addresses recorded by the process running a container are shown,
+    // and given up when it stops.
+    let store = ContainerStore::new(state.path()).unwrap();
+    let mut info = store.load_info(&container_id).unwrap();
+    info.addresses.insert("backend".to_string(), vec!["10.5.0.2".parse().unwrap()]);
+    store.save_info(&info).unwrap();
+    let members = runtime.inspect_network("backend").await.unwrap().members;
+    assert_eq!(members[0].addresses, vec!["10.5.0.2".parse::<std::net::IpAddr>().unwrap()]);
+    
+    // The container joins the network from its next start on.
+    runtime.start(&container_id).await.unwrap();
+    assert!(store.load_info(&container_id).unwrap().addresses.is_empty());
+    assert!(runtime.inspect_network("backend").await.unwrap().members[0].addresses.is_empty());
+    
+    runtime.disconnect_network("backend", &container_id).await.unwrap();
+    assert!(runtime.disconnect_network("bridge", &container_id).await.is_err());
+    runtime.remove_network("backend").await.unwrap();
+    
+    // Networks outlive the runtime that created them.
+    let runtime = WasmRuntime::with_state_dir(state.path()).unwrap();
+    runtime.network_manager().create_network("frontend", &[], &[], None).await.unwrap();
+    let runtime = WasmRuntime::with_state_dir(state.path()).unwrap();
+    let names: Vec<String> = runtime.network_manager().list_networks().await.unwrap()
+        .into_iter()
+        .map(|network| network.name)
+        .collect();
+    assert_eq!(names, vec!["bridge", "frontend"]);
+}
+
+#[tokio::test]
+async fn test_leftovers_of_dead_runs_are_pruned() {
+    let state = TempDir::new().unwrap();
+    let scratch = TempDir::new().unwrap();
+    let config = RuntimeConfig {
+        state_dir: Some(state.path().to_path_buf()),
+        scratch_dir: Some(scratch.path().to_path_buf()),
+        ..RuntimeConfig::default()
+    };
+    
+    // A run whose process died before it could clean up after itself.
+    let store = ContainerStore::new(state.path()).unwrap();
+    let mut container = Container::new(test_image(Some("src/image/demo.wasm")), None, None, vec![]).unwrap();
+    container.set_rootfs_backend(RootfsBackend::Memory);
+    let id = container.id().to_string();
+    store.save(&container, &ContainerInfo {
+        id: id.clone(),
+        name: None,
+        image: "test-image".to_string(),
+        status: "running".to_string(),
+        ports: vec![],
+        addresses: BTreeMap::new(),
+        labels: BTreeMap::new(),
+        created_at: 0,
+        started_at: None,
+        exit_code: None,
+    }).unwrap();
+    let rootfs = scratch.path().join(&id);
+    let tmpfs = scratch.path().join(format!("{}-tmpfs", id));
+    std::fs::create_dir_all(rootfs.join("etc")).unwrap();
+    std::fs::create_dir_all(&tmpfs).unwrap();
+    store.track_rootfs(&id, &RootfsRecord {
+        rootfs: rootfs.clone(),
+        tmpfs: tmpfs.clone(),
+        pid: u32::MAX,
+        persistent: false,
+    }).unwrap();
+    
+    let incomplete = state.path().join("containers/half-created");
+    let staging = state.path().join("layers/sha256/.tmp-interrupted");
+    std::fs::create_dir_all(&incomplete).unwrap();
+    std::fs::create_dir_all(&staging).unwrap();
+    
+    // Dead runs are reconciled as soon as a runtime starts up.
+    let runtime = WasmRuntime::with_config(&config).unwrap();
+    assert!(!rootfs.exists());
+    assert!(!tmpfs.exists());
+    assert!(store.tracked_rootfs(&id).unwrap().is_none());
+    assert_eq!(store.load_info(&id).unwrap().status, "failed");
+    
+    // Other leftovers may still be in use until they age out.
+    assert!(incomplete.exists());
+    assert!(staging.exists());
+    
+    let report = runtime.prune_orphans(Duration::ZERO).unwrap();
+    assert!(!incomplete.exists());
+    assert!(!staging.exists());
+    assert_eq!(report.removed.len(), 2);
+    assert!(store.load(&id).is_ok());
+}
+
+#[tokio::test]
+async fn test_failed_runs_map_to_docker_exit_codes() {
+    let state = TempDir::new().unwrap();
+    let runtime = WasmRuntime::with_state_dir(state.path()).unwrap();
+    let run = async |fixture: &str, export: Option<&str>| {
+        let mut container = Container::new(create_fixture_image(fixture), Some(vec![]), None, vec![]).unwrap();
+        if let Some(export) = export {
+            container.set_export(export);
+        }
+        runtime.run(container).await.unwrap_err().exit_code()
+    };
+    
+    // Without its argument the fixture exits with code 6.
+    assert_eq!(run("host_connect.wat", None).await, 6);
+    assert_eq!(run("missing_import.wat", None).await, EXIT_NOT_INVOKABLE);
+    assert_eq!(run("exports.wat", Some("missing")).await, EXIT_NOT_FOUND);
+    assert_eq!(run("trap.wat", None).await, EXIT_TRAP);
+    assert_eq!(exit_code(&anyhow::anyhow!("No such container: web")), EXIT_RUNTIME_ERROR);
+}
+
+#[tokio::test]
+async fn test_typed_errors() {
+    let state = TempDir::new().unwrap();
+    let mut runtime = WasmRuntime::with_state_dir(state.path()).unwrap();
+    assert!(matches!(runtime.inspect("missing").await.unwrap_err(), Error::ContainerNotFound(id) if id == "missing"));
+    
+    let images = ImageManager::with_cache_dir(state.path().join("images")).unwrap();
+    let error = images.get("myapp:1.0", PullPolicy::Never).await.unwrap_err();
+    assert!(matches!(error, Error::ImageNotFound { image, .. } if image == "myapp:1.0"));
+    
+    let container = Container::new(create_fixture_image("host_connect.wat"), Some(vec![]), None, vec![]).unwrap();
+    let id = container.id().to_string();
+    let error = runtime.run(container).await.unwrap_err();
+    assert!(matches!(error, Error::Exit { exit_code: 6 }));
+    // Converted to anyhow, the error still maps to the guest's exit code.
+    assert_eq!(exit_code(&error.into()), 6);
+    
+    runtime.network_manager().create_network("extra", &[], &[], None).await.unwrap();
+    runtime.connect_network("extra", &id).await.unwrap();
+    let error = runtime.connect_network("extra", &id).await.unwrap_err();
+    assert!(matches!(error, Error::NetworkConflict(_)));
+    let error = runtime.network_manager().inspect_network("missing").await.unwrap_err();
+    assert!(matches!(error, Error::NetworkNotFound(name) if name == "missing"));
+    let volumes = VolumeManager::with_dir(state.path().join("volumes")).unwrap();
+    assert!(matches!(volumes.remove("missing").unwrap_err(), Error::VolumeNotFound(name) if name == "missing"));
+    
+    let mut named = Container::new(test_image(Some("src/image/demo.wasm")), None, None, vec![]).unwrap();
+    named.set_name("web").unwrap();
+    runtime.create(&named).await.unwrap();
+    let mut twin = Container::new(test_image(Some("src/image/demo.wasm")), None, None, vec![]).unwrap();
+    twin.set_name("web").unwrap();
+    let error = runtime.create(&twin).await.unwrap_err();
+    assert!(matches!(error, Error::NameInUse { name, container } if name == "web" && container == named.id()));
+    
+    // Running in this process, as far as the store can tell.
+    let store = ContainerStore::new(state.path()).unwrap();
+    let mut info = runtime.inspect(named.id()).await.unwrap().info;
+    info.status = "running".to_string();
+    store.save(&named, &info).unwrap();
+    let error = runtime.remove(named.id(), false).await.unwrap_err();
+    assert!(matches!(error, Error::ContainerRunning(container) if container == named.id()));
+}
+
+fn create_fixture_image(fixture: &str) -> ImageData {
+    test_image(Some(&format!("tests/fixtures/{}", fixture)))
+}
+
+#[tokio::test]
+async fn test_attached_stdio() {
+    let state = TempDir::new().unwrap();
+    let runtime = WasmRuntime::with_state_dir(state.path()).unwrap();
+    let container = Container::new(create_fixture_image("echo_stdin.wat"), None, None, vec![]).unwrap();
+    let id = container.id().to_string();
+    let mut handle = runtime.attach(&id);
+    assert_eq!(handle.id(), id);
+    let mut stdin = handle.stdin().unwrap();
+    let stdout = handle.stdout().unwrap();
+    let stderr = handle.stderr().unwrap();
+    assert!(handle.stdout().is_none());
+    
+    let run = tokio::spawn(async move {
+        runtime.run(container).await.unwrap();
+        runtime
+    });
+    stdin.write_all(b"ping\n").await.unwrap();
+    stdin.write_all(b"pong\n").await.unwrap();
+    drop(stdin);
+    
+    let stdout: Vec<Bytes> = stdout.collect().await;
+    assert_eq!(stdout.concat(), b"ping\npong\n");
+    let stderr: Vec<Bytes> = stderr.collect().await;
+    assert_eq!(stderr.concat(), b"done\n");
+    
+    // Output still reaches the log, and only the attached run is piped.
+    let runtime = run.await.unwrap();
+    assert_eq!(runtime.logs(&id, 10).await.unwrap(), vec!["ping", "pong", "done"]);
+    runtime.run(Container::new(test_image(Some("src/image/demo.wasm")), None, None, vec![]).unwrap()).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_spawned_containers() {
+    let state = TempDir::new().unwrap();
+    let runtime = WasmRuntime::with_state_dir(state.path()).unwrap();
+    
+    let mut handle = runtime.spawn(Container::new(create_fixture_image("echo_stdin.wat"), None, None, vec![]).unwrap());
+    let mut stdin = handle.stdin().unwrap();
+    let stdout = handle.stdout().unwrap();
+    stdin.write_all(b"ping\n").await.unwrap();
+    drop(stdin);
+    let stdout: Vec<Bytes> = stdout.collect().await;
+    assert_eq!(stdout.concat(), b"ping\n");
+    assert!(handle.wait().await.unwrap().success());
+    
+    // Stdio that isn't taken is closed once waited on.
+    let mut handle = runtime.spawn(Container::new(create_fixture_image("host_connect.wat"), Some(vec![]), None, vec![]).unwrap());
+    assert_eq!(handle.wait().await.unwrap().code(), 6);
+    assert_eq!(handle.wait().await.unwrap().code(), 6);
+    
+    // A guest waiting on its stdin is killed there.
+    let mut handle = runtime.spawn(Container::new(create_fixture_image("echo_stdin.wat"), None, None, vec![]).unwrap());
+    let _stdin = handle.stdin().unwrap();
+    let mut stats = handle.stats().await;
+    for _ in 0..100 {
+        if stats.is_ok() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        stats = handle.stats().await;
+    }
+    stats.unwrap();
+    handle.kill();
+    assert_eq!(handle.wait().await.unwrap().code(), EXIT_CANCELLED);
+    
+    let status = runtime.inspect(handle.id()).await.unwrap().info.exit_code;
+    assert_eq!(status, Some(EXIT_CANCELLED));
+    assert!(runtime.attach("other").wait().await.is_err());
+}
+
+#[tokio::test]
+async fn test_lifecycle_events() {
+    let state = TempDir::new().unwrap();
+    let mut runtime = WasmRuntime::with_state_dir(state.path()).unwrap();
+    let mut events = runtime.subscribe();
+    
+    let container = Container::new(test_image(Some("src/image/demo.wasm")), None, None, vec![]).unwrap();
+    let id = container.id().to_string();
+    runtime.create(&container).await.unwrap();
+    runtime.start(&id).await.unwrap();
+    runtime.remove(&id, false).await.unwrap();
+    let trap = Container::new(create_fixture_image("trap.wat"), Some(vec![]), None, vec![]).unwrap();
+    let trap_id = trap.id().to_string();
+    runtime.run(trap).await.unwrap_err();
+    let mut greedy = Container::new(create_fixture_image("grow_memory.wat"), Some(vec![]), None, vec![]).unwrap();
+    greedy.set_resources(Resources { memory: Some(1024 * 1024), cpus: None });
+    let greedy_id = greedy.id().to_string();
+    runtime.run(greedy).await.unwrap();
+    
+    let mut received = Vec::new();
+    while let Ok(event) = events.try_recv() {
+        received.push(event);
+    }
+    assert_eq!(received[..4], [
+        ContainerEvent::Created { id: id.clone() },
+        ContainerEvent::Started { id: id.clone() },
+        ContainerEvent::Exited { id: id.clone(), exit_code: 0 },
+        ContainerEvent::Removed { id: id.clone() },
+    ]);
+    assert!(matches!(&received[6], ContainerEvent::Failed { id, exit_code, .. } if *id == trap_id && *exit_code == EXIT_TRAP));
+    assert_eq!(received[9..], [
+        ContainerEvent::Oom { id: greedy_id.clone() },
+        ContainerEvent::Exited { id: greedy_id.clone(), exit_code: 0 },
+    ]);
+    assert!(received.iter().all(|event| !event.container_id().is_empty()));
+}
+
+#[tokio::test]
+async fn test_cancelled_runs() {
+    let state = TempDir::new().unwrap();
+    let runtime = WasmRuntime::with_state_dir(state.path()).unwrap();
+    
+    // A guest that never yields is interrupted; the cancel comes from
+    // another thread, as it keeps the test's executor busy.
+    let container = Container::new(create_fixture_image("spin.wat"), Some(vec![]), None, vec![]).unwrap();
+    let id = container.id().to_string();
+    let token = CancellationToken::new();
+    let deadline = token.clone();
+    std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(100));
+        deadline.cancel();
+    });
+    let error = runtime.run_cancellable(container, token).await.unwrap_err();
+    assert!(matches!(error, Error::Cancelled));
+    assert_eq!(error.exit_code(), EXIT_CANCELLED);
+    let info = runtime.inspect(&id).await.unwrap().info;
+    assert_eq!((info.status.as_str(), info.exit_code), ("failed", Some(EXIT_CANCELLED)));
+    
+    // So is one waiting on its stdin.
+    let container = Container::new(create_fixture_image("echo_stdin.wat"), None, None, vec![]).unwrap();
+    let _handle = runtime.attach(container.id());
+    let token = CancellationToken::new();
+    let deadline = token.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        deadline.cancel();
+    });
+    assert!(matches!(runtime.run_cancellable(container, token).await.unwrap_err(), Error::Cancelled));
+    
+    // And one cancelled before it starts never runs.
+    let token = CancellationToken::new();
+    token.cancel();
+    token.cancelled().await;
+    let container = Container::new(test_image(Some("src/image/demo.wasm")), None, None, vec![]).unwrap();
+    assert!(matches!(runtime.run_cancellable(container, token).await.unwrap_err(), Error::Cancelled));
+}
+
+#[tokio::test]
+async fn test_wasi_configure_hooks() {
+    let state = TempDir::new().unwrap();
+    let calls = Arc::new(AtomicUsize::new(0));
+    let counted = calls.clone();
+    let runtime = WasmRuntime::builder()
+        .config(RuntimeConfig { state_dir: Some(state.path().to_path_buf()), ..RuntimeConfig::default() })
+        .wasi_configure(|wasi| {
+            wasi.stdin(MemoryInputPipe::new("from the hook\n"));
+        })
+        .wasi_configure(move |_| {
+            counted.fetch_add(1, Ordering::SeqCst);
+        })
+        .build()
+        .unwrap();
+    
+    let container = Container::new(create_fixture_image("echo_stdin.wat"), None, None, vec![]).unwrap();
+    let id = container.id().to_string();
+    runtime.run(container).await.unwrap();
+    assert_eq!(runtime.logs(&id, 10).await.unwrap(), vec!["from the hook", "done"]);
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn test_host_functions() {
+    let state = TempDir::new().unwrap();
+    let recorded = Arc::new(Mutex::new(Vec::new()));
+    let (record, log) = (recorded.clone(), recorded.clone());
+    let runtime = WasmRuntime::builder()
+        .config(RuntimeConfig { state_dir: Some(state.path().to_path_buf()), ..RuntimeConfig::default() })
+        .host_fn("app", "record", move |caller: Caller<'_, ContainerState>, n: i32| {
+            record.lock().unwrap().push(format!("{} {}", caller.data().container_id, n));
+        })
+        .host_fn("env", "container_log", move |mut caller: Caller<'_, ContainerState>, ptr: i32, len: i32| -> wasmtime::Result<()> {
+            let message = guest_bytes(&mut caller, ptr, len)?;
+            log.lock().unwrap().push(String::from_utf8(message)?);
+            Ok(())
+        })
+        .build()
+        .unwrap();
+    
+    // Each run gets the functions, the runtime's own replaced.
+    for _ in 0..2 {
+        let container = Container::new(create_fixture_image("host_fns.wat"), Some(vec![]), None, vec![]).unwrap();
+        let id = container.id().to_string();
+        runtime.run(container).await.unwrap();
+        assert_eq!(recorded.lock().unwrap().drain(..).collect::<Vec<_>>(), vec![format!("{} 21", id), "hello".to_string()]);
+    }
+    
+    // Without them, guests importing them can't start.
+    let runtime = WasmRuntime::builder()
+        .config(RuntimeConfig { state_dir: Some(state.path().to_path_buf()), ..RuntimeConfig::default() })
+        .host_functions(HostFunctions::empty())
+        .build()
+        .unwrap();
+    let container = Container::new(create_fixture_image("host_fns.wat"), Some(vec![]), None, vec![]).unwrap();
+    assert_eq!(runtime.run(container).await.unwrap_err().exit_code(), EXIT_NOT_INVOKABLE);
+}
+
+#[tokio::test]
+async fn test_state_stores() {
+    for backend in [StateBackend::Json, StateBackend::Memory, StateBackend::Sqlite] {
+        let state = TempDir::new().unwrap();
+        let config = RuntimeConfig {
+            state_dir: Some(state.path().to_path_buf()),
+            state_store: Some(backend),
+            ..RuntimeConfig::default()
+        };
+        let mut runtime = WasmRuntime::with_config(&config).unwrap();
+        let mut container = Container::new(test_image(Some("src/image/demo.wasm")), None, None, vec![]).unwrap();
+        container.set_name("web").unwrap();
+        let id = container.id().to_string();
+        runtime.run(container).await.unwrap();
+        
+        // Stores are shared with other runtimes and with whatever opens the
+        // state directory without asking for one.
+        let other = WasmRuntime::with_config(&config).unwrap();
+        assert_eq!(other.inspect("web").await.unwrap().info.status, "exited", "{}", backend);
+        assert_eq!(other.container_events(&id).unwrap(), vec![
+            ContainerEvent::Created { id: id.clone() },
+            ContainerEvent::Started { id: id.clone() },
+            ContainerEvent::Exited { id: id.clone(), exit_code: 0 },
+        ]);
+        let store = ContainerStore::new(state.path()).unwrap();
+        assert!(store.exists(&id).unwrap());
+        assert!(store.incomplete().unwrap().is_empty());
+        assert_eq!(state.path().join("containers").join(&id).join("info.json").exists(), backend == StateBackend::Json);
+        
+        runtime.remove(&id, false).await.unwrap();
+        assert!(other.list_containers(true).await.unwrap().is_empty());
+        assert!(store.events(&id).unwrap_or_default().is_empty());
+    }
+    
+    // Embedders can bring their own.
+    let state = TempDir::new().unwrap();
+    let runtime = WasmRuntime::builder()
+        .config(RuntimeConfig { state_dir: Some(state.path().to_path_buf()), ..RuntimeConfig::default() })
+        .state_store(MemoryStateStore::new())
+        .build()
+        .unwrap();
+    runtime.run(Container::new(test_image(Some("src/image/demo.wasm")), None, None, vec![]).unwrap()).await.unwrap();
+    assert_eq!(runtime.list_containers(true).await.unwrap().len(), 1);
+    assert!(ContainerStore::new(state.path()).unwrap().list().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_containers_by_name() {
+    let state = TempDir::new().unwrap();
+    let mut runtime = WasmRuntime::with_state_dir(state.path()).unwrap();
+    
+    let mut web = Container::new(test_image(Some("src/image/demo.wasm")), None, None, vec![]).unwrap();
+    web.set_name("web").unwrap();
+    assert_eq!(web.network_config().hostname, "web");
+    let web_id = web.id().to_string();
+    runtime.run(web).await.unwrap();
+    let mut worker = Container::new(test_image(Some("src/image/demo.wasm")), None, None, vec![]).unwrap();
+    worker.set_name("worker").unwrap();
+    let worker_id = worker.id().to_string();
+    runtime.run(worker).await.unwrap();
+    
+    let details = runtime.inspect("web").await.unwrap();
+    assert_eq!(details.info.id, web_id);
+    assert_eq!(details.info.name.as_deref(), Some("web"));
+    assert_eq!(runtime.inspect("wo").await.unwrap().info.id, worker_id);
+    assert_eq!(runtime.inspect(&web_id[..12]).await.unwrap().info.id, web_id);
+    let ambiguous = runtime.inspect("w").await.unwrap_err().to_string();
+    assert!(ambiguous.contains("matches several containers"), "{}", ambiguous);
+    assert!(runtime.inspect("db").await.is_err());
+    
+    let mut duplicate = Container::new(test_image(Some("src/image/demo.wasm")), None, None, vec![]).unwrap();
+    duplicate.set_name("web").unwrap();
+    let taken = runtime.run(duplicate).await.unwrap_err().to_string();
+    assert!(taken.contains(&format!("already in use by container {}", web_id)), "{}", taken);
+    assert!(Container::new(test_image(Some("src/image/demo.wasm")), None, None, vec![]).unwrap().set_name("-web").is_err());
+    
+    runtime.remove("web", false).await.unwrap();
+    assert!(runtime.inspect(&web_id).await.is_err());
+    assert_eq!(runtime.inspect("w").await.unwrap().info.id, worker_id);
+}
+
+#[tokio::test]
+async fn test_host_network() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut ping = [0u8; 4];
+        stream.read_exact(&mut ping).await.unwrap();
+        stream.write_all(b"pong").await.unwrap();
+        ping
+    });
+    
+    let state = TempDir::new().unwrap();
+    let runtime = WasmRuntime::with_state_dir(state.path()).unwrap();
+    let command = vec!["host_connect".to_string(), port.to_string()];
+    let mut container = Container::new(create_fixture_image("host_connect.wat"), Some(command), None, vec![]).unwrap();
+    container.set_networks(vec!["host".to_string()]);
+    let id = container.id().to_string();
+    if let Err(e) = runtime.run(container).await {
+        panic!("host connection failed: {:?}", e);
+    }
+    assert_eq!(&server.await.unwrap(), b"ping");
+    
+    let connect = runtime.connect_network("bridge", &id).await.unwrap_err().to_string();
+    assert!(connect.contains("uses the host's network"), "{}", connect);
+    assert!(runtime.connect_network("host", &id).await.is_err());
+    assert!(runtime.network_manager().create_network("host", &[], &[], None).await.is_err());
+    
+    let mut published = Container::new(test_image(Some("src/image/demo.wasm")), None, None, vec![]).unwrap();
+    published.set_networks(vec!["host".to_string()]);
+    published.add_port_mapping(0, 80, "tcp".to_string());
+    let err = runtime.run(published).await.unwrap_err().to_string();
+    assert!(err.contains("drop -p"), "{}", err);
+}
+
+#[tokio::test]
+async fn test_hostname() {
+    let state = TempDir::new().unwrap();
+    let runtime = WasmRuntime::with_state_dir(state.path()).unwrap();
+    
+    for network in ["bridge", "none"] {
+        let mut container = Container::new(test_image(Some("src/image/demo.wasm")), None, None, vec!["HOSTNAME=other".to_string()]).unwrap();
+        container.set_name(&format!("web-{}", network)).unwrap();
+        container.set_hostname("cache.local").unwrap();
+        container.set_networks(vec![network.to_string()]);
+        let id = container.id().to_string();
+        runtime.run(container).await.unwrap();
+        
+        let rootfs = state.path().join("containers").join(&id).join("rootfs");
+        assert_eq!(std::fs::read_to_string(rootfs.join("etc/hostname")).unwrap(), "cache.local\n");
+        let hosts = std::fs::read_to_string(rootfs.join("etc/hosts")).unwrap();
+        assert!(hosts.lines().any(|line| line.split_whitespace().nth(1) == Some("cache.local")), "{}", hosts);
+        assert!(!hosts.contains(&format!("\t{}\n", id)), "{}", hosts);
+        let environ = std::fs::read(rootfs.join("proc/self/environ")).unwrap();
+        let hostnames: Vec<&[u8]> = environ.split(|b| *b == 0).filter(|var| var.starts_with(b"HOSTNAME=")).collect();
+        assert_eq!(hostnames, vec![&b"HOSTNAME=cache.local"[..]]);
+    }
+    
+    let mut container = Container::new(test_image(Some("src/image/demo.wasm")), None, None, vec![]).unwrap();
+    assert!(container.set_hostname("").is_err());
+    assert!(container.set_hostname("-cache").is_err());
+    assert!(container.set_hostname("cache..local").is_err());
+    assert!(container.set_hostname("cache/local").is_err());
+    assert!(container.set_hostname(&"a".repeat(64)).is_err());
+}
+
+#[tokio::test]
+async fn test_runtime_builder() {
+    let dir = TempDir::new().unwrap();
+    let config = RuntimeConfig {
+        state_dir: Some(dir.path().join("state")),
+        scratch_dir: Some(dir.path().join("scratch")),
+        ..RuntimeConfig::default()
+    };
+    let runtime = WasmRuntime::builder()
+        .config(config.clone())
+        .fuel(10_000_000)
+        .pooling(true)
+        .cache_dir(dir.path().join("cache"))
+        .storage_driver(RootfsBackend::Disk, MemoryDriver::new(dir.path().join("scratch")))
+        .build()
+        .unwrap();
+    let info = runtime.info().await.unwrap();
+    assert_eq!(info.storage_drivers[&RootfsBackend::Disk], "memory");
+    
+    runtime.run(Container::new(test_image(Some("src/image/demo.wasm")), None, None, vec![]).unwrap()).await.unwrap();
+    let cached = std::fs::read_dir(dir.path().join("cache/modules")).unwrap().count();
+    assert!(cached > 0);
+    
+    // Running out of fuel traps a guest that would spin forever.
+    let container = Container::new(create_fixture_image("busy_loop.wat"), None, None, vec![]).unwrap();
+    let error = runtime.run(container).await.unwrap_err();
+    assert!(matches!(error, Error::Trap { exit_code: EXIT_TRAP, .. }));
+    
+    // Runtimes handed the same network manager see each other's networks.
+    runtime.network_manager().create_network("shared", &[], &[], None).await.unwrap();
+    let other = WasmRuntime::builder()
+        .config(RuntimeConfig { state_dir: Some(dir.path().join("other")), ..config })
+        .network_manager(runtime.network_manager())
+        .build()
+        .unwrap();
+    assert!(other.inspect_network("shared").await.is_ok());
+}
+
+#[tokio::test]
+async fn test_container_builder() {
+    let volume = TempDir::new().unwrap();
+    let container = Container::builder(test_image(Some("src/image/demo.wasm")))
+        .name("web")
+        .command(["serve", "--port", "80"])
+        .workdir("/srv")
+        .env("MODE", "production")
+        .label("tier", "front")
+        .volume(volume.path(), "/data", true)
+        .port(8080, 80, "TCP")
+        .port(0, 53, "udp")
+        .network("none")
+        .build()
+        .unwrap();
+    assert_eq!(container.name(), Some("web"));
+    assert_eq!(container.network_config().hostname, "web");
+    assert_eq!(container.command().unwrap(), &vec!["serve", "--port", "80"]);
+    assert_eq!(container.workdir(), Some("/srv"));
+    assert_eq!(container.env_vars()["MODE"], "production");
+    assert_eq!(container.labels()["tier"], "front");
+    assert_eq!(container.volumes()[0].host_path, volume.path().canonicalize().unwrap());
+    assert!(container.volumes()[0].read_only);
+    let ports: Vec<String> = container.network_config().ports.iter().map(ToString::to_string).collect();
+    assert_eq!(ports, vec!["8080->80/tcp", "0->53/udp"]);
+    assert!(container.network_disabled());
+    
+    let state = TempDir::new().unwrap();
+    let runtime = WasmRuntime::with_state_dir(state.path()).unwrap();
+    runtime.run(Container::builder(test_image(Some("src/image/demo.wasm"))).hostname("cache.local").build().unwrap()).await.unwrap();
+    
+    // Bad settings fail the build, before anything is created.
+    let error = |builder: wasm_container::ContainerBuilder| builder.build().unwrap_err().to_string();
+    let builder = || Container::builder(test_image(Some("src/image/demo.wasm")));
+    assert!(error(builder().name("-web")).contains("Invalid container name"));
+    assert!(error(builder().hostname("cache..local")).contains("Invalid hostname"));
+    assert!(error(builder().env("A=B", "c")).contains("Invalid environment variable name"));
+    assert!(error(builder().workdir("srv")).contains("must be absolute"));
+    assert!(error(builder().volume(volume.path(), "data", false)).contains("must be absolute"));
+    assert!(error(builder().volume(volume.path().join("missing"), "/data", false)).contains("not accessible"));
+    assert!(error(builder().port(8080, 80, "sctp")).contains("Unsupported protocol"));
+    assert!(error(builder().port(8080, 80, "tcp").port(8080, 81, "tcp")).contains("published twice"));
+    assert!(error(builder().network("none").network("bridge")).contains("can't be combined"));
+    assert_eq!(runtime.list_containers(true).await.unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn test_labels_and_filters() {
+    let state = TempDir::new().unwrap();
+    let runtime = WasmRuntime::with_state_dir(state.path()).unwrap();
+    
+    for (name, tier) in [("web-1", "front"), ("web-2", "front"), ("db", "back")] {
+        let mut container = Container::new(test_image(Some("src/image/demo.wasm")), None, None, vec![]).unwrap();
+        container.set_name(name).unwrap();
+        container.set_label("tier", tier);
+        container.set_label("app", "shop");
+        runtime.run(container).await.unwrap();
+    }
+    runtime.run(Container::new(test_image(Some("src/image/demo.wasm")), None, None, vec![]).unwrap()).await.unwrap();
+    
+    let containers = runtime.list_containers(true).await.unwrap();
+    let matching = |filters: &[&str]| {
+        let filters: Vec<ContainerFilter> = filters.iter().map(|filter| filter.parse().unwrap()).collect();
+        let mut names: Vec<&str> = containers.iter()
+            .filter(|container| filters.iter().all(|filter| filter.matches(container)))
+            .map(|container| container.name.as_deref().unwrap_or("-"))
+            .collect();
+        names.sort();
+        names
+    };
+    assert_eq!(matching(&["label=tier=front"]), vec!["web-1", "web-2"]);
+    assert_eq!(matching(&["label=app"]), vec!["db", "web-1", "web-2"]);
+    assert_eq!(matching(&["label=app", "name=1"]), vec!["web-1"]);
+    assert_eq!(matching(&["label=tier=side"]), Vec::<&str>::new());
+    assert_eq!(matching(&[]).len(), 4);
+    assert_eq!(runtime.inspect("db").await.unwrap().info.labels.get("tier").unwrap(), "back");
+    
+    assert!("label=".parse::<ContainerFilter>().is_err());
+    assert!("status=running".parse::<ContainerFilter>().is_err());
+    assert_eq!(parse_label("team").unwrap(), ("team".to_string(), String::new()));
+    assert_eq!(parse_label("url=a=b").unwrap(), ("url".to_string(), "a=b".to_string()));
+    assert!(parse_label("=x").is_err());
+}
+
+#[tokio::test]
+async fn test_list_details() {
+    let state = TempDir::new().unwrap();
+    let mut runtime = WasmRuntime::with_state_dir(state.path()).unwrap();
+    
+    let mut container = Container::new(test_image(Some("src/image/demo.wasm")), None, None, vec![]).unwrap();
+    container.set_name("web").unwrap();
+    let id = container.id().to_string();
+    runtime.run(container).await.unwrap();
+    let info = runtime.inspect(&id).await.unwrap().info;
+    assert_eq!(info.exit_code, Some(0));
+    assert!(info.created_at > 0);
+    assert!(info.started_at.unwrap() >= info.created_at);
+    assert_eq!(info.field("Status"), "Exited (0)");
+    assert_eq!(info.field("Names"), "web");
+    assert_eq!(info.field("RunningFor"), "Less than a second ago");
+    
+    // Without its argument the fixture exits with code 6.
+    let failing = Container::new(create_fixture_image("host_connect.wat"), Some(vec![]), None, vec![]).unwrap();
+    let failing_id = failing.id().to_string();
+    assert!(runtime.run(failing).await.is_err());
+    let info = runtime.inspect(&failing_id).await.unwrap().info;
+    assert_eq!(info.exit_code, Some(6));
+    assert_eq!(info.field("State"), "failed");
+    assert_eq!(info.field("Status"), "Failed (6)");
+    
+    // Restarting keeps the creation time.
+    let created_at = runtime.inspect(&id).await.unwrap().info.created_at;
+    std::thread::sleep(Duration::from_millis(1100));
+    runtime.start(&id).await.unwrap();
+    let info = runtime.inspect(&id).await.unwrap().info;
+    assert_eq!(info.created_at, created_at);
+    assert!(info.started_at.unwrap() > created_at);
+}
+
+#[tokio::test]
+async fn test_cached_images_are_listed() {
+    let cache = TempDir::new().unwrap();
+    let images = ImageManager::with_cache_dir(cache.path().join("images")).unwrap();
+    assert!(images.list().unwrap().is_empty());
+    
+    images.pull("myapp:1.0").await.unwrap();
+    images.pull("library/nginx").await.unwrap();
+    // Pulls that never finished leave no metadata behind.
+    let partial = cache.path().join("images/partial/latest");
+    std::fs::create_dir_all(&partial).unwrap();
+    std::fs::write(partial.join("layer.tar.gz"), "half a layer").unwrap();
+    assert_eq!(images.list().unwrap(), vec!["library/nginx:latest", "myapp:1.0"]);
+    
+    // Until they age out, they may still be in progress.
+    let (removed, _) = images.prune(false, &[], Duration::from_secs(3600)).unwrap();
+    assert!(removed.is_empty());
+    let (removed, reclaimed) = images.prune(false, &[], Duration::ZERO).unwrap();
+    assert_eq!(removed, vec!["partial:latest"]);
+    assert!(reclaimed >= 12);
+    assert!(!cache.path().join("images/partial").exists());
+    
+    let in_use = vec!["myapp:1.0".to_string()];
+    let (removed, _) = images.prune(true, &in_use, Duration::ZERO).unwrap();
+    assert_eq!(removed, vec!["library/nginx:latest"]);
+    assert_eq!(images.list().unwrap(), in_use);
+    assert!(!cache.path().join("images/library").exists());
+}
+
+#[tokio::test]
+async fn test_pull_policies() {
+    let cache = TempDir::new().unwrap();
+    let images = ImageManager::with_cache_dir(cache.path().join("images")).unwrap();
+    
+    let err = images.get("myapp:1.0", PullPolicy::Never).await.unwrap_err();
+    assert!(err.to_string().contains("not in the cache"));
+    assert!(images.list().unwrap().is_empty());
+    
+    images.get("myapp:1.0", PullPolicy::Missing).await.unwrap();
+    let digest = images.cached_digest("myapp:1.0").unwrap().unwrap();
+    assert!(digest.starts_with("sha256:"));
+    images.get("myapp:1.0", PullPolicy::Never).await.unwrap();
+    
+    // A cached copy the registry has moved on from is pulled again.
+    let record = cache.path().join("images/myapp/1.0/pull.json");
+    std::fs::write(&record, r#"{"digest": "sha256:stale"}"#).unwrap();
+    images.get("myapp:1.0", PullPolicy::Missing).await.unwrap();
+    assert_eq!(images.cached_digest("myapp:1.0").unwrap().as_deref(), Some("sha256:stale"));
+    images.get("myapp:1.0", PullPolicy::Always).await.unwrap();
+    assert_eq!(images.cached_digest("myapp:1.0").unwrap(), Some(digest));
+    
+    assert!("sometimes".parse::<PullPolicy>().is_err());
+}
+
+#[tokio::test]
+async fn test_pull_for_platform() {
+    let cache = TempDir::new().unwrap();
+    let images = ImageManager::with_cache_dir(cache.path().join("images")).unwrap();
+    images.pull("myapp:1.0").await.unwrap();
+    assert_eq!(images.cached_platform("myapp:1.0").unwrap(), Some(Platform::default()));
+    
+    let amd64: Platform = "linux/amd64".parse().unwrap();
+    let images = images.with_platform(amd64);
+    let err = images.pull("myapp:1.0").await.unwrap_err();
+    assert_eq!(err.to_string(), "No such image: myapp:1.0: not published for linux/amd64 (only for wasi/wasm32)");
+    // What is cached is for another platform.
+    assert!(images.get("myapp:1.0", PullPolicy::Never).await.is_err());
+    
+    let variant: Platform = "wasi/wasm32/v1".parse().unwrap();
+    assert_eq!(variant.to_string(), "wasi/wasm32/v1");
+    assert!(variant.matches(&variant) && !variant.matches(&Platform::default()));
+    assert!(Platform::default().matches(&variant));
+    assert!("wasm32".parse::<Platform>().is_err());
+}
+
+#[tokio::test]
+async fn test_remove_cached_image() {
+    let cache = TempDir::new().unwrap();
+    let images = ImageManager::with_cache_dir(cache.path().join("images")).unwrap();
+    images.pull("myapp:1.0").await.unwrap();
+    images.pull("library/nginx").await.unwrap();
+    
+    let in_use = vec!["myapp:1.0".to_string()];
+    let err = images.remove("myapp:1.0", &in_use).unwrap_err();
+    assert!(err.to_string().contains("used by a container"));
+    
+    let (removed, reclaimed) = images.remove("library/nginx", &in_use).unwrap();
+    assert_eq!(removed, "library/nginx:latest");
+    assert!(reclaimed > 0);
+    assert_eq!(images.list().unwrap(), in_use);
+    assert!(!cache.path().join("images/library").exists());
+    assert!(images.remove("library/nginx", &in_use).unwrap_err().to_string().contains("No such image"));
+}
+
+#[tokio::test]
+async fn test_image_sources() {
+    let dir = TempDir::new().unwrap();
+    let layer = std::fs::read(write_layer(dir.path(), "app/config.txt", "from-layer").path).unwrap();
+    let wasm = std::fs::read("src/image/demo.wasm").unwrap();
+    let config = test_image(Some("src/image/demo.wasm")).config;
+    
+    let mut memory = MemorySource::new();
+    memory.insert("bundled/app", "1.0", &config, &wasm, vec![layer.clone()]).unwrap();
+    memory.insert("localhost:5000/app", "1.0", &config, &wasm, vec![]).unwrap();
+    let images = ImageManager::with_cache_dir(dir.path().join("images")).unwrap().with_source(memory);
+    let image = images.pull("bundled/app:1.0").await.unwrap();
+    assert_eq!(image.layers.len(), 1);
+    assert_eq!(std::fs::read(image.wasm_path.as_ref().unwrap()).unwrap(), wasm);
+    assert!(images.pull("bundled/other").await.unwrap_err().to_string().contains("not found"));
+    
+    // A registry's port isn't taken for the tag.
+    let ported = images.pull("localhost:5000/app:1.0").await.unwrap();
+    assert_eq!((ported.name.as_str(), ported.tag.as_str()), ("localhost:5000/app", "1.0"));
+    assert!(images.pull("localhost:5000/app").await.unwrap_err().to_string().contains("not found"));
+    assert!(images.pull("app:1.0:2.0").await.unwrap_err().to_string().contains("Invalid image reference"));
+    
+    let state = TempDir::new().unwrap();
+    let runtime = WasmRuntime::with_state_dir(state.path()).unwrap();
+    let container = Container::new(image, None, None, vec![]).unwrap();
+    let id = container.id().to_string();
+    runtime.run(container).await.unwrap();
+    let copied = state.path().join("config.txt");
+    runtime.copy_from_container(&id, Path::new("/app/config.txt"), &copied).await.unwrap();
+    assert_eq!(std::fs::read_to_string(copied).unwrap(), "from-layer");
+    
+    // The same image as an OCI layout on disk.
+    let layout = dir.path().join("layout");
+    let blobs = layout.join("blobs/sha256");
+    std::fs::create_dir_all(&blobs).unwrap();
+    let add_blob = |media_type: &str, blob: &[u8]| {
+        let hex: String = Sha256::digest(blob).iter().map(|byte| format!("{:02x}", byte)).collect();
+        std::fs::write(blobs.join(&hex), blob).unwrap();
+        serde_json::json!({ "digest": format!("sha256:{}", hex), "size": blob.len(), "mediaType": media_type })
+    };
+    let manifest = serde_json::json!({
+        "schemaVersion": 2,
+        "config": add_blob(CONFIG_MEDIA_TYPE, &serde_json::to_vec(&config).unwrap()),
+        "layers": [add_blob(LAYER_MEDIA_TYPE, &layer), add_blob(WASM_MEDIA_TYPE, &wasm)],
+    });
+    let index = serde_json::json!({
+        "schemaVersion": 2,
+        "manifests": [add_blob(MANIFEST_MEDIA_TYPE, &serde_json::to_vec(&manifest).unwrap())],
+    });
+    std::fs::create_dir_all(layout.join("local/app/2.0")).unwrap();
+    std::fs::write(layout.join("local/app/2.0/index.json"), index.to_string()).unwrap();
+    
+    let images = ImageManager::with_cache_dir(dir.path().join("local-images")).unwrap().with_source(LocalSource::new(&layout));
+    let image = images.pull("local/app:2.0").await.unwrap();
+    assert_eq!(image.layers.len(), 1);
+    assert_eq!(std::fs::read(image.wasm_path.as_ref().unwrap()).unwrap(), wasm);
+    
+    // Blobs are checked against their digests.
+    let wasm_blob = manifest["layers"][1]["digest"].as_str().unwrap().strip_prefix("sha256:").unwrap().to_string();
+    std::fs::write(blobs.join(wasm_blob), b"tampered").unwrap();
+    let err = images.pull("local/app:2.0").await.unwrap_err();
+    assert!(err.to_string().contains("doesn't match its digest"));
+}
+
+#[tokio::test]
+async fn test_system_prune() {
+    let state = TempDir::new().unwrap();
+    let scratch = TempDir::new().unwrap();
+    let mut runtime = WasmRuntime::with_config(&RuntimeConfig {
+        state_dir: Some(state.path().to_path_buf()),
+        scratch_dir: Some(scratch.path().to_path_buf()),
+        ..RuntimeConfig::default()
+    }).unwrap();
+    let networks = runtime.network_manager();
+    for name in ["backend", "unused", "busy"] {
+        networks.create_network(name, &[], &[], None).await.unwrap();
+    }
+    
+    let mut stopped = Container::new(test_image(Some("src/image/demo.wasm")), None, None, vec![]).unwrap();
+    stopped.set_networks(vec!["backend".to_string()]);
+    let stopped_id = stopped.id().to_string();
+    runtime.run(stopped).await.unwrap();
+    
+    // Running in this process, as far as the store can tell.
+    let store = ContainerStore::new(state.path()).unwrap();
+    let mut running = Container::new(test_image(Some("src/image/demo.wasm")), None, None, vec![]).unwrap();
+    running.set_networks(vec!["busy".to_string()]);
+    let running_id = running.id().to_string();
+    let mut info = runtime.inspect(&stopped_id).await.unwrap().info;
+    info.id = running_id.clone();
+    info.status = "running".to_string();
+    store.save(&running, &info).unwrap();
+    store.track_rootfs(&running_id, &RootfsRecord {
+        rootfs: scratch.path().join(&running_id),
+        tmpfs: scratch.path().join(format!("{}-tmpfs", running_id)),
+        pid: std::process::id(),
+        persistent: false,
+    }).unwrap();
+    
+    let report = runtime.prune().await.unwrap();
+    assert_eq!(report.containers, vec![stopped_id.clone()]);
+    let mut removed = report.networks.clone();
+    removed.sort();
+    assert_eq!(removed, vec!["backend", "unused"]);
+    assert!(report.reclaimed > 0);
+    
+    assert!(runtime.inspect(&stopped_id).await.is_err());
+    assert_eq!(runtime.inspect(&running_id).await.unwrap().info.status, "running");
+    assert!(networks.inspect_network("busy").await.is_ok());
+    assert!(networks.inspect_network("bridge").await.is_ok());
+    assert_eq!(runtime.images_in_use().unwrap(), vec!["test-image:latest"]);
+}
+
+#[tokio::test]
+async fn test_runtime_info() {
+    assert!(WASMTIME_VERSION.starts_with("24."));
+    let features = wasm_features();
+    assert!(features.contains(&"simd".to_string()));
+    assert!(features.contains(&"threads".to_string()));
+    
+    let state = TempDir::new().unwrap();
+    let scratch = TempDir::new().unwrap();
+    let runtime = WasmRuntime::with_config(&RuntimeConfig {
+        state_dir: Some(state.path().to_path_buf()),
+        scratch_dir: Some(scratch.path().to_path_buf()),
+        ..RuntimeConfig::default()
+    }).unwrap();
+    runtime.network_manager().create_network("backend", &[], &[], None).await.unwrap();
+    let container = Container::new(test_image(Some("src/image/demo.wasm")), None, None, vec![]).unwrap();
+    runtime.run(container).await.unwrap();
+    
+    let info = runtime.info().await.unwrap();
+    assert_eq!(info.state_dir, state.path());
+    assert_eq!(info.scratch_dir, scratch.path());
+    assert_eq!(info.storage_drivers[&RootfsBackend::Disk], "overlay");
+    assert_eq!((info.containers, info.running), (1, 0));
+    assert_eq!(info.default_network.name, "bridge");
+    assert_eq!(info.networks, 2);
+}
+
+#[tokio::test]
+async fn test_resource_limits() {
+    let state = TempDir::new().unwrap();
+    let runtime = WasmRuntime::with_config(&RuntimeConfig {
+        state_dir: Some(state.path().to_path_buf()),
+        max_memory: Some(64 * 1024 * 1024),
+        max_cpus: Some("1".parse().unwrap()),
+        ..RuntimeConfig::default()
+    }).unwrap();
+    let limited = |memory: Option<u64>, cpus: Option<&str>| {
+        let mut container = Container::new(create_fixture_image("busy_loop.wat"), None, None, vec![]).unwrap();
+        container.set_resources(Resources {
+            memory,
+            cpus: cpus.map(|cpus| cpus.parse().unwrap()),
+        });
+        container
+    };
+    
+    let above = runtime.run(limited(Some(128 * 1024 * 1024), None)).await.unwrap_err().to_string();
+    assert!(above.contains("above the host maximum"), "{}", above);
+    assert!(runtime.run(limited(None, Some("2"))).await.is_err());
+    assert!(runtime.list_containers(true).await.unwrap().is_empty());
+    
+    // The guest's one page of memory doesn't fit.
+    assert!(runtime.run(limited(Some(1024), None)).await.is_err());
+    
+    let started = std::time::Instant::now();
+    runtime.run(limited(Some(1024 * 1024), None)).await.unwrap();
+    let full = started.elapsed();
+    let started = std::time::Instant::now();
+    runtime.run(limited(None, Some("0.25"))).await.unwrap();
+    let quarter = started.elapsed();
+    assert!(quarter > full * 2, "{:?} at 0.25 CPUs vs {:?} unthrottled", quarter, full);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_usage_and_logs() {
+    let state = TempDir::new().unwrap();
+    let runtime = WasmRuntime::with_state_dir(state.path()).unwrap();
+    
+    let container = Container::new(test_image(Some("src/image/demo.wasm")), None, None, vec![]).unwrap();
+    let id = container.id().to_string();
+    runtime.run(container).await.unwrap();
+    let logs = runtime.logs(&id, 10).await.unwrap();
+    assert!(logs[0].starts_with("Hello from WASM Container!"), "{:?}", logs);
+    assert!(runtime.usage(&id).await.is_err());
+    
+    // Sampled once a second while the guest spins at a tenth of a CPU.
+    let mut container = Container::new(create_fixture_image("busy_loop.wat"), None, None, vec![]).unwrap();
+    container.set_resources(Resources { memory: None, cpus: Some("0.1".parse().unwrap()) });
+    let id = container.id().to_string();
+    let run = tokio::spawn(async move { runtime.run(container).await });
+    let runtime = WasmRuntime::with_state_dir(state.path()).unwrap();
+    let started = std::time::Instant::now();
+    let usage = loop {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        match runtime.usage(&id).await {
+            Ok(usage) if usage.cpu_percent > 0.0 => break usage,
+            _ => assert!(!run.is_finished(), "no usage sampled in {:?}", started.elapsed()),
+        }
+    };
+    assert!(usage.cpu_percent <= 50.0, "{:?}", usage);
+    assert_eq!(usage.memory, 64 * 1024);
+    run.await.unwrap().unwrap();
+}
+
+#[test]
+fn test_cpus_parsing() {
+    let cpus: Cpus = "0.5".parse().unwrap();
+    assert_eq!(cpus.millis(), 500);
+    assert_eq!(cpus.count(), 1);
+    assert_eq!(cpus.to_string(), "0.5");
+    assert_eq!("2".parse::<Cpus>().unwrap().to_string(), "2");
+    assert_eq!("1.25".parse::<Cpus>().unwrap().count(), 2);
+    assert!("0".parse::<Cpus>().is_err());
+    assert!("-1".parse::<Cpus>().is_err());
+    assert!("lots".parse::<Cpus>().is_err());
+}
@@ -0,0 +1,175 @@
+mod common;
+
+use common::test_image;
+use wasm_container::kube::KubePod;
+use wasm_container::runtime::WasmRuntime;
+use wasm_container::volume::{VolumeManager, VolumeSource};
+use std::collections::HashMap;
+use tempfile::TempDir;
+
+const MANIFEST: &str = r#"
+apiVersion: v1
+kind: ConfigMap
+metadata:
+  name: web-config
+data:
+  MODE: production
+  nginx.conf: "listen 80;\n"
+---
+apiVersion: v1
+kind: Service
+metadata:
+  name: web
+spec:
+  ports:
+    - port: 80
+---
+apiVersion: v1
+kind: Pod
+metadata:
+  name: web.app
+spec:
+  containers:
+    - name: server
+      image: web:latest
+      command: ["serve"]
+      args: ["--port", "80"]
+      workingDir: /srv
+      env:
+        - name: DB_HOST
+          value: db
+        - name: MODE
+          valueFrom:
+            configMapKeyRef:
+              name: web-config
+              key: MODE
+      ports:
+        - containerPort: 80
+          hostPort: 8080
+        - containerPort: 9000
+          protocol: UDP
+      volumeMounts:
+        - name: cache
+          mountPath: /cache
+        - name: config
+          mountPath: /etc/web
+        - name: static
+          mountPath: /srv/static
+          readOnly: true
+    - name: sidecar
+      image: sidecar:latest
+      envFrom:
+        - configMapRef:
+            name: web-config
+          prefix: WEB_
+  volumes:
+    - name: cache
+      emptyDir: {}
+    - name: config
+      configMap:
+        name: web-config
+        items:
+          - key: nginx.conf
+            path: conf/nginx.conf
+    - name: static
+      hostPath:
+        path: ./static
+        type: DirectoryOrCreate
+---
+apiVersion: apps/v1
+kind: Deployment
+metadata:
+  name: worker
+spec:
+  replicas: 3
+  template:
+    spec:
+      containers:
+        - name: worker
+          image: worker:latest
+"#;
+
+#[test]
+fn test_kube_translates_pods() {
+    let dir = TempDir::new().unwrap();
+    let state = TempDir::new().unwrap();
+    let pods = KubePod::parse(MANIFEST, dir.path(), state.path()).unwrap();
+    assert_eq!(pods.len(), 2);
+    
+    let web = pods[0].compose();
+    assert_eq!(web.project(), "web-app");
+    let server = web.service("server").unwrap();
+    assert_eq!(server.image, "web:latest");
+    assert_eq!(server.command.clone().unwrap(), vec!["serve", "--port", "80"]);
+    assert_eq!(server.working_dir.as_deref(), Some("/srv"));
+    assert_eq!(server.environment, vec!["DB_HOST=db", "MODE=production"]);
+    // Only ports with a hostPort are published.
+    let ports: Vec<String> = server.ports.iter().map(ToString::to_string).collect();
+    assert_eq!(ports, vec!["8080->80/tcp"]);
+    
+    assert_eq!(server.volumes[0].source, VolumeSource::Named("cache".to_string()));
+    assert_eq!(web.volume_name("cache"), "web-app_cache");
+    assert_eq!(server.volumes[1].source, VolumeSource::HostPath(state.path().join("kube/web-app/config")));
+    assert!(server.volumes[1].options.read_only);
+    assert_eq!(server.volumes[2].source, VolumeSource::HostPath(dir.path().join("static")));
+    assert!(server.volumes[2].options.read_only);
+    
+    let sidecar = web.service("sidecar").unwrap();
+    assert_eq!(sidecar.environment, vec!["WEB_MODE=production", "WEB_nginx.conf=listen 80;\n"]);
+    
+    assert_eq!(pods[1].compose().project(), "worker");
+    assert_eq!(pods[1].compose().start_order().unwrap(), vec!["worker"]);
+}
+
+#[test]
+fn test_kube_rejects_unsupported_specs() {
+    let dir = TempDir::new().unwrap();
+    let parse = |yaml: &str| KubePod::parse(yaml, dir.path(), dir.path());
+    let pod = |spec: &str| format!("kind: Pod\nmetadata:\n  name: app\nspec:\n{}", spec);
+    
+    assert!(parse("kind: Service\nmetadata:\n  name: web\n").unwrap_err().to_string().contains("No Pods"));
+    let missing = parse(&pod("  containers:\n    - name: a\n      image: a\n      envFrom:\n        - configMapRef:\n            name: nope\n"));
+    assert!(missing.unwrap_err().to_string().contains("undefined ConfigMap nope"));
+    let volume = parse(&pod("  containers:\n    - name: a\n      image: a\n  volumes:\n    - name: data\n      persistentVolumeClaim:\n        claimName: data\n"));
+    assert!(volume.unwrap_err().to_string().contains("not supported"));
+    let mount = parse(&pod("  containers:\n    - name: a\n      image: a\n      volumeMounts:\n        - name: data\n          mountPath: /data\n"));
+    assert!(mount.unwrap_err().to_string().contains("undefined volume data"));
+    let secret = parse(&pod("  containers:\n    - name: a\n      image: a\n      env:\n        - name: KEY\n          valueFrom:\n            secretKeyRef:\n              name: s\n              key: k\n"));
+    assert!(secret.unwrap_err().to_string().contains("configMapKeyRef"));
+    let escape = parse(&format!(
+        "kind: ConfigMap\nmetadata:\n  name: cfg\ndata:\n  k: v\n---\n{}",
+        pod("  containers:\n    - name: a\n      image: a\n  volumes:\n    - name: ../../..\n      configMap:\n        name: cfg\n"),
+    ));
+    assert!(escape.unwrap_err().to_string().contains("Invalid volume name"));
+}
+
+#[tokio::test]
+async fn test_kube_up_and_down() {
+    let dir = TempDir::new().unwrap();
+    let state = TempDir::new().unwrap();
+    let pods = KubePod::parse(MANIFEST, dir.path(), state.path()).unwrap();
+    let web = &pods[0];
+    let images = HashMap::from([
+        ("server".to_string(), test_image(Some("src/image/demo.wasm"))),
+        ("sidecar".to_string(), test_image(Some("src/image/demo.wasm"))),
+    ]);
+    
+    web.up(state.path(), &images).await.unwrap();
+    
+    assert_eq!(std::fs::read_to_string(state.path().join("kube/web-app/config/conf/nginx.conf")).unwrap(), "listen 80;\n");
+    assert!(dir.path().join("static").is_dir());
+    let runtime = WasmRuntime::with_state_dir(state.path()).unwrap();
+    let server = runtime.inspect("web-app-server-1").await.unwrap();
+    assert_eq!(server.info.status, "exited");
+    assert!(runtime.inspect_network("web-app_default").await.is_ok());
+    let volumes = VolumeManager::with_dir(state.path().join("volumes")).unwrap();
+    assert!(volumes.inspect("web-app_cache").is_ok());
+    
+    web.down(state.path()).await.unwrap();
+    
+    let runtime = WasmRuntime::with_state_dir(state.path()).unwrap();
+    assert!(runtime.list_containers(true).await.unwrap().is_empty());
+    assert!(runtime.inspect_network("web-app_default").await.is_err());
+    assert!(volumes.inspect("web-app_cache").is_err());
+    assert!(!state.path().join("kube/web-app").exists());
+}
@@ -0,0 +1,1474 @@
+mod common;
+
+use common::test_image;
+use wasm_container::container::{Container, PortMapping};
+use wasm_container::image::PortConfig;
+use wasm_container::network::NetworkManager;
+use wasm_container::network::activation::ActivatedSocket;
+use wasm_container::network::bridge::BridgeListener;
+use wasm_container::network::firewall::{LinkRule, NetworkPolicy};
+use wasm_container::network::forward::ForwardOptions;
+use wasm_container::network::ingress::{self, IngressRule};
+use wasm_container::network::mesh::{Mesh, MeshConfig};
+use wasm_container::network::netem::Netem;
+use wasm_container::network::policy::{EgressDefault, EgressPolicy, HostRule};
+use wasm_container::network::proxy::{OutboundProxy, ProxySettings};
+use wasm_container::network::resolv::ResolvConf;
+use wasm_container::network::throttle::{NetworkStats, Rate};
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use tempfile::TempDir;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+
+fn ip(addr: &str) -> IpAddr {
+    addr.parse().unwrap()
+}
+
+#[test]
+fn test_resolv_conf_parse() {
+    let conf = ResolvConf::parse(
+        "# generated\nnameserver 10.0.0.2\nnameserver not-an-ip\nnameserver ::1\ndomain old.example\nsearch corp.example example.com\noptions ndots:2\n",
+    );
+    assert_eq!(conf.nameservers, vec![ip("10.0.0.2"), ip("::1")]);
+    assert_eq!(conf.search, vec!["corp.example".to_string(), "example.com".to_string()]);
+}
+
+#[test]
+fn test_resolv_conf_layering() {
+    let flags = ResolvConf::new(vec![ip("1.1.1.1")], vec![]);
+    let config = ResolvConf::new(vec![ip("9.9.9.9")], vec!["config.example".to_string()]);
+    let host = ResolvConf::new(vec![ip("10.0.0.2")], vec!["host.example".to_string()]);
+    
+    let conf = ResolvConf::layered(&[flags, config, host.clone()]);
+    assert_eq!(conf.to_string(), "search config.example\nnameserver 1.1.1.1\n");
+    
+    assert_eq!(ResolvConf::layered(&[ResolvConf::default(), host.clone()]), host);
+    assert_eq!(
+        ResolvConf::layered(&[]).to_string(),
+        "nameserver 8.8.8.8\nnameserver 8.8.4.4\n",
+    );
+}
+
+#[test]
+fn test_host_rule_parse() {
+    let rule: HostRule = "api.example.com:443".parse().unwrap();
+    assert_eq!((rule.host.as_str(), rule.port), ("api.example.com", Some(443)));
+    let rule: HostRule = "[2001:db8::1]:443".parse().unwrap();
+    assert_eq!((rule.host.as_str(), rule.port), ("2001:db8::1", Some(443)));
+    assert_eq!(rule.to_string(), "[2001:db8::1]:443");
+    let rule: HostRule = "2001:db8::/32".parse().unwrap();
+    assert_eq!((rule.host.as_str(), rule.port), ("2001:db8::/32", None));
+    let rule: HostRule = "10.0.0.0/8".parse().unwrap();
+    assert_eq!((rule.host.as_str(), rule.port), ("10.0.0.0/8", None));
+    
+    assert!(":443".parse::<HostRule>().is_err());
+    assert!("example.com:0".parse::<HostRule>().is_err());
+    assert!("example.com:http".parse::<HostRule>().is_err());
+    assert!("[::1".parse::<HostRule>().is_err());
+}
+
+#[tokio::test]
+async fn test_egress_policy() {
+    let addr = |addr: &str| addr.parse::<SocketAddr>().unwrap();
+    
+    let open = EgressPolicy::default().resolve().await.unwrap();
+    assert!(open.allows(addr("93.184.216.34:443")));
+    
+    let policy = EgressPolicy {
+        default: EgressDefault::Deny,
+        allow: vec!["10.0.0.0/8".parse().unwrap(), "localhost:443".parse().unwrap()],
+        deny: vec!["10.0.0.5".parse().unwrap()],
+    };
+    let policy = policy.resolve().await.unwrap();
+    assert!(policy.allows(addr("10.1.2.3:80")));
+    assert!(policy.allows(addr("[::ffff:10.1.2.3]:80")));
+    assert!(!policy.allows(addr("10.0.0.5:80")));
+    assert!(policy.allows(addr("127.0.0.1:443")));
+    assert!(!policy.allows(addr("127.0.0.1:80")));
+    assert!(!policy.allows(addr("93.184.216.34:443")));
+    
+    let unresolvable = EgressPolicy {
+        allow: vec!["no-such-host.invalid".parse().unwrap()],
+        ..EgressPolicy::default()
+    };
+    assert!(unresolvable.resolve().await.is_err());
+}
+
+#[tokio::test]
+async fn test_tcp_port_forward_proxies_to_guest_listener() {
+    // Stands in for the guest's listener on the container port.
+    let guest = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let container_port = guest.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = guest.accept().await.unwrap();
+            tokio::spawn(async move {
+                let mut buf = [0u8; 64];
+                while let Ok(n) = stream.read(&mut buf).await {
+                    if n == 0 || stream.write_all(&buf[..n]).await.is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+    });
+    
+    let mut container = Container::new(test_image(None), None, None, vec![]).unwrap();
+    container.add_port_mapping(0, container_port, "tcp".to_string());
+    
+    let manager = NetworkManager::new();
+    let network = manager.setup_container_network(&container).await.unwrap();
+    let host_port = network.port_mappings[0].host_port;
+    assert_ne!(host_port, 0);
+    
+    let mut client = TcpStream::connect(("127.0.0.1", host_port)).await.unwrap();
+    client.write_all(b"ping").await.unwrap();
+    let mut buf = [0u8; 4];
+    client.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"ping");
+    assert_eq!(manager.active_connections(host_port, "tcp").await, Some(1));
+    
+    // Cleanup stops the listener and closes connections in flight.
+    manager.cleanup_container_network(container.id()).await.unwrap();
+    assert_eq!(manager.active_connections(host_port, "tcp").await, None);
+    assert_eq!(client.read(&mut buf).await.unwrap_or(0), 0);
+    assert!(TcpStream::connect(("127.0.0.1", host_port)).await.is_err());
+}
+
+#[tokio::test]
+async fn test_tcp_port_forward_reaches_bridge_listener() {
+    let mut container = Container::new(test_image(None), None, None, vec![]).unwrap();
+    container.add_port_mapping(0, 8080, "tcp".to_string());
+    
+    let manager = NetworkManager::new();
+    let network = manager.setup_container_network(&container).await.unwrap();
+    let host_port = network.port_mappings[0].host_port;
+    
+    // A guest listening with net_listen never binds a host port itself.
+    let mut listener = manager.listen(container.id(), 8080).await.unwrap();
+    let mut client = TcpStream::connect(("127.0.0.1", host_port)).await.unwrap();
+    client.write_all(b"ping").await.unwrap();
+    
+    let (mut server, peer) = tokio::time::timeout(Duration::from_secs(5), listener.accept()).await.unwrap().unwrap();
+    assert_eq!(peer.ip(), ip("127.0.0.1"));
+    let mut buf = [0u8; 4];
+    server.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"ping");
+    server.write_all(b"pong").await.unwrap();
+    client.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"pong");
+    assert_eq!(manager.active_connections(host_port, "tcp").await, Some(1));
+}
+
+#[test]
+fn test_port_spec_parse() {
+    let ports = PortMapping::parse("8000-8002:9000-9002/udp").unwrap();
+    let ports: Vec<_> = ports.iter().map(|p| (p.host_port, p.container_port, p.protocol.as_str())).collect();
+    assert_eq!(ports, [(8000, 9000, "udp"), (8001, 9001, "udp"), (8002, 9002, "udp")]);
+    
+    let ports = PortMapping::parse("8080:80").unwrap();
+    assert_eq!((ports[0].host_port, ports[0].container_port, ports[0].protocol.as_str()), (8080, 80, "tcp"));
+    
+    // Host port 0 leaves the choice to the OS, for each port of a range too.
+    let ports = PortMapping::parse("0:8000-8001").unwrap();
+    let ports: Vec<_> = ports.iter().map(|p| (p.host_port, p.container_port)).collect();
+    assert_eq!(ports, [(0, 8000), (0, 8001)]);
+    
+    let err = PortMapping::parse("8000-8010:8000-8005").unwrap_err();
+    assert!(err.to_string().contains("differ in length"), "{}", err);
+    for invalid in ["80", "8080:80/sctp", "8010-8000:80-90", "x:80", "8080:", "65536:80"] {
+        assert!(PortMapping::parse(invalid).is_err(), "{}", invalid);
+    }
+}
+
+#[tokio::test]
+async fn test_port_range_publishing() {
+    // Three consecutive free ports, the last held by someone else.
+    let (base, taken) = loop {
+        let first = TcpListener::bind("0.0.0.0:0").await.unwrap();
+        let base = first.local_addr().unwrap().port();
+        if base > 65533 {
+            continue;
+        }
+        if std::net::TcpListener::bind(("0.0.0.0", base + 1)).is_err() {
+            continue;
+        }
+        if let Ok(taken) = TcpListener::bind(("0.0.0.0", base + 2)).await {
+            break (base, taken);
+        }
+    };
+    
+    let mut container = Container::new(test_image(None), None, None, vec![]).unwrap();
+    for port_map in PortMapping::parse(&format!("{}-{}:8000-8002", base, base + 2)).unwrap() {
+        container.add_port_mapping(port_map.host_port, port_map.container_port, port_map.protocol);
+    }
+    
+    let manager = NetworkManager::new();
+    let err = manager.setup_container_network(&container).await.unwrap_err();
+    assert!(err.to_string().contains(&format!("Host port {}/tcp is already in use", base + 2)), "{}", err);
+    // Nothing was left published.
+    assert_eq!(manager.active_connections(base, "tcp").await, None);
+    std::net::TcpListener::bind(("0.0.0.0", base)).unwrap();
+    
+    drop(taken);
+    let network = manager.setup_container_network(&container).await.unwrap();
+    let published: Vec<_> = network.port_mappings.iter().map(|p| (p.host_port, p.container_port)).collect();
+    assert_eq!(published, [(base, 8000), (base + 1, 8001), (base + 2, 8002)]);
+    assert_eq!(manager.active_connections(base + 1, "tcp").await, Some(0));
+}
+
+#[tokio::test]
+async fn test_publish_exposed_ports() {
+    let mut image = test_image(None);
+    for port in ["8080/tcp", "53/udp", "443"] {
+        image.config.exposed_ports.insert(port.to_string(), PortConfig { protocol: String::new() });
+    }
+    let mut container = Container::new(image, None, None, vec![]).unwrap();
+    container.add_port_mapping(18443, 443, "tcp".to_string());
+    container.publish_exposed_ports().unwrap();
+    
+    // Ports published explicitly keep their host port.
+    let ports: Vec<_> = container.network_config().ports.iter().map(|p| p.to_string()).collect();
+    assert_eq!(ports, ["18443->443/tcp", "0->53/udp", "0->8080/tcp"]);
+    
+    let manager = NetworkManager::new();
+    let mut container = Container::new(test_image(None), None, None, vec![]).unwrap();
+    container.add_port_mapping(0, 8080, "tcp".to_string());
+    container.add_port_mapping(0, 53, "udp".to_string());
+    let network = manager.setup_container_network(&container).await.unwrap();
+    for port in &network.port_mappings {
+        assert_ne!(port.host_port, 0);
+        assert!(manager.active_connections(port.host_port, &port.protocol).await.is_some());
+    }
+}
+
+#[tokio::test]
+async fn test_port_conflicts() {
+    let manager = NetworkManager::new();
+    let mut web = Container::new(test_image(None), None, None, vec![]).unwrap();
+    web.add_port_mapping(0, 80, "tcp".to_string());
+    let host_port = manager.setup_container_network(&web).await.unwrap().port_mappings[0].host_port;
+    
+    // Ports taken by a container name it; ones taken elsewhere say so.
+    let mut api = Container::new(test_image(None), None, None, vec![]).unwrap();
+    api.add_port_mapping(host_port, 8080, "tcp".to_string());
+    let error = manager.setup_container_network(&api).await.unwrap_err().to_string();
+    assert!(error.contains(&format!("Host port {}/tcp is already published by container {}", host_port, web.id())), "{}", error);
+    let taken = std::net::TcpListener::bind("0.0.0.0:0").unwrap();
+    let taken_port = taken.local_addr().unwrap().port();
+    let mut other = Container::new(test_image(None), None, None, vec![]).unwrap();
+    other.add_port_mapping(taken_port, 8080, "tcp".to_string());
+    let error = manager.setup_container_network(&other).await.unwrap_err().to_string();
+    assert!(error.contains("in use by another process"), "{}", error);
+    
+    // With retries, the next free port is taken instead.
+    api.set_publish_retry(true);
+    let retried = manager.setup_container_network(&api).await.unwrap().port_mappings[0].host_port;
+    assert!(retried > host_port && retried <= host_port + 100, "{} after {}", retried, host_port);
+    assert_eq!(manager.active_connections(retried, "tcp").await, Some(0));
+}
+
+#[tokio::test]
+async fn test_activated_sockets_are_published() {
+    // Stands in for the listener systemd bound before starting us.
+    let activated = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    activated.set_nonblocking(true).unwrap();
+    let host_port = activated.local_addr().unwrap().port();
+    
+    let mut container = Container::new(test_image(None), None, None, vec![]).unwrap();
+    container.add_port_mapping(host_port, 8080, "tcp".to_string());
+    
+    let manager = NetworkManager::new();
+    manager.adopt_activated_sockets(vec![ActivatedSocket::Tcp(activated)]).await;
+    let network = manager.setup_container_network(&container).await.unwrap();
+    assert_eq!(network.port_mappings[0].host_port, host_port);
+    
+    let mut listener = manager.listen(container.id(), 8080).await.unwrap();
+    let mut client = TcpStream::connect(("127.0.0.1", host_port)).await.unwrap();
+    client.write_all(b"ping").await.unwrap();
+    let (mut server, _) = tokio::time::timeout(Duration::from_secs(5), listener.accept()).await.unwrap().unwrap();
+    let mut buf = [0u8; 4];
+    server.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"ping");
+    
+    // A socket the container doesn't publish fails its start.
+    let stray = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    let stray_port = stray.local_addr().unwrap().port();
+    manager.adopt_activated_sockets(vec![ActivatedSocket::Udp(stray)]).await;
+    let other = Container::new(test_image(None), None, None, vec![]).unwrap();
+    let err = manager.setup_container_network(&other).await.unwrap_err();
+    assert!(err.to_string().contains(&format!("port {}/udp isn't published", stray_port)), "{}", err);
+}
+
+#[test]
+fn test_proxy_settings() {
+    let configured = ProxySettings {
+        http_proxy: Some("http://proxy:3128".to_string()),
+        no_proxy: Some("localhost,.corp".to_string()),
+        ..ProxySettings::default()
+    };
+    let own = ProxySettings {
+        https_proxy: Some("http://secure:3129".to_string()),
+        no_proxy: Some("*".to_string()),
+        ..ProxySettings::default()
+    };
+    let proxy = own.or(&configured);
+    assert_eq!(proxy.http_proxy.as_deref(), Some("http://proxy:3128"));
+    assert_eq!(proxy.no_proxy.as_deref(), Some("*"));
+    
+    let env = proxy.env();
+    assert!(env.contains(&("HTTPS_PROXY".to_string(), "http://secure:3129".to_string())));
+    assert!(env.contains(&("https_proxy".to_string(), "http://secure:3129".to_string())));
+    assert!(env.contains(&("no_proxy".to_string(), "*".to_string())));
+    assert_eq!(env.len(), 6);
+    
+    assert!(!configured.outbound().unwrap().unwrap().bypasses(ip("10.0.0.1")));
+    assert!(proxy.outbound().unwrap().unwrap().bypasses(ip("10.0.0.1")));
+    assert!(ProxySettings::default().outbound().unwrap().is_none());
+    for invalid in ["https://proxy:3128", "socks5://proxy:1080", "http://proxy", "proxy:port"] {
+        assert!(OutboundProxy::new(invalid, None).is_err(), "{}", invalid);
+    }
+    
+    let proxy = OutboundProxy::new("proxy:3128/", Some("192.168.0.0/16, 2001:db8::1")).unwrap();
+    assert!(proxy.bypasses(ip("192.168.4.4")));
+    assert!(proxy.bypasses(ip("::ffff:192.168.4.4")));
+    assert!(proxy.bypasses(ip("2001:db8::1")));
+    assert!(!proxy.bypasses(ip("192.169.0.1")));
+}
+
+#[tokio::test]
+async fn test_outbound_proxy_tunnels() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        let mut requests = Vec::new();
+        for reply in ["HTTP/1.1 200 OK\r\n\r\n", "HTTP/1.1 407 Proxy Authentication Required\r\n\r\n"] {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            while !request.ends_with(b"\r\n\r\n") {
+                request.push(stream.read_u8().await.unwrap());
+            }
+            requests.push(String::from_utf8(request).unwrap());
+            stream.write_all(reply.as_bytes()).await.unwrap();
+            stream.write_all(b"data").await.unwrap();
+        }
+        requests
+    });
+    
+    let proxy = OutboundProxy::new(&format!("http://user:secret@{}", addr), None).unwrap();
+    // What the proxy sends after its reply belongs to the tunnel.
+    let mut tunnel = proxy.connect("[2001:db8::1]:443".parse().unwrap()).await.unwrap();
+    let mut buf = [0u8; 4];
+    tunnel.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"data");
+    
+    let err = proxy.connect("192.0.2.1:80".parse().unwrap()).await.unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+    
+    let requests = server.await.unwrap();
+    assert_eq!(
+        requests[0],
+        "CONNECT [2001:db8::1]:443 HTTP/1.1\r\nHost: [2001:db8::1]:443\r\nProxy-Authorization: Basic dXNlcjpzZWNyZXQ=\r\n\r\n"
+    );
+}
+
+#[test]
+fn test_rate_parse() {
+    assert_eq!("10mbit".parse::<Rate>().unwrap().bytes_per_sec(), 1_250_000);
+    assert_eq!("500kbps".parse::<Rate>().unwrap().bytes_per_sec(), 500_000);
+    assert_eq!("1.5Gbit".parse::<Rate>().unwrap().bytes_per_sec(), 187_500_000);
+    assert_eq!("10mbit".parse::<Rate>().unwrap().to_string(), "10mbit");
+    assert_eq!("3bps".parse::<Rate>().unwrap().to_string(), "3bps");
+    for invalid in ["10", "fast", "0mbit", "-1kbit", "mbit"] {
+        assert!(invalid.parse::<Rate>().is_err(), "{}", invalid);
+    }
+}
+
+#[tokio::test]
+async fn test_port_forward_throttling() {
+    let mut container = Container::new(test_image(None), None, None, vec![]).unwrap();
+    container.add_port_mapping(0, 8080, "tcp".to_string());
+    container.set_network_rate("1mbps".parse().unwrap());
+    
+    let manager = NetworkManager::new();
+    let network = manager.setup_container_network(&container).await.unwrap();
+    let host_port = network.port_mappings[0].host_port;
+    let mut listener = manager.listen(container.id(), 8080).await.unwrap();
+    
+    // A tenth of a second's worth goes through at once, the rest at 1MB/s.
+    let payload = vec![7u8; 500_000];
+    let started = std::time::Instant::now();
+    let mut client = TcpStream::connect(("127.0.0.1", host_port)).await.unwrap();
+    let sender = tokio::spawn(async move {
+        client.write_all(&payload).await.unwrap();
+        client
+    });
+    let (mut server, _) = tokio::time::timeout(Duration::from_secs(5), listener.accept()).await.unwrap().unwrap();
+    let mut received = vec![0u8; 500_000];
+    server.read_exact(&mut received).await.unwrap();
+    assert!(started.elapsed() >= Duration::from_millis(350), "took {:?}", started.elapsed());
+    
+    let mut client = sender.await.unwrap();
+    server.write_all(b"done").await.unwrap();
+    let mut buf = [0u8; 4];
+    client.read_exact(&mut buf).await.unwrap();
+    
+    let traffic = manager.traffic(container.id()).await.unwrap();
+    assert_eq!(traffic.received(), 500_000);
+    assert_eq!(traffic.sent(), 4);
+    assert_eq!(traffic.limit(), Some("1mbps".parse().unwrap()));
+    
+    let stats = traffic.stats(&NetworkStats::default(), Duration::from_secs(2));
+    assert_eq!((stats.rx_bytes_per_sec, stats.tx_bytes_per_sec), (250_000, 2));
+    
+    manager.cleanup_container_network(container.id()).await.unwrap();
+    assert!(manager.traffic(container.id()).await.is_none());
+}
+
+#[tokio::test]
+async fn test_port_stats_per_forward() {
+    let mut container = Container::new(test_image(None), None, None, vec![]).unwrap();
+    container.add_port_mapping(0, 8080, "tcp".to_string());
+    container.add_port_mapping(0, 9090, "tcp".to_string());
+    
+    let manager = NetworkManager::new();
+    let network = manager.setup_container_network(&container).await.unwrap();
+    let host_port = network.port_mappings[0].host_port;
+    let mut listener = manager.listen(container.id(), 8080).await.unwrap();
+    
+    let mut client = TcpStream::connect(("127.0.0.1", host_port)).await.unwrap();
+    client.write_all(b"hello").await.unwrap();
+    let (mut server, _) = tokio::time::timeout(Duration::from_secs(5), listener.accept()).await.unwrap().unwrap();
+    let mut buf = [0u8; 5];
+    server.read_exact(&mut buf).await.unwrap();
+    server.write_all(b"hi").await.unwrap();
+    let mut buf = [0u8; 2];
+    client.read_exact(&mut buf).await.unwrap();
+    
+    // Only the port that carried the connection counts it, and the
+    // container's totals include it too.
+    let stats = manager.port_stats(container.id()).await;
+    let used = stats.iter().find(|stats| stats.container_port == 8080).unwrap();
+    assert_eq!((used.host_port, used.connections, used.rx_bytes, used.tx_bytes), (host_port, 1, 5, 2));
+    let unused = stats.iter().find(|stats| stats.container_port == 9090).unwrap();
+    assert_eq!((unused.connections, unused.rx_bytes, unused.tx_bytes), (0, 0, 0));
+    let traffic = manager.traffic(container.id()).await.unwrap();
+    assert_eq!((traffic.received(), traffic.sent()), (5, 2));
+    
+    drop(client);
+    drop(server);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(manager.port_stats(container.id()).await[0].connections, 0);
+    
+    manager.cleanup_container_network(container.id()).await.unwrap();
+    assert!(manager.port_stats(container.id()).await.is_empty());
+}
+
+#[test]
+fn test_netem_parse() {
+    let netem: Netem = "delay=100ms,jitter=10ms,loss=1.5%,rate=1mbit".parse().unwrap();
+    assert_eq!(netem.delay, Duration::from_millis(100));
+    assert_eq!(netem.jitter, Duration::from_millis(10));
+    assert_eq!(netem.loss, 1.5);
+    assert_eq!(netem.rate, Some("1mbit".parse().unwrap()));
+    assert_eq!(netem.to_string(), "delay=100ms,jitter=10ms,loss=1.5%,rate=1mbit");
+    assert_eq!("delay=250us".parse::<Netem>().unwrap().to_string(), "delay=250us");
+    for _ in 0..100 {
+        let delay = netem.next_delay();
+        assert!(delay >= Duration::from_millis(90) && delay <= Duration::from_millis(110), "{:?}", delay);
+    }
+    
+    let never: Netem = "loss=0%".parse().unwrap();
+    let always: Netem = "loss=100".parse().unwrap();
+    assert!((0..100).all(|_| !never.lose() && always.lose()));
+    for invalid in ["delay=fast", "delay=100", "loss=150%", "latency=1ms", "rate=1", "delay"] {
+        assert!(invalid.parse::<Netem>().is_err(), "{}", invalid);
+    }
+}
+
+#[tokio::test]
+async fn test_netem_on_port_forwards() {
+    let mut container = Container::new(test_image(None), None, None, vec![]).unwrap();
+    container.add_port_mapping(0, 8080, "tcp".to_string());
+    container.set_netem("delay=150ms,loss=50%".parse().unwrap());
+    
+    let manager = NetworkManager::new();
+    let network = manager.setup_container_network(&container).await.unwrap();
+    let host_port = network.port_mappings[0].host_port;
+    let mut listener = manager.listen(container.id(), 8080).await.unwrap();
+    
+    // A round trip takes the delay each way, and lost chunks of a stream
+    // still arrive intact.
+    let started = std::time::Instant::now();
+    let mut client = TcpStream::connect(("127.0.0.1", host_port)).await.unwrap();
+    let message: Vec<u8> = (0..100_000u32).map(|i| i as u8).collect();
+    client.write_all(&message).await.unwrap();
+    let (mut server, _) = tokio::time::timeout(Duration::from_secs(5), listener.accept()).await.unwrap().unwrap();
+    let mut received = vec![0u8; message.len()];
+    tokio::time::timeout(Duration::from_secs(10), server.read_exact(&mut received)).await.unwrap().unwrap();
+    assert!(received == message);
+    server.write_all(b"done").await.unwrap();
+    let mut buf = [0u8; 4];
+    client.read_exact(&mut buf).await.unwrap();
+    assert!(started.elapsed() >= Duration::from_millis(300), "took {:?}", started.elapsed());
+    
+    // Lost datagrams never arrive.
+    let guest = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let mut container = Container::new(test_image(None), None, None, vec![]).unwrap();
+    container.add_port_mapping(0, guest.local_addr().unwrap().port(), "udp".to_string());
+    container.set_netem("loss=100%".parse().unwrap());
+    let network = manager.setup_container_network(&container).await.unwrap();
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    client.send_to(b"ping", ("127.0.0.1", network.port_mappings[0].host_port)).await.unwrap();
+    let mut buf = [0u8; 64];
+    assert!(tokio::time::timeout(Duration::from_millis(300), guest.recv_from(&mut buf)).await.is_err());
+}
+
+#[test]
+fn test_port_options_parse() {
+    let options: ForwardOptions = "idle-timeout=5m,keepalive=30s,max-connections=100".parse().unwrap();
+    assert_eq!(options.idle_timeout, Some(Duration::from_secs(300)));
+    assert_eq!(options.keepalive, Some(Duration::from_secs(30)));
+    assert_eq!(options.max_connections, Some(100));
+    assert_eq!(options.to_string(), "idle-timeout=300s,keepalive=30s,max-connections=100");
+    assert_eq!("idle-timeout=1500ms".parse::<ForwardOptions>().unwrap().to_string(), "idle-timeout=1500ms");
+    for invalid in ["idle=5m", "idle-timeout=5", "keepalive=0s", "max-connections=0", "max-connections=-1"] {
+        assert!(invalid.parse::<ForwardOptions>().is_err(), "{}", invalid);
+    }
+    
+    let defaults: ForwardOptions = "idle-timeout=1m,max-connections=10".parse().unwrap();
+    let merged = "max-connections=2".parse::<ForwardOptions>().unwrap().or(&defaults);
+    assert_eq!(merged.to_string(), "idle-timeout=60s,max-connections=2");
+}
+
+#[tokio::test]
+async fn test_port_options_on_forwards() {
+    let mut container = Container::new(test_image(None), None, None, vec![]).unwrap();
+    container.add_port_mapping(0, 8080, "tcp".to_string());
+    container.set_port_options("idle-timeout=300ms,keepalive=10s".parse().unwrap());
+    
+    let manager = NetworkManager::new().with_forward_defaults("max-connections=1".parse().unwrap());
+    let host_port = manager.setup_container_network(&container).await.unwrap().port_mappings[0].host_port;
+    let mut listener = manager.listen(container.id(), 8080).await.unwrap();
+    
+    let mut first = TcpStream::connect(("127.0.0.1", host_port)).await.unwrap();
+    first.write_all(b"ping").await.unwrap();
+    let (mut server, _) = tokio::time::timeout(Duration::from_secs(5), listener.accept()).await.unwrap().unwrap();
+    let mut buf = [0u8; 4];
+    server.read_exact(&mut buf).await.unwrap();
+    
+    // Over the limit, connections are closed straight away.
+    let mut second = TcpStream::connect(("127.0.0.1", host_port)).await.unwrap();
+    let closed = tokio::time::timeout(Duration::from_secs(5), second.read(&mut buf)).await.unwrap();
+    assert!(matches!(closed, Ok(0) | Err(_)), "{:?}", closed);
+    
+    // Idle ones are closed once the timeout passes, freeing their slot.
+    let closed = tokio::time::timeout(Duration::from_secs(5), first.read(&mut buf)).await.unwrap();
+    assert!(matches!(closed, Ok(0) | Err(_)), "{:?}", closed);
+    assert_eq!(server.read(&mut buf).await.unwrap(), 0);
+    assert_eq!(manager.active_connections(host_port, "tcp").await, Some(0));
+    let _third = TcpStream::connect(("127.0.0.1", host_port)).await.unwrap();
+    tokio::time::timeout(Duration::from_secs(5), listener.accept()).await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_capture_records_relayed_traffic() {
+    let guest = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let udp_port = guest.local_addr().unwrap().port();
+    let mut container = Container::new(test_image(None), None, None, vec![]).unwrap();
+    container.add_port_mapping(0, 8080, "tcp".to_string());
+    container.add_port_mapping(0, udp_port, "udp".to_string());
+    let manager = NetworkManager::new();
+    let network = manager.setup_container_network(&container).await.unwrap();
+    let mut listener = manager.listen(container.id(), 8080).await.unwrap();
+    
+    // Connections open before the capture starts are picked up from there.
+    let mut client = TcpStream::connect(("127.0.0.1", network.port_mappings[0].host_port)).await.unwrap();
+    let client_port = client.local_addr().unwrap().port();
+    let (mut server, _) = tokio::time::timeout(Duration::from_secs(5), listener.accept()).await.unwrap().unwrap();
+    let dir = TempDir::new().unwrap();
+    let output = dir.path().join("out.pcap");
+    manager.start_capture(container.id(), &output).await.unwrap();
+    
+    client.write_all(b"ping").await.unwrap();
+    let mut buf = [0u8; 4];
+    server.read_exact(&mut buf).await.unwrap();
+    server.write_all(b"pong").await.unwrap();
+    client.read_exact(&mut buf).await.unwrap();
+    let udp_client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    udp_client.send_to(b"hello", ("127.0.0.1", network.port_mappings[1].host_port)).await.unwrap();
+    tokio::time::timeout(Duration::from_secs(5), guest.recv_from(&mut buf)).await.unwrap().unwrap();
+    drop((client, server));
+    
+    let mut frames = Vec::new();
+    for _ in 0..50 {
+        frames = pcap_frames(&std::fs::read(&output).unwrap());
+        if frames.len() >= 8 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    // A made-up handshake, both chunks, the datagram and both FINs.
+    assert_eq!(frames.len(), 8, "{:?}", frames);
+    let ip_address = match network.ip_address {
+        IpAddr::V4(ip) => ip.octets(),
+        IpAddr::V6(_) => panic!("expected an IPv4 address"),
+    };
+    for frame in &frames {
+        assert_eq!(frame[12..14], [0x08, 0x00]);
+        let ip_header = &frame[14..34];
+        let sum: u32 = ip_header.chunks(2).map(|word| u32::from(word[0]) << 8 | u32::from(word[1])).sum();
+        assert_eq!((sum & 0xffff) + (sum >> 16), 0xffff);
+    }
+    let flags: Vec<u8> = frames.iter().filter(|frame| frame[23] == 6).map(|frame| frame[47]).collect();
+    assert_eq!(flags, [0x02, 0x12, 0x10, 0x18, 0x18, 0x11, 0x11]);
+    let ping = &frames[3];
+    assert_eq!(ping[26..30], [127, 0, 0, 1]);
+    assert_eq!(ping[30..34], ip_address);
+    assert_eq!(ping[34..36], client_port.to_be_bytes());
+    assert_eq!(ping[36..38], 8080u16.to_be_bytes());
+    assert_eq!(&ping[54..], b"ping");
+    assert_eq!(frames[4][26..30], ip_address);
+    assert_eq!(&frames[4][54..], b"pong");
+    let datagram = frames.iter().find(|frame| frame[23] == 17).unwrap();
+    assert_eq!(datagram[36..38], udp_port.to_be_bytes());
+    assert_eq!(&datagram[42..], b"hello");
+    
+    // Once stopped, nothing more is recorded.
+    manager.stop_capture(container.id()).await;
+    udp_client.send_to(b"again", ("127.0.0.1", network.port_mappings[1].host_port)).await.unwrap();
+    tokio::time::timeout(Duration::from_secs(5), guest.recv_from(&mut buf)).await.unwrap().unwrap();
+    assert_eq!(pcap_frames(&std::fs::read(&output).unwrap()).len(), 8);
+}
+
+#[tokio::test]
+async fn test_mdns_advertises_published_ports() {
+    let port = UdpSocket::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap().port();
+    let mut container = Container::new(test_image(None), None, None, vec![]).unwrap();
+    container.add_port_mapping(0, 443, "tcp".to_string());
+    container.add_port_mapping(0, 53, "udp".to_string());
+    container.set_mdns(true);
+    
+    let manager = NetworkManager::new().with_mdns_addr(SocketAddr::from(([127, 0, 0, 1], port)));
+    let network = manager.setup_container_network(&container).await.unwrap();
+    let host_port = network.port_mappings[0].host_port;
+    
+    // A resolver asking from another port than mDNS's gets a plain reply.
+    let mut query = vec![0x12, 0x34, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+    query.extend(dns_name("_https._tcp.local"));
+    query.extend_from_slice(&[0, 12, 0, 1]);
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    client.send_to(&query, ("127.0.0.1", port)).await.unwrap();
+    let mut buf = [0u8; 1500];
+    let (n, _) = tokio::time::timeout(Duration::from_secs(5), client.recv_from(&mut buf)).await.unwrap().unwrap();
+    let reply = &buf[..n];
+    assert_eq!(&reply[..2], &[0x12, 0x34]);
+    // The service, and the SRV record giving its host port and host name.
+    let instance = dns_name(&format!("{}._https._tcp.local", container.id()));
+    assert!(reply.windows(instance.len()).any(|window| window == instance.as_slice()));
+    let mut srv = vec![0, 0, 0, 0];
+    srv.extend_from_slice(&host_port.to_be_bytes());
+    srv.extend(dns_name(&format!("{}.local", container.id())));
+    assert!(reply.windows(srv.len()).any(|window| window == srv.as_slice()));
+    
+    // UDP ports aren't advertised.
+    let mut query = vec![0x56, 0x78, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+    query.extend(dns_name("_http._tcp.local"));
+    query.extend_from_slice(&[0, 12, 0, 1]);
+    client.send_to(&query, ("127.0.0.1", port)).await.unwrap();
+    assert!(tokio::time::timeout(Duration::from_millis(300), client.recv_from(&mut buf)).await.is_err());
+    
+    // Nor are the services of stopped containers.
+    manager.cleanup_container_network(container.id()).await.unwrap();
+    let mut query = vec![0x9a, 0xbc, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+    query.extend(dns_name("_https._tcp.local"));
+    query.extend_from_slice(&[0, 12, 0, 1]);
+    client.send_to(&query, ("127.0.0.1", port)).await.unwrap();
+    assert!(tokio::time::timeout(Duration::from_millis(300), client.recv_from(&mut buf)).await.is_err());
+}
+
+fn dns_name(name: &str) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    for label in name.split('.') {
+        encoded.push(label.len() as u8);
+        encoded.extend_from_slice(label.as_bytes());
+    }
+    encoded.push(0);
+    encoded
+}
+
+#[tokio::test]
+async fn test_udp_port_forward_relays_datagrams_per_client() {
+    // Stands in for the guest's socket on the container port.
+    let guest = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let container_port = guest.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        let mut buf = [0u8; 64];
+        while let Ok((n, peer)) = guest.recv_from(&mut buf).await {
+            let _ = guest.send_to(&buf[..n], peer).await;
+        }
+    });
+    
+    let mut container = Container::new(test_image(None), None, None, vec![]).unwrap();
+    container.add_port_mapping(0, container_port, "udp".to_string());
+    
+    let manager = NetworkManager::new().with_udp_idle_timeout(Duration::from_millis(200));
+    let network = manager.setup_container_network(&container).await.unwrap();
+    let host_port = network.port_mappings[0].host_port;
+    assert_ne!(host_port, 0);
+    
+    // Each client only gets its own replies back.
+    let first = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let second = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    first.send_to(b"ping", ("127.0.0.1", host_port)).await.unwrap();
+    second.send_to(b"pong", ("127.0.0.1", host_port)).await.unwrap();
+    
+    let mut buf = [0u8; 64];
+    let (n, from) = tokio::time::timeout(Duration::from_secs(5), first.recv_from(&mut buf)).await.unwrap().unwrap();
+    assert_eq!(&buf[..n], b"ping");
+    assert_eq!(from.port(), host_port);
+    let (n, _) = tokio::time::timeout(Duration::from_secs(5), second.recv_from(&mut buf)).await.unwrap().unwrap();
+    assert_eq!(&buf[..n], b"pong");
+    assert_eq!(manager.active_connections(host_port, "udp").await, Some(2));
+    
+    // Idle clients are forgotten.
+    tokio::time::sleep(Duration::from_millis(600)).await;
+    assert_eq!(manager.active_connections(host_port, "udp").await, Some(0));
+    
+    manager.cleanup_container_network(container.id()).await.unwrap();
+    assert_eq!(manager.active_connections(host_port, "udp").await, None);
+    UdpSocket::bind(("0.0.0.0", host_port)).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_udp_port_forward_with_tiny_idle_timeout() {
+    let guest = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let mut container = Container::new(test_image(None), None, None, vec![]).unwrap();
+    container.add_port_mapping(0, guest.local_addr().unwrap().port(), "udp".to_string());
+    
+    let manager = NetworkManager::new().with_udp_idle_timeout(Duration::from_nanos(1));
+    let host_port = manager.setup_container_network(&container).await.unwrap().port_mappings[0].host_port;
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    client.send_to(b"ping", ("127.0.0.1", host_port)).await.unwrap();
+    let mut buf = [0u8; 64];
+    let (n, _) = tokio::time::timeout(Duration::from_secs(5), guest.recv_from(&mut buf)).await.unwrap().unwrap();
+    assert_eq!(&buf[..n], b"ping");
+}
+
+#[tokio::test]
+async fn test_tcp_and_udp_share_a_host_port() {
+    // Stand in for the guest's TCP and UDP sockets on one container port.
+    let (tcp_guest, udp_guest) = loop {
+        let tcp = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        if let Ok(udp) = UdpSocket::bind(tcp.local_addr().unwrap()).await {
+            break (tcp, udp);
+        }
+    };
+    let container_port = tcp_guest.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        let (mut stream, _) = tcp_guest.accept().await.unwrap();
+        let mut buf = [0u8; 64];
+        while let Ok(n) = stream.read(&mut buf).await {
+            if n == 0 || stream.write_all(&buf[..n]).await.is_err() {
+                break;
+            }
+        }
+    });
+    tokio::spawn(async move {
+        let mut buf = [0u8; 64];
+        while let Ok((n, peer)) = udp_guest.recv_from(&mut buf).await {
+            let _ = udp_guest.send_to(&buf[..n], peer).await;
+        }
+    });
+    let host_port = loop {
+        let tcp = std::net::TcpListener::bind("0.0.0.0:0").unwrap();
+        let port = tcp.local_addr().unwrap().port();
+        if std::net::UdpSocket::bind(("0.0.0.0", port)).is_ok() {
+            break port;
+        }
+    };
+    
+    let mut container = Container::new(test_image(None), None, None, vec![]).unwrap();
+    container.add_port_mapping(host_port, container_port, "tcp".to_string());
+    container.add_port_mapping(host_port, container_port, "udp".to_string());
+    let manager = NetworkManager::new();
+    manager.setup_container_network(&container).await.unwrap();
+    
+    // Publishing the UDP side leaves the TCP forward running.
+    let mut client = TcpStream::connect(("127.0.0.1", host_port)).await.unwrap();
+    client.write_all(b"ping").await.unwrap();
+    let mut buf = [0u8; 4];
+    tokio::time::timeout(Duration::from_secs(5), client.read_exact(&mut buf)).await.unwrap().unwrap();
+    assert_eq!(&buf, b"ping");
+    
+    let udp_client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    udp_client.send_to(b"pong", ("127.0.0.1", host_port)).await.unwrap();
+    let mut buf = [0u8; 64];
+    let (n, _) = tokio::time::timeout(Duration::from_secs(5), udp_client.recv_from(&mut buf)).await.unwrap().unwrap();
+    assert_eq!(&buf[..n], b"pong");
+    assert_eq!(manager.active_connections(host_port, "tcp").await, Some(1));
+    assert_eq!(manager.active_connections(host_port, "udp").await, Some(1));
+    let stats: Vec<_> = manager.port_stats(container.id()).await.into_iter().map(|stats| stats.protocol).collect();
+    assert_eq!(stats, ["tcp", "udp"]);
+    
+    manager.cleanup_container_network(container.id()).await.unwrap();
+    assert_eq!(manager.active_connections(host_port, "tcp").await, None);
+    assert_eq!(manager.active_connections(host_port, "udp").await, None);
+}
+
+#[tokio::test]
+async fn test_embedded_dns_resolves_peers_and_forwards_other_names() {
+    // An upstream nameserver that answers everything with NXDOMAIN.
+    let upstream = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let upstream_addr = upstream.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut buf = [0u8; 512];
+        while let Ok((n, peer)) = upstream.recv_from(&mut buf).await {
+            buf[2] |= 0x80;
+            buf[3] = 3;
+            let _ = upstream.send_to(&buf[..n], peer).await;
+        }
+    });
+    
+    let manager = NetworkManager::new();
+    let web = Container::new(test_image(None), None, None, vec![]).unwrap();
+    let db = Container::new(test_image(None), None, None, vec![]).unwrap();
+    manager.setup_container_network(&web).await.unwrap();
+    let db_network = manager.setup_container_network(&db).await.unwrap();
+    
+    let server = manager.start_dns("bridge", "127.0.0.1:0".parse().unwrap(), &[upstream_addr]).await.unwrap();
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    client.connect(server).await.unwrap();
+    
+    let reply = dns_exchange(&client, 1, &db.id().to_uppercase(), 1).await;
+    assert_eq!(reply[3] & 0x0f, 0);
+    assert_eq!(u16::from_be_bytes([reply[6], reply[7]]), 1);
+    let IpAddr::V4(ip) = db_network.ip_address else { panic!("expected an IPv4 address") };
+    assert_eq!(reply[reply.len() - 4..], ip.octets());
+    
+    // Known names have no records of other types.
+    let reply = dns_exchange(&client, 2, db.id(), 28).await;
+    assert_eq!(reply[3] & 0x0f, 0);
+    assert_eq!(u16::from_be_bytes([reply[6], reply[7]]), 0);
+    
+    let reply = dns_exchange(&client, 3, "example.com", 1).await;
+    assert_eq!(reply[3] & 0x0f, 3);
+    
+    // Containers that left aren't answered for anymore.
+    manager.cleanup_container_network(db.id()).await.unwrap();
+    let reply = dns_exchange(&client, 4, db.id(), 1).await;
+    assert_eq!(reply[3] & 0x0f, 3);
+    
+    // The server goes away with the network's last container.
+    manager.cleanup_container_network(web.id()).await.unwrap();
+    UdpSocket::bind(server).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_bridge_connects_containers_sharing_a_network() {
+    let manager = NetworkManager::new();
+    let web = Container::new(test_image(None), None, None, vec![]).unwrap();
+    let cache = Container::new(test_image(None), None, None, vec![]).unwrap();
+    manager.setup_container_network(&web).await.unwrap();
+    let cache_network = manager.setup_container_network(&cache).await.unwrap();
+    
+    let mut listener = manager.listen(cache.id(), 6379).await.unwrap();
+    assert_eq!(manager.listen(cache.id(), 6379).await.unwrap_err().kind(), std::io::ErrorKind::AddrInUse);
+    
+    let cache_addr = SocketAddr::new(cache_network.ip_address, 6379);
+    let mut client = manager.connect(web.id(), cache_addr).await.unwrap();
+    client.write_all(b"PING").await.unwrap();
+    let (mut server, _) = listener.accept().await.unwrap();
+    let mut buf = [0u8; 4];
+    server.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"PING");
+    server.write_all(b"PONG").await.unwrap();
+    client.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"PONG");
+    
+    let refused = manager.connect(web.id(), SocketAddr::new(cache_network.ip_address, 6380)).await;
+    assert_eq!(refused.unwrap_err().kind(), std::io::ErrorKind::ConnectionRefused);
+    
+    // Once the cache is gone, its address can't be reached anymore.
+    drop(listener);
+    manager.cleanup_container_network(cache.id()).await.unwrap();
+    let gone = manager.connect(web.id(), cache_addr).await;
+    assert_eq!(gone.unwrap_err().kind(), std::io::ErrorKind::HostUnreachable);
+    let detached = manager.connect(cache.id(), cache_addr).await;
+    assert_eq!(detached.unwrap_err().kind(), std::io::ErrorKind::NetworkUnreachable);
+}
+
+#[tokio::test]
+async fn test_user_defined_networks() {
+    let dir = TempDir::new().unwrap();
+    let manager = NetworkManager::with_dir(dir.path().to_path_buf()).unwrap();
+    
+    let network = manager.create_network("backend", &["10.5.0.0/24"], &[], None).await.unwrap();
+    assert_eq!(network.gateway, ip("10.5.0.1"));
+    assert!(manager.create_network("backend", &["10.6.0.0/24"], &[], None).await.is_err());
+    assert!(manager.create_network("other", &["10.6.0.1/24"], &[], None).await.is_err());
+    assert!(manager.create_network("other", &["10.6.0.0/31"], &[], None).await.is_err());
+    assert!(manager.create_network("../other", &[], &[], None).await.is_err());
+    assert!(manager.create_network("none", &[], &[], None).await.is_err());
+    assert!(manager.create_network("other", &["10.5.0.128/25"], &[], None).await.is_err());
+    assert!(manager.create_network("other", &["10.0.0.0/8"], &[], None).await.is_err());
+    let picked = manager.create_network("frontend", &[], &[], None).await.unwrap();
+    assert_eq!(picked.subnet, "172.18.0.0/16");
+    
+    assert!(manager.remove_network("bridge").await.is_err());
+    manager.remove_network("frontend").await.unwrap();
+    assert!(manager.inspect_network("frontend").await.is_err());
+    
+    let reloaded = NetworkManager::with_dir(dir.path().to_path_buf()).unwrap();
+    let names: Vec<String> = reloaded.list_networks().await.unwrap()
+        .into_iter()
+        .map(|network| network.name)
+        .collect();
+    assert_eq!(names, vec!["backend", "bridge"]);
+}
+
+#[tokio::test]
+async fn test_networks_isolate_their_containers() {
+    let manager = NetworkManager::new();
+    manager.create_network("backend", &["10.5.0.0/24"], &[], None).await.unwrap();
+    
+    let web = Container::new(test_image(None), None, None, vec![]).unwrap();
+    let mut api = Container::new(test_image(None), None, None, vec![]).unwrap();
+    api.connect_network("backend");
+    let mut db = Container::new(test_image(None), None, None, vec![]).unwrap();
+    db.set_networks(vec!["backend".to_string()]);
+    
+    manager.setup_container_network(&web).await.unwrap();
+    let api_network = manager.setup_container_network(&api).await.unwrap();
+    let db_network = manager.setup_container_network(&db).await.unwrap();
+    assert_eq!(db_network.ip_address, ip("10.5.0.3"));
+    assert_eq!(api_network.addresses, vec![
+        ("bridge".to_string(), api_network.ip_address),
+        ("backend".to_string(), ip("10.5.0.2")),
+    ]);
+    
+    let _listener = manager.listen(db.id(), 5432).await.unwrap();
+    let db_addr = SocketAddr::new(db_network.ip_address, 5432);
+    manager.connect(api.id(), db_addr).await.unwrap();
+    let isolated = manager.connect(web.id(), db_addr).await;
+    assert_eq!(isolated.unwrap_err().kind(), std::io::ErrorKind::HostUnreachable);
+    
+    // Networks with containers on them stay.
+    assert!(manager.remove_network("backend").await.is_err());
+    
+    let mut lost = Container::new(test_image(None), None, None, vec![]).unwrap();
+    lost.set_networks(vec!["missing".to_string()]);
+    assert!(manager.setup_container_network(&lost).await.is_err());
+}
+
+#[tokio::test]
+async fn test_network_addresses_come_from_its_subnet_and_are_reused() {
+    let manager = NetworkManager::new();
+    manager.create_network("tiny", &["10.9.0.0/29"], &[], None).await.unwrap();
+    
+    // A /29 has six usable addresses, one of them the gateway's.
+    let mut ips = Vec::new();
+    let mut containers = Vec::new();
+    for _ in 0..5 {
+        let mut container = Container::new(test_image(None), None, None, vec![]).unwrap();
+        container.set_networks(vec!["tiny".to_string()]);
+        ips.push(manager.setup_container_network(&container).await.unwrap().ip_address);
+        containers.push(container.id().to_string());
+    }
+    assert_eq!(ips, ["10.9.0.2", "10.9.0.3", "10.9.0.4", "10.9.0.5", "10.9.0.6"].map(ip));
+    
+    let mut extra = Container::new(test_image(None), None, None, vec![]).unwrap();
+    extra.set_networks(vec!["tiny".to_string()]);
+    let error = manager.setup_container_network(&extra).await.unwrap_err();
+    assert!(error.to_string().contains("No addresses left"), "{}", error);
+    
+    // Freed addresses go to the next container, lowest first.
+    manager.cleanup_container_network(&containers[3]).await.unwrap();
+    manager.cleanup_container_network(&containers[1]).await.unwrap();
+    for _ in 0..20 {
+        let network = manager.setup_container_network(&extra).await.unwrap();
+        assert_eq!(network.ip_address, ip("10.9.0.3"));
+        manager.cleanup_container_network(extra.id()).await.unwrap();
+    }
+    let network = manager.setup_container_network(&extra).await.unwrap();
+    assert_eq!(network.ip_address, ip("10.9.0.3"));
+    let mut last = Container::new(test_image(None), None, None, vec![]).unwrap();
+    last.set_networks(vec!["tiny".to_string()]);
+    assert_eq!(manager.setup_container_network(&last).await.unwrap().ip_address, ip("10.9.0.5"));
+    assert_eq!(manager.inspect_network("tiny").await.unwrap().containers.len(), 5);
+}
+
+#[test]
+fn test_link_rules() {
+    let rule: LinkRule = "allow  web->db:5432".parse().unwrap();
+    assert_eq!((rule.from.as_str(), rule.to.as_str(), rule.port), ("web", "db", Some(5432)));
+    assert_eq!(rule.to_string(), "allow web->db:5432");
+    assert_eq!("deny *->db".parse::<LinkRule>().unwrap().to_string(), "deny *->db");
+    for invalid in ["web->db", "permit web->db", "allow web", "allow ->db", "allow web->db:0", "allow web->:80"] {
+        assert!(invalid.parse::<LinkRule>().is_err(), "{}", invalid);
+    }
+    
+    // The first matching rule decides, and nothing matching is allowed.
+    let policy = NetworkPolicy {
+        internal: false,
+        rules: vec!["allow web->db:5432".parse().unwrap(), "deny *->db".parse().unwrap()],
+    };
+    assert!(policy.allows(&["c1", "web"], &["c2", "db"], 5432));
+    assert!(!policy.allows(&["c1", "web"], &["c2", "db"], 22));
+    assert!(!policy.allows(&["c3", "worker"], &["c2", "db"], 5432));
+    assert!(policy.allows(&["c2", "db"], &["c1", "web"], 80));
+}
+
+#[tokio::test]
+async fn test_join_network_while_running() {
+    let dir = TempDir::new().unwrap();
+    let manager = NetworkManager::with_dir(dir.path().join("networks")).unwrap();
+    let web = Container::new(test_image(None), None, None, vec![]).unwrap();
+    manager.setup_container_network(&web).await.unwrap();
+    manager.attach_hosts_file(web.id(), dir.path().join("web-hosts")).await.unwrap();
+    
+    // Created by another process after this one started.
+    NetworkManager::with_dir(dir.path().join("networks")).unwrap()
+        .create_network("late", &["10.16.0.0/24"], &[], None).await.unwrap();
+    let mut db = Container::new(test_image(None), None, None, vec![]).unwrap();
+    db.set_networks(vec!["late".to_string()]);
+    assert!(manager.setup_container_network(&db).await.is_err());
+    
+    let ips = manager.join_network(web.id(), "late").await.unwrap();
+    assert_eq!(ips.len(), 1);
+    assert!(ips[0].to_string().starts_with("10.16.0."), "{:?}", ips);
+    assert!(manager.join_network(web.id(), "late").await.is_err());
+    assert!(manager.join_network(web.id(), "missing").await.is_err());
+    assert!(manager.join_network("not-running", "late").await.is_err());
+    
+    // Peers on the network reach it at the new address, and the other way
+    // round, and know it by name.
+    manager.setup_container_network(&db).await.unwrap();
+    manager.attach_hosts_file(db.id(), dir.path().join("db-hosts")).await.unwrap();
+    let _listener = manager.listen(web.id(), 80).await.unwrap();
+    manager.connect(db.id(), SocketAddr::new(ips[0], 80)).await.unwrap();
+    let hosts = std::fs::read_to_string(dir.path().join("db-hosts")).unwrap();
+    assert!(hosts.contains(&format!("{}\t{}", ips[0], web.id())), "{}", hosts);
+    let hosts = std::fs::read_to_string(dir.path().join("web-hosts")).unwrap();
+    assert!(hosts.contains(db.id()), "{}", hosts);
+    
+    let members = manager.inspect_network("late").await.unwrap().members;
+    assert_eq!(members.len(), 2);
+    assert!(members.iter().any(|member| member.id == web.id() && member.addresses == ips));
+}
+
+#[tokio::test]
+async fn test_network_policy() {
+    let manager = NetworkManager::new();
+    manager.create_network("guarded", &["10.15.0.0/24"], &[], None).await.unwrap();
+    let on_guarded = || {
+        let mut container = Container::new(test_image(None), None, None, vec![]).unwrap();
+        container.set_networks(vec!["guarded".to_string()]);
+        container
+    };
+    let (web, db, worker) = (on_guarded(), on_guarded(), on_guarded());
+    let policy = NetworkPolicy {
+        internal: true,
+        rules: vec![
+            format!("allow {}->{}:5432", web.id(), db.id()).parse().unwrap(),
+            format!("deny *->{}", db.id()).parse().unwrap(),
+        ],
+    };
+    assert!(manager.set_network_policy("bridge", policy.clone()).await.is_err());
+    assert_eq!(manager.set_network_policy("guarded", policy).await.unwrap().policy.rules.len(), 2);
+    
+    let db_ip = manager.setup_container_network(&db).await.unwrap().ip_address;
+    let web_ip = manager.setup_container_network(&web).await.unwrap().ip_address;
+    manager.setup_container_network(&worker).await.unwrap();
+    let mut published = Container::new(test_image(None), None, None, vec![]).unwrap();
+    published.add_port_mapping(0, 8080, "tcp".to_string());
+    let host_port = manager.setup_container_network(&published).await.unwrap().port_mappings[0].host_port;
+    let _published_listener = manager.listen(published.id(), 8080).await.unwrap();
+    let _db_listeners = (manager.listen(db.id(), 5432).await.unwrap(), manager.listen(db.id(), 22).await.unwrap());
+    let _web_listener = manager.listen(web.id(), 80).await.unwrap();
+    
+    manager.connect(web.id(), SocketAddr::new(db_ip, 5432)).await.unwrap();
+    manager.connect(db.id(), SocketAddr::new(web_ip, 80)).await.unwrap();
+    for (from, port) in [(&web, 22), (&worker, 5432)] {
+        let error = manager.connect(from.id(), SocketAddr::new(db_ip, port)).await.unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::PermissionDenied, "{} to {}", from.id(), port);
+    }
+    
+    // Nothing but the network's containers is reachable from it: not the
+    // gateway, nor published ports through hairpinning.
+    assert!(manager.internal(web.id()).await);
+    assert!(!manager.internal(published.id()).await);
+    let error = manager.connect(web.id(), SocketAddr::new(ip("10.15.0.1"), host_port)).await.unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::HostUnreachable);
+    let error = manager.connect(web.id(), SocketAddr::new(web_ip, host_port)).await.unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::ConnectionRefused);
+    manager.connect(published.id(), SocketAddr::new(ip("172.17.0.1"), host_port)).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_network_gateways() {
+    let manager = NetworkManager::new();
+    
+    let network = manager.create_network("routed", &["10.12.0.0/24", "fd00:12::/64"], &[ip("10.12.0.254")], None)
+        .await
+        .unwrap();
+    assert_eq!(network.gateway, ip("10.12.0.254"));
+    assert_eq!(network.gateway6, Some(ip("fd00:12::1")));
+    
+    // The first host is free for containers once the gateway moves.
+    let mut container = Container::new(test_image(None), None, None, vec![]).unwrap();
+    container.set_networks(vec!["routed".to_string()]);
+    assert_eq!(manager.setup_container_network(&container).await.unwrap().ip_address, ip("10.12.0.1"));
+    assert!(network.check_address(ip("10.12.0.254")).is_err());
+    
+    let picked = manager.create_network("picked", &[], &[], None).await.unwrap();
+    assert_eq!(picked.gateway, ip("172.18.0.1"));
+    
+    for gateways in [
+        vec![ip("10.13.1.1")],
+        vec![ip("10.13.0.0")],
+        vec![ip("10.13.0.255")],
+        vec![ip("10.13.0.1"), ip("10.13.0.2")],
+        vec![ip("fd00:13::1")],
+    ] {
+        assert!(manager.create_network("other", &["10.13.0.0/24"], &gateways, None).await.is_err(), "{:?}", gateways);
+    }
+}
+
+#[tokio::test]
+async fn test_mesh_connects_containers_across_hosts() {
+    let free_port = || std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let (addr_a, addr_b) = (free_port(), free_port());
+    let config = |listen: SocketAddr, peer: SocketAddr| MeshConfig {
+        listen,
+        peers: vec![peer.to_string()],
+        key: "secret".to_string(),
+    };
+    
+    // Each host has its own subnet for the shared network, so addresses
+    // don't clash.
+    let host_a = NetworkManager::new();
+    let host_b = NetworkManager::new();
+    host_a.create_network("shared", &["10.20.0.0/24"], &[], None).await.unwrap();
+    host_b.create_network("shared", &["10.21.0.0/24"], &[], None).await.unwrap();
+    let on_shared = || {
+        let mut container = Container::new(test_image(None), None, None, vec![]).unwrap();
+        container.set_networks(vec!["shared".to_string()]);
+        container
+    };
+    let (client, server) = (on_shared(), on_shared());
+    host_a.setup_container_network(&client).await.unwrap();
+    let server_ip = host_b.setup_container_network(&server).await.unwrap().ip_address;
+    let mut listener = host_b.listen(server.id(), 80).await.unwrap();
+    
+    let dir = TempDir::new().unwrap();
+    host_a.attach_hosts_file(client.id(), dir.path().join("hosts")).await.unwrap();
+    host_a.join_mesh(config(addr_a, addr_b)).await.unwrap();
+    host_b.join_mesh(config(addr_b, addr_a)).await.unwrap();
+    
+    let mut stream = tokio::time::timeout(Duration::from_secs(10), async {
+        loop {
+            match host_a.connect(client.id(), SocketAddr::new(server_ip, 80)).await {
+                Ok(stream) => break stream,
+                Err(_) => tokio::time::sleep(Duration::from_millis(100)).await,
+            }
+        }
+    }).await.unwrap();
+    stream.write_all(b"ping").await.unwrap();
+    let (mut accepted, peer) = tokio::time::timeout(Duration::from_secs(5), listener.accept()).await.unwrap().unwrap();
+    assert_eq!(peer.ip(), ip("10.20.0.2"));
+    let mut buf = [0u8; 4];
+    accepted.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"ping");
+    accepted.write_all(b"pong").await.unwrap();
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"pong");
+    
+    let hosts = std::fs::read_to_string(dir.path().join("hosts")).unwrap();
+    assert!(hosts.contains(&format!("{}\t{}", server_ip, server.id())), "{}", hosts);
+    
+    // Peers that don't know the key are turned away.
+    let intruder = Mesh::new(&MeshConfig { key: "guess".to_string(), ..config(free_port(), addr_a) }, free_port()).unwrap();
+    let error = intruder.sync(&addr_a.to_string(), vec![]).await.unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::PermissionDenied);
+    
+    host_a.leave_mesh().await;
+    host_b.leave_mesh().await;
+    let error = host_a.connect(client.id(), SocketAddr::new(server_ip, 80)).await.unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::HostUnreachable);
+}
+
+#[tokio::test]
+async fn test_hairpin_to_published_ports() {
+    let manager = NetworkManager::new();
+    let mut web = Container::new(test_image(None), None, None, vec![]).unwrap();
+    web.add_port_mapping(0, 8080, "tcp".to_string());
+    let web_network = manager.setup_container_network(&web).await.unwrap();
+    let host_port = web_network.port_mappings[0].host_port;
+    let mut listener = manager.listen(web.id(), 8080).await.unwrap();
+    let client = Container::new(test_image(None), None, None, vec![]).unwrap();
+    let client_ip = manager.setup_container_network(&client).await.unwrap().ip_address;
+    
+    // The host port reaches the publishing container at the host's gateway
+    // address, on localhost, and at either container's address.
+    for (from, ip) in [
+        (&client, ip("172.17.0.1")),
+        (&client, ip("127.0.0.1")),
+        (&client, web_network.ip_address),
+        (&client, client_ip),
+        (&web, web_network.ip_address),
+    ] {
+        let mut stream = manager.connect(from.id(), SocketAddr::new(ip, host_port)).await.unwrap();
+        stream.write_all(b"ping").await.unwrap();
+        let (mut accepted, _) = tokio::time::timeout(Duration::from_secs(5), listener.accept()).await.unwrap().unwrap();
+        let mut buf = [0u8; 4];
+        accepted.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping", "via {}", ip);
+    }
+    
+    // A listener on the port itself comes first.
+    let mut own = manager.listen(client.id(), host_port).await.unwrap();
+    manager.connect(client.id(), SocketAddr::new(ip("127.0.0.1"), host_port)).await.unwrap();
+    tokio::time::timeout(Duration::from_secs(5), own.accept()).await.unwrap().unwrap();
+    
+    let error = manager.connect(client.id(), SocketAddr::new(ip("172.17.0.1"), 1)).await.unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::ConnectionRefused);
+}
+
+#[test]
+fn test_ingress_rules() {
+    let rule: IngressRule = "App.Local->web:80".parse().unwrap();
+    assert_eq!(rule, IngressRule { host: Some("app.local".to_string()), path: "/".to_string(), container: "web".to_string(), port: 80 });
+    assert_eq!(rule.to_string(), "app.local->web:80");
+    for valid in ["/api->api:8080", "app.local/api/->api:8080"] {
+        assert_eq!(valid.parse::<IngressRule>().unwrap().to_string(), valid);
+    }
+    for invalid in ["app.local", "->web:80", "app.local->web", "app.local->:80", "app.local->web:0", "app.local:8080->web:80"] {
+        assert!(invalid.parse::<IngressRule>().is_err(), "{}", invalid);
+    }
+    
+    let rules: Vec<IngressRule> = ["/->site:80", "/api->api:80", "app.local->web:80", "app.local/api->web-api:80"]
+        .iter()
+        .map(|rule| rule.parse().unwrap())
+        .collect();
+    let routed = |host, path| ingress::route(&rules, host, path).map(|rule| rule.container.as_str());
+    assert_eq!(routed(None, "/"), Some("site"));
+    assert_eq!(routed(None, "/api?q=1"), Some("api"));
+    assert_eq!(routed(Some("other"), "/apis"), Some("site"));
+    assert_eq!(routed(Some("APP.local"), "/apis"), Some("web"));
+    assert_eq!(routed(Some("app.local"), "/api/users"), Some("web-api"));
+    assert_eq!(ingress::route(&rules[2..], Some("other"), "/"), None);
+}
+
+#[tokio::test]
+async fn test_ingress_routes_requests() {
+    let manager = NetworkManager::new();
+    let web = Container::new(test_image(None), None, None, vec![]).unwrap();
+    let api = Container::new(test_image(None), None, None, vec![]).unwrap();
+    manager.setup_container_network(&web).await.unwrap();
+    manager.setup_container_network(&api).await.unwrap();
+    let mut web_listener = manager.listen(web.id(), 80).await.unwrap();
+    let mut api_listener = manager.listen(api.id(), 8080).await.unwrap();
+    
+    let rules = vec![
+        format!("app.local->{}:80", web.id()).parse().unwrap(),
+        format!("/api->{}:8080", api.id()).parse().unwrap(),
+        "/gone->stopped:80".parse().unwrap(),
+    ];
+    assert!(manager.start_ingress("127.0.0.1:0".parse().unwrap(), Vec::new()).await.is_err());
+    let addr = manager.start_ingress("127.0.0.1:0".parse().unwrap(), rules.clone()).await.unwrap();
+    assert!(manager.start_ingress("127.0.0.1:0".parse().unwrap(), rules).await.is_err());
+    
+    let request = |head: &'static str| async move {
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client.write_all(head.as_bytes()).await.unwrap();
+        client
+    };
+    // Routed by host, with the client's hop-by-hop headers replaced.
+    let mut client = request("POST /apis HTTP/1.1\r\nHost: app.local:8000\r\nConnection: keep-alive\r\nContent-Length: 4\r\n\r\nbody").await;
+    let received = respond_http(&mut web_listener).await;
+    assert!(received.starts_with("POST /apis HTTP/1.1\r\nHost: app.local:8000\r\nContent-Length: 4\r\n"), "{}", received);
+    assert!(received.contains("X-Forwarded-For: 127.0.0.1\r\n") && received.contains("Connection: close\r\n"), "{}", received);
+    assert!(!received.contains("keep-alive"), "{}", received);
+    let mut response = String::new();
+    client.read_to_string(&mut response).await.unwrap();
+    assert!(response.ends_with("\r\n\r\nok"), "{}", response);
+    
+    // And by path.
+    let mut client = request("POST /api/users HTTP/1.1\r\nHost: example.com\r\nContent-Length: 4\r\n\r\nbody").await;
+    respond_http(&mut api_listener).await;
+    client.read_to_string(&mut String::new()).await.unwrap();
+    
+    for (head, status) in [
+        ("GET / HTTP/1.1\r\nHost: example.com\r\n\r\n", "HTTP/1.1 404 "),
+        ("GET /gone HTTP/1.1\r\n\r\n", "HTTP/1.1 502 "),
+        ("nonsense\r\n\r\n", "HTTP/1.1 400 "),
+    ] {
+        let mut response = String::new();
+        request(head).await.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with(status), "{}", response);
+    }
+    
+    manager.stop_ingress().await;
+    manager.start_ingress("127.0.0.1:0".parse().unwrap(), vec!["/->web:80".parse().unwrap()]).await.unwrap();
+}
+
+// Answers one request whose body is `body`, returning what was received.
+async fn respond_http(listener: &mut BridgeListener) -> String {
+    let (mut server, _) = tokio::time::timeout(Duration::from_secs(5), listener.accept()).await.unwrap().unwrap();
+    let mut received = Vec::new();
+    while !received.ends_with(b"\r\n\r\nbody") {
+        let mut buf = [0u8; 1024];
+        let len = server.read(&mut buf).await.unwrap();
+        assert!(len > 0, "{}", String::from_utf8_lossy(&received));
+        received.extend_from_slice(&buf[..len]);
+    }
+    server.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok").await.unwrap();
+    String::from_utf8(received).unwrap()
+}
+
+#[tokio::test]
+async fn test_network_domains() {
+    let dir = TempDir::new().unwrap();
+    let manager = NetworkManager::new();
+    assert!(manager.create_network("other", &[], &[], Some("bad_domain")).await.is_err());
+    assert!(manager.create_network("other", &[], &[], Some("..")).await.is_err());
+    let network = manager.create_network("services", &["10.14.0.0/24"], &[], Some(".Internal.")).await.unwrap();
+    assert_eq!(network.domain.as_deref(), Some("internal"));
+    
+    let on_services = || {
+        let mut container = Container::new(test_image(None), None, None, vec![]).unwrap();
+        container.set_networks(vec!["services".to_string(), "bridge".to_string()]);
+        container
+    };
+    let (web, db) = (on_services(), on_services());
+    manager.setup_container_network(&web).await.unwrap();
+    let db_ip = manager.setup_container_network(&db).await.unwrap().ip_address;
+    assert_eq!(manager.search_domains(web.id()).await, vec!["internal"]);
+    let inspected = manager.inspect_network("services").await.unwrap();
+    let member = inspected.members.iter().find(|member| member.id == db.id()).unwrap();
+    assert_eq!(member.aliases, vec![format!("{}.internal", db.id())]);
+    
+    // Names are answered bare and under the domain.
+    let server = manager.start_dns("services", "127.0.0.1:0".parse().unwrap(), &[]).await.unwrap();
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    client.connect(server).await.unwrap();
+    let IpAddr::V4(expected) = db_ip else { panic!("expected an IPv4 address") };
+    for (id, name) in [(1, db.id().to_string()), (2, format!("{}.internal", db.id()))] {
+        let reply = dns_exchange(&client, id, &name, 1).await;
+        assert_eq!(reply[3] & 0x0f, 0, "{}", name);
+        assert_eq!(reply[reply.len() - 4..], expected.octets());
+    }
+    
+    manager.attach_hosts_file(web.id(), dir.path().join("hosts")).await.unwrap();
+    let hosts = std::fs::read_to_string(dir.path().join("hosts")).unwrap();
+    assert!(hosts.contains(&format!("{}\t{}.internal {}\n", db_ip, db.id(), db.id())), "{}", hosts);
+}
+
+#[tokio::test]
+async fn test_static_addresses() {
+    let manager = NetworkManager::new();
+    let network = manager.create_network("static", &["10.10.0.0/24"], &[], None).await.unwrap();
+    assert!(network.check_address(ip("10.10.0.50")).is_ok());
+    assert!(network.check_address(ip("10.11.0.50")).is_err());
+    assert!(network.check_address(ip("10.10.0.1")).is_err());
+    assert!(network.check_address(ip("10.10.0.255")).is_err());
+    
+    let with_ip = |addr: &str| {
+        let mut container = Container::new(test_image(None), None, None, vec![]).unwrap();
+        container.set_networks(vec!["static".to_string()]);
+        container.set_ip(ip(addr));
+        container
+    };
+    let service = with_ip("10.10.0.50");
+    let service_network = manager.setup_container_network(&service).await.unwrap();
+    assert_eq!(service_network.ip_address, ip("10.10.0.50"));
+    
+    // Dynamic addresses go around static ones, and a static address can't
+    // be taken twice.
+    let mut dynamic = Container::new(test_image(None), None, None, vec![]).unwrap();
+    dynamic.set_networks(vec!["static".to_string()]);
+    assert_eq!(manager.setup_container_network(&dynamic).await.unwrap().ip_address, ip("10.10.0.2"));
+    let clash = with_ip("10.10.0.50");
+    let error = manager.setup_container_network(&clash).await.unwrap_err();
+    assert!(error.to_string().contains("already in use"), "{}", error);
+    let taken = with_ip("10.10.0.2");
+    assert!(manager.setup_container_network(&taken).await.is_err());
+    assert!(manager.setup_container_network(&with_ip("10.99.0.2")).await.is_err());
+    
+    manager.cleanup_container_network(service.id()).await.unwrap();
+    assert_eq!(manager.setup_container_network(&clash).await.unwrap().ip_address, ip("10.10.0.50"));
+}
+
+#[tokio::test]
+async fn test_dual_stack_networks() {
+    let manager = NetworkManager::new();
+    let network = manager.create_network("dual", &["10.7.0.0/24", "fd00:7::/64"], &[], None).await.unwrap();
+    assert_eq!(network.gateway6, Some(ip("fd00:7::1")));
+    assert!(manager.create_network("other", &["fd00:7::/48"], &[], None).await.is_err());
+    assert!(manager.create_network("other", &["10.8.0.0/24", "10.9.0.0/24"], &[], None).await.is_err());
+    assert!(manager.create_network("other", &["fd00:8::/127"], &[], None).await.is_err());
+    
+    let guest = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mut web = Container::new(test_image(None), None, None, vec![]).unwrap();
+    web.set_networks(vec!["dual".to_string()]);
+    web.add_port_mapping(0, guest.local_addr().unwrap().port(), "tcp".to_string());
+    let mut db = Container::new(test_image(None), None, None, vec![]).unwrap();
+    db.set_networks(vec!["dual".to_string()]);
+    
+    let web_network = manager.setup_container_network(&web).await.unwrap();
+    let db_network = manager.setup_container_network(&db).await.unwrap();
+    assert_eq!(web_network.ip_address, ip("10.7.0.2"));
+    assert_eq!(web_network.ip6_address, Some(ip("fd00:7::2")));
+    assert_eq!(db_network.ip6_address, Some(ip("fd00:7::3")));
+    
+    let mut listener = manager.listen(db.id(), 5432).await.unwrap();
+    manager.connect(web.id(), "[fd00:7::3]:5432".parse().unwrap()).await.unwrap();
+    let (_, peer) = listener.accept().await.unwrap();
+    assert_eq!(peer.ip(), ip("fd00:7::2"));
+    
+    let inspected = manager.inspect_network("dual").await.unwrap();
+    let member = inspected.members.iter().find(|member| member.id == db.id()).unwrap();
+    assert_eq!(member.addresses, vec![ip("10.7.0.3"), ip("fd00:7::3")]);
+    
+    let dir = TempDir::new().unwrap();
+    manager.attach_hosts_file(db.id(), dir.path().join("hosts")).await.unwrap();
+    let hosts = std::fs::read_to_string(dir.path().join("hosts")).unwrap();
+    assert!(hosts.contains("fd00:7::3\t"), "{}", hosts);
+    assert!(hosts.contains("fd00:7::2\t"), "{}", hosts);
+    
+    // Published ports take connections over both IPv4 and IPv6.
+    let host_port = web_network.port_mappings[0].host_port;
+    TcpStream::connect(("127.0.0.1", host_port)).await.unwrap();
+    TcpStream::connect(("::1", host_port)).await.unwrap();
+}
+
+async fn dns_exchange(client: &UdpSocket, id: u16, name: &str, qtype: u16) -> Vec<u8> {
+    let mut query = id.to_be_bytes().to_vec();
+    query.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    query.extend(dns_name(name));
+    query.extend_from_slice(&qtype.to_be_bytes());
+    query.extend_from_slice(&[0, 1]);
+    client.send(&query).await.unwrap();
+    
+    let mut buf = [0u8; 512];
+    let n = tokio::time::timeout(Duration::from_secs(5), client.recv(&mut buf)).await.unwrap().unwrap();
+    assert_eq!(buf[..2], id.to_be_bytes());
+    assert_ne!(buf[2] & 0x80, 0);
+    buf[..n].to_vec()
+}
+
+// The frames of a pcap file, after checking its header.
+fn pcap_frames(pcap: &[u8]) -> Vec<Vec<u8>> {
+    assert_eq!(pcap[..4], 0xa1b2c3d4u32.to_le_bytes());
+    assert_eq!(pcap[20..24], 1u32.to_le_bytes());
+    let mut frames = Vec::new();
+    let mut rest = &pcap[24..];
+    while rest.len() >= 16 {
+        let len = u32::from_le_bytes(rest[8..12].try_into().unwrap()) as usize;
+        frames.push(rest[16..16 + len].to_vec());
+        rest = &rest[16 + len..];
+    }
+    frames
+}
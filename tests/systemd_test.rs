@@ -0,0 +1,59 @@
+mod common;
+
+use common::test_image;
+use std::path::{Path, PathBuf};
+use wasm_container::container::Container;
+use wasm_container::systemd::{RestartPolicy, Unit, UnitOptions};
+
+#[test]
+fn test_container_unit() {
+    let mut container = Container::new(test_image(Some("src/image/demo.wasm")), None, None, vec![]).unwrap();
+    container.set_name("web").unwrap();
+    container.add_volume(PathBuf::from("/srv/my data"), PathBuf::from("/data"), false);
+    let options = UnitOptions {
+        executable: PathBuf::from("/usr/bin/wasm-container"),
+        restart: "always".parse().unwrap(),
+        restart_sec: 5,
+        requires: vec!["db".to_string()],
+        wants: vec!["cache".to_string()],
+    };
+    
+    let unit = Unit::container(&container, &options);
+    assert_eq!(unit.name, "container-web.service");
+    let text = unit.to_string();
+    assert!(text.contains("\nWants=network-online.target container-cache.service\n"));
+    assert!(text.contains("\nRequires=container-db.service\n"));
+    assert!(text.contains("\nAfter=network-online.target container-cache.service container-db.service\n"));
+    assert!(text.contains("\nRequiresMountsFor=\"/srv/my data\"\n"));
+    assert!(text.contains("\nRestart=always\nRestartSec=5\n"));
+    assert!(text.contains("\nExecStart=/usr/bin/wasm-container start web\n"));
+    assert!(text.contains("\nExecStopPost=-/usr/bin/wasm-container stop web\n"));
+    assert!(text.ends_with("[Install]\nWantedBy=default.target\n"));
+}
+
+#[test]
+fn test_compose_unit() {
+    let options = UnitOptions {
+        executable: PathBuf::from("/opt/wasm container/bin/wasm-container"),
+        restart: RestartPolicy::default(),
+        restart_sec: 1,
+        requires: vec![],
+        wants: vec![],
+    };
+    
+    let unit = Unit::compose(Path::new("/srv/shop/compose.yaml"), "shop", &options);
+    assert_eq!(unit.name, "compose-shop.service");
+    let text = unit.to_string();
+    assert!(!text.contains("Requires="));
+    assert!(text.contains("\nRequiresMountsFor=/srv/shop\n"));
+    assert!(text.contains("\nRestart=on-failure\n"));
+    assert!(text.contains("\nExecStart=\"/opt/wasm container/bin/wasm-container\" compose -f /srv/shop/compose.yaml -p shop up\n"));
+    assert!(text.contains("\nExecStopPost=-\"/opt/wasm container/bin/wasm-container\" compose -f /srv/shop/compose.yaml -p shop down\n"));
+}
+
+#[test]
+fn test_restart_policy_parsing() {
+    assert_eq!("on-abnormal".parse::<RestartPolicy>().unwrap(), RestartPolicy::OnAbnormal);
+    assert_eq!(RestartPolicy::OnSuccess.to_string(), "on-success");
+    assert!("sometimes".parse::<RestartPolicy>().is_err());
+}
@@ -0,0 +1,80 @@
+use wasm_container::container::{Consistency, MountMode};
+use wasm_container::volume::{MountOptions, VolumeManager, VolumeSource, VolumeSpec};
+use std::path::PathBuf;
+use tempfile::TempDir;
+
+#[test]
+fn test_volume_lifecycle() {
+    let state = TempDir::new().unwrap();
+    let manager = VolumeManager::with_dir(state.path().join("volumes")).unwrap();
+    
+    let volume = manager.create("data").unwrap();
+    assert!(volume.mountpoint.is_dir());
+    std::fs::write(volume.mountpoint.join("file.txt"), "persisted").unwrap();
+    
+    let again = manager.create("data").unwrap();
+    assert_eq!(again.mountpoint, volume.mountpoint);
+    assert!(again.mountpoint.join("file.txt").exists());
+    
+    let names: Vec<String> = manager.list().unwrap().into_iter().map(|v| v.name).collect();
+    assert_eq!(names, vec!["data".to_string()]);
+    
+    manager.remove("data").unwrap();
+    assert!(manager.inspect("data").is_err());
+    assert!(!volume.mountpoint.exists());
+}
+
+#[test]
+fn test_volume_rejects_invalid_names() {
+    let state = TempDir::new().unwrap();
+    let manager = VolumeManager::with_dir(state.path().join("volumes")).unwrap();
+    
+    assert!(manager.create("").is_err());
+    assert!(manager.create("../escape").is_err());
+    assert!(manager.create("-leading-dash").is_err());
+    assert!(manager.remove("missing").is_err());
+}
+
+#[test]
+fn test_volume_spec_parsing() {
+    let named = VolumeSpec::parse("myvol:/data").unwrap();
+    assert_eq!(named.source, VolumeSource::Named("myvol".to_string()));
+    assert_eq!(named.container_path, PathBuf::from("/data"));
+    
+    let host = VolumeSpec::parse("/srv/app:/app").unwrap();
+    assert_eq!(host.source, VolumeSource::HostPath(PathBuf::from("/srv/app")));
+    
+    let relative = VolumeSpec::parse("./config:/etc/app").unwrap();
+    assert_eq!(relative.source, VolumeSource::HostPath(PathBuf::from("./config")));
+    
+    assert!(VolumeSpec::parse("no-target").is_err());
+    assert!(VolumeSpec::parse(":/data").is_err());
+    assert!(VolumeSpec::parse("myvol:data").is_err());
+    assert!(VolumeSpec::parse("./config:etc/app:ro").is_err());
+}
+
+#[test]
+fn test_volume_spec_options() {
+    let plain = VolumeSpec::parse("/srv/app:/app").unwrap();
+    assert_eq!(plain.options, MountOptions::default());
+    
+    let read_only = VolumeSpec::parse("./config.yaml:/etc/app/config.yaml:ro").unwrap();
+    assert_eq!(read_only.container_path, PathBuf::from("/etc/app/config.yaml"));
+    assert!(read_only.options.read_only);
+    
+    let options = VolumeSpec::parse("myvol:/data:rw,z,cached").unwrap();
+    assert!(!options.options.read_only);
+    assert_eq!(options.options.consistency, Consistency::Cached);
+    
+    assert!(VolumeSpec::parse("myvol:/data:ro,ro").is_ok());
+    assert!(VolumeSpec::parse("myvol:/data:ro,rw").is_err());
+    assert!(VolumeSpec::parse("myvol:/data:cached,delegated").is_err());
+    assert!(VolumeSpec::parse("myvol:/data:noexec").is_err());
+    
+    let synced = VolumeSpec::parse("./src:/app:sync-back").unwrap();
+    assert_eq!(synced.options.mode, MountMode::SyncBack);
+    assert_eq!(VolumeSpec::parse("./src:/app:ro,sync").unwrap().options.mode, MountMode::Sync);
+    assert!(VolumeSpec::parse("./src:/app:ro,sync-back").is_err());
+    assert!(VolumeSpec::parse("./src:/app:copy,sync").is_err());
+    assert!(VolumeSpec::parse("myvol:/data:").is_err());
+}